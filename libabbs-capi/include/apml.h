@@ -0,0 +1,147 @@
+#ifndef LIBABBS_APML_H
+#define LIBABBS_APML_H
+
+/* Generated with cbindgen from src/lib.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ Status codes of calls.
+ */
+typedef enum ApmlStatus {
+  /*
+   The call succeeded.
+   */
+  APML_STATUS_OK = 0,
+  /*
+   An argument is a null pointer or is not valid UTF-8.
+   */
+  APML_STATUS_INVALID_ARGUMENT = 1,
+  /*
+   The source cannot be parsed or evaluated.
+   */
+  APML_STATUS_EVAL_ERROR = 2,
+  /*
+   The variable is not defined.
+   */
+  APML_STATUS_NOT_FOUND = 3,
+  /*
+   The value contains NUL bytes, which C strings cannot hold.
+   */
+  APML_STATUS_UNREPRESENTABLE = 4,
+  /*
+   A panic was caught.
+   */
+  APML_STATUS_PANIC = 5,
+} ApmlStatus;
+
+/*
+ An evaluated context, along with the status of the last call.
+ */
+typedef struct ApmlHandle ApmlHandle;
+
+/*
+ Parses and evaluates a source of `len` bytes.
+
+ Unless `out_handle` is null, a handle is always stored into it, even
+ if evaluation fails, so that the error can be read with
+ [`apml_last_error`]. The handle must be released with
+ [`apml_free_handle`].
+
+ # Safety
+
+ `src` must point to `len` readable bytes, and `out_handle` must be
+ null or valid for writes.
+ */
+ApmlStatus apml_eval_source(const char *src, size_t len, ApmlHandle **out_handle);
+
+/*
+ Returns the value of a variable as a string, or null on failure.
+
+ Arrays are joined with spaces. The string must be released with
+ [`apml_free_string`].
+
+ # Safety
+
+ `handle` must be null or a handle from [`apml_eval_source`], and
+ `name` must be null or point to a NUL-terminated string.
+ */
+char *apml_get_string(ApmlHandle *handle, const char *name);
+
+/*
+ Returns the value of a variable as an array of strings, or null on
+ failure.
+
+ Strings are split into words. The number of elements is stored into
+ `out_len`. The array must be released with [`apml_free_array`],
+ which also releases the elements. Empty arrays are not null.
+
+ # Safety
+
+ `handle` must be null or a handle from [`apml_eval_source`], `name`
+ must be null or point to a NUL-terminated string, and `out_len` must
+ be null or valid for writes.
+ */
+char **apml_get_array(ApmlHandle *handle, const char *name, size_t *out_len);
+
+/*
+ Returns the status of the last call on a handle.
+
+ Failures of [`apml_eval_source`] are kept until the handle is
+ released, as nothing can be read from the handle.
+
+ # Safety
+
+ `handle` must be null or a handle from [`apml_eval_source`].
+ */
+ApmlStatus apml_last_status(const ApmlHandle *handle);
+
+/*
+ Returns the error message of the last call on a handle, or null if
+ the call succeeded.
+
+ The message is owned by the handle, and is valid until the next call
+ on the handle.
+
+ # Safety
+
+ `handle` must be null or a handle from [`apml_eval_source`].
+ */
+const char *apml_last_error(const ApmlHandle *handle);
+
+/*
+ Releases a handle.
+
+ # Safety
+
+ `handle` must be null or a handle from [`apml_eval_source`] that has
+ not been released.
+ */
+void apml_free_handle(ApmlHandle *handle);
+
+/*
+ Releases a string returned by [`apml_get_string`].
+
+ # Safety
+
+ `string` must be null or a string from [`apml_get_string`] that has
+ not been released.
+ */
+void apml_free_string(char *string);
+
+/*
+ Releases an array returned by [`apml_get_array`] along with its
+ elements.
+
+ # Safety
+
+ `array` must be null or an array from [`apml_get_array`] that has not
+ been released, and `len` must be its length.
+ */
+void apml_free_array(char **array, size_t len);
+
+#endif  /* LIBABBS_APML_H */
@@ -0,0 +1,389 @@
+//! C ABI of libabbs for evaluating APML sources.
+//!
+//! The C ABI is built as a `cdylib` by this crate, so that users of the
+//! Rust API of libabbs do not build it. The C header is
+//! `include/apml.h`, which is generated with [cbindgen] from this crate:
+//!
+//! ```sh
+//! cbindgen --config cbindgen.toml --output include/apml.h
+//! ```
+//!
+//! A source is evaluated into an [`ApmlHandle`] with [`apml_eval_source`],
+//! and variables are read from it with [`apml_get_string`] and
+//! [`apml_get_array`]. Strings and arrays returned are owned by the caller
+//! and must be released with the matching `apml_free_*` function.
+//!
+//! Panics never cross the boundary. They are caught and reported as
+//! [`ApmlStatus::Panic`], see [`apml_last_status`].
+//!
+//! [cbindgen]: https://github.com/mozilla/cbindgen
+
+use std::{
+	any::Any,
+	ffi::{CStr, CString, c_char},
+	panic::{UnwindSafe, catch_unwind},
+	ptr,
+};
+
+use libabbs::apml::ApmlContext;
+
+/// Status codes of calls.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ApmlStatus {
+	/// The call succeeded.
+	#[default]
+	Ok = 0,
+	/// An argument is a null pointer or is not valid UTF-8.
+	InvalidArgument = 1,
+	/// The source cannot be parsed or evaluated.
+	EvalError = 2,
+	/// The variable is not defined.
+	NotFound = 3,
+	/// The value contains NUL bytes, which C strings cannot hold.
+	Unrepresentable = 4,
+	/// A panic was caught.
+	Panic = 5,
+}
+
+/// A failed call, with its status and message.
+struct Failure(ApmlStatus, String);
+
+type Result<T> = std::result::Result<T, Failure>;
+
+/// An evaluated context, along with the status of the last call.
+#[derive(Debug, Default)]
+pub struct ApmlHandle {
+	/// The context, or [`None`] if evaluation failed.
+	context: Option<ApmlContext>,
+	status: ApmlStatus,
+	error: Option<CString>,
+}
+
+impl ApmlHandle {
+	/// Records the result of a call.
+	fn record<T>(&mut self, result: Result<T>) -> Option<T> {
+		match result {
+			Ok(value) => {
+				self.status = ApmlStatus::Ok;
+				self.error = None;
+				Some(value)
+			}
+			Err(Failure(status, message)) => {
+				self.status = status;
+				self.error = Some(to_c_string_lossy(message));
+				None
+			}
+		}
+	}
+
+	/// Runs a call reading a variable from the context.
+	///
+	/// If evaluation failed, the call is not run and the evaluation error
+	/// is kept.
+	fn read<T>(
+		&mut self,
+		name: *const c_char,
+		f: impl FnOnce(&libabbs::apml::VariableValue) -> Result<T> + UnwindSafe,
+	) -> Option<T> {
+		let context = self.context.as_ref()?;
+		let result = guard(|| {
+			let name = unsafe { str_arg(name) }?;
+			let value = context.get(name).ok_or_else(|| {
+				Failure(
+					ApmlStatus::NotFound,
+					format!("Variable is not defined: {}", name),
+				)
+			})?;
+			f(value)
+		});
+		self.record(result)
+	}
+}
+
+/// Runs a call, converting panics into [`ApmlStatus::Panic`] failures.
+fn guard<T>(f: impl FnOnce() -> Result<T> + UnwindSafe) -> Result<T> {
+	catch_unwind(f).unwrap_or_else(|payload| {
+		Err(Failure(ApmlStatus::Panic, panic_message(payload)))
+	})
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+	let message = match payload.downcast::<String>() {
+		Ok(message) => *message,
+		Err(payload) => payload
+			.downcast_ref::<&str>()
+			.map_or("unknown panic", |message| message)
+			.to_string(),
+	};
+	format!("Panicked: {}", message)
+}
+
+/// Reads a NUL-terminated UTF-8 string argument.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a NUL-terminated string.
+unsafe fn str_arg<'a>(ptr: *const c_char) -> Result<&'a str> {
+	if ptr.is_null() {
+		return Err(Failure(
+			ApmlStatus::InvalidArgument,
+			"Null pointer argument".to_string(),
+		));
+	}
+	unsafe { CStr::from_ptr(ptr) }
+		.to_str()
+		.map_err(|err| Failure(ApmlStatus::InvalidArgument, err.to_string()))
+}
+
+fn to_c_string(value: String) -> Result<CString> {
+	CString::new(value).map_err(|err| {
+		Failure(
+			ApmlStatus::Unrepresentable,
+			format!("Value contains a NUL byte at {}", err.nul_position()),
+		)
+	})
+}
+
+/// Converts a message into a C string, dropping NUL bytes.
+fn to_c_string_lossy(message: String) -> CString {
+	CString::new(message.replace('\0', ""))
+		.expect("NUL bytes have been removed")
+}
+
+/// Parses and evaluates a source of `len` bytes.
+///
+/// Unless `out_handle` is null, a handle is always stored into it, even
+/// if evaluation fails, so that the error can be read with
+/// [`apml_last_error`]. The handle must be released with
+/// [`apml_free_handle`].
+///
+/// # Safety
+///
+/// `src` must point to `len` readable bytes, and `out_handle` must be
+/// null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apml_eval_source(
+	src: *const c_char,
+	len: usize,
+	out_handle: *mut *mut ApmlHandle,
+) -> ApmlStatus {
+	if out_handle.is_null() || (src.is_null() && len != 0) {
+		return ApmlStatus::InvalidArgument;
+	}
+	let src = if len == 0 {
+		&[][..]
+	} else {
+		unsafe { std::slice::from_raw_parts(src.cast::<u8>(), len) }
+	};
+	let mut handle = ApmlHandle::default();
+	let result = guard(|| {
+		let src = std::str::from_utf8(src).map_err(|err| {
+			Failure(ApmlStatus::InvalidArgument, err.to_string())
+		})?;
+		ApmlContext::eval_source(src)
+			.map_err(|err| Failure(ApmlStatus::EvalError, err.to_string()))
+	});
+	handle.context = handle.record(result);
+	let status = handle.status;
+	unsafe { *out_handle = Box::into_raw(Box::new(handle)) };
+	status
+}
+
+/// Returns the value of a variable as a string, or null on failure.
+///
+/// Arrays are joined with spaces. The string must be released with
+/// [`apml_free_string`].
+///
+/// # Safety
+///
+/// `handle` must be null or a handle from [`apml_eval_source`], and
+/// `name` must be null or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apml_get_string(
+	handle: *mut ApmlHandle,
+	name: *const c_char,
+) -> *mut c_char {
+	let Some(handle) = (unsafe { handle.as_mut() }) else {
+		return ptr::null_mut();
+	};
+	handle
+		.read(name, |value| to_c_string(value.as_string()))
+		.map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Returns the value of a variable as an array of strings, or null on
+/// failure.
+///
+/// Strings are split into words. The number of elements is stored into
+/// `out_len`. The array must be released with [`apml_free_array`],
+/// which also releases the elements. Empty arrays are not null.
+///
+/// # Safety
+///
+/// `handle` must be null or a handle from [`apml_eval_source`], `name`
+/// must be null or point to a NUL-terminated string, and `out_len` must
+/// be null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apml_get_array(
+	handle: *mut ApmlHandle,
+	name: *const c_char,
+	out_len: *mut usize,
+) -> *mut *mut c_char {
+	let Some(handle) = (unsafe { handle.as_mut() }) else {
+		return ptr::null_mut();
+	};
+	if out_len.is_null() {
+		handle.record::<()>(Err(Failure(
+			ApmlStatus::InvalidArgument,
+			"Null pointer argument".to_string(),
+		)));
+		return ptr::null_mut();
+	}
+	let Some(elements) = handle.read(name, |value| {
+		value
+			.as_array()
+			.into_iter()
+			.map(to_c_string)
+			.collect::<Result<Vec<_>>>()
+	}) else {
+		return ptr::null_mut();
+	};
+	unsafe { *out_len = elements.len() };
+	let elements = elements
+		.into_iter()
+		.map(CString::into_raw)
+		.collect::<Box<[_]>>();
+	Box::into_raw(elements).cast()
+}
+
+/// Returns the status of the last call on a handle.
+///
+/// Failures of [`apml_eval_source`] are kept until the handle is
+/// released, as nothing can be read from the handle.
+///
+/// # Safety
+///
+/// `handle` must be null or a handle from [`apml_eval_source`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apml_last_status(
+	handle: *const ApmlHandle,
+) -> ApmlStatus {
+	unsafe { handle.as_ref() }
+		.map_or(ApmlStatus::InvalidArgument, |handle| handle.status)
+}
+
+/// Returns the error message of the last call on a handle, or null if
+/// the call succeeded.
+///
+/// The message is owned by the handle, and is valid until the next call
+/// on the handle.
+///
+/// # Safety
+///
+/// `handle` must be null or a handle from [`apml_eval_source`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apml_last_error(
+	handle: *const ApmlHandle,
+) -> *const c_char {
+	unsafe { handle.as_ref() }
+		.and_then(|handle| handle.error.as_ref())
+		.map_or(ptr::null(), |error| error.as_ptr())
+}
+
+/// Releases a handle.
+///
+/// # Safety
+///
+/// `handle` must be null or a handle from [`apml_eval_source`] that has
+/// not been released.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apml_free_handle(handle: *mut ApmlHandle) {
+	if !handle.is_null() {
+		drop(unsafe { Box::from_raw(handle) });
+	}
+}
+
+/// Releases a string returned by [`apml_get_string`].
+///
+/// # Safety
+///
+/// `string` must be null or a string from [`apml_get_string`] that has
+/// not been released.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apml_free_string(string: *mut c_char) {
+	if !string.is_null() {
+		drop(unsafe { CString::from_raw(string) });
+	}
+}
+
+/// Releases an array returned by [`apml_get_array`] along with its
+/// elements.
+///
+/// # Safety
+///
+/// `array` must be null or an array from [`apml_get_array`] that has not
+/// been released, and `len` must be its length.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn apml_free_array(array: *mut *mut c_char, len: usize) {
+	if array.is_null() {
+		return;
+	}
+	let elements =
+		unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(array, len)) };
+	for element in elements {
+		drop(unsafe { CString::from_raw(element) });
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_capi() {
+		let src = "A=1\nB=(x \"y z\")\n";
+		let mut handle = ptr::null_mut();
+		unsafe {
+			assert_eq!(
+				apml_eval_source(src.as_ptr().cast(), src.len(), &mut handle),
+				ApmlStatus::Ok
+			);
+			let value = apml_get_string(handle, c"B".as_ptr());
+			assert_eq!(CStr::from_ptr(value), c"x y z");
+			apml_free_string(value);
+			let mut len = 0;
+			let array = apml_get_array(handle, c"B".as_ptr(), &mut len);
+			assert_eq!(len, 2);
+			assert_eq!(CStr::from_ptr(*array.add(1)), c"y z");
+			apml_free_array(array, len);
+
+			assert!(apml_get_string(handle, c"C".as_ptr()).is_null());
+			assert_eq!(apml_last_status(handle), ApmlStatus::NotFound);
+			assert_eq!(
+				CStr::from_ptr(apml_last_error(handle)),
+				c"Variable is not defined: C"
+			);
+			assert!(apml_get_string(handle, ptr::null()).is_null());
+			assert_eq!(apml_last_status(handle), ApmlStatus::InvalidArgument);
+			apml_free_handle(handle);
+
+			assert_eq!(
+				apml_eval_source(c"A=\"".as_ptr(), 3, &mut handle),
+				ApmlStatus::EvalError
+			);
+			assert!(!apml_last_error(handle).is_null());
+			assert!(apml_get_string(handle, c"A".as_ptr()).is_null());
+			assert_eq!(apml_last_status(handle), ApmlStatus::EvalError);
+			apml_free_handle(handle);
+		}
+
+		let Err(Failure(status, message)) =
+			guard::<()>(|| panic!("{}", "boom"))
+		else {
+			panic!("panics should be caught");
+		};
+		assert_eq!(status, ApmlStatus::Panic);
+		assert_eq!(message, "Panicked: boom");
+	}
+}
@@ -0,0 +1,51 @@
+#include <stdio.h>
+#include <string.h>
+
+#include "apml.h"
+
+#define CHECK(cond)                                                        \
+	do {                                                                   \
+		if (!(cond)) {                                                     \
+			fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,         \
+			        __LINE__, #cond);                                      \
+			return 1;                                                      \
+		}                                                                  \
+	} while (0)
+
+int main(void) {
+	const char *src = "PKGNAME=foo\n"
+	                  "PKGVER=1.0\n"
+	                  "SRCS=(\"tbl::https://x/$PKGNAME-$PKGVER.tar.gz\" b)\n";
+	ApmlHandle *handle = NULL;
+	CHECK(apml_eval_source(src, strlen(src), &handle) == APML_STATUS_OK);
+	CHECK(apml_last_error(handle) == NULL);
+
+	char *name = apml_get_string(handle, "PKGNAME");
+	CHECK(name != NULL && strcmp(name, "foo") == 0);
+	apml_free_string(name);
+
+	size_t len = 0;
+	char **srcs = apml_get_array(handle, "SRCS", &len);
+	CHECK(srcs != NULL && len == 2);
+	CHECK(strcmp(srcs[0], "tbl::https://x/foo-1.0.tar.gz") == 0);
+	CHECK(strcmp(srcs[1], "b") == 0);
+	apml_free_array(srcs, len);
+
+	CHECK(apml_get_string(handle, "PKGDES") == NULL);
+	CHECK(apml_last_status(handle) == APML_STATUS_NOT_FOUND);
+	CHECK(strcmp(apml_last_error(handle), "Variable is not defined: PKGDES") ==
+	      0);
+	apml_free_handle(handle);
+
+	const char *bad = "A=\"";
+	CHECK(apml_eval_source(bad, strlen(bad), &handle) ==
+	      APML_STATUS_EVAL_ERROR);
+	CHECK(apml_last_error(handle) != NULL);
+	CHECK(apml_get_string(handle, "A") == NULL);
+	apml_free_handle(handle);
+
+	CHECK(apml_eval_source(NULL, 1, &handle) == APML_STATUS_INVALID_ARGUMENT);
+
+	puts("ok");
+	return 0;
+}
@@ -0,0 +1,59 @@
+//! Round-trip test of the C ABI, driven from a C program.
+//!
+//! The program in `tests/capi` is built against `include/apml.h` and the
+//! `cdylib` of this crate. The test is skipped if no C compiler is found.
+
+use std::{
+	env,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	process::Command,
+};
+
+/// Returns the directory containing the `cdylib`.
+fn lib_dir() -> PathBuf {
+	// test binaries are built next to the libraries in target/<profile>/deps
+	let exe = env::current_exe().unwrap();
+	exe.parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn test_roundtrip() {
+	let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+	let lib_dir = lib_dir();
+	let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("capi-roundtrip");
+	let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+	let status = match Command::new(&cc)
+		.arg("-Wall")
+		.arg("-Werror")
+		.arg("-I")
+		.arg(root.join("include"))
+		.arg(root.join("tests/capi/roundtrip.c"))
+		.arg("-L")
+		.arg(&lib_dir)
+		.arg("-llibabbs_capi")
+		.arg("-o")
+		.arg(&out)
+		.status()
+	{
+		Ok(status) => status,
+		Err(err) if err.kind() == ErrorKind::NotFound => {
+			eprintln!("skipped: C compiler {} not found", cc);
+			return;
+		}
+		Err(err) => panic!("failed to run {}: {}", cc, err),
+	};
+	assert!(status.success(), "failed to compile the C program");
+
+	let output = Command::new(&out)
+		.env("LD_LIBRARY_PATH", &lib_dir)
+		.env("DYLD_LIBRARY_PATH", &lib_dir)
+		.output()
+		.unwrap();
+	assert!(
+		output.status.success(),
+		"{}",
+		String::from_utf8_lossy(&output.stderr)
+	);
+	assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
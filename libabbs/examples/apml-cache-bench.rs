@@ -0,0 +1,68 @@
+use std::{
+	env::{self},
+	fs,
+	path::Path,
+	time::Instant,
+};
+
+use libabbs::apml::{
+	cache::{DiskCache, MemoryCache, ParseCache, parse_cached},
+	lst::ApmlLst,
+};
+
+fn collect_apml(path: &Path, result: &mut Vec<String>) {
+	for entry in path.read_dir().unwrap() {
+		let entry = entry.unwrap();
+		if entry.file_name() == "spec"
+			|| entry
+				.file_name()
+				.to_str()
+				.unwrap_or_default()
+				.starts_with("defines")
+		{
+			result.push(fs::read_to_string(entry.path()).unwrap());
+		} else if entry.file_type().unwrap().is_dir() {
+			collect_apml(&entry.path(), result);
+		}
+	}
+}
+
+fn bench(name: &str, srcs: &[String], parse: impl Fn(&str) -> ApmlLst) {
+	let start = Instant::now();
+	for _ in 0..10 {
+		for src in srcs {
+			assert_eq!(parse(src).to_string(), *src);
+		}
+	}
+	let elapsed = start.elapsed();
+	println!("{}: loaded {} files in {:?}", name, srcs.len(), elapsed);
+}
+
+fn main() {
+	let tree = env::var("TREE").expect("TREE env var must be set");
+	let mut srcs = Vec::new();
+	collect_apml(Path::new(&tree), &mut srcs);
+
+	bench("parse", &srcs, |src| ApmlLst::parse(src).expect(src));
+
+	let memory = MemoryCache::new();
+	fill(&memory, &srcs);
+	bench("memory cache", &srcs, |src| {
+		parse_cached(src, &memory).expect(src)
+	});
+
+	let dir = env::temp_dir()
+		.join(format!("libabbs-cache-bench-{}", std::process::id()));
+	let disk = DiskCache::new(&dir);
+	fill(&disk, &srcs);
+	bench("disk cache", &srcs, |src| {
+		parse_cached(src, &disk).expect(src)
+	});
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+fn fill(cache: &dyn ParseCache, srcs: &[String]) {
+	for src in srcs {
+		parse_cached(src, cache).expect(src);
+	}
+}
@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+use libabbs::apml::{
+	ApmlContext, ReadContext,
+	arch::{ArchMap, resolve_arch},
+};
+
+/// Number of variables in the base context.
+const VARIABLES: usize = 1000;
+/// Number of overlays or clones created in each round.
+const COPIES: usize = 100;
+/// Number of rounds, of which the fastest is taken.
+const ROUNDS: usize = 20;
+
+fn fastest<T>(mut round: impl FnMut() -> T) -> Duration {
+	(0..ROUNDS)
+		.map(|_| {
+			let start = Instant::now();
+			std::hint::black_box(round());
+			start.elapsed()
+		})
+		.min()
+		.unwrap()
+}
+
+fn main() {
+	let mut context = ApmlContext::new();
+	for i in 0..VARIABLES {
+		context.insert(format!("VAR{i}"), format!("value of {i}").into());
+	}
+	context.insert("PKGDEP".to_string(), "a".into());
+	context.insert("PKGDEP__LOONGARCH64".to_string(), "b".into());
+	let base = context.freeze();
+
+	let overlay = fastest(|| {
+		(0..COPIES)
+			.map(|i| base.overlay([("ARCH", format!("arch{i}").into())]))
+			.collect::<Vec<_>>()
+	});
+	let clone = fastest(|| {
+		(0..COPIES)
+			.map(|i| {
+				let mut context = (*base).clone();
+				context.insert("ARCH".to_string(), format!("arch{i}").into());
+				context
+			})
+			.collect::<Vec<_>>()
+	});
+	println!(
+		"{COPIES} overlays: {overlay:?} ({:?} each), \
+		{COPIES} clones: {clone:?} ({:?} each)",
+		overlay / COPIES as u32,
+		clone / COPIES as u32,
+	);
+
+	// what-if resolution for each architecture through overlays
+	let map = ArchMap::aosc();
+	let arches = map.arches().into_iter().collect::<Vec<_>>();
+	let resolve = fastest(|| {
+		arches
+			.iter()
+			.map(|arch| {
+				let view = base.overlay([("ARCH", arch.to_string().into())]);
+				resolve_arch(&view, arch, &map).unwrap()
+			})
+			.collect::<Vec<_>>()
+	});
+	println!("resolved {} architectures in {resolve:?}", arches.len());
+
+	let views = (0..COPIES)
+		.map(|i| base.overlay([("ARCH", format!("arch{i}").into())]))
+		.collect::<Vec<_>>();
+	assert!(views.iter().enumerate().all(|(i, view)| {
+		view.read("VAR999") == "value of 999"
+			&& view.read("ARCH") == format!("arch{i}")
+	}));
+	assert!(
+		overlay * 10 < clone,
+		"creating overlays should be at least 10 times as fast as cloning"
+	);
+}
@@ -0,0 +1,50 @@
+//! Loads all APML files in a tree and reports per-file timing
+//! using the `tracing` instrumentation.
+//!
+//! Spans are printed by `tracing-subscriber` when they are closed, along
+//! with the time spent in them. Each `apml_load` and `apml_eval` span
+//! carries the path of the source.
+
+use std::{env, fs, path::Path};
+
+use libabbs::apml::{batch::load_tree, eval::EvalOptions};
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+fn collect_apml(path: &Path, result: &mut Vec<(String, String)>) {
+	for entry in path.read_dir().unwrap() {
+		let entry = entry.unwrap();
+		if entry.file_name() == "spec"
+			|| entry
+				.file_name()
+				.to_str()
+				.unwrap_or_default()
+				.starts_with("defines")
+		{
+			result.push((
+				entry.path().display().to_string(),
+				fs::read_to_string(entry.path()).unwrap(),
+			));
+		} else if entry.file_type().unwrap().is_dir() {
+			collect_apml(&entry.path(), result);
+		}
+	}
+}
+
+fn main() {
+	let tree = env::var("TREE").expect("TREE env var must be set");
+	let mut srcs = Vec::new();
+	collect_apml(Path::new(&tree), &mut srcs);
+
+	tracing_subscriber::fmt()
+		.with_max_level(Level::DEBUG)
+		.with_span_events(FmtSpan::CLOSE)
+		.with_writer(std::io::stderr)
+		.init();
+	let result = load_tree(srcs, EvalOptions::default, None);
+	println!(
+		"loaded {} files, {} failed",
+		result.len(),
+		result.failures.len()
+	);
+}
@@ -0,0 +1,197 @@
+//! Static analysis of APML syntax trees.
+
+use super::{
+	HashMap,
+	ast::{self, AstNode},
+	lst::{self, ApmlLst},
+	span::Span,
+};
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+/// A reference to a variable which occurs before the first definition of
+/// the variable.
+///
+/// As evaluation is strictly top-to-bottom, such a reference expands to
+/// an empty value rather than the value defined later.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ForwardRef {
+	/// Name of the referenced variable.
+	pub name: String,
+	/// Name of the variable whose definition contains the reference.
+	pub referencing: String,
+	/// Span of the reference.
+	///
+	/// The span of a reference nested in a modifier covers the whole
+	/// outermost expansion.
+	pub reference: Span,
+	/// Span of the first definition of the referenced variable.
+	pub definition: Span,
+}
+
+impl ForwardRef {
+	/// Returns a message suggesting to move the definition up.
+	pub fn explain(&self) -> String {
+		format!(
+			"{name} is referenced by {referencing} before {name} is defined, \
+			so the reference expands to an empty value. \
+			Move the definition of {name} before {referencing}.",
+			name = self.name,
+			referencing = self.referencing,
+		)
+	}
+}
+
+/// Finds references to variables which occur textually before the first
+/// definition of the variables.
+///
+/// Each referenced variable is reported at most once for each
+/// definition. Self-references, such as the desugared form of appending,
+/// are not forward references.
+///
+/// References to variables provided by a seed context are expanded to
+/// their values from the context; callers evaluating with a seed should
+/// drop those with
+/// [`ApmlContext::contains_var`](super::ApmlContext::contains_var).
+pub fn forward_references(lst: &ApmlLst) -> Vec<ForwardRef> {
+	let defs = lst.variable_spans().collect::<Vec<_>>();
+	let mut first_defs = HashMap::new();
+	for (span, def) in &defs {
+		first_defs.entry(def.name.as_ref()).or_insert(*span);
+	}
+	let mut result = Vec::new();
+	for (def_span, def) in &defs {
+		let mut seen = Vec::new();
+		for (span, word) in def.word_spans() {
+			for name in word_references(word) {
+				if seen.contains(&name) {
+					continue;
+				}
+				if let Some(&definition) = first_defs.get(name.as_str())
+					&& definition.start > def_span.start
+				{
+					result.push(ForwardRef {
+						name: name.clone(),
+						referencing: def.name.to_string(),
+						reference: Span::with_len(
+							def_span.start + span.start,
+							span.len(),
+						),
+						definition,
+					});
+				}
+				seen.push(name);
+			}
+		}
+	}
+	result
+}
+
+/// Collects names of variables referenced in a value, including
+/// references nested in modifiers.
+pub(crate) fn value_references<'a>(
+	value: &'a ast::VariableValue,
+	out: &mut Vec<&'a str>,
+) {
+	match value {
+		ast::VariableValue::String(text) => text_references(text, out),
+		ast::VariableValue::Array(elements) => {
+			for element in elements {
+				match element {
+					ast::ArrayElement::ArrayInclusion(name) => out.push(name),
+					ast::ArrayElement::Text(text) => text_references(text, out),
+				}
+			}
+		}
+	}
+}
+
+fn text_references<'a>(text: &'a ast::Text, out: &mut Vec<&'a str>) {
+	for word in &text.0 {
+		if let ast::Word::Variable(expansion)
+		| ast::Word::SplitVariable(expansion) = word
+		{
+			expansion_references(expansion, out);
+		}
+	}
+}
+
+fn expansion_references<'a>(
+	expansion: &'a ast::VariableExpansion,
+	out: &mut Vec<&'a str>,
+) {
+	out.push(&expansion.name);
+	if let Some(
+		ast::ExpansionModifier::ReplaceOnce { string, .. }
+		| ast::ExpansionModifier::ReplaceAll { string, .. }
+		| ast::ExpansionModifier::ReplacePrefix { string, .. }
+		| ast::ExpansionModifier::ReplaceSuffix { string, .. }
+		| ast::ExpansionModifier::ErrorOnUnset(string)
+		| ast::ExpansionModifier::WhenUnset(string)
+		| ast::ExpansionModifier::WhenSet(string),
+	) = &expansion.modifier
+	{
+		text_references(string, out);
+	}
+}
+
+/// Returns names of variables referenced by a LST word, including
+/// references nested in modifiers.
+pub(crate) fn word_references(word: &lst::Word) -> Vec<String> {
+	match word {
+		lst::Word::UnbracedVariable(name) => vec![name.to_string()],
+		lst::Word::BracedVariable(exp) => {
+			match ast::VariableExpansion::emit_from(exp) {
+				Ok(expansion) => {
+					let mut names = Vec::new();
+					expansion_references(&expansion, &mut names);
+					names.into_iter().map(str::to_string).collect()
+				}
+				Err(_) => vec![exp.name.to_string()],
+			}
+		}
+		lst::Word::Custom(exp) => {
+			exp.args.iter().map(|(_, arg)| arg.to_string()).collect()
+		}
+		lst::Word::Literal(_) | lst::Word::Subcommand(_) => Vec::new(),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::ApmlContext;
+
+	#[test]
+	fn test_forward_references() {
+		let src = "A=\"$B ${C:-${D}}\"\nB=1\nC=(\"${E[@]}\")\nE=2\nD=3\n\
+			B+=\"$B\"\nF+=x\nG=\"$H\"\nH=4\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let mut refs = forward_references(&lst);
+		assert_eq!(refs.last().unwrap().name, "H");
+		let mut seed = ApmlContext::default();
+		seed.insert("H".to_string(), "h".into());
+		refs.retain(|r| !seed.contains_var(&r.name));
+		assert_eq!(
+			refs.iter()
+				.map(|r| (
+					r.name.as_str(),
+					r.reference.slice(src),
+					r.definition.slice(src)
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				("B", "$B", "B=1"),
+				("C", "${C:-${D}}", "C=(\"${E[@]}\")"),
+				("D", "${C:-${D}}", "D=3"),
+				("E", "${E[@]}", "E=2"),
+			]
+		);
+		assert_eq!(
+			refs[0].explain(),
+			"B is referenced by A before B is defined, so the reference \
+			expands to an empty value. Move the definition of B before A."
+		);
+	}
+}
@@ -0,0 +1,814 @@
+//! Architecture-specific overrides.
+//!
+//! Variables can be overridden for a specific architecture or for
+//! a group of architectures with a suffix, for example `PKGDEP__AMD64`
+//! or `PKGDEP__RETRO`. See [`resolve_arch`].
+//!
+//! Architectures and groups are described by an [`ArchMap`], which is
+//! usually loaded from the tree with [`ArchMap::from_context`].
+
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	fmt::Display,
+};
+
+use thiserror::Error;
+
+use super::{ApmlContext, ReadContext, lst::ApmlLst, span::Span};
+
+/// Errors produced while resolving overrides.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ArchError {
+	#[error(
+		"Conflicting overrides of {name} from groups of the same size: {}",
+		groups.join(", ")
+	)]
+	GroupConflict { name: String, groups: Vec<String> },
+}
+
+/// Errors produced while validating architecture maps.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ArchMapError {
+	#[error("Invalid architecture or group name: {0:?}")]
+	InvalidName(String),
+	#[error("Unknown group in exclusive groups: {0}")]
+	UnknownGroup(String),
+	#[error("{arch} is in exclusive groups: {}", groups.join(", "))]
+	ExclusiveConflict { arch: String, groups: Vec<String> },
+}
+
+/// A map describing architecture groups.
+///
+/// Group names and architecture names are case-insensitive in
+/// override suffixes, and stored in lower case. The known architectures
+/// are those in any group.
+///
+/// Trees declare their own groups, see [`ArchMap::from_context`].
+/// [`ArchMap::aosc`] is only a fallback for trees which do not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchMap {
+	groups: BTreeMap<String, BTreeSet<String>>,
+	exclusive: Vec<BTreeSet<String>>,
+}
+
+impl ArchMap {
+	/// Suffix of the field declaring exclusive groups, see
+	/// [`ArchMap::from_context`].
+	pub const EXCLUSIVE_FIELD: &str = "EXCLUSIVE";
+
+	/// Creates a map without any groups.
+	pub fn empty() -> Self {
+		Self {
+			groups: BTreeMap::new(),
+			exclusive: Vec::new(),
+		}
+	}
+
+	/// Loads groups declared in a context.
+	///
+	/// Each variable named `<field_prefix><GROUP>` declares the
+	/// architectures of a group, as a space-separated string or an array.
+	/// For example, with the prefix `ARCH_GROUP_`:
+	///
+	/// ```bash
+	/// ARCH_GROUP_MAINLINE="amd64 arm64"
+	/// ARCH_GROUP_RETRO=(i486 m68k)
+	/// ARCH_GROUP_EXCLUSIVE=("mainline,retro")
+	/// ```
+	///
+	/// `<field_prefix>EXCLUSIVE` is not a group, but lists sets of
+	/// comma-separated groups which must not share architectures, see
+	/// [`ArchMap::insert_exclusive`].
+	///
+	/// The context is usually evaluated by the caller from a central file
+	/// of the tree. The map is [validated][ArchMap::validate].
+	pub fn from_context<C: ReadContext + ?Sized>(
+		context: &C,
+		field_prefix: &str,
+	) -> Result<Self, ArchMapError> {
+		let mut map = Self::empty();
+		for (name, value) in context.iter() {
+			let Some(group) = name.strip_prefix(field_prefix) else {
+				continue;
+			};
+			if group == Self::EXCLUSIVE_FIELD {
+				for groups in value.as_array() {
+					map.insert_exclusive(groups.split(','));
+				}
+			} else {
+				map.insert_group(group, value.as_array());
+			}
+		}
+		map.validate()?;
+		Ok(map)
+	}
+
+	/// Creates a map with groups used in AOSC OS.
+	///
+	/// This is a fallback for trees not declaring their groups, and may
+	/// drift from the actual groups of AOSC OS.
+	pub fn aosc() -> Self {
+		let mut map = Self::empty();
+		map.insert_group(
+			"mainline",
+			[
+				"amd64",
+				"arm64",
+				"loongarch64",
+				"loongson3",
+				"ppc64el",
+				"riscv",
+			],
+		);
+		map.insert_group(
+			"retro",
+			[
+				"armv4",
+				"armv6hf",
+				"armv7hf",
+				"i486",
+				"loongson2f",
+				"m68k",
+				"powerpc",
+				"ppc64",
+			],
+		);
+		map.insert_group(
+			"arch_lp64",
+			[
+				"amd64",
+				"arm64",
+				"loongarch64",
+				"loongson3",
+				"ppc64",
+				"ppc64el",
+				"riscv",
+			],
+		);
+		map.insert_group(
+			"arch_ilp32",
+			[
+				"armv4",
+				"armv6hf",
+				"armv7hf",
+				"i486",
+				"loongson2f",
+				"m68k",
+				"powerpc",
+			],
+		);
+		map.insert_exclusive(["mainline", "retro"]);
+		map.insert_exclusive(["arch_lp64", "arch_ilp32"]);
+		map
+	}
+
+	/// Inserts or replaces a group.
+	pub fn insert_group<N, I, A>(&mut self, name: N, arches: I)
+	where
+		N: AsRef<str>,
+		I: IntoIterator<Item = A>,
+		A: AsRef<str>,
+	{
+		self.groups.insert(
+			name.as_ref().to_ascii_lowercase(),
+			arches
+				.into_iter()
+				.map(|arch| arch.as_ref().to_ascii_lowercase())
+				.collect(),
+		);
+	}
+
+	/// Removes a group.
+	///
+	/// The group is also removed from exclusive groups.
+	pub fn remove_group(&mut self, name: &str) -> Option<BTreeSet<String>> {
+		let name = name.to_ascii_lowercase();
+		for groups in &mut self.exclusive {
+			groups.remove(&name);
+		}
+		self.groups.remove(&name)
+	}
+
+	/// Declares groups which must not share architectures.
+	///
+	/// This is checked by [`ArchMap::validate`].
+	pub fn insert_exclusive<I, N>(&mut self, groups: I)
+	where
+		I: IntoIterator<Item = N>,
+		N: AsRef<str>,
+	{
+		self.exclusive.push(
+			groups
+				.into_iter()
+				.map(|name| name.as_ref().to_ascii_lowercase())
+				.collect(),
+		);
+	}
+
+	/// Iterates over sets of exclusive groups.
+	pub fn exclusive(&self) -> impl Iterator<Item = &BTreeSet<String>> {
+		self.exclusive.iter()
+	}
+
+	/// Gets architectures in a group.
+	pub fn group(&self, name: &str) -> Option<&BTreeSet<String>> {
+		self.groups.get(&name.to_ascii_lowercase())
+	}
+
+	/// Iterates over all groups.
+	pub fn groups(&self) -> impl Iterator<Item = (&String, &BTreeSet<String>)> {
+		self.groups.iter()
+	}
+
+	/// Iterates over names of groups containing an architecture.
+	pub fn groups_of(&self, arch: &str) -> impl Iterator<Item = &String> {
+		let arch = arch.to_ascii_lowercase();
+		self.groups
+			.iter()
+			.filter(move |(_, arches)| arches.contains(&arch))
+			.map(|(name, _)| name)
+	}
+
+	/// Returns all known architectures, which are those in any group.
+	pub fn arches(&self) -> BTreeSet<&String> {
+		self.groups.values().flatten().collect()
+	}
+
+	/// Checks the map.
+	///
+	/// Names of groups and architectures must consist of ASCII letters,
+	/// digits and underscores, without leading, trailing or consecutive
+	/// underscores, so that they are usable as override suffixes. Each
+	/// architecture must be in at most one group of each set of
+	/// exclusive groups, whose groups must exist.
+	pub fn validate(&self) -> Result<(), ArchMapError> {
+		fn is_valid(name: &str) -> bool {
+			!name.is_empty()
+				&& name
+					.chars()
+					.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+				&& !name.starts_with('_')
+				&& !name.ends_with('_')
+				&& !name.contains("__")
+		}
+		for (group, arches) in &self.groups {
+			if let Some(name) = [group]
+				.into_iter()
+				.chain(arches)
+				.find(|name| !is_valid(name))
+			{
+				return Err(ArchMapError::InvalidName(name.clone()));
+			}
+		}
+		for groups in &self.exclusive {
+			if let Some(group) = groups
+				.iter()
+				.find(|group| !self.groups.contains_key(*group))
+			{
+				return Err(ArchMapError::UnknownGroup(group.clone()));
+			}
+			for arch in self.arches() {
+				let containing = groups
+					.iter()
+					.filter(|group| self.groups[*group].contains(arch))
+					.cloned()
+					.collect::<Vec<_>>();
+				if containing.len() > 1 {
+					return Err(ArchMapError::ExclusiveConflict {
+						arch: arch.clone(),
+						groups: containing,
+					});
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns if a name is a group or an architecture in any group.
+	pub(crate) fn is_known(&self, name: &str) -> bool {
+		self.groups.contains_key(name)
+			|| self.groups.values().any(|arches| arches.contains(name))
+	}
+}
+
+/// Source of a value of a variable on an architecture.
+///
+/// Sources are ordered by precedence, see [`resolve_arch`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OverrideSource {
+	/// The override for the architecture itself, such as `PKGDEP__AMD64`.
+	Arch,
+	/// The override for a group containing the architecture, such as
+	/// `PKGDEP__MAINLINE`.
+	Group {
+		/// Name of the group, in lower case.
+		name: String,
+		/// Number of architectures in the group.
+		size: usize,
+	},
+	/// The variable itself, such as `PKGDEP`.
+	Base,
+}
+
+impl OverrideSource {
+	/// Returns a key ordering sources by precedence.
+	fn rank(&self) -> (u8, usize) {
+		match self {
+			OverrideSource::Arch => (0, 0),
+			OverrideSource::Group { size, .. } => (1, *size),
+			OverrideSource::Base => (2, 0),
+		}
+	}
+
+	/// Returns why a value from the source takes effect, as a clause.
+	pub(crate) fn reason(&self) -> String {
+		match self {
+			OverrideSource::Arch => {
+				"overrides for the architecture take precedence".to_string()
+			}
+			OverrideSource::Group { name, size } => format!(
+				"{} is the smallest group with an override ({} \
+				architectures)",
+				name, size
+			),
+			OverrideSource::Base => {
+				"no override for the architecture or its groups is defined"
+					.to_string()
+			}
+		}
+	}
+}
+
+/// Classifies a variable as a source of a value on an architecture.
+///
+/// Returns the base name and the source, or [`None`] if the variable
+/// overrides another architecture or group known by the map.
+fn classify<'a>(
+	name: &'a str,
+	arch: &str,
+	map: &ArchMap,
+) -> Option<(&'a str, OverrideSource)> {
+	match name.rsplit_once("__") {
+		Some((base, suffix)) if !base.is_empty() => {
+			let suffix = suffix.to_ascii_lowercase();
+			if suffix == arch {
+				Some((base, OverrideSource::Arch))
+			} else if let Some(arches) = map.groups.get(&suffix) {
+				arches.contains(arch).then_some((
+					base,
+					OverrideSource::Group {
+						name: suffix,
+						size: arches.len(),
+					},
+				))
+			} else if map.is_known(&suffix) {
+				None
+			} else {
+				Some((name, OverrideSource::Base))
+			}
+		}
+		_ => Some((name, OverrideSource::Base)),
+	}
+}
+
+/// Picks the candidate taking precedence among sources of a variable.
+///
+/// Returns [`ArchError::GroupConflict`] if the candidates taking
+/// precedence are overrides for groups of the same size.
+fn choose<'a, T>(
+	name: &str,
+	candidates: &'a [(OverrideSource, T)],
+) -> Result<&'a (OverrideSource, T), ArchError> {
+	let best = candidates
+		.iter()
+		.min_by_key(|(source, _)| source.rank())
+		.expect("variables have at least one candidate");
+	if let OverrideSource::Group { size, .. } = best.0 {
+		let mut groups = candidates
+			.iter()
+			.filter_map(|(source, _)| match source {
+				OverrideSource::Group { name, size: other }
+					if *other == size =>
+				{
+					Some(name.clone())
+				}
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		if groups.len() > 1 {
+			groups.sort();
+			return Err(ArchError::GroupConflict {
+				name: name.to_string(),
+				groups,
+			});
+		}
+	}
+	Ok(best)
+}
+
+/// Collects the sources of each variable on an architecture, with base
+/// names in the order of their first source.
+fn candidates<'c, C: ReadContext + ?Sized>(
+	context: &'c C,
+	arch: &str,
+	map: &ArchMap,
+) -> Vec<(&'c str, Vec<(OverrideSource, &'c str)>)> {
+	let mut candidates = Vec::<(&str, Vec<_>)>::new();
+	let mut indices = HashMap::new();
+	for name in context.keys() {
+		let Some((base, source)) = classify(name, arch, map) else {
+			continue;
+		};
+		let index = *indices.entry(base).or_insert_with(|| {
+			candidates.push((base, Vec::new()));
+			candidates.len() - 1
+		});
+		candidates[index].1.push((source, name.as_str()));
+	}
+	candidates
+}
+
+/// Resolves architecture-specific overrides for an architecture.
+///
+/// For each variable `NAME`, the value is taken from, in order of
+/// precedence:
+///
+/// 1. `NAME__<ARCH>`, the override for the concrete architecture,
+/// 2. `NAME__<GROUP>`, the override for a group containing the
+///    architecture, with smaller groups taking precedence over larger
+///    ones,
+/// 3. `NAME` itself.
+///
+/// If overrides from multiple groups of the same size take precedence,
+/// [`ArchError::GroupConflict`] is returned rather than picking one
+/// arbitrarily. Groups in the error are sorted by name. Such conflicts
+/// can be found on all architectures beforehand with
+/// [`lint::check_override_conflicts`], and a single resolution is
+/// explained by [`explain_override`].
+///
+/// Overrides for other architectures and groups known by the map are
+/// dropped from the result. Variables with other suffixes are kept
+/// as is.
+///
+/// [`lint::check_override_conflicts`]: super::lint::check_override_conflicts
+pub fn resolve_arch<C: ReadContext + ?Sized>(
+	context: &C,
+	arch: &str,
+	map: &ArchMap,
+) -> Result<ApmlContext, ArchError> {
+	let arch = arch.to_ascii_lowercase();
+	let mut result = ApmlContext::default();
+	for (base, candidates) in candidates(context, &arch, map) {
+		let (_, chosen) = choose(base, &candidates)?;
+		result.insert(base.to_string(), context.read(chosen));
+	}
+	Ok(result)
+}
+
+/// Returns the conflicts of overrides from groups on an architecture,
+/// which make [`resolve_arch`] fail, in the order of variables.
+pub(crate) fn group_conflicts<C: ReadContext + ?Sized>(
+	context: &C,
+	arch: &str,
+	map: &ArchMap,
+) -> Vec<ArchError> {
+	let arch = arch.to_ascii_lowercase();
+	candidates(context, &arch, map)
+		.into_iter()
+		.filter_map(|(base, candidates)| choose(base, &candidates).err())
+		.collect()
+}
+
+/// A definition considered by [`explain_override`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OverrideCandidate {
+	/// Name of the variable, such as `PKGDEP__AMD64`.
+	pub name: String,
+	/// Source of the value.
+	pub source: OverrideSource,
+	/// Span of the last definition of the variable in the LST, if
+	/// defined there.
+	pub span: Option<Span>,
+}
+
+impl Display for OverrideCandidate {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.write_str(&self.name)?;
+		if let Some(span) = self.span {
+			write!(f, " ({})", span)?;
+		}
+		Ok(())
+	}
+}
+
+/// Explanation of the resolution of a variable on an architecture, see
+/// [`explain_override`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OverrideExplanation {
+	/// Name of the variable without suffixes.
+	pub name: String,
+	/// The architecture, in lower case.
+	pub arch: String,
+	/// Defined variables applicable on the architecture, in order of
+	/// precedence.
+	pub candidates: Vec<OverrideCandidate>,
+}
+
+impl OverrideExplanation {
+	/// Returns the definition taking effect, if any.
+	///
+	/// This is [`None`] if the variable is not defined for the
+	/// architecture, or the resolution is [ambiguous][Self::is_ambiguous].
+	pub fn winner(&self) -> Option<&OverrideCandidate> {
+		if self.is_ambiguous() {
+			return None;
+		}
+		self.candidates.first()
+	}
+
+	/// Returns if overrides from multiple groups of the same size take
+	/// precedence, which makes [`resolve_arch`] fail.
+	pub fn is_ambiguous(&self) -> bool {
+		matches!(
+			self.candidates.as_slice(),
+			[first, second, ..]
+				if matches!(first.source, OverrideSource::Group { .. })
+					&& first.source.rank() == second.source.rank()
+		)
+	}
+
+	/// Describes which candidate applies and why, or why none does.
+	pub fn explain(&self) -> String {
+		let Some(winner) = self.winner() else {
+			if self.candidates.is_empty() {
+				return format!(
+					"{} is not defined on {}.",
+					self.name, self.arch
+				);
+			}
+			let tied = self
+				.candidates
+				.iter()
+				.take_while(|candidate| {
+					candidate.source.rank() == self.candidates[0].source.rank()
+				})
+				.map(|candidate| candidate.to_string())
+				.collect::<Vec<_>>();
+			return format!(
+				"{} is ambiguous on {}, as {} override groups of the same \
+				size.",
+				self.name,
+				self.arch,
+				tied.join(" and ")
+			);
+		};
+		let mut result = format!(
+			"{} applies on {}, as {}.",
+			winner,
+			self.arch,
+			winner.source.reason()
+		);
+		if self.candidates.len() > 1 {
+			let others = self.candidates[1..]
+				.iter()
+				.map(|candidate| candidate.to_string())
+				.collect::<Vec<_>>();
+			result.push_str(&format!(
+				" It takes precedence over {}.",
+				others.join(", ")
+			));
+		}
+		result
+	}
+}
+
+/// Explains which definition of a variable takes effect on an
+/// architecture, and why.
+///
+/// `name` is the variable without suffixes, such as `PKGDEP`. Candidates
+/// are taken from the context, following the precedence of
+/// [`resolve_arch`], and their spans from the LST, which is usually the
+/// file the context is evaluated from.
+pub fn explain_override<C: ReadContext + ?Sized>(
+	context: &C,
+	lst: &ApmlLst,
+	name: &str,
+	arch: &str,
+	map: &ArchMap,
+) -> OverrideExplanation {
+	let arch = arch.to_ascii_lowercase();
+	let mut candidates = context
+		.keys()
+		.filter_map(|variable| {
+			let (base, source) = classify(variable, &arch, map)?;
+			(base == name).then(|| OverrideCandidate {
+				name: variable.clone(),
+				source,
+				span: lst
+					.variable_spans()
+					.filter(|(_, def)| def.name == *variable)
+					.map(|(span, _)| span)
+					.last(),
+			})
+		})
+		.collect::<Vec<_>>();
+	candidates.sort_by_key(|candidate| candidate.source.rank());
+	OverrideExplanation {
+		name: name.to_string(),
+		arch,
+		candidates,
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_arch_map() {
+		let map = ArchMap::aosc();
+		assert_eq!(
+			map.groups_of("AMD64").collect::<Vec<_>>(),
+			vec!["arch_lp64", "mainline"]
+		);
+		assert!(map.group("RETRO").unwrap().contains("i486"));
+		let mut map = ArchMap::empty();
+		map.insert_group("G", ["A"]);
+		assert!(map.group("g").unwrap().contains("a"));
+		assert_eq!(map.groups().count(), 1);
+		assert!(map.remove_group("g").is_some());
+		assert_eq!(map.groups().count(), 0);
+	}
+
+	#[test]
+	fn test_arch_map_from_context() {
+		let context = ApmlContext::eval_source(
+			"ARCH_GROUP_MAINLINE=\"amd64 arm64\"\n\
+			ARCH_GROUP_RETRO=(i486 m68k)\nARCH_GROUP_LP64=(amd64 arm64)\n\
+			ARCH_GROUP_EXCLUSIVE=(mainline,retro)\nOTHER=x\n",
+		)
+		.unwrap();
+		let map = ArchMap::from_context(&context, "ARCH_GROUP_").unwrap();
+		assert_eq!(
+			map.groups().map(|(name, _)| name).collect::<Vec<_>>(),
+			vec!["lp64", "mainline", "retro"]
+		);
+		assert_eq!(
+			map.arches().into_iter().collect::<Vec<_>>(),
+			vec!["amd64", "arm64", "i486", "m68k"]
+		);
+		assert_eq!(
+			map.exclusive().collect::<Vec<_>>(),
+			vec![&BTreeSet::from(["mainline".into(), "retro".into()])]
+		);
+		assert!(ArchMap::aosc().validate().is_ok());
+
+		let load = |src: &str| {
+			ArchMap::from_context(&ApmlContext::eval_source(src).unwrap(), "G_")
+		};
+		assert_eq!(
+			load("G_A=(x y)\nG_B=(y)\nG_C=(y)\nG_EXCLUSIVE=(a,b,c)\n"),
+			Err(ArchMapError::ExclusiveConflict {
+				arch: "y".into(),
+				groups: vec!["a".into(), "b".into(), "c".into()],
+			})
+		);
+		assert_eq!(
+			load("G_A=(x)\nG_EXCLUSIVE=(a,b)\n"),
+			Err(ArchMapError::UnknownGroup("b".into()))
+		);
+		assert_eq!(
+			load("G_A=(x-y)\n"),
+			Err(ArchMapError::InvalidName("x-y".into()))
+		);
+		assert_eq!(
+			load("G_A__B=(x)\n"),
+			Err(ArchMapError::InvalidName("a__b".into()))
+		);
+
+		let mut map = ArchMap::aosc();
+		map.insert_group("retro", ["AMD64"]);
+		assert_eq!(
+			map.validate(),
+			Err(ArchMapError::ExclusiveConflict {
+				arch: "amd64".into(),
+				groups: vec!["mainline".into(), "retro".into()],
+			})
+		);
+		map.remove_group("retro");
+		assert!(map.validate().is_ok());
+	}
+
+	#[test]
+	fn test_resolve_arch() {
+		let context = ApmlContext::eval_source(
+			"PKGDEP=a\nPKGDEP__RETRO=b\nPKGDEP__I486=c\nPKGDEP__AMD64=d\n\
+			SRCS__GIT=e\nA__RETRO=f\nB=g\nB__MAINLINE=h\nB__ARCH_LP64=i\n",
+		)
+		.unwrap();
+		let map = ArchMap::aosc();
+		let resolve = |arch| {
+			resolve_arch(&context, arch, &map)
+				.unwrap()
+				.iter()
+				.map(|(name, value)| format!("{}={}", name, value.as_string()))
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(
+			resolve("i486"),
+			vec!["PKGDEP=c", "SRCS__GIT=e", "A=f", "B=g"]
+		);
+		assert_eq!(
+			resolve("armv4"),
+			vec!["PKGDEP=b", "SRCS__GIT=e", "A=f", "B=g"]
+		);
+		// mainline is smaller than arch_lp64
+		assert_eq!(resolve("amd64"), vec!["PKGDEP=d", "SRCS__GIT=e", "B=h"]);
+		let mut map = ArchMap::empty();
+		map.insert_group("mainline", ["amd64", "arm64"]);
+		map.insert_group("arch_lp64", ["amd64", "ppc64"]);
+		assert_eq!(
+			resolve_arch(&context, "amd64", &map).unwrap_err(),
+			ArchError::GroupConflict {
+				name: "B".to_string(),
+				groups: vec!["arch_lp64".to_string(), "mainline".to_string()],
+			}
+		);
+		assert_eq!(resolve_arch(&context, "arm64", &map).unwrap()["B"], "h");
+		map.insert_group("mainline", ["amd64"]);
+		let resolved = resolve_arch(&context, "amd64", &map).unwrap();
+		assert_eq!(resolved["PKGDEP"], "d");
+		assert_eq!(resolved["B"], "h");
+		assert!(!resolved.contains_var("A"));
+		// overrides for unknown suffixes are kept
+		assert_eq!(resolved["PKGDEP__RETRO"], "b");
+	}
+
+	#[test]
+	fn test_explain_override() {
+		let src = "PKGDEP=a\nPKGDEP__ARM=b\nPKGDEP__ARM64=c\nPKGDEP__ALL=d\n\
+			PKGDEP__X86=e\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let context = ApmlContext::eval_lst(&lst).unwrap();
+		let mut map = ArchMap::empty();
+		map.insert_group("arm", ["arm64", "armv7hf"]);
+		map.insert_group("all", ["amd64", "arm64", "armv7hf"]);
+		map.insert_group("x86", ["amd64", "i486", "i686"]);
+
+		let explanation =
+			explain_override(&context, &lst, "PKGDEP", "ARM64", &map);
+		assert_eq!(
+			explanation
+				.candidates
+				.iter()
+				.map(|candidate| candidate.name.as_str())
+				.collect::<Vec<_>>(),
+			vec!["PKGDEP__ARM64", "PKGDEP__ARM", "PKGDEP__ALL", "PKGDEP"]
+		);
+		assert_eq!(
+			explanation.winner().unwrap().span.unwrap().slice(src),
+			"PKGDEP__ARM64=c"
+		);
+		assert_eq!(
+			explanation.explain(),
+			"PKGDEP__ARM64 (23..38) applies on arm64, as overrides for the \
+			architecture take precedence. It takes precedence over \
+			PKGDEP__ARM (9..22), PKGDEP__ALL (39..52), PKGDEP (0..8)."
+		);
+
+		let explanation =
+			explain_override(&context, &lst, "PKGDEP", "armv7hf", &map);
+		assert_eq!(explanation.winner().unwrap().name, "PKGDEP__ARM");
+		assert_eq!(
+			explanation.candidates[0].source,
+			OverrideSource::Group {
+				name: "arm".into(),
+				size: 2
+			}
+		);
+		assert!(explanation.explain().starts_with(
+			"PKGDEP__ARM (9..22) applies on armv7hf, as arm is the smallest \
+			group with an override (2 architectures)."
+		));
+
+		let explanation =
+			explain_override(&context, &lst, "PKGDEP", "amd64", &map);
+		assert!(explanation.is_ambiguous());
+		assert_eq!(explanation.winner(), None);
+		assert_eq!(
+			explanation.explain(),
+			"PKGDEP is ambiguous on amd64, as PKGDEP__ALL (39..52) and \
+			PKGDEP__X86 (53..66) override groups of the same size."
+		);
+
+		let explanation =
+			explain_override(&context, &lst, "PKGDEP", "m68k", &map);
+		assert_eq!(
+			explanation.explain(),
+			"PKGDEP (0..8) applies on m68k, as no override for the \
+			architecture or its groups is defined."
+		);
+		let explanation = explain_override(&context, &lst, "A", "m68k", &map);
+		assert_eq!(explanation.explain(), "A is not defined on m68k.");
+	}
+}
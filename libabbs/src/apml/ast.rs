@@ -26,11 +26,15 @@
 //! Although not all LST nodes can be represented in AST form, all AST
 //! nodes must have a valid LST form.
 
-use std::{borrow::Cow, cmp::max, num::ParseIntError, sync::Arc};
+use alloc::{borrow::Cow, sync::Arc};
+use core::{cmp::max, num::ParseIntError};
 
 use thiserror::Error;
 
-use super::{lst, pattern::BashPattern};
+use super::{lst, pattern::BashPattern, span::Span};
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 
 /// Trait for AST nodes.
 ///
@@ -45,28 +49,269 @@ pub trait AstNode: Sized {
 	fn lower(&self) -> Self::LST;
 }
 
+/// Errors produced while emitting a LST into AST.
+///
+/// The `Unsupported*` variants carry the span of a [`Construct`] and are
+/// only produced by [`ApmlAst::emit_supported`], which locates the
+/// constructs in the source before emitting. [`AstNode::emit_from`]
+/// works on nodes without positions, emits unsupported constructs as
+/// literals where possible, and fails only with the other variants.
 #[derive(Debug, Error)]
 pub enum EmitError {
+	/// A node which is not valid on its own, such as a comment taken as
+	/// an array element.
 	#[error("Unrepresentable LST node")]
 	Unrepresentable,
+	/// A substring offset or length which is not a literal integer.
+	///
+	/// [`ApmlAst::emit_supported`] reports these as
+	/// [`EmitError::UnsupportedArithmetic`] instead.
 	#[error("Unparsable integer: {0}")]
 	UnparsableInt(#[from] ParseIntError),
 	#[error("Missing delimiters between root elements")]
 	MissingRootElementDelimiter,
 	#[error("Missing delimiters between array elements")]
 	MissingArrayElementDelimiter,
+	#[error(
+		"Unsupported command substitution at {0}: {suggestion}",
+		suggestion = Construct::CommandSubstitution.suggestion()
+	)]
+	UnsupportedCommandSubstitution(Span),
+	#[error(
+		"Unsupported here-document at {0}: {suggestion}",
+		suggestion = Construct::HereDoc.suggestion()
+	)]
+	UnsupportedHereDoc(Span),
+	#[error(
+		"Unsupported control flow at {0}: {suggestion}",
+		suggestion = Construct::ControlFlow.suggestion()
+	)]
+	UnsupportedControlFlow(Span),
+	#[error(
+		"Unsupported redirection at {0}: {suggestion}",
+		suggestion = Construct::Redirect.suggestion()
+	)]
+	UnsupportedRedirect(Span),
+	#[error(
+		"Unsupported tilde expansion at {0}: {suggestion}",
+		suggestion = Construct::TildeExpansion.suggestion()
+	)]
+	UnsupportedTildeExpansion(Span),
+	#[error(
+		"Unsupported pathname expansion at {0}: {suggestion}",
+		suggestion = Construct::PathnameExpansion.suggestion()
+	)]
+	UnsupportedPathnameExpansion(Span),
+	#[error(
+		"Unsupported brace expansion at {0}: {suggestion}",
+		suggestion = Construct::BraceExpansion.suggestion()
+	)]
+	UnsupportedBraceExpansion(Span),
+	#[error(
+		"Unsupported arithmetic expression at {0}: {suggestion}",
+		suggestion = Construct::Arithmetic.suggestion()
+	)]
+	UnsupportedArithmetic(Span),
+	#[error(
+		"Unsupported custom expansion at {0}: {suggestion}",
+		suggestion = Construct::CustomExpansion.suggestion()
+	)]
+	UnsupportedCustomExpansion(Span),
+	#[error(
+		"Unsupported word splitting at {0}: {suggestion}",
+		suggestion = Construct::WordSplitting.suggestion()
+	)]
+	UnsupportedWordSplitting(Span),
+	#[error(
+		"Unsupported shell option at {0}: {suggestion}",
+		suggestion = Construct::ShellOption.suggestion()
+	)]
+	UnsupportedShellOption(Span),
 }
 
-pub type EmitResult<T> = std::result::Result<T, EmitError>;
+impl EmitError {
+	/// Returns the unsupported construct reported by the error, if any.
+	pub fn unsupported(&self) -> Option<Unsupported> {
+		let (construct, span) = match self {
+			EmitError::UnsupportedCommandSubstitution(span) => {
+				(Construct::CommandSubstitution, span)
+			}
+			EmitError::UnsupportedHereDoc(span) => (Construct::HereDoc, span),
+			EmitError::UnsupportedControlFlow(span) => {
+				(Construct::ControlFlow, span)
+			}
+			EmitError::UnsupportedRedirect(span) => (Construct::Redirect, span),
+			EmitError::UnsupportedTildeExpansion(span) => {
+				(Construct::TildeExpansion, span)
+			}
+			EmitError::UnsupportedPathnameExpansion(span) => {
+				(Construct::PathnameExpansion, span)
+			}
+			EmitError::UnsupportedBraceExpansion(span) => {
+				(Construct::BraceExpansion, span)
+			}
+			EmitError::UnsupportedArithmetic(span) => {
+				(Construct::Arithmetic, span)
+			}
+			EmitError::UnsupportedCustomExpansion(span) => {
+				(Construct::CustomExpansion, span)
+			}
+			EmitError::UnsupportedWordSplitting(span) => {
+				(Construct::WordSplitting, span)
+			}
+			EmitError::UnsupportedShellOption(span) => {
+				(Construct::ShellOption, span)
+			}
+			EmitError::Unrepresentable
+			| EmitError::UnparsableInt(_)
+			| EmitError::MissingRootElementDelimiter
+			| EmitError::MissingArrayElementDelimiter => return None,
+		};
+		Some(Unsupported {
+			construct,
+			span: *span,
+		})
+	}
+
+	/// Returns a suggestion for fixing the source, if the error reports
+	/// an unsupported construct.
+	pub fn suggestion(&self) -> Option<&'static str> {
+		self.unsupported()
+			.map(|unsupported| unsupported.construct.suggestion())
+	}
+}
+
+impl From<Unsupported> for EmitError {
+	fn from(value: Unsupported) -> Self {
+		let span = value.span;
+		match value.construct {
+			Construct::CommandSubstitution => {
+				EmitError::UnsupportedCommandSubstitution(span)
+			}
+			Construct::HereDoc => EmitError::UnsupportedHereDoc(span),
+			Construct::ControlFlow => EmitError::UnsupportedControlFlow(span),
+			Construct::Redirect => EmitError::UnsupportedRedirect(span),
+			Construct::TildeExpansion => {
+				EmitError::UnsupportedTildeExpansion(span)
+			}
+			Construct::PathnameExpansion => {
+				EmitError::UnsupportedPathnameExpansion(span)
+			}
+			Construct::BraceExpansion => {
+				EmitError::UnsupportedBraceExpansion(span)
+			}
+			Construct::Arithmetic => EmitError::UnsupportedArithmetic(span),
+			Construct::CustomExpansion => {
+				EmitError::UnsupportedCustomExpansion(span)
+			}
+			Construct::WordSplitting => {
+				EmitError::UnsupportedWordSplitting(span)
+			}
+			Construct::ShellOption => EmitError::UnsupportedShellOption(span),
+		}
+	}
+}
+
+/// A shell construct that the evaluator cannot model.
+///
+/// This is the single enumeration of unsupported constructs shared by
+/// [`ApmlAst::emit_supported`] and [classification][lst::ApmlLst::classify].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Construct {
+	/// Command substitutions (`$(...)` and backquotes).
+	CommandSubstitution,
+	/// Here-documents and here-strings (`<<`).
+	HereDoc,
+	/// Unquoted control operators (`;`, `|` and `&`).
+	ControlFlow,
+	/// Unquoted redirections (`<` and `>`).
+	Redirect,
+	/// Tilde expansions (`~/...`), at the start of a word or after an
+	/// unquoted `:` in a string assignment.
+	TildeExpansion,
+	/// Unquoted pathname expansion patterns in arrays (`*`, `?`, `[`).
+	PathnameExpansion,
+	/// Unquoted brace expansions in arrays (`{a,b}`).
+	BraceExpansion,
+	/// Arithmetic expressions, such as non-literal substring offsets.
+	Arithmetic,
+	/// Custom expansions (`${@name args}`), which are not bash syntax.
+	///
+	/// See [`lst::CustomExpansion`].
+	CustomExpansion,
+	/// Unquoted expansions in arrays, which bash splits into words and
+	/// expands as pathname patterns.
+	WordSplitting,
+	/// `set` statements with options that the evaluator does not support.
+	///
+	/// See [`ShellOptions::apply`][super::eval::ShellOptions::apply].
+	ShellOption,
+}
+
+impl Construct {
+	/// Returns a short suggestion for rewriting the construct.
+	pub fn suggestion(&self) -> &'static str {
+		match self {
+			Construct::CommandSubstitution => {
+				"move this logic into the build script"
+			}
+			Construct::HereDoc => "write the text as a quoted string",
+			Construct::ControlFlow => {
+				"quote the value, or move this logic into the build script"
+			}
+			Construct::Redirect => "move the redirection into the build script",
+			Construct::TildeExpansion => "write the path in full",
+			Construct::PathnameExpansion => "quote the pattern",
+			Construct::BraceExpansion => "list the elements explicitly",
+			Construct::Arithmetic => "use a literal integer",
+			Construct::CustomExpansion => {
+				"evaluate the file with the custom expansions supplied"
+			}
+			Construct::WordSplitting => "quote the expansion",
+			Construct::ShellOption => "remove the option",
+		}
+	}
+}
+
+/// An occurrence of an unsupported [`Construct`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Unsupported {
+	/// The construct.
+	pub construct: Construct,
+	/// Span of the construct in the source.
+	pub span: Span,
+}
+
+pub type EmitResult<T> = core::result::Result<T, EmitError>;
 
 /// A APML abstract syntax tree.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ApmlAst<'a>(pub Vec<VariableDefinition<'a>>);
 
+impl<'a> ApmlAst<'a> {
+	/// Emits a LST into AST, rejecting constructs that the evaluator
+	/// cannot model.
+	///
+	/// Unlike [`ApmlAst::emit_from`], which emits such constructs as
+	/// literals where possible, this fails with the first construct
+	/// reported by [`lst::ApmlLst::unsupported_constructs`]. A LST is
+	/// emitted by this if and only if it is
+	/// [classified][lst::ApmlLst::classify] as data-only.
+	#[cfg(feature = "std")]
+	pub fn emit_supported(lst: &lst::ApmlLst<'a>) -> EmitResult<Self> {
+		if let Some(unsupported) = lst.unsupported_constructs().first() {
+			return Err((*unsupported).into());
+		}
+		Self::emit_from(lst)
+	}
+}
+
 impl<'a> AstNode for ApmlAst<'a> {
 	type LST = lst::ApmlLst<'a>;
 
 	fn emit_from(lst: &Self::LST) -> EmitResult<Self> {
+		#[cfg(feature = "tracing")]
+		let _span = tracing::debug_span!("apml_emit", tokens = lst.0.len()).entered();
 		enum State {
 			/// Ready for elements
 			Ready,
@@ -79,7 +324,7 @@ impl<'a> AstNode for ApmlAst<'a> {
 		let mut result = Vec::new();
 		for token in &lst.0 {
 			match token {
-				lst::Token::Spacy(_) => {}
+				lst::Token::Spacy(_) | lst::Token::LineContinuation => {}
 				lst::Token::Newline => state = State::Ready,
 				lst::Token::Comment(_) => state = State::NeedNewline,
 				lst::Token::Variable(def) => {
@@ -90,6 +335,14 @@ impl<'a> AstNode for ApmlAst<'a> {
 						return Err(EmitError::MissingRootElementDelimiter);
 					}
 				}
+				// shell options are not kept in AST, see `eval::ShellOptions`
+				lst::Token::Set(_) => {
+					if matches!(state, State::Ready) {
+						state = State::NeedDelimiter;
+					} else {
+						return Err(EmitError::MissingRootElementDelimiter);
+					}
+				}
 			}
 		}
 		Ok(Self(result))
@@ -190,7 +443,8 @@ impl<'a> AstNode for VariableValue<'a> {
 				let mut result = Vec::new();
 				for token in tokens {
 					match token {
-						lst::ArrayToken::Spacy(_) => {
+						lst::ArrayToken::Spacy(_)
+						| lst::ArrayToken::LineContinuation => {
 							if matches!(
 								state,
 								State::NeedDelimiter | State::Ready
@@ -253,16 +507,40 @@ impl<'a> AstNode for Text<'a> {
 	}
 
 	fn lower(&self) -> Self::LST {
-		lst::Text(vec![lst::TextUnit::DoubleQuote(
-			self.0.iter().map(Word::lower).collect(),
-		)])
+		let mut units = Vec::new();
+		let mut words = Vec::new();
+		for word in &self.0 {
+			if let Word::AnsiCQuote(text) = word {
+				if !words.is_empty() {
+					units.push(lst::TextUnit::DoubleQuote(core::mem::take(
+						&mut words,
+					)));
+				}
+				units.push(lst::TextUnit::AnsiCQuote(text.clone()));
+			} else if let Word::SplitVariable(_) = word {
+				if !words.is_empty() {
+					units.push(lst::TextUnit::DoubleQuote(core::mem::take(
+						&mut words,
+					)));
+				}
+				units.push(lst::TextUnit::Unquoted(vec![word.lower()]));
+			} else {
+				words.push(word.lower());
+			}
+		}
+		if !words.is_empty() || units.is_empty() {
+			units.push(lst::TextUnit::DoubleQuote(words));
+		}
+		lst::Text(units)
 	}
 }
 
 /// Emits a LST literal string part as string.
 fn emit_text_unit<'a>(lst: &lst::TextUnit<'a>) -> EmitResult<Vec<Word<'a>>> {
 	match lst {
-		lst::TextUnit::Unquoted(words) | lst::TextUnit::DoubleQuote(words) => {
+		lst::TextUnit::Unquoted(words)
+		| lst::TextUnit::DoubleQuote(words)
+		| lst::TextUnit::LocaleQuote(words) => {
 			let mut result = Vec::new();
 			for word in words {
 				result.push(Word::emit_from(word)?);
@@ -272,6 +550,9 @@ fn emit_text_unit<'a>(lst: &lst::TextUnit<'a>) -> EmitResult<Vec<Word<'a>>> {
 		lst::TextUnit::SingleQuote(text) => {
 			Ok(vec![Word::Literal(text.clone())])
 		}
+		lst::TextUnit::AnsiCQuote(text) => {
+			Ok(vec![Word::AnsiCQuote(text.clone())])
+		}
 	}
 }
 
@@ -298,10 +579,23 @@ pub enum Word<'a> {
 	Literal(Cow<'a, str>),
 	/// A variable expansion.
 	Variable(VariableExpansion<'a>),
+	/// An unquoted variable expansion in an array element.
+	///
+	/// As in bash, the value is split into fields on `IFS`, see
+	/// [`eval_word`][super::eval::eval_word]. The word is lowered as an
+	/// unquoted expansion.
+	SplitVariable(VariableExpansion<'a>),
 	/// A complete subcommand string, including `$(` and `)`.
 	///
 	/// The inner string is escaped.
 	Subcommand(Cow<'a, str>),
+	/// The text of an ANSI-C quoted string, excluding `$'` and `'`.
+	///
+	/// The inner string is escaped, and decoded during evaluation.
+	/// When lowered alone, the word is only valid in unquoted texts.
+	AnsiCQuote(Cow<'a, str>),
+	/// A custom expansion.
+	Custom(CustomExpansion<'a>),
 }
 
 impl<'a> AstNode for Word<'a> {
@@ -346,6 +640,9 @@ impl<'a> AstNode for Word<'a> {
 			lst::Word::Subcommand(_) => {
 				Ok(Self::Subcommand(lst.to_string().into()))
 			}
+			lst::Word::Custom(exp) => {
+				Ok(Self::Custom(CustomExpansion::emit_from(exp)?))
+			}
 		}
 	}
 
@@ -354,12 +651,49 @@ impl<'a> AstNode for Word<'a> {
 			Word::Literal(text) => {
 				lst::Word::Literal(lst::LiteralPart::escape(text))
 			}
-			Word::Variable(expansion) => {
+			Word::Variable(expansion) | Word::SplitVariable(expansion) => {
 				lst::Word::BracedVariable(expansion.lower())
 			}
 			Word::Subcommand(text) => {
 				lst::Word::Literal(vec![lst::LiteralPart::String(text.clone())])
 			}
+			Word::AnsiCQuote(text) => {
+				lst::Word::Literal(vec![lst::LiteralPart::String(
+					format!("$'{}'", text).into(),
+				)])
+			}
+			Word::Custom(exp) => lst::Word::Custom(exp.lower()),
+		}
+	}
+}
+
+/// A custom expansion, see [`lst::CustomExpansion`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CustomExpansion<'a> {
+	/// Name of the expansion.
+	pub name: Cow<'a, str>,
+	/// Names of variables passed as arguments.
+	pub args: Vec<Cow<'a, str>>,
+}
+
+impl<'a> AstNode for CustomExpansion<'a> {
+	type LST = lst::CustomExpansion<'a>;
+
+	fn emit_from(lst: &Self::LST) -> EmitResult<Self> {
+		Ok(Self {
+			name: lst.name.clone(),
+			args: lst.args.iter().map(|(_, arg)| arg.clone()).collect(),
+		})
+	}
+
+	fn lower(&self) -> Self::LST {
+		lst::CustomExpansion {
+			name: self.name.clone(),
+			args: self
+				.args
+				.iter()
+				.map(|arg| (Cow::Borrowed(" "), arg.clone()))
+				.collect(),
 		}
 	}
 }
@@ -386,14 +720,7 @@ impl<'a> AstNode for VariableExpansion<'a> {
 	type LST = lst::BracedExpansion<'a>;
 
 	fn emit_from(lst: &Self::LST) -> EmitResult<Self> {
-		let modifier = lst.modifier.as_ref().take_if(|modifier| {
-			!matches!(
-				modifier,
-				lst::ExpansionModifier::SingleWordElements
-					| lst::ExpansionModifier::ArrayElements
-			)
-		});
-		let modifier = if let Some(modifier) = modifier {
+		let modifier = if let Some(modifier) = &lst.modifier {
 			Some(ExpansionModifier::emit_from(modifier)?)
 		} else {
 			None
@@ -414,12 +741,8 @@ impl<'a> AstNode for VariableExpansion<'a> {
 
 /// A modifier in the variable expansion.
 ///
-/// When emitting from LST, `SingleWordElements` is cannot be emitted as
-/// `${NAME[*]}` is the same as `${NAME}` and the caller should discard the modifier.
-///
-/// `ArrayElements` is also unrepresentable and should be discarded.
-/// In strings, it should be the same as no modifier is provided.
-/// In array, it should be emitted as [`ArrayElement::ArrayInclusion`].
+/// A sole quoted `ArrayElements` element in arrays is emitted as
+/// [`ArrayElement::ArrayInclusion`] instead.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ExpansionModifier<'a> {
 	/// Reference to a substring.
@@ -476,6 +799,16 @@ pub enum ExpansionModifier<'a> {
 	WhenUnset(Arc<Text<'a>>),
 	/// Returning a text when the variable is set.
 	WhenSet(Arc<Text<'a>>),
+	/// Joining array elements with spaces.
+	///
+	/// This is the same as no modifier, except that unset variables are
+	/// accepted under `set -u`.
+	ArrayElements,
+	/// Joining array elements with the first character of `IFS`.
+	///
+	/// If `IFS` is unset, elements are joined with a space.
+	/// If `IFS` is empty, elements are joined without separators.
+	SingleWordElements,
 }
 
 impl<'a> AstNode for ExpansionModifier<'a> {
@@ -568,11 +901,9 @@ impl<'a> AstNode for ExpansionModifier<'a> {
 			lst::ExpansionModifier::WhenSet(text) => {
 				Ok(Self::WhenSet(Arc::new(Text::emit_from(text)?)))
 			}
-			lst::ExpansionModifier::ArrayElements => {
-				Err(EmitError::Unrepresentable)
-			}
+			lst::ExpansionModifier::ArrayElements => Ok(Self::ArrayElements),
 			lst::ExpansionModifier::SingleWordElements => {
-				Err(EmitError::Unrepresentable)
+				Ok(Self::SingleWordElements)
 			}
 		}
 	}
@@ -643,6 +974,12 @@ impl<'a> AstNode for ExpansionModifier<'a> {
 			ExpansionModifier::WhenSet(text) => {
 				lst::ExpansionModifier::WhenSet(Arc::new(text.lower()))
 			}
+			ExpansionModifier::ArrayElements => {
+				lst::ExpansionModifier::ArrayElements
+			}
+			ExpansionModifier::SingleWordElements => {
+				lst::ExpansionModifier::SingleWordElements
+			}
 		}
 	}
 }
@@ -653,6 +990,10 @@ impl<'a> AstNode for ExpansionModifier<'a> {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ArrayElement<'a> {
 	/// A element expanding to all elements of another array.
+	///
+	/// As `"${NAME[@]}"` does, an unset variable expands to no elements,
+	/// while a string variable, even an empty one, expands to a single
+	/// element.
 	ArrayInclusion(Cow<'a, str>),
 	/// A text element.
 	Text(Arc<Text<'a>>),
@@ -665,14 +1006,15 @@ impl<'a> AstNode for ArrayElement<'a> {
 		match lst {
 			lst::ArrayToken::Spacy(_)
 			| lst::ArrayToken::Newline
+			| lst::ArrayToken::LineContinuation
 			| lst::ArrayToken::Comment(_) => Err(EmitError::Unrepresentable),
 			lst::ArrayToken::Element(text) => {
 				let units = &text.0;
 				if units.len() == 1 {
 					let unit = &units[0];
 					match unit {
-						lst::TextUnit::Unquoted(words)
-						| lst::TextUnit::DoubleQuote(words) => {
+						lst::TextUnit::DoubleQuote(words)
+						| lst::TextUnit::LocaleQuote(words) => {
 							if words.len() == 1 {
 								let word = &words[0];
 								if let lst::Word::BracedVariable(word) = word {
@@ -684,10 +1026,25 @@ impl<'a> AstNode for ArrayElement<'a> {
 								}
 							}
 						}
-						lst::TextUnit::SingleQuote(_) => {}
+						lst::TextUnit::Unquoted(_)
+						| lst::TextUnit::SingleQuote(_)
+						| lst::TextUnit::AnsiCQuote(_) => {}
+					}
+				}
+				// unquoted expansions are split into fields
+				let mut words = Vec::new();
+				for unit in units {
+					let mut emitted = emit_text_unit(unit)?;
+					if let lst::TextUnit::Unquoted(_) = unit {
+						for word in &mut emitted {
+							if let Word::Variable(expansion) = word {
+								*word = Word::SplitVariable(expansion.clone());
+							}
+						}
 					}
+					words.append(&mut emitted);
 				}
-				Ok(Self::Text(Arc::new(Text::emit_from(text)?)))
+				Ok(Self::Text(Arc::new(Text(words))))
 			}
 		}
 	}
@@ -1003,9 +1360,9 @@ mod test {
 			},
 			VariableExpansion {
 				name: "test".into(),
-				modifier: None,
+				modifier: Some(ExpansionModifier::ArrayElements),
 			},
-			"test",
+			"test[@]",
 		);
 		assert_emit_lower(
 			lst::BracedExpansion {
@@ -1014,9 +1371,9 @@ mod test {
 			},
 			VariableExpansion {
 				name: "test".into(),
-				modifier: None,
+				modifier: Some(ExpansionModifier::SingleWordElements),
 			},
-			"test",
+			"test[*]",
 		);
 	}
 
@@ -1217,11 +1574,15 @@ mod test {
 			ExpansionModifier::WhenSet(text_ast.clone()),
 			":+\"foo\\$\\\\\"",
 		);
-		assert_emit_fail::<ExpansionModifier, _>(
+		assert_emit_lower(
 			lst::ExpansionModifier::ArrayElements,
+			ExpansionModifier::ArrayElements,
+			"[@]",
 		);
-		assert_emit_fail::<ExpansionModifier, _>(
+		assert_emit_lower(
 			lst::ExpansionModifier::SingleWordElements,
+			ExpansionModifier::SingleWordElements,
+			"[*]",
 		);
 	}
 
@@ -1246,6 +1607,34 @@ mod test {
 			ArrayElement::Text(Arc::new(Text(vec![Word::Literal("a".into())]))),
 			"\"a\"",
 		);
+		assert_emit_lower(
+			lst::ArrayToken::Element(Arc::new(lst::Text(vec![
+				lst::TextUnit::Unquoted(vec![
+					lst::Word::Literal(vec![lst::LiteralPart::String(
+						"a".into(),
+					)]),
+					lst::Word::BracedVariable(lst::BracedExpansion {
+						name: "a".into(),
+						modifier: Some(lst::ExpansionModifier::ArrayElements),
+					}),
+				]),
+				lst::TextUnit::DoubleQuote(vec![lst::Word::UnbracedVariable(
+					"b".into(),
+				)]),
+			]))),
+			ArrayElement::Text(Arc::new(Text(vec![
+				Word::Literal("a".into()),
+				Word::SplitVariable(VariableExpansion {
+					name: "a".into(),
+					modifier: Some(ExpansionModifier::ArrayElements),
+				}),
+				Word::Variable(VariableExpansion {
+					name: "b".into(),
+					modifier: None,
+				}),
+			]))),
+			"\"a\"${a[@]}\"${b}\"",
+		);
 		assert_emit_fail::<ArrayElement, _>(lst::ArrayToken::Spacy(' '));
 		assert_emit_fail::<ArrayElement, _>(lst::ArrayToken::Newline);
 		assert_emit_fail::<ArrayElement, _>(lst::ArrayToken::Comment(
@@ -0,0 +1,539 @@
+//! Bulk loading of APML sources.
+//!
+//! See [`load_tree`], [`load_tree_with_stats`] and [`load_tree_cached`].
+//!
+//! With the `rayon` feature enabled, sources are parsed and evaluated
+//! in parallel. With the `tracing` feature enabled, each source is
+//! processed in an `apml_load` span carrying the source name, which is
+//! also passed to the `apml_eval` span as
+//! [`EvalOptions::source_name`].
+
+use std::{
+	collections::BTreeMap,
+	fmt::Display,
+	sync::atomic::{AtomicUsize, Ordering},
+	time::{Duration, Instant},
+};
+
+use super::{
+	ApmlContext, ApmlError, VariableValue,
+	cache::{ParseCache, parse_cached},
+	classify::{FileClass, classify_source},
+	eval::EvalOptions,
+	lst::ApmlLst,
+	parser::ParseError,
+};
+
+/// Result of [`load_tree`].
+#[derive(Debug, Default)]
+pub struct TreeLoadResult {
+	/// Successfully evaluated contexts, keyed by source name.
+	pub contexts: BTreeMap<String, ApmlContext>,
+	/// Sources failed to be parsed or evaluated, keyed by source name.
+	pub failures: BTreeMap<String, ApmlError>,
+	/// Statistics of sources, only collected by [`load_tree_with_stats`].
+	pub stats: Option<TreeStats>,
+}
+
+impl TreeLoadResult {
+	/// Returns the total number of loaded sources.
+	pub fn len(&self) -> usize {
+		self.contexts.len() + self.failures.len()
+	}
+
+	/// Returns if no sources are loaded.
+	pub fn is_empty(&self) -> bool {
+		self.contexts.is_empty() && self.failures.is_empty()
+	}
+
+	/// Returns if all sources are loaded successfully.
+	pub fn is_ok(&self) -> bool {
+		self.failures.is_empty()
+	}
+}
+
+/// Progress reporting configuration for [`load_tree`].
+#[derive(Clone, Copy)]
+pub struct Progress<'a> {
+	/// Number of files between two reports.
+	///
+	/// The callback is always invoked after the last file.
+	pub every: usize,
+	/// The callback, receiving the number of processed files and
+	/// the total number of files.
+	pub callback: &'a (dyn Fn(usize, usize) + Sync),
+}
+
+/// Parses and evaluates a set of named sources.
+///
+/// Each source is evaluated in its own context with options created by
+/// `options`, since options may carry stateful callbacks.
+/// Failure of a source never aborts loading of other sources.
+///
+/// If a name appears multiple times, the last one is kept.
+pub fn load_tree<I, N, S, O>(
+	sources: I,
+	options: O,
+	progress: Option<Progress>,
+) -> TreeLoadResult
+where
+	I: IntoIterator<Item = (N, S)>,
+	N: Into<String>,
+	S: AsRef<str> + Send + Sync,
+	O: Fn() -> EvalOptions + Sync,
+{
+	load(sources, options, progress, false, None)
+}
+
+/// Parses and evaluates a set of named sources like [`load_tree`],
+/// collecting statistics of each source into [`TreeLoadResult::stats`].
+///
+/// Each source is additionally classified with [`classify_source`].
+/// Timings are measured on the thread processing the source, so they
+/// are not comparable between runs with different degrees of
+/// parallelism.
+pub fn load_tree_with_stats<I, N, S, O>(
+	sources: I,
+	options: O,
+	progress: Option<Progress>,
+) -> TreeLoadResult
+where
+	I: IntoIterator<Item = (N, S)>,
+	N: Into<String>,
+	S: AsRef<str> + Send + Sync,
+	O: Fn() -> EvalOptions + Sync,
+{
+	load(sources, options, progress, true, None)
+}
+
+/// Parses and evaluates a set of named sources like [`load_tree`],
+/// reading parsed LSTs from a cache and storing newly parsed ones.
+///
+/// Sources found in the cache skip parsing. See [`parse_cached`]. Entries
+/// of [`DiskCache`] written with other versions of this crate or of the
+/// LST schema, as reported by [capabilities][super::capabilities], are
+/// parsed again.
+///
+/// [`DiskCache`]: super::cache::DiskCache
+pub fn load_tree_cached<I, N, S, O>(
+	sources: I,
+	options: O,
+	progress: Option<Progress>,
+	cache: &dyn ParseCache,
+) -> TreeLoadResult
+where
+	I: IntoIterator<Item = (N, S)>,
+	N: Into<String>,
+	S: AsRef<str> + Send + Sync,
+	O: Fn() -> EvalOptions + Sync,
+{
+	load(sources, options, progress, false, Some(cache))
+}
+
+fn load<I, N, S, O>(
+	sources: I,
+	options: O,
+	progress: Option<Progress>,
+	collect_stats: bool,
+	cache: Option<&dyn ParseCache>,
+) -> TreeLoadResult
+where
+	I: IntoIterator<Item = (N, S)>,
+	N: Into<String>,
+	S: AsRef<str> + Send + Sync,
+	O: Fn() -> EvalOptions + Sync,
+{
+	let sources = sources
+		.into_iter()
+		.map(|(name, src)| (name.into(), src))
+		.collect::<Vec<_>>();
+	let total = sources.len();
+	let done = AtomicUsize::new(0);
+	let load = |(name, src): &(String, S)| {
+		#[cfg(feature = "tracing")]
+		let _span = tracing::debug_span!("apml_load", name = %name).entered();
+		let mut options = options();
+		options.source_name.get_or_insert_with(|| name.clone());
+		let (result, stats) = if collect_stats {
+			let (result, stats) =
+				load_source_with_stats(src.as_ref(), &mut options, cache);
+			(result, Some(stats))
+		} else {
+			(load_source(src.as_ref(), &mut options, cache), None)
+		};
+		if let Some(progress) = progress {
+			let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+			if done == total
+				|| (progress.every != 0 && done.is_multiple_of(progress.every))
+			{
+				(progress.callback)(done, total);
+			}
+		}
+		(name.clone(), result, stats)
+	};
+
+	#[cfg(feature = "rayon")]
+	let results = {
+		use rayon::prelude::*;
+		sources.par_iter().map(load).collect::<Vec<_>>()
+	};
+	#[cfg(not(feature = "rayon"))]
+	let results = sources.iter().map(load).collect::<Vec<_>>();
+
+	let mut result = TreeLoadResult {
+		stats: collect_stats.then(TreeStats::default),
+		..Default::default()
+	};
+	for (name, loaded, stats) in results {
+		if let (Some(tree), Some(stats)) = (&mut result.stats, stats) {
+			tree.files.insert(name.clone(), stats);
+		}
+		match loaded {
+			Ok(context) => {
+				result.failures.remove(&name);
+				result.contexts.insert(name, context);
+			}
+			Err(err) => {
+				result.contexts.remove(&name);
+				result.failures.insert(name, err);
+			}
+		}
+	}
+	result
+}
+
+fn parse<'a>(
+	src: &'a str,
+	cache: Option<&dyn ParseCache>,
+) -> Result<ApmlLst<'a>, ParseError> {
+	match cache {
+		Some(cache) => parse_cached(src, cache),
+		None => ApmlLst::parse(src),
+	}
+}
+
+fn load_source(
+	src: &str,
+	options: &mut EvalOptions,
+	cache: Option<&dyn ParseCache>,
+) -> Result<ApmlContext, ApmlError> {
+	ApmlContext::eval_lst_with(&parse(src, cache)?, options)
+}
+
+fn load_source_with_stats(
+	src: &str,
+	options: &mut EvalOptions,
+	cache: Option<&dyn ParseCache>,
+) -> (Result<ApmlContext, ApmlError>, FileStats) {
+	let start = Instant::now();
+	let lst = parse(src, cache);
+	let parse_time = start.elapsed();
+	let mut stats = FileStats {
+		parse_time,
+		eval_time: Duration::ZERO,
+		variables: 0,
+		value_bytes: 0,
+		class: FileClass::DataOnly,
+	};
+	let lst = match lst {
+		Ok(lst) => lst,
+		Err(err) => {
+			stats.class = classify_source(src);
+			return (Err(err.into()), stats);
+		}
+	};
+	stats.class = lst.classify();
+	let start = Instant::now();
+	let result = ApmlContext::eval_lst_with(&lst, options);
+	stats.eval_time = start.elapsed();
+	if let Ok(context) = &result {
+		stats.variables = context.iter().count();
+		stats.value_bytes = context
+			.iter()
+			.map(|(_, value)| match value {
+				VariableValue::String(text) => text.len(),
+				VariableValue::Array(elements) => {
+					elements.iter().map(String::len).sum()
+				}
+			})
+			.sum();
+	}
+	(result, stats)
+}
+
+/// Statistics of loading a single source.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileStats {
+	/// Time spent on parsing.
+	pub parse_time: Duration,
+	/// Time spent on evaluation, zero if the source failed to be parsed.
+	pub eval_time: Duration,
+	/// Number of variables in the context, zero on failures.
+	pub variables: usize,
+	/// Total length of values in bytes, zero on failures.
+	pub value_bytes: usize,
+	/// Classification of the source.
+	pub class: FileClass,
+}
+
+impl FileStats {
+	/// Returns the time spent on parsing and evaluation.
+	pub fn total_time(&self) -> Duration {
+		self.parse_time + self.eval_time
+	}
+}
+
+/// Statistics of loading a set of sources, see [`load_tree_with_stats`].
+///
+/// The [`Display`] implementation produces a summary with the
+/// [`TreeStats::DEFAULT_TOP`] slowest sources and largest contexts,
+/// while [`TreeStats::summary`] allows choosing the number of sources.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeStats {
+	/// Statistics of each source, keyed by source name.
+	pub files: BTreeMap<String, FileStats>,
+}
+
+impl TreeStats {
+	/// Number of sources listed in the summary by default.
+	pub const DEFAULT_TOP: usize = 10;
+
+	/// Returns the total time spent on parsing.
+	pub fn parse_time(&self) -> Duration {
+		self.files.values().map(|stats| stats.parse_time).sum()
+	}
+
+	/// Returns the total time spent on evaluation.
+	pub fn eval_time(&self) -> Duration {
+		self.files.values().map(|stats| stats.eval_time).sum()
+	}
+
+	/// Returns the number of sources which are not data-only.
+	///
+	/// See [`FileClass::is_data_only`].
+	pub fn non_data_only(&self) -> usize {
+		self.files
+			.values()
+			.filter(|stats| !stats.class.is_data_only())
+			.count()
+	}
+
+	/// Returns sources sorted by their total time, slowest first.
+	pub fn slowest(&self) -> Vec<(&String, &FileStats)> {
+		let mut files = self.files.iter().collect::<Vec<_>>();
+		files.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.total_time()));
+		files
+	}
+
+	/// Returns sources sorted by the size of their values, largest first.
+	pub fn largest(&self) -> Vec<(&String, &FileStats)> {
+		let mut files = self.files.iter().collect::<Vec<_>>();
+		files.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.value_bytes));
+		files
+	}
+
+	/// Returns a displayable summary listing `top` sources in each table.
+	pub fn summary(&self, top: usize) -> TreeStatsSummary<'_> {
+		TreeStatsSummary { stats: self, top }
+	}
+}
+
+impl Display for TreeStats {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		Display::fmt(&self.summary(Self::DEFAULT_TOP), f)
+	}
+}
+
+/// A summary of [`TreeStats`], see [`TreeStats::summary`].
+#[derive(Debug, Clone, Copy)]
+pub struct TreeStatsSummary<'a> {
+	stats: &'a TreeStats,
+	top: usize,
+}
+
+impl Display for TreeStatsSummary<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let stats = self.stats;
+		writeln!(
+			f,
+			"{} files, {} not data-only",
+			stats.files.len(),
+			stats.non_data_only()
+		)?;
+		writeln!(
+			f,
+			"parse time: {:.2?}, evaluation time: {:.2?}",
+			stats.parse_time(),
+			stats.eval_time()
+		)?;
+		writeln!(f, "slowest files:")?;
+		writeln!(f, "{:>12} {:>12}  name", "parse", "eval")?;
+		for (name, file) in stats.slowest().into_iter().take(self.top) {
+			writeln!(
+				f,
+				"{:>12} {:>12}  {}",
+				format!("{:.2?}", file.parse_time),
+				format!("{:.2?}", file.eval_time),
+				name
+			)?;
+		}
+		writeln!(f, "largest contexts:")?;
+		writeln!(f, "{:>12} {:>12}  name", "variables", "bytes")?;
+		for (name, file) in stats.largest().into_iter().take(self.top) {
+			writeln!(
+				f,
+				"{:>12} {:>12}  {}",
+				file.variables, file.value_bytes, name
+			)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(feature = "serde")]
+impl TreeStats {
+	/// Converts the statistics into JSON.
+	///
+	/// The root object has the totals `files`, `non_data_only`,
+	/// `parse_time_us` and `eval_time_us`, and an object of `sources`
+	/// keyed by source name. Each source has `parse_time_us`,
+	/// `eval_time_us`, `variables`, `value_bytes` and `class`, which is
+	/// one of `data_only`, `with_functions` and `with_shell_logic`.
+	pub fn to_json(&self) -> serde_json::Value {
+		use serde_json::{Map, json};
+
+		let micros = |duration: Duration| duration.as_micros() as u64;
+		let sources = self
+			.files
+			.iter()
+			.map(|(name, stats)| {
+				let class = match stats.class {
+					FileClass::DataOnly => "data_only",
+					FileClass::WithFunctions(_) => "with_functions",
+					FileClass::WithShellLogic(_) => "with_shell_logic",
+				};
+				let stats = json!({
+					"parse_time_us": micros(stats.parse_time),
+					"eval_time_us": micros(stats.eval_time),
+					"variables": stats.variables,
+					"value_bytes": stats.value_bytes,
+					"class": class,
+				});
+				(name.clone(), stats)
+			})
+			.collect::<Map<_, _>>();
+		json!({
+			"files": self.files.len(),
+			"non_data_only": self.non_data_only(),
+			"parse_time_us": micros(self.parse_time()),
+			"eval_time_us": micros(self.eval_time()),
+			"sources": sources,
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::Mutex;
+
+	use super::*;
+
+	#[test]
+	fn test_load_tree() {
+		let sources = vec![
+			("a", "A=1\nB=\"$A\"\n".to_string()),
+			("b", "B='a\n".to_string()),
+			("c", "C=${C:?unset}\n".to_string()),
+			("d", "D=1\n".to_string()),
+		];
+		let reports = Mutex::new(Vec::new());
+		let callback =
+			|done, total| reports.lock().unwrap().push((done, total));
+		let result = load_tree(
+			sources,
+			EvalOptions::default,
+			Some(Progress {
+				every: 2,
+				callback: &callback,
+			}),
+		);
+		assert_eq!(result.len(), 4);
+		assert!(!result.is_ok());
+		assert_eq!(result.contexts.keys().collect::<Vec<_>>(), vec!["a", "d"]);
+		assert_eq!(result.contexts["a"]["B"], "1");
+		assert!(matches!(result.failures["b"], ApmlError::Parse(_)));
+		assert!(matches!(result.failures["c"], ApmlError::Eval(_)));
+		let mut reports = reports.into_inner().unwrap();
+		reports.sort();
+		assert_eq!(reports, vec![(2, 4), (4, 4)]);
+
+		let result =
+			load_tree([("a", "A=1"), ("a", "A='")], EvalOptions::default, None);
+		assert!(result.contexts.is_empty());
+		assert_eq!(result.failures.len(), 1);
+		assert!(
+			load_tree::<_, &str, &str, _>([], EvalOptions::default, None)
+				.is_empty()
+		);
+		assert!(result.stats.is_none());
+	}
+
+	#[test]
+	fn test_load_tree_cached() {
+		use crate::apml::cache::MemoryCache;
+
+		let sources =
+			[("a", "A=1\nB=\"$A\"\n"), ("b", "B='a\n"), ("c", "C=1\n")];
+		let cache = MemoryCache::new();
+		let first =
+			load_tree_cached(sources, EvalOptions::default, None, &cache);
+		assert_eq!(cache.len(), 2);
+		let second =
+			load_tree_cached(sources, EvalOptions::default, None, &cache);
+		assert_eq!(cache.len(), 2);
+		assert_eq!(first.contexts, second.contexts);
+		assert_eq!(second.contexts["a"]["B"], "1");
+		assert!(matches!(second.failures["b"], ApmlError::Parse(_)));
+	}
+
+	#[test]
+	fn test_load_tree_with_stats() {
+		let sources = [
+			("a", "A=1\nB=(xyz abcdef)\n"),
+			("b", "B='a\n"),
+			("c", "C=$(true)\n"),
+			("d", "D=\"${D:?}\"\n"),
+		];
+		let result = load_tree_with_stats(sources, EvalOptions::default, None);
+		let stats = result.stats.unwrap();
+		assert_eq!(stats.files.len(), 4);
+		assert_eq!(stats.files["a"].variables, 2);
+		assert_eq!(stats.files["a"].value_bytes, 10);
+		assert_eq!(stats.files["b"].eval_time, Duration::ZERO);
+		assert_eq!(stats.files["d"].variables, 0);
+		assert_eq!(stats.non_data_only(), 2);
+		assert_eq!(stats.largest()[0].0, "a");
+		assert_eq!(
+			stats.parse_time() + stats.eval_time(),
+			stats
+				.slowest()
+				.iter()
+				.map(|(_, stats)| stats.total_time())
+				.sum()
+		);
+
+		let summary = stats.summary(1).to_string();
+		assert!(summary.starts_with("4 files, 2 not data-only\n"));
+		assert!(summary.ends_with("           2           10  a\n"));
+		assert_eq!(summary.lines().count(), 8);
+		assert_eq!(stats.to_string().lines().count(), 14);
+
+		#[cfg(feature = "serde")]
+		{
+			let json = stats.to_json();
+			assert_eq!(json["files"], 4);
+			assert_eq!(json["sources"]["c"]["class"], "with_shell_logic");
+			assert_eq!(json["sources"]["a"]["value_bytes"], 10);
+		}
+	}
+}
@@ -0,0 +1,203 @@
+//! Build settings of packages.
+//!
+//! [`AbHost`] is the host architecture of a package (`ABHOST`), and
+//! [`AbType`] is the build template (`ABTYPE`). Both keep unknown values
+//! as [`AbHost::Other`] and [`AbType::Other`], so that they round-trip
+//! through [`Display`] and [`FromStr`].
+
+use std::{
+	convert::Infallible,
+	fmt::{Display, Formatter},
+	str::FromStr,
+};
+
+use super::ReadContext;
+
+/// Host architecture of a package, written in `ABHOST`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AbHost {
+	/// `noarch`, built once for all architectures.
+	Noarch,
+	/// Any other value, such as the architecture of a cross target.
+	Other(String),
+}
+
+impl AbHost {
+	/// Reads `ABHOST` of a context.
+	///
+	/// Returns [`None`] if it is not defined, in which case packages are
+	/// built for each architecture.
+	pub fn of<C: ReadContext + ?Sized>(context: &C) -> Option<Self> {
+		let Ok(host) = context.get("ABHOST")?.as_string().parse();
+		Some(host)
+	}
+
+	/// Returns the value as written in `ABHOST`.
+	pub fn as_str(&self) -> &str {
+		match self {
+			AbHost::Noarch => "noarch",
+			AbHost::Other(value) => value,
+		}
+	}
+
+	/// Returns if the package is architecture-independent.
+	pub fn is_noarch(&self) -> bool {
+		matches!(self, AbHost::Noarch)
+	}
+}
+
+impl FromStr for AbHost {
+	type Err = Infallible;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"noarch" => AbHost::Noarch,
+			_ => AbHost::Other(s.to_string()),
+		})
+	}
+}
+
+impl Display for AbHost {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+/// Build template of a package, written in `ABTYPE`.
+///
+/// Known templates are those allowed by the [bundled
+/// schema][super::schema::FieldSchema::aosc].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AbType {
+	/// `autotools`.
+	Autotools,
+	/// `cmake`.
+	Cmake,
+	/// `cmakeninja`.
+	CmakeNinja,
+	/// `dummy`, producing a package without contents.
+	Dummy,
+	/// `gomod`.
+	GoMod,
+	/// `meson`.
+	Meson,
+	/// `npm`.
+	Npm,
+	/// `perl`.
+	Perl,
+	/// `pep517`.
+	Pep517,
+	/// `plainmake`.
+	PlainMake,
+	/// `python`.
+	Python,
+	/// `qtproj`.
+	QtProj,
+	/// `rust`.
+	Rust,
+	/// `self`, with build steps written by the package itself.
+	SelfDefined,
+	/// `waf`.
+	Waf,
+	/// Any other template.
+	Other(String),
+}
+
+impl AbType {
+	/// Known templates and their spellings.
+	const KNOWN: [(&'static str, AbType); 15] = [
+		("autotools", AbType::Autotools),
+		("cmake", AbType::Cmake),
+		("cmakeninja", AbType::CmakeNinja),
+		("dummy", AbType::Dummy),
+		("gomod", AbType::GoMod),
+		("meson", AbType::Meson),
+		("npm", AbType::Npm),
+		("perl", AbType::Perl),
+		("pep517", AbType::Pep517),
+		("plainmake", AbType::PlainMake),
+		("python", AbType::Python),
+		("qtproj", AbType::QtProj),
+		("rust", AbType::Rust),
+		("self", AbType::SelfDefined),
+		("waf", AbType::Waf),
+	];
+
+	/// Reads `ABTYPE` of a context.
+	///
+	/// Returns [`None`] if it is not defined, in which case the template
+	/// is detected from the sources. Architecture-specific overrides are
+	/// not considered, see [`resolve_arch`][super::arch::resolve_arch].
+	pub fn of<C: ReadContext + ?Sized>(context: &C) -> Option<Self> {
+		let Ok(ty) = context.get("ABTYPE")?.as_string().parse();
+		Some(ty)
+	}
+
+	/// Returns the value as written in `ABTYPE`.
+	pub fn as_str(&self) -> &str {
+		match self {
+			AbType::Other(value) => value,
+			known => {
+				Self::KNOWN
+					.iter()
+					.find(|(_, ty)| ty == known)
+					.expect("known template")
+					.0
+			}
+		}
+	}
+
+	/// Returns if the template is known.
+	pub fn is_known(&self) -> bool {
+		!matches!(self, AbType::Other(_))
+	}
+}
+
+impl FromStr for AbType {
+	type Err = Infallible;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(Self::KNOWN
+			.into_iter()
+			.find(|(spelling, _)| *spelling == s)
+			.map_or_else(|| AbType::Other(s.to_string()), |(_, ty)| ty))
+	}
+}
+
+impl Display for AbType {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::{ApmlContext, schema::FieldSchema};
+
+	#[test]
+	fn test_build_settings() {
+		let context =
+			ApmlContext::eval_source("ABHOST=noarch\nABTYPE=self\n").unwrap();
+		assert_eq!(AbHost::of(&context), Some(AbHost::Noarch));
+		assert_eq!(AbType::of(&context), Some(AbType::SelfDefined));
+		let context =
+			ApmlContext::eval_source("ABHOST=arm64\nABTYPE=scons\n").unwrap();
+		assert_eq!(AbHost::of(&context), Some(AbHost::Other("arm64".into())));
+		assert!(!AbHost::of(&context).unwrap().is_noarch());
+		let abtype = AbType::of(&context).unwrap();
+		assert!(!abtype.is_known());
+		assert_eq!(abtype.to_string(), "scons");
+		assert_eq!(AbType::of(&ApmlContext::new()), None);
+
+		let schema = FieldSchema::aosc();
+		let field = schema.get("ABTYPE").unwrap();
+		let allowed = field.allowed_values.as_ref().unwrap();
+		assert_eq!(allowed.len(), AbType::KNOWN.len());
+		for value in allowed {
+			let abtype = value.parse::<AbType>().unwrap();
+			assert!(abtype.is_known());
+			assert_eq!(abtype.as_str(), value);
+		}
+	}
+}
@@ -0,0 +1,241 @@
+//! Caches of parsed LSTs.
+//!
+//! A [`ParseCache`] stores LSTs keyed by [`source_hash`], so that
+//! unchanged sources can skip parsing, such as in repeated runs of
+//! [`batch::load_tree_cached`]. [`MemoryCache`] keeps LSTs in memory,
+//! and with the `serde` feature enabled, [`DiskCache`] keeps them as
+//! JSON files in a directory, which are slower to load than parsing.
+//!
+//! [`batch::load_tree_cached`]: super::batch::load_tree_cached
+
+use std::{
+	collections::HashMap,
+	hash::Hasher,
+	sync::{Mutex, PoisonError},
+};
+
+use super::{ContentHasher, lst::ApmlLst, parser::ParseError};
+
+/// Computes the hash of a source, as used for keys of caches.
+///
+/// The hash is computed with 64-bit FNV-1a, so it is the same across
+/// processes and platforms.
+pub fn source_hash(src: &str) -> u64 {
+	let mut hasher = ContentHasher::default();
+	hasher.write(src.as_bytes());
+	hasher.finish()
+}
+
+/// A cache of parsed LSTs, keyed by [`source_hash`].
+///
+/// Caches are best-effort: failures to store entries are ignored, and
+/// invalid entries are treated as missing.
+pub trait ParseCache: Sync {
+	/// Gets the LST of a source by its hash.
+	fn get(&self, hash: u64) -> Option<ApmlLst<'static>>;
+
+	/// Stores the LST of a source by its hash.
+	fn put(&self, hash: u64, lst: &ApmlLst);
+}
+
+/// Parses a source, reading and updating the cache.
+///
+/// LSTs read from the cache are checked against the source, so stale
+/// entries are never returned.
+pub fn parse_cached<'a>(
+	src: &'a str,
+	cache: &dyn ParseCache,
+) -> Result<ApmlLst<'a>, ParseError> {
+	let hash = source_hash(src);
+	if let Some(lst) = cache.get(hash)
+		&& lst.to_string() == src
+	{
+		return Ok(lst);
+	}
+	let lst = ApmlLst::parse(src)?;
+	cache.put(hash, &lst);
+	Ok(lst)
+}
+
+/// A [`ParseCache`] keeping LSTs in memory.
+#[derive(Debug, Default)]
+pub struct MemoryCache(Mutex<HashMap<u64, ApmlLst<'static>>>);
+
+impl MemoryCache {
+	/// Creates an empty cache.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns the number of entries.
+	pub fn len(&self) -> usize {
+		self.0.lock().unwrap_or_else(PoisonError::into_inner).len()
+	}
+
+	/// Returns if the cache has no entries.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl ParseCache for MemoryCache {
+	fn get(&self, hash: u64) -> Option<ApmlLst<'static>> {
+		let entries = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+		entries.get(&hash).cloned()
+	}
+
+	fn put(&self, hash: u64, lst: &ApmlLst) {
+		let mut entries = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+		entries.insert(hash, lst.clone().into_owned());
+	}
+}
+
+#[cfg(feature = "serde")]
+mod disk {
+	use std::{
+		fs,
+		path::{Path, PathBuf},
+	};
+
+	use serde_json::{Value, json};
+
+	use super::{ApmlLst, ParseCache};
+	use crate::apml::capabilities::capabilities;
+
+	/// A [`ParseCache`] keeping LSTs as JSON files in a directory.
+	///
+	/// Each entry is a file named after the hexadecimal hash. Its first
+	/// line stores the version of this crate and the LST schema version
+	/// from the [capabilities] of the build, and the rest is the compact
+	/// [JSON form][ApmlLst::to_json] of the LST, loaded without reading
+	/// the first line again. Entries written with other versions or
+	/// failing to load are treated as missing, and are overwritten when
+	/// the source is parsed again. Other capabilities, such as the
+	/// `rayon` feature, do not change LSTs, so entries are shared by
+	/// builds with different features.
+	///
+	/// This is not a speed-up over parsing: decoding the JSON and
+	/// building the LST from it takes several times as long as parsing
+	/// the source, which is a single pass over it. Use [`MemoryCache`] to
+	/// skip parsing within a process, and see the `apml-cache-bench`
+	/// example for measurements.
+	///
+	/// [`MemoryCache`]: super::MemoryCache
+	#[derive(Debug, Clone)]
+	pub struct DiskCache {
+		dir: PathBuf,
+	}
+
+	impl DiskCache {
+		/// Creates a cache in a directory.
+		///
+		/// The directory is created when storing the first entry.
+		pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+			Self {
+				dir: dir.as_ref().to_path_buf(),
+			}
+		}
+
+		/// Returns the path of the entry of a hash.
+		pub fn entry_path(&self, hash: u64) -> PathBuf {
+			self.dir.join(format!("{:016x}.json", hash))
+		}
+	}
+
+	impl ParseCache for DiskCache {
+		fn get(&self, hash: u64) -> Option<ApmlLst<'static>> {
+			let entry = fs::read_to_string(self.entry_path(hash)).ok()?;
+			let (header, lst) = entry.split_once('\n')?;
+			if serde_json::from_str::<Value>(header).ok()? != entry_key() {
+				return None;
+			}
+			ApmlLst::from_json(lst).ok()
+		}
+
+		fn put(&self, hash: u64, lst: &ApmlLst) {
+			let entry = format!("{}\n{}", entry_key(), lst.to_json_value());
+			let _ = fs::create_dir_all(&self.dir)
+				.and_then(|_| fs::write(self.entry_path(hash), entry));
+		}
+	}
+
+	/// Returns the versions an entry of [`DiskCache`] is valid for.
+	pub(super) fn entry_key() -> Value {
+		let capabilities = capabilities();
+		json!({
+			"crate_version": capabilities.crate_version,
+			"lst_schema": capabilities.lst_schema,
+		})
+	}
+}
+
+#[cfg(feature = "serde")]
+pub use disk::DiskCache;
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_memory_cache() {
+		let cache = MemoryCache::new();
+		let src = "A=1\nB=(${A} x)\n";
+		let lst = parse_cached(src, &cache).unwrap();
+		assert_eq!(cache.len(), 1);
+		assert_eq!(cache.get(source_hash(src)).unwrap(), lst);
+		assert_eq!(parse_cached(src, &cache).unwrap(), lst);
+		assert_eq!(cache.len(), 1);
+		assert!(parse_cached("B='a\n", &cache).is_err());
+		assert_eq!(cache.len(), 1);
+
+		// stale entries are never returned
+		cache.put(source_hash("C=1\n"), &lst);
+		assert_eq!(parse_cached("C=1\n", &cache).unwrap().to_string(), "C=1\n");
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn test_disk_cache() {
+		use std::fs;
+
+		use crate::apml::capabilities::capabilities;
+
+		let dir = std::env::temp_dir()
+			.join(format!("libabbs-cache-{}", std::process::id()));
+		let cache = DiskCache::new(&dir);
+		let src = "A=1\nB=\"${A:-x}\"\n";
+		let hash = source_hash(src);
+		assert!(cache.get(hash).is_none());
+		let lst = parse_cached(src, &cache).unwrap();
+		assert_eq!(cache.get(hash).unwrap(), lst);
+
+		let entry = fs::read_to_string(cache.entry_path(hash)).unwrap();
+		let (header, body) = entry.split_once('\n').unwrap();
+		assert_eq!(header, disk::entry_key().to_string());
+		let mismatched = header.replace(env!("CARGO_PKG_VERSION"), "0.0.0-x");
+		fs::write(cache.entry_path(hash), mismatched + "\n" + body).unwrap();
+		assert!(cache.get(hash).is_none());
+		let schema = capabilities().lst_schema.unwrap();
+		let mismatched = header.replace(
+			&format!("\"lst_schema\":{}", schema),
+			&format!("\"lst_schema\":{}", schema + 1),
+		);
+		assert_ne!(mismatched, header);
+		fs::write(cache.entry_path(hash), mismatched + "\n" + body).unwrap();
+		assert!(cache.get(hash).is_none());
+
+		// entries are shared by builds with other features
+		let rayon = format!("\"rayon\":{}", cfg!(feature = "rayon"));
+		let flipped = format!("\"rayon\":{}", !cfg!(feature = "rayon"));
+		assert!(body.contains(&rayon));
+		let body = body.replacen(&rayon, &flipped, 1);
+		fs::write(cache.entry_path(hash), format!("{}\n{}", header, body))
+			.unwrap();
+		assert_eq!(cache.get(hash).unwrap(), lst);
+		fs::write(cache.entry_path(hash), &entry[..entry.len() / 2]).unwrap();
+		assert!(cache.get(hash).is_none());
+		assert_eq!(parse_cached(src, &cache).unwrap(), lst);
+		assert_eq!(cache.get(hash).unwrap(), lst);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,89 @@
+//! Capabilities of the linked build of this crate.
+//!
+//! See [`capabilities`].
+
+/// Optional features enabled in a build of this crate, along with
+/// versions of its formats.
+///
+/// Data produced by builds with different capabilities may be
+/// incompatible. For example, entries of a [`DiskCache`] are discarded
+/// when written with another crate version or LST schema version. The
+/// capabilities are also recorded in [JSON dumps][super::json] of LSTs.
+///
+/// [`DiskCache`]: super::cache::DiskCache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+	/// Version of this crate.
+	pub crate_version: &'static str,
+	/// Version of the [JSON schema of LSTs][super::json], if the `serde`
+	/// feature is enabled.
+	pub lst_schema: Option<u32>,
+	/// Whether the `serde` feature is enabled, providing JSON forms.
+	pub serde: bool,
+	/// Whether the `rayon` feature is enabled, loading trees in
+	/// parallel.
+	pub rayon: bool,
+	/// Whether the `tracing` feature is enabled.
+	pub tracing: bool,
+	/// Whether the `tree` feature is enabled.
+	pub tree: bool,
+	/// Whether the `testing` feature is enabled.
+	pub testing: bool,
+	/// Whether the `mmap` feature is enabled, parsing memory-mapped
+	/// files.
+	pub mmap: bool,
+}
+
+impl Capabilities {
+	/// Returns the capabilities as a JSON object.
+	///
+	/// Keys are the names of the fields.
+	#[cfg(feature = "serde")]
+	pub fn to_json(&self) -> serde_json::Value {
+		serde_json::json!({
+			"crate_version": self.crate_version,
+			"lst_schema": self.lst_schema,
+			"serde": self.serde,
+			"rayon": self.rayon,
+			"tracing": self.tracing,
+			"tree": self.tree,
+			"testing": self.testing,
+			"mmap": self.mmap,
+		})
+	}
+}
+
+/// Returns the capabilities of the linked build of this crate.
+pub fn capabilities() -> Capabilities {
+	Capabilities {
+		crate_version: env!("CARGO_PKG_VERSION"),
+		#[cfg(feature = "serde")]
+		lst_schema: Some(super::json::SCHEMA_VERSION),
+		#[cfg(not(feature = "serde"))]
+		lst_schema: None,
+		serde: cfg!(feature = "serde"),
+		rayon: cfg!(feature = "rayon"),
+		tracing: cfg!(feature = "tracing"),
+		tree: cfg!(feature = "tree"),
+		testing: cfg!(feature = "testing"),
+		mmap: cfg!(feature = "mmap"),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_capabilities() {
+		let capabilities = capabilities();
+		assert_eq!(capabilities.crate_version, env!("CARGO_PKG_VERSION"));
+		assert_eq!(capabilities.serde, capabilities.lst_schema.is_some());
+		assert_eq!(capabilities.rayon, cfg!(feature = "rayon"));
+		#[cfg(feature = "serde")]
+		assert_eq!(
+			capabilities.to_json()["lst_schema"],
+			crate::apml::json::SCHEMA_VERSION
+		);
+	}
+}
@@ -0,0 +1,244 @@
+//! Checksums of sources.
+//!
+//! See [`sync`] for updating `CHKSUMS` after `SRCS` is edited.
+
+use std::borrow::Cow;
+
+use thiserror::Error;
+
+use super::{
+	ApmlContext, ApmlError, ast,
+	editor::{ApmlEditor, ValueEditor},
+	lst::{self, ApmlLst},
+	srcs::CanonicalSource,
+};
+
+/// Checksum of sources which are not verified.
+pub const SKIP: &str = "SKIP";
+
+/// Fetchers of version control systems, whose sources are not verified
+/// with checksums.
+pub const VCS_FETCHERS: [&str; 5] = ["git", "svn", "bzr", "hg", "fossil"];
+
+/// Beginning of comments left by [`sync`].
+const TODO_COMMENT: &str = " TODO: add checksums of SRCS entries";
+
+/// Errors produced by [`sync`].
+#[derive(Debug, Error)]
+pub enum SyncError {
+	#[error(transparent)]
+	Apml(#[from] ApmlError),
+	#[error("SRCS is not defined")]
+	MissingSources,
+	#[error("CHKSUMS contains expansions")]
+	NotLiteral,
+	#[error("{checksums} checksums are given for {sources} previous sources")]
+	CountMismatch { sources: usize, checksums: usize },
+}
+
+/// Updates `CHKSUMS` of a spec to match its `SRCS`.
+///
+/// `previous` are the sources which the existing checksums are given
+/// for, usually `SRCS` before it was edited. Each entry of the current
+/// `SRCS` gets:
+///
+/// - `SKIP` if it is fetched by a [version control system][VCS_FETCHERS].
+/// - The checksum of the previous source with the same [canonical
+///   URL][CanonicalSource::url], so that reordered sources keep their
+///   checksums.
+/// - `SKIP` as a placeholder otherwise.
+///
+/// Indices of placeholders, which need real checksums, are returned. If
+/// there are any, a `# TODO` comment listing them (counted from 1) is
+/// put before `CHKSUMS`, replacing the one left by an earlier call.
+///
+/// `CHKSUMS` is edited with [`ValueEditor::set_words`], so the layout of
+/// multi-line values is kept. It is added after `SRCS` if not defined.
+/// Architecture-specific overrides are not considered.
+pub fn sync(
+	lst: &mut ApmlLst,
+	previous: &[CanonicalSource],
+) -> Result<Vec<usize>, SyncError> {
+	let context = ApmlContext::eval_lst(lst)?;
+	let sources = context
+		.get("SRCS")
+		.ok_or(SyncError::MissingSources)?
+		.as_array();
+
+	let mut editor = ApmlEditor::wrap(lst);
+	let index = editor.find_var_index("CHKSUMS");
+	let checksums = match index {
+		Some(index) => {
+			let lst::Token::Variable(def) = &mut editor.lst_tokens_mut()[index]
+			else {
+				unreachable!()
+			};
+			let checksums = ValueEditor::wrap(def)
+				.words()
+				.ok_or(SyncError::NotLiteral)?;
+			if checksums.len() != previous.len() {
+				return Err(SyncError::CountMismatch {
+					sources: previous.len(),
+					checksums: checksums.len(),
+				});
+			}
+			checksums
+		}
+		None => vec![],
+	};
+
+	let mut missing = Vec::new();
+	let mut result = Vec::with_capacity(sources.len());
+	for (position, source) in sources.iter().enumerate() {
+		let source = CanonicalSource::parse(source);
+		if VCS_FETCHERS.contains(&source.fetcher.as_str()) {
+			result.push(SKIP);
+		} else if let Some(checksum) = previous
+			.iter()
+			.position(|old| old.url == source.url)
+			.and_then(|old| checksums.get(old))
+		{
+			result.push(checksum);
+		} else {
+			result.push(SKIP);
+			missing.push(position);
+		}
+	}
+
+	let index = match index {
+		Some(index) => {
+			let lst::Token::Variable(def) = &mut editor.lst_tokens_mut()[index]
+			else {
+				unreachable!()
+			};
+			ValueEditor::wrap(def).set_words(&result);
+			index
+		}
+		None => {
+			editor.append_var_ast(
+				"CHKSUMS",
+				&ast::VariableValue::String(result.join(" ").into()),
+				Some("SRCS"),
+			);
+			editor.find_var_index("CHKSUMS").expect("added definition")
+		}
+	};
+
+	let tokens = editor.lst_tokens_mut();
+	let has_comment = index >= 2
+		&& matches!(tokens[index - 1], lst::Token::Newline)
+		&& matches!(
+			&tokens[index - 2],
+			lst::Token::Comment(text) if text.starts_with(TODO_COMMENT)
+		);
+	let comment = (!missing.is_empty()).then(|| {
+		let positions = missing
+			.iter()
+			.map(|position| (position + 1).to_string())
+			.collect::<Vec<_>>();
+		lst::Token::Comment(Cow::Owned(format!(
+			"{} {}",
+			TODO_COMMENT,
+			positions.join(", ")
+		)))
+	});
+	match (has_comment, comment) {
+		(true, Some(comment)) => tokens[index - 2] = comment,
+		(true, None) => {
+			tokens.drain(index - 2..index);
+		}
+		(false, Some(comment)) => {
+			tokens.splice(index..index, [comment, lst::Token::Newline]);
+		}
+		(false, None) => {}
+	}
+	Ok(missing)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn sync_str(
+		src: &str,
+		previous: &[&str],
+	) -> Result<(String, Vec<usize>), SyncError> {
+		let mut lst = ApmlLst::parse(src).unwrap();
+		let previous = previous
+			.iter()
+			.map(|source| CanonicalSource::parse(source))
+			.collect::<Vec<_>>();
+		let missing = sync(&mut lst, &previous)?;
+		Ok((lst.to_string(), missing))
+	}
+
+	#[test]
+	fn test_sync() {
+		let src = "VER=2\nSRCS=\"tbl::https://x/b.tar \\\n\
+			\x20     git::commit=v$VER::https://x/c \\\n\
+			\x20     tbl::https://x/a-$VER.tar\"\n\
+			CHKSUMS=\"sha256::a \\\n         sha256::b\"\n";
+		let (result, missing) =
+			sync_str(src, &["https://x/a-1.tar", "https://X:443/b.tar"])
+				.unwrap();
+		assert_eq!(missing, vec![2]);
+		assert_eq!(
+			result,
+			"VER=2\nSRCS=\"tbl::https://x/b.tar \\\n\
+			\x20     git::commit=v$VER::https://x/c \\\n\
+			\x20     tbl::https://x/a-$VER.tar\"\n\
+			# TODO: add checksums of SRCS entries 3\n\
+			CHKSUMS=\"sha256::b \\\n         SKIP \\\n         SKIP\"\n"
+		);
+		let (result, missing) =
+			sync_str(&result, &["https://x/b.tar", "git::https://x/c", "x"])
+				.unwrap();
+		assert_eq!(missing, vec![2]);
+		assert!(result.contains("entries 3\nCHKSUMS"));
+
+		let src =
+			"SRCS=(a b)\nCHKSUMS=(\n\tsha256::a\n\tSKIP\n\tsha256::c\n)\n";
+		let (result, missing) = sync_str(src, &["a", "git::c", "b"]).unwrap();
+		assert!(missing.is_empty());
+		assert_eq!(
+			result,
+			"SRCS=(a b)\nCHKSUMS=(\n\tsha256::a\n\tsha256::c\n)\n"
+		);
+		let (result, _) = sync_str(
+			"# TODO: add checksums of SRCS entries 1\nSRCS=(a b)\n\
+			CHKSUMS=(sha256::a)\n",
+			&["a"],
+		)
+		.unwrap();
+		assert_eq!(
+			result,
+			"# TODO: add checksums of SRCS entries 1\nSRCS=(a b)\n\
+			# TODO: add checksums of SRCS entries 2\n\
+			CHKSUMS=(sha256::a SKIP)\n"
+		);
+
+		let (result, missing) =
+			sync_str("SRCS=\"git::https://x/a\"\nVER=1\n", &[]).unwrap();
+		assert!(missing.is_empty());
+		assert_eq!(
+			result,
+			"SRCS=\"git::https://x/a\"\nCHKSUMS=\"SKIP\"\nVER=1\n"
+		);
+
+		assert!(matches!(
+			sync_str("SRCS=a\nCHKSUMS=\"$A\"\n", &[]),
+			Err(SyncError::NotLiteral)
+		));
+		assert!(matches!(
+			sync_str("SRCS=a\nCHKSUMS=\"SKIP\"\n", &[]),
+			Err(SyncError::CountMismatch {
+				sources: 0,
+				checksums: 1
+			})
+		));
+		assert!(matches!(
+			sync_str("A=1\n", &[]),
+			Err(SyncError::MissingSources)
+		));
+	}
+}
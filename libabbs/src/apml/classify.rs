@@ -0,0 +1,463 @@
+//! Classification of APML files by the shell features they use.
+//!
+//! ACBS sources `defines` and `spec` files with bash, while the evaluator
+//! of this crate only models variable assignments and parameter
+//! expansions. [`ApmlLst::classify`] tells whether the result of the
+//! evaluator is authoritative for a file.
+
+use super::{
+	ast::{self, AstNode, Construct, Unsupported},
+	eval::ShellOptions,
+	lst::{
+		ApmlLst, ArrayToken, ExpansionModifier, LiteralPart, SetCommand, Text,
+		TextUnit, Token, VariableValue, Word,
+	},
+	span::{Span, display_len},
+};
+
+/// Maximum number of offending constructs recorded in a [`FileClass`].
+pub const MAX_OFFENDING_SPANS: usize = 8;
+
+/// Classification of a APML file.
+///
+/// Spans of at most [`MAX_OFFENDING_SPANS`] offending constructs are
+/// recorded, in the order of their appearance.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FileClass {
+	/// The file only contains assignments which can be fully modelled by
+	/// the evaluator.
+	DataOnly,
+	/// The file defines shell functions.
+	///
+	/// Function definitions are never accepted by the parser, so this is
+	/// only produced by [`classify_source`].
+	/// Files with functions are classified as this even if they
+	/// also contain other shell logic.
+	WithFunctions(Vec<Span>),
+	/// The file contains constructs that the evaluator cannot model,
+	/// such as command substitutions, conditionals or pathname expansions.
+	WithShellLogic(Vec<Span>),
+}
+
+impl FileClass {
+	/// Returns if the file is data-only.
+	pub fn is_data_only(&self) -> bool {
+		matches!(self, FileClass::DataOnly)
+	}
+
+	/// Returns the spans of offending constructs.
+	pub fn spans(&self) -> &[Span] {
+		match self {
+			FileClass::DataOnly => &[],
+			FileClass::WithFunctions(spans)
+			| FileClass::WithShellLogic(spans) => spans,
+		}
+	}
+}
+
+impl ApmlLst<'_> {
+	/// Classifies the file by the shell features it uses.
+	///
+	/// The classification is conservative: anything that the evaluator
+	/// cannot model exactly as bash does is considered shell logic.
+	/// This includes the constructs reported by
+	/// [`ApmlLst::unsupported_constructs`] and definitions that cannot be
+	/// emitted into AST.
+	pub fn classify(&self) -> FileClass {
+		let mut offending = Vec::new();
+		for (span, token) in self.token_spans() {
+			if offending.len() >= MAX_OFFENDING_SPANS {
+				break;
+			}
+			let mut constructs = Vec::new();
+			match token {
+				Token::Variable(def) => {
+					check_definition(def, span.start, &mut constructs);
+					if constructs.is_empty()
+						&& ast::VariableDefinition::emit_from(def).is_err()
+					{
+						offending.push(span);
+					}
+				}
+				Token::Set(set) => check_set(set, span, &mut constructs),
+				_ => {}
+			}
+			offending.extend(constructs.iter().map(|construct| construct.span));
+		}
+		offending.truncate(MAX_OFFENDING_SPANS);
+		if offending.is_empty() {
+			FileClass::DataOnly
+		} else {
+			FileClass::WithShellLogic(offending)
+		}
+	}
+
+	/// Finds constructs that the evaluator cannot model, in the order of
+	/// their appearance:
+	///
+	/// - command substitutions (`$(...)` and backquotes), including
+	///   those nested in expansion modifiers,
+	/// - unquoted here-documents, control operators and redirections
+	///   (`<<`, `;`, `|`, `&`, `<` and `>`),
+	/// - tilde expansions,
+	/// - unquoted pathname and brace expansion patterns in arrays,
+	/// - unquoted expansions in arrays, which bash splits into words,
+	/// - substring offsets and lengths that are not literal integers,
+	/// - custom expansions (`${@name args}`), which bash does not support,
+	/// - `set` statements with options other than `-e`, `-u`, their `+`
+	///   forms, `-o errexit` and `-o nounset`, such as `set -- a b`.
+	///
+	/// Constructs nested in expansion modifiers get the span of the
+	/// outermost expansion.
+	pub fn unsupported_constructs(&self) -> Vec<Unsupported> {
+		let mut constructs = Vec::new();
+		for (span, token) in self.token_spans() {
+			match token {
+				Token::Variable(def) => {
+					check_definition(def, span.start, &mut constructs)
+				}
+				Token::Set(set) => check_set(set, span, &mut constructs),
+				_ => {}
+			}
+		}
+		constructs
+	}
+}
+
+fn check_definition(
+	def: &super::lst::VariableDefinition,
+	start: usize,
+	out: &mut Vec<Unsupported>,
+) {
+	let start = start + def.name.len() + display_len(&def.op);
+	match &def.value {
+		VariableValue::String(text) => check_text(text, start, false, out),
+		VariableValue::Array(tokens) => {
+			let mut pos = start + 1;
+			for token in tokens {
+				if let ArrayToken::Element(text) = token {
+					check_text(text, pos, true, out);
+				}
+				pos += display_len(token);
+			}
+		}
+	}
+}
+
+/// Checks a `set` statement for options that the evaluator rejects.
+fn check_set(set: &SetCommand, span: Span, out: &mut Vec<Unsupported>) {
+	if ShellOptions::default().apply(set, span).is_err() {
+		out.push(Unsupported {
+			construct: Construct::ShellOption,
+			span,
+		});
+	}
+}
+
+/// Classifies a APML source.
+///
+/// Unlike [`ApmlLst::classify`], this also accepts sources that cannot be
+/// parsed. Lines looking like function definitions (`name() {` or
+/// `function name`) are reported as [`FileClass::WithFunctions`], while
+/// other unparsable sources are reported as [`FileClass::WithShellLogic`]
+/// with the span of the line failed to be parsed.
+pub fn classify_source(src: &str) -> FileClass {
+	let error_pos = match ApmlLst::parse(src) {
+		Ok(lst) => return lst.classify(),
+		Err(super::parser::ParseError::UnexpectedSource { pos }) => pos - 1,
+		Err(_) => 0,
+	};
+
+	let mut functions = Vec::new();
+	let mut pos = 0;
+	for line in src.split_inclusive('\n') {
+		if functions.len() >= MAX_OFFENDING_SPANS {
+			break;
+		}
+		let content = line.trim_end_matches('\n');
+		if is_function_header(content.trim()) {
+			functions.push(Span::with_len(pos, content.len()));
+		}
+		pos += line.len();
+	}
+	if !functions.is_empty() {
+		return FileClass::WithFunctions(functions);
+	}
+
+	let line_start = src[..error_pos].rfind('\n').map_or(0, |pos| pos + 1);
+	let line_end = src[error_pos..]
+		.find('\n')
+		.map_or(src.len(), |pos| error_pos + pos);
+	FileClass::WithShellLogic(vec![Span::new(line_start, line_end)])
+}
+
+/// Returns if a line looks like the header of a function definition.
+fn is_function_header(line: &str) -> bool {
+	let is_name = |name: &str| {
+		!name.is_empty()
+			&& name
+				.chars()
+				.all(|ch| ch.is_alphanumeric() || matches!(ch, '_' | '-' | ':'))
+	};
+	if let Some(rest) = line.strip_prefix("function")
+		&& rest.starts_with([' ', '\t'])
+	{
+		let name = rest.trim_start();
+		let name = name
+			.find(|ch: char| ch.is_whitespace() || ch == '(' || ch == '{')
+			.map_or(name, |pos| &name[..pos]);
+		return is_name(name);
+	}
+	match line.split_once('(') {
+		Some((name, rest)) => {
+			is_name(name.trim_end()) && rest.trim_start().starts_with(')')
+		}
+		None => false,
+	}
+}
+
+/// Checks a text starting at `pos` for constructs unable to be modelled.
+fn check_text(
+	text: &Text,
+	mut pos: usize,
+	in_array: bool,
+	out: &mut Vec<Unsupported>,
+) {
+	let text_start = pos;
+	for unit in &text.0 {
+		let (words, quoted, mut word_pos) = match unit {
+			TextUnit::Unquoted(words) => (words, false, pos),
+			TextUnit::DoubleQuote(words) => (words, true, pos + 1),
+			TextUnit::LocaleQuote(words) => (words, true, pos + 2),
+			TextUnit::SingleQuote(_) | TextUnit::AnsiCQuote(_) => {
+				pos += display_len(unit);
+				continue;
+			}
+		};
+		for word in words {
+			let span = Span::with_len(word_pos, display_len(word));
+			let at_start = !quoted && word_pos == text_start;
+			if let Some(construct) =
+				offending_construct(word, quoted, in_array, at_start)
+			{
+				out.push(Unsupported { construct, span });
+			}
+			word_pos = span.end;
+		}
+		pos += display_len(unit);
+	}
+}
+
+fn offending_construct(
+	word: &Word,
+	quoted: bool,
+	in_array: bool,
+	at_start: bool,
+) -> Option<Construct> {
+	match word {
+		Word::Literal(parts) => {
+			// whether the previous part ends with an unquoted `:`, after
+			// which assignments expand tildes
+			let mut after_colon = false;
+			parts
+				.iter()
+				.enumerate()
+				.find_map(|(index, part)| match part {
+					LiteralPart::String(text) => {
+						let tilde = (at_start && index == 0 || after_colon)
+							&& text.starts_with('~')
+							|| !in_array && text.contains(":~");
+						after_colon = !in_array && text.ends_with(':');
+						if text.contains('`') {
+							Some(Construct::CommandSubstitution)
+						} else if quoted {
+							None
+						} else if text.contains("<<") {
+							Some(Construct::HereDoc)
+						} else if text.contains([';', '|', '&']) {
+							Some(Construct::ControlFlow)
+						} else if text.contains(['<', '>']) {
+							Some(Construct::Redirect)
+						} else if in_array && text.contains(['*', '?', '[']) {
+							Some(Construct::PathnameExpansion)
+						} else if in_array && text.contains('{') {
+							Some(Construct::BraceExpansion)
+						} else if tilde {
+							Some(Construct::TildeExpansion)
+						} else {
+							None
+						}
+					}
+					LiteralPart::Escaped(_) | LiteralPart::LineContinuation => {
+						after_colon = false;
+						None
+					}
+				})
+		}
+		Word::UnbracedVariable(_) => {
+			(in_array && !quoted).then_some(Construct::WordSplitting)
+		}
+		Word::BracedVariable(exp) => {
+			if let Some(ExpansionModifier::Substring { offset, length }) =
+				&exp.modifier
+			{
+				let is_int = |text: &str| text.trim().parse::<isize>().is_ok();
+				if !is_int(offset)
+					|| length.as_ref().is_some_and(|length| !is_int(length))
+				{
+					return Some(Construct::Arithmetic);
+				}
+			}
+			// nested texts in modifiers are checked roughly
+			let exp = exp.to_string();
+			if exp.contains("$(") || exp.contains('`') {
+				Some(Construct::CommandSubstitution)
+			} else {
+				(in_array && !quoted).then_some(Construct::WordSplitting)
+			}
+		}
+		Word::Subcommand(_) => Some(Construct::CommandSubstitution),
+		Word::Custom(_) => Some(Construct::CustomExpansion),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn classify(src: &str) -> (bool, Vec<&str>) {
+		let class = ApmlLst::parse(src).unwrap().classify();
+		let spans = class.spans().iter().map(|span| span.slice(src)).collect();
+		(class.is_data_only(), spans)
+	}
+
+	#[test]
+	fn test_classify() {
+		assert_eq!(
+			classify("# c\nA=1\nB=\"${A}x\" C=(a 'b*' \"*\")\nD=\"a;b\"\n"),
+			(true, vec![])
+		);
+		assert_eq!(
+			classify("A=\"x$(uname -m)\"\nB=(a $(ls) \"`id`\")\n"),
+			(false, vec!["$(uname -m)", "$(ls)", "`id`"])
+		);
+		assert_eq!(
+			classify("A=a;b\nB=~/a\nC=a~\nD=(*.c {a,b})\n"),
+			(false, vec!["a;b", "~/a", "*.c", "{a,b}"])
+		);
+		assert_eq!(
+			classify("A=${B:-$(id)}\nB=\"${C//a/b}\"\n"),
+			(false, vec!["${B:-$(id)}"])
+		);
+		assert_eq!(
+			classify("A=($B \"$B\" ${C} '$D' x$E \"${F[@]}\")\n"),
+			(false, vec!["$B", "${C}", "$E"])
+		);
+		assert_eq!(
+			classify("set -eu\nset +u -o nounset -o errexit\nA=1\n"),
+			(true, vec![])
+		);
+		assert_eq!(
+			classify("set -- a b\nset -x\nset -o pipefail\nA=1\n"),
+			(false, vec!["set -- a b", "set -x", "set -o pipefail"])
+		);
+		let src = "A=$(a)\n".repeat(MAX_OFFENDING_SPANS + 2);
+		assert_eq!(classify(&src).1.len(), MAX_OFFENDING_SPANS);
+	}
+
+	#[test]
+	fn test_unsupported_constructs() {
+		let src = "A=\"`id`\"\nB=a<<EOF\nC=a|b\nD=a>b\nE=~/x\n\
+			F=(*.c {a,b})\nG=${A:1-1}\nH=\"${A:1:2}\"\nI='a;b'\n\
+			J=(\"$A\" $A)\nset -- a\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let constructs = lst.unsupported_constructs();
+		assert_eq!(
+			constructs
+				.iter()
+				.map(|unsupported| (
+					unsupported.construct,
+					unsupported.span.slice(src)
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				(Construct::CommandSubstitution, "`id`"),
+				(Construct::HereDoc, "a<<EOF"),
+				(Construct::ControlFlow, "a|b"),
+				(Construct::Redirect, "a>b"),
+				(Construct::TildeExpansion, "~/x"),
+				(Construct::PathnameExpansion, "*.c"),
+				(Construct::BraceExpansion, "{a,b}"),
+				(Construct::Arithmetic, "${A:1-1}"),
+				(Construct::WordSplitting, "$A"),
+				(Construct::ShellOption, "set -- a"),
+			]
+		);
+		assert_eq!(
+			lst.classify().spans(),
+			constructs
+				.iter()
+				.take(MAX_OFFENDING_SPANS)
+				.map(|unsupported| unsupported.span)
+				.collect::<Vec<_>>()
+		);
+
+		let err = ast::ApmlAst::emit_supported(&lst).unwrap_err();
+		assert!(matches!(
+			err,
+			ast::EmitError::UnsupportedCommandSubstitution(_)
+		));
+		assert_eq!(err.unsupported(), Some(constructs[0]));
+		assert_eq!(
+			err.suggestion(),
+			Some("move this logic into the build script")
+		);
+		assert_eq!(
+			err.to_string(),
+			"Unsupported command substitution at 3..7: \
+			move this logic into the build script"
+		);
+		let lst = ApmlLst::parse("A=1\nB=(a \"${A:0:1}\")\n").unwrap();
+		assert!(lst.classify().is_data_only());
+		assert!(ast::ApmlAst::emit_supported(&lst).is_ok());
+
+		// assignments expand tildes after unquoted colons, arrays do not
+		let src = "A=x:~/y\nB=(x:~/y)\nC=x\\:~/y\nD=x:\"~\"\nE=\"x\":~\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(
+			lst.unsupported_constructs()
+				.iter()
+				.map(|unsupported| (
+					unsupported.construct,
+					unsupported.span.slice(src)
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				(Construct::TildeExpansion, "x:~/y"),
+				(Construct::TildeExpansion, ":~"),
+			]
+		);
+	}
+
+	#[test]
+	fn test_classify_source() {
+		let src = "A=1\nfoo() {\n\techo\n}\nfunction bar {\n}\n";
+		let class = classify_source(src);
+		assert_eq!(
+			class
+				.spans()
+				.iter()
+				.map(|span| span.slice(src))
+				.collect::<Vec<_>>(),
+			vec!["foo() {", "function bar {"]
+		);
+		assert!(matches!(class, FileClass::WithFunctions(_)));
+
+		let src = "A=1\nif true; then\nB=2\nfi\n";
+		let class = classify_source(src);
+		assert!(matches!(class, FileClass::WithShellLogic(_)));
+		assert_eq!(class.spans()[0].slice(src), "if true; then");
+
+		assert_eq!(classify_source("A=1\n"), FileClass::DataOnly);
+	}
+}
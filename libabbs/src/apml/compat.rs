@@ -0,0 +1,605 @@
+//! Compatibility between spellings of fields.
+//!
+//! Older trees spell the version fields of `spec` files as `VER` and
+//! `REL`, while some tooling expects `PKGVER` and `PKGREL`, or the other
+//! way around. [`ApmlContext::normalize_version_fields`] converts
+//! evaluated contexts, and [`migrate_version_fields`] rewrites sources.
+//!
+//! Sources are also spelled in legacy fields, such as `SRCTBL` and
+//! `GITSRC` for the single source of older trees, or numbered variants
+//! like `SRCS_1` and `CHKSUMS_1` for additional sources.
+//! [`ApmlContext::normalize_source_fields`] folds them into `SRCS` and
+//! `CHKSUMS`.
+
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use super::{
+	ApmlContext, VariableValue,
+	lst::{self, ApmlLst, ArrayToken, ExpansionModifier, TextUnit, Word},
+	session::Edit,
+};
+
+/// Spelling of version fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VersionSpelling {
+	/// `VER` and `REL`.
+	Legacy,
+	/// `PKGVER` and `PKGREL`.
+	Prefixed,
+}
+
+impl VersionSpelling {
+	/// Pairs of legacy and prefixed spellings of version fields.
+	pub const PAIRS: [(&'static str, &'static str); 2] =
+		[("VER", "PKGVER"), ("REL", "PKGREL")];
+
+	/// Iterates over pairs of names to rename from and to.
+	fn renames(self) -> impl Iterator<Item = (&'static str, &'static str)> {
+		Self::PAIRS
+			.into_iter()
+			.map(move |(legacy, prefixed)| match self {
+				VersionSpelling::Legacy => (prefixed, legacy),
+				VersionSpelling::Prefixed => (legacy, prefixed),
+			})
+	}
+}
+
+/// Both spellings of a version field defined in a context.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpellingConflict {
+	/// Name of the variable which is kept.
+	pub kept: String,
+	/// Value of the variable which is kept.
+	pub kept_value: VariableValue,
+	/// Name of the variable which is dropped.
+	pub dropped: String,
+	/// Value of the variable which is dropped.
+	pub dropped_value: VariableValue,
+}
+
+impl SpellingConflict {
+	/// Returns if both spellings have the same value.
+	pub fn is_consistent(&self) -> bool {
+		self.kept_value == self.dropped_value
+	}
+
+	/// Describes which of the two spellings takes effect.
+	pub fn explain(&self) -> String {
+		if self.is_consistent() {
+			format!(
+				"Both {} and {} are defined, {} is redundant.",
+				self.kept, self.dropped, self.dropped,
+			)
+		} else {
+			format!(
+				"Both {} and {} are defined with different values, {} is \
+				used and {} is ignored.",
+				self.kept, self.dropped, self.kept, self.dropped,
+			)
+		}
+	}
+}
+
+impl ApmlContext {
+	/// Renames version fields into a spelling.
+	///
+	/// If both spellings of a field are defined, the one already in the
+	/// target spelling takes precedence. The other one is removed and
+	/// reported as a [`SpellingConflict`], whether the values agree or
+	/// not. Conflicts are in the order of [`VersionSpelling::PAIRS`].
+	pub fn normalize_version_fields(
+		&mut self,
+		target: VersionSpelling,
+	) -> Vec<SpellingConflict> {
+		let mut conflicts = Vec::new();
+		for (from, to) in target.renames() {
+			let Some(value) = self.get(from) else {
+				continue;
+			};
+			if let Some(kept) = self.get(to) {
+				conflicts.push(SpellingConflict {
+					kept: to.to_string(),
+					kept_value: kept.clone(),
+					dropped: from.to_string(),
+					dropped_value: value.clone(),
+				});
+				self.remove(from);
+			} else {
+				self.rename_keys(|name| (name == from).then(|| to.to_string()))
+					.expect("target name is not defined");
+			}
+		}
+		conflicts
+	}
+}
+
+/// Legacy fields of a single source, consisting of the field of the
+/// URL, the fetcher, and pairs of option keys and fields of fetcher
+/// options.
+type LegacySource = (
+	&'static str,
+	&'static str,
+	&'static [(&'static str, &'static str)],
+);
+
+/// Legacy sources in the order of precedence.
+const LEGACY_SOURCES: [LegacySource; 3] = [
+	("SRCTBL", "tbl", &[]),
+	(
+		"GITSRC",
+		"git",
+		&[("commit", "GITCO"), ("branch", "GITBRCH")],
+	),
+	("SVNSRC", "svn", &[("revision", "SVNCO")]),
+];
+
+/// A deprecated source field defined in a context.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LegacySourceField {
+	/// Name of the variable.
+	pub name: String,
+	/// Value of the variable.
+	pub value: VariableValue,
+	/// Whether the value is ignored, as a field of higher precedence is
+	/// defined.
+	pub ignored: bool,
+}
+
+impl LegacySourceField {
+	/// Returns the field which replaces this field.
+	pub fn replacement(&self) -> &'static str {
+		if self.name.starts_with("CHKSUM") {
+			"CHKSUMS"
+		} else {
+			"SRCS"
+		}
+	}
+
+	/// Describes the deprecation and the field to move the source into.
+	pub fn explain(&self) -> String {
+		if self.ignored {
+			format!(
+				"{} is deprecated and ignored, as {} is already defined.",
+				self.name,
+				self.replacement(),
+			)
+		} else {
+			format!(
+				"{} is deprecated, move it into {} instead.",
+				self.name,
+				self.replacement(),
+			)
+		}
+	}
+}
+
+/// Returns if a field is the URL field of a legacy source, such as
+/// `SRCTBL`.
+pub(crate) fn is_legacy_source_url(name: &str) -> bool {
+	LEGACY_SOURCES.iter().any(|(field, _, _)| *field == name)
+}
+
+/// Returns the number of a numbered variant of a field, such as `1` for
+/// `SRCS_1`.
+pub(crate) fn numbered_variant(name: &str, field: &str) -> Option<u32> {
+	let number = name.strip_prefix(field)?.strip_prefix('_')?;
+	if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	number.parse().ok()
+}
+
+impl ApmlContext {
+	/// Folds legacy source fields into `SRCS` and `CHKSUMS`.
+	///
+	/// Sources are gathered in the following order:
+	///
+	/// 1. `SRCS`, if defined. Otherwise, the first defined field of
+	///    `SRCTBL`, `GITSRC` (with `GITCO` and `GITBRCH`) and `SVNSRC`
+	///    (with `SVNCO`), converted into a source like
+	///    `git::commit=v1.0::https://...`. The other fields are ignored.
+	/// 2. Numbered variants `SRCS_1`, `SRCS_2` and so on, by number.
+	///
+	/// Checksums are gathered in the same order. `CHKSUMS` takes
+	/// precedence over `CHKSUM`, which is only used along with `SRCTBL`,
+	/// and sources of version control systems are given `SKIP`. Numbered
+	/// variants `CHKSUMS_1` and so on follow, each appended in step with
+	/// the sources of the same number: a group with fewer checksums than
+	/// sources is padded with `SKIP`, so that every checksum stays paired
+	/// with its source. Arch-specific overrides of the numbered variants
+	/// are not recognized.
+	///
+	/// Legacy fields are removed from the context and reported in the
+	/// order of the list above, including the ignored ones. If none is
+	/// defined, the context is left unchanged.
+	pub fn normalize_source_fields(&mut self) -> Vec<LegacySourceField> {
+		let mut fields = Vec::new();
+		let mut report = |name: &str, value: &VariableValue, ignored| {
+			fields.push(LegacySourceField {
+				name: name.to_string(),
+				value: value.clone(),
+				ignored,
+			})
+		};
+		let mut srcs = self.get("SRCS").map(VariableValue::as_array);
+		let mut chksums = self.get("CHKSUMS").map(VariableValue::as_array);
+
+		let mut legacy_fetcher = None;
+		for (field, fetcher, options) in LEGACY_SOURCES {
+			let Some(url) = self.get(field) else {
+				continue;
+			};
+			let ignored = srcs.is_some();
+			report(field, url, ignored);
+			let mut source = format!("{}::", fetcher);
+			let mut defined_options = Vec::new();
+			for (key, field) in options {
+				if let Some(value) = self.get(field) {
+					report(field, value, ignored);
+					defined_options.push(format!(
+						"{}={}",
+						key,
+						value.as_string()
+					));
+				}
+			}
+			if !defined_options.is_empty() {
+				source.push_str(&defined_options.join(";"));
+				source.push_str("::");
+			}
+			source.push_str(&url.as_string());
+			if !ignored {
+				srcs = Some(vec![source]);
+				legacy_fetcher = Some(fetcher);
+			}
+		}
+		let chksum = self.get("CHKSUM");
+		if let Some(fetcher) = legacy_fetcher
+			&& chksums.is_none()
+		{
+			chksums = match (fetcher, chksum) {
+				("tbl", Some(chksum)) => Some(vec![chksum.as_string()]),
+				("tbl", None) => None,
+				_ => Some(vec!["SKIP".to_string()]),
+			};
+			if let Some(chksum) = chksum {
+				report("CHKSUM", chksum, fetcher != "tbl");
+			}
+		} else if let Some(chksum) = chksum {
+			report("CHKSUM", chksum, true);
+		}
+
+		let mut numbered = self
+			.iter()
+			.filter_map(|(name, value)| {
+				let key = numbered_variant(name, "SRCS")
+					.map(|number| (number, false))
+					.or_else(|| {
+						numbered_variant(name, "CHKSUMS")
+							.map(|number| (number, true))
+					})?;
+				Some((key, name, value))
+			})
+			.collect::<Vec<_>>();
+		numbered.sort_by_key(|(key, _, _)| *key);
+		// keeps checksums in step with sources across numbered groups
+		let pad = |srcs: &Option<Vec<String>>,
+		           chksums: &mut Option<Vec<String>>| {
+			let len = srcs.as_ref().map_or(0, Vec::len);
+			let chksums = chksums.get_or_insert_default();
+			if chksums.len() < len {
+				chksums.resize(len, "SKIP".to_string());
+			}
+		};
+		let mut group = None;
+		for ((number, is_chksums), name, value) in numbered {
+			if group != Some(number) {
+				pad(&srcs, &mut chksums);
+				group = Some(number);
+			}
+			let target = if is_chksums { &mut chksums } else { &mut srcs };
+			target.get_or_insert_default().extend(value.as_array());
+			report(name, value, false);
+		}
+		if group.is_some() {
+			pad(&srcs, &mut chksums);
+		}
+
+		if fields.is_empty() {
+			return fields;
+		}
+		for field in &fields {
+			self.remove(&field.name);
+		}
+		for (name, value) in [("SRCS", srcs), ("CHKSUMS", chksums)] {
+			if let Some(value) = value {
+				let value = VariableValue::Array(value);
+				match self.get_mut(name) {
+					Some(existing) => *existing = value,
+					None => self.insert(name.to_string(), value),
+				}
+			}
+		}
+		fields
+	}
+}
+
+/// Errors produced while migrating sources.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum MigrationError {
+	#[error("Both {0} and {1} are defined")]
+	BothDefined(String, String),
+}
+
+/// Produces the edits renaming version fields of a LST into a spelling.
+///
+/// Definitions are renamed, and so are references to the fields, so
+/// that other variables keep their values. References nested in
+/// modifiers are renamed as well, while references in command
+/// substitutions are not. References are renamed even if the fields
+/// are not defined in the LST, as `defines` files refer to fields of
+/// `spec` files.
+///
+/// If both spellings of a field are defined in the LST, nothing is
+/// renamed and [`MigrationError::BothDefined`] is returned, as merging
+/// the definitions would change the values seen by references. Such
+/// conflicts can be diagnosed with
+/// [`ApmlContext::normalize_version_fields`].
+///
+/// Edits are in the order of definitions, and can be applied with an
+/// [`EditSession`][super::session::EditSession].
+pub fn migrate_version_fields(
+	lst: &ApmlLst,
+	target: VersionSpelling,
+) -> Result<Vec<Edit>, MigrationError> {
+	let renames = target.renames().collect::<Vec<_>>();
+	for (from, to) in &renames {
+		let defined =
+			|name: &str| lst.variable_spans().any(|(_, def)| def.name == name);
+		if defined(from) && defined(to) {
+			return Err(MigrationError::BothDefined(
+				from.to_string(),
+				to.to_string(),
+			));
+		}
+	}
+	let rename = |name: &str| {
+		renames
+			.iter()
+			.find(|(from, _)| *from == name)
+			.map(|(_, to)| *to)
+	};
+
+	let mut edits = Vec::new();
+	for (span, def) in lst.variable_spans() {
+		let mut changed = def.clone();
+		if let Some(to) = rename(&changed.name) {
+			changed.name = to.into();
+		}
+		match &mut changed.value {
+			lst::VariableValue::String(text) => {
+				rename_in_text(Arc::make_mut(text), &rename)
+			}
+			lst::VariableValue::Array(tokens) => {
+				for token in tokens {
+					if let ArrayToken::Element(text) = token {
+						rename_in_text(Arc::make_mut(text), &rename);
+					}
+				}
+			}
+		}
+		let (old, new) = (def.to_string(), changed.to_string());
+		if old != new {
+			edits.push(Edit::between(span.start, &old, &new));
+		}
+	}
+	Ok(edits)
+}
+
+/// Renames references in a text, including references nested in
+/// modifiers.
+fn rename_in_text(
+	text: &mut lst::Text,
+	rename: &impl Fn(&str) -> Option<&'static str>,
+) {
+	for unit in &mut text.0 {
+		let (TextUnit::Unquoted(words) | TextUnit::DoubleQuote(words)) = unit
+		else {
+			continue;
+		};
+		for word in words {
+			match word {
+				Word::UnbracedVariable(name) => {
+					if let Some(to) = rename(name) {
+						*name = to.into();
+					}
+				}
+				Word::BracedVariable(exp) => {
+					if let Some(to) = rename(&exp.name) {
+						exp.name = to.into();
+					}
+					match &mut exp.modifier {
+						Some(
+							ExpansionModifier::ReplaceOnce { string, .. }
+							| ExpansionModifier::ReplaceAll { string, .. }
+							| ExpansionModifier::ReplacePrefix { string, .. }
+							| ExpansionModifier::ReplaceSuffix { string, .. },
+						) => {
+							if let Some(string) = string {
+								rename_in_text(Arc::make_mut(string), rename);
+							}
+						}
+						Some(
+							ExpansionModifier::ErrorOnUnset(text)
+							| ExpansionModifier::WhenUnset(text)
+							| ExpansionModifier::WhenSet(text),
+						) => rename_in_text(Arc::make_mut(text), rename),
+						_ => {}
+					}
+				}
+				Word::Literal(_) | Word::Subcommand(_) | Word::Custom(_) => {}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::session::EditSession;
+
+	#[test]
+	fn test_normalize_version_fields() {
+		let mut context =
+			ApmlContext::eval_source("A=1\nVER=1.0\nREL=2\nB=2\n").unwrap();
+		assert!(
+			context
+				.normalize_version_fields(VersionSpelling::Prefixed)
+				.is_empty()
+		);
+		assert_eq!(
+			context.keys().collect::<Vec<_>>(),
+			vec!["A", "PKGVER", "PKGREL", "B"]
+		);
+		assert_eq!(context.read("PKGVER"), "1.0");
+		assert!(
+			context
+				.normalize_version_fields(VersionSpelling::Legacy)
+				.is_empty()
+		);
+		assert_eq!(
+			context.keys().collect::<Vec<_>>(),
+			vec!["A", "VER", "REL", "B"]
+		);
+
+		let mut context =
+			ApmlContext::eval_source("VER=1.0\nPKGVER=1.1\nREL=1\nPKGREL=1\n")
+				.unwrap();
+		let conflicts =
+			context.normalize_version_fields(VersionSpelling::Legacy);
+		assert_eq!(context.read("VER"), "1.0");
+		assert_eq!(context.read("REL"), "1");
+		assert!(!context.contains_var("PKGVER"));
+		assert!(!context.contains_var("PKGREL"));
+		assert_eq!(conflicts.len(), 2);
+		assert!(!conflicts[0].is_consistent());
+		assert!(conflicts[1].is_consistent());
+		assert_eq!(
+			conflicts[0].explain(),
+			"Both VER and PKGVER are defined with different values, VER is \
+			used and PKGVER is ignored."
+		);
+
+		let mut context =
+			ApmlContext::eval_source("VER=1.0\nPKGVER=1.1\n").unwrap();
+		let conflicts =
+			context.normalize_version_fields(VersionSpelling::Prefixed);
+		assert_eq!(context.read("PKGVER"), "1.1");
+		assert_eq!(conflicts[0].dropped, "VER");
+		assert_eq!(conflicts[0].dropped_value, "1.0");
+	}
+
+	#[test]
+	fn test_normalize_source_fields() {
+		let mut context = ApmlContext::eval_source(
+			"SRCTBL=https://x/a.tar\nCHKSUM=sha256::a\nSVNSRC=https://x/b\n\
+			SVNCO=42\nSRCS_2=\"git::https://x/d\"\nCHKSUMS_2=sha256::d\n\
+			SRCS_1__AMD64=e\nSRCS_10=\"f g\"\nSRCS_1=c\n",
+		)
+		.unwrap();
+		let fields = context.normalize_source_fields();
+		assert_eq!(
+			fields
+				.iter()
+				.map(|field| (field.name.as_str(), field.ignored))
+				.collect::<Vec<_>>(),
+			vec![
+				("SRCTBL", false),
+				("SVNSRC", true),
+				("SVNCO", true),
+				("CHKSUM", false),
+				("SRCS_1", false),
+				("SRCS_2", false),
+				("CHKSUMS_2", false),
+				("SRCS_10", false),
+			]
+		);
+		assert_eq!(
+			context.keys().collect::<Vec<_>>(),
+			vec!["SRCS_1__AMD64", "SRCS", "CHKSUMS"]
+		);
+		assert_eq!(
+			context.read("SRCS"),
+			VariableValue::Array(
+				["tbl::https://x/a.tar", "c", "git::https://x/d", "f", "g"]
+					.map(String::from)
+					.to_vec()
+			)
+		);
+		assert_eq!(
+			context.read("CHKSUMS"),
+			VariableValue::Array(
+				["sha256::a", "SKIP", "sha256::d", "SKIP", "SKIP"]
+					.map(String::from)
+					.to_vec()
+			)
+		);
+		assert_eq!(
+			fields[0].explain(),
+			"SRCTBL is deprecated, move it into SRCS instead."
+		);
+		assert_eq!(fields[3].replacement(), "CHKSUMS");
+
+		let src = "SRCS=a\nCHKSUMS=b\nX=1\n";
+		let mut context = ApmlContext::eval_source(src).unwrap();
+		assert!(context.normalize_source_fields().is_empty());
+		assert_eq!(context.read("SRCS"), VariableValue::String("a".into()));
+	}
+
+	#[test]
+	fn test_migrate_version_fields() {
+		let src = "VER=1.0 # version\nREL=2\n\
+			SRCS=\"tbl::https://x/$VER/${VER%.*}-${REL:-$VER}.tar\"\n\
+			CHKSUMS=(\"$(echo $VER)\" \"${PKGVER}\")\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let edits =
+			migrate_version_fields(&lst, VersionSpelling::Prefixed).unwrap();
+		let mut session = EditSession::new(&lst);
+		session.extend(edits);
+		let (migrated, map) = session.commit().unwrap();
+		assert_eq!(
+			migrated.to_string(),
+			"PKGVER=1.0 # version\nPKGREL=2\n\
+			SRCS=\"tbl::https://x/$PKGVER/${PKGVER%.*}-${PKGREL:-$PKGVER}.tar\"\n\
+			CHKSUMS=(\"$(echo $VER)\" \"${PKGVER}\")\n"
+		);
+		// the comment is kept in place
+		assert_eq!(map.map_offset(8), Some(11));
+		assert_eq!(
+			ApmlContext::eval_lst(&migrated).unwrap().read("SRCS"),
+			ApmlContext::eval_source(src).unwrap().read("SRCS")
+		);
+
+		let edits =
+			migrate_version_fields(&migrated, VersionSpelling::Legacy).unwrap();
+		let mut session = EditSession::new(&migrated);
+		session.extend(edits);
+		assert_eq!(
+			session.commit().unwrap().0.to_string(),
+			"VER=1.0 # version\nREL=2\n\
+			SRCS=\"tbl::https://x/$VER/${VER%.*}-${REL:-$VER}.tar\"\n\
+			CHKSUMS=(\"$(echo $VER)\" \"${VER}\")\n"
+		);
+
+		let lst = ApmlLst::parse("VER=1.0\nPKGVER=1.1\nREL=1\n").unwrap();
+		assert_eq!(
+			migrate_version_fields(&lst, VersionSpelling::Prefixed),
+			Err(MigrationError::BothDefined(
+				"VER".to_string(),
+				"PKGVER".to_string()
+			))
+		);
+	}
+}
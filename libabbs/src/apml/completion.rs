@@ -0,0 +1,427 @@
+//! Context-aware completion suggestions for editors.
+//!
+//! See [`completions`].
+
+use std::collections::BTreeSet;
+
+use super::{
+	ReadContext,
+	lst::{ApmlLst, VariableOp},
+	recovery::parse_recovering,
+	schema::FieldSchema,
+	span::{Span, display_len},
+};
+
+/// Returns the known option keys of a source fetcher.
+fn fetcher_options(tag: &str) -> &'static [&'static str] {
+	match tag {
+		"git" => &["branch", "commit", "copy-repo", "rename", "submodule"],
+		"svn" | "bzr" => &["rename", "revision"],
+		"hg" | "fossil" => &["commit", "rename"],
+		"tbl" | "file" | "pypi" => &["rename"],
+		_ => &[],
+	}
+}
+
+/// A completion suggestion.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Completion {
+	/// Text to insert.
+	pub label: String,
+	/// Span of source text to be replaced with the label.
+	pub span: Span,
+	/// Kind of the suggested item.
+	pub kind: CompletionKind,
+}
+
+/// Kind of a [`Completion`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompletionKind {
+	/// A ABBS field name from the schema.
+	Field,
+	/// A variable name, used in expansions.
+	Variable,
+	/// An option key of a source fetcher in `SRCS`.
+	SourceOption,
+}
+
+/// Returns completion suggestions at a byte offset in the source of a LST.
+///
+/// Fields are taken from the bundled [`FieldSchema`], see
+/// [`completions_with_schema`].
+pub fn completions<C: ReadContext + ?Sized>(
+	lst: &ApmlLst,
+	context: &C,
+	offset: usize,
+) -> Vec<Completion> {
+	completions_with_schema(lst, context, offset, &FieldSchema::default())
+}
+
+/// Returns completion suggestions at a byte offset in the source of a LST,
+/// with fields taken from a schema.
+///
+/// - At the start of a line, fields that are not defined yet and not
+///   deprecated are suggested.
+/// - Inside `${`, variables defined before the cursor and variables
+///   in the given context are suggested.
+/// - Inside a `SRCS` word after `::`, option keys of the detected fetcher
+///   are suggested.
+///
+/// Only suggestions starting with the already typed prefix are returned.
+pub fn completions_with_schema<C: ReadContext + ?Sized>(
+	lst: &ApmlLst,
+	context: &C,
+	offset: usize,
+	schema: &FieldSchema,
+) -> Vec<Completion> {
+	let src = lst.to_string();
+	if offset > src.len() || !src.is_char_boundary(offset) {
+		return Vec::new();
+	}
+
+	let defined_before = lst
+		.variable_spans()
+		.filter(|(span, _)| span.end <= offset)
+		.map(|(_, def)| def.name.to_string())
+		.collect::<Vec<_>>();
+	for (span, def) in lst.variable_spans() {
+		let name_span = Span::with_len(span.start, def.name.len());
+		if name_span.contains(offset) {
+			if !is_line_start(&src, span.start) {
+				return Vec::new();
+			}
+			return complete_fields(
+				lst, context, schema, &src, span.start, offset,
+			);
+		}
+		let op_len = display_len(&def.op);
+		let value_span = Span::new(name_span.end + op_len, span.end);
+		if offset < value_span.start {
+			return Vec::new();
+		}
+		if !value_span.contains(offset) {
+			continue;
+		}
+		let value_text = &src[value_span.start..offset];
+		if let Some(start) = expansion_name_start(value_text) {
+			let start = value_span.start + start;
+			return complete_variables(
+				context,
+				&defined_before,
+				&src,
+				start,
+				offset,
+			);
+		}
+		if def.name == "SRCS" || def.name.starts_with("SRCS__") {
+			return complete_source_options(&src, value_span.start, offset);
+		}
+		return Vec::new();
+	}
+
+	let line_start = src[..offset].rfind('\n').map_or(0, |pos| pos + 1);
+	if src[line_start..offset].chars().all(is_ident_char) {
+		complete_fields(lst, context, schema, &src, line_start, offset)
+	} else {
+		Vec::new()
+	}
+}
+
+/// Returns completion suggestions at a byte offset in a source which may
+/// be in the middle of being edited.
+///
+/// The source is parsed with [`parse_recovering`], so that unterminated
+/// quotes and expansions, such as the one being typed, do not prevent
+/// completion. Spans of the suggestions are in the source. Nothing is
+/// suggested if the source cannot be recovered.
+pub fn completions_in_source<C: ReadContext + ?Sized>(
+	src: &str,
+	context: &C,
+	offset: usize,
+) -> Vec<Completion> {
+	let Ok(recovered) = parse_recovering(src) else {
+		return Vec::new();
+	};
+	if offset > src.len() {
+		return Vec::new();
+	}
+	completions(&recovered.lst, context, recovered.to_recovered(offset))
+		.into_iter()
+		.map(|completion| Completion {
+			span: recovered.to_source_span(completion.span),
+			..completion
+		})
+		.collect()
+}
+
+/// Returns if a character may be a part of a variable name.
+fn is_ident_char(ch: char) -> bool {
+	ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+fn is_line_start(src: &str, offset: usize) -> bool {
+	offset == 0 || src[..offset].ends_with('\n')
+}
+
+/// Returns the end offset of the identifier-like text starting at `offset`.
+fn ident_end(src: &str, offset: usize) -> usize {
+	src[offset..]
+		.find(|ch| !is_ident_char(ch))
+		.map_or(src.len(), |pos| offset + pos)
+}
+
+/// Finds the start offset of the variable name in an unclosed `${`.
+fn expansion_name_start(text: &str) -> Option<usize> {
+	let opener = text.rfind("${")?;
+	let start = opener + 2;
+	let name = text[start..].strip_prefix('#').unwrap_or(&text[start..]);
+	if name.chars().all(is_ident_char) {
+		Some(text.len() - name.len())
+	} else {
+		None
+	}
+}
+
+fn complete_fields<C: ReadContext + ?Sized>(
+	lst: &ApmlLst,
+	context: &C,
+	schema: &FieldSchema,
+	src: &str,
+	start: usize,
+	offset: usize,
+) -> Vec<Completion> {
+	let prefix = &src[start..offset];
+	let span = Span::new(start, ident_end(src, offset));
+	let defined = lst
+		.variable_spans()
+		.filter(|(span, def)| {
+			// the definition being completed should not hide itself
+			span.start != start || def.op == VariableOp::Append
+		})
+		.map(|(_, def)| def.name.as_ref())
+		.collect::<BTreeSet<_>>();
+	schema
+		.fields()
+		.filter(|field| !field.is_deprecated())
+		.map(|field| field.name.as_str())
+		.filter(|field| field.starts_with(prefix))
+		.filter(|field| {
+			!defined.contains(*field) && !context.contains_var(field)
+		})
+		.map(|field| Completion {
+			label: field.to_string(),
+			span,
+			kind: CompletionKind::Field,
+		})
+		.collect()
+}
+
+fn complete_variables<C: ReadContext + ?Sized>(
+	context: &C,
+	defined_before: &[String],
+	src: &str,
+	start: usize,
+	offset: usize,
+) -> Vec<Completion> {
+	let prefix = &src[start..offset];
+	let span = Span::new(start, ident_end(src, offset));
+	defined_before
+		.iter()
+		.chain(context.keys())
+		.filter(|name| name.starts_with(prefix))
+		.collect::<BTreeSet<_>>()
+		.into_iter()
+		.map(|name| Completion {
+			label: name.clone(),
+			span,
+			kind: CompletionKind::Variable,
+		})
+		.collect()
+}
+
+fn complete_source_options(
+	src: &str,
+	value_start: usize,
+	offset: usize,
+) -> Vec<Completion> {
+	let value = &src[value_start..offset];
+	let word_start = value
+		.rfind(|ch: char| ch.is_whitespace() || matches!(ch, '"' | '\'' | '('))
+		.map_or(0, |pos| pos + 1);
+	let word = &value[word_start..];
+	let Some((tag, options)) = word.split_once("::") else {
+		return Vec::new();
+	};
+	if options.contains("::") {
+		// already in the argument
+		return Vec::new();
+	}
+	let (present, key) = match options.rsplit_once(';') {
+		Some((present, key)) => (present, key),
+		None => ("", options),
+	};
+	if key.contains('=') {
+		return Vec::new();
+	}
+	let present = present
+		.split(';')
+		.filter_map(|pair| pair.split_once('=').map(|(key, _)| key))
+		.collect::<BTreeSet<_>>();
+	let start = offset - key.len();
+	let end = src[offset..]
+		.find(|ch: char| !is_ident_char(ch) && ch != '-')
+		.map_or(src.len(), |pos| offset + pos);
+	fetcher_options(tag)
+		.iter()
+		.filter(|option| option.starts_with(key) && !present.contains(*option))
+		.map(|option| Completion {
+			label: option.to_string(),
+			span: Span::new(start, end),
+			kind: CompletionKind::SourceOption,
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::ApmlContext;
+	use crate::apml::schema::{FieldSpec, FieldType};
+
+	fn labels(src: &str, offset: usize) -> Vec<String> {
+		let lst = ApmlLst::parse(src).unwrap();
+		completions(&lst, &ApmlContext::default(), offset)
+			.into_iter()
+			.map(|completion| completion.label)
+			.collect()
+	}
+
+	#[test]
+	fn test_field_completions() {
+		let src = "PKGNAME=a\nPKGD=b\n\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let result = completions(&lst, &ApmlContext::default(), 14);
+		assert_eq!(
+			result,
+			vec![
+				Completion {
+					label: "PKGDEP".to_string(),
+					span: Span::new(10, 14),
+					kind: CompletionKind::Field,
+				},
+				Completion {
+					label: "PKGDES".to_string(),
+					span: Span::new(10, 14),
+					kind: CompletionKind::Field,
+				},
+			]
+		);
+		let result = labels(src, 17);
+		assert!(result.contains(&"PKGSEC".to_string()));
+		assert!(!result.contains(&"PKGNAME".to_string()));
+		assert!(labels(src, 16).is_empty());
+
+		let mut context = ApmlContext::default();
+		context.insert("PKGDES".to_string(), "test".into());
+		let result = completions(&lst, &context, 14);
+		assert_eq!(result.len(), 1);
+		assert_eq!(result[0].label, "PKGDEP");
+
+		let schema = FieldSchema::builder()
+			.field(FieldSpec::new("PKGDX", FieldType::Scalar))
+			.field(
+				FieldSpec::new("PKGDY", FieldType::Scalar)
+					.deprecated_since("1"),
+			)
+			.build();
+		let result = completions_with_schema(&lst, &context, 14, &schema);
+		assert_eq!(result.len(), 1);
+		assert_eq!(result[0].label, "PKGDX");
+		assert!(!labels(src, 17).contains(&"SRCTBL".to_string()));
+	}
+
+	#[test]
+	fn test_completions_in_source() {
+		let src = "VER=1\nA=\"${V\nSRCS=\"git::com\nB=2\n";
+		let context = ApmlContext::default();
+		assert_eq!(
+			completions_in_source(src, &context, 12),
+			vec![Completion {
+				label: "VER".to_string(),
+				span: Span::new(11, 12),
+				kind: CompletionKind::Variable,
+			}]
+		);
+		assert_eq!(
+			completions_in_source(src, &context, 27),
+			vec![Completion {
+				label: "commit".to_string(),
+				span: Span::new(24, 27),
+				kind: CompletionKind::SourceOption,
+			}]
+		);
+		let result = completions_in_source(src, &context, 29);
+		assert!(
+			result
+				.iter()
+				.any(|completion| completion.label == "BUILDDEP")
+		);
+		assert!(result.iter().all(|completion| {
+			completion.kind == CompletionKind::Field
+				&& completion.span == Span::new(28, 29)
+		}));
+	}
+
+	#[test]
+	fn test_variable_completions() {
+		let src = "VER=1\nVERSION=2\nA=\"${V}\"\nVAR=3\n";
+		let mut context = ApmlContext::default();
+		context.insert("VAL".to_string(), "1".into());
+		let lst = ApmlLst::parse(src).unwrap();
+		let result = completions(&lst, &context, 22);
+		assert_eq!(
+			result
+				.iter()
+				.map(|completion| completion.label.as_str())
+				.collect::<Vec<_>>(),
+			vec!["VAL", "VER", "VERSION"]
+		);
+		assert!(
+			result
+				.iter()
+				.all(|completion| completion.span == Span::new(21, 22)
+					&& completion.kind == CompletionKind::Variable)
+		);
+		assert_eq!(labels("A=1\nB=${#A}\n", 9), vec!["A"]);
+		assert!(labels("A=1\nB=${A}x\n", 11).is_empty());
+	}
+
+	#[test]
+	fn test_source_option_completions() {
+		let src = "SRCS=\"git::c::https://a.com tbl::r\"\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let result = completions(&lst, &ApmlContext::default(), 12);
+		assert_eq!(
+			result,
+			vec![
+				Completion {
+					label: "commit".to_string(),
+					span: Span::new(11, 12),
+					kind: CompletionKind::SourceOption,
+				},
+				Completion {
+					label: "copy-repo".to_string(),
+					span: Span::new(11, 12),
+					kind: CompletionKind::SourceOption,
+				},
+			]
+		);
+		assert_eq!(labels(src, 34), vec!["rename"]);
+		assert!(labels(src, 20).is_empty());
+		assert_eq!(
+			labels("SRCS=\"git::commit=a;\"\n", 20),
+			vec!["branch", "copy-repo", "rename", "submodule"]
+		);
+		assert!(labels("PKGDEP=\"git::c\"\n", 14).is_empty());
+	}
+}
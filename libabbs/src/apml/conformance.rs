@@ -0,0 +1,542 @@
+//! Conformance test kit for APML evaluation.
+//!
+//! The corpus in the `conformance` directory of this crate consists of
+//! cases, each of which is an APML source `<name>.apml` with the expected
+//! evaluated context `<name>.json`. Expected contexts are JSON objects
+//! mapping variable names to strings or arrays of strings, taken from
+//! bash 5.2.
+//!
+//! Downstream crates can check that they interoperate with the semantics
+//! of this crate by calling [`run`] with [`corpus_dir`] in their own test
+//! suites, or with a directory of their own cases in the same layout.
+//!
+//! Every [`ExpansionKind`] must appear in the corpus, which is enforced
+//! by the tests of this module. Adding a new expansion feature therefore
+//! requires adding at least one case.
+//!
+//! Where bash is available, the tests of this module also evaluate each
+//! case with [`eval_with_bash`], checking both the expected context and
+//! the evaluator against it.
+
+use std::{
+	collections::BTreeSet,
+	fs,
+	io::{self, ErrorKind},
+	path::{Path, PathBuf},
+	process::Command,
+};
+
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use super::{
+	ApmlContext, ApmlError, VariableValue,
+	lst::{ApmlLst, ArrayToken, ExpansionModifier, Text, TextUnit, Word},
+};
+
+/// Errors produced while running conformance cases.
+#[derive(Debug, Error)]
+pub enum ConformanceError {
+	#[error("IO error in {0}: {1}")]
+	Io(PathBuf, io::Error),
+	#[error("Invalid expected context in {0}: {1}")]
+	InvalidExpectation(PathBuf, String),
+	#[error("Failed to evaluate {0}: {1}")]
+	Apml(PathBuf, ApmlError),
+	#[error("Failed to evaluate {0} with bash: {1}")]
+	Bash(PathBuf, String),
+	#[error(
+		"Mismatched context in {case}:\nexpected: {expected}\nactual: {actual}"
+	)]
+	Mismatch {
+		case: PathBuf,
+		expected: String,
+		actual: String,
+	},
+}
+
+/// Returns the directory of the bundled corpus.
+pub fn corpus_dir() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance")
+}
+
+/// Runs all cases in a directory.
+///
+/// Returns the number of cases run. Cases are run in the order of
+/// their names, and the first failure is returned as an error.
+pub fn run<P: AsRef<Path>>(dir: P) -> Result<usize, ConformanceError> {
+	let cases = cases(dir.as_ref())?;
+	for case in &cases {
+		run_case(case)?;
+	}
+	Ok(cases.len())
+}
+
+/// Lists the APML sources of cases in a directory, sorted by names.
+fn cases(dir: &Path) -> Result<Vec<PathBuf>, ConformanceError> {
+	let mut cases = fs::read_dir(dir)
+		.map_err(|err| ConformanceError::Io(dir.to_path_buf(), err))?
+		.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+		.filter(|path| path.extension().is_some_and(|ext| ext == "apml"))
+		.collect::<Vec<_>>();
+	cases.sort();
+	Ok(cases)
+}
+
+/// Script printing variables named by its arguments, after sourcing a
+/// file.
+///
+/// The arguments are the file, the number of positional parameters, the
+/// positional parameters and the names of variables.
+///
+/// Each set variable is printed as NUL-terminated fields: the name,
+/// `s` and the value for strings, or `a`, the number of elements and
+/// the elements for arrays.
+const BASH_DUMP: &str = r#"__apml_file=$1 __apml_count=$2
+shift 2
+__apml_names=("${@:__apml_count+1}")
+set -- "${@:1:__apml_count}"
+source "$__apml_file" || exit
+for __apml_name in "${__apml_names[@]}"; do
+	declare -p "$__apml_name" &>/dev/null || continue
+	declare -n __apml_value="$__apml_name"
+	if [[ ${__apml_value@a} == *a* ]]; then
+		printf '%s\0a\0%s\0' "$__apml_name" "${#__apml_value[@]}"
+		if ((${#__apml_value[@]})); then
+			printf '%s\0' "${__apml_value[@]}"
+		fi
+	else
+		printf '%s\0s\0%s\0' "$__apml_name" "$__apml_value"
+	fi
+	declare +n __apml_value
+	unset __apml_value
+done
+"#;
+
+/// Sources an APML file with bash, returning the named variables in the
+/// JSON form of expected contexts.
+///
+/// Variables that are unset after sourcing are left out. Returns
+/// [`None`] if bash cannot be found, so that differential tests can be
+/// skipped on systems without it.
+///
+/// The file is sourced without positional parameters, see
+/// [`eval_with_bash_params`].
+pub fn eval_with_bash<'a, I>(
+	path: &Path,
+	names: I,
+) -> Result<Option<Value>, ConformanceError>
+where
+	I: IntoIterator<Item = &'a str>,
+{
+	eval_with_bash_params(path, &[], names)
+}
+
+/// Sources an APML file with bash and positional parameters, such as
+/// set by [`ApmlContext::set_positional_params`], returning the named
+/// variables as [`eval_with_bash`] does.
+pub fn eval_with_bash_params<'a, I>(
+	path: &Path,
+	params: &[String],
+	names: I,
+) -> Result<Option<Value>, ConformanceError>
+where
+	I: IntoIterator<Item = &'a str>,
+{
+	let bash_err =
+		|message: String| ConformanceError::Bash(path.to_path_buf(), message);
+	let output = match Command::new("bash")
+		.arg("--norc")
+		.arg("--noprofile")
+		.arg("-c")
+		.arg(BASH_DUMP)
+		.arg("bash")
+		.arg(path)
+		.arg(params.len().to_string())
+		.args(params)
+		.args(names)
+		.env_clear()
+		.output()
+	{
+		Ok(output) => output,
+		Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+		Err(err) => return Err(ConformanceError::Io(path.to_path_buf(), err)),
+	};
+	if !output.status.success() {
+		return Err(bash_err(
+			String::from_utf8_lossy(&output.stderr).into_owned(),
+		));
+	}
+	let stdout = String::from_utf8(output.stdout)
+		.map_err(|err| bash_err(err.to_string()))?;
+	let mut fields = stdout.split_terminator('\0');
+	let mut object = Map::new();
+	let mut next = || fields.next().ok_or_else(|| bash_err(stdout.clone()));
+	while let Ok(name) = next() {
+		let value = match next()? {
+			"s" => Value::String(next()?.to_string()),
+			"a" => {
+				let len = next()?
+					.parse::<usize>()
+					.map_err(|err| bash_err(err.to_string()))?;
+				Value::Array(
+					(0..len)
+						.map(|_| next().map(|text| Value::String(text.into())))
+						.collect::<Result<_, _>>()?,
+				)
+			}
+			kind => return Err(bash_err(format!("unknown kind {kind}"))),
+		};
+		object.insert(name.to_string(), value);
+	}
+	Ok(Some(Value::Object(object)))
+}
+
+/// Runs a single case, given the path to its APML source.
+pub fn run_case(path: &Path) -> Result<(), ConformanceError> {
+	let read = |path: &Path| {
+		fs::read_to_string(path)
+			.map_err(|err| ConformanceError::Io(path.to_path_buf(), err))
+	};
+	let src = read(path)?;
+	let expected_path = path.with_extension("json");
+	let expected = serde_json::from_str::<Value>(&read(&expected_path)?)
+		.map_err(|err| {
+			ConformanceError::InvalidExpectation(
+				expected_path.clone(),
+				err.to_string(),
+			)
+		})?;
+	if !is_context_value(&expected) {
+		return Err(ConformanceError::InvalidExpectation(
+			expected_path,
+			"not an object of strings and arrays of strings".to_string(),
+		));
+	}
+	let context = ApmlContext::eval_source(&src)
+		.map_err(|err| ConformanceError::Apml(path.to_path_buf(), err))?;
+	let actual = context_to_json(&context);
+	if actual != expected {
+		return Err(ConformanceError::Mismatch {
+			case: path.to_path_buf(),
+			expected: expected.to_string(),
+			actual: actual.to_string(),
+		});
+	}
+	Ok(())
+}
+
+fn is_context_value(value: &Value) -> bool {
+	value.as_object().is_some_and(|object| {
+		object.values().all(|value| match value {
+			Value::String(_) => true,
+			Value::Array(elements) => elements.iter().all(Value::is_string),
+			_ => false,
+		})
+	})
+}
+
+/// Converts a context into the JSON form used by expected contexts.
+pub fn context_to_json(context: &ApmlContext) -> Value {
+	let mut object = Map::new();
+	for (name, value) in context.iter() {
+		let value = match value {
+			VariableValue::String(text) => Value::String(text.clone()),
+			VariableValue::Array(elements) => Value::Array(
+				elements.iter().cloned().map(Value::String).collect(),
+			),
+		};
+		object.insert(name.clone(), value);
+	}
+	Value::Object(object)
+}
+
+/// Kind of a variable expansion, used to check the coverage of
+/// the corpus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ExpansionKind {
+	/// `$NAME`
+	Unbraced,
+	/// `${NAME}`
+	Braced,
+	/// `${NAME:offset:length}`
+	Substring,
+	/// `${NAME#pattern}`
+	StripShortestPrefix,
+	/// `${NAME##pattern}`
+	StripLongestPrefix,
+	/// `${NAME%pattern}`
+	StripShortestSuffix,
+	/// `${NAME%%pattern}`
+	StripLongestSuffix,
+	/// `${NAME/pattern/string}`
+	ReplaceOnce,
+	/// `${NAME//pattern/string}`
+	ReplaceAll,
+	/// `${NAME/#pattern/string}`
+	ReplacePrefix,
+	/// `${NAME/%pattern/string}`
+	ReplaceSuffix,
+	/// `${NAME^pattern}`
+	UpperOnce,
+	/// `${NAME^^pattern}`
+	UpperAll,
+	/// `${NAME,pattern}`
+	LowerOnce,
+	/// `${NAME,,pattern}`
+	LowerAll,
+	/// `${NAME:?text}`
+	ErrorOnUnset,
+	/// `${#NAME}`
+	Length,
+	/// `${NAME:-text}`
+	WhenUnset,
+	/// `${NAME:+text}`
+	WhenSet,
+	/// `${NAME[@]}`
+	ArrayElements,
+	/// `${NAME[*]}`
+	SingleWordElements,
+}
+
+impl ExpansionKind {
+	/// All kinds, in the order of declaration.
+	pub const ALL: [Self; Self::SingleWordElements as usize + 1] = [
+		Self::Unbraced,
+		Self::Braced,
+		Self::Substring,
+		Self::StripShortestPrefix,
+		Self::StripLongestPrefix,
+		Self::StripShortestSuffix,
+		Self::StripLongestSuffix,
+		Self::ReplaceOnce,
+		Self::ReplaceAll,
+		Self::ReplacePrefix,
+		Self::ReplaceSuffix,
+		Self::UpperOnce,
+		Self::UpperAll,
+		Self::LowerOnce,
+		Self::LowerAll,
+		Self::ErrorOnUnset,
+		Self::Length,
+		Self::WhenUnset,
+		Self::WhenSet,
+		Self::ArrayElements,
+		Self::SingleWordElements,
+	];
+
+	/// Returns the kind of a braced expansion modifier.
+	pub fn of_modifier(modifier: Option<&ExpansionModifier>) -> Self {
+		match modifier {
+			None => Self::Braced,
+			Some(ExpansionModifier::Substring { .. }) => Self::Substring,
+			Some(ExpansionModifier::StripShortestPrefix(_)) => {
+				Self::StripShortestPrefix
+			}
+			Some(ExpansionModifier::StripLongestPrefix(_)) => {
+				Self::StripLongestPrefix
+			}
+			Some(ExpansionModifier::StripShortestSuffix(_)) => {
+				Self::StripShortestSuffix
+			}
+			Some(ExpansionModifier::StripLongestSuffix(_)) => {
+				Self::StripLongestSuffix
+			}
+			Some(ExpansionModifier::ReplaceOnce { .. }) => Self::ReplaceOnce,
+			Some(ExpansionModifier::ReplaceAll { .. }) => Self::ReplaceAll,
+			Some(ExpansionModifier::ReplacePrefix { .. }) => {
+				Self::ReplacePrefix
+			}
+			Some(ExpansionModifier::ReplaceSuffix { .. }) => {
+				Self::ReplaceSuffix
+			}
+			Some(ExpansionModifier::UpperOnce(_)) => Self::UpperOnce,
+			Some(ExpansionModifier::UpperAll(_)) => Self::UpperAll,
+			Some(ExpansionModifier::LowerOnce(_)) => Self::LowerOnce,
+			Some(ExpansionModifier::LowerAll(_)) => Self::LowerAll,
+			Some(ExpansionModifier::ErrorOnUnset(_)) => Self::ErrorOnUnset,
+			Some(ExpansionModifier::Length) => Self::Length,
+			Some(ExpansionModifier::WhenUnset(_)) => Self::WhenUnset,
+			Some(ExpansionModifier::WhenSet(_)) => Self::WhenSet,
+			Some(ExpansionModifier::ArrayElements) => Self::ArrayElements,
+			Some(ExpansionModifier::SingleWordElements) => {
+				Self::SingleWordElements
+			}
+		}
+	}
+}
+
+/// Collects kinds of expansions used in a LST, including expansions
+/// nested in modifiers.
+pub fn expansion_kinds(lst: &ApmlLst) -> BTreeSet<ExpansionKind> {
+	fn walk_text(text: &Text, out: &mut BTreeSet<ExpansionKind>) {
+		for unit in &text.0 {
+			if let TextUnit::Unquoted(words) | TextUnit::DoubleQuote(words) =
+				unit
+			{
+				for word in words {
+					walk_word(word, out);
+				}
+			}
+		}
+	}
+
+	fn walk_word(word: &Word, out: &mut BTreeSet<ExpansionKind>) {
+		match word {
+			Word::UnbracedVariable(_) => {
+				out.insert(ExpansionKind::Unbraced);
+			}
+			Word::BracedVariable(exp) => {
+				out.insert(ExpansionKind::of_modifier(exp.modifier.as_ref()));
+				match &exp.modifier {
+					Some(
+						ExpansionModifier::ReplaceOnce { string, .. }
+						| ExpansionModifier::ReplaceAll { string, .. }
+						| ExpansionModifier::ReplacePrefix { string, .. }
+						| ExpansionModifier::ReplaceSuffix { string, .. },
+					) => {
+						if let Some(string) = string {
+							walk_text(string, out);
+						}
+					}
+					Some(
+						ExpansionModifier::ErrorOnUnset(text)
+						| ExpansionModifier::WhenUnset(text)
+						| ExpansionModifier::WhenSet(text),
+					) => walk_text(text, out),
+					_ => {}
+				}
+			}
+			Word::Literal(_) | Word::Subcommand(_) | Word::Custom(_) => {}
+		}
+	}
+
+	let mut kinds = BTreeSet::new();
+	for (_, def) in lst.variable_spans() {
+		match &def.value {
+			super::lst::VariableValue::String(text) => {
+				walk_text(text, &mut kinds)
+			}
+			super::lst::VariableValue::Array(tokens) => {
+				for token in tokens {
+					if let ArrayToken::Element(text) = token {
+						walk_text(text, &mut kinds);
+					}
+				}
+			}
+		}
+	}
+	kinds
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::{
+		ast::{ApmlAst, AstNode},
+		eval::eval_ast,
+	};
+
+	#[test]
+	fn test_corpus() {
+		assert!(run(corpus_dir()).unwrap() > 0);
+	}
+
+	#[test]
+	fn test_corpus_bash() {
+		for case in cases(&corpus_dir()).unwrap() {
+			let expected = serde_json::from_str::<Value>(
+				&fs::read_to_string(case.with_extension("json")).unwrap(),
+			)
+			.unwrap();
+			let src = fs::read_to_string(&case).unwrap();
+			let lst = ApmlLst::parse(&src).unwrap();
+			assert_eq!(lst.to_string(), src, "LST of {case:?}");
+			let actual = context_to_json(&ApmlContext::eval_lst(&lst).unwrap());
+			let mut names = BTreeSet::new();
+			names.extend(expected.as_object().unwrap().keys());
+			names.extend(actual.as_object().unwrap().keys());
+			let Some(bash) =
+				eval_with_bash(&case, names.iter().map(|name| name.as_str()))
+					.unwrap()
+			else {
+				eprintln!("skipped: bash not found");
+				return;
+			};
+			assert_eq!(expected, bash, "expected context of {case:?}");
+			assert_eq!(actual, bash, "evaluated context of {case:?}");
+		}
+	}
+
+	#[test]
+	fn test_corpus_coverage() {
+		for (index, kind) in ExpansionKind::ALL.iter().enumerate() {
+			assert_eq!(*kind as usize, index);
+		}
+		let mut kinds = BTreeSet::new();
+		for entry in fs::read_dir(corpus_dir()).unwrap() {
+			let path = entry.unwrap().path();
+			if path.extension().is_some_and(|ext| ext == "apml") {
+				let src = fs::read_to_string(&path).unwrap();
+				kinds.extend(expansion_kinds(&ApmlLst::parse(&src).unwrap()));
+			}
+		}
+		let missing = ExpansionKind::ALL
+			.into_iter()
+			.filter(|kind| !kinds.contains(kind))
+			.collect::<Vec<_>>();
+		assert!(missing.is_empty(), "not covered by corpus: {:?}", missing);
+	}
+
+	#[test]
+	fn test_ifs_joining_bash() {
+		let src = "arr=(x 'y z' w)\nA=\"$*\"\nB=\"${*}\"\nC=\"${arr[*]}\"\n\
+			D=\"$@\"\nE=\"${arr[@]}\"\nF=$*\nG=\"${*:-u}\"\nH=\"$2\"\n\
+			I=\"$10$1a\"\n";
+		let params = ["a", "b c", "d"].map(String::from).to_vec();
+		let dir = std::env::temp_dir()
+			.join(format!("libabbs-ifs-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		for (index, ifs) in ["", "IFS=\n", "IFS=:\n", "IFS=:,\n"]
+			.into_iter()
+			.enumerate()
+		{
+			let src = format!("{ifs}{src}");
+			let path = dir.join(format!("{index}.apml"));
+			fs::write(&path, &src).unwrap();
+			let mut context = ApmlContext::default();
+			context.set_positional_params(params.clone());
+			eval_ast(
+				&mut context,
+				&ApmlAst::emit_from(&ApmlLst::parse(&src).unwrap()).unwrap(),
+			)
+			.unwrap();
+			let actual = context_to_json(&context);
+			let names = actual.as_object().unwrap().keys();
+			let bash = eval_with_bash_params(
+				&path,
+				&params,
+				names.map(|name| name.as_str()),
+			)
+			.unwrap();
+			let Some(bash) = bash else {
+				eprintln!("skipped: bash not found");
+				break;
+			};
+			assert_eq!(actual, bash, "{ifs:?}");
+		}
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_mismatch() {
+		let dir = std::env::temp_dir()
+			.join(format!("libabbs-conformance-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(dir.join("a.apml"), "A=1\nB=(x)\n").unwrap();
+		fs::write(dir.join("a.json"), r#"{"A": "1", "B": ["y"]}"#).unwrap();
+		let err = run(&dir).unwrap_err();
+		fs::remove_dir_all(&dir).unwrap();
+		assert!(matches!(err, ConformanceError::Mismatch { .. }));
+	}
+}
@@ -0,0 +1,595 @@
+//! Helpers for relation fields, such as `PKGDEP`.
+//!
+//! [`sort`] sorts the entries of a field in the LST, as asked by review
+//! guidelines, without changing the layout of the definitions.
+
+use std::{borrow::Cow, sync::Arc};
+
+use thiserror::Error;
+
+use super::{
+	lint::literal_value,
+	lst::{self, ApmlLst, ArrayToken, LiteralPart, TextUnit, Word},
+	relations::{Dependency, RelationKind},
+};
+
+/// Errors produced while sorting relation fields.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SortError {
+	#[error("{0} is written in several quoted parts")]
+	MixedQuoting(String),
+	#[error(
+		"Comments in {0} must be on their own lines or follow single elements"
+	)]
+	SharedComment(String),
+}
+
+/// Policy of [`sort`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SortPolicy {
+	/// Whether package names are compared case-insensitively.
+	pub case_insensitive: bool,
+	/// Whether comments starting with `# group:` are kept in place, so
+	/// entries are only sorted between them.
+	pub respect_groups: bool,
+}
+
+impl Default for SortPolicy {
+	fn default() -> Self {
+		Self {
+			case_insensitive: true,
+			respect_groups: true,
+		}
+	}
+}
+
+impl SortPolicy {
+	/// Returns the sort key of an entry.
+	///
+	/// Only package names are compared, so entries with version
+	/// constraints stay next to the entries without. Malformed entries
+	/// are compared as a whole.
+	fn key(&self, entry: &str) -> (String, String) {
+		// the kind does not affect parsing
+		let name = Dependency::parse("", RelationKind::Depends, entry)
+			.map_or_else(|| entry.to_string(), |dependency| dependency.target);
+		let folded = if self.case_insensitive {
+			name.to_lowercase()
+		} else {
+			name.to_string()
+		};
+		(folded, name)
+	}
+
+	/// Returns if a comment is a group marker.
+	fn is_group_marker(&self, comment: &str) -> bool {
+		self.respect_groups && comment.trim_start().starts_with("group:")
+	}
+}
+
+/// Sorts the entries of all definitions of a field by package name.
+///
+/// Each definition, including appending ones, is sorted on its own, and
+/// entries with the same package name keep their order, so sorting is
+/// stable and sorting again changes nothing. Only the order of entries
+/// changes: spaces, line continuations and comments stay where they are,
+/// except that comments in arrays move along with the entries they are
+/// attached to. A comment is attached to the entry before it in the same
+/// line, or to the next entry if it is on its own line. If a comment
+/// is moved into the line of the closing parenthesis, a newline is
+/// inserted after it.
+///
+/// Entries containing expansions, such as `"${PKGDEP[@]}"`, are kept in
+/// place, and other entries are only sorted between them. With
+/// [`SortPolicy::respect_groups`], group markers are kept in place as
+/// well.
+///
+/// Returns if the LST is changed. On errors, the LST is left unchanged.
+pub fn sort(
+	lst: &mut ApmlLst,
+	field: &str,
+	policy: SortPolicy,
+) -> Result<bool, SortError> {
+	let mut sorted = Vec::new();
+	for (index, token) in lst.0.iter().enumerate() {
+		let lst::Token::Variable(def) = token else {
+			continue;
+		};
+		if def.name != field {
+			continue;
+		}
+		let value = match &def.value {
+			lst::VariableValue::String(text) => {
+				lst::VariableValue::String(Arc::new(
+					sort_text(text, &policy)
+						.ok_or_else(|| SortError::MixedQuoting(field.into()))?,
+				))
+			}
+			lst::VariableValue::Array(tokens) => lst::VariableValue::Array(
+				sort_array(tokens, &policy)
+					.ok_or_else(|| SortError::SharedComment(field.into()))?,
+			),
+		};
+		if value.to_string() != def.value.to_string() {
+			sorted.push((index, value));
+		}
+	}
+	let changed = !sorted.is_empty();
+	for (index, value) in sorted {
+		if let lst::Token::Variable(def) = &mut lst.0[index] {
+			def.value = value;
+		}
+	}
+	Ok(changed)
+}
+
+/// Returns the sorted order of items.
+///
+/// Items without keys are kept in place, and other items are stably
+/// sorted between them.
+fn sorted_order(keys: &[Option<(String, String)>]) -> Vec<usize> {
+	let mut order = (0..keys.len()).collect::<Vec<_>>();
+	let mut start = 0;
+	while start < keys.len() {
+		let len = keys[start..]
+			.iter()
+			.position(Option::is_none)
+			.unwrap_or(keys.len() - start);
+		order[start..start + len].sort_by_key(|index| &keys[*index]);
+		start += len + 1;
+	}
+	order
+}
+
+/// A character or an expansion of a string value.
+#[derive(Debug, Clone)]
+enum Atom<'a> {
+	Char(char),
+	Escaped(char),
+	LineContinuation,
+	Word(Word<'a>),
+}
+
+impl Atom<'_> {
+	fn is_separator(&self) -> bool {
+		matches!(self, Atom::Char(' ' | '\t' | '\n') | Atom::LineContinuation)
+	}
+}
+
+/// Sorts the entries of a string value.
+///
+/// Returns [`None`] if the value consists of several text units.
+fn sort_text<'a>(
+	text: &lst::Text<'a>,
+	policy: &SortPolicy,
+) -> Option<lst::Text<'a>> {
+	let unit = match text.0.as_slice() {
+		[TextUnit::SingleQuote(text)] => {
+			let atoms = text.chars().map(Atom::Char).collect();
+			let text = sort_atoms(atoms, policy)
+				.into_iter()
+				.filter_map(|atom| match atom {
+					Atom::Char(ch) => Some(ch),
+					_ => None,
+				})
+				.collect::<String>();
+			TextUnit::SingleQuote(text.into())
+		}
+		[TextUnit::DoubleQuote(words)] => {
+			let mut atoms = Vec::new();
+			for word in words {
+				let Word::Literal(parts) = word else {
+					atoms.push(Atom::Word(word.clone()));
+					continue;
+				};
+				for part in parts {
+					match part {
+						LiteralPart::String(text) => {
+							atoms.extend(text.chars().map(Atom::Char))
+						}
+						LiteralPart::Escaped(ch) => {
+							atoms.push(Atom::Escaped(*ch))
+						}
+						LiteralPart::LineContinuation => {
+							atoms.push(Atom::LineContinuation)
+						}
+					}
+				}
+			}
+			TextUnit::DoubleQuote(words_of(sort_atoms(atoms, policy)))
+		}
+		// a single entry at most
+		[] | [TextUnit::Unquoted(_) | TextUnit::AnsiCQuote(_)] => {
+			return Some(text.clone());
+		}
+		_ => return None,
+	};
+	Some(lst::Text(vec![unit]))
+}
+
+/// Sorts entries of a string value, keeping separators in place.
+fn sort_atoms<'a>(atoms: Vec<Atom<'a>>, policy: &SortPolicy) -> Vec<Atom<'a>> {
+	let mut entries = Vec::new();
+	let mut start = None;
+	for (index, atom) in atoms.iter().enumerate() {
+		match (atom.is_separator(), start) {
+			(false, None) => start = Some(index),
+			(true, Some(begin)) => {
+				entries.push(begin..index);
+				start = None;
+			}
+			_ => {}
+		}
+	}
+	if let Some(begin) = start {
+		entries.push(begin..atoms.len());
+	}
+	let keys = entries
+		.iter()
+		.map(|range| {
+			let mut entry = String::new();
+			for atom in &atoms[range.clone()] {
+				match atom {
+					Atom::Char(ch) | Atom::Escaped(ch) => entry.push(*ch),
+					Atom::LineContinuation => {}
+					Atom::Word(_) => return None,
+				}
+			}
+			Some(policy.key(&entry))
+		})
+		.collect::<Vec<_>>();
+
+	let order = sorted_order(&keys);
+	let mut result = Vec::with_capacity(atoms.len());
+	let mut next = 0;
+	for (index, range) in entries.iter().enumerate() {
+		result.extend_from_slice(&atoms[next..range.start]);
+		result.extend_from_slice(&atoms[entries[order[index]].clone()]);
+		next = range.end;
+	}
+	result.extend_from_slice(&atoms[next..]);
+	result
+}
+
+/// Converts atoms back into words, joining adjacent literal parts.
+fn words_of(atoms: Vec<Atom>) -> Vec<Word> {
+	let mut words = Vec::new();
+	for atom in atoms {
+		let part = match atom {
+			Atom::Word(word) => {
+				words.push(word);
+				continue;
+			}
+			Atom::Char(ch) => {
+				if let Some(Word::Literal(parts)) = words.last_mut()
+					&& let Some(LiteralPart::String(text)) = parts.last_mut()
+				{
+					text.to_mut().push(ch);
+					continue;
+				}
+				LiteralPart::String(Cow::Owned(ch.to_string()))
+			}
+			Atom::Escaped(ch) => LiteralPart::Escaped(ch),
+			Atom::LineContinuation => LiteralPart::LineContinuation,
+		};
+		match words.last_mut() {
+			Some(Word::Literal(parts)) => parts.push(part),
+			_ => words.push(Word::Literal(vec![part])),
+		}
+	}
+	words
+}
+
+/// Sorts the entries of an array value.
+///
+/// Returns [`None`] if comments cannot be attached to single entries.
+fn sort_array<'a>(
+	tokens: &[ArrayToken<'a>],
+	policy: &SortPolicy,
+) -> Option<Vec<ArrayToken<'a>>> {
+	let key = |token: &ArrayToken| match token {
+		ArrayToken::Element(text) => {
+			literal_value(text).map(|entry| policy.key(&entry))
+		}
+		_ => None,
+	};
+	if !tokens
+		.iter()
+		.any(|token| matches!(token, ArrayToken::Comment(_)))
+	{
+		// entries are moved between the slots of entries
+		let slots = tokens
+			.iter()
+			.enumerate()
+			.filter(|(_, token)| matches!(token, ArrayToken::Element(_)))
+			.map(|(index, _)| index)
+			.collect::<Vec<_>>();
+		let keys = slots
+			.iter()
+			.map(|index| key(&tokens[*index]))
+			.collect::<Vec<_>>();
+		let mut result = tokens.to_vec();
+		for (slot, index) in slots.iter().zip(sorted_order(&keys)) {
+			result[*slot] = tokens[slots[index]].clone();
+		}
+		return Some(result);
+	}
+
+	// lines are moved between the slots of lines, keeping indentation
+	let mut lines = Vec::new();
+	let mut start = 0;
+	for (index, token) in tokens.iter().enumerate() {
+		if matches!(token, ArrayToken::Newline) {
+			lines.push(start..index);
+			start = index + 1;
+		}
+	}
+	lines.push(start..tokens.len());
+	let contents = lines
+		.iter()
+		.map(|line| {
+			let indent = tokens[line.clone()]
+				.iter()
+				.take_while(|token| matches!(token, ArrayToken::Spacy(_)))
+				.count();
+			line.start + indent..line.end
+		})
+		.filter(|content| !content.is_empty())
+		.collect::<Vec<_>>();
+
+	// groups of lines, each ending with a entry, a group marker or the
+	// last line
+	let mut groups = Vec::<(Vec<usize>, Option<(String, String)>)>::new();
+	let mut pending = Vec::new();
+	for (index, content) in contents.iter().enumerate() {
+		match &tokens[content.clone()] {
+			[ArrayToken::Comment(comment)] => {
+				if policy.is_group_marker(comment) {
+					if !pending.is_empty() {
+						groups.push((std::mem::take(&mut pending), None));
+					}
+					groups.push((vec![index], None));
+				} else {
+					pending.push(index);
+				}
+			}
+			[element @ ArrayToken::Element(_), rest @ ..]
+				if is_entry_rest(rest) =>
+			{
+				pending.push(index);
+				groups.push((std::mem::take(&mut pending), key(element)));
+			}
+			_ => return None,
+		}
+	}
+	if !pending.is_empty() {
+		groups.push((pending, None));
+	}
+
+	let keys = groups
+		.iter()
+		.map(|(_, key)| key.clone())
+		.collect::<Vec<_>>();
+	let mut sorted = sorted_order(&keys)
+		.into_iter()
+		.flat_map(|index| groups[index].0.iter().map(|line| &contents[*line]));
+	let mut result = Vec::with_capacity(tokens.len());
+	let mut next = 0;
+	for content in &contents {
+		result.extend_from_slice(&tokens[next..content.start]);
+		result.extend_from_slice(&tokens[sorted.next()?.clone()]);
+		next = content.end;
+	}
+	result.extend_from_slice(&tokens[next..]);
+	// a comment moved into the last line would swallow the parenthesis
+	if matches!(result.last(), Some(ArrayToken::Comment(_))) {
+		result.push(ArrayToken::Newline);
+	}
+	Some(result)
+}
+
+/// Returns if the rest of a line after an entry consists of spaces and
+/// an optional comment.
+fn is_entry_rest(rest: &[ArrayToken]) -> bool {
+	let spaces = match rest {
+		[spaces @ .., ArrayToken::Comment(_)] => spaces,
+		spaces => spaces,
+	};
+	spaces
+		.iter()
+		.all(|token| matches!(token, ArrayToken::Spacy(_)))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::{ApmlContext, eval::EvalOptions};
+
+	fn sorted(src: &str, field: &str, policy: SortPolicy) -> String {
+		let mut lst = ApmlLst::parse(src).unwrap();
+		sort(&mut lst, field, policy).unwrap();
+		let result = lst.to_string();
+		assert!(!sort(&mut lst, field, policy).unwrap());
+		result
+	}
+
+	#[test]
+	fn test_sort() {
+		let policy = SortPolicy::default();
+		assert_eq!(
+			sorted(
+				"PKGDEP=\"zlib glibc>=2.35 Bzip2 \\\n\tattr acl \
+				gcc-runtime>=13 \\\n\tncurses\"\n",
+				"PKGDEP",
+				policy
+			),
+			"PKGDEP=\"acl attr Bzip2 \\\n\tgcc-runtime>=13 glibc>=2.35 \
+			ncurses \\\n\tzlib\"\n"
+		);
+		// constrained entries stay next to their base names
+		assert_eq!(
+			sorted("A='foo-x<=1 foo foo-x>=0.9 foo>=1'", "A", policy),
+			"A='foo foo>=1 foo-x<=1 foo-x>=0.9'"
+		);
+		assert_eq!(sorted("A=\"b A a B\"", "A", policy), "A=\"A a B b\"");
+		assert_eq!(
+			sorted(
+				"A=\"b A a B\"",
+				"A",
+				SortPolicy {
+					case_insensitive: false,
+					..policy
+				}
+			),
+			"A=\"A B a b\""
+		);
+		// expansions are kept in place
+		assert_eq!(
+			sorted(
+				"A=\"c b ${B}x a\\ $C d\"\nB=(b a)\nB+=(d c)\n",
+				"A",
+				policy
+			),
+			"A=\"b c ${B}x a\\ $C d\"\nB=(b a)\nB+=(d c)\n"
+		);
+		assert_eq!(
+			sorted("B=(b a)\nB+=(d\n\tc)\nC=x\n", "B", policy),
+			"B=(a b)\nB+=(c\n\td)\nC=x\n"
+		);
+		assert_eq!(sorted("A=b\nB=\n", "A", policy), "A=b\nB=\n");
+
+		let src = "BUILDDEP=(\n\t# group: tools\n\tmeson # build system\n\t\
+			cmake\n\t# group: libraries\n\tlibxml2\n\t# needed for docs\n\t\
+			Doxygen\n\t\"${PKGDEP[@]}\"\n\tboost\n)\n";
+		assert_eq!(
+			sorted(src, "BUILDDEP", policy),
+			"BUILDDEP=(\n\t# group: tools\n\tcmake\n\tmeson # build system\n\t\
+			# group: libraries\n\t# needed for docs\n\tDoxygen\n\tlibxml2\n\t\
+			\"${PKGDEP[@]}\"\n\tboost\n)\n"
+		);
+		assert_eq!(
+			sorted(
+				src,
+				"BUILDDEP",
+				SortPolicy {
+					respect_groups: false,
+					..policy
+				}
+			),
+			"BUILDDEP=(\n\tcmake\n\t# needed for docs\n\tDoxygen\n\t\
+			# group: libraries\n\tlibxml2\n\t# group: tools\n\t\
+			meson # build system\n\t\"${PKGDEP[@]}\"\n\tboost\n)\n"
+		);
+		assert_eq!(
+			sorted("A=(b # x\n a) # y\n", "A", policy),
+			"A=(a\n b # x\n) # y\n"
+		);
+
+		// errors leave the LST unchanged
+		for (src, err) in [
+			(
+				"A=\"b a\"\nA+=(b a # x\n)\n",
+				SortError::SharedComment("A".to_string()),
+			),
+			(
+				"A=\"b a\"\nA+=\"d c\"' e'\n",
+				SortError::MixedQuoting("A".to_string()),
+			),
+		] {
+			let mut lst = ApmlLst::parse(src).unwrap();
+			assert_eq!(sort(&mut lst, "A", policy), Err(err));
+			assert_eq!(lst.to_string(), src);
+		}
+	}
+
+	/// Sorting must not change evaluation results, other than the order
+	/// of entries of the sorted field, in the conformance corpus and the
+	/// test tree.
+	#[test]
+	fn test_sort_corpus() {
+		fn collect(dir: &std::path::Path, out: &mut Vec<std::path::PathBuf>) {
+			for entry in std::fs::read_dir(dir).unwrap() {
+				let path = entry.unwrap().path();
+				if path.is_dir() {
+					collect(&path, out);
+				} else if path.extension().is_some_and(|ext| ext == "apml")
+					|| path.ends_with("spec")
+					|| path.ends_with("defines")
+				{
+					out.push(path);
+				}
+			}
+		}
+
+		let eval = |lst: &ApmlLst| {
+			ApmlContext::eval_lst_with(
+				lst,
+				&mut EvalOptions {
+					track_influences: true,
+					..Default::default()
+				},
+			)
+		};
+		let entries = |context: &ApmlContext, name: &str| {
+			let mut entries = context
+				.read(name)
+				.as_string()
+				.split_whitespace()
+				.map(str::to_string)
+				.collect::<Vec<_>>();
+			entries.sort();
+			entries
+		};
+
+		let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+		let mut paths = Vec::new();
+		collect(&root.join("conformance"), &mut paths);
+		collect(&root.join("testrepo"), &mut paths);
+		let mut changed = 0;
+		for path in paths {
+			let src = std::fs::read_to_string(&path).unwrap();
+			let Ok(lst) = ApmlLst::parse(&src) else {
+				continue;
+			};
+			let Ok(expected) = eval(&lst) else {
+				continue;
+			};
+			for name in expected.keys() {
+				let mut sorted = lst.clone();
+				let Ok(is_changed) =
+					sort(&mut sorted, name, SortPolicy::default())
+				else {
+					continue;
+				};
+				changed += is_changed as usize;
+				assert!(
+					!sort(&mut sorted, name, SortPolicy::default()).unwrap()
+				);
+				let actual = eval(&sorted).unwrap();
+				assert_eq!(
+					entries(&actual, name),
+					entries(&expected, name),
+					"{} {}",
+					path.display(),
+					name
+				);
+				for other in expected.keys() {
+					if other != name
+						&& !expected
+							.influences(other)
+							.is_some_and(|influences| influences.contains(name))
+					{
+						assert_eq!(
+							actual.get(other),
+							expected.get(other),
+							"{} {}",
+							path.display(),
+							name
+						);
+					}
+				}
+			}
+		}
+		assert!(changed > 0);
+	}
+}
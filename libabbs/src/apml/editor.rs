@@ -9,11 +9,76 @@
 //! It basically just allows to add, rewrite and remove existing variable
 //! definitions.
 
+use std::{ops::Range, sync::Arc};
+
 use super::{
+	analysis::forward_references,
 	ast::{self, AstNode},
-	lst::{self, ApmlLst},
+	lint::literal_value,
+	lst::{self, ApmlLst, QuotingStyle},
 };
 
+/// Quoting style for string values written by editors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Style {
+	/// Unquoted (`A=value`), if the value needs no escaping.
+	Bare,
+	/// Single-quoted (`A='value'`), if the value contains no single quote.
+	SingleQuoted,
+	/// Double-quoted (`A="value"`).
+	#[default]
+	DoubleQuoted,
+	/// The style of the existing definition, as reported by
+	/// [`ApmlLst::quoting_of`].
+	///
+	/// Mixed-style values and new definitions are double-quoted.
+	MatchExisting,
+}
+
+impl Style {
+	/// Produces a text for a literal string in the style.
+	///
+	/// Falls back to double quotes if the value cannot be represented
+	/// in the style. [`Style::MatchExisting`] is treated as
+	/// [`Style::DoubleQuoted`]. Values containing control characters
+	/// other than newlines and tabs are always ANSI-C quoted, see
+	/// [`lst::TextUnit::ansi_c_quote`].
+	pub fn quote<'a>(&self, value: &str) -> lst::Text<'a> {
+		let literal = |value: &str| {
+			lst::Word::Literal(vec![lst::LiteralPart::String(
+				value.to_string().into(),
+			)])
+		};
+		let unit = match self {
+			_ if lst::TextUnit::needs_ansi_c_quote(value) => {
+				lst::TextUnit::ansi_c_quote(value)
+			}
+			Style::Bare if value.chars().all(is_bare_char) => {
+				if value.is_empty() {
+					return lst::Text(vec![]);
+				}
+				lst::TextUnit::Unquoted(vec![literal(value)])
+			}
+			Style::SingleQuoted if !value.contains('\'') => {
+				lst::TextUnit::SingleQuote(value.to_string().into())
+			}
+			_ => lst::TextUnit::DoubleQuote(vec![lst::Word::Literal(
+				lst::LiteralPart::escape(value),
+			)]),
+		};
+		lst::Text(vec![unit])
+	}
+}
+
+/// Returns if a character can be used in unquoted words without escaping.
+fn is_bare_char(ch: char) -> bool {
+	ch.is_ascii_alphanumeric()
+		|| matches!(
+			ch,
+			'-' | '_' | '.' | '/' | ':' | '+' | '@' | '%' | ',' | '='
+		)
+}
+
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct ApmlEditor<'a, 'b>(&'a mut ApmlLst<'b>);
@@ -198,6 +263,27 @@ impl<'b> ApmlEditor<'_, 'b> {
 		self.lst_tokens_mut().push(lst::Token::Newline);
 	}
 
+	/// Sets a variable to a literal string in the given quoting style.
+	///
+	/// The first existing definition is replaced, or a new definition is
+	/// appended if there is none.
+	pub fn set_string(&mut self, name: &'b str, value: &str, style: Style) {
+		let style = match style {
+			Style::MatchExisting => {
+				match self.0.quoting_of(name).map(|summary| summary.style()) {
+					Some(QuotingStyle::Bare) => Style::Bare,
+					Some(QuotingStyle::SingleQuoted) => Style::SingleQuoted,
+					_ => Style::DoubleQuoted,
+				}
+			}
+			style => style,
+		};
+		self.replace_var_lst(
+			name,
+			lst::VariableValue::String(Arc::new(style.quote(value))),
+		);
+	}
+
 	/// Removes a variable definition.
 	///
 	/// The given index must points to a variable definition token.
@@ -216,7 +302,12 @@ impl<'b> ApmlEditor<'_, 'b> {
 			.count();
 		let mut start = index;
 		let tokens = self.lst_tokens();
-		if matches!(tokens.get(index - 2), Some(lst::Token::Comment(_))) {
+		let before = |index: usize, offset: usize| {
+			index
+				.checked_sub(offset)
+				.and_then(|index| tokens.get(index))
+		};
+		if matches!(before(index, 2), Some(lst::Token::Comment(_))) {
 			// scan for next line
 			if !tokens
 				.iter()
@@ -227,19 +318,297 @@ impl<'b> ApmlEditor<'_, 'b> {
 				.any(|token| matches!(token, lst::Token::Variable(_)))
 			{
 				// next line is empty, scan for removable comments
-				while matches!(tokens.get(start - 1), Some(lst::Token::Newline))
-					&& matches!(
-						tokens.get(start - 2),
-						Some(lst::Token::Comment(_))
-					) && matches!(
-					tokens.get(start - 3),
-					Some(lst::Token::Newline)
-				) {
+				while matches!(before(start, 1), Some(lst::Token::Newline))
+					&& matches!(before(start, 2), Some(lst::Token::Comment(_)))
+					&& matches!(before(start, 3), Some(lst::Token::Newline))
+				{
 					start -= 2;
 				}
 			}
 		}
-		self.lst_tokens_mut().drain(start..=(index + after));
+		let end = (index + after).min(self.lst_tokens().len() - 1);
+		self.lst_tokens_mut().drain(start..=end);
+	}
+
+	/// Removes the first definition of a variable, along with comments
+	/// removed by [`ApmlEditor::remove_var`].
+	///
+	/// Variables are assumed to be organized into groups separated by
+	/// single blank lines. If the whole group is removed, the blank lines
+	/// around it are merged, so that groups are still separated by one
+	/// blank line.
+	///
+	/// Returns [`false`] if the variable is not defined.
+	pub fn remove_variable(&mut self, name: &str) -> bool {
+		let Some(index) = self.find_var_index(name) else {
+			return false;
+		};
+		let len = self.lst_tokens().len();
+		let end = self.line_end(index);
+		self.remove_var(index);
+		let pos = end - (len - self.lst_tokens().len());
+		self.limit_blank_lines_at(pos, 1);
+		true
+	}
+
+	/// Inserts a variable definition at the position given by the order
+	/// of groups of variables, or replaces the existing definition.
+	///
+	/// A new definition is placed after the nearest preceding variable
+	/// of its group, or before the nearest following one. If no variable
+	/// of its group is defined, a new group is started after the nearest
+	/// preceding group, or before the nearest following group, separated
+	/// by one blank line. Variables not listed in any group are treated
+	/// as a group of their own after all groups.
+	///
+	/// The group order gives way to [forward references](forward_references):
+	/// if the new definition would reference a variable defined later, it is
+	/// placed after the last such definition instead, and if a definition
+	/// before it would reference it, it is placed before the first such
+	/// definition. If both happen, the references form a cycle and the
+	/// group order is kept.
+	pub fn insert_ordered(
+		&mut self,
+		name: &'b str,
+		value: &ast::VariableValue<'b>,
+		groups: &[&[&str]],
+	) {
+		if self.find_var_index(name).is_some() {
+			self.replace_var_ast(name, value);
+			return;
+		}
+		let token = lst::Token::Variable(lst::VariableDefinition {
+			name: name.into(),
+			op: lst::VariableOp::Assignment,
+			value: value.lower(),
+		});
+		let original = self.lst_tokens().clone();
+		self.insert_grouped(name, token.clone(), groups);
+
+		let refs = forward_references(self.0);
+		let referenced = refs
+			.iter()
+			.filter(|r| r.referencing == name)
+			.map(|r| r.name.as_str())
+			.collect::<Vec<_>>();
+		let referencing = refs
+			.iter()
+			.filter(|r| r.name == name)
+			.map(|r| r.reference.start)
+			.min();
+		let index = match (referenced.is_empty(), referencing) {
+			(false, None) => {
+				*self.lst_tokens_mut() = original;
+				let last = referenced
+					.iter()
+					.filter_map(|var| self.find_var_index(var))
+					.max()
+					.expect("referenced variables are defined");
+				self.line_end(last)
+			}
+			(true, Some(offset)) => {
+				// the reference is before the new definition, so its
+				// offset is not shifted by removing the definition
+				*self.lst_tokens_mut() = original;
+				let first = self
+					.0
+					.token_spans()
+					.position(|(span, _)| span.contains(offset))
+					.expect("reference is in the LST");
+				self.comments_start(first)
+			}
+			_ => return,
+		};
+		self.insert_line(index, vec![token]);
+	}
+
+	/// Inserts a new variable definition at the position given by the
+	/// order of groups of variables.
+	fn insert_grouped(
+		&mut self,
+		name: &str,
+		token: lst::Token<'b>,
+		groups: &[&[&str]],
+	) {
+		let (group, position) = groups
+			.iter()
+			.enumerate()
+			.find_map(|(group, members)| {
+				let position = members.iter().position(|var| *var == name)?;
+				Some((group, position))
+			})
+			.unwrap_or((groups.len(), 0));
+
+		// within the group
+		if let Some(members) = groups.get(group) {
+			let (preceding, following) = members.split_at(position);
+			if let Some(index) = preceding
+				.iter()
+				.rev()
+				.find_map(|var| self.find_var_index(var))
+			{
+				let index = self.line_end(index);
+				self.insert_line(index, vec![token]);
+				return;
+			}
+			if let Some(index) =
+				following.iter().find_map(|var| self.find_var_index(var))
+			{
+				let index = self.comments_start(index);
+				self.insert_line(index, vec![token]);
+				return;
+			}
+		}
+
+		// as a new group
+		let defined = |editor: &Self, members: &[&str]| {
+			members
+				.iter()
+				.filter_map(|var| editor.find_var_index(var))
+				.collect::<Vec<_>>()
+		};
+		if let Some(index) = groups[..group.min(groups.len())]
+			.iter()
+			.rev()
+			.find_map(|members| defined(self, members).into_iter().max())
+		{
+			let index = self.line_end(index);
+			self.insert_line(index, vec![lst::Token::Newline, token]);
+			let next = index + 3;
+			if next < self.lst_tokens().len()
+				&& !matches!(self.lst_tokens()[next], lst::Token::Newline)
+				&& self.0.blank_line_runs().all(|run| run.start != next)
+			{
+				self.lst_tokens_mut().insert(next, lst::Token::Newline);
+			}
+		} else if let Some(index) = groups
+			.iter()
+			.skip(group + 1)
+			.find_map(|members| defined(self, members).into_iter().min())
+		{
+			let index = self.comments_start(index);
+			self.insert_line(index, vec![token, lst::Token::Newline]);
+		} else {
+			if self.lst_variables().next().is_some() {
+				self.ensure_end_newline();
+				self.lst_tokens_mut().push(lst::Token::Newline);
+			}
+			self.ensure_end_newline();
+			self.lst_tokens_mut().push(token);
+			self.lst_tokens_mut().push(lst::Token::Newline);
+		}
+	}
+
+	/// Limits blank lines to at most `max_consecutive` lines in a row.
+	///
+	/// Blank lines at the start and the end of the text are removed.
+	pub fn normalize_blank_lines(&mut self, max_consecutive: usize) {
+		let len = self.lst_tokens().len();
+		let runs = self.0.blank_line_runs().collect::<Vec<_>>();
+		for run in runs.into_iter().rev() {
+			let max = if run.start == 0 || run.end == len {
+				0
+			} else {
+				max_consecutive
+			};
+			self.truncate_blank_lines(run, max);
+		}
+	}
+
+	/// Makes the text end with exactly one newline.
+	///
+	/// Blank lines and spaces after the last line are removed, and a
+	/// newline is added if it is missing. Blank texts become empty, see
+	/// [`ApmlLst::is_blank`].
+	pub fn normalize_end_newline(&mut self) {
+		if self.0.is_blank() {
+			self.lst_tokens_mut().clear();
+			return;
+		}
+		let tokens = self.lst_tokens_mut();
+		while matches!(tokens.last(), Some(lst::Token::Spacy(_))) {
+			tokens.pop();
+		}
+		self.ensure_end_newline();
+		let len = self.lst_tokens().len();
+		if let Some(run) = self.0.blank_line_runs().last()
+			&& run.end == len
+		{
+			self.truncate_blank_lines(run, 0);
+		}
+	}
+
+	/// Limits the run of blank lines containing or touching a position.
+	fn limit_blank_lines_at(&mut self, pos: usize, max_consecutive: usize) {
+		let len = self.lst_tokens().len();
+		let run = self
+			.0
+			.blank_line_runs()
+			.find(|run| run.start <= pos && pos <= run.end);
+		if let Some(run) = run {
+			let max = if run.start == 0 || run.end == len {
+				0
+			} else {
+				max_consecutive
+			};
+			self.truncate_blank_lines(run, max);
+		}
+	}
+
+	/// Removes leading lines of a run of blank lines, keeping at most
+	/// `max` lines.
+	fn truncate_blank_lines(&mut self, run: Range<usize>, max: usize) {
+		let tokens = self.lst_tokens_mut();
+		let newlines = run
+			.clone()
+			.filter(|index| matches!(tokens[*index], lst::Token::Newline))
+			.collect::<Vec<_>>();
+		if newlines.len() > max {
+			let end = newlines[newlines.len() - max - 1] + 1;
+			tokens.drain(run.start..end);
+		}
+	}
+
+	/// Returns the index after the newline ending the line of a token.
+	fn line_end(&self, index: usize) -> usize {
+		let tokens = &self.0.0;
+		tokens[index..]
+			.iter()
+			.position(|token| matches!(token, lst::Token::Newline))
+			.map_or(tokens.len(), |offset| index + offset + 1)
+	}
+
+	/// Returns the index of the first comment line directly before
+	/// the line of a token, or the start of the line.
+	fn comments_start(&self, index: usize) -> usize {
+		let tokens = &self.0.0;
+		let mut start = tokens[..index]
+			.iter()
+			.rposition(|token| matches!(token, lst::Token::Newline))
+			.map_or(0, |index| index + 1);
+		while start >= 2
+			&& matches!(tokens[start - 2], lst::Token::Comment(_))
+			&& (start == 2 || matches!(tokens[start - 3], lst::Token::Newline))
+		{
+			start -= 2;
+		}
+		start
+	}
+
+	/// Inserts tokens followed by a newline at the start of a line.
+	///
+	/// If the index is the end of the text, a newline is ensured before.
+	fn insert_line(
+		&mut self,
+		mut index: usize,
+		mut tokens: Vec<lst::Token<'b>>,
+	) {
+		if index == self.lst_tokens().len() {
+			self.ensure_end_newline();
+			index = self.lst_tokens().len();
+		}
+		tokens.push(lst::Token::Newline);
+		self.lst_tokens_mut().splice(index..index, tokens);
 	}
 
 	/// Iterates over all comment lines.
@@ -254,6 +623,356 @@ impl<'b> ApmlEditor<'_, 'b> {
 	}
 }
 
+/// Editing API for the value of a single variable definition.
+///
+/// See [`ApmlLst::edit_matching`].
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct ValueEditor<'a, 'b>(&'a mut lst::VariableDefinition<'b>);
+
+impl<'a, 'b> ValueEditor<'a, 'b> {
+	/// Wraps the given definition with editing API.
+	pub fn wrap(def: &'a mut lst::VariableDefinition<'b>) -> Self {
+		Self(def)
+	}
+}
+
+impl<'b> ValueEditor<'_, 'b> {
+	/// Returns the name of the variable.
+	pub fn name(&self) -> &str {
+		&self.0.name
+	}
+
+	/// Returns the value in LST form.
+	pub fn value(&self) -> &lst::VariableValue<'b> {
+		&self.0.value
+	}
+
+	/// Returns the value in LST form for modification.
+	pub fn value_mut(&mut self) -> &mut lst::VariableValue<'b> {
+		&mut self.0.value
+	}
+
+	/// Returns the words of a literal value.
+	///
+	/// Strings are split at whitespaces, and arrays give their elements.
+	/// Returns [`None`] if the value contains expansions.
+	pub fn words(&self) -> Option<Vec<String>> {
+		match &self.0.value {
+			lst::VariableValue::String(text) => Some(
+				literal_value(text)?
+					.split_whitespace()
+					.map(str::to_string)
+					.collect(),
+			),
+			lst::VariableValue::Array(tokens) => tokens
+				.iter()
+				.filter_map(|token| match token {
+					lst::ArrayToken::Element(text) => Some(literal_value(text)),
+					_ => None,
+				})
+				.collect(),
+		}
+	}
+
+	/// Returns if a literal value contains a word.
+	pub fn contains_word(&self, word: &str) -> bool {
+		self.words()
+			.is_some_and(|words| words.iter().any(|item| item == word))
+	}
+
+	/// Appends a word to a literal value.
+	///
+	/// Strings get the word after a space, inside the last quoted part if
+	/// there is one, so that the layout of multi-line values is kept.
+	/// Arrays get a new element after the last one.
+	///
+	/// Returns `false` without changing anything if the value contains
+	/// expansions.
+	pub fn push_word(&mut self, word: &str) -> bool {
+		if self.words().is_none() {
+			return false;
+		}
+		match &mut self.0.value {
+			lst::VariableValue::String(text) => {
+				let old = literal_value(text).unwrap_or_default();
+				let separator = if old.is_empty() { "" } else { " " };
+				let addition = format!("{}{}", separator, word);
+				let text = Arc::make_mut(text);
+				match text.0.last_mut() {
+					Some(lst::TextUnit::DoubleQuote(words)) => {
+						words.push(lst::Word::Literal(
+							lst::LiteralPart::escape(&addition),
+						));
+					}
+					Some(lst::TextUnit::SingleQuote(value))
+						if !word.contains('\'') =>
+					{
+						value.to_mut().push_str(&addition);
+					}
+					_ => {
+						let style = match lst::QuotingSummary::of_text(text)
+							.style()
+						{
+							QuotingStyle::Bare => Style::Bare,
+							QuotingStyle::SingleQuoted => Style::SingleQuoted,
+							_ => Style::DoubleQuoted,
+						};
+						*text = style.quote(&(old + &addition));
+					}
+				}
+			}
+			lst::VariableValue::Array(tokens) => {
+				let element =
+					lst::ArrayToken::Element(Arc::new(Style::Bare.quote(word)));
+				match tokens.iter().rposition(|token| {
+					matches!(token, lst::ArrayToken::Element(_))
+				}) {
+					Some(index) => {
+						tokens.splice(
+							index + 1..index + 1,
+							[lst::ArrayToken::Spacy(' '), element],
+						);
+					}
+					None => tokens.insert(0, element),
+				}
+			}
+		}
+		true
+	}
+
+	/// Replaces the words of a literal value, keeping its layout.
+	///
+	/// Words are replaced in place, and the separators between them,
+	/// including line continuations and newlines, are kept. Extra words
+	/// are appended with the separator before the last word, or before
+	/// the only word, so that values written one word per line stay so.
+	/// Removed words are taken away along with the separators before them.
+	///
+	/// Strings written in a single double-quoted or single-quoted part
+	/// keep their layout. Other strings are rewritten with words joined
+	/// by spaces.
+	///
+	/// Returns `false` without changing anything if the value contains
+	/// expansions.
+	pub fn set_words(&mut self, words: &[&str]) -> bool {
+		let Some(old) = self.words() else {
+			return false;
+		};
+		if old == words {
+			return true;
+		}
+		match &mut self.0.value {
+			lst::VariableValue::String(text) => {
+				let text = Arc::make_mut(text);
+				let relaid = match text.0.as_slice() {
+					[unit] => relayout_unit(unit, words),
+					_ => None,
+				};
+				*text = match relaid {
+					Some(unit) => lst::Text(vec![unit]),
+					None => style_of(text).quote(&words.join(" ")),
+				};
+			}
+			lst::VariableValue::Array(tokens) => set_array_words(tokens, words),
+		}
+		true
+	}
+}
+
+/// Returns the editor style closest to the quoting of a text.
+fn style_of(text: &lst::Text) -> Style {
+	match lst::QuotingSummary::of_text(text).style() {
+		QuotingStyle::Bare => Style::Bare,
+		QuotingStyle::SingleQuoted => Style::SingleQuoted,
+		_ => Style::DoubleQuoted,
+	}
+}
+
+/// Character of a quoted string, or a line continuation in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Atom {
+	Char(char),
+	LineContinuation,
+}
+
+impl Atom {
+	fn is_separator(self) -> bool {
+		match self {
+			Atom::Char(ch) => ch.is_whitespace(),
+			Atom::LineContinuation => true,
+		}
+	}
+}
+
+/// Replaces the words of a quoted text unit, see
+/// [`ValueEditor::set_words`].
+///
+/// Returns [`None`] if the unit is not quoted, or if the words cannot be
+/// written in its quoting.
+fn relayout_unit<'a>(
+	unit: &lst::TextUnit,
+	words: &[&str],
+) -> Option<lst::TextUnit<'a>> {
+	let atoms = match unit {
+		lst::TextUnit::SingleQuote(text) => {
+			if words.iter().any(|word| word.contains('\'')) {
+				return None;
+			}
+			text.chars().map(Atom::Char).collect::<Vec<_>>()
+		}
+		lst::TextUnit::DoubleQuote(text) => {
+			let mut atoms = Vec::new();
+			for word in text {
+				let lst::Word::Literal(parts) = word else {
+					return None;
+				};
+				for part in parts {
+					match part {
+						lst::LiteralPart::String(text) => {
+							atoms.extend(text.chars().map(Atom::Char))
+						}
+						lst::LiteralPart::Escaped(ch) => {
+							atoms.push(Atom::Char(*ch))
+						}
+						lst::LiteralPart::LineContinuation => {
+							atoms.push(Atom::LineContinuation)
+						}
+					}
+				}
+			}
+			atoms
+		}
+		_ => return None,
+	};
+
+	// split into separators around words
+	let mut separators = vec![vec![]];
+	for (index, atom) in atoms.iter().enumerate() {
+		if atom.is_separator() {
+			separators.last_mut().unwrap().push(*atom);
+		} else if atoms.get(index + 1).is_none_or(|next| next.is_separator()) {
+			separators.push(vec![]);
+		}
+	}
+	let count = separators.len() - 1;
+	let between = match count {
+		0 => vec![],
+		1 => separators[0].clone(),
+		_ => separators[count - 1].clone(),
+	};
+	let between = if between.is_empty() {
+		vec![Atom::Char(' ')]
+	} else {
+		between
+	};
+	let mut result = Vec::new();
+	for (index, word) in words.iter().enumerate() {
+		match separators.get(index) {
+			Some(separator) if index < count => {
+				result.extend_from_slice(separator)
+			}
+			_ if index == 0 => {}
+			_ => result.extend_from_slice(&between),
+		}
+		result.extend(word.chars().map(Atom::Char));
+	}
+	if !words.is_empty() {
+		result.extend_from_slice(&separators[count]);
+	}
+
+	Some(match unit {
+		lst::TextUnit::SingleQuote(_) => lst::TextUnit::SingleQuote(
+			result
+				.into_iter()
+				.filter_map(|atom| match atom {
+					Atom::Char(ch) => Some(ch),
+					Atom::LineContinuation => None,
+				})
+				.collect::<String>()
+				.into(),
+		),
+		_ => {
+			let mut parts = Vec::new();
+			for chunk in result.chunk_by(|a, b| {
+				(*a == Atom::LineContinuation) == (*b == Atom::LineContinuation)
+			}) {
+				if chunk[0] == Atom::LineContinuation {
+					parts.extend(
+						chunk
+							.iter()
+							.map(|_| lst::LiteralPart::LineContinuation),
+					);
+				} else {
+					let text = chunk
+						.iter()
+						.filter_map(|atom| match atom {
+							Atom::Char(ch) => Some(*ch),
+							Atom::LineContinuation => None,
+						})
+						.collect::<String>();
+					parts.extend(lst::LiteralPart::escape(text));
+				}
+			}
+			lst::TextUnit::DoubleQuote(if parts.is_empty() {
+				vec![]
+			} else {
+				vec![lst::Word::Literal(parts)]
+			})
+		}
+	})
+}
+
+/// Replaces the elements of an array, see [`ValueEditor::set_words`].
+fn set_array_words(tokens: &mut Vec<lst::ArrayToken>, words: &[&str]) {
+	let positions = tokens
+		.iter()
+		.enumerate()
+		.filter(|(_, token)| matches!(token, lst::ArrayToken::Element(_)))
+		.map(|(index, _)| index)
+		.collect::<Vec<_>>();
+	let count = positions.len();
+	for (position, word) in positions.iter().zip(words) {
+		let lst::ArrayToken::Element(text) = &mut tokens[*position] else {
+			unreachable!()
+		};
+		*text = Arc::new(style_of(text).quote(word));
+	}
+	if words.len() < count {
+		let start = match words.len() {
+			0 => positions[0],
+			kept => positions[kept - 1] + 1,
+		};
+		tokens.drain(start..=positions[count - 1]);
+	} else if words.len() > count {
+		let between = match count {
+			0 => &[][..],
+			1 => &tokens[..positions[0]],
+			_ => &tokens[positions[count - 2] + 1..positions[count - 1]],
+		};
+		// comments end the separators of their lines
+		let start = between
+			.iter()
+			.rposition(|token| matches!(token, lst::ArrayToken::Comment(_)))
+			.map_or(0, |index| index + 1);
+		let mut between = between[start..].to_vec();
+		if between.is_empty() {
+			between.push(lst::ArrayToken::Spacy(' '));
+		}
+		let mut index = positions.last().map_or(0, |position| position + 1);
+		for (added, word) in words[count..].iter().enumerate() {
+			let element =
+				lst::ArrayToken::Element(Arc::new(Style::Bare.quote(word)));
+			if count + added != 0 {
+				tokens.splice(index..index, between.iter().cloned());
+				index += between.len();
+			}
+			tokens.insert(index, element);
+			index += 1;
+		}
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use crate::apml::lst::ApmlLst;
@@ -338,6 +1057,30 @@ mod test {
 		assert_eq!(lst.to_string(), "a=\"a\"\nb=c");
 	}
 
+	#[test]
+	fn test_set_string() {
+		let mut lst =
+			ApmlLst::parse("a=b\nb='c'\nc=\"d\"\nd=\"e\"'f'\n").unwrap();
+		let mut editor = ApmlEditor::wrap(&mut lst);
+		editor.set_string("a", "x", Style::MatchExisting);
+		editor.set_string("b", "x", Style::MatchExisting);
+		editor.set_string("c", "x", Style::MatchExisting);
+		editor.set_string("d", "x", Style::MatchExisting);
+		editor.set_string("e", "x", Style::MatchExisting);
+		assert_eq!(lst.to_string(), "a=x\nb='x'\nc=\"x\"\nd=\"x\"\ne=\"x\"\n");
+		let mut editor = ApmlEditor::wrap(&mut lst);
+		editor.set_string("a", "x y", Style::MatchExisting);
+		editor.set_string("b", "it's $x", Style::MatchExisting);
+		editor.set_string("c", "", Style::Bare);
+		assert_eq!(
+			lst.to_string(),
+			"a=\"x y\"\nb=\"it's \\$x\"\nc=\nd=\"x\"\ne=\"x\"\n"
+		);
+		let mut editor = ApmlEditor::wrap(&mut lst);
+		editor.set_string("b", "x\ry", Style::SingleQuoted);
+		assert!(lst.to_string().starts_with("a=\"x y\"\nb=$'x\\ry'\n"));
+	}
+
 	#[test]
 	fn test_remove_var() {
 		let mut lst = ApmlLst::parse("a=b\nb=c\n\nc=\"$1\"").unwrap();
@@ -351,6 +1094,90 @@ mod test {
 		assert_eq!(lst.to_string(), "a=b # a\n\n# a\nc=\"$1\"");
 	}
 
+	#[test]
+	fn test_remove_variable() {
+		let mut lst = ApmlLst::parse("a=1\nb=2\n\nc=3\n\nd=4\n").unwrap();
+		let mut editor = ApmlEditor::wrap(&mut lst);
+		assert!(editor.remove_variable("c"));
+		assert!(!editor.remove_variable("c"));
+		assert_eq!(lst.to_string(), "a=1\nb=2\n\nd=4\n");
+		let mut editor = ApmlEditor::wrap(&mut lst);
+		assert!(editor.remove_variable("b"));
+		assert_eq!(lst.to_string(), "a=1\n\nd=4\n");
+		let mut editor = ApmlEditor::wrap(&mut lst);
+		assert!(editor.remove_variable("a"));
+		assert_eq!(lst.to_string(), "d=4\n");
+
+		let mut lst = ApmlLst::parse("a=1\n\n# c\nc=3\n\nd=4").unwrap();
+		let mut editor = ApmlEditor::wrap(&mut lst);
+		assert!(editor.remove_variable("c"));
+		assert_eq!(lst.to_string(), "a=1\n\nd=4");
+		let mut editor = ApmlEditor::wrap(&mut lst);
+		assert!(editor.remove_variable("d"));
+		assert_eq!(lst.to_string(), "a=1\n");
+	}
+
+	#[test]
+	fn test_insert_ordered() {
+		const GROUPS: &[&[&str]] = &[&["a", "b"], &["c", "d"], &["e"]];
+		let value = ast::VariableValue::String("x".into());
+		let insert = |src: &str, name: &'static str| {
+			let mut lst = ApmlLst::parse(src).unwrap();
+			ApmlEditor::wrap(&mut lst).insert_ordered(name, &value, GROUPS);
+			lst.to_string()
+		};
+		assert_eq!(insert("a=1\n\nd=4\n", "b"), "a=1\nb=\"x\"\n\nd=4\n");
+		assert_eq!(
+			insert("a=1\n\n# d\nd=4\n", "c"),
+			"a=1\n\nc=\"x\"\n# d\nd=4\n"
+		);
+		assert_eq!(insert("a=1\n\nd=4\n", "a"), "a=\"x\"\n\nd=4\n");
+		// into empty groups
+		assert_eq!(insert("a=1\n\ne=5\n", "c"), "a=1\n\nc=\"x\"\n\ne=5\n");
+		assert_eq!(insert("a=1\ne=5", "d"), "a=1\n\nd=\"x\"\n\ne=5");
+		assert_eq!(insert("# e\ne=5\n", "b"), "b=\"x\"\n\n# e\ne=5\n");
+		assert_eq!(insert("a=1", "e"), "a=1\n\ne=\"x\"\n");
+		assert_eq!(insert("a=1\n", "f"), "a=1\n\nf=\"x\"\n");
+		assert_eq!(insert("", "f"), "f=\"x\"\n");
+
+		// forward references
+		let lst = ApmlLst::parse("v=\"$e\"\n").unwrap();
+		let value = ast::ApmlAst::emit_from(&lst).unwrap().0.remove(0).value;
+		let mut lst = ApmlLst::parse("a=1\n\ne=5\n").unwrap();
+		ApmlEditor::wrap(&mut lst).insert_ordered("b", &value, GROUPS);
+		assert_eq!(lst.to_string(), "a=1\n\ne=5\nb=\"${e}\"\n");
+		assert_eq!(insert("# a\na=\"$e\"\n", "e"), "e=\"x\"\n# a\na=\"$e\"\n");
+	}
+
+	#[test]
+	fn test_normalize_blank_lines() {
+		let mut lst =
+			ApmlLst::parse("\n\na=1\n\n  \n\nb=2 # b\n\nc=3\n\n\n").unwrap();
+		assert_eq!(
+			lst.blank_line_runs().collect::<Vec<_>>(),
+			vec![0..2, 4..9, 13..14, 16..18]
+		);
+		ApmlEditor::wrap(&mut lst).normalize_blank_lines(1);
+		assert_eq!(lst.to_string(), "a=1\n\nb=2 # b\n\nc=3\n");
+	}
+
+	#[test]
+	fn test_normalize_end_newline() {
+		let normalize = |src: &str| {
+			let mut lst = ApmlLst::parse(src).unwrap();
+			ApmlEditor::wrap(&mut lst).normalize_end_newline();
+			lst.to_string()
+		};
+		assert_eq!(normalize("a=1"), "a=1\n");
+		assert_eq!(normalize("a=1 # x  "), "a=1 # x  \n");
+		assert_eq!(normalize("a=1\n"), "a=1\n");
+		assert_eq!(normalize("\na=1\n\n  \n\t"), "\na=1\n");
+		assert_eq!(normalize("a=(\n1\n)  "), "a=(\n1\n)\n");
+		assert_eq!(normalize("a=1 \\\n"), "a=1 \\\n\n");
+		assert_eq!(normalize(""), "");
+		assert_eq!(normalize(" \n\t\n "), "");
+	}
+
 	#[test]
 	fn test_comments() {
 		let mut lst =
@@ -358,4 +1185,35 @@ mod test {
 		let editor = ApmlEditor::wrap(&mut lst);
 		assert_eq!(editor.comments().count(), 4);
 	}
+	#[test]
+	fn test_set_words() {
+		let set = |src: &str, words: &[&str]| {
+			let mut lst = ApmlLst::parse(src).unwrap();
+			let lst::Token::Variable(def) = &mut lst.0[0] else {
+				unreachable!()
+			};
+			let changed = ValueEditor::wrap(def).set_words(words);
+			(changed, lst.to_string())
+		};
+		assert_eq!(
+			set("a=\" x \\\n  y \"", &["z", "y", "w"]),
+			(true, "a=\" z \\\n  y \\\n  w \"".to_string())
+		);
+		assert_eq!(set("a='x  y\nz'", &["x"]), (true, "a='x'".to_string()));
+		assert_eq!(set("a='x'", &["x", "y"]), (true, "a='x y'".to_string()));
+		assert_eq!(set("a=\"x\"", &["$y"]), (true, "a=\"\\$y\"".to_string()));
+		assert_eq!(set("a=x'y'", &["x", "y"]), (true, "a=\"x y\"".to_string()));
+		assert_eq!(set("a=\"$x\"", &["y"]), (false, "a=\"$x\"".to_string()));
+		assert_eq!(
+			set("a=(\n  x # x\n  \"y\"\n)", &["y", "z", "w"]),
+			(true, "a=(\n  y # x\n  \"z\"\n  w\n)".to_string())
+		);
+		assert_eq!(set("a=(x y z)", &["w"]), (true, "a=(w)".to_string()));
+		assert_eq!(set("a=(x y)", &[]), (true, "a=()".to_string()));
+		assert_eq!(set("a=()", &["x", "y"]), (true, "a=(x y)".to_string()));
+		assert_eq!(
+			set("a=(\n\tx\n)", &["x", "y"]),
+			(true, "a=(\n\tx\n\ty\n)".to_string())
+		);
+	}
 }
@@ -1,257 +1,2537 @@
 //! APML expression evaluator.
 
-use std::cmp::min;
+use alloc::{
+	borrow::Cow,
+	collections::{BTreeMap, BTreeSet},
+	sync::Arc,
+};
+use core::{
+	cmp::min,
+	fmt::{Debug, Display},
+	iter::Peekable,
+	str::Chars,
+	sync::atomic::{AtomicBool, Ordering},
+};
+#[cfg(feature = "std")]
+use std::time::Instant;
 
 use thiserror::Error;
 
-use super::{ApmlContext, VariableValue, ast};
+use super::{
+	ApmlContext, HashMap, HashSet, VariableValue,
+	ast::{self, AstNode},
+	lst::{self, ApmlLst},
+	parser::ParseOptions,
+	span::Span,
+};
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 
 #[derive(Error, Debug)]
 pub enum EvalError {
 	#[error("Glob-as-regex error: {0}")]
-	RegexError(#[from] regex::Error),
+	RegexError(#[cfg_attr(feature = "std", from)] regex::Error),
 	#[error("Required variable is unset: {0}")]
 	Unset(String),
+	#[error("Unbound variable {name} at {span}")]
+	Unbound {
+		/// Name of the expanded variable.
+		name: String,
+		/// Span of the definition expanding the variable, or of the
+		/// custom expansion taking it as an argument.
+		span: Span,
+	},
+	#[error("Unsupported shell option {option} at {span}")]
+	UnsupportedOption { option: String, span: Span },
+	#[error("Policy violation at {span}: {message}")]
+	PolicyViolation { message: String, span: Span },
+	#[error("Unknown custom expansion: {0}")]
+	UnknownExpansion(String),
+	#[error("Custom expansion {name} failed: {message}")]
+	CustomExpansion { name: String, message: String },
+	#[error("{0}")]
+	Strict(EvalWarning),
+	#[error("Invalid UTF-8 in variable {variable} at byte {offset}")]
+	InvalidUtf8 {
+		/// Name of the assigned variable, or empty when evaluating
+		/// a single text.
+		variable: String,
+		/// Offset of the first invalid byte in the string or array
+		/// element being evaluated.
+		offset: usize,
+	},
+	#[error("Evaluation cancelled while evaluating {variable}")]
+	Cancelled {
+		/// Name of the variable being evaluated, or empty when
+		/// evaluating a single text.
+		variable: String,
+		/// Variables assigned before the cancellation.
+		partial: Box<ApmlContext>,
+	},
+	#[error("Deadline exceeded while evaluating {variable}")]
+	DeadlineExceeded {
+		/// Name of the variable being evaluated, or empty when
+		/// evaluating a single text.
+		variable: String,
+		/// Variables assigned before the deadline.
+		partial: Box<ApmlContext>,
+	},
+	#[error(
+		"Value of {variable} has {size} bytes, exceeding the limit of \
+		{limit} bytes: {chain}"
+	)]
+	LimitExceeded {
+		/// Name of the assigned variable.
+		variable: String,
+		/// Size of the value, see [`EvalOptions::max_value_len`].
+		size: usize,
+		/// The limit.
+		limit: usize,
+		/// Definitions the value is expanded from.
+		chain: ExpansionChain,
+	},
+}
+
+/// `regex::Error` implements [`core::error::Error`] only with `std`, which
+/// `#[from]` requires.
+#[cfg(not(feature = "std"))]
+impl From<regex::Error> for EvalError {
+	fn from(value: regex::Error) -> Self {
+		Self::RegexError(value)
+	}
+}
+
+/// Maximum number of definitions displayed in an [`ExpansionChain`].
+const MAX_DISPLAYED_CHAIN: usize = 8;
+
+/// Definitions a value is expanded from, as reported by
+/// [`EvalError::LimitExceeded`].
+///
+/// The chain starts with the assigned variable. Each following entry is
+/// the definition of the variable contributing the largest value to
+/// the previous one, at the time it was evaluated. Entries are names and
+/// spans of definitions, which are empty when evaluating an AST without
+/// source information.
+///
+/// It is displayed as `PKGDEP (10..30) ← DEPS_COMMON (0..9)`, with
+/// entries after the first [`MAX_DISPLAYED_CHAIN`] elided.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct ExpansionChain(pub Vec<(String, Span)>);
+
+impl Display for ExpansionChain {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		for (index, (name, span)) in
+			self.0.iter().take(MAX_DISPLAYED_CHAIN).enumerate()
+		{
+			if index != 0 {
+				f.write_str(" ← ")?;
+			}
+			f.write_str(name)?;
+			if *span != Span::default() {
+				write!(f, " ({})", span)?;
+			}
+		}
+		if let Some(elided) = self.0.len().checked_sub(MAX_DISPLAYED_CHAIN)
+			&& elided != 0
+		{
+			write!(f, " ← … ({} more)", elided)?;
+		}
+		Ok(())
+	}
+}
+
+impl EvalError {
+	/// Returns the context built before the evaluation is interrupted by
+	/// [`EvalOptions::cancel`] or [`EvalOptions::deadline`].
+	pub fn partial_context(&self) -> Option<&ApmlContext> {
+		match self {
+			EvalError::Cancelled { partial, .. }
+			| EvalError::DeadlineExceeded { partial, .. } => Some(partial),
+			_ => None,
+		}
+	}
+
+	/// Fills the variable and the partial context of interruptions.
+	fn interrupted_at(self, name: &str, apml: &ApmlContext) -> Self {
+		match self {
+			EvalError::Cancelled { .. } => EvalError::Cancelled {
+				variable: name.to_string(),
+				partial: Box::new(apml.clone()),
+			},
+			EvalError::DeadlineExceeded { .. } => EvalError::DeadlineExceeded {
+				variable: name.to_string(),
+				partial: Box::new(apml.clone()),
+			},
+			err => err,
+		}
+	}
+}
+
+/// A warning produced during evaluation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EvalWarning {
+	/// Kind of the warning.
+	pub kind: EvalWarningKind,
+	/// Name of the expanded variable.
+	pub name: String,
+	/// Span of the expansion.
+	pub span: Span,
+	/// Suggested name, for [`EvalWarningKind::PossibleTypo`].
+	pub suggestion: Option<String>,
 }
 
-type Result<T> = std::result::Result<T, EvalError>;
+/// Kind of a [`EvalWarning`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EvalWarningKind {
+	/// An array variable is expanded with `[@]` or `[*]` in a string
+	/// assignment, joining the elements into a string.
+	ArrayInScalar,
+	/// A string variable is expanded with `[@]` or `[*]`.
+	ScalarIndexed,
+	/// A undefined variable is expanded, while a defined variable has
+	/// a similar name.
+	///
+	/// See [`did_you_mean`] for how similar names are found.
+	///
+	/// [`did_you_mean`]: super::suggest::did_you_mean
+	PossibleTypo,
+	/// Escape sequences produce invalid UTF-8, which is replaced with
+	/// U+FFFD under [`EvalOptions::lossy_utf8`].
+	///
+	/// The span is the span of the whole definition.
+	InvalidUtf8,
+}
+
+impl Display for EvalWarning {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self.kind {
+			EvalWarningKind::ArrayInScalar => f.write_fmt(format_args!(
+				"Array variable {} is expanded in a string assignment at {}",
+				self.name, self.span
+			)),
+			EvalWarningKind::ScalarIndexed => f.write_fmt(format_args!(
+				"String variable {} is indexed as an array at {}",
+				self.name, self.span
+			)),
+			EvalWarningKind::PossibleTypo => {
+				f.write_fmt(format_args!(
+					"Undefined variable {} is expanded at {}",
+					self.name, self.span
+				))?;
+				if let Some(suggestion) = &self.suggestion {
+					f.write_fmt(format_args!(
+						", did you mean `{}`?",
+						suggestion
+					))?;
+				}
+				Ok(())
+			}
+			EvalWarningKind::InvalidUtf8 => f.write_fmt(format_args!(
+				"Invalid UTF-8 in variable {} is replaced at {}",
+				self.name, self.span
+			)),
+		}
+	}
+}
+
+type Result<T> = core::result::Result<T, EvalError>;
+
+/// Size in bytes above which an expansion is logged as a debug event.
+#[cfg(feature = "tracing")]
+const LARGE_EXPANSION_THRESHOLD: usize = 4096;
+
+/// A function of a custom expansion, see
+/// [`EvalOptions::custom_expansions`].
+pub type ExpansionFn = Box<
+	dyn Fn(&[VariableValue]) -> core::result::Result<VariableValue, String>,
+>;
+
+/// A callback invoked for each assignment, see [`EvalOptions::on_assign`].
+pub type AssignHook = Box<
+	dyn FnMut(&str, &VariableValue, Span) -> core::result::Result<(), String>,
+>;
+
+/// A callback receiving warnings, see [`EvalOptions::on_warning`].
+pub type WarningHook = Box<dyn FnMut(&EvalWarning)>;
+
+/// How references to undefined variables are evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum UnknownPolicy {
+	/// Undefined variables expand to empty strings, as bash does.
+	#[default]
+	Empty,
+	/// Expansions of undefined variables are kept verbatim, such as
+	/// `${ARCH}`, so that the result can be expanded again later with
+	/// [`ApmlContext::expand_str`] once the values are known.
+	///
+	/// Expansions with modifiers are kept verbatim as a whole, instead
+	/// of being partially evaluated. This also applies to modifiers
+	/// with symbolic arguments, and to modifiers on variables whose values
+	/// are symbolic, which are considered unresolved as well. Undefined
+	/// arrays included in arrays are kept as `${NAME[@]}` elements.
+	///
+	/// Literal parts of symbolic values are quoted when needed, so each
+	/// symbolic string or array element is a template that can be
+	/// expanded again. Variables with symbolic values are reported by
+	/// [`ApmlContext::is_symbolic`] and unresolved variables are recorded
+	/// in [`ApmlContext::unresolved`].
+	Symbolic,
+}
+
+/// Shell options changed by `set` statements in a LST.
+///
+/// Only `nounset` (`set -u`) changes evaluation, making expansions of
+/// unset variables fail with [`EvalError::Unbound`]. `errexit`
+/// (`set -e`) is recorded but ignored, as there are no commands that
+/// could fail. See [`ApmlLst::shell_options`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ShellOptions {
+	/// Whether `set -e` is in effect.
+	pub errexit: bool,
+	/// Whether `set -u` is in effect.
+	pub nounset: bool,
+}
+
+impl ShellOptions {
+	/// Applies a `set` statement at a span.
+	///
+	/// `-e`, `-u`, their `+` forms and their long forms (`-o errexit`
+	/// and `-o nounset`) are supported. Other arguments are rejected with
+	/// [`EvalError::UnsupportedOption`].
+	pub fn apply(&mut self, set: &lst::SetCommand, span: Span) -> Result<()> {
+		let unsupported =
+			|option: String| Err(EvalError::UnsupportedOption { option, span });
+		let mut args = set.args.iter().map(|(_, arg)| arg.as_ref());
+		while let Some(arg) = args.next() {
+			let (enable, flags) = match arg.split_at_checked(1) {
+				Some(("-", flags)) => (true, flags),
+				Some(("+", flags)) => (false, flags),
+				_ => return unsupported(arg.to_string()),
+			};
+			match flags {
+				"o" => match args.next() {
+					Some("errexit") => self.errexit = enable,
+					Some("nounset") => self.nounset = enable,
+					Some(name) => return unsupported(format!("{arg} {name}")),
+					None => return unsupported(arg.to_string()),
+				},
+				"" => return unsupported(arg.to_string()),
+				_ => {
+					for flag in flags.chars() {
+						match flag {
+							'e' => self.errexit = enable,
+							'u' => self.nounset = enable,
+							_ => {
+								return unsupported(format!(
+									"{}{flag}",
+									&arg[..1]
+								));
+							}
+						}
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+impl ApmlLst<'_> {
+	/// Returns `set` statements along with the shell options in effect
+	/// after each of them.
+	///
+	/// Options take effect for the remainder of the file, starting with
+	/// all options disabled.
+	pub fn shell_options(&self) -> Result<Vec<(Span, ShellOptions)>> {
+		let mut options = ShellOptions::default();
+		let mut result = Vec::new();
+		for (span, token) in self.token_spans() {
+			if let lst::Token::Set(set) = token {
+				options.apply(set, span)?;
+				result.push((span, options));
+			}
+		}
+		Ok(result)
+	}
+
+	/// Returns the shell options in effect at an offset of the source.
+	pub fn shell_options_at(&self, offset: usize) -> Result<ShellOptions> {
+		Ok(self
+			.shell_options()?
+			.into_iter()
+			.take_while(|(span, _)| span.end <= offset)
+			.last()
+			.map(|(_, options)| options)
+			.unwrap_or_default())
+	}
+}
+
+/// Options for evaluation.
+#[derive(Default)]
+pub struct EvalOptions {
+	/// Callback invoked after the value of each assignment is computed,
+	/// but before it is stored into the context.
+	///
+	/// The callback receives the name of the variable, the value to be
+	/// stored and the span of the definition. For appending (`+=`),
+	/// the value is the result after appending.
+	///
+	/// Returning an [`Err`] aborts the evaluation with
+	/// [`EvalError::PolicyViolation`].
+	///
+	/// When evaluating an AST without source information, the span is
+	/// always empty.
+	pub on_assign: Option<AssignHook>,
+	/// Whether to record variables influencing each assigned variable.
+	///
+	/// See [`ApmlContext::influences`].
+	pub track_influences: bool,
+	/// Callback receiving warnings about suspicious constructs.
+	///
+	/// Warnings are only produced when evaluating a LST, as the
+	/// constructs are not preserved in AST.
+	pub on_warning: Option<WarningHook>,
+	/// Whether to turn warnings into [`EvalError::Strict`] errors.
+	pub strict: bool,
+	/// How references to undefined variables are evaluated.
+	pub unknown_policy: UnknownPolicy,
+	/// Whether to replace invalid UTF-8 produced by escape sequences of
+	/// ANSI-C quoted strings (`$'\xff'`) with U+FFFD, reporting
+	/// [`EvalWarningKind::InvalidUtf8`] warnings, instead of failing with
+	/// [`EvalError::InvalidUtf8`].
+	pub lossy_utf8: bool,
+	/// Functions of custom expansions (`${@name args}`), by names.
+	///
+	/// Each function receives the values of the variables named by the
+	/// arguments, with unset variables as empty strings, and the result
+	/// is joined with spaces if it is an array. Errors are reported as
+	/// [`EvalError::CustomExpansion`].
+	///
+	/// Custom expansions are not bash syntax, so they are only parsed
+	/// when any is supplied, see [`ApmlLst::parse_with`], and files using
+	/// them are never [classified][ApmlLst::classify] as data-only.
+	pub custom_expansions: HashMap<String, ExpansionFn>,
+	/// Flag to cancel the evaluation from another thread.
+	///
+	/// The flag is checked before each definition and each expansion,
+	/// and once set, the evaluation fails with [`EvalError::Cancelled`].
+	pub cancel: Option<Arc<AtomicBool>>,
+	/// Time after which the evaluation fails with
+	/// [`EvalError::DeadlineExceeded`].
+	///
+	/// This is checked along with [`EvalOptions::cancel`], and is only
+	/// available with the `std` feature.
+	#[cfg(feature = "std")]
+	pub deadline: Option<Instant>,
+	/// Maximum size in bytes of assigned values, failing with
+	/// [`EvalError::LimitExceeded`] when exceeded.
+	///
+	/// The size of an array is the total size of its elements. Values
+	/// are evaluated once when assigned, so there is no recursion to
+	/// limit the depth of. Instead, definitions expanding each other
+	/// repeatedly, such as `A="$B$B"` and `B="$A$A"`, grow quickly, and
+	/// the error shows the [chain][ExpansionChain] of definitions they
+	/// are expanded from.
+	pub max_value_len: Option<usize>,
+	/// Name of the evaluated source, such as its path.
+	///
+	/// This only labels the `apml_eval` span with the `tracing` feature
+	/// enabled. [`batch::load_tree`] sets it to the name of each source.
+	///
+	/// [`batch::load_tree`]: super::batch::load_tree
+	pub source_name: Option<String>,
+}
+
+impl Debug for EvalOptions {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		let mut debug = f.debug_struct("EvalOptions");
+		debug
+			.field("on_assign", &self.on_assign.as_ref().map(|_| ".."))
+			.field("track_influences", &self.track_influences)
+			.field("on_warning", &self.on_warning.as_ref().map(|_| ".."))
+			.field("strict", &self.strict)
+			.field("unknown_policy", &self.unknown_policy)
+			.field("lossy_utf8", &self.lossy_utf8)
+			.field(
+				"custom_expansions",
+				&self.custom_expansions.keys().collect::<Vec<_>>(),
+			)
+			.field("cancel", &self.cancel);
+		#[cfg(feature = "std")]
+		debug.field("deadline", &self.deadline);
+		debug
+			.field("max_value_len", &self.max_value_len)
+			.field("source_name", &self.source_name)
+			.finish()
+	}
+}
+
+impl From<&EvalOptions> for ParseOptions {
+	/// Accepts custom expansions if any is supplied.
+	fn from(options: &EvalOptions) -> Self {
+		Self {
+			custom_expansions: !options.custom_expansions.is_empty(),
+		}
+	}
+}
+
+/// A source of values of variables read during evaluation.
+///
+/// Expansions are first resolved from variables assigned in the output
+/// context, and then from the resolver, so that values can be provided
+/// by sources other than a context, such as a database of defaults or
+/// values computed on demand. Assignments always go to the output
+/// context. See [`eval_ast_with_resolver`].
+///
+/// This is implemented for contexts and for closures taking the name of
+/// a variable.
+pub trait VariableResolver {
+	/// Resolves the value of a variable.
+	///
+	/// This may be called multiple times for the same variable.
+	fn resolve(&self, name: &str) -> Option<VariableValue>;
+
+	/// Describes where the value of a variable comes from, such as
+	/// the path of a file.
+	///
+	/// Descriptions of variables resolved during evaluation are recorded
+	/// in [`ApmlContext::provenance`].
+	fn provenance(&self, name: &str) -> Option<String> {
+		let _ = name;
+		None
+	}
+}
+
+impl VariableResolver for ApmlContext {
+	fn resolve(&self, name: &str) -> Option<VariableValue> {
+		self.get(name).cloned()
+	}
+}
+
+impl<F> VariableResolver for F
+where
+	F: Fn(&str) -> Option<VariableValue>,
+{
+	fn resolve(&self, name: &str) -> Option<VariableValue> {
+		self(name)
+	}
+}
 
 pub fn eval_ast(apml: &mut ApmlContext, tree: &ast::ApmlAst) -> Result<()> {
+	eval_ast_with(apml, tree, &mut EvalOptions::default())
+}
+
+pub fn eval_text(apml: &ApmlContext, text: &ast::Text) -> Result<String> {
+	eval_text_with(apml, text, &EvalOptions::default())
+}
+
+/// Evaluates a text with options.
+///
+/// Only [`EvalOptions::unknown_policy`], [`EvalOptions::lossy_utf8`],
+/// [`EvalOptions::custom_expansions`], [`EvalOptions::cancel`] and
+/// [`EvalOptions::deadline`] apply to a single text:
+/// [`EvalOptions::on_assign`] is never invoked as no assignment is made,
+/// and influences and unresolved variables are not recorded as there is
+/// no variable to record them for.
+pub fn eval_text_with(
+	apml: &ApmlContext,
+	text: &ast::Text,
+	options: &EvalOptions,
+) -> Result<String> {
+	Evaluator::with_options(apml, options).eval_text(text)
+}
+
+/// Evaluates a single word against a resolver, returning the produced
+/// fields.
+///
+/// The word is evaluated as an element of an array, the same way as in
+/// assignments, so `"${NAME[@]}"` produces a field for each element of
+/// an array and none for an unset variable. Unquoted expansions are
+/// split into fields on `IFS`, without pathname expansion. Any other
+/// word produces exactly one field. Variables are only read from the
+/// resolver, which may be an [`ApmlContext`].
+///
+/// Options apply as in [`eval_text_with`].
+pub fn eval_word(
+	word: &ast::ArrayElement,
+	resolver: &dyn VariableResolver,
+	options: &EvalOptions,
+) -> Result<Vec<String>> {
+	let empty = ApmlContext::default();
+	let mut evaluator = Evaluator::with_options(&empty, options);
+	evaluator.resolver = Some(resolver);
+	let mut fields = Vec::new();
+	evaluator.eval_array_element(word, &mut fields)?;
+	Ok(fields)
+}
+
+/// Evaluates a single word as in [`eval_word`], joining the fields with
+/// spaces.
+pub fn eval_word_scalar(
+	word: &ast::ArrayElement,
+	resolver: &dyn VariableResolver,
+	options: &EvalOptions,
+) -> Result<String> {
+	Ok(eval_word(word, resolver, options)?.join(" "))
+}
+
+/// Evaluates a AST with options.
+pub fn eval_ast_with(
+	apml: &mut ApmlContext,
+	tree: &ast::ApmlAst,
+	options: &mut EvalOptions,
+) -> Result<()> {
+	eval_ast_spanned(apml, tree, &[], None, options)
+}
+
+/// Evaluates a AST with options, reading variables not assigned in the
+/// output context from a resolver.
+///
+/// See [`VariableResolver`].
+pub fn eval_ast_with_resolver(
+	apml: &mut ApmlContext,
+	tree: &ast::ApmlAst,
+	resolver: &dyn VariableResolver,
+	options: &mut EvalOptions,
+) -> Result<()> {
+	eval_ast_spanned(apml, tree, &[], Some(resolver), options)
+}
+
+/// Source information of a definition.
+#[derive(Debug, Default)]
+pub(crate) struct DefinitionSource {
+	/// Span of the definition.
+	pub span: Span,
+	/// Names and spans of `${NAME[@]}` and `${NAME[*]}` expansions.
+	pub array_expansions: Vec<(String, Span)>,
+	/// Names of referenced variables and spans of the expansions.
+	///
+	/// References nested in modifiers get the span of the outermost
+	/// expansion.
+	pub references: Vec<(String, Span)>,
+	/// Shell options in effect.
+	pub shell_options: ShellOptions,
+}
+
+/// Evaluates a AST with options and source information of each
+/// definition.
+///
+/// Definitions without corresponding source information get an empty
+/// span.
+pub(crate) fn eval_ast_spanned(
+	apml: &mut ApmlContext,
+	tree: &ast::ApmlAst,
+	sources: &[DefinitionSource],
+	resolver: Option<&dyn VariableResolver>,
+	options: &mut EvalOptions,
+) -> Result<()> {
 	let ast::ApmlAst(defs) = tree;
-	for def in defs {
-		eval_variable_def(apml, def)?;
+	#[cfg(feature = "tracing")]
+	let _span = tracing::debug_span!(
+		"apml_eval",
+		source = options.source_name.as_deref().unwrap_or_default(),
+		defs = defs.len()
+	)
+	.entered();
+	let mut assigned = HashSet::new();
+	let mut chains = ChainRecords::default();
+	let empty_source = DefinitionSource::default();
+	for (index, def) in defs.iter().enumerate() {
+		let source = sources.get(index).unwrap_or(&empty_source);
+		#[cfg(feature = "tracing")]
+		let _span = tracing::trace_span!("apml_eval_var", name = %def.name).entered();
+		Budget::of(options)
+			.check()
+			.map_err(|err| err.interrupted_at(&def.name, apml))?;
+		check_array_expansions(apml, resolver, def, source, options)?;
+		check_references(apml, resolver, source, options)?;
+		eval_variable_def(
+			apml,
+			resolver,
+			def,
+			source,
+			options,
+			&mut assigned,
+			&mut chains,
+		)?;
 	}
 	Ok(())
 }
 
-#[inline]
-fn eval_variable_def(
-	apml: &mut ApmlContext,
+/// Definitions evaluated so far, for chains of
+/// [`EvalError::LimitExceeded`].
+///
+/// Records are only kept if [`EvalOptions::max_value_len`] is set.
+#[derive(Debug, Default)]
+struct ChainRecords {
+	/// Name and span of each definition, with the record of the
+	/// variable contributing the largest value to it.
+	records: Vec<(String, Span, Option<usize>)>,
+	/// Index of the latest record of each variable.
+	latest: HashMap<String, usize>,
+}
+
+impl ChainRecords {
+	/// Returns the chain starting from a record.
+	fn chain(&self, mut next: Option<usize>) -> Vec<(String, Span)> {
+		let mut chain = Vec::new();
+		// sources always come from earlier records
+		while let Some(index) = next {
+			let (name, span, source) = &self.records[index];
+			chain.push((name.clone(), *span));
+			next = *source;
+		}
+		chain
+	}
+}
+
+/// Returns the size of a value, see [`EvalOptions::max_value_len`].
+fn value_len(value: &VariableValue) -> usize {
+	match value {
+		VariableValue::String(text) => text.len(),
+		VariableValue::Array(elements) => {
+			elements.iter().map(String::len).sum()
+		}
+	}
+}
+
+/// Reports suspicious `[@]` and `[*]` expansions in a definition.
+fn check_array_expansions(
+	apml: &ApmlContext,
+	resolver: Option<&dyn VariableResolver>,
 	def: &ast::VariableDefinition,
+	source: &DefinitionSource,
+	options: &mut EvalOptions,
 ) -> Result<()> {
-	let name = def.name.to_string();
-	let value = eval_variable_value(apml, &def.value)?;
-	apml.variables.insert(name, value);
+	let in_string = matches!(def.value, ast::VariableValue::String(_));
+	for (name, span) in &source.array_expansions {
+		let mut evaluator = Evaluator::new(apml);
+		evaluator.resolver = resolver;
+		let kind = match evaluator.lookup(name).as_deref() {
+			Some(VariableValue::Array(_)) if in_string => {
+				EvalWarningKind::ArrayInScalar
+			}
+			Some(VariableValue::String(_)) => EvalWarningKind::ScalarIndexed,
+			_ => continue,
+		};
+		report_warning(
+			EvalWarning {
+				kind,
+				name: name.clone(),
+				span: *span,
+				suggestion: None,
+			},
+			options,
+		)?;
+	}
 	Ok(())
 }
 
-#[inline]
-fn eval_variable_value(
+/// Reports references to undefined variables with similar names defined.
+fn check_references(
 	apml: &ApmlContext,
-	value: &ast::VariableValue,
-) -> Result<VariableValue> {
-	match value {
-		ast::VariableValue::String(text) => {
-			Ok(VariableValue::String(eval_text(apml, text)?))
+	resolver: Option<&dyn VariableResolver>,
+	source: &DefinitionSource,
+	options: &mut EvalOptions,
+) -> Result<()> {
+	let mut reported = HashSet::new();
+	for (name, span) in &source.references {
+		if apml.variables.contains_key(name)
+			|| is_special(name)
+			|| resolver.is_some_and(|resolver| resolver.resolve(name).is_some())
+			|| !reported.insert(name)
+		{
+			continue;
 		}
-		ast::VariableValue::Array(element) => {
-			let mut result = Vec::new();
-			for element in element {
-				eval_array_element(apml, element, &mut result)?;
-			}
-			Ok(VariableValue::Array(result))
+		if let Some(suggestion) = super::suggest::did_you_mean(
+			name,
+			apml.variables.keys().map(String::as_str),
+		) {
+			report_warning(
+				EvalWarning {
+					kind: EvalWarningKind::PossibleTypo,
+					name: name.clone(),
+					span: *span,
+					suggestion: Some(suggestion.to_string()),
+				},
+				options,
+			)?;
 		}
 	}
+	Ok(())
+}
+
+/// Passes a warning to the callback, or returns it as an error in
+/// strict mode.
+fn report_warning(
+	warning: EvalWarning,
+	options: &mut EvalOptions,
+) -> Result<()> {
+	if options.strict {
+		return Err(EvalError::Strict(warning));
+	}
+	if let Some(on_warning) = &mut options.on_warning {
+		on_warning(&warning);
+	}
+	Ok(())
 }
 
 #[inline]
-fn eval_array_element(
-	apml: &ApmlContext,
-	element: &ast::ArrayElement,
-	values: &mut Vec<String>,
+fn eval_variable_def(
+	apml: &mut ApmlContext,
+	resolver: Option<&dyn VariableResolver>,
+	def: &ast::VariableDefinition,
+	source: &DefinitionSource,
+	options: &mut EvalOptions,
+	assigned: &mut HashSet<String>,
+	chains: &mut ChainRecords,
 ) -> Result<()> {
-	match element {
-		ast::ArrayElement::ArrayInclusion(name) => {
-			// expand array elements
-			values.append(
-				&mut apml
-					.variables
-					.get(name.as_ref())
-					.cloned()
-					.unwrap_or_default()
-					.into_array(),
-			);
-			Ok(())
+	let name = def.name.to_string();
+	let span = source.span;
+	let mut evaluator = Evaluator::with_options(apml, options);
+	evaluator.resolver = resolver;
+	if options.track_influences {
+		evaluator.refs = Some(BTreeSet::new());
+	}
+	evaluator.nounset = source.shell_options.nounset;
+	evaluator.references = &source.references;
+	let value = evaluator.eval_definition(def).map_err(|err| match err {
+		EvalError::InvalidUtf8 { offset, .. } => EvalError::InvalidUtf8 {
+			variable: name.clone(),
+			offset,
+		},
+		EvalError::Unbound { name, span: at } => EvalError::Unbound {
+			name,
+			span: if at == Span::default() { span } else { at },
+		},
+		err => err.interrupted_at(&name, apml),
+	})?;
+	let Evaluator {
+		refs,
+		symbolic,
+		unresolved,
+		provenance,
+		replaced_invalid_utf8,
+		largest,
+		..
+	} = evaluator;
+	if let Some(limit) = options.max_value_len {
+		let source = largest
+			.and_then(|(largest, _)| chains.latest.get(&largest).copied());
+		let size = value_len(&value);
+		if size > limit {
+			let mut chain = vec![(name.clone(), span)];
+			chain.extend(chains.chain(source));
+			return Err(EvalError::LimitExceeded {
+				variable: name,
+				size,
+				limit,
+				chain: ExpansionChain(chain),
+			});
 		}
-		ast::ArrayElement::Text(text) => {
-			values.push(eval_text(apml, text)?);
-			Ok(())
+		chains.latest.insert(name.clone(), chains.records.len());
+		chains.records.push((name.clone(), span, source));
+	}
+	if replaced_invalid_utf8 {
+		report_warning(
+			EvalWarning {
+				kind: EvalWarningKind::InvalidUtf8,
+				name: name.clone(),
+				span,
+				suggestion: None,
+			},
+			options,
+		)?;
+	}
+	if let Some(on_assign) = &mut options.on_assign {
+		on_assign(&name, &value, span)
+			.map_err(|message| EvalError::PolicyViolation { message, span })?;
+	}
+	if let Some(refs) = refs {
+		let mut influences = BTreeSet::new();
+		for var in refs {
+			if assigned.contains(&var) {
+				if let Some(transitive) = apml.influences.get(&var) {
+					influences.extend(transitive.iter().cloned());
+				}
+				// previous layers of appending are not influences
+				if var != name {
+					influences.insert(var);
+				}
+			} else {
+				apml.external_influences.insert(var.clone());
+				influences.insert(var);
+			}
 		}
+		apml.influences.insert(name.clone(), influences);
 	}
+	if symbolic {
+		apml.symbolic.insert(name.clone());
+	} else {
+		apml.symbolic.remove(&name);
+	}
+	apml.unresolved.extend(unresolved);
+	apml.provenance.extend(provenance);
+	apml.content_hash.take();
+	apml.last_defined = Some(name.clone());
+	apml.variables.insert(name.clone(), value);
+	assigned.insert(name);
+	Ok(())
 }
 
-pub fn eval_text(apml: &ApmlContext, text: &ast::Text) -> Result<String> {
-	let mut result = String::new();
-	let ast::Text(words) = text;
-	for word in words {
-		result.push_str(&eval_word(apml, word)?);
+/// Returns if a name is a special parameter (`$*`, `$@`) or
+/// a positional parameter (`$1`, ...).
+fn is_special(name: &str) -> bool {
+	name == "*" || name == "@" || name.chars().all(|ch| ch.is_ascii_digit())
+}
+
+/// Quotes a literal part of a symbolic value, so that it is kept as is
+/// when the value is expanded again.
+///
+/// Texts containing control characters other than newlines and tabs are
+/// ANSI-C quoted.
+fn quote_literal(text: &str) -> Cow<'_, str> {
+	if text.chars().all(|ch| {
+		ch.is_ascii_alphanumeric()
+			|| matches!(ch, '-' | '_' | '.' | '/' | ':' | '+' | ',' | '@' | '%')
+	}) {
+		Cow::Borrowed(text)
+	} else if lst::TextUnit::needs_ansi_c_quote(text) {
+		Cow::Owned(lst::TextUnit::ansi_c_quote(text).to_string())
+	} else {
+		Cow::Owned(format!("'{}'", text.replace('\'', "'\\''")))
 	}
-	Ok(result)
 }
 
-#[inline]
-fn eval_word(apml: &ApmlContext, word: &ast::Word) -> Result<String> {
-	match word {
-		ast::Word::Literal(text) | ast::Word::Subcommand(text) => {
-			Ok(text.to_string())
-		}
-		ast::Word::Variable(expansion) => {
-			let val = apml
-				.variables
-				.get(expansion.name.as_ref())
-				.cloned()
-				.unwrap_or_default();
-			if let Some(modifier) = &expansion.modifier {
-				apply_expansion_modifier(apml, modifier, val)
-			} else {
-				Ok(val.into_string())
+/// Decodes escape sequences of an ANSI-C quoted string (`$'...'`) into
+/// bytes, following bash.
+///
+/// Unknown escape sequences are kept as is.
+fn decode_ansi_c(text: &str) -> Vec<u8> {
+	fn push_char(result: &mut Vec<u8>, ch: char) {
+		result.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+	}
+
+	/// Takes up to `max` digits, accumulating onto `value`.
+	fn take_digits(
+		chars: &mut Peekable<Chars>,
+		radix: u32,
+		max: usize,
+		mut value: Option<u32>,
+	) -> Option<u32> {
+		for _ in 0..max {
+			let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(radix))
+			else {
+				break;
+			};
+			chars.next();
+			value = Some(value.unwrap_or(0) * radix + digit);
+		}
+		value
+	}
+
+	let mut result = Vec::with_capacity(text.len());
+	let mut chars = text.chars().peekable();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			push_char(&mut result, ch);
+			continue;
+		}
+		let Some(escape) = chars.next() else {
+			result.push(b'\\');
+			break;
+		};
+		match escape {
+			'a' => result.push(0x07),
+			'b' => result.push(0x08),
+			'e' | 'E' => result.push(0x1b),
+			'f' => result.push(0x0c),
+			'n' => result.push(b'\n'),
+			'r' => result.push(b'\r'),
+			't' => result.push(b'\t'),
+			'v' => result.push(0x0b),
+			'\\' | '\'' | '"' | '?' => result.push(escape as u8),
+			'0'..='7' => {
+				let value = take_digits(&mut chars, 8, 2, escape.to_digit(8));
+				result.push(value.unwrap_or_default() as u8);
+			}
+			'x' => match take_digits(&mut chars, 16, 2, None) {
+				Some(value) => result.push(value as u8),
+				None => result.extend_from_slice(b"\\x"),
+			},
+			'u' | 'U' => {
+				let max = if escape == 'u' { 4 } else { 8 };
+				match take_digits(&mut chars, 16, max, None).map(char::from_u32)
+				{
+					Some(Some(ch)) => push_char(&mut result, ch),
+					_ => {
+						result.push(b'\\');
+						push_char(&mut result, escape);
+					}
+				}
+			}
+			'c' => match chars.next() {
+				Some('?') => result.push(0x7f),
+				Some(ch) if ch.is_ascii() => {
+					result.push(ch.to_ascii_uppercase() as u8 & 0x1f)
+				}
+				Some(ch) => {
+					result.extend_from_slice(b"\\c");
+					push_char(&mut result, ch);
+				}
+				None => result.extend_from_slice(b"\\c"),
+			},
+			_ => {
+				result.push(b'\\');
+				push_char(&mut result, escape);
 			}
 		}
 	}
+	result
 }
 
-fn apply_expansion_modifier(
-	apml: &ApmlContext,
-	modifier: &ast::ExpansionModifier,
-	value: VariableValue,
-) -> Result<String> {
-	struct MatchReplacer(usize);
-	impl regex::Replacer for MatchReplacer {
-		fn replace_append(
-			&mut self,
-			caps: &regex::Captures<'_>,
-			dst: &mut String,
-		) {
-			dst.push_str(&caps[self.0]);
+/// Cancellation flag and deadline of an evaluation.
+#[derive(Clone, Copy, Default)]
+struct Budget<'a> {
+	cancel: Option<&'a AtomicBool>,
+	#[cfg(feature = "std")]
+	deadline: Option<Instant>,
+}
+
+impl<'a> Budget<'a> {
+	fn of(options: &'a EvalOptions) -> Self {
+		Self {
+			cancel: options.cancel.as_deref(),
+			#[cfg(feature = "std")]
+			deadline: options.deadline,
 		}
 	}
 
-	struct UppercaseReplacer;
-	impl regex::Replacer for UppercaseReplacer {
-		fn replace_append(
-			&mut self,
-			caps: &regex::Captures<'_>,
-			dst: &mut String,
-		) {
-			dst.push_str(&caps[0].to_ascii_uppercase());
+	/// Returns an error if the evaluation should stop.
+	///
+	/// The variable and the partial context are left empty, see
+	/// [`EvalError::interrupted_at`].
+	fn check(&self) -> Result<()> {
+		if self
+			.cancel
+			.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+		{
+			return Err(EvalError::Cancelled {
+				variable: String::new(),
+				partial: Box::default(),
+			});
 		}
+		#[cfg(feature = "std")]
+		if self
+			.deadline
+			.is_some_and(|deadline| Instant::now() >= deadline)
+		{
+			return Err(EvalError::DeadlineExceeded {
+				variable: String::new(),
+				partial: Box::default(),
+			});
+		}
+		Ok(())
 	}
+}
 
-	struct LowercaseReplacer;
-	impl regex::Replacer for LowercaseReplacer {
-		fn replace_append(
-			&mut self,
-			caps: &regex::Captures<'_>,
-			dst: &mut String,
-		) {
-			dst.push_str(&caps[0].to_ascii_lowercase());
+/// State of evaluating a single definition.
+struct Evaluator<'a> {
+	apml: &'a ApmlContext,
+	/// Fallback of variables not defined in the context.
+	resolver: Option<&'a dyn VariableResolver>,
+	/// Provenance of variables read from the resolver.
+	provenance: BTreeMap<String, String>,
+	/// Referenced variables, if influences are tracked.
+	refs: Option<BTreeSet<String>>,
+	unknown_policy: UnknownPolicy,
+	/// Whether the result contains symbolic expansions.
+	symbolic: bool,
+	/// Variables kept as symbolic expansions.
+	unresolved: BTreeSet<String>,
+	lossy_utf8: bool,
+	/// Whether invalid UTF-8 has been replaced.
+	replaced_invalid_utf8: bool,
+	/// Whether expanding unset variables is an error.
+	nounset: bool,
+	custom_expansions: Option<&'a HashMap<String, ExpansionFn>>,
+	/// Referenced variables and spans of the expansions in the source.
+	references: &'a [(String, Span)],
+	budget: Budget<'a>,
+	/// Expanded variable with the largest value and its size.
+	largest: Option<(String, usize)>,
+}
+
+impl<'a> Evaluator<'a> {
+	fn new(apml: &'a ApmlContext) -> Self {
+		Self {
+			apml,
+			resolver: None,
+			provenance: BTreeMap::new(),
+			refs: None,
+			unknown_policy: UnknownPolicy::Empty,
+			symbolic: false,
+			unresolved: BTreeSet::new(),
+			lossy_utf8: false,
+			replaced_invalid_utf8: false,
+			nounset: false,
+			custom_expansions: None,
+			references: &[],
+			budget: Budget::default(),
+			largest: None,
+		}
+	}
+
+	/// Creates an evaluator with options applying to any evaluation.
+	fn with_options(apml: &'a ApmlContext, options: &'a EvalOptions) -> Self {
+		let mut evaluator = Self::new(apml);
+		evaluator.unknown_policy = options.unknown_policy;
+		evaluator.lossy_utf8 = options.lossy_utf8;
+		evaluator.custom_expansions = Some(&options.custom_expansions);
+		evaluator.budget = Budget::of(options);
+		evaluator
+	}
+
+	/// Gets the value of a variable from the context or the resolver.
+	fn lookup(&mut self, name: &str) -> Option<Cow<'a, VariableValue>> {
+		if let Some(value) = self.apml.variables.get(name) {
+			return Some(Cow::Borrowed(value));
+		}
+		let resolver = self.resolver?;
+		let value = resolver.resolve(name)?;
+		if let Some(provenance) = resolver.provenance(name) {
+			self.provenance.insert(name.to_string(), provenance);
+		}
+		Some(Cow::Owned(value))
+	}
+
+	/// Checks if an expansion should be kept symbolic.
+	///
+	/// Returns [`true`] if the variable is unresolved. Variables with
+	/// symbolic values are unresolved if they cannot be expanded as is
+	/// (`partial`).
+	fn keep_symbolic(&mut self, name: &str, partial: bool) -> bool {
+		if self.unknown_policy != UnknownPolicy::Symbolic || is_special(name) {
+			return false;
+		}
+		let unresolved = match self.lookup(name) {
+			None => true,
+			Some(_) if self.apml.symbolic.contains(name) => {
+				self.symbolic = true;
+				partial
+			}
+			Some(_) => false,
+		};
+		if unresolved {
+			self.symbolic = true;
+			self.unresolved.insert(name.to_string());
+		}
+		unresolved
+	}
+
+	/// Records a reference to a variable.
+	fn reference(&mut self, name: &str) {
+		if let Some(refs) = &mut self.refs
+			&& !refs.contains(name)
+		{
+			refs.insert(name.to_string());
+		}
+	}
+
+	/// Evaluates the value of a definition.
+	///
+	/// As in bash, assigning a string to a variable that has been assigned
+	/// an array sets the first element of the array, keeping the others.
+	/// Together with expansions of arrays giving their first element, this
+	/// makes the desugared form of `NAME+="VALUE"` append to the first
+	/// element.
+	fn eval_definition(
+		&mut self,
+		def: &ast::VariableDefinition,
+	) -> Result<VariableValue> {
+		let value = self.eval_variable_value(&def.value)?;
+		let (VariableValue::String(text), Some(VariableValue::Array(elements))) =
+			(&value, self.apml.variables.get(def.name.as_ref()))
+		else {
+			return Ok(value);
+		};
+		let mut elements = elements.clone();
+		let mut text = text.clone();
+		if self.apml.symbolic.contains(def.name.as_ref()) {
+			// other elements are templates
+			if !self.symbolic {
+				text = quote_literal(&text).into_owned();
+				self.symbolic = true;
+			}
+		} else if self.symbolic {
+			for element in elements.iter_mut().skip(1) {
+				*element = quote_literal(element).into_owned();
+			}
+		}
+		match elements.first_mut() {
+			Some(first) => *first = text,
+			None => elements.push(text),
+		}
+		Ok(VariableValue::Array(elements))
+	}
+
+	#[inline]
+	fn eval_variable_value(
+		&mut self,
+		value: &ast::VariableValue,
+	) -> Result<VariableValue> {
+		match value {
+			ast::VariableValue::String(text) => {
+				Ok(VariableValue::String(self.eval_text(text)?))
+			}
+			ast::VariableValue::Array(element) => {
+				let mut result = Vec::new();
+				let mut symbolic_elements = Vec::new();
+				for element in element {
+					let outer = core::mem::take(&mut self.symbolic);
+					self.eval_array_element(element, &mut result)?;
+					symbolic_elements.resize(result.len(), self.symbolic);
+					self.symbolic |= outer;
+				}
+				if self.symbolic {
+					// other elements must be templates too
+					for (value, symbolic) in
+						result.iter_mut().zip(symbolic_elements)
+					{
+						if !symbolic {
+							*value = quote_literal(value).into_owned();
+						}
+					}
+				}
+				Ok(VariableValue::Array(result))
+			}
 		}
 	}
 
-	match modifier {
-		ast::ExpansionModifier::Substring { offset, length } => {
-			let value = value.into_string();
-			if let Some(length) = length {
-				if *length > 0 {
-					Ok(value
-						[*offset..min(*offset + *length as usize, value.len())]
-						.to_string())
+	#[inline]
+	fn eval_array_element(
+		&mut self,
+		element: &ast::ArrayElement,
+		values: &mut Vec<String>,
+	) -> Result<()> {
+		match element {
+			ast::ArrayElement::ArrayInclusion(name) => {
+				// expand array elements
+				self.reference(name);
+				if self.keep_symbolic(name, false) {
+					values.push(format!("${{{}[@]}}", name));
+					return Ok(());
+				}
+				match self.lookup(name).map(Cow::into_owned) {
+					None => {}
+					// a string is a single element, even if empty
+					Some(VariableValue::String(text)) => values.push(text),
+					Some(VariableValue::Array(elements)) => {
+						values.extend(elements)
+					}
+				}
+				Ok(())
+			}
+			ast::ArrayElement::Text(text) => {
+				if text
+					.0
+					.iter()
+					.any(|word| matches!(word, ast::Word::SplitVariable(_)))
+				{
+					self.eval_split_text(text, values)
 				} else {
-					Ok(value[*offset..(value.len() - (-*length) as usize)]
-						.to_string())
+					values.push(self.eval_text(text)?);
+					Ok(())
 				}
-			} else {
-				Ok(value[*offset..].to_string())
-			}
-		}
-		ast::ExpansionModifier::StripShortestPrefix(pattern) => Ok(pattern
-			.to_regex("^(?:", ")?(.*)$", false)?
-			.replace(&value.into_string(), MatchReplacer(1))
-			.to_string()),
-		ast::ExpansionModifier::StripLongestPrefix(pattern) => Ok(pattern
-			.to_regex("^(?:", ")?(.*?)$", true)?
-			.replace(&value.into_string(), MatchReplacer(1))
-			.to_string()),
-		ast::ExpansionModifier::StripShortestSuffix(pattern) => Ok(pattern
-			.to_regex("^(.*)(?:", ")$", false)?
-			.replace(&value.into_string(), MatchReplacer(1))
-			.to_string()),
-		ast::ExpansionModifier::StripLongestSuffix(pattern) => Ok(pattern
-			.to_regex("^(.*?)(?:", ")$", true)?
-			.replace(&value.into_string(), MatchReplacer(1))
-			.to_string()),
-		ast::ExpansionModifier::ReplaceOnce { pattern, string } => Ok(pattern
-			.to_regex("", "", true)?
-			.replace(&value.into_string(), &eval_text(apml, string)?)
-			.to_string()),
-		ast::ExpansionModifier::ReplaceAll { pattern, string } => Ok(pattern
-			.to_regex("", "", true)?
-			.replace_all(&value.into_string(), &eval_text(apml, string)?)
-			.to_string()),
-		ast::ExpansionModifier::ReplacePrefix { pattern, string } => {
-			Ok(pattern
-				.to_regex("^", "", true)?
-				.replace_all(&value.into_string(), &eval_text(apml, string)?)
-				.to_string())
-		}
-		ast::ExpansionModifier::ReplaceSuffix { pattern, string } => {
-			Ok(pattern
-				.to_regex("", "$", true)?
-				.replace_all(&value.into_string(), &eval_text(apml, string)?)
-				.to_string())
-		}
-		ast::ExpansionModifier::UpperOnce(pattern) => Ok(pattern
-			.to_regex("", "", true)?
-			.replace(&value.into_string(), UppercaseReplacer)
-			.to_string()),
-		ast::ExpansionModifier::UpperAll(pattern) => Ok(pattern
-			.to_regex("", "", true)?
-			.replace_all(&value.into_string(), UppercaseReplacer)
-			.to_string()),
-		ast::ExpansionModifier::LowerOnce(pattern) => Ok(pattern
-			.to_regex("", "", true)?
-			.replace(&value.into_string(), LowercaseReplacer)
-			.to_string()),
-		ast::ExpansionModifier::LowerAll(pattern) => Ok(pattern
-			.to_regex("", "", true)?
-			.replace_all(&value.into_string(), LowercaseReplacer)
-			.to_string()),
-		ast::ExpansionModifier::ErrorOnUnset(text) => {
-			if value.is_empty() {
-				Err(EvalError::Unset(eval_text(apml, text)?))
-			} else {
-				Ok(value.into_string())
 			}
 		}
-		ast::ExpansionModifier::Length => Ok(value.len().to_string()),
-		ast::ExpansionModifier::WhenUnset(text) => {
-			if value.is_empty() {
-				eval_text(apml, text)
-			} else {
-				Ok(value.into_string())
+	}
+
+	/// Evaluates a text with unquoted expansions, splitting their values
+	/// into fields.
+	///
+	/// As in bash, whitespace characters of `IFS` separate fields and are
+	/// collapsed, while other characters of `IFS` end a field each, even
+	/// an empty one. A text only made of expansions expanding to nothing
+	/// produces no field. Templates are not split, as their values are
+	/// unknown.
+	fn eval_split_text(
+		&mut self,
+		text: &ast::Text,
+		values: &mut Vec<String>,
+	) -> Result<()> {
+		let ifs = self.ifs();
+		let outer = core::mem::take(&mut self.symbolic);
+		let mut fields = Vec::new();
+		let mut field = String::new();
+		// if the current field exists even when empty
+		let mut present = false;
+		// if the last field was ended by whitespace
+		let mut ended = false;
+		let mut words = text.0.iter().peekable();
+		while let Some(word) = words.next() {
+			let ast::Word::SplitVariable(_) = word else {
+				// evaluate runs of other words together, as in `eval_text`
+				let mut run = vec![word.clone()];
+				while let Some(word) = words.next_if(|word| {
+					!matches!(word, ast::Word::SplitVariable(_))
+				}) {
+					run.push(word.clone());
+				}
+				let value = self.eval_text(&ast::Text(run))?;
+				if self.symbolic {
+					break;
+				}
+				present |= !value.is_empty();
+				ended = false;
+				field.push_str(&value);
+				continue;
+			};
+			let value = self.eval_word(word)?;
+			if self.symbolic {
+				break;
 			}
+			for ch in value.chars() {
+				if !ifs.contains(ch) {
+					field.push(ch);
+					present = true;
+					ended = false;
+				} else if ch.is_whitespace() {
+					if present {
+						fields.push(core::mem::take(&mut field));
+						present = false;
+						ended = true;
+					}
+				} else if ended {
+					// absorbed into the preceding whitespace
+					ended = false;
+				} else {
+					fields.push(core::mem::take(&mut field));
+					present = false;
+				}
+			}
+		}
+		if self.symbolic {
+			self.symbolic = false;
+			values.push(self.eval_text(text)?);
+			self.symbolic |= outer;
+			return Ok(());
+		}
+		self.symbolic = outer;
+		if present {
+			fields.push(field);
+		}
+		values.extend(fields);
+		Ok(())
+	}
+
+	/// Returns the characters of `IFS`, which defaults to whitespace.
+	fn ifs(&mut self) -> String {
+		self.reference("IFS");
+		match self.lookup("IFS").as_deref() {
+			None => " \t\n".to_string(),
+			Some(ifs) => ifs.as_string(),
 		}
-		ast::ExpansionModifier::WhenSet(text) => {
-			if !value.is_empty() {
-				eval_text(apml, text)
+	}
+
+	fn eval_text(&mut self, text: &ast::Text) -> Result<String> {
+		let ast::Text(words) = text;
+		let outer = core::mem::take(&mut self.symbolic);
+		let mut pieces = Vec::with_capacity(words.len());
+		let mut symbolic = false;
+		let mut offset = 0;
+		let mut words = words.iter().peekable();
+		while let Some(word) = words.next() {
+			let value = if let ast::Word::AnsiCQuote(text) = word {
+				// adjacent quotes are decoded together, as the bytes of
+				// a character may be split across them
+				let mut bytes = decode_ansi_c(text);
+				while let Some(ast::Word::AnsiCQuote(text)) = words.peek() {
+					bytes.extend(decode_ansi_c(text));
+					words.next();
+				}
+				self.decode_utf8(bytes, offset)?
 			} else {
-				Ok(value.into_string())
+				self.eval_word(word)?
+			};
+			offset += value.len();
+			let verbatim = core::mem::take(&mut self.symbolic);
+			symbolic |= verbatim;
+			pieces.push((value, verbatim));
+		}
+		self.symbolic = outer || symbolic;
+		if symbolic {
+			Ok(pieces
+				.iter()
+				.map(|(value, verbatim)| {
+					if *verbatim {
+						Cow::Borrowed(value.as_str())
+					} else {
+						quote_literal(value)
+					}
+				})
+				.collect())
+		} else {
+			Ok(pieces.into_iter().map(|(value, _)| value).collect())
+		}
+	}
+
+	#[inline]
+	fn eval_word(&mut self, word: &ast::Word) -> Result<String> {
+		if matches!(
+			word,
+			ast::Word::Variable(_)
+				| ast::Word::SplitVariable(_)
+				| ast::Word::Custom(_)
+		) {
+			self.budget.check()?;
+		}
+		match word {
+			ast::Word::Literal(text) | ast::Word::Subcommand(text) => {
+				Ok(text.to_string())
+			}
+			ast::Word::AnsiCQuote(text) => {
+				self.decode_utf8(decode_ansi_c(text), 0)
+			}
+			ast::Word::Variable(expansion)
+			| ast::Word::SplitVariable(expansion) => {
+				// joined templates are no longer templates
+				let partial = expansion.modifier.is_some();
+				if self.keep_symbolic(&expansion.name, partial) {
+					self.reference(&expansion.name);
+					return Ok(format!("${{{}}}", expansion.lower()));
+				}
+				self.check_bound(expansion)?;
+				let mut val = self.expand_variable(&expansion.name);
+				if !matches!(
+					expansion.modifier,
+					Some(
+						ast::ExpansionModifier::ArrayElements
+							| ast::ExpansionModifier::SingleWordElements
+					)
+				) && let VariableValue::Array(elements) = val
+				{
+					// as in bash, only `[@]` and `[*]` expand all elements
+					val = VariableValue::String(
+						elements.into_iter().next().unwrap_or_default(),
+					);
+				}
+				let result = if let Some(modifier) = &expansion.modifier {
+					let outer = core::mem::take(&mut self.symbolic);
+					let result =
+						self.apply_expansion_modifier(modifier, val)?;
+					if self.symbolic {
+						// symbolic arguments are not applied partially
+						return Ok(format!("${{{}}}", expansion.lower()));
+					}
+					self.symbolic = outer;
+					result
+				} else {
+					val.into_string()
+				};
+				#[cfg(feature = "tracing")]
+				if result.len() > LARGE_EXPANSION_THRESHOLD {
+					tracing::debug!(
+						name = %expansion.name,
+						len = result.len(),
+						"large expansion"
+					);
+				}
+				Ok(result)
+			}
+			ast::Word::Custom(expansion) => self.eval_custom(expansion),
+		}
+	}
+
+	/// Evaluates a custom expansion.
+	///
+	/// The expansion is kept symbolic if any argument is.
+	fn eval_custom(
+		&mut self,
+		expansion: &ast::CustomExpansion,
+	) -> Result<String> {
+		let function = self
+			.custom_expansions
+			.and_then(|functions| functions.get(expansion.name.as_ref()))
+			.ok_or_else(|| {
+				EvalError::UnknownExpansion(expansion.name.to_string())
+			})?;
+		let mut args = Vec::with_capacity(expansion.args.len());
+		let mut symbolic = false;
+		for name in &expansion.args {
+			symbolic |= self.keep_symbolic(name, true);
+			self.reference(name);
+			if self.nounset && self.lookup(name).is_none() {
+				let span = self
+					.references
+					.iter()
+					.find(|(reference, _)| reference == name.as_ref())
+					.map(|(_, span)| *span)
+					.unwrap_or_default();
+				return Err(EvalError::Unbound {
+					name: name.to_string(),
+					span,
+				});
+			}
+			args.push(self.expand_variable(name));
+		}
+		if symbolic {
+			return Ok(expansion.lower().to_string());
+		}
+		function(&args)
+			.map(VariableValue::into_string)
+			.map_err(|message| EvalError::CustomExpansion {
+				name: expansion.name.to_string(),
+				message,
+			})
+	}
+
+	/// Converts decoded bytes into a string, starting at `offset` of
+	/// the text being evaluated.
+	fn decode_utf8(&mut self, bytes: Vec<u8>, offset: usize) -> Result<String> {
+		match String::from_utf8(bytes) {
+			Ok(text) => Ok(text),
+			Err(err) if self.lossy_utf8 => {
+				self.replaced_invalid_utf8 = true;
+				Ok(String::from_utf8_lossy(err.as_bytes()).into_owned())
+			}
+			Err(err) => Err(EvalError::InvalidUtf8 {
+				variable: String::new(),
+				offset: offset + err.utf8_error().valid_up_to(),
+			}),
+		}
+	}
+
+	/// Gets the value of a variable or a special parameter.
+	///
+	/// See [`is_special`] for special parameters.
+	fn expand_variable(&mut self, name: &str) -> VariableValue {
+		let apml = self.apml;
+		match name {
+			"*" => VariableValue::String(
+				self.join_with_ifs(apml.positional_params()),
+			),
+			"@" => VariableValue::String(apml.positional_params().join(" ")),
+			_ if is_special(name) => {
+				let index = name.parse::<usize>().unwrap_or_default();
+				let param = index
+					.checked_sub(1)
+					.and_then(|index| apml.positional_params().get(index));
+				VariableValue::String(param.cloned().unwrap_or_default())
+			}
+			_ => {
+				self.reference(name);
+				let value =
+					self.lookup(name).map(Cow::into_owned).unwrap_or_default();
+				let size = value_len(&value);
+				if self
+					.largest
+					.as_ref()
+					.is_none_or(|(_, largest)| size > *largest)
+				{
+					self.largest = Some((name.to_string(), size));
+				}
+				value
+			}
+		}
+	}
+
+	/// Fails if an unset variable is expanded under `set -u`.
+	///
+	/// As in bash, expansions with defaults or alternatives and
+	/// expansions of all elements of arrays, such as `${NAME[*]}`, do
+	/// not fail.
+	fn check_bound(
+		&mut self,
+		expansion: &ast::VariableExpansion,
+	) -> Result<()> {
+		if !self.nounset
+			|| is_special(&expansion.name)
+			|| matches!(
+				expansion.modifier,
+				Some(
+					ast::ExpansionModifier::WhenUnset(_)
+						| ast::ExpansionModifier::WhenSet(_)
+						| ast::ExpansionModifier::ErrorOnUnset(_)
+						| ast::ExpansionModifier::ArrayElements
+						| ast::ExpansionModifier::SingleWordElements
+				)
+			) || self.lookup(&expansion.name).is_some()
+		{
+			return Ok(());
+		}
+		Err(EvalError::Unbound {
+			name: expansion.name.to_string(),
+			span: Span::default(),
+		})
+	}
+
+	/// Joins words with the first character of `IFS`, as `"$*"` does.
+	///
+	/// If `IFS` is unset, words are joined with a space.
+	/// If `IFS` is empty, words are joined without separators.
+	fn join_with_ifs(&mut self, words: &[String]) -> String {
+		self.reference("IFS");
+		match self.lookup("IFS").as_deref() {
+			None => words.join(" "),
+			Some(ifs) => match ifs.as_string().chars().next() {
+				None => words.concat(),
+				Some(separator) => {
+					words.join(separator.encode_utf8(&mut [0; 4]))
+				}
+			},
+		}
+	}
+
+	fn apply_expansion_modifier(
+		&mut self,
+		modifier: &ast::ExpansionModifier,
+		value: VariableValue,
+	) -> Result<String> {
+		struct MatchReplacer(usize);
+		impl regex::Replacer for MatchReplacer {
+			fn replace_append(
+				&mut self,
+				caps: &regex::Captures<'_>,
+				dst: &mut String,
+			) {
+				dst.push_str(&caps[self.0]);
+			}
+		}
+
+		struct UppercaseReplacer;
+		impl regex::Replacer for UppercaseReplacer {
+			fn replace_append(
+				&mut self,
+				caps: &regex::Captures<'_>,
+				dst: &mut String,
+			) {
+				dst.push_str(&caps[0].to_ascii_uppercase());
+			}
+		}
+
+		struct LowercaseReplacer;
+		impl regex::Replacer for LowercaseReplacer {
+			fn replace_append(
+				&mut self,
+				caps: &regex::Captures<'_>,
+				dst: &mut String,
+			) {
+				dst.push_str(&caps[0].to_ascii_lowercase());
+			}
+		}
+
+		match modifier {
+			ast::ExpansionModifier::Substring { offset, length } => {
+				let value = value.into_string();
+				if let Some(length) = length {
+					if *length > 0 {
+						Ok(value[*offset
+							..min(*offset + *length as usize, value.len())]
+							.to_string())
+					} else {
+						Ok(value[*offset..(value.len() - (-*length) as usize)]
+							.to_string())
+					}
+				} else {
+					Ok(value[*offset..].to_string())
+				}
+			}
+			ast::ExpansionModifier::StripShortestPrefix(pattern) => Ok(pattern
+				.to_regex("^(?:", ")?(.*)$", false)?
+				.replace(&value.into_string(), MatchReplacer(1))
+				.to_string()),
+			ast::ExpansionModifier::StripLongestPrefix(pattern) => Ok(pattern
+				.to_regex("^(?:", ")?(.*?)$", true)?
+				.replace(&value.into_string(), MatchReplacer(1))
+				.to_string()),
+			ast::ExpansionModifier::StripShortestSuffix(pattern) => Ok(pattern
+				.to_regex("^(.*)(?:", ")$", false)?
+				.replace(&value.into_string(), MatchReplacer(1))
+				.to_string()),
+			ast::ExpansionModifier::StripLongestSuffix(pattern) => Ok(pattern
+				.to_regex("^(.*?)(?:", ")$", true)?
+				.replace(&value.into_string(), MatchReplacer(1))
+				.to_string()),
+			ast::ExpansionModifier::ReplaceOnce { pattern, string } => {
+				Ok(pattern
+					.to_regex("", "", true)?
+					.replace(&value.into_string(), &self.eval_text(string)?)
+					.to_string())
+			}
+			ast::ExpansionModifier::ReplaceAll { pattern, string } => {
+				Ok(pattern
+					.to_regex("", "", true)?
+					.replace_all(&value.into_string(), &self.eval_text(string)?)
+					.to_string())
 			}
+			ast::ExpansionModifier::ReplacePrefix { pattern, string } => {
+				Ok(pattern
+					.to_regex("^", "", true)?
+					.replace_all(&value.into_string(), &self.eval_text(string)?)
+					.to_string())
+			}
+			ast::ExpansionModifier::ReplaceSuffix { pattern, string } => {
+				Ok(pattern
+					.to_regex("", "$", true)?
+					.replace_all(&value.into_string(), &self.eval_text(string)?)
+					.to_string())
+			}
+			ast::ExpansionModifier::UpperOnce(pattern) => Ok(pattern
+				.to_regex("", "", true)?
+				.replace(&value.into_string(), UppercaseReplacer)
+				.to_string()),
+			ast::ExpansionModifier::UpperAll(pattern) => Ok(pattern
+				.to_regex("", "", true)?
+				.replace_all(&value.into_string(), UppercaseReplacer)
+				.to_string()),
+			ast::ExpansionModifier::LowerOnce(pattern) => Ok(pattern
+				.to_regex("", "", true)?
+				.replace(&value.into_string(), LowercaseReplacer)
+				.to_string()),
+			ast::ExpansionModifier::LowerAll(pattern) => Ok(pattern
+				.to_regex("", "", true)?
+				.replace_all(&value.into_string(), LowercaseReplacer)
+				.to_string()),
+			ast::ExpansionModifier::ErrorOnUnset(text) => {
+				if value.is_empty() {
+					Err(EvalError::Unset(self.eval_text(text)?))
+				} else {
+					Ok(value.into_string())
+				}
+			}
+			ast::ExpansionModifier::Length => Ok(value.len().to_string()),
+			ast::ExpansionModifier::WhenUnset(text) => {
+				if value.is_empty() {
+					self.eval_text(text)
+				} else {
+					Ok(value.into_string())
+				}
+			}
+			ast::ExpansionModifier::WhenSet(text) => {
+				if !value.is_empty() {
+					self.eval_text(text)
+				} else {
+					Ok(value.into_string())
+				}
+			}
+			ast::ExpansionModifier::ArrayElements => Ok(value.into_string()),
+			ast::ExpansionModifier::SingleWordElements => match value {
+				VariableValue::String(text) => Ok(text),
+				VariableValue::Array(els) => Ok(self.join_with_ifs(&els)),
+			},
 		}
 	}
 }
 
 #[cfg(test)]
 mod test {
-	use std::sync::Arc;
+	use std::{cell::RefCell, rc::Rc, sync::Arc};
+	#[cfg(feature = "std")]
+	use std::{
+		sync::atomic::{AtomicBool, Ordering},
+		time::{Duration, Instant},
+	};
 
 	use crate::apml::{
-		ApmlContext,
-		ast::{ExpansionModifier, Text, Word},
-		eval::apply_expansion_modifier,
+		ApmlContext, ApmlError, VariableValue,
+		ast::{self, ApmlAst, AstNode, ExpansionModifier, Text, Word},
+		eval::{
+			EvalError, EvalOptions, EvalWarning, EvalWarningKind, Evaluator,
+			Result, ShellOptions, UnknownPolicy, VariableResolver,
+			eval_ast_with_resolver, eval_word, eval_word_scalar,
+		},
+		lst::ApmlLst,
 		pattern::{BashPattern, GlobPart},
+		span::Span,
 	};
 
+	fn apply_expansion_modifier(
+		apml: &ApmlContext,
+		modifier: &ExpansionModifier,
+		value: VariableValue,
+	) -> Result<String> {
+		Evaluator::new(apml).apply_expansion_modifier(modifier, value)
+	}
+
+	#[test]
+	fn test_empty_values() {
+		// results are taken from bash 5.2
+		let apml = ApmlContext::eval_source(
+			"A=\nB=()\nC=\nC+=(x)\nD=()\nD+=(x)\nE+=(x)\nF=\"a b\"\n\
+			F+=(c)\nG=(\"${A[@]}\" \"${B[@]}\" \"${U[@]}\")\nH=()\nH+=x\n\
+			I=(a b)\nI+=c\nJ=(a b)\nJ=\"${J}d\"\n",
+		)
+		.unwrap();
+		assert_eq!(apml["A"], VariableValue::String(String::new()));
+		assert_eq!(apml["B"], VariableValue::Array(vec![]));
+		assert_eq!(apml.get("U"), None);
+		assert_eq!(apml.read("U"), VariableValue::String(String::new()));
+		let array = |els: &[&str]| {
+			VariableValue::Array(els.iter().map(|s| s.to_string()).collect())
+		};
+		assert_eq!(apml["C"], array(&["", "x"]));
+		assert_eq!(apml["D"], array(&["x"]));
+		assert_eq!(apml["E"], array(&["x"]));
+		assert_eq!(apml["F"], array(&["a b", "c"]));
+		assert_eq!(apml["G"], array(&[""]));
+		assert_eq!(apml["H"], array(&["x"]));
+		assert_eq!(apml["I"], array(&["ac", "b"]));
+		assert_eq!(apml["J"], array(&["ad", "b"]));
+		assert_eq!(apml["A"].to_string(), "''");
+		assert_eq!(apml["B"].to_string(), "()");
+	}
+
+	#[test]
+	fn test_warnings() {
+		let src = "A=(a b)\nS=s\nB=\"x${A[@]}\"\nC=(\"${A[@]}\" ${S[*]})\n\
+			D=\"${A[*]}${U[@]}\"\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let warnings = Rc::new(RefCell::new(Vec::new()));
+		let mut options = EvalOptions {
+			on_warning: Some(Box::new({
+				let warnings = warnings.clone();
+				move |warning| warnings.borrow_mut().push(warning.clone())
+			})),
+			..Default::default()
+		};
+		let apml = ApmlContext::eval_lst_with(&lst, &mut options).unwrap();
+		assert_eq!(apml["B"], "xa b");
+		let warnings = warnings
+			.borrow()
+			.iter()
+			.map(|warning| (warning.kind, warning.span.slice(src)))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			warnings,
+			vec![
+				(EvalWarningKind::ArrayInScalar, "${A[@]}"),
+				(EvalWarningKind::ScalarIndexed, "${S[*]}"),
+				(EvalWarningKind::ArrayInScalar, "${A[*]}"),
+			]
+		);
+
+		let mut options = EvalOptions {
+			strict: true,
+			..Default::default()
+		};
+		let err = ApmlContext::eval_lst_with(&lst, &mut options).unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"Array variable A is expanded in a string assignment at 16..23"
+		);
+		assert!(matches!(
+			err,
+			ApmlError::Eval(EvalError::Strict(EvalWarning {
+				kind: EvalWarningKind::ArrayInScalar,
+				..
+			}))
+		));
+	}
+
+	#[test]
+	fn test_invalid_utf8() {
+		let apml = ApmlContext::eval_source(
+			r"A=$'\xe4\xb8\xad'
+B=$'\xe4'$'\xb8\xad'
+C=$'\u4e2d\t\101\cA\q\x'
+",
+		)
+		.unwrap();
+		assert_eq!(apml["A"], "中");
+		assert_eq!(apml["B"], "中");
+		assert_eq!(apml["C"], "中\tA\x01\\q\\x");
+
+		// escapes covering part of a multi-byte character
+		for (src, offset) in [
+			(r"X=x$'\xe4\xb8'y", 1),
+			(r"X=($'\xad')", 0),
+			("X=$'\\xe4'\u{b8ad}", 0),
+			("X=\u{4e2d}$'\\xe4'", 3),
+		] {
+			let err = ApmlContext::eval_source(src).unwrap_err();
+			assert!(
+				matches!(
+					&err,
+					ApmlError::Eval(EvalError::InvalidUtf8 { variable, offset: actual })
+						if variable == "X" && *actual == offset
+				),
+				"{src}: {err:?}"
+			);
+		}
+
+		let lst = ApmlLst::parse(r"X=x$'\xe4\xb8'y").unwrap();
+		let warnings = Rc::new(RefCell::new(Vec::new()));
+		let mut options = EvalOptions {
+			lossy_utf8: true,
+			on_warning: Some(Box::new({
+				let warnings = warnings.clone();
+				move |warning| warnings.borrow_mut().push(warning.clone())
+			})),
+			..Default::default()
+		};
+		let apml = ApmlContext::eval_lst_with(&lst, &mut options).unwrap();
+		assert_eq!(apml["X"], "x\u{fffd}y");
+		assert_eq!(warnings.borrow().len(), 1);
+		assert_eq!(warnings.borrow()[0].kind, EvalWarningKind::InvalidUtf8);
+		assert_eq!(warnings.borrow()[0].name, "X");
+	}
+
+	#[test]
+	fn test_custom_expansions() {
+		let src =
+			"PKGVER=1.2-3\nSRCS=\"tbl::https://x/${@rel_ver PKGVER}.tgz\"\n";
+		assert!(ApmlLst::parse(src).is_err());
+		assert!(ApmlLst::parse_with(src, &EvalOptions::default()).is_err());
+
+		let mut options = EvalOptions::default();
+		options.custom_expansions.insert(
+			"rel_ver".to_string(),
+			Box::new(|args| match args {
+				[VariableValue::String(ver)] => Ok(VariableValue::String(
+					ver.split('-').next().unwrap_or_default().to_string(),
+				)),
+				_ => Err("expected a version".to_string()),
+			}),
+		);
+		options.custom_expansions.insert(
+			"all".to_string(),
+			Box::new(|args| {
+				Ok(VariableValue::Array(
+					args.iter().map(VariableValue::as_string).collect(),
+				))
+			}),
+		);
+		let lst = ApmlLst::parse_with(src, &options).unwrap();
+		assert_eq!(lst.to_string(), src);
+		#[cfg(feature = "std")]
+		{
+			assert!(!lst.classify().is_data_only());
+			assert!(super::ast::ApmlAst::emit_supported(&lst).is_err());
+		}
+		let apml = ApmlContext::eval_lst_with(&lst, &mut options).unwrap();
+		assert_eq!(apml["SRCS"], "tbl::https://x/1.2.tgz");
+		assert_eq!(
+			apml.expand_str("${@all PKGVER  SRCS}", &options).unwrap(),
+			"1.2-3 tbl::https://x/1.2.tgz"
+		);
+
+		// without the functions, the file still parses but fails
+		let err = ApmlContext::eval_lst(&lst).unwrap_err();
+		assert!(matches!(
+			err,
+			ApmlError::Eval(EvalError::UnknownExpansion(name)) if name == "rel_ver"
+		));
+		let err = ApmlContext::eval_source_with(
+			"A=(x)\nB=${@rel_ver A}\n",
+			&mut options,
+		)
+		.unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"Custom expansion rel_ver failed: expected a version"
+		);
+		let err = ApmlContext::eval_source_with(
+			"set -u\nA=x\nB=\"a ${@all A C}\"\n",
+			&mut options,
+		)
+		.unwrap_err();
+		assert!(matches!(
+			err,
+			ApmlError::Eval(EvalError::Unbound { name, span })
+				if name == "C" && span == Span::new(16, 27)
+		));
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn test_cancellation() {
+		let mut options = EvalOptions {
+			deadline: Some(Instant::now()),
+			..Default::default()
+		};
+		let err = ApmlContext::eval_source_with("A=1\nB=2\n", &mut options)
+			.unwrap_err();
+		let ApmlError::Eval(err) = err else { panic!() };
+		assert!(matches!(
+			&err,
+			EvalError::DeadlineExceeded { variable, .. } if variable == "A"
+		));
+		assert!(err.partial_context().unwrap().keys().next().is_none());
+
+		// cancelled by an expansion, detected at the next expansion
+		let cancel = Arc::new(AtomicBool::new(false));
+		let mut options = EvalOptions {
+			cancel: Some(cancel.clone()),
+			..Default::default()
+		};
+		let flag = cancel.clone();
+		options.custom_expansions.insert(
+			"stop".to_string(),
+			Box::new(move |_| {
+				flag.store(true, Ordering::Relaxed);
+				Ok(VariableValue::default())
+			}),
+		);
+		let err = ApmlContext::eval_source_with(
+			"A=1\nB=${@stop}\nC=x$A\nD=2\n",
+			&mut options,
+		)
+		.unwrap_err();
+		let ApmlError::Eval(err) = err else { panic!() };
+		assert_eq!(err.to_string(), "Evaluation cancelled while evaluating C");
+		let partial = err.partial_context().unwrap();
+		assert_eq!(partial["A"], "1");
+		assert_eq!(partial["B"], "");
+		assert!(!partial.contains_var("C"));
+		cancel.store(false, Ordering::Relaxed);
+		assert_eq!(
+			ApmlContext::eval_source_with("A=1\nC=x$A\n", &mut options)
+				.unwrap()["C"],
+			"x1"
+		);
+
+		// cancelled from another thread, in about 10s of evaluation
+		options.custom_expansions.insert(
+			"slow".to_string(),
+			Box::new(|_| {
+				std::thread::sleep(Duration::from_millis(1));
+				Ok(VariableValue::default())
+			}),
+		);
+		let src = (0..10000)
+			.map(|i| format!("V{}=${{@slow}}\n", i))
+			.collect::<String>();
+		let lst = ApmlLst::parse_with(&src, &options).unwrap();
+		let flag = cancel.clone();
+		let cancelled_at = std::thread::spawn(move || {
+			std::thread::sleep(Duration::from_millis(20));
+			flag.store(true, Ordering::Relaxed);
+			Instant::now()
+		});
+		let err = ApmlContext::eval_lst_with(&lst, &mut options).unwrap_err();
+		let returned_at = Instant::now();
+		let cancelled_at = cancelled_at.join().unwrap();
+		let ApmlError::Eval(err) = err else { panic!() };
+		assert!(matches!(err, EvalError::Cancelled { .. }));
+		assert!(
+			returned_at.duration_since(cancelled_at) < Duration::from_secs(1)
+		);
+	}
+
+	#[test]
+	fn test_value_limit() {
+		let mut options = EvalOptions {
+			max_value_len: Some(64),
+			..Default::default()
+		};
+		let src = "X=a\nB=\"$X$X\"\n".to_string()
+			+ &"A=\"$B$B\"\nB=\"$A$A\"\n".repeat(3);
+		let err =
+			ApmlContext::eval_source_with(&src, &mut options).unwrap_err();
+		let ApmlError::Eval(err) = err else { panic!() };
+		let EvalError::LimitExceeded {
+			variable,
+			size,
+			limit,
+			chain,
+		} = &err
+		else {
+			panic!()
+		};
+		assert_eq!((variable.as_str(), *size, *limit), ("B", 128, 64));
+		assert_eq!(
+			chain
+				.0
+				.iter()
+				.map(|(name, _)| name.as_str())
+				.collect::<Vec<_>>(),
+			vec!["B", "A", "B", "A", "B", "A", "B", "X"]
+		);
+		assert_eq!(
+			err.to_string(),
+			"Value of B has 128 bytes, exceeding the limit of 64 bytes: \
+			B (58..66) ← A (49..57) ← B (40..48) ← A (31..39) ← B (22..30) \
+			← A (13..21) ← B (4..12) ← X (0..3)"
+		);
+
+		let src = "A=\n".to_string() + &"A+=01234567\n".repeat(9);
+		let err =
+			ApmlContext::eval_source_with(&src, &mut options).unwrap_err();
+		let ApmlError::Eval(err) = err else { panic!() };
+		assert_eq!(
+			err.to_string(),
+			"Value of A has 72 bytes, exceeding the limit of 64 bytes: \
+			A (99..110) ← A (87..98) ← A (75..86) ← A (63..74) ← A (51..62) \
+			← A (39..50) ← A (27..38) ← A (15..26) ← … (2 more)"
+		);
+		options.max_value_len = Some(128);
+		assert_eq!(
+			ApmlContext::eval_source_with(
+				"A=(aa bb)\nB=\"${A[@]}\"\n",
+				&mut options
+			)
+			.unwrap()["B"],
+			"aa bb"
+		);
+	}
+
+	#[test]
+	fn test_shell_options() {
+		let src = "A=$X\nset -eu\nB=\"${X:-x}${Y[*]}$1\"\nset +u\nC=$X\n\
+			set -o nounset\nD=$A\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(lst.to_string(), src);
+		let apml = ApmlContext::eval_lst(&lst).unwrap();
+		assert_eq!(apml["B"], "x");
+		assert_eq!(
+			lst.shell_options()
+				.unwrap()
+				.iter()
+				.map(|(span, options)| (span.slice(src), options.nounset))
+				.collect::<Vec<_>>(),
+			vec![
+				("set -eu", true),
+				("set +u", false),
+				("set -o nounset", true)
+			]
+		);
+		let at = |offset| lst.shell_options_at(offset).unwrap();
+		assert_eq!(at(0), ShellOptions::default());
+		assert_eq!(
+			at(src.find("B=").unwrap()),
+			ShellOptions {
+				errexit: true,
+				nounset: true
+			}
+		);
+		assert!(!at(src.find("C=").unwrap()).nounset);
+		assert!(at(src.find("C=").unwrap()).errexit);
+
+		let err =
+			ApmlContext::eval_source("set -u\nA=1\nB=\"$A$C\"\n").unwrap_err();
+		assert!(
+			matches!(
+				&err,
+				ApmlError::Eval(EvalError::Unbound { name, span })
+					if name == "C" && *span == Span::new(11, 19)
+			),
+			"{err:?}"
+		);
+		assert!(ApmlContext::eval_source("set -u\nA=${#B}\n").is_err());
+		let apml = ApmlContext::eval_source(
+			"set -u\nA=${Y[@]}\nB=\"x${Y[@]}\"\nC=(${Y[@]} \"${Y[@]}\")\n\
+			Z=(a b)\nD=\"${Z[@]}\"\n",
+		)
+		.unwrap();
+		assert_eq!(apml["A"], "");
+		assert_eq!(apml["B"], "x");
+		assert_eq!(apml["C"], VariableValue::Array(vec![]));
+		assert_eq!(apml["D"], "a b");
+		for (src, option) in [
+			("set -x\n", "-x"),
+			("set +eux\n", "+x"),
+			("set -o pipefail\n", "-o pipefail"),
+			("set foo\n", "foo"),
+		] {
+			let err = ApmlContext::eval_source(src).unwrap_err();
+			assert_eq!(
+				err.to_string(),
+				format!(
+					"Unsupported shell option {option} at 0..{}",
+					src.len() - 1
+				)
+			);
+		}
+		assert!(ApmlContext::eval_source("A=1 set -e\n").is_err());
+	}
+
+	#[test]
+	fn test_possible_typos() {
+		let src = "PKGVER=1\nA=\"$pkgver ${PKGVR:-$pkgver}\"\nB=\"$1$C\"\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let warnings = Rc::new(RefCell::new(Vec::new()));
+		let mut options = EvalOptions {
+			on_warning: Some(Box::new({
+				let warnings = warnings.clone();
+				move |warning| warnings.borrow_mut().push(warning.clone())
+			})),
+			..Default::default()
+		};
+		ApmlContext::eval_lst_with(&lst, &mut options).unwrap();
+		let warnings = warnings
+			.borrow()
+			.iter()
+			.map(|warning| {
+				(
+					warning.name.as_str().to_string(),
+					warning.span.slice(src).to_string(),
+					warning.suggestion.clone().unwrap(),
+				)
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			warnings,
+			vec![
+				("pkgver".into(), "$pkgver".into(), "PKGVER".into()),
+				("PKGVR".into(), "${PKGVR:-$pkgver}".into(), "PKGVER".into()),
+			]
+		);
+
+		let mut options = EvalOptions {
+			strict: true,
+			..Default::default()
+		};
+		let err = ApmlContext::eval_lst_with(&lst, &mut options).unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"Undefined variable pkgver is expanded at 12..19, \
+			did you mean `PKGVER`?"
+		);
+	}
+
+	#[test]
+	fn test_symbolic() {
+		let src = "A=1\nB=\"$A-$ARCH\"\nC=\"${B/1/2}\"\nD=${U:-d}\n\
+			E=(\"${L[@]}\" \"a b\" $A)\nE+=(\"${B}\")\nF=\"${A/1/$V}\\$\"\n\
+			G=\"x y\"\nG+=\"$ARCH\"\nH=(x \"y z\")\nH+=\"$ARCH\"\n";
+		let mut options = EvalOptions {
+			unknown_policy: UnknownPolicy::Symbolic,
+			..Default::default()
+		};
+		let apml = ApmlContext::eval_lst_with(
+			&ApmlLst::parse(src).unwrap(),
+			&mut options,
+		)
+		.unwrap();
+		assert_eq!(apml["A"], "1");
+		assert_eq!(apml["B"], "1-${ARCH}");
+		assert_eq!(apml["C"], "${B/1/\"2\"}");
+		assert_eq!(apml["D"], "${U:-\"d\"}");
+		assert_eq!(
+			apml["E"],
+			VariableValue::Array(vec![
+				"${L[@]}".to_string(),
+				"'a b'".to_string(),
+				"1".to_string(),
+				"1-${ARCH}".to_string(),
+			])
+		);
+		assert_eq!(apml["F"], "${A/1/\"${V}\"}'$'");
+		assert_eq!(apml["G"], "'x y'${ARCH}");
+		assert_eq!(
+			apml["H"],
+			VariableValue::Array(vec![
+				"x${ARCH}".to_string(),
+				"'y z'".to_string(),
+			])
+		);
+		assert!(!apml.is_symbolic("A"));
+		assert!(apml.is_symbolic("B"));
+		assert!(apml.is_symbolic("E"));
+		assert_eq!(
+			apml.unresolved().iter().collect::<Vec<_>>(),
+			vec!["ARCH", "B", "L", "U", "V"]
+		);
+
+		// expand again once the missing values are known
+		let mut ctx = apml.clone();
+		ctx.insert("ARCH".to_string(), VariableValue::String("amd64".into()));
+		ctx.insert("V".to_string(), VariableValue::String("v".into()));
+		let expand = |ctx: &ApmlContext, name: &str| {
+			ctx.expand_str(&apml[name].as_string(), &Default::default())
+				.unwrap()
+		};
+		let b = expand(&ctx, "B");
+		assert_eq!(b, "1-amd64");
+		ctx.insert("B".to_string(), VariableValue::String(b));
+		assert_eq!(expand(&ctx, "C"), "2-amd64");
+		assert_eq!(expand(&ctx, "D"), "d");
+		assert_eq!(expand(&ctx, "F"), "v$");
+		assert_eq!(expand(&ctx, "G"), "x yamd64");
+
+		// default policy
+		let apml = ApmlContext::eval_source(src).unwrap();
+		assert_eq!(apml["B"], "1-");
+		assert!(apml.unresolved().is_empty());
+	}
+
+	#[test]
+	fn test_resolver() {
+		let requested = RefCell::new(Vec::new());
+		let resolver = |name: &str| {
+			requested.borrow_mut().push(name.to_string());
+			match name {
+				"ARCH" => Some("amd64".into()),
+				"CROSS" => Some(VariableValue::Array(vec!["arm64".into()])),
+				_ => None,
+			}
+		};
+		let ast = ApmlAst::emit_from(
+			&ApmlLst::parse(
+				"ARCH=\"${ARCH}-x\"\nA=\"$ARCH\"\nB=(\"${CROSS[@]}\" b)\n\
+				C=\"$UNKNOWN\"\n",
+			)
+			.unwrap(),
+		)
+		.unwrap();
+		let mut apml = ApmlContext::default();
+		eval_ast_with_resolver(
+			&mut apml,
+			&ast,
+			&resolver,
+			&mut EvalOptions::default(),
+		)
+		.unwrap();
+		assert_eq!(apml.read("ARCH"), "amd64-x");
+		assert_eq!(apml.read("A"), "amd64-x");
+		assert_eq!(
+			apml.read("B"),
+			VariableValue::Array(vec!["arm64".into(), "b".into()])
+		);
+		assert_eq!(apml.read("C"), "");
+		assert!(!apml.contains_var("CROSS"));
+		// only variables not assigned yet are resolved
+		assert_eq!(*requested.borrow(), vec!["ARCH", "CROSS", "UNKNOWN"]);
+
+		struct Defaults;
+		impl VariableResolver for Defaults {
+			fn resolve(&self, name: &str) -> Option<VariableValue> {
+				(name == "ARCH").then(|| "loongarch64".into())
+			}
+
+			fn provenance(&self, name: &str) -> Option<String> {
+				Some(format!("defaults/{}", name))
+			}
+		}
+		let mut apml = ApmlContext::default();
+		eval_ast_with_resolver(
+			&mut apml,
+			&ast,
+			&Defaults,
+			&mut EvalOptions::default(),
+		)
+		.unwrap();
+		assert_eq!(apml.read("A"), "loongarch64-x");
+		assert_eq!(apml.provenance("ARCH"), Some("defaults/ARCH"));
+		assert_eq!(apml.provenance("CROSS"), None);
+	}
+
+	#[test]
+	fn test_eval_word() {
+		// results are taken from bash 5.2
+		let apml = ApmlContext::eval_source(
+			"A=(a \"b c\")\nS=/usr/lib/x.so.1\nE=\nM=\"  w1  w2 \"\n",
+		)
+		.unwrap();
+		let options = EvalOptions::default();
+		let eval = |src: &str| {
+			let src = format!("W=({})\n", src);
+			let tree =
+				ApmlAst::emit_from(&ApmlLst::parse(&src).unwrap()).unwrap();
+			let ast::VariableValue::Array(elements) = &tree.0[0].value else {
+				panic!("not an array");
+			};
+			let [word] = elements.as_slice() else {
+				panic!("not a single word");
+			};
+			eval_word(word, &apml, &options).unwrap()
+		};
+		assert_eq!(eval("\"${A[@]}\""), vec!["a", "b c"]);
+		assert_eq!(eval("\"${U[@]}\""), Vec::<String>::new());
+		assert_eq!(eval("\"${E[@]}\""), vec![""]);
+		assert_eq!(eval("\"${A[*]}\""), vec!["a b c"]);
+		assert_eq!(eval("${S##*/}"), vec!["x.so.1"]);
+		assert_eq!(eval("\"${S%.*}\""), vec!["/usr/lib/x.so"]);
+		assert_eq!(eval("${U:-${S/lib/lib64}}"), vec!["/usr/lib64/x.so.1"]);
+		assert_eq!(eval("\"${S:5:3}\""), vec!["lib"]);
+		assert_eq!(eval("$'a\\tb'\"${#S}\""), vec!["a\tb15"]);
+
+		// unquoted expansions are split into fields
+		assert_eq!(eval("$M"), vec!["w1", "w2"]);
+		assert_eq!(eval("\"$M\""), vec!["  w1  w2 "]);
+		assert_eq!(eval("a$M\"x y\""), vec!["a", "w1", "w2", "x y"]);
+		assert_eq!(eval("a\"$M\"b"), vec!["a  w1  w2 b"]);
+		assert_eq!(eval("${A[@]}"), vec!["a", "b", "c"]);
+		assert_eq!(eval("${A[*]}"), vec!["a", "b", "c"]);
+		assert_eq!(eval("$E"), Vec::<String>::new());
+		assert_eq!(eval("\"$E\""), vec![""]);
+		assert_eq!(eval("${U}"), Vec::<String>::new());
+		let resolver = |name: &str| match name {
+			"IFS" => Some(VariableValue::from(": ")),
+			"X" => Some(VariableValue::from("a::b : c")),
+			_ => None,
+		};
+		let tree =
+			ApmlAst::emit_from(&ApmlLst::parse("W=($X)\n").unwrap()).unwrap();
+		let ast::VariableValue::Array(elements) = &tree.0[0].value else {
+			panic!("not an array");
+		};
+		assert_eq!(
+			eval_word(&elements[0], &resolver, &options).unwrap(),
+			vec!["a", "", "b", "c"]
+		);
+
+		let resolver =
+			|name: &str| (name == "A").then(|| VariableValue::from("x"));
+		let tree =
+			ApmlAst::emit_from(&ApmlLst::parse("W=(\"${A[@]}\")\n").unwrap())
+				.unwrap();
+		let ast::VariableValue::Array(elements) = &tree.0[0].value else {
+			panic!("not an array");
+		};
+		assert_eq!(
+			eval_word_scalar(&elements[0], &apml, &options).unwrap(),
+			"a b c"
+		);
+		assert_eq!(
+			eval_word_scalar(&elements[0], &resolver, &options).unwrap(),
+			"x"
+		);
+	}
+
+	#[test]
+	fn test_influences() {
+		let src = "A=1\nB=\"$A$X\"\nC=\"${B}${D:-$E}${A:-$F}\"\nC+=\"$G\"\n\
+			H=(\"${C[@]}\")\nI=\"${H[*]}\"\n";
+		let mut options = EvalOptions {
+			track_influences: true,
+			..Default::default()
+		};
+		let ctx = ApmlContext::eval_lst_with(
+			&ApmlLst::parse(src).unwrap(),
+			&mut options,
+		)
+		.unwrap();
+		let influences = |name| {
+			ctx.influences(name)
+				.unwrap()
+				.iter()
+				.map(String::as_str)
+				.collect::<Vec<_>>()
+		};
+		assert!(influences("A").is_empty());
+		assert_eq!(influences("B"), vec!["A", "X"]);
+		assert_eq!(influences("C"), vec!["A", "B", "D", "E", "G", "X"]);
+		assert_eq!(influences("H"), vec!["A", "B", "C", "D", "E", "G", "X"]);
+		assert_eq!(
+			influences("I"),
+			vec!["A", "B", "C", "D", "E", "G", "H", "IFS", "X"]
+		);
+		assert_eq!(
+			ctx.external_influences().iter().collect::<Vec<_>>(),
+			vec!["D", "E", "G", "IFS", "X"]
+		);
+		crate::assert_context_eq!(ctx, ApmlContext::eval_source(src).unwrap());
+		assert!(
+			ApmlContext::eval_source(src)
+				.unwrap()
+				.influences("A")
+				.is_none()
+		);
+	}
+
+	#[test]
+	fn test_positional_params() {
+		// joining with each IFS is checked against bash by the
+		// conformance tests
+		let src = "IFS=:\nA=\"$*\"\nH=\"$2\"\nI=\"$10$1a\"\n";
+		let mut ctx = ApmlContext::default();
+		ctx.set_positional_params(vec![
+			"a".to_string(),
+			"b c".to_string(),
+			"d".to_string(),
+		]);
+		let ast = ApmlAst::emit_from(&ApmlLst::parse(src).unwrap()).unwrap();
+		super::eval_ast(&mut ctx, &ast).unwrap();
+		assert_eq!(ctx["A"], "a:b c:d");
+		assert_eq!(ctx["H"], "b c");
+		assert_eq!(ctx["I"], "a0aa");
+	}
+
+	#[test]
+	fn test_on_assign() {
+		let src = "A=\"a\tb\"\nPKGVER=1-2\nB=(1)\nB+=(2)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let seen = Rc::new(RefCell::new(Vec::new()));
+		let mut options = EvalOptions {
+			on_assign: Some(Box::new({
+				let seen = seen.clone();
+				move |name, value, span| {
+					seen.borrow_mut().push((
+						name.to_string(),
+						value.clone(),
+						span,
+					));
+					Ok(())
+				}
+			})),
+			..Default::default()
+		};
+		let ctx = ApmlContext::eval_lst_with(&lst, &mut options).unwrap();
+		assert_eq!(ctx["B"].len(), 2);
+		assert_eq!(
+			*seen.borrow(),
+			vec![
+				("A".to_string(), "a\tb".into(), Span::new(0, 7)),
+				("PKGVER".to_string(), "1-2".into(), Span::new(8, 18)),
+				(
+					"B".to_string(),
+					VariableValue::Array(vec!["1".to_string()]),
+					Span::new(19, 24)
+				),
+				(
+					"B".to_string(),
+					VariableValue::Array(vec![
+						"1".to_string(),
+						"2".to_string()
+					]),
+					Span::new(25, 31)
+				),
+			]
+		);
+
+		let mut options = EvalOptions {
+			on_assign: Some(Box::new(|name, value, _| {
+				if name == "PKGVER" && value.as_string().contains('-') {
+					Err("PKGVER must not contain '-'".to_string())
+				} else {
+					Ok(())
+				}
+			})),
+			..Default::default()
+		};
+		let err = ApmlContext::eval_lst_with(&lst, &mut options).unwrap_err();
+		match err {
+			ApmlError::Eval(EvalError::PolicyViolation { message, span }) => {
+				assert_eq!(message, "PKGVER must not contain '-'");
+				assert_eq!(span.slice(src), "PKGVER=1-2");
+			}
+			_ => panic!("unexpected error: {:?}", err),
+		}
+
+		let mut options = EvalOptions {
+			on_assign: Some(Box::new(|_, value, _| {
+				if value.as_string().contains('\t') {
+					Err("tab".to_string())
+				} else {
+					Ok(())
+				}
+			})),
+			..Default::default()
+		};
+		let mut ctx = ApmlContext::default();
+		let ast = ApmlAst::emit_from(&lst).unwrap();
+		let err =
+			super::eval_ast_with(&mut ctx, &ast, &mut options).unwrap_err();
+		assert!(matches!(
+			err,
+			EvalError::PolicyViolation { span, .. } if span == Span::default()
+		));
+		assert!(!ctx.contains_var("A"));
+	}
+
 	#[test]
 	fn test_expansion_modifier() {
 		let mut ctx = ApmlContext::new();
@@ -0,0 +1,544 @@
+//! Explanations of values of variables.
+//!
+//! See [`explain`].
+
+use std::{cell::RefCell, fmt::Display, rc::Rc};
+
+use super::{
+	ApmlContext, VariableValue,
+	analysis::value_references,
+	arch::{ArchMap, OverrideExplanation, explain_override},
+	eval::EvalOptions,
+	lst,
+	pipeline::Analysis,
+	span::{LineCol, SourceIndex, Span},
+};
+
+/// Explanation of the final value of a variable, produced by [`explain`].
+///
+/// It is displayed as an indented narrative, see [`explain`] for an
+/// example.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Explanation {
+	/// Name of the explained variable, without architecture suffixes.
+	pub name: String,
+	/// The architecture, in lower case, if the variable is resolved for
+	/// one.
+	pub arch: Option<String>,
+	/// Resolution of architecture overrides, if resolved for an
+	/// architecture.
+	pub resolution: Option<OverrideExplanation>,
+	/// Positions of the candidates of [`Explanation::resolution`], in
+	/// the same order, or [`None`] for candidates not defined in the
+	/// source.
+	pub candidate_positions: Vec<Option<LineCol>>,
+	/// The variable providing the value, such as `PKGDEP__AMD64`.
+	///
+	/// This is [`None`] if the resolution is ambiguous.
+	pub variable: Option<String>,
+	/// The final value, or [`None`] if the variable is not defined.
+	pub value: Option<VariableValue>,
+	/// Definitions of [`Explanation::variable`], in order of evaluation.
+	pub steps: Vec<ExplanationStep>,
+	/// Variables influencing the final value, sorted by names.
+	///
+	/// See [`ApmlContext::influences`].
+	pub influences: Vec<Influence>,
+}
+
+/// A definition contributing to an [`Explanation`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExplanationStep {
+	/// Span of the definition.
+	pub span: Span,
+	/// Position of the definition.
+	pub position: LineCol,
+	/// Source text of the definition.
+	pub source: String,
+	/// Whether the definition appends (`+=`).
+	pub append: bool,
+	/// Value of the variable after the definition.
+	pub value: VariableValue,
+	/// Variables expanded by the definition, in order of first
+	/// occurrence, with their values at that time.
+	///
+	/// The implicit reference of appending to the previous value is not
+	/// included.
+	pub references: Vec<Reference>,
+	/// What later definitions do to the value.
+	pub fate: StepFate,
+}
+
+/// A variable expanded by an [`ExplanationStep`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Reference {
+	/// Name of the variable.
+	pub name: String,
+	/// Value of the variable when expanded, or [`None`] if undefined.
+	pub value: Option<VariableValue>,
+	/// Position of the definition providing the value.
+	pub defined_at: Option<LineCol>,
+}
+
+/// What later definitions do to the value of an [`ExplanationStep`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StepFate {
+	/// The value is final.
+	Final,
+	/// The value is kept and appended to by a later definition.
+	Appended {
+		/// Position of the appending definition.
+		by: LineCol,
+	},
+	/// The value is expanded by a later assignment of the variable,
+	/// such as `A="$A x"`.
+	Expanded {
+		/// Position of the expanding definition.
+		by: LineCol,
+	},
+	/// The value is discarded by a later assignment.
+	Replaced {
+		/// Position of the replacing definition.
+		by: LineCol,
+	},
+}
+
+/// A variable influencing an [`Explanation`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Influence {
+	/// Name of the variable.
+	pub name: String,
+	/// Position of the last definition of the variable, or [`None`] if it
+	/// is not defined in the source.
+	pub defined_at: Option<LineCol>,
+}
+
+/// An assignment recorded while evaluating again.
+struct Record {
+	name: String,
+	value: VariableValue,
+	position: LineCol,
+}
+
+/// Explains why a variable has its final value in an analyzed source.
+///
+/// The explanation composes:
+///
+/// - The [resolution of overrides][explain_override] if `arch` is given,
+///   with architecture groups of [`ArchMap::aosc`].
+/// - Each definition of the variable providing the value, along with
+///   the value after it, whether later definitions append to or replace
+///   it, and the variables it expands with their values at that time.
+/// - Variables [influencing][ApmlContext::influences] the final value.
+///
+/// Positions are lines and columns in [`Analysis::source`]. The source
+/// is evaluated again to record intermediate values.
+///
+/// It is displayed like:
+///
+/// ```text
+/// PKGDEP on riscv is 'glibc'
+///   PKGDEP__RISCV (line 3) applies, as overrides for the architecture take precedence.
+///     PKGDEP (line 2) is overridden.
+///   line 3: PKGDEP__RISCV="$BASE"
+///     sets 'glibc'
+///     expands BASE = 'glibc' (line 1)
+///     is the final value
+///   influenced by BASE (line 1)
+/// ```
+pub fn explain(
+	analysis: &Analysis,
+	name: &str,
+	arch: Option<&str>,
+) -> Explanation {
+	let index = SourceIndex::new(analysis.source());
+	let (context, records) = evaluate(analysis, &index);
+
+	let resolution = arch.map(|arch| {
+		explain_override(
+			analysis.context(),
+			analysis.lst(),
+			name,
+			arch,
+			&ArchMap::aosc(),
+		)
+	});
+	let candidate_positions = resolution
+		.iter()
+		.flat_map(|resolution| &resolution.candidates)
+		.map(|candidate| candidate.span.map(|span| index.line_col(span.start)))
+		.collect();
+	let variable = match &resolution {
+		Some(resolution) => {
+			resolution.winner().map(|winner| winner.name.clone())
+		}
+		None => Some(name.to_string()),
+	};
+	let value = variable
+		.as_ref()
+		.and_then(|variable| context.get(variable))
+		.cloned();
+
+	let mut steps = Vec::new();
+	if let Some(variable) = &variable {
+		let defs = analysis
+			.lst()
+			.variable_spans()
+			.zip(&analysis.ast().0)
+			.enumerate()
+			.filter(|(_, ((_, def), _))| def.name == *variable)
+			.collect::<Vec<_>>();
+		for (position, (record, ((span, def), ast_def))) in
+			defs.iter().enumerate()
+		{
+			let append = def.op == lst::VariableOp::Append;
+			let mut names = Vec::new();
+			value_references(&ast_def.value, &mut names);
+			let mut references = Vec::<Reference>::new();
+			for referenced in names {
+				if (append && referenced == variable)
+					|| references.iter().any(|r| r.name == referenced)
+				{
+					continue;
+				}
+				let defined = records[..*record]
+					.iter()
+					.rfind(|record| record.name == referenced);
+				references.push(Reference {
+					name: referenced.to_string(),
+					value: defined.map(|record| record.value.clone()),
+					defined_at: defined.map(|record| record.position),
+				});
+			}
+			let fate = match defs.get(position + 1) {
+				Some((_, ((span, def), ast_def))) => {
+					let by = index.line_col(span.start);
+					let mut names = Vec::new();
+					value_references(&ast_def.value, &mut names);
+					match def.op {
+						lst::VariableOp::Append => StepFate::Appended { by },
+						lst::VariableOp::Assignment
+							if names.contains(&variable.as_str()) =>
+						{
+							StepFate::Expanded { by }
+						}
+						lst::VariableOp::Assignment => {
+							StepFate::Replaced { by }
+						}
+					}
+				}
+				None => StepFate::Final,
+			};
+			steps.push(ExplanationStep {
+				span: *span,
+				position: records[*record].position,
+				source: span.slice(analysis.source()).to_string(),
+				append,
+				value: records[*record].value.clone(),
+				references,
+				fate,
+			});
+		}
+	}
+
+	let influences = variable
+		.as_ref()
+		.and_then(|variable| context.influences(variable))
+		.into_iter()
+		.flatten()
+		.map(|influence| Influence {
+			name: influence.clone(),
+			defined_at: records
+				.iter()
+				.rfind(|record| record.name == *influence)
+				.map(|record| record.position),
+		})
+		.collect();
+
+	Explanation {
+		name: name.to_string(),
+		arch: resolution
+			.as_ref()
+			.map(|resolution| resolution.arch.clone()),
+		resolution,
+		candidate_positions,
+		variable,
+		value,
+		steps,
+		influences,
+	}
+}
+
+/// Evaluates an analyzed source again, tracking influences and
+/// recording each assignment.
+fn evaluate(
+	analysis: &Analysis,
+	index: &SourceIndex,
+) -> (ApmlContext, Vec<Record>) {
+	let records = Rc::new(RefCell::new(Vec::new()));
+	let mut options = EvalOptions {
+		on_assign: Some(Box::new({
+			let records = records.clone();
+			move |name, value, span| {
+				records.borrow_mut().push((
+					name.to_string(),
+					value.clone(),
+					span,
+				));
+				Ok(())
+			}
+		})),
+		track_influences: true,
+		..Default::default()
+	};
+	let context =
+		ApmlContext::eval_emitted(analysis.lst(), analysis.ast(), &mut options)
+			.expect("analyzed sources evaluate");
+	drop(options);
+	let records = Rc::into_inner(records)
+		.expect("callback has been dropped")
+		.into_inner()
+		.into_iter()
+		.map(
+			|(name, value, span): (String, VariableValue, Span)| Record {
+				name,
+				value,
+				position: index.line_col(span.start),
+			},
+		)
+		.collect();
+	(context, records)
+}
+
+impl Display for Explanation {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.name)?;
+		if let Some(arch) = &self.arch {
+			write!(f, " on {}", arch)?;
+		}
+		match &self.value {
+			Some(value) => writeln!(f, " is {}", value)?,
+			None => writeln!(f, " is not defined")?,
+		}
+
+		if let Some(resolution) = &self.resolution {
+			let line = |index: usize| {
+				let name = &resolution.candidates[index].name;
+				match self.candidate_positions.get(index).copied().flatten() {
+					Some(at) => format!("{} (line {})", name, at.line),
+					None => name.clone(),
+				}
+			};
+			match resolution.winner() {
+				Some(winner) => {
+					writeln!(
+						f,
+						"  {} applies, as {}.",
+						line(0),
+						winner.source.reason()
+					)?;
+					for index in 1..resolution.candidates.len() {
+						writeln!(f, "    {} is overridden.", line(index))?;
+					}
+				}
+				None if !resolution.candidates.is_empty() => {
+					writeln!(f, "  {}", resolution.explain())?;
+				}
+				None => {}
+			}
+		}
+
+		for step in &self.steps {
+			writeln!(f, "  line {}: {}", step.position.line, step.source)?;
+			if step.append {
+				writeln!(f, "    appends, making it {}", step.value)?;
+			} else {
+				writeln!(f, "    sets {}", step.value)?;
+			}
+			for reference in &step.references {
+				match (&reference.value, reference.defined_at) {
+					(Some(value), Some(at)) => writeln!(
+						f,
+						"    expands {} = {} (line {})",
+						reference.name, value, at.line
+					)?,
+					_ => writeln!(
+						f,
+						"    expands {}, which is undefined",
+						reference.name
+					)?,
+				}
+			}
+			match step.fate {
+				StepFate::Final => writeln!(f, "    is the final value")?,
+				StepFate::Appended { by } => writeln!(
+					f,
+					"    is kept, and appended to on line {}",
+					by.line
+				)?,
+				StepFate::Expanded { by } => writeln!(
+					f,
+					"    is kept, and expanded on line {}",
+					by.line
+				)?,
+				StepFate::Replaced { by } => {
+					writeln!(f, "    is replaced on line {}", by.line)?
+				}
+			}
+		}
+
+		if !self.influences.is_empty() {
+			let influences = self
+				.influences
+				.iter()
+				.map(|influence| match influence.defined_at {
+					Some(at) => {
+						format!("{} (line {})", influence.name, at.line)
+					}
+					None => format!("{} (undefined)", influence.name),
+				})
+				.collect::<Vec<_>>();
+			writeln!(f, "  influenced by {}", influences.join(", "))?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::{AnalysisOptions, analyze};
+
+	#[test]
+	fn test_explain() {
+		let src = "BASE=glibc\nPKGDEP=\"$BASE libfoo\"\n\
+			PKGDEP__RISCV=\"$BASE\"\n";
+		let analysis = analyze(src, &AnalysisOptions::default()).unwrap();
+		let explanation = explain(&analysis, "PKGDEP", Some("RISCV"));
+		assert_eq!(explanation.arch.as_deref(), Some("riscv"));
+		assert_eq!(explanation.variable.as_deref(), Some("PKGDEP__RISCV"));
+		assert_eq!(explanation.value, Some("glibc".into()));
+		assert_eq!(
+			explanation.candidate_positions,
+			vec![
+				Some(LineCol { line: 3, column: 1 }),
+				Some(LineCol { line: 2, column: 1 })
+			]
+		);
+		assert_eq!(explanation.steps.len(), 1);
+		assert_eq!(
+			explanation.steps[0].references,
+			vec![Reference {
+				name: "BASE".to_string(),
+				value: Some("glibc".into()),
+				defined_at: Some(LineCol { line: 1, column: 1 }),
+			}]
+		);
+		assert_eq!(
+			explanation.to_string(),
+			"PKGDEP on riscv is 'glibc'\n\
+			\x20 PKGDEP__RISCV (line 3) applies, as overrides for the \
+			architecture take precedence.\n\
+			\x20   PKGDEP (line 2) is overridden.\n\
+			\x20 line 3: PKGDEP__RISCV=\"$BASE\"\n\
+			\x20   sets 'glibc'\n\
+			\x20   expands BASE = 'glibc' (line 1)\n\
+			\x20   is the final value\n\
+			\x20 influenced by BASE (line 1)\n"
+		);
+
+		let explanation = explain(&analysis, "PKGDEP", Some("amd64"));
+		assert_eq!(explanation.variable.as_deref(), Some("PKGDEP"));
+		assert_eq!(explanation.value, Some("glibc libfoo".into()));
+
+		let explanation = explain(&analysis, "OTHER", Some("amd64"));
+		assert_eq!(explanation.variable, None);
+		assert!(explanation.steps.is_empty());
+		assert_eq!(explanation.to_string(), "OTHER on amd64 is not defined\n");
+		let explanation = explain(&analysis, "OTHER", None);
+		assert_eq!(explanation.value, None);
+		assert_eq!(explanation.to_string(), "OTHER is not defined\n");
+	}
+
+	#[test]
+	fn test_explain_steps() {
+		let src = "PKGDEP=\"$BASE libfoo\"\nBASE=glibc\nPKGDEP+=\" $BASE\"\n\
+			PKGDEP=\"$PKGDEP x\"\nPKGDEP=y\nPKGDEP=z\n";
+		let analysis = analyze(src, &AnalysisOptions::default()).unwrap();
+		let explanation = explain(&analysis, "PKGDEP", None);
+		assert_eq!(explanation.variable.as_deref(), Some("PKGDEP"));
+		assert_eq!(
+			explanation
+				.steps
+				.iter()
+				.map(|step| (
+					step.position.line,
+					step.append,
+					step.value.as_string(),
+					step.fate
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				(
+					1,
+					false,
+					" libfoo".to_string(),
+					StepFate::Appended {
+						by: LineCol { line: 3, column: 1 }
+					}
+				),
+				(
+					3,
+					true,
+					" libfoo glibc".to_string(),
+					StepFate::Expanded {
+						by: LineCol { line: 4, column: 1 }
+					}
+				),
+				(
+					4,
+					false,
+					" libfoo glibc x".to_string(),
+					StepFate::Replaced {
+						by: LineCol { line: 5, column: 1 }
+					}
+				),
+				(
+					5,
+					false,
+					"y".to_string(),
+					StepFate::Replaced {
+						by: LineCol { line: 6, column: 1 }
+					}
+				),
+				(6, false, "z".to_string(), StepFate::Final),
+			]
+		);
+		// BASE is undefined on line 1, and the implicit reference of
+		// appending is left out
+		assert_eq!(explanation.steps[0].references[0].value, None);
+		assert_eq!(
+			explanation.steps[1]
+				.references
+				.iter()
+				.map(|reference| reference.name.as_str())
+				.collect::<Vec<_>>(),
+			vec!["BASE"]
+		);
+		assert_eq!(explanation.steps[2].references[0].name, "PKGDEP");
+		assert!(explanation.influences.is_empty());
+		let rendered = explanation.to_string();
+		assert!(rendered.contains(
+			"  line 1: PKGDEP=\"$BASE libfoo\"\n\
+			\x20   sets ' libfoo'\n\
+			\x20   expands BASE, which is undefined\n\
+			\x20   is kept, and appended to on line 3\n"
+		));
+		assert!(rendered.contains("    appends, making it ' libfoo glibc'\n"));
+		assert!(rendered.contains("    is kept, and expanded on line 4\n"));
+		assert!(rendered.contains("    is replaced on line 5\n"));
+
+		let explanation = explain(&analysis, "PKGDEP", Some("amd64"));
+		assert_eq!(explanation.steps, explain(&analysis, "PKGDEP", None).steps);
+	}
+}
@@ -0,0 +1,131 @@
+//! Histories of values across revisions of a spec.
+//!
+//! See [`field_timeline`].
+
+use super::{
+	ApmlContext, VariableValue,
+	cache::{MemoryCache, ParseCache, parse_cached},
+};
+
+/// Value of a field in a revision, as recorded by [`field_timeline`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FieldValue {
+	/// The field is defined with a value.
+	Defined(VariableValue),
+	/// The field is not defined.
+	Undefined,
+	/// The revision fails to parse or evaluate, so the value is unknown.
+	Gap,
+}
+
+impl FieldValue {
+	/// Returns the value if the field is defined.
+	pub fn value(&self) -> Option<&VariableValue> {
+		match self {
+			FieldValue::Defined(value) => Some(value),
+			_ => None,
+		}
+	}
+
+	/// Returns if the revision fails to parse or evaluate.
+	pub fn is_gap(&self) -> bool {
+		matches!(self, FieldValue::Gap)
+	}
+}
+
+/// Computes the evolution of a field across revisions of a spec.
+///
+/// Revisions are given in order, each tagged by the caller, such as with
+/// a commit ID. Each revision is parsed and evaluated, and consecutive
+/// revisions with the same value are collapsed into the first of them,
+/// so each entry of the result marks a change.
+///
+/// Revisions failing to parse or evaluate are recorded as
+/// [`FieldValue::Gap`] instead of aborting. As the value is unknown
+/// during a gap, the value after it is always recorded, even if it is
+/// the same as the one before.
+///
+/// Revisions with the same source are parsed once, see
+/// [`field_timeline_cached`] for sharing a cache across calls.
+pub fn field_timeline<T: Clone>(
+	sources: &[(T, &str)],
+	field: &str,
+) -> Vec<(T, FieldValue)> {
+	field_timeline_cached(sources, field, &MemoryCache::new())
+}
+
+/// Computes the evolution of a field across revisions of a spec, parsing
+/// with a cache.
+///
+/// See [`field_timeline`].
+pub fn field_timeline_cached<T: Clone>(
+	sources: &[(T, &str)],
+	field: &str,
+	cache: &dyn ParseCache,
+) -> Vec<(T, FieldValue)> {
+	let mut timeline: Vec<(T, FieldValue)> = Vec::new();
+	for (tag, src) in sources {
+		let value = match parse_cached(src, cache)
+			.map_err(From::from)
+			.and_then(|lst| ApmlContext::eval_lst(&lst))
+		{
+			Ok(context) => match context.get(field) {
+				Some(value) => FieldValue::Defined(value.clone()),
+				None => FieldValue::Undefined,
+			},
+			Err(_) => FieldValue::Gap,
+		};
+		if timeline.last().is_none_or(|(_, last)| *last != value) {
+			timeline.push((tag.clone(), value));
+		}
+	}
+	timeline
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_field_timeline() {
+		let sources = [
+			("a", "VER=8.0\n"),
+			("b", "VER=8.0\nREL=1\n"),
+			("c", "VER=8.1\n"),
+			("d", "VER=\"8.2\n"),
+			("e", "VER=${A:?}\n"),
+			("f", "VER=8.2\n"),
+			("g", "VER=8.2\n"),
+			("h", "PKGVER=8.2\n"),
+		];
+		let cache = MemoryCache::new();
+		let timeline = field_timeline_cached(&sources, "VER", &cache);
+		assert_eq!(
+			timeline,
+			vec![
+				("a", FieldValue::Defined("8.0".into())),
+				("c", FieldValue::Defined("8.1".into())),
+				("d", FieldValue::Gap),
+				("f", FieldValue::Defined("8.2".into())),
+				("h", FieldValue::Undefined),
+			]
+		);
+		assert_eq!(cache.len(), 6);
+		assert!(timeline[2].1.is_gap());
+		assert_eq!(timeline[3].1.value(), Some(&"8.2".into()));
+		assert_eq!(timeline[4].1.value(), None);
+
+		let timeline = field_timeline(
+			&[(1, "VER=1\n"), (2, "VER=\"\n"), (3, "VER=1\n")],
+			"VER",
+		);
+		assert_eq!(
+			timeline,
+			vec![
+				(1, FieldValue::Defined("1".into())),
+				(2, FieldValue::Gap),
+				(3, FieldValue::Defined("1".into())),
+			]
+		);
+	}
+}
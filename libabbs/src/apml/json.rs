@@ -0,0 +1,848 @@
+//! Stable JSON representation of the [LST][super::lst].
+//!
+//! See [`ApmlLst::to_json`] and [`ApmlLst::from_json`]. Unlike the
+//! [`Debug`] representation, which may change between versions, the
+//! schema described here is stable.
+//! Any change to it increases [`SCHEMA_VERSION`]. Documents of older
+//! versions are still loaded, as long as they are valid in the current
+//! schema.
+//!
+//! # Schema
+//!
+//! The root object has the following keys:
+//!
+//! - `schema`: the schema version, currently `7`.
+//! - `capabilities`: the [capabilities][super::capabilities] of the build
+//!   writing the document, as returned by [`Capabilities::to_json`]. It is
+//!   not read when loading documents.
+//! - `kind`: always `"file"`.
+//! - `span`: `[start, end]` byte offsets of the whole source.
+//! - `children`: list of token nodes.
+//!
+//! Every other node is an object with at least a `kind` and a `span`.
+//! Nodes with child nodes carry them in `children`. The kinds are:
+//!
+//! | Kind                | Other keys                   | Children       |
+//! |---------------------|------------------------------|----------------|
+//! | `space`             | `char`                       |                |
+//! | `newline`           |                              |                |
+//! | `comment`           | `text` (without `#`)         |                |
+//! | `definition`        | `name`, `op` (`=` or `+=`)   | one value      |
+//! | `set`               | `args`                       |                |
+//! | `string`            |                              | text units     |
+//! | `array`             |                              | array tokens   |
+//! | `element`           |                              | text units     |
+//! | `unquoted`          |                              | words          |
+//! | `single_quoted`     | `text` (without quotes)      |                |
+//! | `double_quoted`     |                              | words          |
+//! | `ansi_c_quoted`     | `text` (without quotes)      |                |
+//! | `locale_quoted`     |                              | words          |
+//! | `literal`           |                              | literal parts  |
+//! | `variable`          | `name`                       |                |
+//! | `braced_variable`   | `name`, `modifier`           |                |
+//! | `subcommand`        |                              | array tokens   |
+//! | `custom`            | `name`, `args`               |                |
+//! | `text`              | `text`                       |                |
+//! | `escaped`           | `char`                       |                |
+//! | `line_continuation` |                              |                |
+//!
+//! Token nodes are `space`, `newline`, `line_continuation`, `comment`,
+//! `definition` and `set` nodes. Array tokens are `space`, `newline`,
+//! `line_continuation`, `comment` and `element` nodes.
+//! The `args` of a `set` statement and of a custom expansion are
+//! `[space, argument]` pairs, where `space` is the whitespaces before the
+//! argument.
+//! The `modifier` of a braced variable is `null` or an object with
+//! a `kind` (see [`modifier_kind`]) and its `source` text.
+//!
+//! Keys of objects are sorted and the output is pretty-printed with
+//! two-space indentation.
+//!
+//! [`Capabilities::to_json`]: super::Capabilities::to_json
+
+use std::{borrow::Cow, sync::Arc};
+
+use serde_json::{Map, Value, json};
+use thiserror::Error;
+
+use super::{
+	capabilities::capabilities,
+	lst::{
+		ApmlLst, ArrayToken, CustomExpansion, ExpansionModifier, LiteralPart,
+		SetCommand, Text, TextUnit, Token, VariableDefinition, VariableOp,
+		VariableValue, Word,
+	},
+	parser::{ParseOptions, apml_word},
+	span::display_len,
+};
+
+/// Errors produced while loading a LST from JSON.
+#[derive(Debug, Error)]
+pub enum JsonError {
+	#[error(transparent)]
+	Json(#[from] serde_json::Error),
+	#[error("Unsupported schema version: {0}")]
+	UnsupportedSchema(u64),
+	#[error("Invalid LST: {0}")]
+	Invalid(String),
+}
+
+/// Version of the JSON schema.
+///
+/// Version 2 adds `ansi_c_quoted` nodes, version 3 adds `set` nodes,
+/// version 4 adds `custom` nodes, version 5 adds `locale_quoted` nodes,
+/// version 6 allows `line_continuation` nodes between tokens and array
+/// tokens, and version 7 adds `capabilities` to the root.
+pub const SCHEMA_VERSION: u32 = 7;
+
+impl ApmlLst<'_> {
+	/// Dumps the LST into pretty-printed JSON.
+	///
+	/// See [the module documentation][self] for the schema.
+	pub fn to_json(&self) -> String {
+		serde_json::to_string_pretty(&self.to_json_value())
+			.expect("serializing JSON value never fails")
+	}
+
+	/// Dumps the LST into a JSON value, as [`ApmlLst::to_json`] does.
+	pub(crate) fn to_json_value(&self) -> Value {
+		let mut pos = 0;
+		let children = self
+			.0
+			.iter()
+			.map(|token| token_node(token, &mut pos))
+			.collect::<Vec<_>>();
+		json!({
+			"schema": SCHEMA_VERSION,
+			"capabilities": capabilities().to_json(),
+			"kind": "file",
+			"span": [0, pos],
+			"children": children,
+		})
+	}
+
+	/// Loads a LST from JSON produced by [`ApmlLst::to_json`].
+	///
+	/// Spans are not read, as they are implied by the nodes. Modifiers
+	/// of braced variables are parsed again from their `source`.
+	pub fn from_json(src: &str) -> Result<ApmlLst<'static>, JsonError> {
+		let root = serde_json::from_str::<Value>(src)?;
+		let schema = root
+			.get("schema")
+			.and_then(Value::as_u64)
+			.ok_or_else(|| invalid("missing schema"))?;
+		if !(1..=SCHEMA_VERSION as u64).contains(&schema) {
+			return Err(JsonError::UnsupportedSchema(schema));
+		}
+		if kind_of(&root)? != "file" {
+			return Err(invalid("root is not a file"));
+		}
+		let tokens = children_of(&root)?
+			.iter()
+			.map(token_from_node)
+			.collect::<Result<_, _>>()?;
+		Ok(ApmlLst(tokens))
+	}
+}
+
+/// Returns the `kind` name of an expansion modifier.
+pub fn modifier_kind(modifier: &ExpansionModifier) -> &'static str {
+	match modifier {
+		ExpansionModifier::Substring { .. } => "substring",
+		ExpansionModifier::StripShortestPrefix(_) => "strip_shortest_prefix",
+		ExpansionModifier::StripLongestPrefix(_) => "strip_longest_prefix",
+		ExpansionModifier::StripShortestSuffix(_) => "strip_shortest_suffix",
+		ExpansionModifier::StripLongestSuffix(_) => "strip_longest_suffix",
+		ExpansionModifier::ReplaceOnce { .. } => "replace_once",
+		ExpansionModifier::ReplaceAll { .. } => "replace_all",
+		ExpansionModifier::ReplacePrefix { .. } => "replace_prefix",
+		ExpansionModifier::ReplaceSuffix { .. } => "replace_suffix",
+		ExpansionModifier::UpperOnce(_) => "upper_once",
+		ExpansionModifier::UpperAll(_) => "upper_all",
+		ExpansionModifier::LowerOnce(_) => "lower_once",
+		ExpansionModifier::LowerAll(_) => "lower_all",
+		ExpansionModifier::ErrorOnUnset(_) => "error_on_unset",
+		ExpansionModifier::Length => "length",
+		ExpansionModifier::WhenUnset(_) => "when_unset",
+		ExpansionModifier::WhenSet(_) => "when_set",
+		ExpansionModifier::ArrayElements => "array_elements",
+		ExpansionModifier::SingleWordElements => "single_word_elements",
+	}
+}
+
+/// Creates a node of a LST node starting at `pos`, advancing `pos`
+/// to the end of the node.
+fn node<T: std::fmt::Display + ?Sized>(
+	kind: &str,
+	lst: &T,
+	pos: &mut usize,
+	extra: impl FnOnce(&mut Map<String, Value>, usize),
+) -> Value {
+	let start = *pos;
+	let end = start + display_len(lst);
+	let mut map = Map::new();
+	map.insert("kind".to_string(), kind.into());
+	map.insert("span".to_string(), json!([start, end]));
+	extra(&mut map, start);
+	*pos = end;
+	Value::Object(map)
+}
+
+fn children(map: &mut Map<String, Value>, children: Vec<Value>) {
+	map.insert("children".to_string(), Value::Array(children));
+}
+
+fn token_node(token: &Token, pos: &mut usize) -> Value {
+	match token {
+		Token::Spacy(ch) => node("space", token, pos, |map, _| {
+			map.insert("char".to_string(), ch.to_string().into());
+		}),
+		Token::Newline => node("newline", token, pos, |_, _| {}),
+		Token::LineContinuation => {
+			node("line_continuation", token, pos, |_, _| {})
+		}
+		Token::Comment(text) => node("comment", token, pos, |map, _| {
+			map.insert("text".to_string(), text.as_ref().into());
+		}),
+		Token::Variable(def) => node("definition", token, pos, |map, start| {
+			map.insert("name".to_string(), def.name.as_ref().into());
+			let op = match def.op {
+				VariableOp::Assignment => "=",
+				VariableOp::Append => "+=",
+			};
+			map.insert("op".to_string(), op.into());
+			let mut pos = start + def.name.len() + op.len();
+			children(map, vec![value_node(&def.value, &mut pos)]);
+		}),
+		Token::Set(set) => node("set", token, pos, |map, _| {
+			map.insert("args".to_string(), json!(set.args));
+		}),
+	}
+}
+
+fn value_node(value: &VariableValue, pos: &mut usize) -> Value {
+	match value {
+		VariableValue::String(text) => {
+			node("string", value, pos, |map, start| {
+				children(map, text_nodes(text, start));
+			})
+		}
+		VariableValue::Array(tokens) => {
+			node("array", value, pos, |map, start| {
+				children(map, array_token_nodes(tokens, start + 1));
+			})
+		}
+	}
+}
+
+fn array_token_nodes(tokens: &[ArrayToken], mut pos: usize) -> Vec<Value> {
+	tokens
+		.iter()
+		.map(|token| match token {
+			ArrayToken::Spacy(ch) => {
+				node("space", token, &mut pos, |map, _| {
+					map.insert("char".to_string(), ch.to_string().into());
+				})
+			}
+			ArrayToken::Newline => node("newline", token, &mut pos, |_, _| {}),
+			ArrayToken::LineContinuation => {
+				node("line_continuation", token, &mut pos, |_, _| {})
+			}
+			ArrayToken::Comment(text) => {
+				node("comment", token, &mut pos, |map, _| {
+					map.insert("text".to_string(), text.as_ref().into());
+				})
+			}
+			ArrayToken::Element(text) => {
+				node("element", token, &mut pos, |map, start| {
+					children(map, text_nodes(text, start));
+				})
+			}
+		})
+		.collect()
+}
+
+fn text_nodes(text: &Text, mut pos: usize) -> Vec<Value> {
+	text.0
+		.iter()
+		.map(|unit| match unit {
+			TextUnit::Unquoted(words) => {
+				node("unquoted", unit, &mut pos, |map, start| {
+					children(map, word_nodes(words, start));
+				})
+			}
+			TextUnit::SingleQuote(text) => {
+				node("single_quoted", unit, &mut pos, |map, _| {
+					map.insert("text".to_string(), text.as_ref().into());
+				})
+			}
+			TextUnit::DoubleQuote(words) => {
+				node("double_quoted", unit, &mut pos, |map, start| {
+					children(map, word_nodes(words, start + 1));
+				})
+			}
+			TextUnit::AnsiCQuote(text) => {
+				node("ansi_c_quoted", unit, &mut pos, |map, _| {
+					map.insert("text".to_string(), text.as_ref().into());
+				})
+			}
+			TextUnit::LocaleQuote(words) => {
+				node("locale_quoted", unit, &mut pos, |map, start| {
+					children(map, word_nodes(words, start + 2));
+				})
+			}
+		})
+		.collect()
+}
+
+fn word_nodes(words: &[Word], mut pos: usize) -> Vec<Value> {
+	words
+		.iter()
+		.map(|word| match word {
+			Word::Literal(parts) => {
+				node("literal", word, &mut pos, |map, start| {
+					children(map, literal_part_nodes(parts, start));
+				})
+			}
+			Word::UnbracedVariable(name) => {
+				node("variable", word, &mut pos, |map, _| {
+					map.insert("name".to_string(), name.as_ref().into());
+				})
+			}
+			Word::BracedVariable(exp) => {
+				node("braced_variable", word, &mut pos, |map, _| {
+					map.insert("name".to_string(), exp.name.as_ref().into());
+					let modifier = match &exp.modifier {
+						Some(modifier) => json!({
+							"kind": modifier_kind(modifier),
+							"source": modifier.to_string(),
+						}),
+						None => Value::Null,
+					};
+					map.insert("modifier".to_string(), modifier);
+				})
+			}
+			Word::Subcommand(tokens) => {
+				node("subcommand", word, &mut pos, |map, start| {
+					children(map, array_token_nodes(tokens, start + 2));
+				})
+			}
+			Word::Custom(exp) => node("custom", word, &mut pos, |map, _| {
+				map.insert("name".to_string(), exp.name.as_ref().into());
+				map.insert("args".to_string(), json!(exp.args));
+			}),
+		})
+		.collect()
+}
+
+fn literal_part_nodes(parts: &[LiteralPart], mut pos: usize) -> Vec<Value> {
+	parts
+		.iter()
+		.map(|part| match part {
+			LiteralPart::String(text) => {
+				node("text", part, &mut pos, |map, _| {
+					map.insert("text".to_string(), text.as_ref().into());
+				})
+			}
+			LiteralPart::Escaped(ch) => {
+				node("escaped", part, &mut pos, |map, _| {
+					map.insert("char".to_string(), ch.to_string().into());
+				})
+			}
+			LiteralPart::LineContinuation => {
+				node("line_continuation", part, &mut pos, |_, _| {})
+			}
+		})
+		.collect()
+}
+
+fn invalid(message: &str) -> JsonError {
+	JsonError::Invalid(message.to_string())
+}
+
+fn kind_of(node: &Value) -> Result<&str, JsonError> {
+	node.get("kind")
+		.and_then(Value::as_str)
+		.ok_or_else(|| invalid("missing kind"))
+}
+
+fn string_of<'a>(node: &'a Value, key: &str) -> Result<&'a str, JsonError> {
+	node.get(key)
+		.and_then(Value::as_str)
+		.ok_or_else(|| invalid(&format!("missing {}", key)))
+}
+
+fn owned_string_of(
+	node: &Value,
+	key: &str,
+) -> Result<Cow<'static, str>, JsonError> {
+	Ok(Cow::Owned(string_of(node, key)?.to_string()))
+}
+
+fn char_of(node: &Value) -> Result<char, JsonError> {
+	let mut chars = string_of(node, "char")?.chars();
+	match (chars.next(), chars.next()) {
+		(Some(ch), None) => Ok(ch),
+		_ => Err(invalid("char is not a single character")),
+	}
+}
+
+fn children_of(node: &Value) -> Result<&Vec<Value>, JsonError> {
+	node.get("children")
+		.and_then(Value::as_array)
+		.ok_or_else(|| invalid("missing children"))
+}
+
+fn unknown(node: &Value) -> JsonError {
+	invalid(&format!(
+		"unexpected node kind {}",
+		kind_of(node).unwrap_or("")
+	))
+}
+
+fn token_from_node(node: &Value) -> Result<Token<'static>, JsonError> {
+	match kind_of(node)? {
+		"space" => Ok(Token::Spacy(char_of(node)?)),
+		"newline" => Ok(Token::Newline),
+		"line_continuation" => Ok(Token::LineContinuation),
+		"comment" => Ok(Token::Comment(owned_string_of(node, "text")?)),
+		"definition" => {
+			let op = match string_of(node, "op")? {
+				"=" => VariableOp::Assignment,
+				"+=" => VariableOp::Append,
+				op => return Err(invalid(&format!("unknown op {}", op))),
+			};
+			let [value] = children_of(node)?.as_slice() else {
+				return Err(invalid("definition has not exactly one value"));
+			};
+			Ok(Token::Variable(VariableDefinition {
+				name: owned_string_of(node, "name")?,
+				op,
+				value: value_from_node(value)?,
+			}))
+		}
+		"set" => Ok(Token::Set(SetCommand {
+			args: args_of(node)?,
+		})),
+		_ => Err(unknown(node)),
+	}
+}
+
+fn value_from_node(node: &Value) -> Result<VariableValue<'static>, JsonError> {
+	match kind_of(node)? {
+		"string" => Ok(VariableValue::String(Arc::new(text_from_node(node)?))),
+		"array" => Ok(VariableValue::Array(array_tokens_from_node(node)?)),
+		_ => Err(unknown(node)),
+	}
+}
+
+fn array_tokens_from_node(
+	node: &Value,
+) -> Result<Vec<ArrayToken<'static>>, JsonError> {
+	children_of(node)?
+		.iter()
+		.map(|node| match kind_of(node)? {
+			"space" => Ok(ArrayToken::Spacy(char_of(node)?)),
+			"newline" => Ok(ArrayToken::Newline),
+			"line_continuation" => Ok(ArrayToken::LineContinuation),
+			"comment" => {
+				Ok(ArrayToken::Comment(owned_string_of(node, "text")?))
+			}
+			"element" => {
+				Ok(ArrayToken::Element(Arc::new(text_from_node(node)?)))
+			}
+			_ => Err(unknown(node)),
+		})
+		.collect()
+}
+
+fn text_from_node(node: &Value) -> Result<Text<'static>, JsonError> {
+	let units = children_of(node)?
+		.iter()
+		.map(|node| match kind_of(node)? {
+			"unquoted" => Ok(TextUnit::Unquoted(words_from_node(node)?)),
+			"single_quoted" => {
+				Ok(TextUnit::SingleQuote(owned_string_of(node, "text")?))
+			}
+			"double_quoted" => {
+				Ok(TextUnit::DoubleQuote(words_from_node(node)?))
+			}
+			"ansi_c_quoted" => {
+				Ok(TextUnit::AnsiCQuote(owned_string_of(node, "text")?))
+			}
+			"locale_quoted" => {
+				Ok(TextUnit::LocaleQuote(words_from_node(node)?))
+			}
+			_ => Err(unknown(node)),
+		})
+		.collect::<Result<_, _>>()?;
+	Ok(Text(units))
+}
+
+fn words_from_node(node: &Value) -> Result<Vec<Word<'static>>, JsonError> {
+	children_of(node)?
+		.iter()
+		.map(|node| match kind_of(node)? {
+			"literal" => Ok(Word::Literal(
+				children_of(node)?
+					.iter()
+					.map(literal_part_from_node)
+					.collect::<Result<_, _>>()?,
+			)),
+			"variable" => {
+				Ok(Word::UnbracedVariable(owned_string_of(node, "name")?))
+			}
+			"braced_variable" => braced_variable_from_node(node),
+			"subcommand" => Ok(Word::Subcommand(array_tokens_from_node(node)?)),
+			"custom" => Ok(Word::Custom(CustomExpansion {
+				name: owned_string_of(node, "name")?,
+				args: args_of(node)?,
+			})),
+			_ => Err(unknown(node)),
+		})
+		.collect()
+}
+
+/// `[space, argument]` pairs of a `set` statement or a custom expansion.
+type Args = Vec<(Cow<'static, str>, Cow<'static, str>)>;
+
+/// Reads the `[space, argument]` pairs in `args` of a node.
+fn args_of(node: &Value) -> Result<Args, JsonError> {
+	node.get("args")
+		.and_then(Value::as_array)
+		.ok_or_else(|| invalid("missing args"))?
+		.iter()
+		.map(|arg| match arg.as_array().map(Vec::as_slice) {
+			Some([Value::String(space), Value::String(arg)]) => {
+				Ok((Cow::Owned(space.clone()), Cow::Owned(arg.clone())))
+			}
+			_ => Err(invalid("argument is not a pair of strings")),
+		})
+		.collect()
+}
+
+fn braced_variable_from_node(node: &Value) -> Result<Word<'static>, JsonError> {
+	let name = string_of(node, "name")?;
+	let src = match node.get("modifier") {
+		None | Some(Value::Null) => format!("${{{}}}", name),
+		Some(modifier) => match string_of(modifier, "kind")? {
+			"length" => format!("${{#{}}}", name),
+			_ => format!("${{{}{}}}", name, string_of(modifier, "source")?),
+		},
+	};
+	let invalid_modifier = || invalid(&format!("invalid modifier in {}", src));
+	let word = match apml_word(&src, ParseOptions::default()) {
+		Ok(("", Text(mut units))) if units.len() == 1 => match units.pop() {
+			Some(TextUnit::Unquoted(mut words)) if words.len() == 1 => {
+				words.pop()
+			}
+			_ => None,
+		},
+		_ => None,
+	};
+	let Some(Word::BracedVariable(exp)) = word else {
+		return Err(invalid_modifier());
+	};
+	let kind = exp.modifier.as_ref().map(modifier_kind);
+	let expected = match node.get("modifier") {
+		None | Some(Value::Null) => None,
+		Some(modifier) => Some(string_of(modifier, "kind")?),
+	};
+	if exp.name != name || kind != expected {
+		return Err(invalid_modifier());
+	}
+	Ok(Word::BracedVariable(exp.into_owned()))
+}
+
+fn literal_part_from_node(
+	node: &Value,
+) -> Result<LiteralPart<'static>, JsonError> {
+	match kind_of(node)? {
+		"text" => Ok(LiteralPart::String(owned_string_of(node, "text")?)),
+		"escaped" => Ok(LiteralPart::Escaped(char_of(node)?)),
+		"line_continuation" => Ok(LiteralPart::LineContinuation),
+		_ => Err(unknown(node)),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Golden output of the schema, without `capabilities`.
+	///
+	/// Update [`SCHEMA_VERSION`] when changing this.
+	const GOLDEN: &str = r#"{
+  "children": [
+    {
+      "kind": "comment",
+      "span": [
+        0,
+        3
+      ],
+      "text": " c"
+    },
+    {
+      "kind": "newline",
+      "span": [
+        3,
+        4
+      ]
+    },
+    {
+      "children": [
+        {
+          "children": [
+            {
+              "children": [
+                {
+                  "children": [
+                    {
+                      "kind": "text",
+                      "span": [
+                        7,
+                        8
+                      ],
+                      "text": "a"
+                    },
+                    {
+                      "char": "$",
+                      "kind": "escaped",
+                      "span": [
+                        8,
+                        10
+                      ]
+                    }
+                  ],
+                  "kind": "literal",
+                  "span": [
+                    7,
+                    10
+                  ]
+                },
+                {
+                  "kind": "variable",
+                  "name": "B",
+                  "span": [
+                    10,
+                    12
+                  ]
+                }
+              ],
+              "kind": "unquoted",
+              "span": [
+                7,
+                12
+              ]
+            },
+            {
+              "children": [
+                {
+                  "kind": "braced_variable",
+                  "modifier": {
+                    "kind": "when_unset",
+                    "source": ":-x"
+                  },
+                  "name": "C",
+                  "span": [
+                    13,
+                    20
+                  ]
+                }
+              ],
+              "kind": "double_quoted",
+              "span": [
+                12,
+                21
+              ]
+            }
+          ],
+          "kind": "string",
+          "span": [
+            7,
+            21
+          ]
+        }
+      ],
+      "kind": "definition",
+      "name": "A",
+      "op": "+=",
+      "span": [
+        4,
+        21
+      ]
+    },
+    {
+      "char": " ",
+      "kind": "space",
+      "span": [
+        21,
+        22
+      ]
+    },
+    {
+      "children": [
+        {
+          "children": [
+            {
+              "children": [
+                {
+                  "kind": "single_quoted",
+                  "span": [
+                    25,
+                    28
+                  ],
+                  "text": "x"
+                }
+              ],
+              "kind": "element",
+              "span": [
+                25,
+                28
+              ]
+            },
+            {
+              "kind": "newline",
+              "span": [
+                28,
+                29
+              ]
+            },
+            {
+              "children": [
+                {
+                  "children": [
+                    {
+                      "children": [
+                        {
+                          "children": [
+                            {
+                              "children": [
+                                {
+                                  "children": [
+                                    {
+                                      "kind": "text",
+                                      "span": [
+                                        31,
+                                        33
+                                      ],
+                                      "text": "ls"
+                                    }
+                                  ],
+                                  "kind": "literal",
+                                  "span": [
+                                    31,
+                                    33
+                                  ]
+                                }
+                              ],
+                              "kind": "unquoted",
+                              "span": [
+                                31,
+                                33
+                              ]
+                            }
+                          ],
+                          "kind": "element",
+                          "span": [
+                            31,
+                            33
+                          ]
+                        }
+                      ],
+                      "kind": "subcommand",
+                      "span": [
+                        29,
+                        34
+                      ]
+                    }
+                  ],
+                  "kind": "unquoted",
+                  "span": [
+                    29,
+                    34
+                  ]
+                }
+              ],
+              "kind": "element",
+              "span": [
+                29,
+                34
+              ]
+            }
+          ],
+          "kind": "array",
+          "span": [
+            24,
+            35
+          ]
+        }
+      ],
+      "kind": "definition",
+      "name": "D",
+      "op": "=",
+      "span": [
+        22,
+        35
+      ]
+    }
+  ],
+  "kind": "file",
+  "schema": 7,
+  "span": [
+    0,
+    35
+  ]
+}"#;
+
+	/// Dumps a LST, checking and removing the capabilities.
+	fn dump(lst: &ApmlLst) -> String {
+		let mut root = serde_json::from_str::<Value>(&lst.to_json()).unwrap();
+		let root_map = root.as_object_mut().unwrap();
+		assert_eq!(
+			root_map.remove("capabilities"),
+			Some(capabilities().to_json())
+		);
+		serde_json::to_string_pretty(&root).unwrap()
+	}
+
+	#[test]
+	fn test_to_json() {
+		let src = "# c\nA+=a\\$$B\"${C:-x}\" D=('x'\n$(ls))";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(dump(&lst), GOLDEN);
+	}
+
+	#[test]
+	fn test_from_json() {
+		let lst = ApmlLst::from_json(GOLDEN).unwrap();
+		assert_eq!(lst, ApmlLst::parse(&lst.to_string()).unwrap());
+		assert_eq!(dump(&lst), GOLDEN);
+
+		let src = "A=\\\n${B/#x*/$C}${#D}${E[@]}$'\\n'$\"$G\" # c\nF=()\n\
+			set -e\t+u\nB=b \\\n# c\nC=(c \\\n# c\nd)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(ApmlLst::from_json(&lst.to_json()).unwrap(), lst);
+		let lst = ApmlLst::parse_with_options(
+			"A=\"${@f B\tC}\"\n",
+			ParseOptions {
+				custom_expansions: true,
+			},
+		)
+		.unwrap();
+		assert_eq!(ApmlLst::from_json(&lst.to_json()).unwrap(), lst);
+
+		assert!(matches!(
+			ApmlLst::from_json(r#"{"schema": 8, "kind": "file"}"#),
+			Err(JsonError::UnsupportedSchema(8))
+		));
+		let old = GOLDEN.replace("\"schema\": 7", "\"schema\": 1");
+		assert_eq!(dump(&ApmlLst::from_json(&old).unwrap()), GOLDEN);
+		let broken = GOLDEN.replace("\"when_unset\"", "\"when_set\"");
+		assert!(matches!(
+			ApmlLst::from_json(&broken),
+			Err(JsonError::Invalid(_))
+		));
+		assert!(ApmlLst::from_json("{").is_err());
+	}
+}
@@ -0,0 +1,250 @@
+//! Frozen contexts and copy-on-write overlays.
+//!
+//! A context can be frozen with [`ApmlContext::freeze`], after which
+//! overlays are created cheaply with [`FrozenContext::overlay`]. An
+//! overlay only stores its own writes, while reads of other variables
+//! fall through to the frozen base. This is useful for what-if analysis,
+//! such as resolving a context for each architecture.
+//!
+//! Creating an overlay does not copy the base, so its cost does not grow
+//! with the size of the base; the `apml-overlay-bench` example compares
+//! it with cloning a context. Read-only consumers, such as
+//! [`resolve_arch`](super::arch::resolve_arch), take any [`ReadContext`].
+
+use std::{collections::HashSet, ops::Deref, sync::Arc};
+
+use indexmap::IndexMap;
+
+use super::{ApmlContext, ReadContext, VariableValue, eval::VariableResolver};
+
+impl ApmlContext {
+	/// Freezes the context, allowing cheap overlays and clones.
+	pub fn freeze(self) -> FrozenContext {
+		FrozenContext(Arc::new(self))
+	}
+}
+
+/// An immutable, shared [`ApmlContext`].
+///
+/// Cloning a frozen context only increments a reference count.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrozenContext(Arc<ApmlContext>);
+
+impl FrozenContext {
+	/// Creates an overlay over the context with initial writes.
+	pub fn overlay<I, S>(&self, writes: I) -> LayeredContext
+	where
+		I: IntoIterator<Item = (S, VariableValue)>,
+		S: Into<String>,
+	{
+		let mut overlay = LayeredContext {
+			base: self.clone(),
+			writes: IndexMap::new(),
+			removed: HashSet::new(),
+		};
+		for (name, value) in writes {
+			overlay.insert(name.into(), value);
+		}
+		overlay
+	}
+}
+
+impl Deref for FrozenContext {
+	type Target = ApmlContext;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl ReadContext for FrozenContext {
+	fn get(&self, name: &str) -> Option<&VariableValue> {
+		self.0.get(name)
+	}
+
+	fn keys(&self) -> impl Iterator<Item = &String> {
+		self.0.keys()
+	}
+}
+
+/// A copy-on-write overlay over a [`FrozenContext`].
+///
+/// Variables are kept in the order of their first definition, with
+/// variables of the base first. Metadata recorded during evaluation of
+/// the base, such as influences, is not carried to overlays.
+#[derive(Debug, Clone)]
+pub struct LayeredContext {
+	base: FrozenContext,
+	writes: IndexMap<String, VariableValue>,
+	/// Variables of the base removed in the overlay.
+	removed: HashSet<String>,
+}
+
+impl LayeredContext {
+	/// Returns the frozen base of the overlay.
+	pub fn base(&self) -> &FrozenContext {
+		&self.base
+	}
+
+	/// Iterates over variables written in the overlay.
+	pub fn writes(&self) -> impl Iterator<Item = (&String, &VariableValue)> {
+		self.writes.iter()
+	}
+
+	/// Inserts a variable.
+	pub fn insert(&mut self, name: String, value: VariableValue) {
+		self.removed.remove(&name);
+		self.writes.insert(name, value);
+	}
+
+	/// Removes a variable value.
+	pub fn remove(&mut self, name: &str) -> Option<VariableValue> {
+		let written = self.writes.shift_remove(name);
+		match self.base.get(name) {
+			Some(value) if self.removed.insert(name.to_string()) => {
+				written.or_else(|| Some(value.clone()))
+			}
+			_ => written,
+		}
+	}
+
+	/// Gets a mutable reference to a variable value, copying it from
+	/// the base on the first write.
+	pub fn get_mut(&mut self, name: &str) -> Option<&mut VariableValue> {
+		if !self.writes.contains_key(name) {
+			let value = ReadContext::get(self, name)?.clone();
+			self.writes.insert(name.to_string(), value);
+		}
+		self.writes.get_mut(name)
+	}
+
+	/// Merges the overlay into a new [`ApmlContext`].
+	pub fn to_context(&self) -> ApmlContext {
+		let mut context = ApmlContext::default();
+		for (name, value) in ReadContext::iter(self) {
+			context.insert(name.clone(), value.clone());
+		}
+		context
+	}
+}
+
+impl ReadContext for LayeredContext {
+	fn get(&self, name: &str) -> Option<&VariableValue> {
+		if let Some(value) = self.writes.get(name) {
+			Some(value)
+		} else if self.removed.contains(name) {
+			None
+		} else {
+			self.base.get(name)
+		}
+	}
+
+	fn keys(&self) -> impl Iterator<Item = &String> {
+		self.base
+			.keys()
+			.filter(|name| {
+				self.writes.contains_key(name.as_str())
+					|| !self.removed.contains(name.as_str())
+			})
+			.chain(
+				self.writes
+					.keys()
+					.filter(|name| !self.base.contains_var(name.as_str())),
+			)
+	}
+}
+
+impl VariableResolver for FrozenContext {
+	fn resolve(&self, name: &str) -> Option<VariableValue> {
+		self.0.get(name).cloned()
+	}
+}
+
+impl VariableResolver for LayeredContext {
+	fn resolve(&self, name: &str) -> Option<VariableValue> {
+		ReadContext::get(self, name).cloned()
+	}
+}
+
+impl PartialEq for LayeredContext {
+	fn eq(&self, other: &Self) -> bool {
+		ReadContext::iter(self).eq(ReadContext::iter(other))
+	}
+}
+
+impl Eq for LayeredContext {}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::{
+		arch::{ArchMap, resolve_arch},
+		build::AbHost,
+		completion::completions_in_source,
+		relations::Relations,
+		srcs::infer_template,
+	};
+
+	#[test]
+	fn test_overlay() {
+		let base = ApmlContext::eval_source(
+			"ARCH=amd64\nPKGDEP=a\nPKGDEP__LOONGARCH64=b\nFOO=c\n",
+		)
+		.unwrap()
+		.freeze();
+		let mut view = base.overlay([("ARCH", "loongarch64".into())]);
+		assert_eq!(view.read("ARCH"), "loongarch64");
+		assert_eq!(base.read("ARCH"), "amd64");
+		assert_eq!(view.read("PKGDEP"), "a");
+
+		view.insert("BAR".to_string(), "d".into());
+		assert_eq!(view.remove("FOO"), Some("c".into()));
+		assert_eq!(view.remove("FOO"), None);
+		*view.get_mut("PKGDEP").unwrap() += "x";
+		assert_eq!(
+			view.keys().collect::<Vec<_>>(),
+			vec!["ARCH", "PKGDEP", "PKGDEP__LOONGARCH64", "BAR"]
+		);
+		assert_eq!(view.writes().count(), 3);
+		assert_eq!(base.read("PKGDEP"), "a");
+		assert_eq!(base.read("FOO"), "c");
+
+		let resolved =
+			resolve_arch(&view, "loongarch64", &ArchMap::aosc()).unwrap();
+		assert_eq!(resolved.read("PKGDEP"), "b");
+		assert_eq!(
+			view.to_context(),
+			ApmlContext::eval_source(
+				"ARCH=loongarch64\nPKGDEP=ax\nPKGDEP__LOONGARCH64=b\nBAR=d\n"
+			)
+			.unwrap()
+		);
+		assert_eq!(view.clone(), view);
+		assert_eq!(view.resolve("ARCH"), Some("loongarch64".into()));
+		assert_eq!(view.resolve("FOO"), None);
+		assert_ne!(base.overlay::<_, String>([]), view);
+	}
+
+	#[test]
+	fn test_overlay_consumers() {
+		let base = ApmlContext::eval_source(
+			"PKGNAME=foo\nVER=1.0\nPKGDEP=\"bar baz>=2\"\nABHOST=noarch\n",
+		)
+		.unwrap()
+		.freeze();
+		let view = base.overlay([("PKGDEP", "qux".into())]);
+		let relations = Relations::from_context(&view).unwrap();
+		assert_eq!(relations.dependencies.len(), 1);
+		assert_eq!(relations.dependencies[0].target, "qux");
+		assert_eq!(AbHost::of(&view), Some(AbHost::Noarch));
+		assert_eq!(
+			infer_template("https://x/foo-1.0.tar.gz", &view).as_deref(),
+			Some("https://x/${PKGNAME}-${VER}.tar.gz")
+		);
+		let labels = completions_in_source("A=${PKG", &view, 7)
+			.into_iter()
+			.map(|completion| completion.label)
+			.collect::<Vec<_>>();
+		assert_eq!(labels, vec!["PKGDEP", "PKGNAME"]);
+	}
+}
@@ -0,0 +1,2737 @@
+//! Lints on APML sources.
+//!
+//! See [`check_values`], [`check_desc`], [`check_shapes`], [`check_relations`],
+//! [`check_splitting`], [`check_braces`], [`check_locale_quotes`],
+//! [`check_control_characters`], [`check_backticks`],
+//! [`check_trailing_newline`], [`check_noarch_overrides`],
+//! [`check_override_conflicts`], [`check_fail_arch`] and
+//! [`check_constraints`].
+//!
+//! Rules with machine-applicable fixes are gathered by [`diagnostics`],
+//! whose fixes are applied with [`apply_fixes`].
+
+use std::{
+	borrow::Cow, cell::RefCell, collections::HashMap, rc::Rc, sync::Arc,
+};
+
+use thiserror::Error;
+
+use super::{
+	ApmlContext, ApmlError, ReadContext, VariableValue,
+	arch::{ArchError, ArchMap, group_conflicts, resolve_arch},
+	build::AbHost,
+	eval::EvalOptions,
+	lst::{
+		self, ApmlLst, ArrayToken, BracedExpansion, ExpansionModifier,
+		LiteralPart, TextUnit, Word,
+	},
+	relations::{Dependency, RelationKind},
+	schema::{
+		DescPolicy, FieldConstraint, FieldSchema, FieldType, ValueChecks,
+	},
+	session::{Edit, EditError, EditSession},
+	span::{Span, display_len},
+};
+
+/// A value containing characters that the consumer of a field mangles.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ValueIssue {
+	/// Name of the variable.
+	pub name: String,
+	/// Kind of the issue.
+	pub kind: ValueIssueKind,
+	/// Span of the definition which introduced the issue.
+	pub span: Span,
+}
+
+impl ValueIssue {
+	/// Names the variable and what is wrong with its value.
+	pub fn explain(&self) -> String {
+		let problem = match self.kind {
+			ValueIssueKind::Newline => "contains a newline",
+			ValueIssueKind::Tab => "contains a tab",
+			ValueIssueKind::SurroundingWhitespace => {
+				"starts or ends with whitespaces"
+			}
+			ValueIssueKind::InvisibleControl => {
+				"contains a carriage return not followed by a newline, \
+				a vertical tab or a form feed"
+			}
+			ValueIssueKind::ControlCharacter => {
+				"contains control characters other than newlines and tabs"
+			}
+			ValueIssueKind::TrailingPeriod => "ends with a period",
+			ValueIssueKind::TooLong => "is too long",
+			ValueIssueKind::StartsWithName => {
+				"starts with the name of the package"
+			}
+		};
+		format!("{} {}.", self.name, problem)
+	}
+
+	/// Returns the fix suggested for the issue, if it can be fixed
+	/// automatically.
+	pub fn fix(&self) -> Option<ValueFix> {
+		match self.kind {
+			ValueIssueKind::Newline | ValueIssueKind::Tab => {
+				Some(ValueFix::CollapseWhitespace)
+			}
+			ValueIssueKind::SurroundingWhitespace => Some(ValueFix::Trim),
+			ValueIssueKind::TrailingPeriod => {
+				Some(ValueFix::DropTrailingPeriod)
+			}
+			ValueIssueKind::InvisibleControl
+			| ValueIssueKind::ControlCharacter
+			| ValueIssueKind::TooLong
+			| ValueIssueKind::StartsWithName => None,
+		}
+	}
+
+	/// Produces the edit of the suggested fix on an LST.
+	///
+	/// See [`ValueFix::edit`].
+	pub fn edit(&self, lst: &ApmlLst) -> Option<Edit> {
+		self.fix()?.edit(lst, self.span)
+	}
+}
+
+/// Kind of a [`ValueIssue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ValueIssueKind {
+	/// The value contains a newline.
+	Newline,
+	/// The value contains a tab.
+	Tab,
+	/// The value starts or ends with whitespaces.
+	SurroundingWhitespace,
+	/// The value contains a carriage return not followed by a newline,
+	/// a vertical tab or a form feed.
+	InvisibleControl,
+	/// The description contains control characters other than newlines
+	/// and tabs.
+	ControlCharacter,
+	/// The description ends with a period.
+	TrailingPeriod,
+	/// The description is longer than [`DescPolicy::max_len`].
+	TooLong,
+	/// The description starts with the name of the package.
+	StartsWithName,
+}
+
+impl ValueIssueKind {
+	/// Kinds checked on descriptions, see [`check_desc`].
+	const DESC: [Self; 4] = [
+		ValueIssueKind::ControlCharacter,
+		ValueIssueKind::TrailingPeriod,
+		ValueIssueKind::TooLong,
+		ValueIssueKind::StartsWithName,
+	];
+
+	/// Returns if a value has the issue.
+	///
+	/// `pkgname` is the name of the package, if known.
+	fn is_present(
+		&self,
+		value: &str,
+		checks: &ValueChecks,
+		pkgname: Option<&str>,
+	) -> bool {
+		match self {
+			ValueIssueKind::Newline => value.contains('\n'),
+			ValueIssueKind::Tab => value.contains('\t'),
+			ValueIssueKind::SurroundingWhitespace => {
+				value.starts_with(char::is_whitespace)
+					|| value.ends_with(char::is_whitespace)
+			}
+			ValueIssueKind::InvisibleControl => {
+				lst::invisible_controls(value).next().is_some()
+			}
+			ValueIssueKind::ControlCharacter => value
+				.chars()
+				.any(|ch| ch.is_control() && ch != '\n' && ch != '\t'),
+			ValueIssueKind::TrailingPeriod => {
+				let value = value.trim_end();
+				value.ends_with('.') && !value.ends_with("..")
+			}
+			ValueIssueKind::TooLong => checks
+				.desc
+				.is_some_and(|desc| value.chars().count() > desc.max_len),
+			ValueIssueKind::StartsWithName => pkgname.is_some_and(|pkgname| {
+				value.split_whitespace().next().is_some_and(|word| {
+					word.trim_end_matches([',', ':'])
+						.eq_ignore_ascii_case(pkgname)
+				})
+			}),
+		}
+	}
+
+	/// Returns kinds enabled by checks.
+	fn enabled(checks: &ValueChecks) -> impl Iterator<Item = Self> {
+		[
+			(checks.newline, ValueIssueKind::Newline),
+			(checks.tab, ValueIssueKind::Tab),
+			(
+				checks.surrounding_whitespace,
+				ValueIssueKind::SurroundingWhitespace,
+			),
+			(checks.invisible_control, ValueIssueKind::InvisibleControl),
+		]
+		.into_iter()
+		.filter_map(|(enabled, kind)| enabled.then_some(kind))
+		.chain(
+			checks
+				.desc
+				.is_some()
+				.then_some(ValueIssueKind::DESC)
+				.into_iter()
+				.flatten(),
+		)
+	}
+}
+
+/// Checks a package description against the rules of descriptions.
+///
+/// Returns the violated rules among [`ValueIssueKind::ControlCharacter`],
+/// [`ValueIssueKind::TrailingPeriod`], [`ValueIssueKind::TooLong`] and
+/// [`ValueIssueKind::StartsWithName`]. Whitespaces are checked by the
+/// other [`ValueChecks`] instead. `pkgname` is the name of the package,
+/// if known.
+pub fn check_desc(
+	desc: &str,
+	pkgname: Option<&str>,
+	policy: &DescPolicy,
+) -> Vec<ValueIssueKind> {
+	let checks = ValueChecks {
+		desc: Some(*policy),
+		..ValueChecks::NONE
+	};
+	ValueIssueKind::DESC
+		.into_iter()
+		.filter(|kind| kind.is_present(desc, &checks, pkgname))
+		.collect()
+}
+
+/// A fix for a [`ValueIssue`], applied on literal parts of definitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueFix {
+	/// Strips leading and trailing whitespaces.
+	Trim,
+	/// Replaces runs of whitespaces with single spaces.
+	CollapseWhitespace,
+	/// Removes a period ending the value.
+	DropTrailingPeriod,
+}
+
+impl ValueFix {
+	/// Applies the fix on a variable definition.
+	///
+	/// Only literal parts of the definition are changed, so issues coming
+	/// from expanded variables are not fixed. For arrays, the fix is
+	/// applied on each element.
+	pub fn apply(&self, def: &mut lst::VariableDefinition) {
+		match &mut def.value {
+			lst::VariableValue::String(text) => {
+				self.apply_text(Arc::make_mut(text))
+			}
+			lst::VariableValue::Array(tokens) => {
+				for token in tokens {
+					if let ArrayToken::Element(text) = token {
+						self.apply_text(Arc::make_mut(text));
+					}
+				}
+			}
+		}
+	}
+
+	/// Produces the edit applying the fix on the definition at a span in
+	/// an LST.
+	///
+	/// The definition is found by the start of the span. Returns [`None`]
+	/// if there is no definition starting there or if the fix changes
+	/// nothing. Edits of multiple fixes can be applied together with an
+	/// [`EditSession`].
+	///
+	/// [`EditSession`]: super::session::EditSession
+	pub fn edit(&self, lst: &ApmlLst, span: Span) -> Option<Edit> {
+		edit_definition(lst, span, |def| {
+			self.apply(def);
+			true
+		})
+	}
+
+	fn apply_text(&self, text: &mut lst::Text) {
+		let mut slots = literal_slots(text);
+		match self {
+			ValueFix::Trim => {
+				for slot in slots.iter_mut() {
+					let Some(slot) = slot else { break };
+					**slot = Cow::Owned(slot.trim_start().to_string());
+					if !slot.is_empty() {
+						break;
+					}
+				}
+				for slot in slots.iter_mut().rev() {
+					let Some(slot) = slot else { break };
+					**slot = Cow::Owned(slot.trim_end().to_string());
+					if !slot.is_empty() {
+						break;
+					}
+				}
+			}
+			ValueFix::CollapseWhitespace => {
+				for slot in slots.into_iter().flatten() {
+					if slot.contains(char::is_whitespace) {
+						*slot = Cow::Owned(collapse_whitespace(slot));
+					}
+				}
+			}
+			ValueFix::DropTrailingPeriod => {
+				for slot in slots.into_iter().rev() {
+					let Some(slot) = slot else { break };
+					let end = slot.trim_end().len();
+					if end == 0 {
+						continue;
+					}
+					if slot[..end].ends_with('.') {
+						slot.to_mut().remove(end - 1);
+					}
+					break;
+				}
+			}
+		}
+	}
+}
+
+/// Produces the edit of a change on the definition starting at the start
+/// of a span in an LST.
+///
+/// The change is made on a copy of the definition and returns if it
+/// succeeded. Returns [`None`] if the change fails or changes nothing.
+fn edit_definition(
+	lst: &ApmlLst,
+	span: Span,
+	change: impl FnOnce(&mut lst::VariableDefinition) -> bool,
+) -> Option<Edit> {
+	let (span, def) = lst
+		.variable_spans()
+		.find(|(def_span, _)| def_span.start == span.start)?;
+	let mut changed = def.clone();
+	if !change(&mut changed) {
+		return None;
+	}
+	let (old, new) = (def.to_string(), changed.to_string());
+	(old != new).then(|| Edit::between(span.start, &old, &new))
+}
+
+/// Returns mutable references to literal strings of a text, in order.
+///
+/// Other parts, such as expansions, are represented as [`None`].
+fn literal_slots<'a, 'b>(
+	text: &'b mut lst::Text<'a>,
+) -> Vec<Option<&'b mut Cow<'a, str>>> {
+	let mut slots = Vec::new();
+	for unit in &mut text.0 {
+		match unit {
+			TextUnit::SingleQuote(text) => slots.push(Some(text)),
+			TextUnit::AnsiCQuote(_) => slots.push(None),
+			TextUnit::Unquoted(words)
+			| TextUnit::DoubleQuote(words)
+			| TextUnit::LocaleQuote(words) => {
+				for word in words {
+					match word {
+						Word::Literal(parts) => {
+							for part in parts {
+								match part {
+									LiteralPart::String(text) => {
+										slots.push(Some(text))
+									}
+									LiteralPart::Escaped(_) => slots.push(None),
+									LiteralPart::LineContinuation => {}
+								}
+							}
+						}
+						_ => slots.push(None),
+					}
+				}
+			}
+		}
+	}
+	slots
+}
+
+fn collapse_whitespace(text: &str) -> String {
+	let mut result = String::with_capacity(text.len());
+	let mut in_whitespace = false;
+	for ch in text.chars() {
+		if ch.is_whitespace() {
+			if !in_whitespace {
+				result.push(' ');
+			}
+			in_whitespace = true;
+		} else {
+			result.push(ch);
+			in_whitespace = false;
+		}
+	}
+	result
+}
+
+/// Checks evaluated values of fields for characters that the consumers
+/// mangle.
+///
+/// Fields and the checks applied are taken from the schema, see
+/// [`ValueChecks`]. For arrays, each element is checked. Descriptions are
+/// checked against the `PKGNAME` defined before them, see
+/// [`check_desc`].
+///
+/// Each issue is reported with the span of the definition which first
+/// introduced it into the final value, and issues are sorted by spans.
+pub fn check_values(
+	lst: &ApmlLst,
+	schema: &FieldSchema,
+) -> Result<Vec<ValueIssue>, ApmlError> {
+	let origins = Rc::new(RefCell::new(HashMap::new()));
+	let mut options = EvalOptions {
+		on_assign: Some(Box::new({
+			let origins = origins.clone();
+			let schema = schema.clone();
+			let mut pkgname = None;
+			move |name, value, span| {
+				if name == "PKGNAME" {
+					pkgname = Some(value.as_string());
+				}
+				let Some(field) = schema.lookup(name) else {
+					return Ok(());
+				};
+				let mut origins = origins.borrow_mut();
+				for kind in ValueIssueKind::enabled(&field.checks) {
+					if !has_issue(
+						value,
+						kind,
+						&field.checks,
+						pkgname.as_deref(),
+					) {
+						origins.remove(&(name.to_string(), kind));
+					} else {
+						origins.entry((name.to_string(), kind)).or_insert(span);
+					}
+				}
+				Ok(())
+			}
+		})),
+		..Default::default()
+	};
+	ApmlContext::eval_lst_with(lst, &mut options)?;
+	drop(options);
+	let mut issues = Rc::into_inner(origins)
+		.expect("callback has been dropped")
+		.into_inner()
+		.into_iter()
+		.map(|((name, kind), span)| ValueIssue { name, kind, span })
+		.collect::<Vec<_>>();
+	issues.sort_by(|a, b| {
+		(a.span.start, &a.name, a.kind).cmp(&(b.span.start, &b.name, b.kind))
+	});
+	Ok(issues)
+}
+
+fn has_issue(
+	value: &VariableValue,
+	kind: ValueIssueKind,
+	checks: &ValueChecks,
+	pkgname: Option<&str>,
+) -> bool {
+	match value {
+		VariableValue::String(text) => kind.is_present(text, checks, pkgname),
+		VariableValue::Array(elements) => elements
+			.iter()
+			.any(|element| kind.is_present(element, checks, pkgname)),
+	}
+}
+
+/// A definition assigning an array to a field which ACBS reads as
+/// a scalar.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShapeIssue {
+	/// Name of the variable.
+	pub name: String,
+	/// Type of the field.
+	pub expected: FieldType,
+	/// Span of the definition.
+	pub span: Span,
+}
+
+impl ShapeIssue {
+	/// Names the type of the field, which must be written as a string.
+	pub fn explain(&self) -> String {
+		format!(
+			"{} is assigned an array, but it is a {} field which must be \
+			written as a string.",
+			self.name,
+			self.expected.ident(),
+		)
+	}
+}
+
+/// Checks definitions of fields for values of the wrong shape.
+///
+/// Arrays assigned to fields not accepting arrays, see
+/// [`FieldType::accepts_array`], are reported in the order of
+/// definitions. Strings assigned to array fields are accepted, as they
+/// are split into words.
+///
+/// This is checked on the LST, so no evaluation is needed. Evaluated
+/// values are checked by [`Package::new`](super::package::Package::new).
+pub fn check_shapes(lst: &ApmlLst, schema: &FieldSchema) -> Vec<ShapeIssue> {
+	lst.variable_spans()
+		.filter(|(_, def)| matches!(def.value, lst::VariableValue::Array(_)))
+		.filter_map(|(span, def)| {
+			let field = schema.lookup(&def.name)?;
+			(!field.ty.accepts_array()).then(|| ShapeIssue {
+				name: def.name.to_string(),
+				expected: field.ty,
+				span,
+			})
+		})
+		.collect()
+}
+
+/// A package relation repeated in a relation field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RelationIssue {
+	/// Name of the variable.
+	pub name: String,
+	/// Kind of the issue.
+	pub kind: RelationIssueKind,
+	/// The first occurrence of the package.
+	pub first: String,
+	/// Span of the definition which introduced the first occurrence.
+	pub first_span: Span,
+	/// The repeated occurrence of the package.
+	pub second: String,
+	/// Span of the definition which introduced the repeated occurrence.
+	pub second_span: Span,
+}
+
+/// Kind of a [`RelationIssue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RelationIssueKind {
+	/// The same relation is repeated.
+	Duplicate,
+	/// The same package is repeated with a different version constraint.
+	Conflict,
+}
+
+impl RelationIssue {
+	/// Names the repeated relation, and the first one if they conflict.
+	pub fn explain(&self) -> String {
+		match self.kind {
+			RelationIssueKind::Duplicate => {
+				format!("{} is repeated in {}.", self.second, self.name)
+			}
+			RelationIssueKind::Conflict => format!(
+				"{} conflicts with {} in {}, as both constrain the same \
+				package.",
+				self.second, self.first, self.name,
+			),
+		}
+	}
+
+	/// Produces the edit removing the repeated occurrence of a duplicate
+	/// relation from an LST.
+	///
+	/// The occurrence is only removed if it is written literally in the
+	/// definition at [`RelationIssue::second_span`], as a whole array
+	/// element or as a whitespace-separated word of a string. The last
+	/// such occurrence in the definition is removed, together with one
+	/// adjacent whitespace.
+	///
+	/// Returns [`None`] if nothing is removed, which is always the case
+	/// for conflicts, as the intended constraint cannot be decided.
+	pub fn remove_duplicate(&self, lst: &ApmlLst) -> Option<Edit> {
+		if self.kind != RelationIssueKind::Duplicate {
+			return None;
+		}
+		edit_definition(lst, self.second_span, |def| match &mut def.value {
+			lst::VariableValue::String(text) => {
+				remove_word(Arc::make_mut(text), &self.second)
+			}
+			lst::VariableValue::Array(tokens) => {
+				remove_element(tokens, &self.second)
+			}
+		})
+	}
+}
+
+/// Returns the name of the package of a relation, such as `glibc` for
+/// `glibc>=2.35`, or the whole relation if it is malformed.
+fn relation_package(relation: &str) -> String {
+	// the kind does not affect parsing
+	Dependency::parse("", RelationKind::Depends, relation)
+		.map_or_else(|| relation.to_string(), |dependency| dependency.target)
+}
+
+/// Removes the last whitespace-separated literal occurrence of a word
+/// from a text.
+fn remove_word(text: &mut lst::Text, word: &str) -> bool {
+	let mut slots = literal_slots(text);
+	let count = slots.len();
+	for (index, slot) in slots.iter_mut().enumerate().rev() {
+		let Some(slot) = slot else { continue };
+		// slot edges are word boundaries only at the edges of the text
+		let bounded_start = |offset: usize| {
+			slot[..offset]
+				.chars()
+				.next_back()
+				.map_or(index == 0, char::is_whitespace)
+		};
+		let bounded_end = |offset: usize| {
+			slot[offset..]
+				.chars()
+				.next()
+				.map_or(index + 1 == count, char::is_whitespace)
+		};
+		let Some(start) = slot
+			.rmatch_indices(word)
+			.map(|(start, _)| start)
+			.find(|start| {
+				bounded_start(*start) && bounded_end(*start + word.len())
+			})
+		else {
+			continue;
+		};
+		let mut range = start..start + word.len();
+		if let Some(ch) = slot[..range.start].chars().next_back() {
+			range.start -= ch.len_utf8();
+		} else if let Some(ch) = slot[range.end..].chars().next() {
+			range.end += ch.len_utf8();
+		}
+		let mut value = slot.to_string();
+		value.replace_range(range, "");
+		**slot = Cow::Owned(value);
+		return true;
+	}
+	false
+}
+
+/// Removes the last literal occurrence of an element from an array.
+fn remove_element(tokens: &mut Vec<ArrayToken>, element: &str) -> bool {
+	let Some(index) = tokens.iter().rposition(|token| {
+		matches!(token, ArrayToken::Element(text)
+			if literal_value(text).as_deref() == Some(element))
+	}) else {
+		return false;
+	};
+	tokens.remove(index);
+	if index > 0 && matches!(tokens[index - 1], ArrayToken::Spacy(_)) {
+		tokens.remove(index - 1);
+	} else if matches!(tokens.get(index), Some(ArrayToken::Spacy(_))) {
+		tokens.remove(index);
+	}
+	true
+}
+
+/// Returns the value of a text consisting of literals only.
+pub(crate) fn literal_value(text: &lst::Text) -> Option<String> {
+	let mut value = String::new();
+	for unit in &text.0 {
+		match unit {
+			TextUnit::SingleQuote(text) => value.push_str(text),
+			TextUnit::AnsiCQuote(_) => return None,
+			TextUnit::Unquoted(words)
+			| TextUnit::DoubleQuote(words)
+			| TextUnit::LocaleQuote(words) => {
+				for word in words {
+					let Word::Literal(parts) = word else {
+						return None;
+					};
+					for part in parts {
+						match part {
+							LiteralPart::String(text) => value.push_str(text),
+							LiteralPart::Escaped(ch) => value.push(*ch),
+							LiteralPart::LineContinuation => {}
+						}
+					}
+				}
+			}
+		}
+	}
+	Some(value)
+}
+
+/// Checks evaluated values of relation fields for repeated packages.
+///
+/// Relation fields are taken from the schema, see
+/// [`FieldSpec::relation`]. Values are checked after evaluation, so
+/// repetitions introduced by expansions are found as well. Each
+/// occurrence is reported with the span of the definition which
+/// introduced it, and a repeated occurrence is reported against the
+/// first occurrence of the package only.
+///
+/// Issues are sorted by spans of the repeated occurrences.
+///
+/// [`FieldSpec::relation`]: super::schema::FieldSpec::relation
+pub fn check_relations(
+	lst: &ApmlLst,
+	schema: &FieldSchema,
+) -> Result<Vec<RelationIssue>, ApmlError> {
+	// origins of each element of relation fields
+	let origins =
+		Rc::new(RefCell::new(HashMap::<String, Vec<(String, Span)>>::new()));
+	let mut options = EvalOptions {
+		on_assign: Some(Box::new({
+			let origins = origins.clone();
+			let schema = schema.clone();
+			move |name, value, span| {
+				if !schema.lookup(name).is_some_and(|field| field.relation) {
+					return Ok(());
+				}
+				let mut origins = origins.borrow_mut();
+				let mut previous = origins
+					.remove(name)
+					.unwrap_or_default()
+					.into_iter()
+					.map(Some)
+					.collect::<Vec<_>>();
+				// elements kept from the previous value keep their origins,
+				// wherever they are moved to by prepending or appending
+				let elements = value
+					.as_array()
+					.into_iter()
+					.map(|element| {
+						let kept = previous.iter_mut().find(|old| {
+							old.as_ref().is_some_and(|(old, _)| *old == element)
+						});
+						match kept.and_then(Option::take) {
+							Some((_, origin)) => (element, origin),
+							None => (element, span),
+						}
+					})
+					.collect();
+				origins.insert(name.to_string(), elements);
+				Ok(())
+			}
+		})),
+		..Default::default()
+	};
+	ApmlContext::eval_lst_with(lst, &mut options)?;
+	drop(options);
+	let origins = Rc::into_inner(origins)
+		.expect("callback has been dropped")
+		.into_inner();
+	let mut issues = Vec::new();
+	for (name, elements) in origins {
+		for (index, (second, second_span)) in elements.iter().enumerate() {
+			let package = relation_package(second);
+			let Some((first, first_span)) = elements[..index]
+				.iter()
+				.find(|(first, _)| relation_package(first) == package)
+			else {
+				continue;
+			};
+			issues.push(RelationIssue {
+				name: name.clone(),
+				kind: if first == second {
+					RelationIssueKind::Duplicate
+				} else {
+					RelationIssueKind::Conflict
+				},
+				first: first.clone(),
+				first_span: *first_span,
+				second: second.clone(),
+				second_span: *second_span,
+			});
+		}
+	}
+	issues.sort_by(|a, b| {
+		(a.second_span.start, &a.name, &a.second).cmp(&(
+			b.second_span.start,
+			&b.name,
+			&b.second,
+		))
+	});
+	Ok(issues)
+}
+
+/// An unquoted variable expansion in an array literal, which is split
+/// into words.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SplittingIssue {
+	/// Name of the array variable.
+	pub name: String,
+	/// Name of the expanded variable.
+	pub variable: String,
+	/// Kind of the issue.
+	pub kind: SplittingKind,
+	/// Span of the definition.
+	pub span: Span,
+	/// Span of the expansion.
+	pub expansion_span: Span,
+}
+
+/// Kind of a [`SplittingIssue`], classified by the value of the expanded
+/// variable at the definition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SplittingKind {
+	/// The variable is a string, which is intentionally split into
+	/// whitespace-separated words.
+	Intentional,
+	/// The variable is an array, of which only the first element is
+	/// expanded.
+	Array,
+	/// The variable is undefined.
+	Undefined,
+}
+
+impl SplittingKind {
+	/// Returns if the expansion is likely a bug.
+	pub fn is_suspicious(&self) -> bool {
+		!matches!(self, SplittingKind::Intentional)
+	}
+}
+
+impl SplittingIssue {
+	/// Describes how the expansion is split, depending on the kind.
+	pub fn explain(&self) -> String {
+		match self.kind {
+			SplittingKind::Intentional => format!(
+				"{} is expanded unquoted in {}, so it is split into words \
+				and the words are expanded as globs.",
+				self.variable, self.name,
+			),
+			SplittingKind::Array => format!(
+				"{variable} is an array, but only its first element is \
+				expanded in {name}. Use \"${{{variable}[@]}}\" to include all \
+				elements.",
+				variable = self.variable,
+				name = self.name,
+			),
+			SplittingKind::Undefined => format!(
+				"{} is undefined when it is expanded unquoted in {}.",
+				self.variable, self.name,
+			),
+		}
+	}
+
+	/// Produces the edit rewriting an array element consisting of the
+	/// expansion of an array only into the `"${NAME[@]}"` form.
+	///
+	/// The definition is found by [`SplittingIssue::span`]. Returns
+	/// [`None`] if nothing is rewritten, which is always the case for
+	/// kinds other than [`SplittingKind::Array`].
+	pub fn quote_array(&self, lst: &ApmlLst) -> Option<Edit> {
+		if self.kind != SplittingKind::Array {
+			return None;
+		}
+		edit_definition(lst, self.span, |def| self.quote_element(def))
+	}
+
+	fn quote_element(&self, def: &mut lst::VariableDefinition) -> bool {
+		let start = self.expansion_span.start - self.span.start;
+		let mut pos = def.name.len() + display_len(&def.op) + 1;
+		let lst::VariableValue::Array(tokens) = &mut def.value else {
+			return false;
+		};
+		for token in tokens {
+			let len = display_len(&*token);
+			if pos == start
+				&& let ArrayToken::Element(text) = token
+				&& let [TextUnit::Unquoted(words)] = text.0.as_slice()
+				&& let [word] = words.as_slice()
+				&& expanded_name(word) == Some(self.variable.as_str())
+			{
+				*text = Arc::new(lst::Text(vec![TextUnit::DoubleQuote(vec![
+					Word::BracedVariable(BracedExpansion {
+						name: self.variable.clone().into(),
+						modifier: Some(ExpansionModifier::ArrayElements),
+					}),
+				])]));
+				return true;
+			}
+			pos += len;
+		}
+		false
+	}
+}
+
+/// Returns the name of the variable expanded by an unquoted word which is
+/// split into words.
+fn expanded_name<'a>(word: &'a Word) -> Option<&'a str> {
+	match word {
+		Word::UnbracedVariable(name) => Some(name),
+		Word::BracedVariable(exp) => match exp.modifier {
+			Some(
+				ExpansionModifier::ArrayElements
+				| ExpansionModifier::SingleWordElements
+				| ExpansionModifier::Length,
+			) => None,
+			_ => Some(&exp.name),
+		},
+		Word::Literal(_) | Word::Subcommand(_) | Word::Custom(_) => None,
+	}
+}
+
+/// Returns unquoted expansions split into words in elements of an array
+/// definition, along with their spans.
+fn split_expansions<'a>(
+	def: &'a lst::VariableDefinition,
+	span: Span,
+) -> Vec<(Span, &'a str)> {
+	let mut result = Vec::new();
+	let lst::VariableValue::Array(tokens) = &def.value else {
+		return result;
+	};
+	let mut pos = span.start + def.name.len() + display_len(&def.op) + 1;
+	for token in tokens {
+		if let ArrayToken::Element(text) = token {
+			let mut unit_pos = pos;
+			for unit in &text.0 {
+				if let TextUnit::Unquoted(words) = unit {
+					let mut word_pos = unit_pos;
+					for word in words {
+						let word_span =
+							Span::with_len(word_pos, display_len(word));
+						if let Some(name) = expanded_name(word) {
+							result.push((word_span, name));
+						}
+						word_pos = word_span.end;
+					}
+				}
+				unit_pos += display_len(unit);
+			}
+		}
+		pos += display_len(token);
+	}
+	result
+}
+
+/// Checks array literals for unquoted variable expansions.
+///
+/// Expansions are classified by whether the expanded variable is an
+/// array or a string at the definition. This is decided from previous
+/// definitions, or from the seed context if the variable is not defined
+/// before, without evaluation. Special parameters and expansions with
+/// `[@]`, `[*]` or `#` are not reported.
+///
+/// Issues are reported in the order of expansions.
+pub fn check_splitting(
+	lst: &ApmlLst,
+	seed: &ApmlContext,
+) -> Vec<SplittingIssue> {
+	// whether each variable defined so far is an array
+	let mut arrays = HashMap::<&str, bool>::new();
+	let is_array = |arrays: &HashMap<&str, bool>, name: &str| {
+		arrays.get(name).copied().or_else(|| {
+			seed.get(name)
+				.map(|value| matches!(value, VariableValue::Array(_)))
+		})
+	};
+	let mut issues = Vec::new();
+	for (span, def) in lst.variable_spans() {
+		for (expansion_span, variable) in split_expansions(def, span) {
+			if variable == "*"
+				|| variable == "@"
+				|| variable.starts_with(|ch: char| ch.is_ascii_digit())
+			{
+				continue;
+			}
+			issues.push(SplittingIssue {
+				name: def.name.to_string(),
+				variable: variable.to_string(),
+				kind: match is_array(&arrays, variable) {
+					Some(false) => SplittingKind::Intentional,
+					Some(true) => SplittingKind::Array,
+					None => SplittingKind::Undefined,
+				},
+				span,
+				expansion_span,
+			});
+		}
+		let array = match (&def.op, &def.value) {
+			(_, lst::VariableValue::Array(_)) => true,
+			(lst::VariableOp::Append, _) => {
+				is_array(&arrays, &def.name).unwrap_or_default()
+			}
+			(lst::VariableOp::Assignment, _) => false,
+		};
+		arrays.insert(&def.name, array);
+	}
+	issues
+}
+
+/// An unbraced expansion (`$NAME`) directly followed by literal text.
+///
+/// The boundary of the name is easily misread in such cases, so the
+/// braced form (`${NAME}`) is preferred.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BraceIssue {
+	/// Name of the defined variable.
+	pub name: String,
+	/// Name of the expanded variable.
+	pub variable: String,
+	/// The first character of the following literal text.
+	pub next: char,
+	/// Span of the definition.
+	pub span: Span,
+	/// Span of the expansion.
+	pub expansion_span: Span,
+}
+
+impl BraceIssue {
+	/// Returns if the following character is valid in names, which is
+	/// only possible across quotes or escapes, such as `$A"_b"`.
+	///
+	/// In such cases, the author probably wanted braces.
+	pub fn continues_name(&self) -> bool {
+		self.next.is_alphanumeric() || self.next == '_'
+	}
+
+	/// Suggests the braced form of the expansion.
+	pub fn explain(&self) -> String {
+		format!(
+			"${variable} is directly followed by `{next}` in {name}. \
+			Write ${{{variable}}} to make the end of the name clear.",
+			variable = self.variable,
+			next = self.next,
+			name = self.name,
+		)
+	}
+
+	/// Produces the edit rewriting the expansion into the braced form.
+	///
+	/// The definition is found by [`BraceIssue::span`]. The braced form
+	/// always expands to the same value. Returns [`None`] if the
+	/// expansion is not found.
+	pub fn add_braces(&self, lst: &ApmlLst) -> Option<Edit> {
+		edit_definition(lst, self.span, |def| self.brace_word(def))
+	}
+
+	fn brace_word(&self, def: &mut lst::VariableDefinition) -> bool {
+		let start = self.expansion_span.start - self.span.start;
+		let mut pos = def.name.len() + display_len(&def.op);
+		let word = match &mut def.value {
+			lst::VariableValue::String(text) => {
+				word_at_mut(Arc::make_mut(text), pos, start)
+			}
+			lst::VariableValue::Array(tokens) => {
+				pos += 1;
+				let mut found = None;
+				for token in tokens {
+					let len = display_len(&*token);
+					if let ArrayToken::Element(text) = token
+						&& (pos..pos + len).contains(&start)
+					{
+						found = word_at_mut(Arc::make_mut(text), pos, start);
+						break;
+					}
+					pos += len;
+				}
+				found
+			}
+		};
+		let Some(word) = word else {
+			return false;
+		};
+		let Word::UnbracedVariable(name) = word else {
+			return false;
+		};
+		*word = Word::BracedVariable(BracedExpansion {
+			name: std::mem::take(name),
+			modifier: None,
+		});
+		true
+	}
+}
+
+/// Finds the unquoted or double-quoted word starting at `start` in a text
+/// starting at `pos`.
+fn word_at_mut<'a, 'b>(
+	text: &'a mut lst::Text<'b>,
+	mut pos: usize,
+	start: usize,
+) -> Option<&'a mut Word<'b>> {
+	for unit in &mut text.0 {
+		let len = display_len(&*unit);
+		let words = match unit {
+			TextUnit::Unquoted(words) => Some((words, pos)),
+			TextUnit::DoubleQuote(words) => Some((words, pos + 1)),
+			TextUnit::LocaleQuote(words) => Some((words, pos + 2)),
+			TextUnit::SingleQuote(_) | TextUnit::AnsiCQuote(_) => None,
+		};
+		if let Some((words, mut word_pos)) = words
+			&& (pos..pos + len).contains(&start)
+		{
+			for word in words {
+				if word_pos == start {
+					return Some(word);
+				}
+				word_pos += display_len(&*word);
+			}
+			return None;
+		}
+		pos += len;
+	}
+	None
+}
+
+/// Returns the first character of a literal word.
+fn literal_start(parts: &[LiteralPart]) -> Option<char> {
+	parts.iter().find_map(|part| match part {
+		LiteralPart::String(text) => text.chars().next(),
+		LiteralPart::Escaped(ch) => Some(*ch),
+		LiteralPart::LineContinuation => None,
+	})
+}
+
+/// Collects unbraced expansions directly followed by literal text in
+/// a text starting at `pos`.
+fn unbraced_followed<'a>(
+	text: &'a lst::Text,
+	mut pos: usize,
+	out: &mut Vec<(Span, &'a str, char)>,
+) {
+	// the last unbraced expansion not followed by anything yet
+	let mut pending: Option<(Span, &str)> = None;
+	let mut follow = |pending: &mut Option<(Span, &'a str)>,
+	                  next: Option<char>| {
+		if let Some((span, name)) = pending.take()
+			&& let Some(next) = next
+			&& !next.is_whitespace()
+		{
+			out.push((span, name, next));
+		}
+	};
+	for unit in &text.0 {
+		match unit {
+			TextUnit::Unquoted(words)
+			| TextUnit::DoubleQuote(words)
+			| TextUnit::LocaleQuote(words) => {
+				let mut word_pos = pos;
+				match unit {
+					TextUnit::DoubleQuote(_) => word_pos += 1,
+					TextUnit::LocaleQuote(_) => word_pos += 2,
+					_ => {}
+				}
+				for word in words {
+					let len = display_len(word);
+					match word {
+						Word::Literal(parts) => {
+							if let Some(next) = literal_start(parts) {
+								follow(&mut pending, Some(next));
+							}
+						}
+						Word::UnbracedVariable(name)
+							if !is_special_name(name) =>
+						{
+							follow(&mut pending, None);
+							pending =
+								Some((Span::with_len(word_pos, len), name));
+						}
+						_ => follow(&mut pending, None),
+					}
+					word_pos += len;
+				}
+			}
+			TextUnit::SingleQuote(text) | TextUnit::AnsiCQuote(text) => {
+				// empty quotes concatenate nothing
+				if let Some(next) = text.chars().next() {
+					follow(&mut pending, Some(next));
+				}
+			}
+		}
+		pos += display_len(unit);
+	}
+}
+
+/// Returns if a name is a special or positional parameter.
+fn is_special_name(name: &str) -> bool {
+	name == "*"
+		|| name == "@"
+		|| name.starts_with(|ch: char| ch.is_ascii_digit())
+}
+
+/// Checks for unbraced expansions directly followed by literal text,
+/// such as `$PKGNAME-doc`, in strings and array elements.
+///
+/// Expansions followed by whitespace, by other expansions or by the end
+/// of the text are not reported, nor are special and positional
+/// parameters. Issues are reported in the order of expansions.
+pub fn check_braces(lst: &ApmlLst) -> Vec<BraceIssue> {
+	let mut issues = Vec::new();
+	for (span, def) in lst.variable_spans() {
+		let mut found = Vec::new();
+		let pos = span.start + def.name.len() + display_len(&def.op);
+		match &def.value {
+			lst::VariableValue::String(text) => {
+				unbraced_followed(text, pos, &mut found)
+			}
+			lst::VariableValue::Array(tokens) => {
+				let mut pos = pos + 1;
+				for token in tokens {
+					if let ArrayToken::Element(text) = token {
+						unbraced_followed(text, pos, &mut found);
+					}
+					pos += display_len(token);
+				}
+			}
+		}
+		issues.extend(found.into_iter().map(
+			|(expansion_span, variable, next)| BraceIssue {
+				name: def.name.to_string(),
+				variable: variable.to_string(),
+				next,
+				span,
+				expansion_span,
+			},
+		));
+	}
+	issues
+}
+
+/// A locale-translated string (`$"..."`).
+///
+/// Translation is not supported, so such strings are evaluated as
+/// double-quoted strings, and the `$` has no effect.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocaleQuoteIssue {
+	/// Name of the defined variable.
+	pub name: String,
+	/// Span of the definition.
+	pub span: Span,
+	/// Span of the string, including the `$` and the quotes.
+	pub quote_span: Span,
+}
+
+impl LocaleQuoteIssue {
+	/// Notes that the string is evaluated without translation.
+	pub fn explain(&self) -> String {
+		format!(
+			"{} contains a locale-translated string, which is not translated \
+			but evaluated as a double-quoted string.",
+			self.name,
+		)
+	}
+}
+
+/// Checks for locale-translated strings (`$"..."`) in strings and array
+/// elements.
+///
+/// Strings nested in expansions and sub-commands are not reported.
+/// Issues are reported in the order of the strings.
+pub fn check_locale_quotes(lst: &ApmlLst) -> Vec<LocaleQuoteIssue> {
+	let mut issues = Vec::new();
+	for (span, def) in lst.variable_spans() {
+		for (mut pos, text) in value_texts(def, span) {
+			for unit in &text.0 {
+				let len = display_len(unit);
+				if let TextUnit::LocaleQuote(_) = unit {
+					issues.push(LocaleQuoteIssue {
+						name: def.name.to_string(),
+						span,
+						quote_span: Span::with_len(pos, len),
+					});
+				}
+				pos += len;
+			}
+		}
+	}
+	issues
+}
+
+/// Returns the string or array elements of a definition at a span, along
+/// with their offsets.
+fn value_texts<'a, 'b>(
+	def: &'a lst::VariableDefinition<'b>,
+	span: Span,
+) -> Vec<(usize, &'a lst::Text<'b>)> {
+	let mut pos = span.start + def.name.len() + display_len(&def.op);
+	match &def.value {
+		lst::VariableValue::String(text) => vec![(pos, text.as_ref())],
+		lst::VariableValue::Array(tokens) => {
+			pos += 1;
+			let mut texts = Vec::new();
+			for token in tokens {
+				if let ArrayToken::Element(text) = token {
+					texts.push((pos, text.as_ref()));
+				}
+				pos += display_len(token);
+			}
+			texts
+		}
+	}
+}
+
+/// A command substitution in backticks (`` `cmd` ``).
+///
+/// Backticks are not parsed as command substitutions, so the command is
+/// kept as is in the value, while the `$(cmd)` form is preferred in
+/// bash for its clearer nesting and quoting.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BacktickIssue {
+	/// Name of the defined variable.
+	pub name: String,
+	/// The command between the backticks.
+	pub command: String,
+	/// Span of the definition.
+	pub span: Span,
+	/// Span of the substitution, including the backticks.
+	pub backtick_span: Span,
+}
+
+impl BacktickIssue {
+	/// Suggests the `$(cmd)` form of the substitution.
+	pub fn explain(&self) -> String {
+		format!(
+			"{} contains a command substitution in backticks. Write \
+			$({}) instead.",
+			self.name, self.command,
+		)
+	}
+
+	/// Produces the edit rewriting the substitution into the `$(cmd)`
+	/// form.
+	///
+	/// Returns [`None`] if the definition does not parse after the
+	/// rewrite, such as if the command contains unbalanced parentheses.
+	pub fn convert(&self, lst: &ApmlLst) -> Option<Edit> {
+		let src = lst.to_string();
+		let mut def = self.span.slice(&src).to_string();
+		def.replace_range(
+			self.backtick_span.start - self.span.start
+				..self.backtick_span.end - self.span.start,
+			&format!("$({})", self.command),
+		);
+		match ApmlLst::parse(&def).ok()?.0.as_slice() {
+			[lst::Token::Variable(_)] => Some(Edit::new(
+				self.backtick_span,
+				format!("$({})", self.command),
+			)),
+			_ => None,
+		}
+	}
+}
+
+/// Checks for command substitutions in backticks in unquoted and
+/// double-quoted parts of strings and array elements.
+///
+/// Escaped backticks are not considered, and a backtick without a pair
+/// in the same literal string is ignored. Issues are reported in the
+/// order of substitutions.
+pub fn check_backticks(lst: &ApmlLst) -> Vec<BacktickIssue> {
+	let mut issues = Vec::new();
+	for (span, def) in lst.variable_spans() {
+		for (mut pos, text) in value_texts(def, span) {
+			for unit in &text.0 {
+				let (words, mut word_pos) = match unit {
+					TextUnit::Unquoted(words) => (words, pos),
+					TextUnit::DoubleQuote(words) => (words, pos + 1),
+					TextUnit::LocaleQuote(words) => (words, pos + 2),
+					TextUnit::SingleQuote(_) | TextUnit::AnsiCQuote(_) => {
+						pos += display_len(unit);
+						continue;
+					}
+				};
+				for word in words {
+					if let Word::Literal(parts) = word {
+						let mut part_pos = word_pos;
+						for part in parts {
+							if let LiteralPart::String(text) = part {
+								let ticks = text
+									.match_indices('`')
+									.map(|(index, _)| index)
+									.collect::<Vec<_>>();
+								for pair in ticks.chunks_exact(2) {
+									issues.push(BacktickIssue {
+										name: def.name.to_string(),
+										command: text[pair[0] + 1..pair[1]]
+											.to_string(),
+										span,
+										backtick_span: Span::new(
+											part_pos + pair[0],
+											part_pos + pair[1] + 1,
+										),
+									});
+								}
+							}
+							part_pos += display_len(part);
+						}
+					}
+					word_pos += display_len(word);
+				}
+				pos += display_len(unit);
+			}
+		}
+	}
+	issues
+}
+
+/// A carriage return not followed by a newline, a vertical tab or a form
+/// feed in a value.
+///
+/// Such characters are invisible in most views of a source, see
+/// [`lst::invisible_controls`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ControlCharacterIssue {
+	/// Name of the defined variable.
+	pub name: String,
+	/// Span of the definition.
+	pub span: Span,
+	/// Span of the character.
+	pub char_span: Span,
+	/// The character.
+	pub ch: char,
+}
+
+impl ControlCharacterIssue {
+	/// Names the invisible character and suggests escaping it.
+	pub fn explain(&self) -> String {
+		let what = match self.ch {
+			'\r' => "a carriage return not followed by a newline",
+			'\x0b' => "a vertical tab",
+			_ => "a form feed",
+		};
+		format!(
+			"{} contains {}, which is invisible in most editors. Write it \
+			with an escape sequence in $'...' if it is intended.",
+			self.name, what,
+		)
+	}
+}
+
+/// Checks for raw carriage returns not followed by a newline, vertical
+/// tabs and form feeds in values.
+///
+/// Such characters are kept as is by the LST. Escape sequences in ANSI-C
+/// quoted strings (`$'\r'`) are not reported. Issues are reported in the
+/// order of the characters.
+pub fn check_control_characters(lst: &ApmlLst) -> Vec<ControlCharacterIssue> {
+	let src = lst.to_string();
+	let mut issues = Vec::new();
+	for (span, def) in lst.variable_spans() {
+		let start = span.start + def.name.len() + display_len(&def.op);
+		let value = &src[start..span.end];
+		for pos in lst::invisible_controls(value) {
+			let ch = value[pos..].chars().next().expect("character");
+			issues.push(ControlCharacterIssue {
+				name: def.name.to_string(),
+				span,
+				char_span: Span::with_len(start + pos, ch.len_utf8()),
+				ch,
+			});
+		}
+	}
+	issues
+}
+
+/// A source missing the newline at its end.
+///
+/// POSIX tools expect text files to end with a newline, and some of them
+/// drop or mangle an unterminated last line.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TrailingNewlineIssue {
+	/// Span of the unterminated last line.
+	pub span: Span,
+}
+
+impl TrailingNewlineIssue {
+	/// Notes the missing newline.
+	pub fn explain(&self) -> String {
+		"The source does not end with a newline.".to_string()
+	}
+
+	/// Produces the edit adding the newline, which keeps evaluation
+	/// results.
+	pub fn add_newline(&self) -> Edit {
+		Edit::new(Span::with_len(self.span.end, 0), "\n")
+	}
+}
+
+/// Checks if a source misses the newline at its end.
+///
+/// Blank sources are not checked, see [`ApmlLst::is_blank`], so empty
+/// files are accepted. See [`ApmlLst::ends_with_newline`] for line
+/// continuations at the end.
+pub fn check_trailing_newline(lst: &ApmlLst) -> Option<TrailingNewlineIssue> {
+	if lst.is_blank() || lst.ends_with_newline() {
+		return None;
+	}
+	let src = lst.to_string();
+	let start = src.rfind('\n').map_or(0, |pos| pos + 1);
+	Some(TrailingNewlineIssue {
+		span: Span::new(start, src.len()),
+	})
+}
+
+/// An architecture-specific override in a `noarch` package.
+///
+/// Packages with `ABHOST=noarch` are built once for all architectures,
+/// so such overrides never take effect.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NoarchOverrideIssue {
+	/// Name of the override, such as `PKGDEP__AMD64`.
+	pub name: String,
+	/// Suffix of the override, such as `AMD64`.
+	pub suffix: String,
+	/// Span of the definition.
+	pub span: Span,
+}
+
+impl NoarchOverrideIssue {
+	/// Notes that the override has no effect on `noarch` packages.
+	pub fn explain(&self) -> String {
+		format!(
+			"{} overrides for {}, which has no effect as ABHOST is noarch.",
+			self.name, self.suffix,
+		)
+	}
+}
+
+/// Checks for architecture-specific overrides in a `noarch` package.
+///
+/// `ABHOST` is read from the context, which is usually the combined
+/// context of a package, so that both `spec` and `defines` can be
+/// checked. Overrides are definitions whose suffix after `__` is an
+/// architecture or a group known by the map. Issues are reported in
+/// the order of definitions, and nothing is reported if the package is
+/// not `noarch`.
+pub fn check_noarch_overrides<C: ReadContext + ?Sized>(
+	lst: &ApmlLst,
+	context: &C,
+	map: &ArchMap,
+) -> Vec<NoarchOverrideIssue> {
+	if !AbHost::of(context).is_some_and(|host| host.is_noarch()) {
+		return Vec::new();
+	}
+	lst.variable_spans()
+		.filter_map(|(span, def)| {
+			let (base, suffix) = def.name.rsplit_once("__")?;
+			(!base.is_empty() && map.is_known(&suffix.to_ascii_lowercase()))
+				.then(|| NoarchOverrideIssue {
+					name: def.name.to_string(),
+					suffix: suffix.to_string(),
+					span,
+				})
+		})
+		.collect()
+}
+
+/// Overrides of a variable from groups of the same size applying on the
+/// same architectures, so that none of them takes precedence.
+///
+/// See [`resolve_arch`] for the precedence of overrides.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OverrideConflictIssue {
+	/// Name of the variable without suffixes.
+	pub name: String,
+	/// Names of the conflicting groups, sorted by name.
+	pub groups: Vec<String>,
+	/// Architectures on which the overrides conflict, sorted by name.
+	pub arches: Vec<String>,
+}
+
+impl OverrideConflictIssue {
+	/// Names the tied groups and the architectures they tie on.
+	pub fn explain(&self) -> String {
+		format!(
+			"{} is overridden for groups of the same size ({}), so none of \
+			them takes precedence on {}.",
+			self.name,
+			self.groups.join(", "),
+			self.arches.join(", "),
+		)
+	}
+}
+
+/// Checks for conflicting overrides from groups on each architecture of
+/// the map.
+///
+/// Such conflicts make [`resolve_arch`] fail, so this reports all of
+/// them at once instead. Issues are sorted by names of variables.
+pub fn check_override_conflicts<C: ReadContext + ?Sized>(
+	context: &C,
+	map: &ArchMap,
+) -> Vec<OverrideConflictIssue> {
+	let mut issues = Vec::<OverrideConflictIssue>::new();
+	for arch in map.arches() {
+		for conflict in group_conflicts(context, arch, map) {
+			let ArchError::GroupConflict { name, groups } = conflict;
+			match issues
+				.iter_mut()
+				.find(|issue| issue.name == name && issue.groups == groups)
+			{
+				Some(issue) => issue.arches.push(arch.clone()),
+				None => issues.push(OverrideConflictIssue {
+					name,
+					groups,
+					arches: vec![arch.clone()],
+				}),
+			}
+		}
+	}
+	issues.sort_by(|a, b| (&a.name, &a.groups).cmp(&(&b.name, &b.groups)));
+	issues
+}
+
+/// An architecture in `FAIL_ARCH` which is not known by the architecture
+/// map.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FailArchIssue {
+	/// Name of the architecture, as written in `FAIL_ARCH`.
+	pub arch: String,
+}
+
+impl FailArchIssue {
+	/// Names the unknown architecture.
+	pub fn explain(&self) -> String {
+		format!(
+			"FAIL_ARCH mentions {}, which is not a known architecture.",
+			self.arch
+		)
+	}
+}
+
+/// Checks that architectures in `FAIL_ARCH` are known by the map.
+///
+/// `FAIL_ARCH` is a pattern such as `!(amd64|arm64)`. Each alternative
+/// without wildcards is taken as an architecture, and reported if it is
+/// not in any group of the map. Issues are reported in the order of
+/// `FAIL_ARCH`, once for each architecture.
+pub fn check_fail_arch<C: ReadContext + ?Sized>(
+	context: &C,
+	map: &ArchMap,
+) -> Vec<FailArchIssue> {
+	let Some(value) = context.get("FAIL_ARCH") else {
+		return Vec::new();
+	};
+	let pattern = value.as_string();
+	let arches = map.arches();
+	let mut issues = Vec::<FailArchIssue>::new();
+	for arch in pattern
+		.split(|ch: char| "!@+()|".contains(ch) || ch.is_whitespace())
+		.filter(|arch| {
+			!arch.is_empty()
+				&& arch
+					.chars()
+					.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+		}) {
+		if !arches.contains(&arch.to_ascii_lowercase())
+			&& issues.iter().all(|issue| issue.arch != arch)
+		{
+			issues.push(FailArchIssue {
+				arch: arch.to_string(),
+			});
+		}
+	}
+	issues
+}
+
+/// A violated constraint between fields.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConstraintIssue {
+	/// The violated constraint.
+	pub constraint: FieldConstraint,
+	/// Architectures on which the constraint is violated, sorted by name.
+	///
+	/// This is empty if no architectures are checked.
+	pub arches: Vec<String>,
+}
+
+impl ConstraintIssue {
+	/// States the violated constraint and the architectures it fails on.
+	pub fn explain(&self) -> String {
+		let [a, b] = self.constraint.fields();
+		let problem = match &self.constraint {
+			FieldConstraint::Conflicts(..) => {
+				format!("{} and {} are set together", a, b)
+			}
+			FieldConstraint::Requires(..) => {
+				format!("{} is set, but {} is not", a, b)
+			}
+			FieldConstraint::Implies {
+				value, then_value, ..
+			} => format!(
+				"{} is {:?}, but {} is not {:?}",
+				a, value, b, then_value
+			),
+		};
+		if self.arches.is_empty() {
+			format!("{}.", problem)
+		} else {
+			format!("{} on {}.", problem, self.arches.join(", "))
+		}
+	}
+}
+
+/// Checks an evaluated context against constraints between fields.
+///
+/// Constraints are taken from the schema, see
+/// [`FieldSchema::constraints`]. As fields of `spec` files may be
+/// constrained as well, the context should contain variables of both
+/// `spec` and `defines` files.
+///
+/// The context is [resolved][resolve_arch] for each architecture in the
+/// groups of the map, so overrides such as `SRCS__AMD64` are checked
+/// on the architectures they apply to, and each issue lists the
+/// architectures on which the constraint is violated. If the map has no
+/// architectures, the context is checked as is.
+///
+/// Issues are in the order of constraints in the schema.
+pub fn check_constraints<C: ReadContext + ?Sized>(
+	context: &C,
+	schema: &FieldSchema,
+	map: &ArchMap,
+) -> Result<Vec<ConstraintIssue>, ArchError> {
+	let arches = map.arches();
+	let mut violated = schema
+		.constraints()
+		.map(|constraint| (constraint, None))
+		.collect::<Vec<(_, Option<Vec<String>>)>>();
+	if arches.is_empty() {
+		for (constraint, arches) in &mut violated {
+			if !constraint.is_satisfied(context) {
+				*arches = Some(Vec::new());
+			}
+		}
+	}
+	for arch in arches {
+		let resolved = resolve_arch(context, arch, map)?;
+		for (constraint, arches) in &mut violated {
+			if !constraint.is_satisfied(&resolved) {
+				arches.get_or_insert_default().push(arch.clone());
+			}
+		}
+	}
+	Ok(violated
+		.into_iter()
+		.filter_map(|(constraint, arches)| {
+			Some(ConstraintIssue {
+				constraint: constraint.clone(),
+				arches: arches?,
+			})
+		})
+		.collect())
+}
+
+/// A rule producing [`LintDiagnostic`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LintRule {
+	/// See [`check_values`].
+	Values,
+	/// See [`check_relations`].
+	Relations,
+	/// See [`check_splitting`].
+	Splitting,
+	/// See [`check_braces`].
+	Braces,
+	/// See [`check_backticks`].
+	Backticks,
+	/// See [`check_trailing_newline`].
+	TrailingNewline,
+}
+
+/// A machine-applicable fix of a [`LintDiagnostic`].
+///
+/// A fix consists of non-overlapping [`Edit`]s of the source of the
+/// linted LST. See [`apply_fixes`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fix {
+	edits: Vec<Edit>,
+	preserves_evaluation: bool,
+}
+
+impl Fix {
+	/// Creates a fix from edits.
+	///
+	/// `preserves_evaluation` claims that applying the fix does not
+	/// change evaluation results, which is verified by [`apply_fixes`].
+	/// Returns [`None`] if any two edits conflict, see
+	/// [`Edit::conflicts_with`].
+	pub fn new(edits: Vec<Edit>, preserves_evaluation: bool) -> Option<Self> {
+		for (index, edit) in edits.iter().enumerate() {
+			if edits[index + 1..]
+				.iter()
+				.any(|other| edit.conflicts_with(other))
+			{
+				return None;
+			}
+		}
+		Some(Self {
+			edits,
+			preserves_evaluation,
+		})
+	}
+
+	/// Returns the edits of the fix.
+	pub fn edits(&self) -> &[Edit] {
+		&self.edits
+	}
+
+	/// Returns if the fix claims to keep evaluation results.
+	pub fn preserves_evaluation(&self) -> bool {
+		self.preserves_evaluation
+	}
+
+	/// Returns if two fixes cannot be applied together.
+	pub fn conflicts_with(&self, other: &Fix) -> bool {
+		self.edits.iter().any(|edit| {
+			other.edits.iter().any(|other| edit.conflicts_with(other))
+		})
+	}
+}
+
+/// A problem found by a lint, in a form shared by all rules.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LintDiagnostic {
+	/// The rule finding the problem.
+	pub rule: LintRule,
+	/// Span of the problem in the source of the LST.
+	pub span: Span,
+	/// Explanation of the problem, suitable for lint output.
+	pub message: String,
+	/// The fix suggested for the problem, if it can be fixed
+	/// automatically.
+	pub fix: Option<Fix>,
+}
+
+impl LintDiagnostic {
+	fn new(
+		rule: LintRule,
+		span: Span,
+		message: String,
+		edit: Option<Edit>,
+		preserves_evaluation: bool,
+	) -> Self {
+		Self {
+			rule,
+			span,
+			message,
+			fix: edit
+				.and_then(|edit| Fix::new(vec![edit], preserves_evaluation)),
+		}
+	}
+}
+
+impl ValueIssue {
+	/// Converts the issue into a diagnostic on an LST, fixed by
+	/// [`ValueIssue::edit`].
+	pub fn diagnostic(&self, lst: &ApmlLst) -> LintDiagnostic {
+		LintDiagnostic::new(
+			LintRule::Values,
+			self.span,
+			self.explain(),
+			self.edit(lst),
+			false,
+		)
+	}
+}
+
+impl RelationIssue {
+	/// Converts the issue into a diagnostic on an LST, fixed by
+	/// [`RelationIssue::remove_duplicate`].
+	pub fn diagnostic(&self, lst: &ApmlLst) -> LintDiagnostic {
+		LintDiagnostic::new(
+			LintRule::Relations,
+			self.second_span,
+			self.explain(),
+			self.remove_duplicate(lst),
+			false,
+		)
+	}
+}
+
+impl SplittingIssue {
+	/// Converts the issue into a diagnostic on an LST, fixed by
+	/// [`SplittingIssue::quote_array`].
+	pub fn diagnostic(&self, lst: &ApmlLst) -> LintDiagnostic {
+		LintDiagnostic::new(
+			LintRule::Splitting,
+			self.expansion_span,
+			self.explain(),
+			self.quote_array(lst),
+			false,
+		)
+	}
+}
+
+impl BraceIssue {
+	/// Converts the issue into a diagnostic on an LST, fixed by
+	/// [`BraceIssue::add_braces`], which keeps evaluation results.
+	pub fn diagnostic(&self, lst: &ApmlLst) -> LintDiagnostic {
+		LintDiagnostic::new(
+			LintRule::Braces,
+			self.expansion_span,
+			self.explain(),
+			self.add_braces(lst),
+			true,
+		)
+	}
+}
+
+impl BacktickIssue {
+	/// Converts the issue into a diagnostic on an LST, fixed by
+	/// [`BacktickIssue::convert`].
+	pub fn diagnostic(&self, lst: &ApmlLst) -> LintDiagnostic {
+		LintDiagnostic::new(
+			LintRule::Backticks,
+			self.backtick_span,
+			self.explain(),
+			self.convert(lst),
+			false,
+		)
+	}
+}
+
+impl TrailingNewlineIssue {
+	/// Converts the issue into a diagnostic, fixed by
+	/// [`TrailingNewlineIssue::add_newline`].
+	pub fn diagnostic(&self) -> LintDiagnostic {
+		LintDiagnostic::new(
+			LintRule::TrailingNewline,
+			self.span,
+			self.explain(),
+			Some(self.add_newline()),
+			true,
+		)
+	}
+}
+
+/// Runs all rules of [`LintRule`] on an LST.
+///
+/// Fields are taken from the schema, and expansions in arrays are
+/// classified without a seed context, see [`check_splitting`].
+/// Diagnostics are sorted by spans, then by rules.
+pub fn diagnostics(
+	lst: &ApmlLst,
+	schema: &FieldSchema,
+) -> Result<Vec<LintDiagnostic>, ApmlError> {
+	let mut diagnostics = Vec::new();
+	diagnostics.extend(
+		check_values(lst, schema)?
+			.iter()
+			.map(|issue| issue.diagnostic(lst)),
+	);
+	diagnostics.extend(
+		check_relations(lst, schema)?
+			.iter()
+			.map(|issue| issue.diagnostic(lst)),
+	);
+	diagnostics.extend(
+		check_splitting(lst, &ApmlContext::default())
+			.iter()
+			.map(|issue| issue.diagnostic(lst)),
+	);
+	diagnostics
+		.extend(check_braces(lst).iter().map(|issue| issue.diagnostic(lst)));
+	diagnostics.extend(
+		check_backticks(lst)
+			.iter()
+			.map(|issue| issue.diagnostic(lst)),
+	);
+	diagnostics
+		.extend(check_trailing_newline(lst).map(|issue| issue.diagnostic()));
+	diagnostics.sort_by_key(|diagnostic| (diagnostic.span, diagnostic.rule));
+	Ok(diagnostics)
+}
+
+/// Errors produced by [`apply_fixes`].
+#[derive(Debug, Error)]
+pub enum FixError {
+	#[error(transparent)]
+	Edit(#[from] EditError),
+	#[error("Fixes claiming to keep evaluation results change them")]
+	EvaluationChanged,
+}
+
+/// Applies fixes of diagnostics on the LST they are produced from.
+///
+/// Fixes are taken in order, and a fix conflicting with an earlier one
+/// is skipped, so that it can be applied after linting the result
+/// again. Indexes of the diagnostics whose fixes are applied are
+/// returned.
+///
+/// If the LST evaluates, the fixes claiming to
+/// [preserve evaluation][Fix::preserves_evaluation] are verified to do
+/// so before applying anything. On errors, the LST is left unchanged.
+pub fn apply_fixes(
+	lst: &mut ApmlLst,
+	diagnostics: &[LintDiagnostic],
+) -> Result<Vec<usize>, FixError> {
+	let mut applied = Vec::new();
+	let mut fixes = Vec::<&Fix>::new();
+	for (index, diagnostic) in diagnostics.iter().enumerate() {
+		if let Some(fix) = &diagnostic.fix
+			&& !fixes.iter().any(|other| other.conflicts_with(fix))
+		{
+			applied.push(index);
+			fixes.push(fix);
+		}
+	}
+	let commit = |fixes: &mut dyn Iterator<Item = &&Fix>| {
+		let mut session = EditSession::new(lst);
+		session.extend(fixes.flat_map(|fix| fix.edits.iter().cloned()));
+		session.commit()
+	};
+	if fixes.iter().any(|fix| fix.preserves_evaluation)
+		&& let Ok(expected) = ApmlContext::eval_lst(lst)
+	{
+		let (preserved, _) =
+			commit(&mut fixes.iter().filter(|fix| fix.preserves_evaluation))?;
+		if ApmlContext::eval_lst(&preserved).ok() != Some(expected) {
+			return Err(FixError::EvaluationChanged);
+		}
+	}
+	let (fixed, _) = commit(&mut fixes.iter())?;
+	*lst = fixed;
+	Ok(applied)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::session::{EditError, EditSession};
+
+	#[test]
+	fn test_check_values() {
+		let src = "PKGDES=\" a\nb\"\nPKGDES__AMD64=x\nPKGDEP=\"a\"\n\
+			PKGDEP+=\"\tb\"\nBUILDDEP=(\"a\tb\" c)\nFOO=\" \"\n\
+			VER=1\nVER+=\" \"\nVER=2\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let issues = check_values(&lst, &FieldSchema::default()).unwrap();
+		assert_eq!(
+			issues
+				.iter()
+				.map(|issue| (
+					issue.name.as_str(),
+					issue.kind,
+					issue.span.slice(src)
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				("PKGDES", ValueIssueKind::Newline, "PKGDES=\" a\nb\""),
+				(
+					"PKGDES",
+					ValueIssueKind::SurroundingWhitespace,
+					"PKGDES=\" a\nb\""
+				),
+				("PKGDEP", ValueIssueKind::Tab, "PKGDEP+=\"\tb\""),
+				("BUILDDEP", ValueIssueKind::Tab, "BUILDDEP=(\"a\tb\" c)"),
+			]
+		);
+
+		// fixes of the same definition touch different parts of it
+		let mut session = EditSession::new(&lst);
+		for issue in &issues {
+			session.push(issue.edit(&lst).unwrap());
+		}
+		let (lst, _) = session.commit().unwrap();
+		assert_eq!(
+			lst.to_string(),
+			"PKGDES=\"a b\"\nPKGDES__AMD64=x\nPKGDEP=\"a\"\n\
+			PKGDEP+=\" b\"\nBUILDDEP=(\"a b\" c)\nFOO=\" \"\n\
+			VER=1\nVER+=\" \"\nVER=2\n"
+		);
+		assert!(
+			check_values(&lst, &FieldSchema::default())
+				.unwrap()
+				.is_empty()
+		);
+	}
+
+	#[test]
+	fn test_value_fix() {
+		let fix = |fix: ValueFix, src: &str| {
+			let mut lst = ApmlLst::parse(src).unwrap();
+			if let lst::Token::Variable(def) = &mut lst.0[0] {
+				fix.apply(def);
+			}
+			lst.to_string()
+		};
+		assert_eq!(
+			fix(ValueFix::Trim, "A=\" \"' a'$B' '\n"),
+			"A=\"\"'a'$B''\n"
+		);
+		assert_eq!(fix(ValueFix::Trim, "A=(' a ' \\ b)\n"), "A=('a' \\ b)\n");
+		assert_eq!(
+			fix(ValueFix::CollapseWhitespace, "A=\"a \t\n b\"\n"),
+			"A=\"a b\"\n"
+		);
+		assert_eq!(
+			fix(ValueFix::DropTrailingPeriod, "A=\"a. \"'b.'\"  \"\n"),
+			"A=\"a. \"'b'\"  \"\n"
+		);
+		assert_eq!(fix(ValueFix::DropTrailingPeriod, "A=a.$B\n"), "A=a.$B\n");
+	}
+
+	#[test]
+	fn test_check_desc() {
+		let policy = DescPolicy { max_len: 10 };
+		let check = |desc| check_desc(desc, Some("foo"), &policy);
+		assert!(check("A library").is_empty());
+		assert!(check("Wait...").is_empty());
+		assert_eq!(check("A library."), vec![ValueIssueKind::TrailingPeriod]);
+		assert_eq!(
+			check("Foo: a tool\x07"),
+			vec![
+				ValueIssueKind::ControlCharacter,
+				ValueIssueKind::TooLong,
+				ValueIssueKind::StartsWithName
+			]
+		);
+		assert!(check_desc("foo", None, &policy).is_empty());
+
+		let src = "PKGNAME=foo\nPKGDES=\"foo is a tool. \"\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let issues = check_values(&lst, &FieldSchema::default()).unwrap();
+		assert_eq!(
+			issues.iter().map(|issue| issue.kind).collect::<Vec<_>>(),
+			vec![
+				ValueIssueKind::SurroundingWhitespace,
+				ValueIssueKind::TrailingPeriod,
+				ValueIssueKind::StartsWithName,
+			]
+		);
+		let mut session = EditSession::new(&lst);
+		session.extend(issues.iter().filter_map(|issue| issue.edit(&lst)));
+		let (lst, _) = session.commit().unwrap();
+		assert_eq!(lst.to_string(), "PKGNAME=foo\nPKGDES=\"foo is a tool\"\n");
+	}
+
+	#[test]
+	fn test_check_shapes() {
+		let src = "VER=(1 2)\nREL=(1)\nPKGNAME=(a b)\nPKGDES=()\n\
+			PKGSEC+=(x)\nDUMMYSRC=(1)\nPKGDEP=\"a b\"\nBUILDDEP=\"c\"\n\
+			PKGRECOM=d\nSRCS=\"tbl::e\"\nCHKSUMS=SKIP\nPKGDEP__AMD64=(f)\n\
+			FOO=(g)\nPKGEPOCH=1\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let issues = check_shapes(&lst, &FieldSchema::default());
+		assert_eq!(
+			issues
+				.iter()
+				.map(|issue| (
+					issue.name.as_str(),
+					issue.expected,
+					issue.span.slice(src)
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				("VER", FieldType::Scalar, "VER=(1 2)"),
+				("REL", FieldType::Int, "REL=(1)"),
+				("PKGNAME", FieldType::Scalar, "PKGNAME=(a b)"),
+				("PKGDES", FieldType::Scalar, "PKGDES=()"),
+				("PKGSEC", FieldType::Scalar, "PKGSEC+=(x)"),
+				("DUMMYSRC", FieldType::Bool, "DUMMYSRC=(1)"),
+			]
+		);
+		assert_eq!(
+			issues[0].explain(),
+			"VER is assigned an array, but it is a scalar field which must \
+			be written as a string."
+		);
+
+		// strings in array fields are split into words
+		let context = ApmlContext::eval_lst(&lst).unwrap();
+		assert_eq!(context.read("PKGDEP").as_array(), vec!["a", "b"]);
+		assert_eq!(context.read("PKGRECOM").as_array(), vec!["d"]);
+	}
+
+	#[test]
+	fn test_check_relations() {
+		let src = "PKGDEP=\"glibc gcc-runtime\"\nPKGDEP+=\" glibc\"\n\
+			BUILDDEP=(cmake 'ninja')\nBUILDDEP+=(${BUILDDEP[@]})\n\
+			PKGRECOM=\"foo>=1 $PKGRECOM_EXTRA foo<=2\"\nFOO=\"a a\"\n\
+			PKGBREAK=(\"bar\" x bar)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let issues = check_relations(&lst, &FieldSchema::default()).unwrap();
+		assert_eq!(
+			issues
+				.iter()
+				.map(|issue| (
+					issue.kind,
+					issue.second.as_str(),
+					issue.first_span.slice(src),
+					issue.second_span.slice(src)
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				(
+					RelationIssueKind::Duplicate,
+					"glibc",
+					"PKGDEP=\"glibc gcc-runtime\"",
+					"PKGDEP+=\" glibc\""
+				),
+				(
+					RelationIssueKind::Duplicate,
+					"cmake",
+					"BUILDDEP=(cmake 'ninja')",
+					"BUILDDEP+=(${BUILDDEP[@]})"
+				),
+				(
+					RelationIssueKind::Duplicate,
+					"ninja",
+					"BUILDDEP=(cmake 'ninja')",
+					"BUILDDEP+=(${BUILDDEP[@]})"
+				),
+				(
+					RelationIssueKind::Conflict,
+					"foo<=2",
+					"PKGRECOM=\"foo>=1 $PKGRECOM_EXTRA foo<=2\"",
+					"PKGRECOM=\"foo>=1 $PKGRECOM_EXTRA foo<=2\""
+				),
+				(
+					RelationIssueKind::Duplicate,
+					"bar",
+					"PKGBREAK=(\"bar\" x bar)",
+					"PKGBREAK=(\"bar\" x bar)"
+				),
+			]
+		);
+
+		let mut session = EditSession::new(&lst);
+		let fixed = issues
+			.iter()
+			.map(|issue| match issue.remove_duplicate(&lst) {
+				Some(edit) => {
+					session.push(edit);
+					true
+				}
+				None => false,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(fixed, vec![true, false, false, false, true]);
+		let (fixed, _) = session.clone().commit().unwrap();
+		assert_eq!(
+			fixed.to_string(),
+			"PKGDEP=\"glibc gcc-runtime\"\nPKGDEP+=\"\"\n\
+			BUILDDEP=(cmake 'ninja')\nBUILDDEP+=(${BUILDDEP[@]})\n\
+			PKGRECOM=\"foo>=1 $PKGRECOM_EXTRA foo<=2\"\nFOO=\"a a\"\n\
+			PKGBREAK=(\"bar\" x)\n"
+		);
+
+		// trimming the same string conflicts with the removal
+		let trim = ValueFix::Trim.edit(&lst, issues[0].second_span);
+		let index = session.push(trim.unwrap());
+		let Err(EditError::Conflict(conflicts)) = session.commit() else {
+			panic!("edits should conflict");
+		};
+		assert_eq!(
+			conflicts
+				.iter()
+				.map(|conflict| (conflict.first, conflict.second))
+				.collect::<Vec<_>>(),
+			vec![(index, 0)]
+		);
+
+		// prepending keeps origins of the previous elements
+		let src = "PKGREP=a\nPKGREP=\"b $PKGREP a\"\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let issues = check_relations(&lst, &FieldSchema::default()).unwrap();
+		assert_eq!(issues.len(), 1);
+		assert_eq!(issues[0].first_span.slice(src), "PKGREP=a");
+		assert_eq!(issues[0].second_span.slice(src), "PKGREP=\"b $PKGREP a\"");
+	}
+
+	#[test]
+	fn test_remove_word() {
+		let remove = |src: &str, word: &str| {
+			let mut lst = ApmlLst::parse(src).unwrap();
+			let removed = match &mut lst.0[0] {
+				lst::Token::Variable(def) => match &mut def.value {
+					lst::VariableValue::String(text) => {
+						remove_word(Arc::make_mut(text), word)
+					}
+					_ => unreachable!(),
+				},
+				_ => unreachable!(),
+			};
+			(removed, lst.to_string())
+		};
+		assert_eq!(remove("A=\"a b a\"\n", "a"), (true, "A=\"a b\"\n".into()));
+		assert_eq!(remove("A=\"a b\"\n", "a"), (true, "A=\"b\"\n".into()));
+		assert_eq!(remove("A=\"ab b\"\n", "a"), (false, "A=\"ab b\"\n".into()));
+		assert_eq!(
+			remove("A=\"${B}a c\"\n", "a"),
+			(false, "A=\"${B}a c\"\n".into())
+		);
+		assert_eq!(remove("A=\"$B a\"\n", "a"), (true, "A=\"$B\"\n".into()));
+	}
+
+	#[test]
+	fn test_check_splitting() {
+		let src = "S=\"a b\"\nA=(x y)\nA+=z\nB=($S $A ${U} x${A} \"$S\" \
+			${A[@]} $1 $V)\nC=($A)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let mut seed = ApmlContext::default();
+		seed.insert("V".to_string(), VariableValue::Array(vec![]));
+		let issues = check_splitting(&lst, &seed);
+		assert_eq!(
+			issues
+				.iter()
+				.map(|issue| (
+					issue.name.as_str(),
+					issue.expansion_span.slice(src),
+					issue.kind
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				("B", "$S", SplittingKind::Intentional),
+				("B", "$A", SplittingKind::Array),
+				("B", "${U}", SplittingKind::Undefined),
+				("B", "${A}", SplittingKind::Array),
+				("B", "$V", SplittingKind::Array),
+				("C", "$A", SplittingKind::Array),
+			]
+		);
+		assert!(!issues[0].kind.is_suspicious());
+		assert!(issues[1].kind.is_suspicious());
+		assert!(issues[1].explain().contains("\"${A[@]}\""));
+
+		let mut session = EditSession::new(&lst);
+		for issue in &issues {
+			let edit = issue.quote_array(&lst);
+			assert_eq!(
+				edit.is_some(),
+				issue.expansion_span.slice(src) != "${A}"
+					&& issue.kind == SplittingKind::Array
+			);
+			session.extend(edit);
+		}
+		let (lst, _) = session.commit().unwrap();
+		assert_eq!(
+			lst.to_string(),
+			"S=\"a b\"\nA=(x y)\nA+=z\nB=($S \"${A[@]}\" ${U} x${A} \"$S\" \
+			${A[@]} $1 \"${V[@]}\")\nC=(\"${A[@]}\")\n"
+		);
+	}
+
+	#[test]
+	fn test_check_braces() {
+		let src = "A=$B-x\nC=\"$B.$D $E\"$F'_g'$1x\nH=($I/a $J\\_k \"$L\")\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let issues = check_braces(&lst);
+		assert_eq!(
+			issues
+				.iter()
+				.map(|issue| (
+					issue.name.as_str(),
+					issue.expansion_span.slice(src),
+					issue.next,
+					issue.continues_name()
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				("A", "$B", '-', false),
+				("C", "$B", '.', false),
+				("C", "$F", '_', true),
+				("H", "$I", '/', false),
+				("H", "$J", '_', true),
+			]
+		);
+		assert!(issues[0].explain().contains("${B}"));
+
+		let mut session = EditSession::new(&lst);
+		for issue in &issues {
+			session.push(issue.add_braces(&lst).unwrap());
+		}
+		let (lst, map) = session.commit().unwrap();
+		assert_eq!(
+			map.map_span(issues[1].span)
+				.map(|span| span.slice(&lst.to_string()).to_string()),
+			Some("C=\"${B}.$D $E\"${F}'_g'$1x".to_string())
+		);
+		assert_eq!(
+			lst.to_string(),
+			"A=${B}-x\nC=\"${B}.$D $E\"${F}'_g'$1x\nH=(${I}/a ${J}\\_k \"$L\")\n"
+		);
+		assert!(check_braces(&lst).is_empty());
+	}
+
+	#[test]
+	fn test_check_backticks() {
+		let src = "A=`a`\nB=\"x `b c` y`d`\" # `e`\nC='`f`' D=(`g` $'`h`')\n\
+			E=$\"`i`\"\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let issues = check_backticks(&lst);
+		assert_eq!(
+			issues
+				.iter()
+				.map(|issue| (
+					issue.name.as_str(),
+					issue.command.as_str(),
+					issue.backtick_span.slice(src)
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				("A", "a", "`a`"),
+				("B", "b c", "`b c`"),
+				("B", "d", "`d`"),
+				("D", "g", "`g`"),
+				("E", "i", "`i`"),
+			]
+		);
+		assert!(issues[1].explain().contains("$(b c)"));
+
+		let mut session = EditSession::new(&lst);
+		for issue in &issues {
+			session.push(issue.convert(&lst).unwrap());
+		}
+		let (lst, _) = session.commit().unwrap();
+		assert_eq!(
+			lst.to_string(),
+			"A=$(a)\nB=\"x $(b c) y$(d)\" # `e`\nC='`f`' D=($(g) $'`h`')\n\
+			E=$\"$(i)\"\n"
+		);
+		assert!(check_backticks(&lst).is_empty());
+	}
+
+	#[test]
+	fn test_check_trailing_newline() {
+		for src in ["", " \n\t", "A=1\n", "# x\nA=(\n\t1\n)\n"] {
+			let lst = ApmlLst::parse(src).unwrap();
+			assert_eq!(check_trailing_newline(&lst), None, "{src:?}");
+		}
+
+		let src = "A=1\nB=2 # b";
+		let lst = ApmlLst::parse(src).unwrap();
+		let issue = check_trailing_newline(&lst).unwrap();
+		assert_eq!(issue.span.slice(src), "B=2 # b");
+		let mut fixed = lst.clone();
+		let diagnostics = diagnostics(&lst, &FieldSchema::default()).unwrap();
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].rule, LintRule::TrailingNewline);
+		assert_eq!(apply_fixes(&mut fixed, &diagnostics).unwrap(), vec![0]);
+		assert_eq!(fixed.to_string(), "A=1\nB=2 # b\n");
+		assert!(fixed.ends_with_newline());
+		assert_eq!(check_trailing_newline(&fixed), None);
+
+		let src = "A=\"x\ny\"";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(
+			check_trailing_newline(&lst).unwrap().span.slice(src),
+			"y\""
+		);
+		let src = "A=1 \\\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let mut session = EditSession::new(&lst);
+		session.push(check_trailing_newline(&lst).unwrap().add_newline());
+		let (lst, _) = session.commit().unwrap();
+		assert_eq!(lst.to_string(), "A=1 \\\n\n");
+		assert!(lst.ends_with_newline());
+	}
+
+	#[test]
+	fn test_check_locale_quotes() {
+		let src = "A=$\"x $B\"y\nC=\"\\$\"\nD=(a $\"b\" \"c\")\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(lst.to_string(), src);
+		let issues = check_locale_quotes(&lst);
+		assert_eq!(
+			issues
+				.iter()
+				.map(|issue| (issue.name.as_str(), issue.quote_span.slice(src)))
+				.collect::<Vec<_>>(),
+			vec![("A", "$\"x $B\""), ("D", "$\"b\"")]
+		);
+		assert_eq!(
+			issues[0].explain(),
+			"A contains a locale-translated string, which is not translated \
+			but evaluated as a double-quoted string."
+		);
+		crate::assert_evals_to!(&format!("B=1\n{}", src), {
+			"A" => "x 1y",
+			"C" => "$",
+			"D" => ["a", "b", "c"],
+		});
+	}
+
+	#[test]
+	fn test_check_control_characters() {
+		let src = "PKGDES=\"a\rb\x0c\"\nPKGDEP=(a\x0b b)\nA='c\r\n'\n\
+			B=$'\\r'\nPKGSEC=\"\r\"\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(lst.to_string(), src);
+		let issues = check_control_characters(&lst);
+		assert_eq!(
+			issues
+				.iter()
+				.map(|issue| (
+					issue.name.as_str(),
+					issue.ch,
+					issue.char_span.slice(src)
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				("PKGDES", '\r', "\r"),
+				("PKGDES", '\x0c', "\x0c"),
+				("PKGDEP", '\x0b', "\x0b"),
+				("PKGSEC", '\r', "\r"),
+			]
+		);
+		assert_eq!(issues[0].char_span, Span::with_len(9, 1));
+		assert_eq!(issues[0].span.slice(src), "PKGDES=\"a\rb\x0c\"");
+		assert_eq!(
+			issues[2].explain(),
+			"PKGDEP contains a vertical tab, which is invisible in most \
+			editors. Write it with an escape sequence in $'...' if it is \
+			intended."
+		);
+
+		let issues = check_values(&lst, &FieldSchema::default()).unwrap();
+		assert_eq!(
+			issues
+				.iter()
+				.filter(|issue| issue.kind == ValueIssueKind::InvisibleControl)
+				.map(|issue| issue.name.as_str())
+				.collect::<Vec<_>>(),
+			vec!["PKGDES", "PKGSEC"]
+		);
+		assert!(issues.iter().all(|issue| issue.name != "PKGDEP"));
+	}
+
+	/// Adding braces must not change evaluation results of any source in
+	/// the conformance corpus and the test tree.
+	#[test]
+	fn test_add_braces_corpus() {
+		fn collect(dir: &std::path::Path, out: &mut Vec<std::path::PathBuf>) {
+			for entry in std::fs::read_dir(dir).unwrap() {
+				let path = entry.unwrap().path();
+				if path.is_dir() {
+					collect(&path, out);
+				} else if path.extension().is_some_and(|ext| ext == "apml")
+					|| path.ends_with("spec")
+					|| path.ends_with("defines")
+				{
+					out.push(path);
+				}
+			}
+		}
+
+		let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+		let mut paths = Vec::new();
+		collect(&root.join("conformance"), &mut paths);
+		collect(&root.join("testrepo"), &mut paths);
+		let mut fixed = 0;
+		for path in paths {
+			let src = std::fs::read_to_string(&path).unwrap();
+			let Ok(lst) = ApmlLst::parse(&src) else {
+				continue;
+			};
+			let Ok(expected) = ApmlContext::eval_lst(&lst) else {
+				continue;
+			};
+			let issues = check_braces(&lst);
+			let mut session = EditSession::new(&lst);
+			for issue in &issues {
+				let edit = issue.add_braces(&lst);
+				assert!(edit.is_some(), "{}", path.display());
+				session.extend(edit);
+			}
+			fixed += issues.len();
+			let (lst, _) = session.commit().unwrap();
+			assert!(check_braces(&lst).is_empty());
+			crate::assert_context_eq!(
+				ApmlContext::eval_lst(&lst).unwrap(),
+				expected,
+				"{}",
+				path.display()
+			);
+		}
+		assert!(fixed > 0);
+	}
+
+	#[test]
+	fn test_check_override_conflicts() {
+		let context = ApmlContext::eval_source(
+			"B__X=1\nB__Y=2\nA__X=1\nA__Y=2\nA__AMD64=3\nC__X=1\nC__Z=2\n",
+		)
+		.unwrap();
+		let mut map = ArchMap::empty();
+		map.insert_group("x", ["amd64", "arm64"]);
+		map.insert_group("y", ["amd64", "arm64"]);
+		map.insert_group("z", ["arm64"]);
+		let issues = check_override_conflicts(&context, &map);
+		assert_eq!(
+			issues,
+			vec![
+				OverrideConflictIssue {
+					name: "A".into(),
+					groups: vec!["x".into(), "y".into()],
+					arches: vec!["arm64".into()],
+				},
+				OverrideConflictIssue {
+					name: "B".into(),
+					groups: vec!["x".into(), "y".into()],
+					arches: vec!["amd64".into(), "arm64".into()],
+				},
+			]
+		);
+		assert_eq!(
+			issues[1].explain(),
+			"B is overridden for groups of the same size (x, y), so none of \
+			them takes precedence on amd64, arm64."
+		);
+		assert!(
+			check_override_conflicts(&context, &ArchMap::aosc()).is_empty()
+		);
+	}
+
+	#[test]
+	fn test_check_fail_arch() {
+		let map = ArchMap::aosc();
+		let check = |src| {
+			check_fail_arch(&ApmlContext::eval_source(src).unwrap(), &map)
+		};
+		assert!(check("FAIL_ARCH=\"!(amd64|ARM64)\"\n").is_empty());
+		assert!(check("A=1\n").is_empty());
+		let issues = check("FAIL_ARCH=\"@(i586|loongson*|mips64r6el|i586)\"\n");
+		assert_eq!(
+			issues,
+			vec![
+				FailArchIssue {
+					arch: "i586".into()
+				},
+				FailArchIssue {
+					arch: "mips64r6el".into()
+				},
+			]
+		);
+		assert_eq!(
+			issues[0].explain(),
+			"FAIL_ARCH mentions i586, which is not a known architecture."
+		);
+	}
+
+	#[test]
+	fn test_check_constraints() {
+		let mut map = ArchMap::empty();
+		map.insert_group("a", ["amd64", "arm64"]);
+		map.insert_group("b", ["riscv"]);
+		let schema = FieldSchema::default();
+		let context = ApmlContext::eval_source(
+			"DUMMYSRC=1\nSRCS__AMD64=\"tbl::x\"\nCHKSUMS__B=\"SKIP\"\n\
+			NOSTATIC=1\nABSTATIC=1\n",
+		)
+		.unwrap();
+		let issues = check_constraints(&context, &schema, &map).unwrap();
+		assert_eq!(
+			issues
+				.iter()
+				.map(|issue| (issue.constraint.to_string(), issue.explain()))
+				.collect::<Vec<_>>(),
+			vec![
+				(
+					"DUMMYSRC conflicts with SRCS".to_string(),
+					"DUMMYSRC and SRCS are set together on amd64.".to_string()
+				),
+				(
+					"DUMMYSRC conflicts with CHKSUMS".to_string(),
+					"DUMMYSRC and CHKSUMS are set together on riscv."
+						.to_string()
+				),
+				(
+					"CHKSUMS requires SRCS".to_string(),
+					"CHKSUMS is set, but SRCS is not on riscv.".to_string()
+				),
+				(
+					"NOSTATIC conflicts with ABSTATIC".to_string(),
+					"NOSTATIC and ABSTATIC are set together on amd64, arm64, \
+					riscv."
+						.to_string()
+				),
+			]
+		);
+
+		let schema = FieldSchema::builder()
+			.constraint(FieldConstraint::implies("ABTYPE", "dummy", "X", "1"))
+			.build();
+		let context = ApmlContext::eval_source("ABTYPE=dummy\n").unwrap();
+		let issues =
+			check_constraints(&context, &schema, &ArchMap::empty()).unwrap();
+		assert_eq!(issues.len(), 1);
+		assert_eq!(
+			issues[0].explain(),
+			"ABTYPE is \"dummy\", but X is not \"1\"."
+		);
+	}
+
+	#[test]
+	fn test_apply_fixes() {
+		let src = "PKGDES=\" a \"\nB=$C-x\n";
+		let mut lst = ApmlLst::parse(src).unwrap();
+		let diagnostics = diagnostics(&lst, &FieldSchema::default()).unwrap();
+		assert_eq!(
+			diagnostics
+				.iter()
+				.map(|diagnostic| (diagnostic.rule, diagnostic.span.slice(src)))
+				.collect::<Vec<_>>(),
+			vec![
+				(LintRule::Values, "PKGDES=\" a \""),
+				(LintRule::Braces, "$C"),
+			]
+		);
+		assert!(diagnostics[1].fix.as_ref().unwrap().preserves_evaluation());
+		assert_eq!(apply_fixes(&mut lst, &diagnostics).unwrap(), vec![0, 1]);
+		assert_eq!(lst.to_string(), "PKGDES=\"a\"\nB=${C}-x\n");
+
+		let edit = Edit::new(Span::new(2, 3), "b");
+		assert!(Fix::new(vec![edit.clone(), edit.clone()], false).is_none());
+		let changing = LintDiagnostic {
+			rule: LintRule::Braces,
+			span: Span::new(2, 3),
+			message: String::new(),
+			fix: Fix::new(vec![edit], true),
+		};
+		let mut lst = ApmlLst::parse("A=a\n").unwrap();
+		assert!(matches!(
+			apply_fixes(&mut lst, &[changing]),
+			Err(FixError::EvaluationChanged)
+		));
+		assert_eq!(lst.to_string(), "A=a\n");
+	}
+
+	#[test]
+	fn test_apply_fixes_messy() {
+		let src = include_str!("../../testdata/lint/messy.apml");
+		let schema = FieldSchema::default();
+		let mut lst = ApmlLst::parse(src).unwrap();
+		let expected = ApmlContext::eval_lst(&lst).unwrap();
+		let mut rules = Vec::new();
+		for _ in 0..4 {
+			let diagnostics = diagnostics(&lst, &schema).unwrap();
+			if diagnostics.is_empty() {
+				break;
+			}
+			assert!(
+				diagnostics
+					.iter()
+					.all(|diagnostic| diagnostic.fix.is_some())
+			);
+			let applied = apply_fixes(&mut lst, &diagnostics).unwrap();
+			assert!(!applied.is_empty());
+			rules.extend(applied.iter().map(|index| diagnostics[*index].rule));
+		}
+		assert!(diagnostics(&lst, &schema).unwrap().is_empty());
+		rules.sort();
+		rules.dedup();
+		assert_eq!(
+			rules,
+			vec![
+				LintRule::Values,
+				LintRule::Relations,
+				LintRule::Splitting,
+				LintRule::Braces,
+				LintRule::Backticks,
+			]
+		);
+
+		let context = ApmlContext::eval_lst(&lst).unwrap();
+		for name in ["VER", "PKGNAME", "DEPS", "EXTRA", "SRCS"] {
+			assert_eq!(context.get(name), expected.get(name), "{name}");
+		}
+		assert_eq!(context["PKGDES"].as_string(), "A messy package");
+		assert_eq!(context["PKGDEP"].as_string(), "glibc zlib openssl");
+		assert_eq!(
+			context["BUILDDEP"].as_array(),
+			vec!["cmake", "libfoo", "libbar"]
+		);
+	}
+}
@@ -17,23 +17,27 @@
 //! the serialized APML may be invalid.
 //! </div>
 
-use std::{
-	borrow::Cow,
+use alloc::{borrow::Cow, sync::Arc};
+use core::{
 	fmt::{Debug, Display, Write},
-	sync::Arc,
+	ops::Range,
 };
 
 use super::{
-	parser::{ParseError, apml_lst},
+	parser::{ParseError, ParseOptions, apml_lst},
 	pattern::BashPattern,
+	span::{Span, display_len},
 };
 
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
 /// A APML parse-tree, consisting of a list of tokens.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ApmlLst<'a>(pub Vec<Token<'a>>);
 
 impl Display for ApmlLst<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		for token in &self.0 {
 			Display::fmt(token, f)?;
 		}
@@ -47,9 +51,42 @@ impl<'a> ApmlLst<'a> {
 	/// This is a wrapper calling [`apml_lst`] parser combinator,
 	/// while errors produced by the parser are converted into [`ParseError`],
 	/// and [`ParseError::UnexpectedSource`] is produced when
-	/// there are some unparsable texts in the input.
+	/// there are some unparsable texts in the input. Unterminated or
+	/// invalid `${...}` expansions produce
+	/// [`ParseError::MalformedExpansion`] instead, pointing to the `$`.
+	///
+	/// Sequences such as arrays and concatenated words are parsed in loops,
+	/// so the stack does not grow with their lengths. On 64-bit targets,
+	/// both parsing and [evaluating][super::ApmlContext::eval_lst] the LST
+	/// use at most 64 times the size of the source in memory.
 	pub fn parse(src: &'a str) -> Result<Self, ParseError> {
-		let (out, tree) = apml_lst(src)?;
+		Self::parse_with_options(src, ParseOptions::default())
+	}
+
+	/// Parses a APML source string with custom expansions
+	/// (`${@name args}`) accepted if any is supplied by the options.
+	///
+	/// See [`EvalOptions::custom_expansions`].
+	///
+	/// [`EvalOptions::custom_expansions`]: super::eval::EvalOptions::custom_expansions
+	pub fn parse_with(
+		src: &'a str,
+		options: &super::eval::EvalOptions,
+	) -> Result<Self, ParseError> {
+		Self::parse_with_options(src, options.into())
+	}
+
+	/// Parses a APML source string with options of the parser.
+	///
+	/// See [`ApmlLst::parse`].
+	pub fn parse_with_options(
+		src: &'a str,
+		options: ParseOptions,
+	) -> Result<Self, ParseError> {
+		#[cfg(feature = "tracing")]
+		let _span = tracing::debug_span!("apml_parse", len = src.len()).entered();
+		let (out, tree) = apml_lst(src, options)
+			.map_err(|err| ParseError::locate(src, err))?;
 		if !out.is_empty() {
 			return Err(ParseError::UnexpectedSource {
 				pos: nom::Offset::offset(src, out) + 1,
@@ -57,6 +94,175 @@ impl<'a> ApmlLst<'a> {
 		}
 		Ok(tree)
 	}
+
+	/// Iterates over all tokens along with their spans in the source.
+	pub fn token_spans(&self) -> impl Iterator<Item = (Span, &Token<'a>)> {
+		let mut pos = 0;
+		self.0.iter().map(move |token| {
+			let span = Span::with_len(pos, display_len(token));
+			pos = span.end;
+			(span, token)
+		})
+	}
+
+	/// Iterates over all variable definitions along with their spans.
+	///
+	/// Each [`ast::VariableDefinition`] emitted from the LST corresponds to
+	/// one item of this iterator, in the same order.
+	///
+	/// [`ast::VariableDefinition`]: super::ast::VariableDefinition
+	pub fn variable_spans(
+		&self,
+	) -> impl Iterator<Item = (Span, &VariableDefinition<'a>)> {
+		self.token_spans().filter_map(|(span, token)| match token {
+			Token::Variable(def) => Some((span, def)),
+			_ => None,
+		})
+	}
+
+	/// Returns if the source ends with a newline.
+	///
+	/// Sources are kept byte by byte, so a missing newline at the end is
+	/// preserved when serialized. A line continuation at the end continues
+	/// the last line instead of ending it, and empty sources do not end
+	/// with a newline either.
+	pub fn ends_with_newline(&self) -> bool {
+		matches!(self.0.last(), Some(Token::Newline))
+	}
+
+	/// Returns if all tokens are [empty][Token::is_empty], including the
+	/// case of an empty source.
+	///
+	/// Blank sources evaluate to empty contexts.
+	pub fn is_blank(&self) -> bool {
+		self.0.iter().all(Token::is_empty)
+	}
+
+	/// Iterates over runs of consecutive blank lines.
+	///
+	/// A blank line is a line consisting of spaces only, terminated by
+	/// a newline. Each run is given as the range of indexes of the tokens
+	/// of its lines, including the newlines.
+	pub fn blank_line_runs(&self) -> impl Iterator<Item = Range<usize>> {
+		let mut line_start = 0;
+		let mut run: Option<Range<usize>> = None;
+		let mut runs = Vec::new();
+		for (index, token) in self.0.iter().enumerate() {
+			match token {
+				Token::Spacy(_) | Token::LineContinuation => {}
+				Token::Newline => {
+					let blank = self.0[line_start..index]
+						.iter()
+						.all(|token| matches!(token, Token::Spacy(_)));
+					if blank {
+						run.get_or_insert(line_start..index).end = index + 1;
+					} else if let Some(run) = run.take() {
+						runs.push(run);
+					}
+					line_start = index + 1;
+				}
+				Token::Comment(_) | Token::Variable(_) | Token::Set(_) => {
+					if let Some(run) = run.take() {
+						runs.push(run);
+					}
+				}
+			}
+		}
+		runs.extend(run);
+		runs.into_iter()
+	}
+
+	/// Summarizes the quoting styles used in the value of the first
+	/// definition of a variable.
+	///
+	/// Returns [`None`] if the variable is not defined.
+	pub fn quoting_of(&self, name: &str) -> Option<QuotingSummary> {
+		self.0.iter().find_map(|token| match token {
+			Token::Variable(def) if def.name == name => {
+				Some(QuotingSummary::of_value(&def.value))
+			}
+			_ => None,
+		})
+	}
+}
+
+/// Counts of text units in each quoting style of a value.
+///
+/// See [`ApmlLst::quoting_of`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct QuotingSummary {
+	/// Number of unquoted units.
+	pub unquoted: usize,
+	/// Number of single-quoted units, including ANSI-C quoted units
+	/// (`$'text'`).
+	pub single_quoted: usize,
+	/// Number of double-quoted units.
+	pub double_quoted: usize,
+}
+
+impl QuotingSummary {
+	/// Summarizes the text units of a text.
+	pub fn of_text(text: &Text) -> Self {
+		let mut summary = Self::default();
+		summary.add_text(text);
+		summary
+	}
+
+	/// Summarizes the text units of a value, including all array elements.
+	pub fn of_value(value: &VariableValue) -> Self {
+		let mut summary = Self::default();
+		match value {
+			VariableValue::String(text) => summary.add_text(text),
+			VariableValue::Array(tokens) => {
+				for token in tokens {
+					if let ArrayToken::Element(text) = token {
+						summary.add_text(text);
+					}
+				}
+			}
+		}
+		summary
+	}
+
+	fn add_text(&mut self, text: &Text) {
+		for unit in &text.0 {
+			match unit {
+				TextUnit::Unquoted(_) => self.unquoted += 1,
+				TextUnit::SingleQuote(_) | TextUnit::AnsiCQuote(_) => {
+					self.single_quoted += 1
+				}
+				TextUnit::DoubleQuote(_) | TextUnit::LocaleQuote(_) => {
+					self.double_quoted += 1
+				}
+			}
+		}
+	}
+
+	/// Returns the overall quoting style.
+	///
+	/// Values without any text unit, such as `A=`, are considered
+	/// [bare](QuotingStyle::Bare).
+	pub fn style(&self) -> QuotingStyle {
+		match (self.unquoted, self.single_quoted, self.double_quoted) {
+			(_, 0, 0) => QuotingStyle::Bare,
+			(0, _, 0) => QuotingStyle::SingleQuoted,
+			(0, 0, _) => QuotingStyle::DoubleQuoted,
+			_ => QuotingStyle::Mixed,
+		}
+	}
+}
+
+/// Overall quoting style of a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuotingStyle {
+	/// Only unquoted units (`A=value`).
+	Bare,
+	/// Only single-quoted units (`A='value'`).
+	SingleQuoted,
+	/// Only double-quoted units (`A="value"`).
+	DoubleQuoted,
+	/// Units in more than one style (`A="value"'s'`).
+	Mixed,
 }
 
 /// A token in the LST.
@@ -70,27 +276,61 @@ pub enum Token<'a> {
 	Spacy(char),
 	/// A newline character (`'\n'`, ASCII code 0x0A).
 	Newline,
+	/// A line continuation (`"\\\n"`) between tokens.
+	///
+	/// As in bash, this joins the next line, so the statement goes on
+	/// unless the next line is blank or a comment.
+	LineContinuation,
 	/// A comment (`"#<text>"`).
 	Comment(Cow<'a, str>),
 	/// A variable definition.
 	Variable(VariableDefinition<'a>),
+	/// A `set` statement changing shell options.
+	Set(SetCommand<'a>),
 }
 
 impl Token<'_> {
-	/// Returns if the token is a space or a newline.
+	/// Returns if the token is a space, a newline or a line
+	/// continuation.
 	pub fn is_empty(&self) -> bool {
-		matches!(&self, Token::Newline | Token::Spacy(_))
+		matches!(
+			&self,
+			Token::Newline | Token::Spacy(_) | Token::LineContinuation
+		)
 	}
 }
 
 impl Display for Token<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
 			Token::Spacy(ch) => f.write_char(*ch),
 			Token::Newline => f.write_char('\n'),
+			Token::LineContinuation => f.write_str("\\\n"),
 			Token::Comment(text) => f.write_fmt(format_args!("#{}", text)),
 			Token::Variable(def) => Display::fmt(def, f),
+			Token::Set(set) => Display::fmt(set, f),
+		}
+	}
+}
+
+/// A `set` statement (`"set <args>"`), such as `set -eu`.
+///
+/// Arguments are kept as is and are interpreted during evaluation, see
+/// [`ShellOptions`][super::eval::ShellOptions].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SetCommand<'a> {
+	/// Arguments, each along with the whitespaces before it.
+	pub args: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+}
+
+impl Display for SetCommand<'_> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.write_str("set")?;
+		for (space, arg) in &self.args {
+			f.write_str(space)?;
+			f.write_str(arg)?;
 		}
+		Ok(())
 	}
 }
 
@@ -106,7 +346,7 @@ pub struct VariableDefinition<'a> {
 }
 
 impl Display for VariableDefinition<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		f.write_str(&self.name)?;
 		Display::fmt(&self.op, f)?;
 		Display::fmt(&self.value, f)?;
@@ -114,6 +354,78 @@ impl Display for VariableDefinition<'_> {
 	}
 }
 
+impl<'a> VariableDefinition<'a> {
+	/// Returns words in unquoted and double-quoted texts of the value
+	/// along with their spans.
+	///
+	/// Spans are relative to the start of the definition.
+	/// Words nested in modifiers and sub-commands are not included.
+	pub(crate) fn word_spans(&self) -> Vec<(Span, &Word<'a>)> {
+		fn walk_text<'a, 'b>(
+			text: &'b Text<'a>,
+			mut pos: usize,
+			out: &mut Vec<(Span, &'b Word<'a>)>,
+		) {
+			for unit in &text.0 {
+				let words = match unit {
+					TextUnit::Unquoted(words) => Some((words, pos)),
+					TextUnit::DoubleQuote(words) => Some((words, pos + 1)),
+					TextUnit::LocaleQuote(words) => Some((words, pos + 2)),
+					TextUnit::SingleQuote(_) | TextUnit::AnsiCQuote(_) => None,
+				};
+				if let Some((words, mut pos)) = words {
+					for word in words {
+						let span = Span::with_len(pos, display_len(word));
+						out.push((span, word));
+						pos = span.end;
+					}
+				}
+				pos += display_len(unit);
+			}
+		}
+
+		let mut result = Vec::new();
+		let start = self.name.len() + display_len(&self.op);
+		match &self.value {
+			VariableValue::String(text) => walk_text(text, start, &mut result),
+			VariableValue::Array(tokens) => {
+				let mut pos = start + 1;
+				for token in tokens {
+					if let ArrayToken::Element(text) = token {
+						walk_text(text, pos, &mut result);
+					}
+					pos += display_len(token);
+				}
+			}
+		}
+		result
+	}
+
+	/// Returns braced expansions with `[@]` or `[*]` in the value.
+	///
+	/// Spans are relative to the start of the definition.
+	/// Expansions nested in modifiers are not included.
+	pub(crate) fn array_expansions(&self) -> Vec<(Span, &BracedExpansion<'a>)> {
+		self.word_spans()
+			.into_iter()
+			.filter_map(|(span, word)| match word {
+				Word::BracedVariable(exp)
+					if matches!(
+						exp.modifier,
+						Some(
+							ExpansionModifier::ArrayElements
+								| ExpansionModifier::SingleWordElements
+						)
+					) =>
+				{
+					Some((span, exp))
+				}
+				_ => None,
+			})
+			.collect()
+	}
+}
+
 /// A variable operator.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum VariableOp {
@@ -124,7 +436,7 @@ pub enum VariableOp {
 }
 
 impl Display for VariableOp {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
 			VariableOp::Assignment => f.write_char('='),
 			VariableOp::Append => f.write_str("+="),
@@ -142,7 +454,7 @@ pub enum VariableValue<'a> {
 }
 
 impl Display for VariableValue<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
 			VariableValue::String(text) => Display::fmt(text, f),
 			VariableValue::Array(tokens) => {
@@ -167,7 +479,7 @@ impl Display for VariableValue<'_> {
 pub struct Text<'a>(pub Vec<TextUnit<'a>>);
 
 impl Display for Text<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		for unit in &self.0 {
 			Display::fmt(unit, f)?;
 		}
@@ -186,10 +498,20 @@ pub enum TextUnit<'a> {
 	SingleQuote(Cow<'a, str>),
 	/// A double-quoted text unit (`"\"<words>\""`).
 	DoubleQuote(Vec<Word<'a>>),
+	/// An ANSI-C quoted text unit (`"$'<text>'"`).
+	///
+	/// The text is kept escaped. Escape sequences are decoded during
+	/// evaluation, as they may produce invalid UTF-8.
+	AnsiCQuote(Cow<'a, str>),
+	/// A locale-translated text unit (`"$\"<words>\""`).
+	///
+	/// Translation is not supported, so this is evaluated as
+	/// a double-quoted text unit.
+	LocaleQuote(Vec<Word<'a>>),
 }
 
 impl Display for TextUnit<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
 			TextUnit::Unquoted(words) => {
 				for word in words {
@@ -200,6 +522,9 @@ impl Display for TextUnit<'_> {
 			TextUnit::SingleQuote(text) => {
 				f.write_fmt(format_args!("'{}'", text))
 			}
+			TextUnit::AnsiCQuote(text) => {
+				f.write_fmt(format_args!("$'{}'", text))
+			}
 			TextUnit::DoubleQuote(words) => {
 				f.write_char('"')?;
 				for word in words {
@@ -208,6 +533,14 @@ impl Display for TextUnit<'_> {
 				f.write_char('"')?;
 				Ok(())
 			}
+			TextUnit::LocaleQuote(words) => {
+				f.write_str("$\"")?;
+				for word in words {
+					Display::fmt(word, f)?;
+				}
+				f.write_char('"')?;
+				Ok(())
+			}
 		}
 	}
 }
@@ -223,10 +556,12 @@ pub enum Word<'a> {
 	BracedVariable(BracedExpansion<'a>),
 	/// A sub-command expansion (`"$(<tokens>)"`).
 	Subcommand(Vec<ArrayToken<'a>>),
+	/// A custom expansion (`"${@<name> <args>}"`).
+	Custom(CustomExpansion<'a>),
 }
 
 impl Display for Word<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
 			Word::Literal(parts) => {
 				for part in parts {
@@ -248,7 +583,37 @@ impl Display for Word<'_> {
 				f.write_str(")")?;
 				Ok(())
 			}
+			Word::Custom(exp) => Display::fmt(exp, f),
+		}
+	}
+}
+
+/// A custom expansion (`"${@<name> <args>}"`), such as
+/// `${@rel_ver PKGVER}`.
+///
+/// This is not bash syntax, and is only accepted by the parser when
+/// custom expansions are supplied, see
+/// [`EvalOptions::custom_expansions`].
+///
+/// [`EvalOptions::custom_expansions`]: super::eval::EvalOptions::custom_expansions
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CustomExpansion<'a> {
+	/// Name of the expansion.
+	pub name: Cow<'a, str>,
+	/// Names of variables passed as arguments, each along with the
+	/// whitespaces before it.
+	pub args: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+}
+
+impl Display for CustomExpansion<'_> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.write_str("${@")?;
+		f.write_str(&self.name)?;
+		for (space, arg) in &self.args {
+			f.write_str(space)?;
+			f.write_str(arg)?;
 		}
+		f.write_str("}")
 	}
 }
 
@@ -264,7 +629,7 @@ pub enum LiteralPart<'a> {
 }
 
 impl Display for LiteralPart<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
 			LiteralPart::String(text) => f.write_str(text),
 			LiteralPart::Escaped(ch) => f.write_fmt(format_args!("\\{}", ch)),
@@ -305,6 +670,21 @@ impl LiteralPart<'_> {
 	}
 }
 
+/// Returns byte offsets of the carriage returns not followed by
+/// a newline, vertical tabs and form feeds in a string.
+///
+/// Such characters are invisible in most views of a source, and stray
+/// carriage returns are dropped or moved by tools normalizing line
+/// endings. They are kept as is in LSTs, and emitters escape them with
+/// [`TextUnit::ansi_c_quote`].
+pub fn invisible_controls(text: &str) -> impl Iterator<Item = usize> + '_ {
+	text.char_indices().filter_map(|(pos, ch)| match ch {
+		'\r' if !text[pos + 1..].starts_with('\n') => Some(pos),
+		'\x0b' | '\x0c' => Some(pos),
+		_ => None,
+	})
+}
+
 /// A braced variable expansion (`"<name>[modifier]"`).
 ///
 /// Note that for [ExpansionModifier::Length], the format is `"#<name>"`.
@@ -317,7 +697,7 @@ pub struct BracedExpansion<'a> {
 }
 
 impl Display for BracedExpansion<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match &self.modifier {
 			Some(ExpansionModifier::Length) => {
 				f.write_fmt(format_args!("#{}", self.name))
@@ -404,7 +784,7 @@ pub enum ExpansionModifier<'a> {
 }
 
 impl Display for ExpansionModifier<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
 			ExpansionModifier::Substring { offset, length } => match length {
 				None => f.write_fmt(format_args!(":{}", offset)),
@@ -491,6 +871,12 @@ pub enum ArrayToken<'a> {
 	Spacy(char),
 	/// A newline character (`'\n'`, ASCII code 0x0A).
 	Newline,
+	/// A line continuation (`"\\\n"`) between elements.
+	///
+	/// See [Token::LineContinuation] for more. Line continuations
+	/// within elements are [literal parts][LiteralPart::LineContinuation]
+	/// instead.
+	LineContinuation,
 	/// A comment (`"#<text>"`).
 	Comment(Cow<'a, str>),
 	/// A array element (`"<text>"`).
@@ -498,10 +884,11 @@ pub enum ArrayToken<'a> {
 }
 
 impl Display for ArrayToken<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
 			ArrayToken::Spacy(ch) => f.write_char(*ch),
 			ArrayToken::Newline => f.write_char('\n'),
+			ArrayToken::LineContinuation => f.write_str("\\\n"),
 			ArrayToken::Comment(text) => {
 				f.write_char('#')?;
 				f.write_str(text)?;
@@ -511,6 +898,256 @@ impl Display for ArrayToken<'_> {
 		}
 	}
 }
+
+fn owned_str(text: Cow<'_, str>) -> Cow<'static, str> {
+	Cow::Owned(text.into_owned())
+}
+
+fn owned_text(text: Arc<Text<'_>>) -> Arc<Text<'static>> {
+	Arc::new(Arc::unwrap_or_clone(text).into_owned())
+}
+
+fn owned_array_tokens(tokens: Vec<ArrayToken<'_>>) -> Vec<ArrayToken<'static>> {
+	tokens.into_iter().map(ArrayToken::into_owned).collect()
+}
+
+fn owned_words(words: Vec<Word<'_>>) -> Vec<Word<'static>> {
+	words.into_iter().map(Word::into_owned).collect()
+}
+
+impl ApmlLst<'_> {
+	/// Converts the LST into one owning all strings, so that it no longer
+	/// borrows from the source.
+	pub fn into_owned(self) -> ApmlLst<'static> {
+		ApmlLst(self.0.into_iter().map(Token::into_owned).collect())
+	}
+}
+
+impl Token<'_> {
+	/// Converts the token into one owning all strings.
+	pub fn into_owned(self) -> Token<'static> {
+		match self {
+			Token::Spacy(ch) => Token::Spacy(ch),
+			Token::Newline => Token::Newline,
+			Token::LineContinuation => Token::LineContinuation,
+			Token::Comment(text) => Token::Comment(owned_str(text)),
+			Token::Variable(def) => Token::Variable(def.into_owned()),
+			Token::Set(set) => Token::Set(set.into_owned()),
+		}
+	}
+}
+
+impl SetCommand<'_> {
+	/// Converts the statement into one owning all strings.
+	pub fn into_owned(self) -> SetCommand<'static> {
+		SetCommand {
+			args: self
+				.args
+				.into_iter()
+				.map(|(space, arg)| (owned_str(space), owned_str(arg)))
+				.collect(),
+		}
+	}
+}
+
+impl VariableDefinition<'_> {
+	/// Converts the definition into one owning all strings.
+	pub fn into_owned(self) -> VariableDefinition<'static> {
+		VariableDefinition {
+			name: owned_str(self.name),
+			op: self.op,
+			value: self.value.into_owned(),
+		}
+	}
+}
+
+impl VariableValue<'_> {
+	/// Converts the value into one owning all strings.
+	pub fn into_owned(self) -> VariableValue<'static> {
+		match self {
+			VariableValue::String(text) => {
+				VariableValue::String(owned_text(text))
+			}
+			VariableValue::Array(tokens) => {
+				VariableValue::Array(owned_array_tokens(tokens))
+			}
+		}
+	}
+}
+
+impl Text<'_> {
+	/// Converts the text into one owning all strings.
+	pub fn into_owned(self) -> Text<'static> {
+		Text(self.0.into_iter().map(TextUnit::into_owned).collect())
+	}
+}
+
+impl TextUnit<'_> {
+	/// Converts the unit into one owning all strings.
+	pub fn into_owned(self) -> TextUnit<'static> {
+		match self {
+			TextUnit::Unquoted(words) => TextUnit::Unquoted(owned_words(words)),
+			TextUnit::SingleQuote(text) => {
+				TextUnit::SingleQuote(owned_str(text))
+			}
+			TextUnit::DoubleQuote(words) => {
+				TextUnit::DoubleQuote(owned_words(words))
+			}
+			TextUnit::AnsiCQuote(text) => TextUnit::AnsiCQuote(owned_str(text)),
+			TextUnit::LocaleQuote(words) => {
+				TextUnit::LocaleQuote(owned_words(words))
+			}
+		}
+	}
+
+	/// Returns if a string needs to be ANSI-C quoted to be written
+	/// without raw control characters, which is if it contains control
+	/// characters other than newlines and tabs.
+	pub fn needs_ansi_c_quote(text: &str) -> bool {
+		text.chars()
+			.any(|ch| ch.is_control() && ch != '\n' && ch != '\t')
+	}
+
+	/// Produces an ANSI-C quoted text unit (`$'...'`) for a string.
+	///
+	/// Backslashes, single quotes and all control characters are escaped,
+	/// so the text of the unit contains no control characters.
+	pub fn ansi_c_quote(text: &str) -> TextUnit<'static> {
+		let mut result = String::with_capacity(text.len());
+		for ch in text.chars() {
+			match ch {
+				'\\' => result.push_str("\\\\"),
+				'\'' => result.push_str("\\'"),
+				'\n' => result.push_str("\\n"),
+				'\t' => result.push_str("\\t"),
+				'\r' => result.push_str("\\r"),
+				'\x0b' => result.push_str("\\v"),
+				'\x0c' => result.push_str("\\f"),
+				_ if ch.is_ascii_control() => {
+					write!(result, "\\x{:02x}", ch as u32)
+						.expect("writing to strings never fails")
+				}
+				_ if ch.is_control() => write!(result, "\\u{:04x}", ch as u32)
+					.expect("writing to strings never fails"),
+				_ => result.push(ch),
+			}
+		}
+		TextUnit::AnsiCQuote(Cow::Owned(result))
+	}
+}
+
+impl Word<'_> {
+	/// Converts the word into one owning all strings.
+	pub fn into_owned(self) -> Word<'static> {
+		match self {
+			Word::Literal(parts) => Word::Literal(
+				parts.into_iter().map(LiteralPart::into_owned).collect(),
+			),
+			Word::UnbracedVariable(name) => {
+				Word::UnbracedVariable(owned_str(name))
+			}
+			Word::BracedVariable(exp) => Word::BracedVariable(exp.into_owned()),
+			Word::Subcommand(tokens) => {
+				Word::Subcommand(owned_array_tokens(tokens))
+			}
+			Word::Custom(exp) => Word::Custom(exp.into_owned()),
+		}
+	}
+}
+
+impl CustomExpansion<'_> {
+	/// Converts the expansion into one owning all strings.
+	pub fn into_owned(self) -> CustomExpansion<'static> {
+		CustomExpansion {
+			name: owned_str(self.name),
+			args: self
+				.args
+				.into_iter()
+				.map(|(space, arg)| (owned_str(space), owned_str(arg)))
+				.collect(),
+		}
+	}
+}
+
+impl LiteralPart<'_> {
+	/// Converts the part into one owning all strings.
+	pub fn into_owned(self) -> LiteralPart<'static> {
+		match self {
+			LiteralPart::String(text) => LiteralPart::String(owned_str(text)),
+			LiteralPart::Escaped(ch) => LiteralPart::Escaped(ch),
+			LiteralPart::LineContinuation => LiteralPart::LineContinuation,
+		}
+	}
+}
+
+impl BracedExpansion<'_> {
+	/// Converts the expansion into one owning all strings.
+	pub fn into_owned(self) -> BracedExpansion<'static> {
+		BracedExpansion {
+			name: owned_str(self.name),
+			modifier: self.modifier.map(ExpansionModifier::into_owned),
+		}
+	}
+}
+
+impl ExpansionModifier<'_> {
+	/// Converts the modifier into one owning all strings.
+	pub fn into_owned(self) -> ExpansionModifier<'static> {
+		use ExpansionModifier::*;
+		let pattern = |pattern: Arc<BashPattern<'_>>| {
+			Arc::new(Arc::unwrap_or_clone(pattern).into_owned())
+		};
+		match self {
+			Substring { offset, length } => Substring {
+				offset: owned_str(offset),
+				length: length.map(owned_str),
+			},
+			StripShortestPrefix(p) => StripShortestPrefix(pattern(p)),
+			StripLongestPrefix(p) => StripLongestPrefix(pattern(p)),
+			StripShortestSuffix(p) => StripShortestSuffix(pattern(p)),
+			StripLongestSuffix(p) => StripLongestSuffix(pattern(p)),
+			ReplaceOnce { pattern: p, string } => ReplaceOnce {
+				pattern: pattern(p),
+				string: string.map(owned_text),
+			},
+			ReplaceAll { pattern: p, string } => ReplaceAll {
+				pattern: pattern(p),
+				string: string.map(owned_text),
+			},
+			ReplacePrefix { pattern: p, string } => ReplacePrefix {
+				pattern: pattern(p),
+				string: string.map(owned_text),
+			},
+			ReplaceSuffix { pattern: p, string } => ReplaceSuffix {
+				pattern: pattern(p),
+				string: string.map(owned_text),
+			},
+			UpperOnce(p) => UpperOnce(pattern(p)),
+			UpperAll(p) => UpperAll(pattern(p)),
+			LowerOnce(p) => LowerOnce(pattern(p)),
+			LowerAll(p) => LowerAll(pattern(p)),
+			ErrorOnUnset(text) => ErrorOnUnset(owned_text(text)),
+			Length => Length,
+			WhenUnset(text) => WhenUnset(owned_text(text)),
+			WhenSet(text) => WhenSet(owned_text(text)),
+			ArrayElements => ArrayElements,
+			SingleWordElements => SingleWordElements,
+		}
+	}
+}
+
+impl ArrayToken<'_> {
+	/// Converts the token into one owning all strings.
+	pub fn into_owned(self) -> ArrayToken<'static> {
+		match self {
+			ArrayToken::Spacy(ch) => ArrayToken::Spacy(ch),
+			ArrayToken::Newline => ArrayToken::Newline,
+			ArrayToken::LineContinuation => ArrayToken::LineContinuation,
+			ArrayToken::Comment(text) => ArrayToken::Comment(owned_str(text)),
+			ArrayToken::Element(text) => ArrayToken::Element(owned_text(text)),
+		}
+	}
+}
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -528,6 +1165,7 @@ mod test {
 		assert!(Token::Newline.is_empty());
 		assert!(Token::Spacy(' ').is_empty());
 		assert!(Token::Spacy('\t').is_empty());
+		assert!(Token::LineContinuation.is_empty());
 		assert!(!Token::Comment(Cow::Borrowed("Test")).is_empty());
 		assert!(
 			!Token::Variable(VariableDefinition {
@@ -539,6 +1177,30 @@ mod test {
 		);
 	}
 
+	#[test]
+	fn test_ends_with_newline() {
+		for (src, ends_with_newline, blank) in [
+			("", false, true),
+			("\n", true, true),
+			(" \t", false, true),
+			(" \\\n \n", true, true),
+			("A=1", false, false),
+			("A=1\n", true, false),
+			("A=1 # x", false, false),
+			("A=1 \\\n", false, false),
+			("A=\"1\n\"", false, false),
+		] {
+			let lst = ApmlLst::parse(src).unwrap();
+			assert_eq!(lst.to_string(), src);
+			assert_eq!(lst.ends_with_newline(), ends_with_newline, "{src:?}");
+			assert_eq!(lst.is_blank(), blank, "{src:?}");
+			if blank {
+				let context = crate::apml::ApmlContext::eval_lst(&lst).unwrap();
+				assert_eq!(context, crate::apml::ApmlContext::default());
+			}
+		}
+	}
+
 	#[test]
 	fn test_literal_part_escape() {
 		assert!(LiteralPart::should_escape('$'));
@@ -557,4 +1219,53 @@ mod test {
 			LiteralPart::Escaped('\\'),
 		]);
 	}
+
+	#[test]
+	fn test_ansi_c_quote() {
+		assert!(!TextUnit::needs_ansi_c_quote("a\tb\n"));
+		assert!(TextUnit::needs_ansi_c_quote("a\rb"));
+		let value = "a\r\x0b\x0c\n\t'\\\x01\u{85}";
+		let unit = TextUnit::ansi_c_quote(value);
+		assert_eq!(unit.to_string(), "$'a\\r\\v\\f\\n\\t\\'\\\\\\x01\\u0085'");
+		assert!(!unit.to_string().contains(char::is_control));
+		assert_eq!(
+			crate::apml::VariableValue::from(value).to_string(),
+			unit.to_string()
+		);
+		let src = format!("A={}\n", unit);
+		assert_eq!(ApmlLst::parse(&src).unwrap().to_string(), src);
+		crate::assert_evals_to!(&src, { "A" => value });
+
+		// raw characters are kept
+		let src = "A=\"a\rb\x0b\x0c\r\n\"\nB='\r'\n";
+		assert_eq!(ApmlLst::parse(src).unwrap().to_string(), src);
+		assert_eq!(
+			invisible_controls(src).collect::<Vec<_>>(),
+			vec![4, 6, 7, 15]
+		);
+	}
+
+	#[test]
+	fn test_quoting_of() {
+		let lst = ApmlLst::parse(
+			"A=a\nB='b'\nC=\"c\"\nD=\"d\"'e'\nE=\nF=('a' 'b')\n",
+		)
+		.unwrap();
+		let style = |name| lst.quoting_of(name).map(|summary| summary.style());
+		assert_eq!(style("A"), Some(QuotingStyle::Bare));
+		assert_eq!(style("B"), Some(QuotingStyle::SingleQuoted));
+		assert_eq!(style("C"), Some(QuotingStyle::DoubleQuoted));
+		assert_eq!(style("D"), Some(QuotingStyle::Mixed));
+		assert_eq!(style("E"), Some(QuotingStyle::Bare));
+		assert_eq!(style("F"), Some(QuotingStyle::SingleQuoted));
+		assert_eq!(style("G"), None);
+		assert_eq!(
+			lst.quoting_of("D").unwrap(),
+			QuotingSummary {
+				unquoted: 0,
+				single_quoted: 1,
+				double_quoted: 1,
+			}
+		);
+	}
 }
@@ -0,0 +1,396 @@
+//! Parsing of memory-mapped sources.
+//!
+//! See [`ApmlLst::parse_mmap`].
+//!
+//! Reading a source into a [`String`] and parsing it keeps both the
+//! source and the LST in memory. With the source mapped instead, strings
+//! of the LST borrow from pages of the file, which the kernel may load
+//! on demand and drop under memory pressure. This pays off for large
+//! generated sources, while for usual `spec` and `defines` files the
+//! cost of mapping exceeds that of reading.
+//!
+//! Files are only mapped on unix. On other platforms, they are read into
+//! memory instead, as [`std::fs::read`] does, so the same API is available
+//! without saving memory.
+//!
+//! The trade-offs are:
+//!
+//! - The file must not be changed while it is mapped, see the safety
+//!   requirements of [`ApmlLst::parse_mmap`].
+//! - The source is validated as UTF-8 lazily, in chunks of lines as
+//!   tokens reach them, since the parser works on [`str`]. Validation
+//!   does not copy the source, and each page is validated right before
+//!   it is parsed. A token spanning several chunks is parsed again when
+//!   the next chunk is validated.
+//! - Tokens of the LST are still allocated, so only the memory of the
+//!   strings is saved. Use [`MappedLst::into_owned`] to release the map
+//!   if the LST outlives the file.
+
+#[cfg(not(unix))]
+use std::io::Read;
+use std::{fmt::Debug, fs::File, io, path::Path};
+#[cfg(unix)]
+use std::{
+	os::fd::AsRawFd,
+	ptr::{self, NonNull},
+	slice,
+};
+
+use thiserror::Error;
+
+use super::{
+	lst::{ApmlLst, LiteralPart, TextUnit, Token, VariableValue, Word},
+	parser::{ParseError, ParseOptions, token},
+};
+
+/// Number of bytes validated at once at least.
+const CHUNK_LEN: usize = 64 << 10;
+
+/// Errors produced by [`ApmlLst::parse_mmap`].
+#[derive(Debug, Error)]
+pub enum MmapError {
+	#[error(transparent)]
+	Io(#[from] io::Error),
+	/// The source is not valid UTF-8.
+	///
+	/// The position points to the first byte of the invalid sequence,
+	/// counted from 1 as in [`ParseError`].
+	#[error("Invalid UTF-8 at char {pos}")]
+	InvalidUtf8 { pos: usize },
+	#[error(transparent)]
+	Parse(#[from] ParseError),
+}
+
+/// A read-only private mapping of a whole file.
+#[cfg(unix)]
+struct Mmap {
+	ptr: NonNull<u8>,
+	len: usize,
+}
+
+// The mapping is read-only and owned by the value.
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+	/// Maps a file.
+	///
+	/// # Safety
+	///
+	/// The file must not be changed while it is mapped.
+	unsafe fn map(file: &File) -> io::Result<Self> {
+		let len = usize::try_from(file.metadata()?.len())
+			.map_err(|_| io::Error::from(io::ErrorKind::FileTooLarge))?;
+		if len == 0 {
+			// Empty mappings are rejected by mmap(2).
+			return Ok(Self {
+				ptr: NonNull::dangling(),
+				len,
+			});
+		}
+		let ptr = unsafe {
+			libc::mmap(
+				ptr::null_mut(),
+				len,
+				libc::PROT_READ,
+				libc::MAP_PRIVATE,
+				file.as_raw_fd(),
+				0,
+			)
+		};
+		if ptr == libc::MAP_FAILED {
+			return Err(io::Error::last_os_error());
+		}
+		let ptr = NonNull::new(ptr.cast()).expect("mapped address");
+		Ok(Self { ptr, len })
+	}
+
+	fn as_bytes(&self) -> &[u8] {
+		unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+	}
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+	fn drop(&mut self) {
+		if self.len != 0 {
+			unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len) };
+		}
+	}
+}
+
+/// A whole file read into memory, in place of a mapping.
+#[cfg(not(unix))]
+struct Mmap(Box<[u8]>);
+
+#[cfg(not(unix))]
+impl Mmap {
+	/// Reads a file.
+	///
+	/// # Safety
+	///
+	/// Reading has no safety requirement, this only mirrors the mapping
+	/// on unix.
+	unsafe fn map(mut file: &File) -> io::Result<Self> {
+		let mut data = Vec::new();
+		file.read_to_end(&mut data)?;
+		Ok(Self(data.into_boxed_slice()))
+	}
+
+	fn as_bytes(&self) -> &[u8] {
+		&self.0
+	}
+}
+
+impl Debug for Mmap {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Mmap")
+			.field("len", &self.as_bytes().len())
+			.finish()
+	}
+}
+
+/// An LST borrowing from a memory-mapped source, which owns the map.
+///
+/// The LST is only lent out with the lifetime of the wrapper, so it
+/// never outlives the map. See [`ApmlLst::parse_mmap`].
+#[derive(Debug)]
+pub struct MappedLst {
+	// Declared before the map, so that it is dropped first.
+	lst: ApmlLst<'static>,
+	map: Mmap,
+}
+
+impl MappedLst {
+	/// Returns the LST.
+	pub fn lst(&self) -> &ApmlLst<'_> {
+		&self.lst
+	}
+
+	/// Returns the source the LST is parsed from.
+	pub fn source(&self) -> &str {
+		// Validated in `ApmlLst::parse_mmap`.
+		unsafe { std::str::from_utf8_unchecked(self.map.as_bytes()) }
+	}
+
+	/// Copies borrowed strings of the LST, and unmaps the source.
+	pub fn into_owned(self) -> ApmlLst<'static> {
+		self.lst.into_owned()
+	}
+}
+
+impl ApmlLst<'_> {
+	/// Parses a file into a lossless syntax tree, borrowing from a memory
+	/// map of it.
+	///
+	/// The source is validated as UTF-8 as parsing reaches it, and the
+	/// first invalid sequence reached produces [`MmapError::InvalidUtf8`].
+	/// See the [module documentation][self] for the trade-offs.
+	///
+	/// # Safety
+	///
+	/// The file must not be changed, such as truncated or written, by
+	/// this or any other process until the returned [`MappedLst`] is
+	/// dropped. Changes may be seen through the LST, breaking the
+	/// guarantee of strings being UTF-8, and truncation may raise
+	/// `SIGBUS` on accesses.
+	pub unsafe fn parse_mmap<P: AsRef<Path>>(
+		path: P,
+	) -> Result<MappedLst, MmapError> {
+		let file = File::open(path)?;
+		let map = unsafe { Mmap::map(&file) }?;
+		// The map is neither moved out of the wrapper nor dropped before
+		// the LST, so the borrow can be extended to the wrapper.
+		let bytes = unsafe { &*std::ptr::from_ref::<[u8]>(map.as_bytes()) };
+		let lst = parse_lazy(bytes)?;
+		Ok(MappedLst { lst, map })
+	}
+}
+
+/// Parses a source, validating it as UTF-8 in chunks as tokens reach
+/// them.
+///
+/// Chunks end at line ends. As alternatives of the parser fall back to
+/// shorter tokens at the end of the input, such as `A=` for `A="a` or
+/// `A=(` for an unclosed array, tokens are only taken once their line
+/// is complete in the validated chunks. Otherwise, more of the source is
+/// validated and the line is parsed again.
+fn parse_lazy(bytes: &[u8]) -> Result<ApmlLst<'_>, MmapError> {
+	let mut tokens = Vec::new();
+	// tokens of the current line, and where it starts
+	let mut line = Vec::new();
+	let mut line_start = 0;
+	// length of the validated prefix, and the first invalid byte
+	let mut valid = 0;
+	let mut invalid = None;
+	let mut pos = 0;
+	loop {
+		// validated in previous iterations
+		let src = unsafe { std::str::from_utf8_unchecked(&bytes[..valid]) };
+		let complete = valid == bytes.len();
+		match token(&src[pos..], ParseOptions::default()) {
+			Ok((rest, token))
+				if complete || (!rest.is_empty() && !maybe_array(&token)) =>
+			{
+				pos = valid - rest.len();
+				let newline = matches!(token, Token::Newline);
+				line.push(token);
+				if newline {
+					tokens.append(&mut line);
+					line_start = pos;
+				}
+				continue;
+			}
+			Err(nom::Err::Error(_)) if complete => {
+				if pos != valid {
+					return Err(
+						ParseError::UnexpectedSource { pos: pos + 1 }.into()
+					);
+				}
+				tokens.append(&mut line);
+				break;
+			}
+			Err(err) if complete => {
+				return Err(ParseError::locate(src, err).into());
+			}
+			_ => {}
+		}
+		if let Some(pos) = invalid {
+			return Err(MmapError::InvalidUtf8 { pos: pos + 1 });
+		}
+		// validate at least another chunk, to the end of its last line,
+		// doubling the pending source for long lines
+		let end = (valid + CHUNK_LEN.max(valid - line_start)).min(bytes.len());
+		let end = bytes[end..]
+			.iter()
+			.position(|byte| *byte == b'\n')
+			.map_or(bytes.len(), |len| end + len + 1);
+		match std::str::from_utf8(&bytes[valid..end]) {
+			Ok(_) => valid = end,
+			Err(err) => {
+				valid += err.valid_up_to();
+				invalid = Some(valid);
+			}
+		}
+		line.clear();
+		pos = line_start;
+	}
+	tokens.shrink_to_fit();
+	Ok(ApmlLst(tokens))
+}
+
+/// Returns if a token is a string definition starting with `(`, which
+/// is an array if closed later in the source.
+fn maybe_array(token: &Token) -> bool {
+	let Token::Variable(def) = token else {
+		return false;
+	};
+	let VariableValue::String(text) = &def.value else {
+		return false;
+	};
+	matches!(
+		text.0.first(),
+		Some(TextUnit::Unquoted(words)) if matches!(
+			words.first(),
+			Some(Word::Literal(parts)) if matches!(
+				parts.first(),
+				Some(LiteralPart::String(text)) if text.starts_with('(')
+			)
+		)
+	)
+}
+
+#[cfg(test)]
+mod test {
+	use std::{fmt::Write, fs};
+
+	use super::*;
+	use crate::apml::ApmlContext;
+
+	#[test]
+	fn test_parse_mmap() {
+		let dir = std::env::temp_dir()
+			.join(format!("libabbs-mmap-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+
+		let mut src = String::from("# generated\nPREFIX=pkg\n");
+		let mut index = 0;
+		while src.len() < 2 << 20 {
+			writeln!(
+				src,
+				"VAR_{index}=\"${{PREFIX}}-{index} ünïcode\"\n\
+				ARR_{index}=(a \"$VAR_{index}\" c) # {index}"
+			)
+			.unwrap();
+			index += 1;
+		}
+		let path = dir.join("large.apml");
+		fs::write(&path, &src).unwrap();
+		let mapped = unsafe { ApmlLst::parse_mmap(&path) }.unwrap();
+		assert_eq!(mapped.source(), src);
+		assert_eq!(mapped.lst(), &ApmlLst::parse(&src).unwrap());
+		let context = ApmlContext::eval_lst(mapped.lst()).unwrap();
+		let last = format!("VAR_{}", index - 1);
+		assert_eq!(
+			context[last.as_str()].as_string(),
+			format!("pkg-{} ünïcode", index - 1)
+		);
+		let lst = mapped.into_owned();
+		assert_eq!(lst.to_string(), src);
+
+		// invalid sequences are located in the whole file
+		let mut bytes = src.clone().into_bytes();
+		bytes.extend_from_slice(b"A=(a\nb\xff)\n");
+		let path = dir.join("large-invalid.apml");
+		fs::write(&path, &bytes).unwrap();
+		assert!(matches!(
+			unsafe { ApmlLst::parse_mmap(&path) },
+			Err(MmapError::InvalidUtf8 { pos }) if pos == src.len() + 7
+		));
+		// tokens spanning chunks are parsed once the chunks are validated
+		let src = format!(
+			"A=\"{0}\"\nB=(\n{0})\nC=(\n{0}\n",
+			"element\n".repeat(CHUNK_LEN / 4)
+		);
+		let path = dir.join("large-array.apml");
+		fs::write(&path, &src).unwrap();
+		let Err(MmapError::Parse(err)) =
+			(unsafe { ApmlLst::parse_mmap(&path) })
+		else {
+			panic!("unclosed array is accepted");
+		};
+		assert_eq!(
+			err.to_string(),
+			ApmlLst::parse(&src).unwrap_err().to_string()
+		);
+		let src = &src[..src.rfind("C=").unwrap()];
+		fs::write(&path, src).unwrap();
+		let mapped = unsafe { ApmlLst::parse_mmap(&path) }.unwrap();
+		assert_eq!(mapped.lst(), &ApmlLst::parse(src).unwrap());
+
+		let path = dir.join("empty.apml");
+		fs::write(&path, "").unwrap();
+		let mapped = unsafe { ApmlLst::parse_mmap(&path) }.unwrap();
+		assert!(mapped.lst().0.is_empty());
+
+		let path = dir.join("invalid.apml");
+		fs::write(&path, b"A=1\nB=\"\xc3\x28\"\n").unwrap();
+		assert!(matches!(
+			unsafe { ApmlLst::parse_mmap(&path) },
+			Err(MmapError::InvalidUtf8 { pos: 8 })
+		));
+		fs::write(&path, "A=1\nB=\"\n").unwrap();
+		assert!(matches!(
+			unsafe { ApmlLst::parse_mmap(&path) },
+			Err(MmapError::Parse(_))
+		));
+		assert!(matches!(
+			unsafe { ApmlLst::parse_mmap(dir.join("missing.apml")) },
+			Err(MmapError::Io(_))
+		));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,27 +1,163 @@
 //! ACBS Package Metadata Language (APML) syntax tree and parsers.
+//!
+//! With the `tracing` feature enabled, the pipeline is instrumented with
+//! [tracing](https://docs.rs/tracing) spans: `apml_parse`, `apml_emit`
+//! and `apml_eval` for each source, `apml_eval_var` for each variable
+//! and `apml_load` for each named source in [`batch::load_tree`].
+//! `apml_eval` spans carry the name of the source in a `source` field,
+//! see [`eval::EvalOptions::source_name`].
+//! Expansions producing large values are reported as debug events.
 
-use std::{
-	collections::HashMap,
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::{
 	fmt::{Display, Write},
+	hash::{Hash, Hasher},
 	ops::{Add, AddAssign, Index},
+	sync::atomic::{AtomicBool, AtomicU32, Ordering},
 };
 
 use ast::{ApmlAst, AstNode};
+use indexmap::IndexMap;
 use lst::ApmlLst;
 use thiserror::Error;
 
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod arch;
 pub mod ast;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod build;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod capabilities;
+#[cfg(feature = "std")]
+pub mod chksums;
+#[cfg(feature = "std")]
+pub mod classify;
+#[cfg(feature = "std")]
+pub mod compat;
+#[cfg(feature = "std")]
+pub mod completion;
+#[cfg(feature = "serde")]
+pub mod conformance;
+#[cfg(feature = "std")]
+pub mod deps;
+#[cfg(feature = "std")]
 pub mod editor;
 pub mod eval;
+#[cfg(feature = "std")]
+pub mod explain;
+#[cfg(feature = "std")]
+pub mod history;
+#[cfg(feature = "serde")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod layered;
+#[cfg(feature = "std")]
+pub mod lint;
 pub mod lst;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "std")]
+pub mod package;
 pub mod parser;
 pub mod pattern;
+#[cfg(feature = "std")]
+pub mod pipeline;
+pub mod recovery;
+#[cfg(feature = "std")]
+pub mod relations;
+#[cfg(feature = "std")]
+pub mod schema;
+#[cfg(feature = "std")]
+pub mod session;
+pub mod span;
+#[cfg(feature = "std")]
+pub mod srcs;
+pub mod suggest;
 pub mod value;
+#[cfg(feature = "std")]
+pub mod version;
+#[cfg(feature = "std")]
+pub mod wrap;
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(feature = "std")]
+pub use capabilities::{Capabilities, capabilities};
+#[cfg(feature = "std")]
+pub use completion::{completions, completions_in_source};
+#[cfg(feature = "std")]
+pub use explain::{Explanation, explain};
+#[cfg(feature = "std")]
+pub use pipeline::{Analysis, AnalysisOptions, analyze};
+
+// Hash maps and sets, which are replaced by B-tree maps and sets
+// without `std`.
+#[cfg(not(feature = "std"))]
+pub(crate) use alloc::collections::{BTreeMap as HashMap, BTreeSet as HashSet};
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
+
+/// Hasher of variables of contexts.
+///
+/// Without `std`, there is no source of random seeds, so the FNV-1a
+/// hasher of [`ApmlContext::content_hash`] is used instead.
+#[cfg(feature = "std")]
+type VariableHasher = std::hash::RandomState;
+#[cfg(not(feature = "std"))]
+type VariableHasher = core::hash::BuildHasherDefault<ContentHasher>;
 
 /// A evaluated APML context.
-#[derive(Debug, PartialEq, Eq, Clone, Default)]
+///
+/// Variables are kept in the order of their first definition.
+///
+/// Metadata recorded during evaluation, such as [influences], is not
+/// considered when comparing or hashing contexts. The order of variables
+/// is not considered either, so contexts with the same variables and
+/// positional parameters are equal and hash equally regardless of the
+/// order of definitions. See [`ApmlContext::content_hash`] for hashes
+/// that can be persisted.
+///
+/// [influences]: ApmlContext::influences
+#[derive(Debug, Clone, Default)]
 pub struct ApmlContext {
-	variables: HashMap<String, VariableValue>,
+	variables: IndexMap<String, VariableValue, VariableHasher>,
+	positional_params: Vec<String>,
+	influences: HashMap<String, BTreeSet<String>>,
+	external_influences: BTreeSet<String>,
+	unresolved: BTreeSet<String>,
+	/// Variables whose values contain symbolic expansions.
+	symbolic: HashSet<String>,
+	provenance: BTreeMap<String, String>,
+	/// See [`ApmlContext::last_defined`].
+	last_defined: Option<String>,
+	/// Cached [`ApmlContext::content_hash`], cleared on modifications.
+	content_hash: HashCache,
+}
+
+impl PartialEq for ApmlContext {
+	fn eq(&self, other: &Self) -> bool {
+		if let (Some(hash), Some(other_hash)) =
+			(self.content_hash.get(), other.content_hash.get())
+			&& hash != other_hash
+		{
+			return false;
+		}
+		self.variables == other.variables
+			&& self.positional_params == other.positional_params
+	}
+}
+
+impl Eq for ApmlContext {}
+
+impl Hash for ApmlContext {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		state.write_u64(self.content_hash());
+	}
 }
 
 impl ApmlContext {
@@ -31,22 +167,117 @@ impl ApmlContext {
 	}
 
 	/// Evaluates a APML AST, expanding variables.
-	pub fn eval_ast(ast: &ApmlAst) -> std::result::Result<Self, ApmlError> {
+	pub fn eval_ast(ast: &ApmlAst) -> core::result::Result<Self, ApmlError> {
 		let mut apml = ApmlContext::default();
 		eval::eval_ast(&mut apml, ast)?;
 		Ok(apml)
 	}
 
 	/// Emits and evaluates a APML LST.
-	pub fn eval_lst(lst: &ApmlLst) -> std::result::Result<Self, ApmlError> {
-		Self::eval_ast(&ApmlAst::emit_from(lst)?)
+	///
+	/// Shell options set by `set` statements are honored, see
+	/// [`eval::ShellOptions`].
+	pub fn eval_lst(lst: &ApmlLst) -> core::result::Result<Self, ApmlError> {
+		Self::eval_lst_with(lst, &mut eval::EvalOptions::default())
+	}
+
+	/// Emits and evaluates a APML LST with options.
+	///
+	/// Unlike [`ApmlContext::eval_ast`], spans passed to callbacks and
+	/// reported in errors point to the source of the LST.
+	pub fn eval_lst_with(
+		lst: &ApmlLst,
+		options: &mut eval::EvalOptions,
+	) -> core::result::Result<Self, ApmlError> {
+		Self::eval_emitted(lst, &ApmlAst::emit_from(lst)?, options)
+	}
+
+	/// Evaluates a AST emitted from a LST with options.
+	///
+	/// This is [`ApmlContext::eval_lst_with`] without emitting the AST
+	/// again.
+	pub(crate) fn eval_emitted(
+		lst: &ApmlLst,
+		ast: &ApmlAst,
+		options: &mut eval::EvalOptions,
+	) -> core::result::Result<Self, ApmlError> {
+		let shell_options = lst.shell_options()?;
+		let sources = lst
+			.variable_spans()
+			.map(|(span, def)| eval::DefinitionSource {
+				span,
+				shell_options: shell_options
+					.iter()
+					.take_while(|(set_span, _)| set_span.end <= span.start)
+					.last()
+					.map(|(_, options)| *options)
+					.unwrap_or_default(),
+				array_expansions: def
+					.array_expansions()
+					.into_iter()
+					.map(|(exp_span, exp)| {
+						(exp.name.to_string(), exp_span.offset(span.start))
+					})
+					.collect(),
+				references: def
+					.word_spans()
+					.into_iter()
+					.flat_map(|(word_span, word)| {
+						analysis::word_references(word).into_iter().map(
+							move |name| (name, word_span.offset(span.start)),
+						)
+					})
+					.collect(),
+			})
+			.collect::<Vec<_>>();
+		let mut apml = ApmlContext::default();
+		eval::eval_ast_spanned(&mut apml, ast, &sources, None, options)?;
+		Ok(apml)
 	}
 
 	/// Parses a APML source code, expanding variables.
-	pub fn eval_source(src: &str) -> std::result::Result<Self, ApmlError> {
+	pub fn eval_source(src: &str) -> core::result::Result<Self, ApmlError> {
 		Self::eval_lst(&ApmlLst::parse(src)?)
 	}
 
+	/// Parses a APML source code and evaluates it with options.
+	///
+	/// Custom expansions are parsed if any is supplied, see
+	/// [`ApmlLst::parse_with`].
+	pub fn eval_source_with(
+		src: &str,
+		options: &mut eval::EvalOptions,
+	) -> core::result::Result<Self, ApmlError> {
+		Self::eval_lst_with(&ApmlLst::parse_with(src, options)?, options)
+	}
+
+	/// Expands a template string against the context.
+	///
+	/// The template is parsed as a single APML word, so all expansion
+	/// syntax and quoting is supported. Assignments are rejected with
+	/// [`ParseError::SyntaxError`], and unquoted whitespace or newlines,
+	/// which would start another word or statement, are rejected with
+	/// [`ParseError::UnexpectedSource`].
+	///
+	/// [`ParseError::SyntaxError`]: parser::ParseError::SyntaxError
+	/// [`ParseError::UnexpectedSource`]: parser::ParseError::UnexpectedSource
+	pub fn expand_str(
+		&self,
+		template: &str,
+		options: &eval::EvalOptions,
+	) -> core::result::Result<String, ApmlError> {
+		let (out, text) = parser::apml_word(template, options.into())
+			.map_err(|err| parser::ParseError::locate(template, err))?;
+		if !out.is_empty() {
+			return Err(parser::ParseError::UnexpectedSource {
+				pos: nom::Offset::offset(template, out) + 1,
+			}
+			.into());
+		}
+		let text = ast::Text::emit_from(&text)?;
+		Ok(eval::eval_text_with(self, &text, options)?)
+	}
+
 	/// Gets a variable value.
 	#[must_use]
 	pub fn get(&self, name: &str) -> Option<&VariableValue> {
@@ -62,33 +293,377 @@ impl ApmlContext {
 	/// Gets a variable value.
 	#[must_use]
 	pub fn get_mut(&mut self, name: &str) -> Option<&mut VariableValue> {
+		self.content_hash.take();
 		self.variables.get_mut(name)
 	}
 
 	/// Removes a variable value.
 	pub fn remove(&mut self, name: &str) -> Option<VariableValue> {
-		self.variables.remove(name)
+		self.content_hash.take();
+		self.influences.remove(name);
+		self.symbolic.remove(name);
+		if self.last_defined.as_deref() == Some(name) {
+			self.last_defined = None;
+		}
+		self.variables.shift_remove(name)
+	}
+
+	/// Retains only the variables specified by the predicate.
+	pub fn retain<F>(&mut self, mut f: F)
+	where
+		F: FnMut(&str, &mut VariableValue) -> bool,
+	{
+		self.content_hash.take();
+		self.variables.retain(|name, value| f(name, value));
+		let variables = &self.variables;
+		self.influences
+			.retain(|name, _| variables.contains_key(name));
+		self.symbolic.retain(|name| variables.contains_key(name));
+		self.last_defined
+			.take_if(|name| !variables.contains_key(name));
+	}
+
+	/// Replaces the value of each variable with the result of a function.
+	pub fn map_values<F>(&mut self, mut f: F)
+	where
+		F: FnMut(&str, VariableValue) -> VariableValue,
+	{
+		self.content_hash.take();
+		for (name, value) in &mut self.variables {
+			*value = f(name, core::mem::take(value));
+		}
+	}
+
+	/// Renames variables.
+	///
+	/// The function returns the new name of a variable, or [`None`] to
+	/// keep the name unchanged. Influences of renamed variables are moved
+	/// along, while references to them in influences of other variables
+	/// are kept as is.
+	///
+	/// If multiple variables would end up with the same name, the
+	/// context is left unchanged and [`RenameError::Collision`] is
+	/// returned.
+	pub fn rename_keys<F>(&mut self, mut f: F) -> Result<(), RenameError>
+	where
+		F: FnMut(&str) -> Option<String>,
+	{
+		let renames = self
+			.variables
+			.keys()
+			.map(|name| f(name))
+			.collect::<Vec<_>>();
+		let mut names = HashSet::new();
+		for (name, rename) in self.variables.keys().zip(&renames) {
+			let name = rename.as_ref().unwrap_or(name);
+			if !names.insert(name) {
+				return Err(RenameError::Collision(name.to_string()));
+			}
+		}
+
+		self.content_hash.take();
+		let variables = core::mem::take(&mut self.variables);
+		let mut influences = core::mem::take(&mut self.influences);
+		let mut symbolic = core::mem::take(&mut self.symbolic);
+		for ((name, value), rename) in variables.into_iter().zip(renames) {
+			let new_name = rename.unwrap_or_else(|| name.clone());
+			if let Some(influence) = influences.remove(&name) {
+				self.influences.insert(new_name.clone(), influence);
+			}
+			if symbolic.remove(&name) {
+				self.symbolic.insert(new_name.clone());
+			}
+			if self.last_defined.as_ref() == Some(&name) {
+				self.last_defined = Some(new_name.clone());
+			}
+			self.variables.insert(new_name, value);
+		}
+		Ok(())
 	}
 
 	/// Inserts a variable.
 	pub fn insert(&mut self, name: String, value: VariableValue) {
+		self.content_hash.take();
+		self.symbolic.remove(&name);
+		self.last_defined = Some(name.clone());
 		self.variables.insert(name, value);
 	}
 
-	/// Iterates over all variables.
-	pub fn iter(&self) -> impl Iterator<Item = (&String, &VariableValue)> {
+	/// Iterates over all variables, in the order of their first
+	/// definition.
+	///
+	/// The iterator can be reversed, for example to list variables
+	/// starting from the latest ones.
+	pub fn iter(
+		&self,
+	) -> impl DoubleEndedIterator<Item = (&String, &VariableValue)> + ExactSizeIterator
+	{
 		self.variables.iter()
 	}
 
-	/// Iterates over all variable names.
-	pub fn keys(&self) -> impl Iterator<Item = &String> {
+	/// Iterates over all variable names, in the order of their first
+	/// definition.
+	pub fn keys(
+		&self,
+	) -> impl DoubleEndedIterator<Item = &String> + ExactSizeIterator {
 		self.variables.keys()
 	}
 
+	/// Returns the name of the most recently defined variable.
+	///
+	/// Unlike the last of [`ApmlContext::keys`], this follows
+	/// redefinitions, so it is `A` after evaluating `A=1 B=2 A=3`.
+	/// Returns [`None`] if no variable is defined, or if the variable
+	/// has been removed since.
+	pub fn last_defined(&self) -> Option<&str> {
+		self.last_defined.as_deref()
+	}
+
 	/// Returns if a variable is defined.
 	pub fn contains_var<S: AsRef<str>>(&self, key: S) -> bool {
 		self.variables.contains_key(key.as_ref())
 	}
+
+	/// Returns the positional parameters (`$1`, `$2`, ...).
+	pub fn positional_params(&self) -> &[String] {
+		&self.positional_params
+	}
+
+	/// Sets the positional parameters (`$1`, `$2`, ...).
+	pub fn set_positional_params(&mut self, params: Vec<String>) {
+		self.content_hash.take();
+		self.positional_params = params;
+	}
+
+	/// Returns a hash of the variables and positional parameters.
+	///
+	/// Variables are hashed in the order of their names, so the hash
+	/// does not depend on the order of definitions, and metadata is not
+	/// hashed, as with comparing contexts. The hash is cached until the
+	/// context is modified, and comparing contexts with cached hashes
+	/// fails fast if the hashes differ.
+	///
+	/// Unlike the [`Hash`] implementation, whose result depends on the
+	/// hasher and is usually randomized for each process, the content
+	/// hash is computed with 64-bit FNV-1a over a fixed encoding, so it
+	/// is the same across processes and platforms and can be persisted,
+	/// such as in keys of on-disk caches. Changes to the encoding are
+	/// breaking changes of this crate.
+	pub fn content_hash(&self) -> u64 {
+		self.content_hash.get_or_init(|| {
+			let mut hasher = ContentHasher::default();
+			let mut variables = self.variables.iter().collect::<Vec<_>>();
+			variables.sort_unstable_by_key(|(name, _)| *name);
+			hasher.write_len(variables.len());
+			for (name, value) in variables {
+				hasher.write_str(name);
+				match value {
+					VariableValue::String(text) => {
+						hasher.write(&[0]);
+						hasher.write_str(text);
+					}
+					VariableValue::Array(elements) => {
+						hasher.write(&[1]);
+						hasher.write_strs(elements);
+					}
+				}
+			}
+			hasher.write_strs(&self.positional_params);
+			hasher.finish()
+		})
+	}
+
+	/// Returns variables that influenced the last assigned value of
+	/// a variable, including transitive references.
+	///
+	/// This is only recorded when evaluating with
+	/// [`EvalOptions::track_influences`] enabled.
+	/// Variables are only included when they are referenced, so defaults
+	/// that are not taken do not influence the value.
+	///
+	/// Variables not defined by the evaluated source, such as seeded ones,
+	/// are also included and can be told apart with
+	/// [`ApmlContext::external_influences`].
+	///
+	/// [`EvalOptions::track_influences`]: eval::EvalOptions::track_influences
+	pub fn influences(&self, name: &str) -> Option<&BTreeSet<String>> {
+		self.influences.get(name)
+	}
+
+	/// Returns variables referenced during evaluation but not defined by
+	/// the evaluated source at the time of reference.
+	pub fn external_influences(&self) -> &BTreeSet<String> {
+		&self.external_influences
+	}
+
+	/// Returns variables kept as symbolic expansions during evaluation.
+	///
+	/// This is only recorded when evaluating with
+	/// [`UnknownPolicy::Symbolic`]. Values of these variables are
+	/// required to fully expand symbolic values.
+	///
+	/// [`UnknownPolicy::Symbolic`]: eval::UnknownPolicy::Symbolic
+	pub fn unresolved(&self) -> &BTreeSet<String> {
+		&self.unresolved
+	}
+
+	/// Returns where the value of a variable read from a resolver during
+	/// evaluation comes from.
+	///
+	/// This is only recorded when evaluating with
+	/// [`eval::eval_ast_with_resolver`], for variables with provenance
+	/// described by [`VariableResolver::provenance`].
+	///
+	/// [`VariableResolver::provenance`]: eval::VariableResolver::provenance
+	pub fn provenance(&self, name: &str) -> Option<&str> {
+		self.provenance.get(name).map(String::as_str)
+	}
+
+	/// Returns if the value of a variable contains symbolic expansions.
+	///
+	/// See [`ApmlContext::unresolved`].
+	pub fn is_symbolic(&self, name: &str) -> bool {
+		self.symbolic.contains(name)
+	}
+}
+
+/// Cache of [`ApmlContext::content_hash`].
+///
+/// The hash is kept in two 32-bit atomics, so that contexts are [`Sync`]
+/// with or without `std`, including on targets without 64-bit atomics.
+/// Threads racing to fill the cache store the same hash, so halves of
+/// their stores never combine into a wrong one.
+#[derive(Debug, Default)]
+struct HashCache {
+	filled: AtomicBool,
+	high: AtomicU32,
+	low: AtomicU32,
+}
+
+impl HashCache {
+	fn get(&self) -> Option<u64> {
+		self.filled.load(Ordering::Acquire).then(|| {
+			(u64::from(self.high.load(Ordering::Relaxed)) << 32)
+				| u64::from(self.low.load(Ordering::Relaxed))
+		})
+	}
+
+	fn get_or_init(&self, f: impl FnOnce() -> u64) -> u64 {
+		if let Some(hash) = self.get() {
+			return hash;
+		}
+		let hash = f();
+		self.high.store((hash >> 32) as u32, Ordering::Relaxed);
+		self.low.store(hash as u32, Ordering::Relaxed);
+		self.filled.store(true, Ordering::Release);
+		hash
+	}
+
+	fn take(&mut self) -> Option<u64> {
+		let hash = self.get();
+		*self.filled.get_mut() = false;
+		hash
+	}
+}
+
+impl Clone for HashCache {
+	fn clone(&self) -> Self {
+		let cache = Self::default();
+		if let Some(hash) = self.get() {
+			cache.get_or_init(|| hash);
+		}
+		cache
+	}
+}
+
+// contexts can be shared between threads, with or without `std`
+const _: () = {
+	const fn assert_sync<T: Send + Sync>() {}
+	assert_sync::<ApmlContext>();
+};
+
+/// 64-bit FNV-1a hasher for [`ApmlContext::content_hash`] and
+/// [`cache::source_hash`].
+pub(crate) struct ContentHasher(u64);
+
+impl Default for ContentHasher {
+	fn default() -> Self {
+		Self(0xcbf29ce484222325)
+	}
+}
+
+impl ContentHasher {
+	fn write_len(&mut self, len: usize) {
+		self.write(&(len as u64).to_le_bytes());
+	}
+
+	fn write_str(&mut self, text: &str) {
+		self.write_len(text.len());
+		self.write(text.as_bytes());
+	}
+
+	fn write_strs(&mut self, texts: &[String]) {
+		self.write_len(texts.len());
+		for text in texts {
+			self.write_str(text);
+		}
+	}
+}
+
+impl Hasher for ContentHasher {
+	fn finish(&self) -> u64 {
+		self.0
+	}
+
+	fn write(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.0 ^= *byte as u64;
+			self.0 = self.0.wrapping_mul(0x100000001b3);
+		}
+	}
+}
+
+/// Read access to variables of a context.
+///
+/// This is implemented by [`ApmlContext`] as well as by frozen contexts
+/// and overlays in [`layered`], so that read-only consumers such as
+/// [`arch::resolve_arch`] accept any of them.
+pub trait ReadContext {
+	/// Gets a variable value.
+	fn get(&self, name: &str) -> Option<&VariableValue>;
+
+	/// Iterates over all variable names, in the order of their first
+	/// definition.
+	fn keys(&self) -> impl Iterator<Item = &String>;
+
+	/// Gets a variable value or returns a default value if not found.
+	fn read(&self, name: &str) -> VariableValue {
+		self.get(name).cloned().unwrap_or_default()
+	}
+
+	/// Returns if a variable is defined.
+	fn contains_var(&self, name: &str) -> bool {
+		self.get(name).is_some()
+	}
+
+	/// Iterates over all variables.
+	fn iter(&self) -> impl Iterator<Item = (&String, &VariableValue)> {
+		self.keys().filter_map(|name| Some((name, self.get(name)?)))
+	}
+}
+
+impl ReadContext for ApmlContext {
+	fn get(&self, name: &str) -> Option<&VariableValue> {
+		self.variables.get(name)
+	}
+
+	fn keys(&self) -> impl Iterator<Item = &String> {
+		self.variables.keys()
+	}
+
+	fn iter(&self) -> impl Iterator<Item = (&String, &VariableValue)> {
+		self.variables.iter()
+	}
 }
 
 impl<S: AsRef<str>> Index<S> for ApmlContext {
@@ -103,13 +678,20 @@ impl<S: AsRef<str>> Index<S> for ApmlContext {
 impl IntoIterator for ApmlContext {
 	type Item = (String, VariableValue);
 
-	type IntoIter = <HashMap<String, VariableValue> as IntoIterator>::IntoIter;
+	type IntoIter = indexmap::map::IntoIter<String, VariableValue>;
 
 	fn into_iter(self) -> Self::IntoIter {
 		self.variables.into_iter()
 	}
 }
 
+/// Error returned by [`ApmlContext::rename_keys`].
+#[derive(Debug, Error)]
+pub enum RenameError {
+	#[error("Multiple variables are renamed to {0}")]
+	Collision(String),
+}
+
 #[derive(Debug, Error)]
 pub enum ApmlError {
 	#[error(transparent)]
@@ -121,6 +703,38 @@ pub enum ApmlError {
 }
 
 /// Value of variables.
+///
+/// Empty strings and empty arrays are distinct values:
+/// `VAR=` evaluates to `String("")` while `VAR=()` evaluates to
+/// `Array([])`. The distinction is kept when displaying values, and
+/// values are only converted when explicitly requested, such as with
+/// [`VariableValue::as_array`]. An unset variable is represented by
+/// the absence of a value.
+///
+/// Comparing two values takes the variant into account, so a string
+/// never equals an array, even an array with the same single element.
+/// Comparing a value with a string compares the string form of the
+/// value instead, see [`VariableValue::as_string`].
+///
+/// # Equality and hashing
+///
+/// Values are used as keys of hash sets and maps, so [`PartialEq`] and
+/// [`Hash`] follow a contract, which new variants must keep as well:
+///
+/// - Equal values have equal hashes, both with [`Hash`] and within
+///   [`ApmlContext::content_hash`].
+/// - Values of different variants are never equal, and the variant is
+///   part of the hash. `String("a")` never equals `Array(["a"])`.
+/// - Values of a variant are equal if their contents are equal in
+///   order, such as elements of arrays. A variant holding unordered
+///   entries, such as an associative array, must compare and hash its
+///   entries regardless of their order, as
+///   [`ApmlContext::content_hash`] does for variables.
+///
+/// Comparisons with strings are not part of the contract, as a string
+/// hashes differently from values equal to it. For this reason, values
+/// do not implement [`Borrow<str>`][core::borrow::Borrow], and sets of
+/// values cannot be looked up with strings.
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum VariableValue {
 	String(String),
@@ -223,14 +837,110 @@ impl VariableValue {
 			VariableValue::Array(els) => els.is_empty(),
 		}
 	}
+
+	/// Maps each element of the value with a fallible function.
+	///
+	/// Elements are taken as by [`VariableValue::as_array`]. All elements
+	/// are visited even after a failure, and every failure is reported
+	/// with the index and the text of the element.
+	pub fn try_map_elements<T, E, F>(
+		&self,
+		mut f: F,
+	) -> Result<Vec<T>, ElementErrors<E>>
+	where
+		F: FnMut(&str) -> Result<T, E>,
+	{
+		let mut mapped = Vec::new();
+		let mut errors = Vec::new();
+		for (index, element) in self.as_array().into_iter().enumerate() {
+			match f(&element) {
+				Ok(value) => mapped.push(value),
+				Err(error) => errors.push(ElementError {
+					index,
+					element,
+					error,
+				}),
+			}
+		}
+		if errors.is_empty() {
+			Ok(mapped)
+		} else {
+			Err(ElementErrors(errors))
+		}
+	}
+
+	/// Returns an array of the elements matching a predicate.
+	///
+	/// Elements are taken as by [`VariableValue::as_array`], so filtering
+	/// a string value always produces an array.
+	#[must_use]
+	pub fn filter_elements<P>(&self, mut predicate: P) -> VariableValue
+	where
+		P: FnMut(&str) -> bool,
+	{
+		let mut elements = self.as_array();
+		elements.retain(|element| predicate(element));
+		VariableValue::Array(elements)
+	}
+}
+
+/// A failure of mapping an element of a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementError<E> {
+	/// Index of the element.
+	pub index: usize,
+	/// Text of the element.
+	pub element: String,
+	/// The error returned for the element.
+	pub error: E,
+}
+
+/// Failures of mapping elements, returned by
+/// [`VariableValue::try_map_elements`].
+///
+/// Failures are in the order of elements, and there is always at least
+/// one failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementErrors<E>(pub Vec<ElementError<E>>);
+
+impl<E> ElementErrors<E> {
+	/// Iterates over the failures.
+	pub fn iter(&self) -> impl Iterator<Item = &ElementError<E>> {
+		self.0.iter()
+	}
+}
+
+impl<E: Display> Display for ElementErrors<E> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		for (i, failure) in self.0.iter().enumerate() {
+			if i != 0 {
+				f.write_str(", ")?;
+			}
+			write!(
+				f,
+				"{} (element {}): {}",
+				failure.element, failure.index, failure.error
+			)?;
+		}
+		Ok(())
+	}
 }
 
+impl<E: core::error::Error> core::error::Error for ElementErrors<E> {}
+
 impl Default for VariableValue {
 	fn default() -> Self {
 		Self::String(String::new())
 	}
 }
 
+/// Concatenates values, keeping the variant of the left-hand side.
+///
+/// Strings are concatenated with the string form of the right-hand side,
+/// and arrays are extended with its array form. This is simpler than
+/// `+=` in APML sources, which follows bash: appending an array to
+/// a string makes the string the first element of an array, and
+/// appending a string to an array appends to its first element.
 impl Add for VariableValue {
 	type Output = Self;
 
@@ -303,23 +1013,16 @@ impl<S: AsRef<str>> PartialEq<S> for VariableValue {
 }
 
 impl Display for VariableValue {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
-			Self::String(val) => {
-				f.write_char('\'')?;
-				f.write_str(&val.replace('\'', "'\\''"))?;
-				f.write_char('\'')?;
-				Ok(())
-			}
+			Self::String(val) => write_quoted(f, val),
 			Self::Array(val) => {
 				f.write_char('(')?;
 				for (idx, val) in (1..).zip(val) {
 					if idx != 1 {
 						f.write_char(' ')?;
 					}
-					f.write_char('\'')?;
-					f.write_str(&val.replace('\'', "'\\''"))?;
-					f.write_char('\'')?;
+					write_quoted(f, val)?;
 				}
 				f.write_char(')')?;
 				Ok(())
@@ -328,8 +1031,28 @@ impl Display for VariableValue {
 	}
 }
 
+/// Writes a string in single quotes, or in ANSI-C quotes if it contains
+/// control characters other than newlines and tabs.
+fn write_quoted(
+	f: &mut core::fmt::Formatter<'_>,
+	val: &str,
+) -> core::fmt::Result {
+	if lst::TextUnit::needs_ansi_c_quote(val) {
+		Display::fmt(&lst::TextUnit::ansi_c_quote(val), f)
+	} else {
+		f.write_char('\'')?;
+		f.write_str(&val.replace('\'', "'\\''"))?;
+		f.write_char('\'')
+	}
+}
+
 #[cfg(test)]
 mod test {
+	// hashed sets, also without `std`
+	use std::collections::HashSet;
+
+	use proptest::prelude::*;
+
 	use super::*;
 
 	#[test]
@@ -427,6 +1150,38 @@ mod test {
 		);
 	}
 
+	#[test]
+	fn test_element_helpers() {
+		let value = VariableValue::String("1 x 3 y".into());
+		let errors = value.try_map_elements(str::parse::<u32>).unwrap_err();
+		assert_eq!(
+			errors.iter().map(|e| e.index).collect::<Vec<_>>(),
+			vec![1, 3]
+		);
+		assert_eq!(errors.0[1].element, "y");
+		assert_eq!(
+			errors.to_string(),
+			"x (element 1): invalid digit found in string, \
+			 y (element 3): invalid digit found in string"
+		);
+		assert_eq!(
+			VariableValue::Array(vec!["1".into(), "2".into()])
+				.try_map_elements(str::parse::<u32>)
+				.unwrap(),
+			vec![1, 2]
+		);
+		assert_eq!(
+			VariableValue::default()
+				.try_map_elements(str::parse::<u32>)
+				.unwrap(),
+			Vec::<u32>::new()
+		);
+		assert_eq!(
+			value.filter_elements(|e| e.parse::<u32>().is_ok()),
+			VariableValue::Array(vec!["1".into(), "3".into()])
+		);
+	}
+
 	#[test]
 	fn test_apml_context() {
 		let mut apml = ApmlContext::eval_source(
@@ -487,4 +1242,194 @@ B="${VAR1[*]}"
 			assert_eq!(entries, vec!["A", "B", "VAR1"]);
 		}
 	}
+
+	#[test]
+	fn test_expand_str() {
+		let apml = ApmlContext::eval_source("A=1\nB=(x y)\nC=a.b.c\n").unwrap();
+		let options = eval::EvalOptions::default();
+		let expand = |template| apml.expand_str(template, &options);
+		assert_eq!(expand("").unwrap(), "");
+		assert_eq!(expand("v$A-${C#a.}").unwrap(), "v1-b.c");
+		assert_eq!(expand("${C%%.*}\"${B[@]}\"'$A'").unwrap(), "ax y$A");
+		assert_eq!(expand("\"a b\"").unwrap(), "a b");
+		assert!(matches!(
+			expand("A=1"),
+			Err(ApmlError::Parse(parser::ParseError::SyntaxError(_)))
+		));
+		assert!(matches!(
+			expand("a b"),
+			Err(ApmlError::Parse(parser::ParseError::UnexpectedSource {
+				pos: 2
+			}))
+		));
+		assert!(matches!(
+			expand("a\nB=1"),
+			Err(ApmlError::Parse(
+				parser::ParseError::UnexpectedSource { .. }
+			))
+		));
+		assert!(matches!(expand("'a"), Err(ApmlError::Parse(_))));
+		assert!(matches!(expand("${D:?}"), Err(ApmlError::Eval(_))));
+	}
+
+	#[test]
+	fn test_bulk_transformations() {
+		let src = "C=1\nA=\"$C\"\nB__AMD64=2\nB=3\nA__ARM64=4\n";
+		let mut options = eval::EvalOptions {
+			track_influences: true,
+			..Default::default()
+		};
+		let mut apml = ApmlContext::eval_lst_with(
+			&ApmlLst::parse(src).unwrap(),
+			&mut options,
+		)
+		.unwrap();
+		assert_eq!(
+			apml.keys().collect::<Vec<_>>(),
+			vec!["C", "A", "B__AMD64", "B", "A__ARM64"]
+		);
+
+		apml.retain(|name, _| !name.contains("__"));
+		assert_eq!(apml.keys().collect::<Vec<_>>(), vec!["C", "A", "B"]);
+		assert!(apml.influences("B__AMD64").is_none());
+
+		apml.map_values(|name, value| {
+			if name == "B" {
+				VariableValue::Array(value.into_array())
+			} else {
+				value + name
+			}
+		});
+		assert_eq!(apml["C"], "1C");
+		assert_eq!(apml["B"], VariableValue::Array(vec!["3".to_string()]));
+
+		assert!(matches!(
+			apml.rename_keys(|name| (name == "A").then(|| "C".to_string())),
+			Err(RenameError::Collision(name)) if name == "C"
+		));
+		assert_eq!(apml.keys().collect::<Vec<_>>(), vec!["C", "A", "B"]);
+		apml.rename_keys(|name| match name {
+			"A" => Some("C".to_string()),
+			"C" => Some("A".to_string()),
+			_ => None,
+		})
+		.unwrap();
+		assert_eq!(apml.keys().collect::<Vec<_>>(), vec!["A", "C", "B"]);
+		assert_eq!(apml["C"], "1A");
+		assert_eq!(
+			apml.influences("C").unwrap().iter().collect::<Vec<_>>(),
+			vec!["C"]
+		);
+	}
+
+	#[test]
+	fn test_iteration_order() {
+		fn assert_bounds<I: DoubleEndedIterator + ExactSizeIterator>(_: &I) {}
+
+		let mut apml =
+			ApmlContext::eval_source("A=1\nB=2\nC=3\nA=4\n").unwrap();
+		assert_bounds(&apml.keys());
+		assert_bounds(&apml.iter());
+		assert_bounds(&apml.clone().into_iter());
+		assert_eq!(apml.keys().len(), 3);
+		assert_eq!(apml.keys().rev().collect::<Vec<_>>(), vec!["C", "B", "A"]);
+		assert_eq!(*apml.iter().next_back().unwrap().1, "3");
+		assert_eq!(apml.last_defined(), Some("A"));
+
+		apml.rename_keys(|name| (name == "A").then(|| "D".to_string()))
+			.unwrap();
+		assert_eq!(apml.last_defined(), Some("D"));
+		apml.insert("B".to_string(), "5".into());
+		assert_eq!(apml.last_defined(), Some("B"));
+		apml.retain(|name, _| name != "B");
+		assert_eq!(apml.last_defined(), None);
+		apml.insert("E".to_string(), "6".into());
+		apml.remove("E");
+		assert_eq!(apml.last_defined(), None);
+		assert_eq!(ApmlContext::new().last_defined(), None);
+	}
+
+	#[test]
+	// the cached content hash never changes while shared
+	#[allow(clippy::mutable_key_type)]
+	fn test_content_hash() {
+		let a = ApmlContext::eval_source("A=1\nB=(x y)\n").unwrap();
+		let mut b = ApmlContext::eval_source("B=(x y)\nA=1\n").unwrap();
+		assert_eq!(a, b);
+		assert_eq!(a.content_hash(), b.content_hash());
+		// the content hash is stable
+		assert_eq!(a.content_hash(), 7013298670184208834);
+		let set = HashSet::from([a.clone()]);
+		assert!(set.contains(&b));
+
+		b.insert("A".to_string(), "2".into());
+		assert_ne!(a.content_hash(), b.content_hash());
+		assert_ne!(a, b);
+		*b.get_mut("A").unwrap() = "1".into();
+		assert_eq!(a, b);
+		assert_eq!(a.content_hash(), b.content_hash());
+		b.set_positional_params(vec!["1".to_string()]);
+		assert_ne!(a.content_hash(), b.content_hash());
+
+		// variants are distinguished
+		let a = ApmlContext::eval_source("A=x\n").unwrap();
+		let b = ApmlContext::eval_source("A=(x)\n").unwrap();
+		assert_ne!(a.content_hash(), b.content_hash());
+		assert_ne!(a, b);
+	}
+
+	fn hash(value: &VariableValue) -> u64 {
+		let mut hasher = ContentHasher::default();
+		value.hash(&mut hasher);
+		hasher.finish()
+	}
+
+	/// Generates values of few distinct elements, so that equal values
+	/// are generated often.
+	fn value_strategy() -> impl Strategy<Value = VariableValue> {
+		prop_oneof![
+			"[ab ]{0,2}".prop_map(VariableValue::String),
+			prop::collection::vec("[ab ]{0,2}", 0..3)
+				.prop_map(VariableValue::Array),
+		]
+	}
+
+	/// Generates pairs of values, which are equal half of the time.
+	fn value_pair_strategy()
+	-> impl Strategy<Value = (VariableValue, VariableValue)> {
+		value_strategy().prop_flat_map(|a| {
+			let b = prop_oneof![Just(a.clone()), value_strategy()];
+			(Just(a), b)
+		})
+	}
+
+	proptest! {
+		/// Checks the equality and hashing contract of [`VariableValue`].
+		#[test]
+		fn test_value_hash_contract((a, b) in value_pair_strategy()) {
+			prop_assume!(a == b);
+			prop_assert_eq!(hash(&a), hash(&b), "{} and {}", a, b);
+			let context = |value: &VariableValue| {
+				let mut context = ApmlContext::default();
+				context.insert("A".to_string(), value.clone());
+				context.content_hash()
+			};
+			prop_assert_eq!(context(&a), context(&b), "{} and {}", a, b);
+			prop_assert_eq!(
+				core::mem::discriminant(&a),
+				core::mem::discriminant(&b)
+			);
+		}
+	}
+
+	#[test]
+	fn test_value_hash_kinds() {
+		let string = VariableValue::String("a".into());
+		let array = VariableValue::Array(vec!["a".into()]);
+		assert_ne!(string, array);
+		assert_ne!(hash(&string), hash(&array));
+		assert!(string == "a" && array == "a");
+		let set = HashSet::from([string.clone(), array.clone()]);
+		assert_eq!(set.len(), 2);
+	}
 }
@@ -0,0 +1,592 @@
+//! Packages combined from `spec` and `defines` files.
+//!
+//! A [`Package`] keeps the LSTs of both files along with the combined
+//! context, so that edits of variables can be written back to the file
+//! they belong to. See [`Package::locate_field`].
+
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use super::{
+	ApmlContext, ApmlError, VariableValue,
+	arch::{ArchError, ArchMap, resolve_arch},
+	ast::{ApmlAst, AstNode},
+	build::{AbHost, AbType},
+	compat::LegacySourceField,
+	editor::{ApmlEditor, Style},
+	eval::{self, EvalOptions, VariableResolver},
+	lint::{NoarchOverrideIssue, ShapeIssue, check_noarch_overrides},
+	lst::{ApmlLst, Token},
+	schema::FieldSchema,
+	span::Span,
+	version::{PackageVersion, VersionError},
+};
+
+/// A file of a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileTarget {
+	/// The `spec` file.
+	Spec,
+	/// The `defines` file.
+	Defines,
+}
+
+impl FileTarget {
+	/// Returns the name of the file.
+	pub fn file_name(&self) -> &'static str {
+		match self {
+			FileTarget::Spec => "spec",
+			FileTarget::Defines => "defines",
+		}
+	}
+}
+
+/// Errors produced while expanding a package for an architecture.
+#[derive(Debug, Error)]
+pub enum ArchViewError {
+	#[error(transparent)]
+	Apml(#[from] ApmlError),
+	#[error(transparent)]
+	Arch(#[from] ArchError),
+}
+
+/// Errors produced while evaluating a package.
+#[derive(Debug, Error)]
+pub enum PackageError {
+	#[error(transparent)]
+	Apml(#[from] ApmlError),
+	#[error(
+		"{} ({} at {})",
+		.issue.explain(),
+		.target.file_name(),
+		.issue.span
+	)]
+	Shape {
+		/// The field with a value of the wrong shape.
+		issue: ShapeIssue,
+		/// The file of the definition taking effect.
+		target: FileTarget,
+	},
+}
+
+/// A package combined from its `spec` and `defines` files.
+///
+/// As in ACBS, `defines` is evaluated after `spec` in the same context,
+/// so definitions in `defines` take precedence.
+///
+/// Legacy source fields are folded into `SRCS` and `CHKSUMS` of the
+/// combined context, see [`Package::legacy_sources`].
+#[derive(Debug, Clone)]
+pub struct Package<'a> {
+	spec: ApmlLst<'a>,
+	defines: ApmlLst<'a>,
+	context: ApmlContext,
+	legacy_sources: Vec<LegacySourceField>,
+	schema: FieldSchema,
+}
+
+impl<'a> Package<'a> {
+	/// Parses and evaluates a package.
+	pub fn parse(
+		spec: &'a str,
+		defines: &'a str,
+	) -> Result<Self, PackageError> {
+		let parse = |src| ApmlLst::parse(src).map_err(ApmlError::from);
+		Self::new(parse(spec)?, parse(defines)?)
+	}
+
+	/// Evaluates a package from the LSTs of its files.
+	///
+	/// Fields are placed and checked with the
+	/// [bundled schema][FieldSchema::aosc] by default, see
+	/// [`Package::with_schema`].
+	///
+	/// Fields which do not [accept arrays][accepts_array] must
+	/// not evaluate to arrays, or [`PackageError::Shape`] is returned for
+	/// the first such field. Strings are accepted for array fields, as
+	/// they are split into words. See also
+	/// [`check_shapes`][super::lint::check_shapes], which checks the LST
+	/// before evaluation.
+	///
+	/// [accepts_array]: super::schema::FieldType::accepts_array
+	pub fn new(
+		spec: ApmlLst<'a>,
+		defines: ApmlLst<'a>,
+	) -> Result<Self, PackageError> {
+		let (context, legacy_sources) = Self::eval(&spec, &defines)?;
+		let schema = FieldSchema::aosc();
+		check_shapes(&spec, &defines, &context, &schema)?;
+		Ok(Self {
+			spec,
+			defines,
+			context,
+			legacy_sources,
+			schema,
+		})
+	}
+
+	/// Sets the schema used to place and check fields.
+	///
+	/// The current values are checked against the schema as in
+	/// [`Package::new`].
+	pub fn with_schema(
+		mut self,
+		schema: FieldSchema,
+	) -> Result<Self, PackageError> {
+		check_shapes(&self.spec, &self.defines, &self.context, &schema)?;
+		self.schema = schema;
+		Ok(self)
+	}
+
+	fn eval(
+		spec: &ApmlLst,
+		defines: &ApmlLst,
+	) -> Result<(ApmlContext, Vec<LegacySourceField>), ApmlError> {
+		Self::eval_with(spec, defines, &|_: &str| None::<VariableValue>)
+	}
+
+	fn eval_with(
+		spec: &ApmlLst,
+		defines: &ApmlLst,
+		resolver: &dyn VariableResolver,
+	) -> Result<(ApmlContext, Vec<LegacySourceField>), ApmlError> {
+		let mut context = ApmlContext::default();
+		for lst in [spec, defines] {
+			eval::eval_ast_with_resolver(
+				&mut context,
+				&ApmlAst::emit_from(lst)?,
+				resolver,
+				&mut EvalOptions::default(),
+			)?;
+		}
+		let legacy_sources = context.normalize_source_fields();
+		Ok((context, legacy_sources))
+	}
+
+	/// Returns the combined context.
+	pub fn context(&self) -> &ApmlContext {
+		&self.context
+	}
+
+	/// Returns the host architecture (`ABHOST`) of the package.
+	pub fn abhost(&self) -> Option<AbHost> {
+		AbHost::of(&self.context)
+	}
+
+	/// Returns the build template (`ABTYPE`) of the package.
+	pub fn abtype(&self) -> Option<AbType> {
+		AbType::of(&self.context)
+	}
+
+	/// Returns the version of the package.
+	///
+	/// See [`check_bump`][super::version::check_bump] for checking
+	/// upgrades.
+	pub fn version(&self) -> Result<PackageVersion, VersionError> {
+		PackageVersion::of(&self.context)
+	}
+
+	/// Checks for architecture-specific overrides in both files of
+	/// a `noarch` package.
+	///
+	/// See [`check_noarch_overrides`]. Issues in `spec` come first.
+	pub fn noarch_overrides(
+		&self,
+		map: &ArchMap,
+	) -> Vec<(FileTarget, NoarchOverrideIssue)> {
+		[FileTarget::Spec, FileTarget::Defines]
+			.into_iter()
+			.flat_map(|target| {
+				check_noarch_overrides(self.lst(target), &self.context, map)
+					.into_iter()
+					.map(move |issue| (target, issue))
+			})
+			.collect()
+	}
+
+	/// Returns the legacy source fields of the package, along with the
+	/// file and the span of their effective definitions.
+	///
+	/// These fields are absent in the [combined context][Self::context],
+	/// see [`ApmlContext::normalize_source_fields`].
+	pub fn legacy_sources(
+		&self,
+	) -> impl Iterator<Item = (FileTarget, Span, &LegacySourceField)> {
+		self.legacy_sources.iter().filter_map(|field| {
+			let (target, span) = self.locate_definition(&field.name)?;
+			Some((target, span, field))
+		})
+	}
+
+	/// Expands the package for each of the given architectures.
+	///
+	/// For each architecture, both files are evaluated again with `ARCH`
+	/// set to the architecture, so that values referring to it, such as
+	/// `tbl::https://x/foo-${ARCH}.tar`, are fully expanded, and
+	/// overrides are then [resolved][resolve_arch]. `ARCH` is read from
+	/// a [`VariableResolver`], so it is not in the results unless the
+	/// package defines it.
+	///
+	/// Results are keyed by architecture names in lower case. Each
+	/// architecture is expanded on its own, so a broken override only
+	/// fails the architectures it applies to.
+	pub fn expand_for_arches(
+		&self,
+		arches: &[&str],
+		map: &ArchMap,
+	) -> BTreeMap<String, Result<ApmlContext, ArchViewError>> {
+		arches
+			.iter()
+			.map(|arch| {
+				let arch = arch.to_ascii_lowercase();
+				let resolver = |name: &str| {
+					(name == "ARCH").then(|| VariableValue::from(arch.as_str()))
+				};
+				let result =
+					Self::eval_with(&self.spec, &self.defines, &resolver)
+						.map_err(ArchViewError::from)
+						.and_then(|(context, _)| {
+							Ok(resolve_arch(&context, &arch, map)?)
+						});
+				(arch, result)
+			})
+			.collect()
+	}
+
+	/// Returns the file and the span of the definition of a variable
+	/// which takes effect last.
+	///
+	/// Returns [`None`] if neither file defines the variable.
+	pub fn locate_definition(&self, name: &str) -> Option<(FileTarget, Span)> {
+		locate_definition(&self.spec, &self.defines, name)
+	}
+
+	/// Returns the LST of a file.
+	pub fn lst(&self, target: FileTarget) -> &ApmlLst<'a> {
+		match target {
+			FileTarget::Spec => &self.spec,
+			FileTarget::Defines => &self.defines,
+		}
+	}
+
+	/// Returns the LSTs of `spec` and `defines`.
+	pub fn into_lsts(self) -> (ApmlLst<'a>, ApmlLst<'a>) {
+		(self.spec, self.defines)
+	}
+
+	/// Returns the file that a variable should be written to.
+	///
+	/// This is the file whose definition takes effect in the combined
+	/// context, which is `defines` if both files define the variable.
+	/// Variables defined in neither file are placed according to
+	/// [`FieldSpec::spec`], and unknown variables go to `defines`.
+	///
+	/// [`FieldSpec::spec`]: super::schema::FieldSpec::spec
+	pub fn locate_field(&self, name: &str) -> FileTarget {
+		let defines = |lst: &ApmlLst| {
+			lst.variable_spans().any(|(_, def)| def.name == name)
+		};
+		if defines(&self.defines) {
+			FileTarget::Defines
+		} else if defines(&self.spec)
+			|| self.schema.lookup(name).is_some_and(|field| field.spec)
+		{
+			FileTarget::Spec
+		} else {
+			FileTarget::Defines
+		}
+	}
+
+	/// Sets a variable to a literal string in the file located by
+	/// [`Package::locate_field`], and evaluates the package again.
+	///
+	/// The quoting style of the existing definition is kept, see
+	/// [`ApmlEditor::set_string`]. Later definitions of the variable in
+	/// the same file, including appends, are removed so that the new
+	/// value takes effect. Returns the file that was edited.
+	///
+	/// If the edited package fails to evaluate or to be checked as in
+	/// [`Package::new`], the package is left unchanged.
+	pub fn set(
+		&mut self,
+		name: &'a str,
+		value: &str,
+	) -> Result<FileTarget, PackageError> {
+		let target = self.locate_field(name);
+		let mut lst = self.lst(target).clone();
+		let mut editor = ApmlEditor::wrap(&mut lst);
+		editor.set_string(name, value, Style::MatchExisting);
+		let later = editor
+			.lst_tokens_iter()
+			.enumerate()
+			.filter_map(|(index, token)| match token {
+				Token::Variable(def) if def.name == name => Some(index),
+				_ => None,
+			})
+			.skip(1)
+			.collect::<Vec<_>>();
+		for index in later.into_iter().rev() {
+			editor.remove_var(index);
+		}
+		let (spec, defines) = match target {
+			FileTarget::Spec => (&lst, &self.defines),
+			FileTarget::Defines => (&self.spec, &lst),
+		};
+		let (context, legacy_sources) = Self::eval(spec, defines)?;
+		check_shapes(spec, defines, &context, &self.schema)?;
+		(self.context, self.legacy_sources) = (context, legacy_sources);
+		match target {
+			FileTarget::Spec => self.spec = lst,
+			FileTarget::Defines => self.defines = lst,
+		}
+		Ok(target)
+	}
+}
+
+/// Returns the file and the span of the definition of a variable which
+/// takes effect last.
+fn locate_definition(
+	spec: &ApmlLst,
+	defines: &ApmlLst,
+	name: &str,
+) -> Option<(FileTarget, Span)> {
+	[(FileTarget::Defines, defines), (FileTarget::Spec, spec)]
+		.into_iter()
+		.find_map(|(target, lst)| {
+			let span = lst
+				.variable_spans()
+				.filter(|(_, def)| def.name == name)
+				.map(|(span, _)| span)
+				.last()?;
+			Some((target, span))
+		})
+}
+
+/// Checks that fields not accepting arrays are not evaluated to arrays.
+fn check_shapes(
+	spec: &ApmlLst,
+	defines: &ApmlLst,
+	context: &ApmlContext,
+	schema: &FieldSchema,
+) -> Result<(), PackageError> {
+	for (name, value) in context.iter() {
+		let VariableValue::Array(_) = value else {
+			continue;
+		};
+		let Some(field) = schema.lookup(name) else {
+			continue;
+		};
+		if field.ty.accepts_array() {
+			continue;
+		}
+		let (target, span) = locate_definition(spec, defines, name)
+			.unwrap_or((FileTarget::Defines, Span::default()));
+		return Err(PackageError::Shape {
+			issue: ShapeIssue {
+				name: name.clone(),
+				expected: field.ty,
+				span,
+			},
+			target,
+		});
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::schema::{FieldSpec, FieldType};
+
+	#[test]
+	fn test_set() {
+		let spec = "VER=1.0\nSRCS=\"tbl::https://x/foo-$VER.tar.gz\"\n";
+		let defines = "PKGNAME=foo\nPKGDEP='a b'\n";
+		let mut package = Package::parse(spec, defines).unwrap();
+		assert_eq!(
+			package.context().read("SRCS"),
+			"tbl::https://x/foo-1.0.tar.gz"
+		);
+
+		assert_eq!(package.set("VER", "2.0").unwrap(), FileTarget::Spec);
+		assert_eq!(
+			package.lst(FileTarget::Spec).to_string(),
+			"VER=2.0\nSRCS=\"tbl::https://x/foo-$VER.tar.gz\"\n"
+		);
+		assert_eq!(package.lst(FileTarget::Defines).to_string(), defines);
+		assert_eq!(
+			package.context().read("SRCS"),
+			"tbl::https://x/foo-2.0.tar.gz"
+		);
+
+		let spec = package.lst(FileTarget::Spec).to_string();
+		assert_eq!(package.set("PKGDEP", "c").unwrap(), FileTarget::Defines);
+		assert_eq!(
+			package.lst(FileTarget::Defines).to_string(),
+			"PKGNAME=foo\nPKGDEP='c'\n"
+		);
+		assert_eq!(package.lst(FileTarget::Spec).to_string(), spec);
+
+		assert_eq!(package.locate_field("CHKUPDATE"), FileTarget::Spec);
+		assert_eq!(package.locate_field("PKGDES"), FileTarget::Defines);
+		assert_eq!(package.locate_field("FOO"), FileTarget::Defines);
+		assert_eq!(package.set("PKGDES", "Foo").unwrap(), FileTarget::Defines);
+		assert_eq!(package.lst(FileTarget::Spec).to_string(), spec);
+		assert_eq!(package.context().read("PKGDES"), "Foo");
+
+		let mut package =
+			Package::parse("", "PKGDEP=a\nPKGDEP+=\" b\"\nPKGDEP=c\n").unwrap();
+		package.set("PKGDEP", "d").unwrap();
+		assert_eq!(package.lst(FileTarget::Defines).to_string(), "PKGDEP=d\n");
+		assert_eq!(package.context().read("PKGDEP"), "d");
+
+		// failed evaluation leaves the package unchanged
+		let spec = "VER=1\nSRCS=\"${VER:?no version}\"\n";
+		let mut package = Package::parse(spec, "").unwrap();
+		assert!(package.set("VER", "").is_err());
+		assert_eq!(package.lst(FileTarget::Spec).to_string(), spec);
+		assert_eq!(package.context().read("SRCS"), "1");
+
+		let package = Package::parse("PKGDEP=a\n", "").unwrap();
+		assert_eq!(package.locate_field("PKGDEP"), FileTarget::Spec);
+		let package = Package::parse("PKGDEP=a\n", "PKGDEP=b\n").unwrap();
+		assert_eq!(package.locate_field("PKGDEP"), FileTarget::Defines);
+		assert_eq!(package.context().read("PKGDEP"), "b");
+	}
+
+	#[test]
+	fn test_shapes() {
+		for field in ["VER", "REL", "PKGNAME", "PKGDES", "PKGSEC"] {
+			let defines = format!("{field}=x\n{field}+=(y)\n");
+			let Err(PackageError::Shape { issue, target }) =
+				Package::parse("", &defines)
+			else {
+				panic!("arrays should be rejected for {field}");
+			};
+			assert_eq!(issue.name, field);
+			assert_eq!(target, FileTarget::Defines);
+			assert_eq!(issue.span.slice(&defines), format!("{field}+=(y)"));
+		}
+		for field in ["PKGDEP", "BUILDDEP", "PKGRECOM", "PKGBREAK", "SRCS"] {
+			let defines = format!("{field}=\"a b\"\n");
+			let package = Package::parse("", &defines).unwrap();
+			assert_eq!(package.context().read(field).as_array(), ["a", "b"]);
+		}
+
+		let mut package = Package::parse("VER=1\n", "").unwrap();
+		assert!(matches!(package.set("VER", "2"), Ok(FileTarget::Spec)));
+		let package = Package::parse("", "FOO=(a)\n").unwrap();
+		let schema = FieldSchema::builder()
+			.field(FieldSpec::new("FOO", FieldType::Scalar))
+			.build();
+		assert!(matches!(
+			package.with_schema(schema),
+			Err(PackageError::Shape {
+				target: FileTarget::Defines,
+				..
+			})
+		));
+	}
+
+	#[test]
+	fn test_build_settings() {
+		let spec = "VER=1\nSRCS__AMD64=a\n";
+		let defines = "ABHOST=noarch\nABTYPE=dummy\nPKGDEP__RETRO=b\n\
+			PKGDEP__FOO=c\n";
+		let package = Package::parse(spec, defines).unwrap();
+		assert_eq!(package.abhost(), Some(AbHost::Noarch));
+		assert_eq!(package.abtype(), Some(AbType::Dummy));
+		let issues = package.noarch_overrides(&ArchMap::aosc());
+		assert_eq!(
+			issues
+				.iter()
+				.map(|(target, issue)| (
+					*target,
+					issue
+						.span
+						.slice(package.lst(*target).to_string().as_str())
+						.to_string(),
+					issue.suffix.as_str()
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				(FileTarget::Spec, "SRCS__AMD64=a".to_string(), "AMD64"),
+				(FileTarget::Defines, "PKGDEP__RETRO=b".to_string(), "RETRO"),
+			]
+		);
+		assert_eq!(
+			issues[0].1.explain(),
+			"SRCS__AMD64 overrides for AMD64, which has no effect as ABHOST \
+			is noarch."
+		);
+
+		let package = Package::parse(spec, "ABHOST=amd64\n").unwrap();
+		assert!(package.noarch_overrides(&ArchMap::aosc()).is_empty());
+		assert_eq!(package.abtype(), None);
+	}
+
+	#[test]
+	fn test_legacy_sources() {
+		let spec = "VER=1.0\nGITSRC=https://x/foo\nGITCO=v$VER\n\
+			SRCS_1=\"tbl::https://x/a.tar\"\nCHKSUMS_1=\"sha256::a\"\n";
+		let package = Package::parse(spec, "PKGNAME=foo\n").unwrap();
+		let context = package.context();
+		assert_eq!(
+			context.read("SRCS"),
+			"git::commit=v1.0::https://x/foo tbl::https://x/a.tar"
+		);
+		assert_eq!(context.read("CHKSUMS"), "SKIP sha256::a");
+		assert!(!context.contains_var("GITSRC"));
+		let spans = package
+			.legacy_sources()
+			.map(|(target, span, field)| {
+				(target, &spec[span.start..span.end], field.ignored)
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			spans,
+			vec![
+				(FileTarget::Spec, "GITSRC=https://x/foo", false),
+				(FileTarget::Spec, "GITCO=v$VER", false),
+				(FileTarget::Spec, "SRCS_1=\"tbl::https://x/a.tar\"", false),
+				(FileTarget::Spec, "CHKSUMS_1=\"sha256::a\"", false),
+			]
+		);
+
+		let package =
+			Package::parse("SRCS=a\nSRCTBL=b\n", "SRCTBL=c\n").unwrap();
+		assert_eq!(package.context().read("SRCS"), "a");
+		let (target, _, field) = package.legacy_sources().next().unwrap();
+		assert_eq!(target, FileTarget::Defines);
+		assert_eq!(
+			field.explain(),
+			"SRCTBL is deprecated and ignored, as SRCS is already defined."
+		);
+	}
+	#[test]
+	fn test_expand_for_arches() {
+		let spec = "VER=1.0\nSRCS=\"tbl::https://x/foo-$ARCH.tar\"\n";
+		let defines = "PKGDEP=a\nPKGDEP__AMD64=\"b-${ARCH}\"\n\
+			B__MAINLINE=x\nB__ARCH_LP64=y\n";
+		let package = Package::parse(spec, defines).unwrap();
+		let views =
+			package.expand_for_arches(&["i486", "AMD64"], &ArchMap::aosc());
+		assert_eq!(views.keys().collect::<Vec<_>>(), vec!["amd64", "i486"]);
+		// mainline is smaller than arch_lp64
+		assert_eq!(views["amd64"].as_ref().unwrap().read("B"), "x");
+		let context = views["i486"].as_ref().unwrap();
+		assert_eq!(context.read("SRCS"), "tbl::https://x/foo-i486.tar");
+		assert_eq!(context.read("PKGDEP"), "a");
+		assert!(!context.contains_var("ARCH"));
+		assert!(!context.contains_var("B"));
+
+		let package =
+			Package::parse(spec, "PKGDEP__AMD64=\"b-$ARCH\"\n").unwrap();
+		let views = package.expand_for_arches(&["amd64"], &ArchMap::aosc());
+		let context = views["amd64"].as_ref().unwrap();
+		assert_eq!(context.read("SRCS"), "tbl::https://x/foo-amd64.tar");
+		assert_eq!(context.read("PKGDEP"), "b-amd64");
+		assert!(!package.context().contains_var("ARCH"));
+	}
+}
@@ -1,15 +1,18 @@
 //! Parser combinators to parse APML source code to [LST][super::lst].
 
-use std::{borrow::Cow, sync::Arc};
+use alloc::{borrow::Cow, sync::Arc};
 
 use nom::{
 	IResult,
 	branch::alt,
-	bytes::complete::{tag, take, take_till, take_while, take_while1},
+	bytes::complete::{
+		is_not, tag, take, take_till, take_while, take_while_m_n, take_while1,
+	},
 	character::complete::{anychar, char, newline, one_of},
 	combinator::{map, opt, recognize, value},
+	error::{ErrorKind, ParseError as _},
 	multi::{many0, many1},
-	sequence::{delimited, pair, preceded, tuple},
+	sequence::{delimited, pair, preceded, terminated, tuple},
 };
 use thiserror::Error;
 
@@ -17,6 +20,9 @@ use crate::apml::pattern::{BashPattern, bash_pattern};
 
 use super::lst::*;
 
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
 /// Errors produced while parsing the input source.
 #[derive(Debug, Error)]
 pub enum ParseError {
@@ -24,6 +30,31 @@ pub enum ParseError {
 	SyntaxError(String),
 	#[error("Unexpected source at char {pos}")]
 	UnexpectedSource { pos: usize },
+	/// A `${` not followed by a valid expansion and a closing `}`.
+	///
+	/// The position points to the `$`. Bash rejects such expansions as
+	/// bad substitutions, and does not treat a `#` in them as the start
+	/// of a comment, so the parser does neither.
+	#[error("Malformed expansion starting at char {pos}")]
+	MalformedExpansion { pos: usize },
+}
+
+impl ParseError {
+	/// Converts an error produced by parsing a source, locating
+	/// malformed expansions in the source.
+	pub(crate) fn locate(
+		src: &str,
+		err: nom::Err<nom::error::Error<&str>>,
+	) -> Self {
+		match err {
+			nom::Err::Failure(err) if err.code == ErrorKind::Fail => {
+				Self::MalformedExpansion {
+					pos: nom::Offset::offset(src, err.input) + 1,
+				}
+			}
+			err => err.into(),
+		}
+	}
 }
 
 impl From<nom::Err<nom::error::Error<&str>>> for ParseError {
@@ -32,22 +63,116 @@ impl From<nom::Err<nom::error::Error<&str>>> for ParseError {
 	}
 }
 
+/// Options of the parser.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+	/// Whether custom expansions (`${@name args}`) are accepted.
+	///
+	/// Custom expansions are not bash syntax, so they are rejected unless
+	/// enabled by this. See [`CustomExpansion`].
+	pub custom_expansions: bool,
+}
+
 /// Parses a complete APML source into LST.
-pub fn apml_lst(i: &str) -> IResult<&str, ApmlLst> {
-	map(many0(token), ApmlLst)(i)
+pub fn apml_lst(i: &str, options: ParseOptions) -> IResult<&str, ApmlLst> {
+	map(collect0(|s| token(s, options)), ApmlLst)(i)
+}
+
+/// Applies a parser until it fails, collecting the results.
+///
+/// Unlike [`many0`], the vector is not left with spare capacity, as
+/// most sequences in sources are short and there are a lot of them. This
+/// keeps the memory used by an LST within a constant factor of the size
+/// of the source.
+fn collect0<'a, O, F>(
+	mut f: F,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<O>>
+where
+	F: FnMut(&'a str) -> IResult<&'a str, O>,
+{
+	move |mut i| {
+		let mut results = Vec::new();
+		loop {
+			match f(i) {
+				Ok((rest, result)) => {
+					// infinite loop check, see many0
+					if rest.len() == i.len() {
+						return Err(nom::Err::Error(
+							nom::error::Error::from_error_kind(
+								i,
+								ErrorKind::Many0,
+							),
+						));
+					}
+					results.push(result);
+					i = rest;
+				}
+				Err(nom::Err::Error(_)) => break,
+				Err(err) => return Err(err),
+			}
+		}
+		results.shrink_to_fit();
+		Ok((i, results))
+	}
 }
 
+/// Applies a parser until it fails, collecting at least one result.
+///
+/// See [`collect0`].
+fn collect1<'a, O, F>(
+	f: F,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<O>>
+where
+	F: FnMut(&'a str) -> IResult<&'a str, O>,
+{
+	let mut f = collect0(f);
+	move |i| {
+		let (rest, results) = f(i)?;
+		if results.is_empty() {
+			return Err(nom::Err::Error(nom::error::Error::from_error_kind(
+				i,
+				ErrorKind::Many1,
+			)));
+		}
+		Ok((rest, results))
+	}
+}
+
+/// Parses a single APML word, as used in [`ApmlContext::expand_str`].
+///
+/// The word ends at the first unquoted whitespace or newline.
+/// Assignments are rejected.
+///
+/// [`ApmlContext::expand_str`]: super::ApmlContext::expand_str
+pub fn apml_word(i: &str, options: ParseOptions) -> IResult<&str, Text<'_>> {
+	if pair(assignment_name, variable_op)(i).is_ok() {
+		return Err(nom::Err::Error(nom::error::Error::new(
+			i,
+			nom::error::ErrorKind::Verify,
+		)));
+	}
+	text_or_null(i, options, &|ch| ch != ' ' && ch != '\t')
+}
+
+/// Parses a single top-level token.
 #[inline]
-fn token(i: &str) -> IResult<&str, Token> {
+pub(super) fn token(
+	i: &str,
+	options: ParseOptions,
+) -> IResult<&str, Token<'_>> {
 	alt((
 		// spacy
 		map(spacy_char, Token::Spacy),
 		// newline
 		value(Token::Newline, newline),
+		// line continuation
+		value(Token::LineContinuation, tag("\\\n")),
 		// comment
 		comment_token,
 		// variable definition
-		map(variable_def, Token::Variable),
+		map(|s| variable_def(s, options), Token::Variable),
+		// set statement
+		map(set_command, Token::Set),
 	))(i)
 }
 
@@ -64,9 +189,33 @@ fn comment_token(i: &str) -> IResult<&str, Token> {
 }
 
 #[inline]
-fn variable_def(i: &str) -> IResult<&str, VariableDefinition> {
+fn set_command(i: &str) -> IResult<&str, SetCommand<'_>> {
 	map(
-		tuple((variable_name, variable_op, variable_value)),
+		preceded(
+			tag("set"),
+			many1(pair(
+				take_while1(|ch| ch == ' ' || ch == '\t'),
+				take_while1(|ch: char| {
+					ch.is_ascii_alphanumeric() || "-+_".contains(ch)
+				}),
+			)),
+		),
+		|args| SetCommand {
+			args: args
+				.into_iter()
+				.map(|(space, arg)| (Cow::Borrowed(space), Cow::Borrowed(arg)))
+				.collect(),
+		},
+	)(i)
+}
+
+#[inline]
+fn variable_def(
+	i: &str,
+	options: ParseOptions,
+) -> IResult<&str, VariableDefinition> {
+	map(
+		tuple((assignment_name, variable_op, |s| variable_value(s, options))),
 		|(name, op, value)| VariableDefinition {
 			name: Cow::Borrowed(name),
 			op,
@@ -88,60 +237,110 @@ fn variable_name(i: &str) -> IResult<&str, &str> {
 	take_while1(|ch: char| ch.is_alphanumeric() || ch == '_')(i)
 }
 
+/// Parses the name of an assigned variable.
+///
+/// Unlike [`variable_name`], only identifiers are accepted, since bash
+/// takes words such as `1A=x` as commands. Only the first `=` following
+/// the name is an operator, and other `=` characters in the statement
+/// are literal, as in `A=B=C` or `A=(-DFOO=bar)`.
 #[inline]
-fn variable_value(i: &str) -> IResult<&str, VariableValue> {
+fn assignment_name(i: &str) -> IResult<&str, &str> {
+	recognize(pair(
+		take_while_m_n(1, 1, |ch: char| ch.is_ascii_alphabetic() || ch == '_'),
+		take_while(|ch: char| ch.is_ascii_alphanumeric() || ch == '_'),
+	))(i)
+}
+
+/// Parses a name that can be expanded, including special parameters.
+#[inline]
+fn expansion_name(i: &str) -> IResult<&str, &str> {
+	alt((variable_name, tag("*"), tag("@")))(i)
+}
+
+/// Parses a name following an unbraced `$`.
+///
+/// As in bash, only a single digit is taken for positional parameters,
+/// so `$10` and `$1a` expand `$1` followed by literal text.
+#[inline]
+fn unbraced_name(i: &str) -> IResult<&str, &str> {
+	alt((
+		take_while_m_n(1, 1, |ch: char| ch.is_ascii_digit()),
+		expansion_name,
+	))(i)
+}
+
+#[inline]
+fn variable_value(
+	i: &str,
+	options: ParseOptions,
+) -> IResult<&str, VariableValue> {
 	alt((
 		// array
 		map(
-			delimited(char('('), many0(array_token), char(')')),
+			delimited(
+				char('('),
+				collect0(|s| array_token(s, options)),
+				char(')'),
+			),
 			VariableValue::Array,
 		),
 		// string
 		map(
-			|s| text_or_null(s, &|ch| ch != ' ' && ch != '#'),
+			|s| text_or_null(s, options, &|ch| ch != ' ' && ch != '\t'),
 			|text| VariableValue::String(Arc::new(text)),
 		),
 	))(i)
 }
 
 #[inline]
-fn array_token(i: &str) -> IResult<&str, ArrayToken> {
+fn array_token(i: &str, options: ParseOptions) -> IResult<&str, ArrayToken> {
 	alt((
 		// spacy
 		map(spacy_char, ArrayToken::Spacy),
 		// newline
 		value(ArrayToken::Newline, newline),
+		// line continuation
+		value(ArrayToken::LineContinuation, tag("\\\n")),
 		//comment
 		map(preceded(char('#'), take_till(|ch| ch == '\n')), |comment| {
 			ArrayToken::Comment(Cow::Borrowed(comment))
 		}),
 		// element
 		map(
-			|s| text(s, &|ch| ch != ' ' && ch != '#' && ch != ')'),
+			|s| text(s, options, &|ch| ch != ' ' && ch != '\t' && ch != ')'),
 			|text| ArrayToken::Element(Arc::new(text)),
 		),
 	))(i)
 }
 
 #[inline]
-fn text<'a, Cond>(i: &'a str, cond: &Cond) -> IResult<&'a str, Text<'a>>
+fn text<'a, Cond>(
+	i: &'a str,
+	options: ParseOptions,
+	cond: &Cond,
+) -> IResult<&'a str, Text<'a>>
 where
 	Cond: Fn(char) -> bool,
 {
-	map(many1(|s| text_unit(s, &cond)), Text)(i)
+	map(collect1(|s| text_unit(s, options, &cond)), Text)(i)
 }
 
 #[inline]
-fn text_or_null<'a, Cond>(i: &'a str, cond: &Cond) -> IResult<&'a str, Text<'a>>
+fn text_or_null<'a, Cond>(
+	i: &'a str,
+	options: ParseOptions,
+	cond: &Cond,
+) -> IResult<&'a str, Text<'a>>
 where
 	Cond: Fn(char) -> bool,
 {
-	map(many0(|s| text_unit(s, &cond)), Text)(i)
+	map(collect0(|s| text_unit(s, options, &cond)), Text)(i)
 }
 
 #[inline]
 fn text_unit<'a, Cond>(
 	i: &'a str,
+	options: ParseOptions,
 	cond: &Cond,
 ) -> IResult<&'a str, TextUnit<'a>>
 where
@@ -156,19 +355,45 @@ where
 			}),
 			char('\''),
 		),
+		// ANSI-C quoted
+		delimited(
+			tag("$'"),
+			map(
+				recognize(many0(alt((
+					recognize(pair(char('\\'), anychar)),
+					is_not("\\'"),
+				)))),
+				|s| TextUnit::AnsiCQuote(Cow::Borrowed(s)),
+			),
+			char('\''),
+		),
+		// locale translated
+		delimited(
+			tag("$\""),
+			map(
+				collect0(|s| word(s, options, &|_| true, &one_of("$\\\"`"))),
+				TextUnit::LocaleQuote,
+			),
+			char('"'),
+		),
 		// double quoted
 		delimited(
 			char('"'),
 			map(
-				many0(|s| word(s, &|_| true, &one_of("$\\\"`"))),
+				collect0(|s| word(s, options, &|_| true, &one_of("$\\\"`"))),
 				TextUnit::DoubleQuote,
 			),
 			char('"'),
 		),
 		// unquoted
 		map(
-			many1(|s| {
-				word(s, &|ch| cond(ch) && ch != '\'' && ch != '\n', &anychar)
+			collect1(|s| {
+				word(
+					s,
+					options,
+					&|ch| cond(ch) && ch != '\'' && ch != '\n',
+					&anychar,
+				)
 			}),
 			TextUnit::Unquoted,
 		),
@@ -178,6 +403,7 @@ where
 #[inline]
 fn word<'a, Cond, EscCond>(
 	i: &'a str,
+	options: ParseOptions,
 	cond: &Cond,
 	escape_cond: &EscCond,
 ) -> IResult<&'a str, Word<'a>>
@@ -186,21 +412,28 @@ where
 	EscCond: Fn(&'a str) -> IResult<&'a str, char>,
 {
 	alt((
+		// custom expansion
+		map(|s| custom_expansion(s, options), Word::Custom),
 		// braced variable
-		map(delimited(tag("${"), braced_expansion, char('}')), |exp| {
-			Word::BracedVariable(exp)
-		}),
+		map(|s| braced_variable(s, options), Word::BracedVariable),
 		// unbraced variable
-		map(preceded(char('$'), variable_name), |name| {
+		map(preceded(char('$'), unbraced_name), |name| {
 			Word::UnbracedVariable(Cow::Borrowed(name))
 		}),
 		// subcommand
 		map(
-			delimited(tag("$("), many0(array_token), char(')')),
+			delimited(
+				tag("$("),
+				collect0(|s| array_token(s, options)),
+				char(')'),
+			),
 			Word::Subcommand,
 		),
 		// literal
-		map(many1(|s| literal_part(s, cond, escape_cond)), Word::Literal),
+		map(
+			collect1(|s| literal_part(s, cond, escape_cond)),
+			Word::Literal,
+		),
 	))(i)
 }
 
@@ -232,16 +465,73 @@ where
 }
 
 #[inline]
-fn braced_expansion(i: &str) -> IResult<&str, BracedExpansion> {
+fn custom_expansion(
+	i: &str,
+	options: ParseOptions,
+) -> IResult<&str, CustomExpansion<'_>> {
+	if !options.custom_expansions {
+		return Err(nom::Err::Error(nom::error::Error::new(
+			i,
+			ErrorKind::Verify,
+		)));
+	}
+	map(
+		delimited(
+			tag("${@"),
+			pair(
+				variable_name,
+				collect0(pair(
+					take_while1(|ch| ch == ' ' || ch == '\t'),
+					variable_name,
+				)),
+			),
+			char('}'),
+		),
+		|(name, args)| CustomExpansion {
+			name: Cow::Borrowed(name),
+			args: args
+				.into_iter()
+				.map(|(space, arg)| (Cow::Borrowed(space), Cow::Borrowed(arg)))
+				.collect(),
+		},
+	)(i)
+}
+
+/// Parses a braced expansion (`${...}`).
+///
+/// Once `${` is seen, the expansion must be complete, otherwise parsing
+/// fails at the `$` without backtracking, instead of leaving the rest of
+/// the expansion to other parsers.
+#[inline]
+fn braced_variable(
+	i: &str,
+	options: ParseOptions,
+) -> IResult<&str, BracedExpansion<'_>> {
+	let (rest, _) = tag("${")(i)?;
+	match terminated(|s| braced_expansion(s, options), char('}'))(rest) {
+		Err(nom::Err::Error(_)) => Err(nom::Err::Failure(
+			nom::error::Error::new(i, ErrorKind::Fail),
+		)),
+		result => result,
+	}
+}
+
+#[inline]
+fn braced_expansion(
+	i: &str,
+	options: ParseOptions,
+) -> IResult<&str, BracedExpansion> {
 	alt((
 		// length of
-		map(preceded(char('#'), variable_name), |name| BracedExpansion {
-			name: Cow::Borrowed(name),
-			modifier: Some(ExpansionModifier::Length),
+		map(preceded(char('#'), expansion_name), |name| {
+			BracedExpansion {
+				name: Cow::Borrowed(name),
+				modifier: Some(ExpansionModifier::Length),
+			}
 		}),
 		// other
 		map(
-			pair(variable_name, opt(expansion_modifier)),
+			pair(expansion_name, opt(|s| expansion_modifier(s, options))),
 			|(name, modifier)| BracedExpansion {
 				name: Cow::Borrowed(name),
 				modifier,
@@ -251,7 +541,10 @@ fn braced_expansion(i: &str) -> IResult<&str, BracedExpansion> {
 }
 
 #[inline]
-fn expansion_modifier(i: &str) -> IResult<&str, ExpansionModifier> {
+fn expansion_modifier(
+	i: &str,
+	options: ParseOptions,
+) -> IResult<&str, ExpansionModifier> {
 	#[inline]
 	fn expansion_glob(i: &str) -> IResult<&str, Arc<BashPattern>> {
 		map(|s| bash_pattern(s, "}"), Arc::new)(i)
@@ -260,10 +553,8 @@ fn expansion_modifier(i: &str) -> IResult<&str, ExpansionModifier> {
 	fn expansion_glob_replace(i: &str) -> IResult<&str, Arc<BashPattern>> {
 		map(|s| bash_pattern(s, "}/"), Arc::new)(i)
 	}
-	#[inline]
-	fn expansion_text(i: &str) -> IResult<&str, Arc<Text>> {
-		map(|s| text_or_null(s, &|ch| ch != '}'), Arc::new)(i)
-	}
+	let expansion_text =
+		|s| map(|s| text_or_null(s, options, &|ch| ch != '}'), Arc::new)(s);
 	alt((
 		map(
 			preceded(tag("##"), expansion_glob),
@@ -394,10 +685,16 @@ fn substring_expansion_modifier(i: &str) -> IResult<&str, ExpansionModifier> {
 #[cfg(test)]
 mod test {
 	use crate::apml::{
+		ApmlContext, VariableValue as Value,
 		parser::*,
 		pattern::{BashPattern, GlobPart},
 	};
 
+	/// Options accepting only bash syntax.
+	const BASH: ParseOptions = ParseOptions {
+		custom_expansions: false,
+	};
+
 	#[test]
 	fn test_ast() {
 		let src = r##"# Test APML
@@ -414,7 +711,7 @@ b+=("-a" \
 )
 "##;
 		assert_eq!(
-			apml_lst(src).unwrap(),
+			apml_lst(src, BASH).unwrap(),
 			(
 				"",
 				ApmlLst(vec![
@@ -801,11 +1098,7 @@ b+=("-a" \
 								)])
 							]))),
 							ArrayToken::Spacy(' '),
-							ArrayToken::Element(Arc::new(Text(vec![
-								TextUnit::Unquoted(vec![Word::Literal(vec![
-									LiteralPart::LineContinuation
-								])])
-							]))),
+							ArrayToken::LineContinuation,
 							ArrayToken::Spacy(' '),
 							ArrayToken::Spacy(' '),
 							ArrayToken::Spacy(' '),
@@ -849,7 +1142,7 @@ b+=("-a" \
 				])
 			)
 		);
-		assert_eq!(apml_lst(src).unwrap().1.to_string(), src);
+		assert_eq!(apml_lst(src, BASH).unwrap().1.to_string(), src);
 		let src = r##"PKGVER=8.2
 PKGDEP="x11-lib libdrm expat systemd elfutils libvdpau nettle \
         libva wayland s2tc lm-sensors libglvnd llvm-runtime libclc"
@@ -858,28 +1151,32 @@ MESON_AFTER="-Ddri-drivers-path=/usr/lib/xorg/modules/dri \
 MESON_AFTER__AMD64=" \
              ${MESON_AFTER} \
              -Dlibunwind=true""##;
-		assert_eq!(apml_lst(src).unwrap().1.to_string(), src);
+		assert_eq!(apml_lst(src, BASH).unwrap().1.to_string(), src);
 	}
 
 	#[test]
 	fn test_token() {
 		assert_eq!(
-			token("#asdf").unwrap(),
+			token("#asdf", BASH).unwrap(),
 			("", Token::Comment(Cow::Borrowed("asdf")))
 		);
 		assert_eq!(
-			token("#asdf \n").unwrap(),
+			token("#asdf \n", BASH).unwrap(),
 			("\n", Token::Comment(Cow::Borrowed("asdf ")))
 		);
 		assert_eq!(
-			token("#\n").unwrap(),
+			token("#\n", BASH).unwrap(),
 			("\n", Token::Comment(Cow::Borrowed("")))
 		);
-		assert_eq!(token(" ").unwrap(), ("", Token::Spacy(' ')));
-		assert_eq!(token("\t").unwrap(), ("", Token::Spacy('\t')));
-		assert_eq!(token("\n").unwrap(), ("", Token::Newline));
+		assert_eq!(token(" ", BASH).unwrap(), ("", Token::Spacy(' ')));
+		assert_eq!(token("\t", BASH).unwrap(), ("", Token::Spacy('\t')));
+		assert_eq!(token("\n", BASH).unwrap(), ("", Token::Newline));
 		assert_eq!(
-			token("a=\n").unwrap(),
+			token("\\\n#c", BASH).unwrap(),
+			("#c", Token::LineContinuation)
+		);
+		assert_eq!(
+			token("a=\n", BASH).unwrap(),
 			(
 				"\n",
 				Token::Variable(VariableDefinition {
@@ -889,6 +1186,113 @@ MESON_AFTER__AMD64=" \
 				})
 			)
 		);
+		assert_eq!(
+			token("set -e  +u #c\n", BASH).unwrap(),
+			(
+				" #c\n",
+				Token::Set(SetCommand {
+					args: vec![
+						(Cow::Borrowed(" "), Cow::Borrowed("-e")),
+						(Cow::Borrowed("  "), Cow::Borrowed("+u")),
+					]
+				})
+			)
+		);
+		assert!(matches!(
+			token("set=1\n", BASH).unwrap(),
+			("\n", Token::Variable(_))
+		));
+		assert!(token("set\n", BASH).is_err());
+		assert!(token("set $A\n", BASH).is_err());
+	}
+
+	#[test]
+	fn test_comment_detection() {
+		let src = "A=foo #comment\nB=\"foo # x\" #c\nC=foo\\ #still\n\
+			D=foo#glued\nE=#x\nF=foo\t#c\nG=(a#b #c\n d)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(lst.to_string(), src);
+		let comments = lst
+			.0
+			.iter()
+			.filter_map(|token| match token {
+				Token::Comment(text) => Some(text.as_ref()),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(comments, vec!["comment", "c", "c"]);
+		let ctx = ApmlContext::eval_lst(&lst).unwrap();
+		assert_eq!(ctx["A"], "foo");
+		assert_eq!(ctx["B"], "foo # x");
+		assert_eq!(ctx["C"], "foo #still");
+		assert_eq!(ctx["D"], "foo#glued");
+		assert_eq!(ctx["E"], "#x");
+		assert_eq!(ctx["F"], "foo");
+		assert_eq!(
+			ctx["G"],
+			Value::Array(vec!["a#b".to_string(), "d".to_string()])
+		);
+	}
+
+	#[test]
+	fn test_line_continuation() {
+		// continuations.apml of the conformance corpus is checked against bash
+		let src = "A=a \\\n# c\nB=\"a\\\n# c\nb\"\nC=c \\\n\n\
+			D=\"a\\\n\nb\"\nE=(a \\\n# c\nb)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(lst.to_string(), src);
+		let ctx = ApmlContext::eval_lst(&lst).unwrap();
+		assert_eq!(ctx["A"], "a");
+		assert_eq!(ctx["B"], "a# c\nb");
+		assert_eq!(ctx["C"], "c");
+		assert_eq!(ctx["D"], "a\nb");
+		assert_eq!(
+			ctx["E"],
+			Value::Array(vec!["a".to_string(), "b".to_string()])
+		);
+		assert_eq!(ctx.keys().len(), 5);
+		// the comment is glued to the value, as in `A=a# c`
+		assert!(ApmlLst::parse("A=a\\\n# c\n").is_err());
+	}
+
+	#[test]
+	fn test_malformed_expansion() {
+		// `#` in an expansion is an operator, never a comment
+		let src = "A=/a/b.c\nB=${A#*/}\nC=\"${A##*/}\"\nD=${#A}\n\
+			E=${A%.c}#c\nF=(${A#/a} #c\n)\nG=${A:-x #y} #c\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(lst.to_string(), src);
+		assert_eq!(
+			lst.0
+				.iter()
+				.filter(|token| matches!(token, Token::Comment(_)))
+				.count(),
+			1
+		);
+		crate::assert_evals_to!(src, {
+			"B" => "a/b.c",
+			"C" => "b.c",
+			"D" => "6",
+			"E" => "/a/b#c",
+			"F" => ["/b.c"],
+			"G" => "/a/b.c",
+		});
+
+		// malformed expansions are reported at the opener
+		let pos = |src| match ApmlLst::parse(src) {
+			Err(ParseError::MalformedExpansion { pos }) => pos,
+			result => panic!("{:?}", result),
+		};
+		assert_eq!(pos("A=${B #c}\n"), 3);
+		assert_eq!(pos("A=\"x ${B #c}\"\n"), 6);
+		assert_eq!(pos("A=(a ${B #c})\n"), 6);
+		assert_eq!(pos("A=x\nB=${A#c\n"), 7);
+		assert_eq!(pos("A=${A:-${B #}}\n"), 8);
+		assert_eq!(pos("A=${\n"), 3);
+		assert_eq!(
+			ParseError::MalformedExpansion { pos: 3 }.to_string(),
+			"Malformed expansion starting at char 3"
+		);
 	}
 
 	#[test]
@@ -901,10 +1305,10 @@ MESON_AFTER__AMD64=" \
 
 	#[test]
 	fn test_variable_def() {
-		variable_def("=\n").unwrap_err();
-		variable_def("?=\n").unwrap_err();
+		variable_def("=\n", BASH).unwrap_err();
+		variable_def("?=\n", BASH).unwrap_err();
 		assert_eq!(
-			variable_def("a=\n").unwrap(),
+			variable_def("a=\n", BASH).unwrap(),
 			("\n", VariableDefinition {
 				name: Cow::Borrowed("a"),
 				op: VariableOp::Assignment,
@@ -912,7 +1316,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			variable_def("a=b$0\n").unwrap(),
+			variable_def("a=b$0\n", BASH).unwrap(),
 			("\n", VariableDefinition {
 				name: Cow::Borrowed("a"),
 				op: VariableOp::Assignment,
@@ -927,7 +1331,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			variable_def("a+=b$0\n").unwrap(),
+			variable_def("a+=b$0\n", BASH).unwrap(),
 			("\n", VariableDefinition {
 				name: Cow::Borrowed("a"),
 				op: VariableOp::Append,
@@ -943,6 +1347,38 @@ MESON_AFTER__AMD64=" \
 		);
 	}
 
+	#[test]
+	fn test_assignment_name() {
+		assert_eq!(assignment_name("_a1=").unwrap(), ("=", "_a1"));
+		assignment_name("1a").unwrap_err();
+		assignment_name("é").unwrap_err();
+		variable_def("1a=b\n", BASH).unwrap_err();
+		assert!(ApmlLst::parse("1A=x\n").is_err());
+	}
+
+	#[test]
+	fn test_equals_in_values() {
+		let src = "A=B=C\nB+=x+=y\nC=(-DFOO=bar x=y =z \"-DX=1\")\n\
+			D=a==b\nE===\nF=\"x\"=$A\nG=${H:-x=y}\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		assert_eq!(
+			lst.0
+				.iter()
+				.filter(|token| matches!(token, Token::Variable(_)))
+				.count(),
+			7
+		);
+		crate::assert_evals_to!(src, {
+			"A" => "B=C",
+			"B" => "x+=y",
+			"C" => ["-DFOO=bar", "x=y", "=z", "-DX=1"],
+			"D" => "a==b",
+			"E" => "==",
+			"F" => "x=B=C",
+			"G" => "x=y",
+		});
+	}
+
 	#[test]
 	fn test_variable_op() {
 		assert_eq!(
@@ -957,6 +1393,73 @@ MESON_AFTER__AMD64=" \
 		variable_name("").unwrap_err();
 	}
 
+	#[test]
+	fn test_apml_word() {
+		assert_eq!(
+			apml_word("a${b}'c d' e", BASH).unwrap(),
+			(
+				" e",
+				Text(vec![
+					TextUnit::Unquoted(vec![
+						Word::Literal(vec![LiteralPart::String(
+							Cow::Borrowed("a")
+						)]),
+						Word::BracedVariable(BracedExpansion {
+							name: Cow::Borrowed("b"),
+							modifier: None
+						}),
+					]),
+					TextUnit::SingleQuote(Cow::Borrowed("c d")),
+				])
+			)
+		);
+		assert_eq!(apml_word("", BASH).unwrap(), ("", Text(vec![])));
+		assert_eq!(apml_word("a\nb", BASH).unwrap().0, "\nb");
+		assert!(apml_word("A=b", BASH).is_err());
+		assert!(apml_word("A+=b", BASH).is_err());
+		assert!(apml_word("=b", BASH).is_ok());
+	}
+
+	#[test]
+	fn test_expansion_name() {
+		assert_eq!(expansion_name("*a").unwrap(), ("a", "*"));
+		assert_eq!(expansion_name("@}").unwrap(), ("}", "@"));
+		assert_eq!(expansion_name("1a").unwrap(), ("", "1a"));
+		expansion_name("!").unwrap_err();
+		variable_name("*").unwrap_err();
+		assert_eq!(unbraced_name("10").unwrap(), ("0", "1"));
+		assert_eq!(unbraced_name("1a").unwrap(), ("a", "1"));
+		assert_eq!(unbraced_name("a1").unwrap(), ("", "a1"));
+		assert_eq!(
+			word("$10", BASH, &|_| true, &anychar).unwrap(),
+			("0", Word::UnbracedVariable(Cow::Borrowed("1")))
+		);
+		assert_eq!(
+			word("${10}", BASH, &|_| true, &anychar).unwrap(),
+			(
+				"",
+				Word::BracedVariable(BracedExpansion {
+					name: Cow::Borrowed("10"),
+					modifier: None,
+				})
+			)
+		);
+		assert_eq!(
+			word("$*a", BASH, &|_| true, &anychar).unwrap(),
+			("a", Word::UnbracedVariable(Cow::Borrowed("*")))
+		);
+		assert_eq!(
+			word("${*}", BASH, &|_| true, &anychar).unwrap(),
+			(
+				"",
+				Word::BracedVariable(BracedExpansion {
+					name: Cow::Borrowed("*"),
+					modifier: None,
+				})
+			)
+		);
+	}
+
 	#[test]
 	fn test_variable_name() {
 		assert_eq!(variable_name("123a").unwrap(), ("", "123a"));
@@ -968,13 +1471,13 @@ MESON_AFTER__AMD64=" \
 	#[test]
 	fn test_variable_value() {
 		assert_eq!(
-			variable_value("\n").unwrap(),
+			variable_value("\n", BASH).unwrap(),
 			("\n", VariableValue::String(Arc::new(Text(vec![]))))
 		);
 		assert_eq!(
-			variable_value("123\\n\\\na!!@$1#").unwrap(),
+			variable_value("123\\n\\\na!!@$1 #", BASH).unwrap(),
 			(
-				"#",
+				" #",
 				VariableValue::String(Arc::new(Text(vec![
 					TextUnit::Unquoted(vec![
 						Word::Literal(vec![
@@ -989,7 +1492,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			variable_value("\"${#a} b\\ #l \\\nc\"\n").unwrap(),
+			variable_value("\"${#a} b\\ #l \\\nc\"\n", BASH).unwrap(),
 			(
 				"\n",
 				VariableValue::String(Arc::new(Text(vec![
@@ -1010,7 +1513,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			variable_value("(a b)\n").unwrap(),
+			variable_value("(a b)\n", BASH).unwrap(),
 			(
 				"\n",
 				VariableValue::Array(vec![
@@ -1029,7 +1532,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			variable_value("(a \"${#a} b\\ \\\\#l \\\nc\"\n)\n").unwrap(),
+			variable_value("(a \"${#a} b\\ \\\\#l \\\nc\"\n)\n", BASH).unwrap(),
 			(
 				"\n",
 				VariableValue::Array(vec![
@@ -1063,15 +1566,28 @@ MESON_AFTER__AMD64=" \
 
 	#[test]
 	fn test_array_token() {
-		assert_eq!(array_token(" a").unwrap(), ("a", ArrayToken::Spacy(' ')));
-		assert_eq!(array_token("\ta").unwrap(), ("a", ArrayToken::Spacy('\t')));
-		assert_eq!(array_token("\na").unwrap(), ("a", ArrayToken::Newline));
 		assert_eq!(
-			array_token("#asdf\na").unwrap(),
+			array_token(" a", BASH).unwrap(),
+			("a", ArrayToken::Spacy(' '))
+		);
+		assert_eq!(
+			array_token("\ta", BASH).unwrap(),
+			("a", ArrayToken::Spacy('\t'))
+		);
+		assert_eq!(
+			array_token("\na", BASH).unwrap(),
+			("a", ArrayToken::Newline)
+		);
+		assert_eq!(
+			array_token("\\\na", BASH).unwrap(),
+			("a", ArrayToken::LineContinuation)
+		);
+		assert_eq!(
+			array_token("#asdf\na", BASH).unwrap(),
 			("\na", ArrayToken::Comment(Cow::Borrowed("asdf")))
 		);
 		assert_eq!(
-			array_token("asdf ").unwrap(),
+			array_token("asdf ", BASH).unwrap(),
 			(
 				" ",
 				ArrayToken::Element(Arc::new(Text(vec![TextUnit::Unquoted(
@@ -1082,7 +1598,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			array_token("'asdf' ").unwrap(),
+			array_token("'asdf' ", BASH).unwrap(),
 			(
 				" ",
 				ArrayToken::Element(Arc::new(Text(vec![
@@ -1094,11 +1610,15 @@ MESON_AFTER__AMD64=" \
 
 	#[test]
 	fn test_text() {
-		text("", &|_| true).unwrap_err();
-		assert_eq!(text_or_null("", &|_| true).unwrap(), ("", Text(vec![])));
+		text("", BASH, &|_| true).unwrap_err();
 		assert_eq!(
-			text("asd\\f\\\n134$a'test'\"a$a${a}  \" a", &|ch| ch != ' '
-				&& ch != '#')
+			text_or_null("", BASH, &|_| true).unwrap(),
+			("", Text(vec![]))
+		);
+		assert_eq!(
+			text("asd\\f\\\n134$a'test'\"a$a${a}  \" a", BASH, &|ch| {
+				ch != ' ' && ch != '#'
+			})
 			.unwrap(),
 			(
 				" a",
@@ -1130,7 +1650,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			text("asd\\f\n134$a'test'\"a$a${a}  \" a", &|ch| ch != ' ')
+			text("asd\\f\n134$a'test'\"a$a${a}  \" a", BASH, &|ch| ch != ' ')
 				.unwrap(),
 			(
 				"\n134$a'test'\"a$a${a}  \" a",
@@ -1145,7 +1665,7 @@ MESON_AFTER__AMD64=" \
 	#[test]
 	fn test_text_unit() {
 		assert_eq!(
-			text_unit("asdf134 a", &|ch| ch != ' ').unwrap(),
+			text_unit("asdf134 a", BASH, &|ch| ch != ' ').unwrap(),
 			(
 				" a",
 				TextUnit::Unquoted(vec![Word::Literal(vec![
@@ -1154,11 +1674,29 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			text_unit("'123 a'", &|ch| ch != ' ').unwrap(),
+			text_unit("'123 a'", BASH, &|ch| ch != ' ').unwrap(),
 			("", TextUnit::SingleQuote(Cow::Borrowed("123 a")))
 		);
 		assert_eq!(
-			text_unit("1$a${#b}' a$a", &|ch| ch != ' ').unwrap(),
+			text_unit(r"$'\x41 \'b'c", BASH, &|ch| ch != ' ').unwrap(),
+			("c", TextUnit::AnsiCQuote(Cow::Borrowed(r"\x41 \'b")))
+		);
+		assert!(text_unit("$'a", BASH, &|ch| ch != ' ').is_err());
+		assert_eq!(
+			text_unit(r#"$"a \"$b"c"#, BASH, &|ch| ch != ' ').unwrap(),
+			(
+				"c",
+				TextUnit::LocaleQuote(vec![
+					Word::Literal(vec![
+						LiteralPart::String(Cow::Borrowed("a ")),
+						LiteralPart::Escaped('"'),
+					]),
+					Word::UnbracedVariable(Cow::Borrowed("b")),
+				])
+			)
+		);
+		assert_eq!(
+			text_unit("1$a${#b}' a$a", BASH, &|ch| ch != ' ').unwrap(),
 			(
 				"' a$a",
 				TextUnit::Unquoted(vec![
@@ -1174,7 +1712,8 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			text_unit("\"1\\\na$a${#b}安同'\" a", &|ch| ch != ' ').unwrap(),
+			text_unit("\"1\\\na$a${#b}安同'\" a", BASH, &|ch| ch != ' ')
+				.unwrap(),
 			(
 				" a",
 				TextUnit::DoubleQuote(vec![
@@ -1194,13 +1733,13 @@ MESON_AFTER__AMD64=" \
 				])
 			)
 		);
-		text_unit("", &|ch| ch != ' ').unwrap_err();
+		text_unit("", BASH, &|ch| ch != ' ').unwrap_err();
 	}
 
 	#[test]
 	fn test_word() {
 		assert_eq!(
-			word("asdf134 a", &|ch| ch != ' ', &anychar).unwrap(),
+			word("asdf134 a", BASH, &|ch| ch != ' ', &anychar).unwrap(),
 			(
 				" a",
 				Word::Literal(vec![LiteralPart::String(Cow::Borrowed(
@@ -1209,7 +1748,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			word("asdf134 a", &|_| true, &anychar).unwrap(),
+			word("asdf134 a", BASH, &|_| true, &anychar).unwrap(),
 			(
 				"",
 				Word::Literal(vec![LiteralPart::String(Cow::Borrowed(
@@ -1218,7 +1757,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			word("asdf\\134\\\n a", &|_| true, &anychar).unwrap(),
+			word("asdf\\134\\\n a", BASH, &|_| true, &anychar).unwrap(),
 			(
 				"",
 				Word::Literal(vec![
@@ -1231,7 +1770,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			word("asdf\\1\\34\\\n a", &|_| true, &one_of("3")).unwrap(),
+			word("asdf\\1\\34\\\n a", BASH, &|_| true, &one_of("3")).unwrap(),
 			(
 				"",
 				Word::Literal(vec![
@@ -1245,11 +1784,11 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			word("$123 a", &|ch| ch != ' ', &anychar).unwrap(),
-			(" a", Word::UnbracedVariable(Cow::Borrowed("123")))
+			word("$123 a", BASH, &|ch| ch != ' ', &anychar).unwrap(),
+			("23 a", Word::UnbracedVariable(Cow::Borrowed("1")))
 		);
 		assert_eq!(
-			word("${abc} a", &|ch| ch != ' ', &anychar).unwrap(),
+			word("${abc} a", BASH, &|ch| ch != ' ', &anychar).unwrap(),
 			(
 				" a",
 				Word::BracedVariable(BracedExpansion {
@@ -1259,7 +1798,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			word("${#abc} a", &|ch| ch != ' ', &anychar).unwrap(),
+			word("${#abc} a", BASH, &|ch| ch != ' ', &anychar).unwrap(),
 			(
 				" a",
 				Word::BracedVariable(BracedExpansion {
@@ -1268,10 +1807,10 @@ MESON_AFTER__AMD64=" \
 				})
 			)
 		);
-		word("${#abc:1} a", &|ch| ch != ' ', &anychar).unwrap_err();
-		word("", &|ch| ch != ' ', &anychar).unwrap_err();
+		word("${#abc:1} a", BASH, &|ch| ch != ' ', &anychar).unwrap_err();
+		word("", BASH, &|ch| ch != ' ', &anychar).unwrap_err();
 		assert_eq!(
-			word("${abc:1:2} a", &|ch| ch != ' ', &anychar).unwrap(),
+			word("${abc:1:2} a", BASH, &|ch| ch != ' ', &anychar).unwrap(),
 			(
 				" a",
 				Word::BracedVariable(BracedExpansion {
@@ -1284,7 +1823,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			word("${abc#test?} a", &|ch| ch != ' ', &anychar).unwrap(),
+			word("${abc#test?} a", BASH, &|ch| ch != ' ', &anychar).unwrap(),
 			(
 				" a",
 				Word::BracedVariable(BracedExpansion {
@@ -1299,7 +1838,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			word("$(123 ) a", &|ch| ch != ' ', &anychar).unwrap(),
+			word("$(123 ) a", BASH, &|ch| ch != ' ', &anychar).unwrap(),
 			(
 				" a",
 				Word::Subcommand(vec![
@@ -1349,14 +1888,14 @@ MESON_AFTER__AMD64=" \
 	#[test]
 	fn test_braced_expansion() {
 		assert_eq!(
-			braced_expansion("asdf134").unwrap(),
+			braced_expansion("asdf134", BASH).unwrap(),
 			("", BracedExpansion {
 				name: Cow::Borrowed("asdf134"),
 				modifier: None
 			})
 		);
 		assert_eq!(
-			braced_expansion("asdf:10").unwrap(),
+			braced_expansion("asdf:10", BASH).unwrap(),
 			("", BracedExpansion {
 				name: Cow::Borrowed("asdf"),
 				modifier: Some(ExpansionModifier::Substring {
@@ -1366,7 +1905,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			braced_expansion("#1").unwrap(),
+			braced_expansion("#1", BASH).unwrap(),
 			("", BracedExpansion {
 				name: Cow::Borrowed("1"),
 				modifier: Some(ExpansionModifier::Length)
@@ -1377,30 +1916,30 @@ MESON_AFTER__AMD64=" \
 	#[test]
 	fn test_expansion_modifier() {
 		assert_eq!(
-			expansion_modifier(":10").unwrap(),
+			expansion_modifier(":10", BASH).unwrap(),
 			("", ExpansionModifier::Substring {
 				offset: Cow::Borrowed("10"),
 				length: None
 			})
 		);
 		assert_eq!(
-			expansion_modifier(":10:1").unwrap(),
+			expansion_modifier(":10:1", BASH).unwrap(),
 			("", ExpansionModifier::Substring {
 				offset: Cow::Borrowed("10"),
 				length: Some(Cow::Borrowed("1"))
 			})
 		);
 		assert_eq!(
-			expansion_modifier(": -10:-1").unwrap(),
+			expansion_modifier(": -10:-1", BASH).unwrap(),
 			("", ExpansionModifier::Substring {
 				offset: Cow::Borrowed(" -10"),
 				length: Some(Cow::Borrowed("-1"))
 			})
 		);
-		expansion_modifier(":").unwrap_err();
-		expansion_modifier("1").unwrap_err();
+		expansion_modifier(":", BASH).unwrap_err();
+		expansion_modifier("1", BASH).unwrap_err();
 		assert_eq!(
-			expansion_modifier("#a*").unwrap(),
+			expansion_modifier("#a*", BASH).unwrap(),
 			(
 				"",
 				ExpansionModifier::StripShortestPrefix(Arc::new(BashPattern(
@@ -1412,7 +1951,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier("##a*").unwrap(),
+			expansion_modifier("##a*", BASH).unwrap(),
 			(
 				"",
 				ExpansionModifier::StripLongestPrefix(Arc::new(BashPattern(
@@ -1424,7 +1963,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier("%%a*").unwrap(),
+			expansion_modifier("%%a*", BASH).unwrap(),
 			(
 				"",
 				ExpansionModifier::StripLongestSuffix(Arc::new(BashPattern(
@@ -1436,7 +1975,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier("%a*").unwrap(),
+			expansion_modifier("%a*", BASH).unwrap(),
 			(
 				"",
 				ExpansionModifier::StripShortestSuffix(Arc::new(BashPattern(
@@ -1448,7 +1987,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier("/a*/$b}").unwrap(),
+			expansion_modifier("/a*/$b}", BASH).unwrap(),
 			("}", ExpansionModifier::ReplaceOnce {
 				pattern: Arc::new(BashPattern(vec![
 					GlobPart::String(Cow::Borrowed("a")),
@@ -1460,7 +1999,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			expansion_modifier("/a*}").unwrap(),
+			expansion_modifier("/a*}", BASH).unwrap(),
 			("}", ExpansionModifier::ReplaceOnce {
 				pattern: Arc::new(BashPattern(vec![
 					GlobPart::String(Cow::Borrowed("a")),
@@ -1470,7 +2009,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			expansion_modifier("//a*/$b}").unwrap(),
+			expansion_modifier("//a*/$b}", BASH).unwrap(),
 			("}", ExpansionModifier::ReplaceAll {
 				pattern: Arc::new(BashPattern(vec![
 					GlobPart::String(Cow::Borrowed("a")),
@@ -1482,7 +2021,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			expansion_modifier("//a*}").unwrap(),
+			expansion_modifier("//a*}", BASH).unwrap(),
 			("}", ExpansionModifier::ReplaceAll {
 				pattern: Arc::new(BashPattern(vec![
 					GlobPart::String(Cow::Borrowed("a")),
@@ -1492,7 +2031,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			expansion_modifier("/#a*/$b}").unwrap(),
+			expansion_modifier("/#a*/$b}", BASH).unwrap(),
 			("}", ExpansionModifier::ReplacePrefix {
 				pattern: Arc::new(BashPattern(vec![
 					GlobPart::String(Cow::Borrowed("a")),
@@ -1504,7 +2043,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			expansion_modifier("/#a*}").unwrap(),
+			expansion_modifier("/#a*}", BASH).unwrap(),
 			("}", ExpansionModifier::ReplacePrefix {
 				pattern: Arc::new(BashPattern(vec![
 					GlobPart::String(Cow::Borrowed("a")),
@@ -1514,7 +2053,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			expansion_modifier("/%a*/$b}").unwrap(),
+			expansion_modifier("/%a*/$b}", BASH).unwrap(),
 			("}", ExpansionModifier::ReplaceSuffix {
 				pattern: Arc::new(BashPattern(vec![
 					GlobPart::String(Cow::Borrowed("a")),
@@ -1526,7 +2065,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			expansion_modifier("/%a*}").unwrap(),
+			expansion_modifier("/%a*}", BASH).unwrap(),
 			("}", ExpansionModifier::ReplaceSuffix {
 				pattern: Arc::new(BashPattern(vec![
 					GlobPart::String(Cow::Borrowed("a")),
@@ -1536,7 +2075,7 @@ MESON_AFTER__AMD64=" \
 			})
 		);
 		assert_eq!(
-			expansion_modifier("^a*}").unwrap(),
+			expansion_modifier("^a*}", BASH).unwrap(),
 			(
 				"}",
 				ExpansionModifier::UpperOnce(Arc::new(BashPattern(vec![
@@ -1546,7 +2085,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier("^^a*}").unwrap(),
+			expansion_modifier("^^a*}", BASH).unwrap(),
 			(
 				"}",
 				ExpansionModifier::UpperAll(Arc::new(BashPattern(vec![
@@ -1556,7 +2095,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier(",a*}").unwrap(),
+			expansion_modifier(",a*}", BASH).unwrap(),
 			(
 				"}",
 				ExpansionModifier::LowerOnce(Arc::new(BashPattern(vec![
@@ -1566,7 +2105,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier(",,a*}").unwrap(),
+			expansion_modifier(",,a*}", BASH).unwrap(),
 			(
 				"}",
 				ExpansionModifier::LowerAll(Arc::new(BashPattern(vec![
@@ -1576,7 +2115,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier("^a*}").unwrap(),
+			expansion_modifier("^a*}", BASH).unwrap(),
 			(
 				"}",
 				ExpansionModifier::UpperOnce(Arc::new(BashPattern(vec![
@@ -1586,7 +2125,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier(":?a$a}").unwrap(),
+			expansion_modifier(":?a$a}", BASH).unwrap(),
 			(
 				"}",
 				ExpansionModifier::ErrorOnUnset(Arc::new(Text(vec![
@@ -1600,7 +2139,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier(":-a${a}}").unwrap(),
+			expansion_modifier(":-a${a}}", BASH).unwrap(),
 			(
 				"}",
 				ExpansionModifier::WhenUnset(Arc::new(Text(vec![
@@ -1617,7 +2156,7 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier(":+a${#a}}").unwrap(),
+			expansion_modifier(":+a${#a}}", BASH).unwrap(),
 			(
 				"}",
 				ExpansionModifier::WhenSet(Arc::new(Text(vec![
@@ -1634,11 +2173,11 @@ MESON_AFTER__AMD64=" \
 			)
 		);
 		assert_eq!(
-			expansion_modifier("[@]}").unwrap(),
+			expansion_modifier("[@]}", BASH).unwrap(),
 			("}", ExpansionModifier::ArrayElements)
 		);
 		assert_eq!(
-			expansion_modifier("[*]}").unwrap(),
+			expansion_modifier("[*]}", BASH).unwrap(),
 			("}", ExpansionModifier::SingleWordElements)
 		);
 	}
@@ -1,9 +1,7 @@
 //! Bash pattern matching used in APML.
 
-use std::{
-	borrow::Cow,
-	fmt::{Display, Write},
-};
+use alloc::borrow::Cow;
+use core::fmt::{Display, Write};
 
 use nom::{
 	IResult,
@@ -15,13 +13,17 @@ use nom::{
 	sequence::{delimited, preceded, terminated},
 };
 use regex::{Regex, RegexBuilder};
+use thiserror::Error;
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 
 /// A pattern, consisting of one or more [`GlobPart`]s.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BashPattern<'a>(pub Vec<GlobPart<'a>>);
 
 impl Display for BashPattern<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		for part in &self.0 {
 			Display::fmt(part, f)?;
 		}
@@ -55,7 +57,7 @@ pub enum GlobPart<'a> {
 }
 
 impl Display for GlobPart<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		match self {
 			GlobPart::String(text) => f.write_str(text),
 			GlobPart::Escaped(ch) => {
@@ -86,7 +88,7 @@ impl Display for GlobPart<'_> {
 pub struct PatternList<'a>(pub Vec<BashPattern<'a>>);
 
 impl Display for PatternList<'_> {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		for (idx, pattern) in (1..).zip(&self.0) {
 			if idx != 1 {
 				f.write_char('|')?;
@@ -97,6 +99,48 @@ impl Display for PatternList<'_> {
 	}
 }
 
+impl BashPattern<'_> {
+	/// Converts the pattern into one owning all strings.
+	pub fn into_owned(self) -> BashPattern<'static> {
+		BashPattern(self.0.into_iter().map(GlobPart::into_owned).collect())
+	}
+}
+
+impl GlobPart<'_> {
+	/// Converts the part into one owning all strings.
+	pub fn into_owned(self) -> GlobPart<'static> {
+		match self {
+			GlobPart::String(text) => {
+				GlobPart::String(text.into_owned().into())
+			}
+			GlobPart::Escaped(ch) => GlobPart::Escaped(ch),
+			GlobPart::AnyString => GlobPart::AnyString,
+			GlobPart::AnyChar => GlobPart::AnyChar,
+			GlobPart::Range(range) => {
+				GlobPart::Range(range.into_owned().into())
+			}
+			GlobPart::ZeroOrOneOf(list) => {
+				GlobPart::ZeroOrOneOf(list.into_owned())
+			}
+			GlobPart::ZeroOrMoreOf(list) => {
+				GlobPart::ZeroOrMoreOf(list.into_owned())
+			}
+			GlobPart::OneOrMoreOf(list) => {
+				GlobPart::OneOrMoreOf(list.into_owned())
+			}
+			GlobPart::OneOf(list) => GlobPart::OneOf(list.into_owned()),
+			GlobPart::Not(list) => GlobPart::Not(list.into_owned()),
+		}
+	}
+}
+
+impl PatternList<'_> {
+	/// Converts the list into one owning all strings.
+	pub fn into_owned(self) -> PatternList<'static> {
+		PatternList(self.0.into_iter().map(BashPattern::into_owned).collect())
+	}
+}
+
 impl BashPattern<'_> {
 	/// Converts a pattern into regex string.
 	pub fn build_regex(&self, result: &mut String, greedy: bool) {
@@ -114,7 +158,23 @@ impl BashPattern<'_> {
 					result.push_str(lazy_flag);
 				}
 				GlobPart::AnyChar => result.push_str(".?"),
-				GlobPart::Range(_) => todo!(),
+				GlobPart::Range(range) => {
+					result.push('[');
+					let range = match range.strip_prefix(['!', '^']) {
+						Some(range) => {
+							result.push('^');
+							range
+						}
+						None => range,
+					};
+					for ch in range.chars() {
+						if matches!(ch, '[' | ']' | '\\' | '&' | '~') {
+							result.push('\\');
+						}
+						result.push(ch);
+					}
+					result.push(']');
+				}
 				GlobPart::ZeroOrOneOf(list) => {
 					list.build_regex(result, greedy);
 					result.push('?');
@@ -240,6 +300,185 @@ fn pattern_list(i: &str) -> IResult<&str, PatternList> {
 	)(i)
 }
 
+/// An ordered list of allow and deny patterns for matching names.
+///
+/// A filter is written as comma-separated clauses, for example
+/// `llvm-*,!*-dbg`. Each clause is a bash pattern matched against the
+/// whole name, and clauses prefixed with `!` are deny clauses.
+///
+/// Clauses are evaluated in order and the last matching clause decides.
+/// If no clause matches, the name is accepted only when the filter
+/// has no allow clauses, so that an empty filter accepts everything and
+/// a deny-only filter accepts everything not denied.
+///
+/// Commas and leading exclamation marks can be escaped with a backslash
+/// (`\,` and `\!`). Spaces around clauses are ignored, and so are
+/// empty clauses.
+#[derive(Debug, Clone)]
+pub struct NameFilter {
+	clauses: Vec<NameFilterClause>,
+}
+
+/// A clause in [`NameFilter`].
+#[derive(Debug, Clone)]
+pub struct NameFilterClause {
+	/// Whether names matching the pattern are denied.
+	pub deny: bool,
+	/// The pattern, without the leading `!`.
+	pub pattern: String,
+	regex: Regex,
+}
+
+impl NameFilterClause {
+	/// Returns if a name is matched by the pattern.
+	pub fn is_match(&self, name: &str) -> bool {
+		self.regex.is_match(name)
+	}
+}
+
+impl Display for NameFilterClause {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		if self.deny {
+			f.write_char('!')?;
+		}
+		f.write_str(&self.pattern.replace(',', "\\,"))
+	}
+}
+
+/// The reason of a [`NameFilter`] decision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameFilterReason {
+	/// The last matching clause, with its index.
+	Clause(usize),
+	/// No clause matched and the filter has no allow clauses.
+	NoAllowClause,
+	/// No clause matched while the filter has allow clauses.
+	NotAllowed,
+}
+
+/// Errors produced when parsing [`NameFilter`].
+#[derive(Debug, Error)]
+pub enum NameFilterError {
+	#[error("Invalid pattern in name filter: {0}")]
+	InvalidPattern(String),
+	#[error("Glob-as-regex error: {0}")]
+	RegexError(#[cfg_attr(feature = "std", from)] regex::Error),
+}
+
+/// `regex::Error` implements [`core::error::Error`] only with `std`, which
+/// `#[from]` requires.
+#[cfg(not(feature = "std"))]
+impl From<regex::Error> for NameFilterError {
+	fn from(value: regex::Error) -> Self {
+		Self::RegexError(value)
+	}
+}
+
+impl NameFilter {
+	/// Parses a comma-separated name filter.
+	pub fn parse(filter: &str) -> Result<Self, NameFilterError> {
+		let mut clauses = Vec::new();
+		let mut buffer = String::new();
+		let mut chars = filter.chars();
+		loop {
+			match chars.next() {
+				Some('\\') => match chars.next() {
+					Some(',') => buffer.push(','),
+					Some(ch) => {
+						buffer.push('\\');
+						buffer.push(ch);
+					}
+					None => buffer.push('\\'),
+				},
+				Some(',') => {
+					Self::push_clause(&mut clauses, &buffer)?;
+					buffer.clear();
+				}
+				Some(ch) => buffer.push(ch),
+				None => {
+					Self::push_clause(&mut clauses, &buffer)?;
+					break;
+				}
+			}
+		}
+		Ok(Self { clauses })
+	}
+
+	fn push_clause(
+		clauses: &mut Vec<NameFilterClause>,
+		clause: &str,
+	) -> Result<(), NameFilterError> {
+		let clause = clause.trim();
+		if clause.is_empty() {
+			return Ok(());
+		}
+		let (deny, pattern) = match clause.strip_prefix('!') {
+			Some(pattern) => (true, pattern),
+			None => (false, clause),
+		};
+		let regex = match bash_pattern(pattern, "") {
+			Ok(("", parsed)) => parsed.to_regex("^(?:", ")$", true)?,
+			_ => {
+				return Err(NameFilterError::InvalidPattern(
+					pattern.to_string(),
+				));
+			}
+		};
+		clauses.push(NameFilterClause {
+			deny,
+			pattern: pattern.to_string(),
+			regex,
+		});
+		Ok(())
+	}
+
+	/// Returns the clauses of the filter.
+	pub fn clauses(&self) -> &[NameFilterClause] {
+		&self.clauses
+	}
+
+	/// Returns if the filter has no clauses.
+	pub fn is_empty(&self) -> bool {
+		self.clauses.is_empty()
+	}
+
+	/// Returns if a name is accepted by the filter.
+	pub fn matches(&self, name: &str) -> bool {
+		self.explain(name).0
+	}
+
+	/// Returns if a name is accepted by the filter and the reason.
+	pub fn explain(&self, name: &str) -> (bool, NameFilterReason) {
+		match self
+			.clauses
+			.iter()
+			.enumerate()
+			.rev()
+			.find(|(_, clause)| clause.is_match(name))
+		{
+			Some((index, clause)) => {
+				(!clause.deny, NameFilterReason::Clause(index))
+			}
+			None if self.clauses.iter().all(|clause| clause.deny) => {
+				(true, NameFilterReason::NoAllowClause)
+			}
+			None => (false, NameFilterReason::NotAllowed),
+		}
+	}
+}
+
+impl Display for NameFilter {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		for (idx, clause) in self.clauses.iter().enumerate() {
+			if idx != 0 {
+				f.write_char(',')?;
+			}
+			Display::fmt(clause, f)?;
+		}
+		Ok(())
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -327,4 +566,66 @@ mod test {
 			.build_regex(&mut result, false);
 		assert_eq!(result, "(abc|LA.?)");
 	}
+
+	#[test]
+	fn test_range() {
+		let pattern = bash_pattern("[a-c]x[!0-9]", "").unwrap().1;
+		let regex = pattern.to_regex("^", "$", true).unwrap();
+		assert!(regex.is_match("bxy"));
+		assert!(!regex.is_match("dxy"));
+		assert!(!regex.is_match("ax1"));
+	}
+
+	#[test]
+	fn test_name_filter() {
+		let filter = NameFilter::parse("llvm-*,!*-dbg").unwrap();
+		assert!(filter.matches("llvm-runtime"));
+		assert!(!filter.matches("llvm-runtime-dbg"));
+		assert!(!filter.matches("clang"));
+		assert_eq!(
+			filter.explain("llvm-debug"),
+			(true, NameFilterReason::Clause(0))
+		);
+		assert_eq!(
+			filter.explain("llvm-a-dbg"),
+			(false, NameFilterReason::Clause(1))
+		);
+		assert_eq!(
+			filter.explain("gcc"),
+			(false, NameFilterReason::NotAllowed)
+		);
+		assert_eq!(filter.to_string(), "llvm-*,!*-dbg");
+
+		// later clauses take precedence
+		let filter = NameFilter::parse("!*-dbg, llvm-*").unwrap();
+		assert!(filter.matches("llvm-runtime-dbg"));
+		assert!(!filter.matches("gcc-dbg"));
+
+		let filter = NameFilter::parse("").unwrap();
+		assert!(filter.is_empty());
+		assert_eq!(
+			filter.explain("a"),
+			(true, NameFilterReason::NoAllowClause)
+		);
+		let filter = NameFilter::parse(" , !*-dbg,").unwrap();
+		assert_eq!(filter.clauses().len(), 1);
+		assert!(filter.matches("gcc"));
+		assert!(!filter.matches("gcc-dbg"));
+
+		let filter = NameFilter::parse("a\\,b,\\!c,d\\*").unwrap();
+		assert_eq!(filter.clauses().len(), 3);
+		assert!(filter.matches("a,b"));
+		assert!(filter.matches("!c"));
+		assert!(filter.matches("d*"));
+		assert!(!filter.matches("a"));
+		assert!(!filter.matches("c"));
+		assert!(!filter.matches("dd"));
+		assert_eq!(filter.to_string(), "a\\,b,\\!c,d\\*");
+
+		assert!(NameFilter::parse("lib[a-z]*").unwrap().matches("libfoo"));
+		assert!(matches!(
+			NameFilter::parse("a,!"),
+			Err(NameFilterError::InvalidPattern(_))
+		));
+	}
 }
@@ -0,0 +1,345 @@
+//! All-in-one analysis of APML sources.
+//!
+//! [`analyze`] parses, emits and evaluates a source at once, returning
+//! all the artifacts as an [`Analysis`], optionally along with lint
+//! results. This is the recommended entry point for tooling, such as
+//! editors, which can keep the analysis up to date with
+//! [`Analysis::reanalyze_after_edit`].
+
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+use super::{
+	ApmlContext, ApmlError,
+	ast::{ApmlAst, AstNode},
+	eval::{EvalOptions, EvalWarning},
+	lint::{
+		self, BraceIssue, RelationIssue, ShapeIssue, SplittingIssue, ValueIssue,
+	},
+	lst::{ApmlLst, Token},
+	schema::FieldSchema,
+};
+
+/// Options of [`analyze`].
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisOptions {
+	/// Schema to lint against, or [`None`] to skip lints.
+	pub lint: Option<FieldSchema>,
+}
+
+/// Results of lints, see [`AnalysisOptions::lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lints {
+	/// Results of [`lint::check_values`].
+	pub values: Vec<ValueIssue>,
+	/// Results of [`lint::check_shapes`].
+	pub shapes: Vec<ShapeIssue>,
+	/// Results of [`lint::check_relations`].
+	pub relations: Vec<RelationIssue>,
+	/// Results of [`lint::check_splitting`], with an empty seed.
+	pub splitting: Vec<SplittingIssue>,
+	/// Results of [`lint::check_braces`].
+	pub braces: Vec<BraceIssue>,
+}
+
+/// Artifacts of analyzing a source, produced by [`analyze`].
+#[derive(Debug, Clone)]
+pub struct Analysis {
+	source: String,
+	lst: ApmlLst<'static>,
+	ast: ApmlAst<'static>,
+	context: ApmlContext,
+	warnings: Vec<EvalWarning>,
+	lints: Option<Lints>,
+	options: AnalysisOptions,
+}
+
+/// Parses, emits and evaluates a source.
+///
+/// The source is parsed once, and the AST emitted from the LST is both
+/// kept and evaluated. Warnings are collected as with
+/// [`EvalOptions::on_warning`].
+pub fn analyze(
+	src: &str,
+	options: &AnalysisOptions,
+) -> Result<Analysis, ApmlError> {
+	let lst = ApmlLst::parse(src)?.into_owned();
+	let ast = ApmlAst::emit_from(&lst)?;
+	let (context, warnings) = evaluate(&lst, &ast)?;
+	let lints = run_lints(&lst, options)?;
+	Ok(Analysis {
+		source: src.to_string(),
+		lst,
+		ast,
+		context,
+		warnings,
+		lints,
+		options: options.clone(),
+	})
+}
+
+impl Analysis {
+	/// Returns the analyzed source.
+	pub fn source(&self) -> &str {
+		&self.source
+	}
+
+	/// Returns the LST of the source.
+	pub fn lst(&self) -> &ApmlLst<'static> {
+		&self.lst
+	}
+
+	/// Returns the AST emitted from the LST.
+	pub fn ast(&self) -> &ApmlAst<'static> {
+		&self.ast
+	}
+
+	/// Returns the evaluated context.
+	pub fn context(&self) -> &ApmlContext {
+		&self.context
+	}
+
+	/// Returns warnings produced during evaluation, in the order they
+	/// are produced.
+	pub fn warnings(&self) -> &[EvalWarning] {
+		&self.warnings
+	}
+
+	/// Returns results of lints, if they are enabled.
+	pub fn lints(&self) -> Option<&Lints> {
+		self.lints.as_ref()
+	}
+
+	/// Updates the analysis after replacing a byte range of the source
+	/// with a text.
+	///
+	/// Only the lines touched by the edit are parsed again and emitted
+	/// into AST, and the rest of the LST and AST is reused. If the lines
+	/// cannot be parsed on their own, for example as a quote is opened,
+	/// the whole source is parsed again. Evaluation is skipped when the
+	/// edit leaves the AST unchanged and no `set` statement or warning
+	/// is in the touched lines, such as when editing comments, and
+	/// warnings are moved along with the source instead. Lints are
+	/// always run again.
+	///
+	/// The result is the same as analyzing the edited source from
+	/// scratch. On errors, the analysis is left unchanged.
+	///
+	/// # Panics
+	///
+	/// Panics if the range is out of bounds or does not lie on character
+	/// boundaries, as with [`String::replace_range`].
+	pub fn reanalyze_after_edit(
+		&mut self,
+		range: Range<usize>,
+		text: &str,
+	) -> Result<(), ApmlError> {
+		let mut source = self.source.clone();
+		source.replace_range(range.clone(), text);
+		let tokens = &self.lst.0;
+		let spans = self
+			.lst
+			.token_spans()
+			.map(|(span, _)| span)
+			.collect::<Vec<_>>();
+
+		// the window starts after the newline before the edit and ends
+		// with the newline after it, so the parser starts and stops at
+		// the top level
+		let touched = spans
+			.iter()
+			.position(|span| span.end > range.start)
+			.unwrap_or(tokens.len());
+		let first = tokens[..touched]
+			.iter()
+			.rposition(|token| matches!(token, Token::Newline))
+			.map_or(0, |index| index + 1);
+		let last = (touched..tokens.len()).find(|&index| {
+			matches!(tokens[index], Token::Newline)
+				&& spans[index].start >= range.end
+		});
+		let end = last.map_or(tokens.len(), |index| index + 1);
+		let old_start = spans.get(first).map_or(self.source.len(), |s| s.start);
+		let old_end = last.map_or(self.source.len(), |index| spans[index].end);
+		let new_end = old_end + text.len() - range.len();
+
+		let window =
+			ApmlLst::parse(&source[old_start..new_end])
+				.ok()
+				.filter(|window| {
+					last.is_none()
+						|| matches!(window.0.last(), Some(Token::Newline))
+				});
+		let (lst, ast, touches_set) = if let Some(window) = window {
+			let window = window.into_owned();
+			let window_ast = ApmlAst::emit_from(&window)?;
+			let is_def = |token: &&Token| matches!(token, Token::Variable(_));
+			let before = tokens[..first].iter().filter(is_def).count();
+			let after = tokens[end..].iter().filter(is_def).count();
+			let is_set = |token: &Token| matches!(token, Token::Set(_));
+			let touches_set = tokens[first..end].iter().any(is_set)
+				|| window.0.iter().any(is_set);
+			let mut ast = self.ast.0[..before].to_vec();
+			ast.extend(window_ast.0);
+			ast.extend_from_slice(&self.ast.0[self.ast.0.len() - after..]);
+			let mut lst = tokens[..first].to_vec();
+			lst.extend(window.0);
+			lst.extend_from_slice(&tokens[end..]);
+			(ApmlLst(lst), ApmlAst(ast), touches_set)
+		} else {
+			let lst = ApmlLst::parse(&source)?.into_owned();
+			let ast = ApmlAst::emit_from(&lst)?;
+			(lst, ast, true)
+		};
+
+		let reusable = !touches_set
+			&& ast == self.ast
+			&& self.warnings.iter().all(|warning| {
+				warning.span.end <= old_start || warning.span.start >= old_end
+			});
+		let (context, warnings) = if reusable {
+			let warnings = self
+				.warnings
+				.iter()
+				.cloned()
+				.map(|mut warning| {
+					if warning.span.start >= old_end {
+						warning.span.start =
+							warning.span.start - old_end + new_end;
+						warning.span.end = warning.span.end - old_end + new_end;
+					}
+					warning
+				})
+				.collect();
+			(self.context.clone(), warnings)
+		} else {
+			evaluate(&lst, &ast)?
+		};
+		let lints = run_lints(&lst, &self.options)?;
+		*self = Self {
+			source,
+			lst,
+			ast,
+			context,
+			warnings,
+			lints,
+			options: self.options.clone(),
+		};
+		Ok(())
+	}
+}
+
+/// Evaluates an emitted AST, collecting warnings.
+fn evaluate(
+	lst: &ApmlLst,
+	ast: &ApmlAst,
+) -> Result<(ApmlContext, Vec<EvalWarning>), ApmlError> {
+	let warnings = Rc::new(RefCell::new(Vec::new()));
+	let mut options = EvalOptions {
+		on_warning: Some(Box::new({
+			let warnings = warnings.clone();
+			move |warning| warnings.borrow_mut().push(warning.clone())
+		})),
+		..Default::default()
+	};
+	let context = ApmlContext::eval_emitted(lst, ast, &mut options)?;
+	drop(options);
+	let warnings = Rc::into_inner(warnings)
+		.expect("callback has been dropped")
+		.into_inner();
+	Ok((context, warnings))
+}
+
+/// Runs lints enabled by options.
+fn run_lints(
+	lst: &ApmlLst,
+	options: &AnalysisOptions,
+) -> Result<Option<Lints>, ApmlError> {
+	let Some(schema) = &options.lint else {
+		return Ok(None);
+	};
+	Ok(Some(Lints {
+		values: lint::check_values(lst, schema)?,
+		shapes: lint::check_shapes(lst, schema),
+		relations: lint::check_relations(lst, schema)?,
+		splitting: lint::check_splitting(lst, &ApmlContext::default()),
+		braces: lint::check_braces(lst),
+	}))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Asserts that an analysis equals analyzing its source from scratch.
+	fn assert_fresh(analysis: &Analysis) {
+		let fresh = analyze(analysis.source(), &analysis.options).unwrap();
+		assert_eq!(analysis.lst(), fresh.lst());
+		assert_eq!(analysis.ast(), fresh.ast());
+		crate::assert_context_eq!(analysis.context(), fresh.context());
+		assert_eq!(analysis.warnings(), fresh.warnings());
+		assert_eq!(analysis.lints(), fresh.lints());
+	}
+
+	#[test]
+	fn test_analyze() {
+		let options = AnalysisOptions {
+			lint: Some(FieldSchema::default()),
+		};
+		let src = "PKGNAME=foo\nPKGDEP=\"a a\"\nX=\"${PKGDEP[@]}\"\n";
+		let analysis = analyze(src, &options).unwrap();
+		assert_eq!(analysis.source(), src);
+		assert_eq!(analysis.lst().to_string(), src);
+		assert_eq!(analysis.ast().0.len(), 3);
+		assert_eq!(analysis.context().read("X"), "a a");
+		assert_eq!(analysis.warnings().len(), 1);
+		assert_eq!(analysis.lints().unwrap().relations.len(), 1);
+		assert!(
+			analyze(src, &AnalysisOptions::default())
+				.unwrap()
+				.lints()
+				.is_none()
+		);
+		assert!(analyze("A=\"", &options).is_err());
+	}
+
+	#[test]
+	fn test_reanalyze_after_edit() {
+		let options = AnalysisOptions {
+			lint: Some(FieldSchema::default()),
+		};
+		let src = "# c\nA=1\nB=(x y)\nC=\"${B[@]}\" # d\nD=$A # \"\n";
+		let mut analysis = analyze(src, &options).unwrap();
+		assert_eq!(analysis.warnings().len(), 1);
+		// replaces the first occurrence of a text, or inserts before it
+		let mut edit = |find: &str, insert: bool, text: &str| {
+			let start = analysis.source().find(find).unwrap();
+			let end = if insert { start } else { start + find.len() };
+			analysis.reanalyze_after_edit(start..end, text).unwrap();
+			assert_fresh(&analysis);
+		};
+		// comments only, so evaluation is skipped
+		edit("# c", false, "# comment");
+		// changes a value
+		edit("A=1", false, "A=2");
+		// comments out a line and restores it
+		edit("\nA=", false, " A=");
+		edit(" A=", false, "\nA=");
+		// opens a quote closed in the next line and removes it again
+		edit("${B", true, "\"");
+		edit("\"${B", false, "${B");
+		// appends at the end and removes everything
+		let len = analysis.source().len();
+		analysis.reanalyze_after_edit(len..len, "F=${B}").unwrap();
+		assert_fresh(&analysis);
+		let len = analysis.source().len();
+		analysis.reanalyze_after_edit(0..len, "").unwrap();
+		assert_fresh(&analysis);
+		assert!(analysis.ast().0.is_empty());
+
+		// errors leave the analysis unchanged
+		let mut analysis = analyze(src, &options).unwrap();
+		assert!(analysis.reanalyze_after_edit(4..4, "\"").is_err());
+		assert_eq!(analysis.source(), src);
+		assert_fresh(&analysis);
+	}
+}
@@ -0,0 +1,410 @@
+//! Parsing of sources being edited.
+//!
+//! Sources in editors are often in the middle of being typed, with
+//! quotes or expansions opened but not closed yet. [`parse_recovering`]
+//! closes such constructs at the end of the lines they begin on, so that
+//! lines around them are still parsed as usual.
+//!
+//! The closing text is synthetic: it does not exist in the source, and
+//! is recorded in [`RecoveredLst::synthetic`]. Spans in the recovered LST
+//! are mapped back to the source with [`RecoveredLst::to_source`].
+
+use super::{
+	lst::{ApmlLst, VariableDefinition},
+	parser::ParseError,
+	span::Span,
+};
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+/// Kind of a synthetic text inserted by [`parse_recovering`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Recovery {
+	/// A `'` closing a single-quoted or ANSI-C quoted string.
+	SingleQuote,
+	/// A `"` closing a double-quoted or locale-quoted string.
+	DoubleQuote,
+	/// A `}` closing a `${` expansion.
+	Expansion,
+	/// A `\` escaping the `$` of a malformed expansion, which is then
+	/// taken literally.
+	EscapedExpansion,
+}
+
+impl Recovery {
+	/// Returns the inserted text.
+	pub fn text(&self) -> &'static str {
+		match self {
+			Recovery::SingleQuote => "'",
+			Recovery::DoubleQuote => "\"",
+			Recovery::Expansion => "}",
+			Recovery::EscapedExpansion => "\\",
+		}
+	}
+}
+
+/// A synthetic text inserted by [`parse_recovering`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Synthetic {
+	/// Offset in the source, before which the text is inserted.
+	pub offset: usize,
+	/// Kind of the text.
+	pub recovery: Recovery,
+}
+
+/// Result of [`parse_recovering`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecoveredLst {
+	/// The LST of the source with synthetic texts inserted.
+	pub lst: ApmlLst<'static>,
+	/// Synthetic texts, sorted by offsets in the source.
+	///
+	/// Texts inserted at the same offset are in the order they appear in
+	/// the recovered source.
+	pub synthetic: Vec<Synthetic>,
+}
+
+impl RecoveredLst {
+	/// Returns if any synthetic text is inserted.
+	pub fn is_recovered(&self) -> bool {
+		!self.synthetic.is_empty()
+	}
+
+	/// Returns the spans of synthetic texts in the recovered source.
+	pub fn synthetic_spans(&self) -> impl Iterator<Item = Span> + '_ {
+		let mut shift = 0;
+		self.synthetic.iter().map(move |synthetic| {
+			let len = synthetic.recovery.text().len();
+			let span = Span::with_len(synthetic.offset + shift, len);
+			shift += len;
+			span
+		})
+	}
+
+	/// Returns if a span in the recovered source overlaps synthetic texts.
+	pub fn is_synthetic(&self, span: Span) -> bool {
+		self.synthetic_spans().any(|synthetic| {
+			synthetic.start < span.end && span.start < synthetic.end
+		})
+	}
+
+	/// Maps an offset in the source to the recovered source.
+	///
+	/// Synthetic texts inserted at the offset are placed after it, so
+	/// that a cursor at the end of a line stays before the closing text.
+	pub fn to_recovered(&self, offset: usize) -> usize {
+		offset
+			+ self
+				.synthetic
+				.iter()
+				.take_while(|synthetic| synthetic.offset < offset)
+				.map(|synthetic| synthetic.recovery.text().len())
+				.sum::<usize>()
+	}
+
+	/// Maps an offset in the recovered source to the source.
+	///
+	/// Offsets in or right after a synthetic text are mapped to the
+	/// offset it is inserted at.
+	pub fn to_source(&self, offset: usize) -> usize {
+		let mut shift = 0;
+		for span in self.synthetic_spans() {
+			if offset <= span.start {
+				break;
+			}
+			shift += offset.min(span.end) - span.start;
+		}
+		offset - shift
+	}
+
+	/// Maps a span in the recovered source to the source.
+	pub fn to_source_span(&self, span: Span) -> Span {
+		Span::new(self.to_source(span.start), self.to_source(span.end))
+	}
+
+	/// Iterates over all variable definitions along with their spans in
+	/// the source.
+	///
+	/// See [`ApmlLst::variable_spans`].
+	pub fn variable_spans(
+		&self,
+	) -> impl Iterator<Item = (Span, &VariableDefinition<'static>)> {
+		self.lst
+			.variable_spans()
+			.map(|(span, def)| (self.to_source_span(span), def))
+	}
+}
+
+/// Parses a source which may be in the middle of being edited.
+///
+/// The source is parsed as [`ApmlLst::parse`] does. If it fails,
+/// constructs beginning in a line and still open at its end are closed
+/// there. The line is the first one followed by a variable definition or
+/// the end of the source, as such lines hardly continue in the next
+/// ones, or else the first one at or after the error:
+///
+/// - `'...` and `$'...` are closed with `'`.
+/// - `"...` and `$"...` are closed with `"`.
+/// - `${...` is closed with `}`.
+///
+/// Otherwise, the `$` of a malformed expansion, such as the `${}` left by
+/// closing a `${` without a name, is escaped with `\`. This repeats till
+/// the source is parsed, and the error is returned if nothing can be
+/// recovered.
+///
+/// Constructs are never closed in lines after the ones they begin in, so
+/// lines after the closed constructs are parsed as usual.
+pub fn parse_recovering(src: &str) -> Result<RecoveredLst, ParseError> {
+	let mut recovered = RecoveredLst {
+		lst: ApmlLst(Vec::new()),
+		synthetic: Vec::new(),
+	};
+	let mut text = src.to_string();
+	loop {
+		let err = match ApmlLst::parse(&text) {
+			Ok(lst) => {
+				recovered.lst = lst.into_owned();
+				return Ok(recovered);
+			}
+			Err(err) => err,
+		};
+		let from = match err {
+			ParseError::UnexpectedSource { pos }
+			| ParseError::MalformedExpansion { pos } => pos - 1,
+			ParseError::SyntaxError(_) => 0,
+		};
+		let lines = open_at_line_ends(&text);
+		let line = lines
+			.iter()
+			.find(|(line_end, _)| starts_definition(&text[*line_end..]))
+			.or_else(|| lines.iter().find(|(line_end, _)| *line_end >= from));
+		let inserted = match (line, &err) {
+			(Some((line_end, closing)), _) => {
+				let offset = recovered.to_source(*line_end);
+				closing
+					.iter()
+					.map(|&recovery| Synthetic { offset, recovery })
+					.collect()
+			}
+			(None, ParseError::MalformedExpansion { pos }) => vec![Synthetic {
+				offset: recovered.to_source(pos - 1),
+				recovery: Recovery::EscapedExpansion,
+			}],
+			(None, _) => return Err(err),
+		};
+		if inserted
+			.iter()
+			.any(|synthetic| recovered.synthetic.contains(synthetic))
+		{
+			return Err(err);
+		}
+		for synthetic in inserted {
+			let index = recovered
+				.synthetic
+				.partition_point(|other| other.offset <= synthetic.offset);
+			recovered.synthetic.insert(index, synthetic);
+		}
+		text = insert_synthetic(src, &recovered.synthetic);
+	}
+}
+
+/// Builds the recovered source.
+fn insert_synthetic(src: &str, synthetic: &[Synthetic]) -> String {
+	let mut text = String::with_capacity(src.len() + synthetic.len());
+	let mut pos = 0;
+	for synthetic in synthetic {
+		text.push_str(&src[pos..synthetic.offset]);
+		text.push_str(synthetic.recovery.text());
+		pos = synthetic.offset;
+	}
+	text.push_str(&src[pos..]);
+	text
+}
+
+/// Returns if the text after a line end is empty or starts with a
+/// variable definition, which hardly continues a multi-line construct.
+fn starts_definition(text: &str) -> bool {
+	let line = text.strip_prefix('\n').unwrap_or(text);
+	let name_len = line
+		.find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+		.unwrap_or(line.len());
+	let rest = &line[name_len..];
+	line.is_empty()
+		|| name_len != 0 && (rest.starts_with('=') || rest.starts_with("+="))
+}
+
+/// Constructs tracked by [`open_at_line_end`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Open {
+	/// `'...'`.
+	SingleQuote,
+	/// `$'...'`, where backslashes escape quotes.
+	AnsiCQuote,
+	/// `"..."` or `$"..."`.
+	DoubleQuote,
+	/// `${...}`.
+	Expansion,
+}
+
+impl Open {
+	fn closing(self) -> Recovery {
+		match self {
+			Open::SingleQuote | Open::AnsiCQuote => Recovery::SingleQuote,
+			Open::DoubleQuote => Recovery::DoubleQuote,
+			Open::Expansion => Recovery::Expansion,
+		}
+	}
+}
+
+/// Finds lines with constructs beginning in them and open at their ends.
+///
+/// Returns the end offset of each line, along with texts closing the
+/// constructs, innermost first.
+fn open_at_line_ends(text: &str) -> Vec<(usize, Vec<Recovery>)> {
+	let bytes = text.as_bytes();
+	let mut stack = Vec::new();
+	let mut line_start = 0;
+	let mut lines = Vec::new();
+	let close = |line_start: usize, stack: &[(Open, usize)]| {
+		let closing = stack
+			.iter()
+			.rev()
+			.take_while(|(_, start)| *start >= line_start)
+			.map(|(open, _)| open.closing())
+			.collect::<Vec<_>>();
+		(!closing.is_empty()).then_some(closing)
+	};
+	let mut pos = 0;
+	while pos < bytes.len() {
+		let ch = bytes[pos];
+		let next = bytes.get(pos + 1).copied();
+		match (stack.last().map(|(open, _)| *open), ch) {
+			(_, b'\n') => {
+				lines.extend(
+					close(line_start, &stack).map(|closing| (pos, closing)),
+				);
+				line_start = pos + 1;
+			}
+			(Some(Open::SingleQuote), b'\'') => {
+				stack.pop();
+			}
+			(Some(Open::SingleQuote), _) => {}
+			(_, b'\\') => pos += 1,
+			(Some(Open::AnsiCQuote), b'\'')
+			| (Some(Open::DoubleQuote), b'"')
+			| (Some(Open::Expansion), b'}') => {
+				stack.pop();
+			}
+			(Some(Open::AnsiCQuote), _) => {}
+			(_, b'$') if next == Some(b'{') => {
+				stack.push((Open::Expansion, pos));
+				pos += 1;
+			}
+			(None | Some(Open::Expansion), b'$') if next == Some(b'\'') => {
+				stack.push((Open::AnsiCQuote, pos));
+				pos += 1;
+			}
+			(None | Some(Open::Expansion), b'\'') => {
+				stack.push((Open::SingleQuote, pos));
+			}
+			(None | Some(Open::Expansion), b'"') => {
+				stack.push((Open::DoubleQuote, pos));
+			}
+			(None, b'#')
+				if pos == 0
+					|| matches!(
+						bytes[pos - 1],
+						b' ' | b'\t' | b'\n' | b'('
+					) =>
+			{
+				pos =
+					text[pos..].find('\n').map_or(text.len(), |end| pos + end);
+				continue;
+			}
+			_ => {}
+		}
+		pos += 1;
+	}
+	lines
+		.extend(close(line_start, &stack).map(|closing| (text.len(), closing)));
+	lines
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn names(recovered: &RecoveredLst) -> Vec<(Span, String)> {
+		recovered
+			.variable_spans()
+			.map(|(span, def)| (span, def.name.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn test_parse_recovering() {
+		let src = "PKGNAME=a\nPKGDES=\"An editor\nPKGDEP=\"x y\"\n";
+		let recovered = parse_recovering(src).unwrap();
+		assert_eq!(
+			recovered.lst.to_string(),
+			"PKGNAME=a\nPKGDES=\"An editor\"\nPKGDEP=\"x y\"\n"
+		);
+		assert_eq!(
+			recovered.synthetic,
+			vec![Synthetic {
+				offset: 27,
+				recovery: Recovery::DoubleQuote
+			}]
+		);
+		assert_eq!(
+			names(&recovered),
+			vec![
+				(Span::new(0, 9), "PKGNAME".to_string()),
+				(Span::new(10, 27), "PKGDES".to_string()),
+				(Span::new(28, 40), "PKGDEP".to_string()),
+			]
+		);
+		assert!(recovered.is_synthetic(Span::new(27, 28)));
+		assert!(!recovered.is_synthetic(Span::new(28, 29)));
+		assert_eq!(recovered.to_recovered(27), 27);
+		assert_eq!(recovered.to_recovered(28), 29);
+		assert_eq!(recovered.to_source(28), 27);
+		assert_eq!(recovered.to_source(29), 28);
+
+		let recovered = parse_recovering("A=1\n").unwrap();
+		assert!(!recovered.is_recovered());
+
+		// quotes spanning lines are not closed early
+		let recovered = parse_recovering("A=\"a\nb\"\nB='c\nC=1").unwrap();
+		assert_eq!(recovered.lst.to_string(), "A=\"a\nb\"\nB='c'\nC=1");
+		let recovered = parse_recovering("# it's\nC=$'d\\'\nD=1").unwrap();
+		assert_eq!(recovered.lst.to_string(), "# it's\nC=$'d\\''\nD=1");
+
+		let recovered = parse_recovering("A=\"${V\nB=\"${\n").unwrap();
+		assert_eq!(recovered.lst.to_string(), "A=\"${V}\"\nB=\"\\${}\"\n");
+		assert_eq!(
+			recovered
+				.synthetic
+				.iter()
+				.map(|synthetic| (synthetic.offset, synthetic.recovery))
+				.collect::<Vec<_>>(),
+			vec![
+				(6, Recovery::Expansion),
+				(6, Recovery::DoubleQuote),
+				(10, Recovery::EscapedExpansion),
+				(12, Recovery::Expansion),
+				(12, Recovery::DoubleQuote),
+			]
+		);
+		assert_eq!(
+			names(&recovered),
+			vec![
+				(Span::new(0, 6), "A".to_string()),
+				(Span::new(7, 12), "B".to_string()),
+			]
+		);
+
+		assert!(parse_recovering("A=1\n)\n").is_err());
+	}
+}
@@ -0,0 +1,592 @@
+//! Package relations, such as dependencies and conflicts.
+//!
+//! [`Relations::from_context`] collects the relations declared by the
+//! relation fields of a package, such as `PKGDEP` and `PKGBREAK`. Each
+//! entry is written as a package name optionally followed by a version
+//! constraint, such as `glibc>=2.35`.
+//!
+//! With the `serde` feature enabled, relations can be exported as JSON
+//! with [`to_json`] and read back with [`from_json`], or exported as
+//! SPDX-style relationships with [`to_spdx_json`].
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use thiserror::Error;
+
+use super::{ElementErrors, ReadContext};
+
+/// Errors produced while reading relations.
+#[derive(Debug, Error)]
+pub enum RelationsError {
+	#[cfg(feature = "serde")]
+	#[error(transparent)]
+	Json(#[from] serde_json::Error),
+	#[error("Unsupported relations version: {0}")]
+	UnsupportedVersion(u64),
+	#[error("Invalid relations in {field}: {errors}")]
+	InvalidRelations {
+		field: String,
+		errors: ElementErrors<MalformedRelation>,
+	},
+	#[error("Invalid relations: {0}")]
+	Invalid(String),
+}
+
+/// Error of a relation entry which cannot be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("expected a package name with an optional version constraint")]
+pub struct MalformedRelation;
+
+/// Kind of a relation, corresponding to a relation field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RelationKind {
+	/// `PKGDEP`
+	Depends,
+	/// `BUILDDEP`
+	BuildDepends,
+	/// `PKGRECOM`
+	Recommends,
+	/// `PKGSUG`
+	Suggests,
+	/// `PKGPROV`
+	Provides,
+	/// `PKGREP`
+	Replaces,
+	/// `PKGBREAK`
+	Breaks,
+	/// `PKGCONFL`
+	Conflicts,
+}
+
+impl RelationKind {
+	/// All kinds, in the order of declaration.
+	pub const ALL: [Self; Self::Conflicts as usize + 1] = [
+		Self::Depends,
+		Self::BuildDepends,
+		Self::Recommends,
+		Self::Suggests,
+		Self::Provides,
+		Self::Replaces,
+		Self::Breaks,
+		Self::Conflicts,
+	];
+
+	/// Returns the name of the field declaring relations of the kind.
+	pub fn field(&self) -> &'static str {
+		match self {
+			Self::Depends => "PKGDEP",
+			Self::BuildDepends => "BUILDDEP",
+			Self::Recommends => "PKGRECOM",
+			Self::Suggests => "PKGSUG",
+			Self::Provides => "PKGPROV",
+			Self::Replaces => "PKGREP",
+			Self::Breaks => "PKGBREAK",
+			Self::Conflicts => "PKGCONFL",
+		}
+	}
+
+	/// Recognizes a field name.
+	pub fn from_field(field: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|kind| kind.field() == field)
+	}
+}
+
+/// Operator of a version constraint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VersionOp {
+	/// `<`
+	Lt,
+	/// `<=`
+	Le,
+	/// `=`, also written as `==`
+	Eq,
+	/// `>=`
+	Ge,
+	/// `>`
+	Gt,
+}
+
+impl VersionOp {
+	/// Returns the symbol of the operator.
+	pub fn symbol(&self) -> &'static str {
+		match self {
+			Self::Lt => "<",
+			Self::Le => "<=",
+			Self::Eq => "=",
+			Self::Ge => ">=",
+			Self::Gt => ">",
+		}
+	}
+
+	/// Recognizes a symbol of an operator.
+	pub fn from_symbol(symbol: &str) -> Option<Self> {
+		match symbol {
+			"<" => Some(Self::Lt),
+			"<=" => Some(Self::Le),
+			"=" | "==" => Some(Self::Eq),
+			">=" => Some(Self::Ge),
+			">" => Some(Self::Gt),
+			_ => None,
+		}
+	}
+}
+
+impl Display for VersionOp {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.symbol())
+	}
+}
+
+/// A single relation of a package to another.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Dependency {
+	/// Name of the package declaring the relation.
+	pub package: String,
+	/// Kind of the relation.
+	pub kind: RelationKind,
+	/// Name of the related package.
+	pub target: String,
+	/// Version constraint on the related package.
+	pub constraint: Option<(VersionOp, String)>,
+}
+
+impl Dependency {
+	/// Parses an entry of a relation field, such as `glibc>=2.35`.
+	///
+	/// Returns [`None`] if the target name is empty, or if the operator
+	/// is not followed by a version.
+	pub fn parse<S: Into<String>>(
+		package: S,
+		kind: RelationKind,
+		relation: &str,
+	) -> Option<Self> {
+		let split = relation.find(['<', '>', '=']).unwrap_or(relation.len());
+		let (target, constraint) = relation.split_at(split);
+		if target.is_empty() {
+			return None;
+		}
+		let constraint = if constraint.is_empty() {
+			None
+		} else {
+			let version_start = constraint
+				.find(|ch| !matches!(ch, '<' | '>' | '='))
+				.unwrap_or(constraint.len());
+			let (op, version) = constraint.split_at(version_start);
+			if version.is_empty() {
+				return None;
+			}
+			Some((VersionOp::from_symbol(op)?, version.to_string()))
+		};
+		Some(Self {
+			package: package.into(),
+			kind,
+			target: target.to_string(),
+			constraint,
+		})
+	}
+
+	/// Returns the operator of the version constraint.
+	pub fn op(&self) -> Option<VersionOp> {
+		self.constraint.as_ref().map(|(op, _)| *op)
+	}
+
+	/// Returns the version of the version constraint.
+	pub fn version(&self) -> Option<&str> {
+		self.constraint
+			.as_ref()
+			.map(|(_, version)| version.as_str())
+	}
+}
+
+impl Display for Dependency {
+	/// Formats the relation as written in relation fields.
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.target)?;
+		if let Some((op, version)) = &self.constraint {
+			write!(f, "{}{}", op, version)?;
+		}
+		Ok(())
+	}
+}
+
+/// Relations of packages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Relations {
+	/// All relations, in the order of fields and then of entries.
+	pub dependencies: Vec<Dependency>,
+}
+
+impl Relations {
+	/// Collects relations declared by a package.
+	///
+	/// The package name is taken from `PKGNAME`, and relation fields are
+	/// read in the order of [`RelationKind::ALL`]. Architecture-specific
+	/// overrides such as `PKGDEP__AMD64` are not considered, so contexts
+	/// should be [resolved][super::arch::resolve_arch] first.
+	pub fn from_context<C: ReadContext + ?Sized>(
+		context: &C,
+	) -> Result<Self, RelationsError> {
+		let package = context.read("PKGNAME").into_string();
+		let mut dependencies = Vec::new();
+		for kind in RelationKind::ALL {
+			let Some(value) = context.get(kind.field()) else {
+				continue;
+			};
+			let parsed = value
+				.try_map_elements(|relation| {
+					Dependency::parse(&package, kind, relation)
+						.ok_or(MalformedRelation)
+				})
+				.map_err(|errors| RelationsError::InvalidRelations {
+					field: kind.field().to_string(),
+					errors,
+				})?;
+			dependencies.extend(parsed);
+		}
+		Ok(Self { dependencies })
+	}
+
+	/// Iterates over relations of a kind.
+	pub fn of_kind(
+		&self,
+		kind: RelationKind,
+	) -> impl Iterator<Item = &Dependency> {
+		self.dependencies.iter().filter(move |dep| dep.kind == kind)
+	}
+}
+
+/// Default mapping from relation kinds to SPDX relationship types.
+///
+/// `PKGDEP` and `BUILDDEP` map to the SPDX `DEPENDS_ON` and
+/// `HAS_PREREQUISITE` types. SPDX has no equivalents for the other kinds,
+/// which map to types named after them in the same style.
+pub const SPDX_RELATIONSHIPS: [(RelationKind, &str); 8] = [
+	(RelationKind::Depends, "DEPENDS_ON"),
+	(RelationKind::BuildDepends, "HAS_PREREQUISITE"),
+	(RelationKind::Recommends, "RECOMMENDS"),
+	(RelationKind::Suggests, "SUGGESTS"),
+	(RelationKind::Provides, "PROVIDES"),
+	(RelationKind::Replaces, "REPLACES"),
+	(RelationKind::Breaks, "CONFLICTS"),
+	(RelationKind::Conflicts, "CONFLICTS"),
+];
+
+/// Mapping from relation kinds to SPDX relationship types.
+///
+/// The default mapping is [`SPDX_RELATIONSHIPS`]. Relations of kinds
+/// without a type are omitted from exports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpdxMapping(pub BTreeMap<RelationKind, String>);
+
+impl Default for SpdxMapping {
+	fn default() -> Self {
+		Self(
+			SPDX_RELATIONSHIPS
+				.into_iter()
+				.map(|(kind, ty)| (kind, ty.to_string()))
+				.collect(),
+		)
+	}
+}
+
+impl SpdxMapping {
+	/// Returns the relationship type of a kind.
+	pub fn get(&self, kind: RelationKind) -> Option<&str> {
+		self.0.get(&kind).map(String::as_str)
+	}
+
+	/// Overrides the relationship type of a kind.
+	pub fn set<S: Into<String>>(mut self, kind: RelationKind, ty: S) -> Self {
+		self.0.insert(kind, ty.into());
+		self
+	}
+
+	/// Omits relations of a kind.
+	pub fn omit(mut self, kind: RelationKind) -> Self {
+		self.0.remove(&kind);
+		self
+	}
+}
+
+/// Returns the SPDX element ID of a package.
+///
+/// Characters not allowed in IDs are replaced with `-`.
+pub fn spdx_id(package: &str) -> String {
+	let mut id = "SPDXRef-Package-".to_string();
+	id.extend(package.chars().map(|ch| {
+		if ch.is_ascii_alphanumeric() || ch == '.' || ch == '-' {
+			ch
+		} else {
+			'-'
+		}
+	}));
+	id
+}
+
+#[cfg(feature = "serde")]
+mod json {
+	use serde_json::{Map, Value, json};
+
+	use super::*;
+
+	/// Version of the JSON format of relations.
+	pub const RELATIONS_FORMAT_VERSION: u64 = 1;
+
+	/// Saves relations into pretty-printed JSON.
+	///
+	/// The root object has a `version`, which is
+	/// [`RELATIONS_FORMAT_VERSION`], and a list of `relations`. Each
+	/// relation is an object with the `package` declaring it, its `kind`
+	/// as the name of the relation field, such as `PKGDEP`, the `target`
+	/// package and the `operator` and `version` of the version
+	/// constraint. The latter two are `null` if there is no constraint.
+	pub fn to_json(relations: &Relations) -> String {
+		let relations = relations
+			.dependencies
+			.iter()
+			.map(|dep| {
+				json!({
+					"package": dep.package,
+					"kind": dep.kind.field(),
+					"target": dep.target,
+					"operator": dep.op().map(|op| op.symbol()),
+					"version": dep.version(),
+				})
+			})
+			.collect::<Vec<_>>();
+		let root = json!({
+			"version": RELATIONS_FORMAT_VERSION,
+			"relations": relations,
+		});
+		serde_json::to_string_pretty(&root)
+			.expect("serializing JSON value never fails")
+	}
+
+	/// Loads relations from JSON produced by [`to_json`].
+	pub fn from_json(src: &str) -> Result<Vec<Dependency>, RelationsError> {
+		let root = serde_json::from_str::<Value>(src)?;
+		let version = root
+			.get("version")
+			.and_then(Value::as_u64)
+			.ok_or_else(|| invalid("missing version"))?;
+		if version != RELATIONS_FORMAT_VERSION {
+			return Err(RelationsError::UnsupportedVersion(version));
+		}
+		root.get("relations")
+			.and_then(Value::as_array)
+			.ok_or_else(|| invalid("missing relations"))?
+			.iter()
+			.map(dependency_from_json)
+			.collect()
+	}
+
+	/// Saves relations into pretty-printed SPDX-style relationships,
+	/// with the [default mapping][SpdxMapping::default].
+	pub fn to_spdx_json(relations: &Relations) -> String {
+		to_spdx_json_with(relations, &SpdxMapping::default())
+	}
+
+	/// Saves relations into pretty-printed SPDX-style relationships.
+	///
+	/// The root object has a list of `relationships`, each of which has
+	/// a `spdxElementId` and a `relatedSpdxElement` as produced by
+	/// [`spdx_id`], and a `relationshipType` given by the mapping.
+	/// Version constraints are kept in the `comment`.
+	pub fn to_spdx_json_with(
+		relations: &Relations,
+		mapping: &SpdxMapping,
+	) -> String {
+		let relationships = relations
+			.dependencies
+			.iter()
+			.filter_map(|dep| {
+				let mut object = Map::new();
+				object.insert(
+					"spdxElementId".to_string(),
+					spdx_id(&dep.package).into(),
+				);
+				object.insert(
+					"relationshipType".to_string(),
+					mapping.get(dep.kind)?.into(),
+				);
+				object.insert(
+					"relatedSpdxElement".to_string(),
+					spdx_id(&dep.target).into(),
+				);
+				if let Some((op, version)) = &dep.constraint {
+					object.insert(
+						"comment".to_string(),
+						format!("{} {}{}", dep.target, op, version).into(),
+					);
+				}
+				Some(Value::Object(object))
+			})
+			.collect::<Vec<_>>();
+		serde_json::to_string_pretty(&json!({ "relationships": relationships }))
+			.expect("serializing JSON value never fails")
+	}
+
+	fn invalid(message: &str) -> RelationsError {
+		RelationsError::Invalid(message.to_string())
+	}
+
+	fn dependency_from_json(
+		value: &Value,
+	) -> Result<Dependency, RelationsError> {
+		let object = value
+			.as_object()
+			.ok_or_else(|| invalid("relation is not an object"))?;
+		let string = |key: &str| -> Result<Option<&str>, RelationsError> {
+			match object.get(key) {
+				None | Some(Value::Null) => Ok(None),
+				Some(Value::String(value)) => Ok(Some(value)),
+				Some(_) => Err(invalid(&format!("{} is not a string", key))),
+			}
+		};
+		let required = |key: &str| {
+			string(key)?.ok_or_else(|| invalid(&format!("missing {}", key)))
+		};
+		let kind = required("kind")?;
+		let kind = RelationKind::from_field(kind)
+			.ok_or_else(|| invalid(&format!("unknown kind {}", kind)))?;
+		let constraint = match (string("operator")?, string("version")?) {
+			(None, None) => None,
+			(Some(op), Some(version)) => Some((
+				VersionOp::from_symbol(op).ok_or_else(|| {
+					invalid(&format!("unknown operator {}", op))
+				})?,
+				version.to_string(),
+			)),
+			_ => return Err(invalid("operator and version must be both set")),
+		};
+		Ok(Dependency {
+			package: required("package")?.to_string(),
+			kind,
+			target: required("target")?.to_string(),
+			constraint,
+		})
+	}
+}
+
+#[cfg(feature = "serde")]
+pub use json::*;
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::ApmlContext;
+
+	#[test]
+	fn test_parse() {
+		let dep = Dependency::parse("a", RelationKind::Depends, "glibc>=2.35")
+			.unwrap();
+		assert_eq!(dep.target, "glibc");
+		assert_eq!(dep.op(), Some(VersionOp::Ge));
+		assert_eq!(dep.version(), Some("2.35"));
+		assert_eq!(dep.to_string(), "glibc>=2.35");
+		let dep = Dependency::parse("a", RelationKind::Depends, "gcc-runtime")
+			.unwrap();
+		assert_eq!(dep.constraint, None);
+		let dep = Dependency::parse("a", RelationKind::Breaks, "b==1").unwrap();
+		assert_eq!(dep.to_string(), "b=1");
+		assert_eq!(Dependency::parse("a", RelationKind::Depends, ">=1"), None);
+		assert_eq!(Dependency::parse("a", RelationKind::Depends, "b>="), None);
+		assert_eq!(Dependency::parse("a", RelationKind::Depends, "b=>1"), None);
+	}
+
+	#[test]
+	fn test_from_context() {
+		let context = ApmlContext::eval_source(
+			"PKGNAME=foo\nPKGDEP=\"glibc>=2.35 bar\"\nPKGBREAK=(baz\\<1)\n",
+		)
+		.unwrap();
+		let relations = Relations::from_context(&context).unwrap();
+		assert_eq!(
+			relations
+				.dependencies
+				.iter()
+				.map(|dep| (dep.kind, dep.to_string()))
+				.collect::<Vec<_>>(),
+			vec![
+				(RelationKind::Depends, "glibc>=2.35".to_string()),
+				(RelationKind::Depends, "bar".to_string()),
+				(RelationKind::Breaks, "baz<1".to_string()),
+			]
+		);
+		assert_eq!(relations.of_kind(RelationKind::Breaks).count(), 1);
+		assert!(
+			relations
+				.dependencies
+				.iter()
+				.all(|dep| dep.package == "foo")
+		);
+
+		let context =
+			ApmlContext::eval_source("PKGNAME=foo\nPKGDEP=\"a b>= c <1\"\n")
+				.unwrap();
+		let Err(RelationsError::InvalidRelations { field, errors }) =
+			Relations::from_context(&context)
+		else {
+			panic!("relations should be invalid");
+		};
+		assert_eq!(field, "PKGDEP");
+		assert_eq!(
+			errors
+				.iter()
+				.map(|e| (e.index, e.element.as_str()))
+				.collect::<Vec<_>>(),
+			vec![(1, "b>="), (3, "<1")]
+		);
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn test_json() {
+		let context = ApmlContext::eval_source(
+			"PKGNAME=foo+bar\nPKGDEP=\"glibc>=2.35 x\"\nPKGSUG=y\n",
+		)
+		.unwrap();
+		let relations = Relations::from_context(&context).unwrap();
+		let json = to_json(&relations);
+		assert_eq!(from_json(&json).unwrap(), relations.dependencies);
+		assert!(matches!(
+			from_json(r#"{"version": 2, "relations": []}"#),
+			Err(RelationsError::UnsupportedVersion(2))
+		));
+		assert!(
+			from_json(
+				r#"{"version": 1, "relations": [{"package": "a",
+				"kind": "PKGDEP", "target": "b", "operator": ">="}]}"#
+			)
+			.is_err()
+		);
+
+		let spdx = serde_json::from_str::<serde_json::Value>(&to_spdx_json(
+			&relations,
+		))
+		.unwrap();
+		let relationships = spdx["relationships"].as_array().unwrap();
+		assert_eq!(relationships.len(), 3);
+		assert_eq!(
+			relationships[0]["spdxElementId"],
+			"SPDXRef-Package-foo-bar"
+		);
+		assert_eq!(relationships[0]["relationshipType"], "DEPENDS_ON");
+		assert_eq!(relationships[0]["comment"], "glibc >=2.35");
+		assert_eq!(relationships[2]["relationshipType"], "SUGGESTS");
+
+		let mapping = SpdxMapping::default()
+			.set(RelationKind::Depends, "RUNTIME_DEPENDENCY_OF")
+			.omit(RelationKind::Suggests);
+		let spdx = serde_json::from_str::<serde_json::Value>(
+			&to_spdx_json_with(&relations, &mapping),
+		)
+		.unwrap();
+		let relationships = spdx["relationships"].as_array().unwrap();
+		assert_eq!(relationships.len(), 2);
+		assert_eq!(
+			relationships[0]["relationshipType"],
+			"RUNTIME_DEPENDENCY_OF"
+		);
+	}
+}
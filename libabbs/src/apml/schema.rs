@@ -0,0 +1,1080 @@
+//! Schema of ABBS fields.
+//!
+//! A [`FieldSchema`] describes the known fields of `defines` and `spec`
+//! files: their types, allowed values and deprecation status. It is
+//! the single source of truth for features that need to know about
+//! fields, such as [completions][super::completion].
+//!
+//! [`FieldSchema::aosc`] is the bundled schema of autobuild4-era
+//! fields. With the `serde` feature enabled, trees can ship their own
+//! schema as JSON, see [`FieldSchema::from_json`].
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use thiserror::Error;
+
+use super::ReadContext;
+
+/// Errors produced while loading a schema.
+#[derive(Debug, Error)]
+pub enum SchemaError {
+	#[cfg(feature = "serde")]
+	#[error(transparent)]
+	Json(#[from] serde_json::Error),
+	#[error("Unsupported schema version: {0}")]
+	UnsupportedVersion(u64),
+	#[error("Invalid schema: {0}")]
+	Invalid(String),
+}
+
+/// Type of the value of a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FieldType {
+	/// A single string.
+	#[default]
+	Scalar,
+	/// A list of words, either written as a whitespace-separated string
+	/// or as a bash array.
+	Array,
+	/// A boolean, written as `0`, `1`, `true`, `false`, `yes` or `no`.
+	Bool,
+	/// A non-negative integer.
+	Int,
+}
+
+impl FieldType {
+	/// Returns the identifier of the type.
+	pub fn ident(&self) -> &'static str {
+		match self {
+			FieldType::Scalar => "scalar",
+			FieldType::Array => "array",
+			FieldType::Bool => "bool",
+			FieldType::Int => "int",
+		}
+	}
+
+	/// Returns if the field can be written as a bash array.
+	///
+	/// Fields of other types must be written as strings, as ACBS reads
+	/// them as scalars. Arrays may also be written as strings, which are
+	/// split into words.
+	pub fn accepts_array(&self) -> bool {
+		matches!(self, FieldType::Array)
+	}
+
+	/// Recognizes a type identifier.
+	pub fn from_ident(ident: &str) -> Option<Self> {
+		match ident {
+			"scalar" => Some(FieldType::Scalar),
+			"array" => Some(FieldType::Array),
+			"bool" => Some(FieldType::Bool),
+			"int" => Some(FieldType::Int),
+			_ => None,
+		}
+	}
+}
+
+/// Checks of characters in values that consumers of a field mangle.
+///
+/// ACBS word-splits some fields, so such characters produce confusing
+/// results. See [`lint::check_values`].
+///
+/// [`lint::check_values`]: super::lint::check_values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ValueChecks {
+	/// Whether to flag embedded newlines.
+	pub newline: bool,
+	/// Whether to flag tabs.
+	pub tab: bool,
+	/// Whether to flag leading and trailing whitespaces.
+	pub surrounding_whitespace: bool,
+	/// Whether to flag carriage returns not followed by a newline,
+	/// vertical tabs and form feeds, see [`lst::invisible_controls`].
+	///
+	/// [`lst::invisible_controls`]: super::lst::invisible_controls
+	pub invisible_control: bool,
+	/// Rules of package descriptions to check, if the field is one.
+	pub desc: Option<DescPolicy>,
+}
+
+impl ValueChecks {
+	/// Checks nothing.
+	pub const NONE: Self = Self {
+		newline: false,
+		tab: false,
+		surrounding_whitespace: false,
+		invisible_control: false,
+		desc: None,
+	};
+
+	/// Returns the default checks for a type of fields.
+	///
+	/// Newlines are flagged in all fields but arrays, tabs are flagged in
+	/// all fields but scalars, and surrounding whitespaces and invisible
+	/// control characters are flagged in all fields but arrays, which
+	/// are the human-readable ones.
+	pub fn for_type(ty: FieldType) -> Self {
+		match ty {
+			FieldType::Scalar => Self {
+				newline: true,
+				tab: false,
+				surrounding_whitespace: true,
+				invisible_control: true,
+				desc: None,
+			},
+			FieldType::Array => Self {
+				newline: false,
+				tab: true,
+				surrounding_whitespace: false,
+				invisible_control: false,
+				desc: None,
+			},
+			FieldType::Bool | FieldType::Int => Self {
+				newline: true,
+				tab: true,
+				surrounding_whitespace: true,
+				invisible_control: true,
+				desc: None,
+			},
+		}
+	}
+}
+
+/// Limits of package descriptions, such as `PKGDES`.
+///
+/// Besides the limits, descriptions must not contain control characters,
+/// end with a period, or start with the name of the package. See
+/// [`lint::check_desc`].
+///
+/// [`lint::check_desc`]: super::lint::check_desc
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DescPolicy {
+	/// Maximum length in characters.
+	pub max_len: usize,
+}
+
+impl Default for DescPolicy {
+	fn default() -> Self {
+		Self { max_len: 80 }
+	}
+}
+
+/// Metadata of a field.
+///
+/// Fields are created with [`FieldSpec::new`] and configured with the
+/// builder-style methods.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldSpec {
+	/// Name of the field.
+	pub name: String,
+	/// Type of the value.
+	pub ty: FieldType,
+	/// Whether the field can be overridden for architectures with
+	/// suffixes, such as `PKGDEP__AMD64`.
+	pub arch_overridable: bool,
+	/// Version since which the field is deprecated, if it is.
+	pub deprecated_since: Option<String>,
+	/// Human-readable description.
+	pub description: String,
+	/// Allowed values, if the field only accepts some values.
+	///
+	/// For arrays, this applies to each element.
+	pub allowed_values: Option<Vec<String>>,
+	/// Checks of characters in values.
+	///
+	/// Defaults to [`ValueChecks::for_type`].
+	pub checks: ValueChecks,
+	/// Whether the field is a list of package relations, such as
+	/// `glibc>=2.35`.
+	pub relation: bool,
+	/// Whether the field belongs to `spec` files rather than `defines`
+	/// files.
+	pub spec: bool,
+}
+
+impl FieldSpec {
+	/// Creates a field.
+	pub fn new<S: Into<String>>(name: S, ty: FieldType) -> Self {
+		Self {
+			name: name.into(),
+			ty,
+			arch_overridable: false,
+			deprecated_since: None,
+			description: String::new(),
+			allowed_values: None,
+			checks: ValueChecks::for_type(ty),
+			relation: false,
+			spec: false,
+		}
+	}
+
+	/// Sets if the field can be overridden for architectures.
+	pub fn arch_overridable(mut self, arch_overridable: bool) -> Self {
+		self.arch_overridable = arch_overridable;
+		self
+	}
+
+	/// Marks the field as deprecated.
+	pub fn deprecated_since<S: Into<String>>(mut self, version: S) -> Self {
+		self.deprecated_since = Some(version.into());
+		self
+	}
+
+	/// Sets the description.
+	pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+		self.description = description.into();
+		self
+	}
+
+	/// Sets the allowed values.
+	pub fn allowed_values<I, S>(mut self, values: I) -> Self
+	where
+		I: IntoIterator<Item = S>,
+		S: Into<String>,
+	{
+		self.allowed_values =
+			Some(values.into_iter().map(Into::into).collect());
+		self
+	}
+
+	/// Sets the checks of characters in values.
+	pub fn checks(mut self, checks: ValueChecks) -> Self {
+		self.checks = checks;
+		self
+	}
+
+	/// Sets if the field is a list of package relations.
+	pub fn relation(mut self, relation: bool) -> Self {
+		self.relation = relation;
+		self
+	}
+
+	/// Sets if the field belongs to `spec` files.
+	pub fn spec(mut self, spec: bool) -> Self {
+		self.spec = spec;
+		self
+	}
+
+	/// Returns if the field is deprecated.
+	pub fn is_deprecated(&self) -> bool {
+		self.deprecated_since.is_some()
+	}
+
+	/// Returns if a value is allowed for the field.
+	///
+	/// Only allowed values are checked, the type is not.
+	pub fn allows(&self, value: &str) -> bool {
+		match &self.allowed_values {
+			Some(values) => values.iter().any(|allowed| allowed == value),
+			None => true,
+		}
+	}
+}
+
+/// A constraint between two fields.
+///
+/// A field is considered set if it is defined with a value which is
+/// neither empty nor a false boolean (`0`, `false` or `no`). See
+/// [`lint::check_constraints`].
+///
+/// [`lint::check_constraints`]: super::lint::check_constraints
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FieldConstraint {
+	/// The two fields must not be set together.
+	Conflicts(String, String),
+	/// If the first field is set, the second field must be set.
+	Requires(String, String),
+	/// If the first field has a value, the second field must have
+	/// a value. Empty values match undefined fields.
+	Implies {
+		field: String,
+		value: String,
+		then: String,
+		then_value: String,
+	},
+}
+
+impl FieldConstraint {
+	/// Creates a constraint that two fields must not be set together.
+	pub fn conflicts<A: Into<String>, B: Into<String>>(a: A, b: B) -> Self {
+		Self::Conflicts(a.into(), b.into())
+	}
+
+	/// Creates a constraint that a field must be set if another field is.
+	pub fn requires<A: Into<String>, B: Into<String>>(a: A, b: B) -> Self {
+		Self::Requires(a.into(), b.into())
+	}
+
+	/// Creates a constraint that a field must have a value if another
+	/// field has a value.
+	pub fn implies<A, V, B, W>(a: A, value: V, b: B, then_value: W) -> Self
+	where
+		A: Into<String>,
+		V: Into<String>,
+		B: Into<String>,
+		W: Into<String>,
+	{
+		Self::Implies {
+			field: a.into(),
+			value: value.into(),
+			then: b.into(),
+			then_value: then_value.into(),
+		}
+	}
+
+	/// Returns the names of the two constrained fields.
+	pub fn fields(&self) -> [&str; 2] {
+		match self {
+			Self::Conflicts(a, b) | Self::Requires(a, b) => [a, b],
+			Self::Implies { field, then, .. } => [field, then],
+		}
+	}
+
+	/// Returns if the constraint is satisfied by a context.
+	///
+	/// Architecture-specific overrides are not considered, so contexts
+	/// should be [resolved][super::arch::resolve_arch] first.
+	pub fn is_satisfied<C: ReadContext + ?Sized>(&self, context: &C) -> bool {
+		let is_set = |name: &str| {
+			context.get(name).is_some_and(|value| {
+				!matches!(value.as_string().as_str(), "" | "0" | "false" | "no")
+			})
+		};
+		let has_value = |name: &str, expected: &str| {
+			context.get(name).map_or(expected.is_empty(), |value| {
+				value.as_string() == expected
+			})
+		};
+		match self {
+			Self::Conflicts(a, b) => !(is_set(a) && is_set(b)),
+			Self::Requires(a, b) => !is_set(a) || is_set(b),
+			Self::Implies {
+				field,
+				value,
+				then,
+				then_value,
+			} => !has_value(field, value) || has_value(then, then_value),
+		}
+	}
+}
+
+impl Display for FieldConstraint {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Conflicts(a, b) => write!(f, "{} conflicts with {}", a, b),
+			Self::Requires(a, b) => write!(f, "{} requires {}", a, b),
+			Self::Implies {
+				field,
+				value,
+				then,
+				then_value,
+			} => write!(
+				f,
+				"{}={:?} implies {}={:?}",
+				field, value, then, then_value
+			),
+		}
+	}
+}
+
+/// A schema of ABBS fields.
+///
+/// The [`Default`] schema is [`FieldSchema::aosc`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSchema {
+	fields: BTreeMap<String, FieldSpec>,
+	constraints: Vec<FieldConstraint>,
+}
+
+impl Default for FieldSchema {
+	fn default() -> Self {
+		Self::aosc()
+	}
+}
+
+/// Builder of [`FieldSchema`].
+#[derive(Debug, Clone, Default)]
+pub struct FieldSchemaBuilder {
+	fields: BTreeMap<String, FieldSpec>,
+	constraints: Vec<FieldConstraint>,
+}
+
+impl FieldSchemaBuilder {
+	/// Adds a field, replacing the field with the same name.
+	pub fn field(mut self, field: FieldSpec) -> Self {
+		self.fields.insert(field.name.clone(), field);
+		self
+	}
+
+	/// Adds a constraint between fields, unless it is already added.
+	pub fn constraint(mut self, constraint: FieldConstraint) -> Self {
+		if !self.constraints.contains(&constraint) {
+			self.constraints.push(constraint);
+		}
+		self
+	}
+
+	/// Adds fields and constraints from another schema, replacing fields
+	/// with the same names.
+	pub fn extend(mut self, schema: &FieldSchema) -> Self {
+		self.fields.extend(
+			schema
+				.fields
+				.iter()
+				.map(|(name, field)| (name.clone(), field.clone())),
+		);
+		for constraint in &schema.constraints {
+			self = self.constraint(constraint.clone());
+		}
+		self
+	}
+
+	/// Builds the schema.
+	pub fn build(self) -> FieldSchema {
+		FieldSchema {
+			fields: self.fields,
+			constraints: self.constraints,
+		}
+	}
+}
+
+impl FieldSchema {
+	/// Creates a builder of an empty schema.
+	pub fn builder() -> FieldSchemaBuilder {
+		FieldSchemaBuilder::default()
+	}
+
+	/// Creates the bundled schema of autobuild4-era fields.
+	///
+	/// Constraints of the schema include that `DUMMYSRC` conflicts with
+	/// `SRCS` and `NOSTATIC` conflicts with `ABSTATIC`.
+	pub fn aosc() -> Self {
+		use FieldType::*;
+		let field = |name, ty, description| {
+			FieldSpec::new(name, ty).description(description)
+		};
+		let arch_field = |name, ty, description| {
+			field(name, ty, description).arch_overridable(true)
+		};
+		let relation_field = |name, description| {
+			arch_field(name, Array, description).relation(true)
+		};
+		let spec_field =
+			|name, ty, description| field(name, ty, description).spec(true);
+		let legacy_source = |name, description| {
+			spec_field(name, Scalar, description).deprecated_since("autobuild4")
+		};
+		Self::builder()
+			// spec
+			.field(spec_field("VER", Scalar, "Version of the package."))
+			.field(spec_field("REL", Int, "Revision of the package."))
+			.field(arch_field("SRCS", Array, "Sources to fetch.").spec(true))
+			.field(
+				arch_field("CHKSUMS", Array, "Checksums of the sources.")
+					.spec(true),
+			)
+			.field(spec_field(
+				"CHKUPDATE",
+				Scalar,
+				"Rule for checking updates of the package.",
+			))
+			.field(spec_field("SUBDIR", Scalar, "Directory to build in."))
+			.field(spec_field("DUMMYSRC", Bool, "Whether there is no source."))
+			.field(legacy_source("SRCTBL", "URL of the source tarball."))
+			.field(legacy_source("GITSRC", "URL of the source repository."))
+			.field(legacy_source("GITCO", "Commit of the source repository."))
+			.field(legacy_source("GITBRCH", "Branch of the source repository."))
+			.field(legacy_source("SVNSRC", "URL of the SVN repository."))
+			.field(legacy_source("SVNCO", "Revision of the SVN repository."))
+			.field(
+				spec_field("CHKSUM", Scalar, "Checksum of the source tarball.")
+					.deprecated_since("autobuild4"),
+			)
+			// defines
+			.field(field("PKGNAME", Scalar, "Name of the package."))
+			.field(field("PKGSEC", Scalar, "Section of the package."))
+			.field(
+				field("PKGDES", Scalar, "Description of the package.").checks(
+					ValueChecks {
+						tab: true,
+						desc: Some(DescPolicy::default()),
+						..ValueChecks::for_type(Scalar)
+					},
+				),
+			)
+			.field(field("PKGEPOCH", Int, "Epoch of the package version."))
+			.field(relation_field("PKGDEP", "Runtime dependencies."))
+			.field(relation_field("BUILDDEP", "Build-time dependencies."))
+			.field(relation_field("PKGRECOM", "Recommended packages."))
+			.field(relation_field("PKGSUG", "Suggested packages."))
+			.field(relation_field("PKGPROV", "Provided virtual packages."))
+			.field(relation_field("PKGREP", "Replaced packages."))
+			.field(relation_field("PKGBREAK", "Broken packages."))
+			.field(relation_field("PKGCONFL", "Conflicting packages."))
+			.field(
+				field("ABHOST", Scalar, "Host architecture of the package.")
+					.allowed_values(["noarch"]),
+			)
+			.field(
+				arch_field("ABTYPE", Scalar, "Build template to use.")
+					.allowed_values([
+						"autotools",
+						"cmake",
+						"cmakeninja",
+						"dummy",
+						"gomod",
+						"meson",
+						"npm",
+						"perl",
+						"pep517",
+						"plainmake",
+						"python",
+						"qtproj",
+						"rust",
+						"self",
+						"waf",
+					]),
+			)
+			.field(field(
+				"FAIL_ARCH",
+				Scalar,
+				"Pattern of architectures on which the package fails to build.",
+			))
+			.field(arch_field("ABSTRIP", Bool, "Whether to strip binaries."))
+			.field(arch_field(
+				"ABSPLITDBG",
+				Bool,
+				"Whether to split debug symbols.",
+			))
+			.field(arch_field("NOLTO", Bool, "Whether to disable LTO."))
+			.field(arch_field(
+				"NOSTATIC",
+				Bool,
+				"Whether to drop static libraries.",
+			))
+			.field(arch_field(
+				"NOPARALLEL",
+				Bool,
+				"Whether to disable parallel building.",
+			))
+			.field(arch_field("USECLANG", Bool, "Whether to build with Clang."))
+			.field(arch_field(
+				"ABSHADOW",
+				Bool,
+				"Whether to build out of the source tree.",
+			))
+			.field(arch_field(
+				"RECONF",
+				Bool,
+				"Whether to regenerate the build system.",
+			))
+			.field(arch_field(
+				"AUTOTOOLS_AFTER",
+				Array,
+				"Extra arguments to configure scripts.",
+			))
+			.field(arch_field(
+				"CMAKE_AFTER",
+				Array,
+				"Extra arguments to CMake.",
+			))
+			.field(arch_field(
+				"MESON_AFTER",
+				Array,
+				"Extra arguments to Meson.",
+			))
+			.field(arch_field("MAKE_AFTER", Array, "Extra arguments to make."))
+			.field(arch_field(
+				"CARGO_AFTER",
+				Array,
+				"Extra arguments to Cargo.",
+			))
+			.constraint(FieldConstraint::conflicts("DUMMYSRC", "SRCS"))
+			.constraint(FieldConstraint::conflicts("DUMMYSRC", "CHKSUMS"))
+			.constraint(FieldConstraint::requires("CHKSUMS", "SRCS"))
+			.constraint(FieldConstraint::conflicts("NOSTATIC", "ABSTATIC"))
+			.build()
+	}
+
+	/// Gets a field by its exact name.
+	pub fn get(&self, name: &str) -> Option<&FieldSpec> {
+		self.fields.get(name)
+	}
+
+	/// Gets a field by the name of a variable.
+	///
+	/// For arch-overridable fields, overrides such as `PKGDEP__AMD64`
+	/// are resolved to the field as well.
+	pub fn lookup(&self, name: &str) -> Option<&FieldSpec> {
+		if let Some(field) = self.fields.get(name) {
+			return Some(field);
+		}
+		let (base, suffix) = name.rsplit_once("__")?;
+		if suffix.is_empty() {
+			return None;
+		}
+		self.fields.get(base).filter(|field| field.arch_overridable)
+	}
+
+	/// Returns if a variable is a known field.
+	///
+	/// See [`FieldSchema::lookup`].
+	pub fn contains(&self, name: &str) -> bool {
+		self.lookup(name).is_some()
+	}
+
+	/// Suggests a field for a unknown name.
+	///
+	/// See [`did_you_mean`] for the ranking. Deprecated fields are never
+	/// suggested.
+	///
+	/// [`did_you_mean`]: super::suggest::did_you_mean
+	pub fn suggest(&self, name: &str) -> Option<&str> {
+		super::suggest::did_you_mean(
+			name,
+			self.fields()
+				.filter(|field| !field.is_deprecated())
+				.map(|field| field.name.as_str()),
+		)
+	}
+
+	/// Iterates over constraints between fields, in the order they are
+	/// added.
+	pub fn constraints(&self) -> impl Iterator<Item = &FieldConstraint> {
+		self.constraints.iter()
+	}
+
+	/// Iterates over all fields, sorted by name.
+	pub fn fields(&self) -> impl Iterator<Item = &FieldSpec> {
+		self.fields.values()
+	}
+
+	/// Returns the number of fields.
+	pub fn len(&self) -> usize {
+		self.fields.len()
+	}
+
+	/// Returns if the schema has no fields.
+	pub fn is_empty(&self) -> bool {
+		self.fields.is_empty()
+	}
+}
+
+#[cfg(feature = "serde")]
+mod json {
+	use serde_json::{Map, Value, json};
+
+	use super::{
+		DescPolicy, FieldConstraint, FieldSchema, FieldSpec, FieldType,
+		SchemaError, ValueChecks,
+	};
+
+	/// Version of the JSON format of schemas.
+	pub const SCHEMA_FORMAT_VERSION: u64 = 1;
+
+	impl FieldSchema {
+		/// Loads a schema from JSON.
+		///
+		/// The root object has a `version`, which must be
+		/// [`SCHEMA_FORMAT_VERSION`], and a list of `fields`. Each field
+		/// is an object with a `name` and a `type` (`scalar`, `array`,
+		/// `bool` or `int`), and optionally `arch_overridable`,
+		/// `deprecated_since`, `description`, `allowed_values`, `checks`,
+		/// `relation` and `spec`. `checks` is an object with optional
+		/// `newline`, `tab`, `surrounding_whitespace` and
+		/// `invisible_control` booleans, defaulting to
+		/// [`ValueChecks::for_type`], and an optional `desc` object with
+		/// a `max_len`.
+		///
+		/// The root object may also have a list of `constraints`, each
+		/// being an object with a single key: `conflicts` or `requires`
+		/// with a list of two field names, or `implies` with a list of
+		/// a field name, a value, a field name and a value. See
+		/// [`FieldConstraint`].
+		pub fn from_json(src: &str) -> Result<Self, SchemaError> {
+			let root = serde_json::from_str::<Value>(src)?;
+			let version = root
+				.get("version")
+				.and_then(Value::as_u64)
+				.ok_or_else(|| invalid("missing version"))?;
+			if version != SCHEMA_FORMAT_VERSION {
+				return Err(SchemaError::UnsupportedVersion(version));
+			}
+			let fields = root
+				.get("fields")
+				.and_then(Value::as_array)
+				.ok_or_else(|| invalid("missing fields"))?;
+			let mut builder = FieldSchema::builder();
+			for field in fields {
+				builder = builder.field(field_from_json(field)?);
+			}
+			match root.get("constraints") {
+				None | Some(Value::Null) => {}
+				Some(Value::Array(constraints)) => {
+					for constraint in constraints {
+						builder = builder
+							.constraint(constraint_from_json(constraint)?);
+					}
+				}
+				Some(_) => return Err(invalid("constraints is not an array")),
+			}
+			Ok(builder.build())
+		}
+
+		/// Saves the schema into pretty-printed JSON.
+		///
+		/// See [`FieldSchema::from_json`] for the format.
+		pub fn to_json(&self) -> String {
+			let fields = self.fields().map(field_to_json).collect::<Vec<_>>();
+			let constraints = self
+				.constraints()
+				.map(constraint_to_json)
+				.collect::<Vec<_>>();
+			let root = json!({
+				"version": SCHEMA_FORMAT_VERSION,
+				"fields": fields,
+				"constraints": constraints,
+			});
+			serde_json::to_string_pretty(&root)
+				.expect("serializing JSON value never fails")
+		}
+	}
+
+	fn invalid(message: &str) -> SchemaError {
+		SchemaError::Invalid(message.to_string())
+	}
+
+	fn field_from_json(value: &Value) -> Result<FieldSpec, SchemaError> {
+		let object = value
+			.as_object()
+			.ok_or_else(|| invalid("field is not an object"))?;
+		let string = |key: &str| -> Result<Option<&str>, SchemaError> {
+			match object.get(key) {
+				None | Some(Value::Null) => Ok(None),
+				Some(Value::String(value)) => Ok(Some(value)),
+				Some(_) => Err(invalid(&format!("{} is not a string", key))),
+			}
+		};
+		let name = string("name")?.ok_or_else(|| invalid("missing name"))?;
+		let ty = string("type")?.ok_or_else(|| invalid("missing type"))?;
+		let ty = FieldType::from_ident(ty)
+			.ok_or_else(|| invalid(&format!("unknown type {}", ty)))?;
+		let mut field = FieldSpec::new(name, ty);
+		if let Some(value) = bool_field(object, "arch_overridable")? {
+			field.arch_overridable = value;
+		}
+		if let Some(value) = bool_field(object, "relation")? {
+			field.relation = value;
+		}
+		if let Some(value) = bool_field(object, "spec")? {
+			field.spec = value;
+		}
+		field.deprecated_since = string("deprecated_since")?.map(Into::into);
+		field.description = string("description")?.unwrap_or_default().into();
+		match object.get("allowed_values") {
+			None | Some(Value::Null) => {}
+			Some(Value::Array(values)) => {
+				field.allowed_values = Some(
+					values
+						.iter()
+						.map(|value| {
+							value.as_str().map(Into::into).ok_or_else(|| {
+								invalid("allowed value is not a string")
+							})
+						})
+						.collect::<Result<_, _>>()?,
+				)
+			}
+			Some(_) => return Err(invalid("allowed_values is not an array")),
+		}
+		match object.get("checks") {
+			None | Some(Value::Null) => {}
+			Some(Value::Object(checks)) => {
+				let ValueChecks {
+					newline,
+					tab,
+					surrounding_whitespace,
+					invisible_control,
+					desc,
+				} = &mut field.checks;
+				for (key, value) in [
+					("newline", newline),
+					("tab", tab),
+					("surrounding_whitespace", surrounding_whitespace),
+					("invisible_control", invisible_control),
+				] {
+					if let Some(check) = bool_field(checks, key)? {
+						*value = check;
+					}
+				}
+				*desc = desc_from_json(checks.get("desc"))?;
+			}
+			Some(_) => return Err(invalid("checks is not an object")),
+		}
+		Ok(field)
+	}
+
+	fn desc_from_json(
+		value: Option<&Value>,
+	) -> Result<Option<DescPolicy>, SchemaError> {
+		match value {
+			None | Some(Value::Null) => Ok(None),
+			Some(Value::Object(desc)) => {
+				let mut policy = DescPolicy::default();
+				match desc.get("max_len") {
+					None | Some(Value::Null) => {}
+					Some(value) => {
+						policy.max_len = value
+							.as_u64()
+							.and_then(|len| len.try_into().ok())
+							.ok_or_else(|| invalid("max_len is not a size"))?
+					}
+				}
+				Ok(Some(policy))
+			}
+			Some(_) => Err(invalid("desc is not an object")),
+		}
+	}
+
+	fn constraint_from_json(
+		value: &Value,
+	) -> Result<FieldConstraint, SchemaError> {
+		let (kind, args) = value
+			.as_object()
+			.filter(|object| object.len() == 1)
+			.and_then(|object| object.iter().next())
+			.ok_or_else(|| {
+				invalid("constraint is not an object with one key")
+			})?;
+		let args = args
+			.as_array()
+			.and_then(|args| {
+				args.iter().map(Value::as_str).collect::<Option<Vec<_>>>()
+			})
+			.ok_or_else(|| {
+				invalid(&format!("{} is not a list of strings", kind))
+			})?;
+		match (kind.as_str(), args.as_slice()) {
+			("conflicts", [a, b]) => Ok(FieldConstraint::conflicts(*a, *b)),
+			("requires", [a, b]) => Ok(FieldConstraint::requires(*a, *b)),
+			("implies", [a, value, b, then_value]) => {
+				Ok(FieldConstraint::implies(*a, *value, *b, *then_value))
+			}
+			("conflicts" | "requires" | "implies", _) => {
+				Err(invalid(&format!("wrong number of arguments to {}", kind)))
+			}
+			_ => Err(invalid(&format!("unknown constraint {}", kind))),
+		}
+	}
+
+	fn constraint_to_json(constraint: &FieldConstraint) -> Value {
+		match constraint {
+			FieldConstraint::Conflicts(a, b) => json!({ "conflicts": [a, b] }),
+			FieldConstraint::Requires(a, b) => json!({ "requires": [a, b] }),
+			FieldConstraint::Implies {
+				field,
+				value,
+				then,
+				then_value,
+			} => json!({ "implies": [field, value, then, then_value] }),
+		}
+	}
+
+	fn bool_field(
+		object: &Map<String, Value>,
+		key: &str,
+	) -> Result<Option<bool>, SchemaError> {
+		match object.get(key) {
+			None | Some(Value::Null) => Ok(None),
+			Some(Value::Bool(value)) => Ok(Some(*value)),
+			Some(_) => Err(invalid(&format!("{} is not a bool", key))),
+		}
+	}
+
+	fn field_to_json(field: &FieldSpec) -> Value {
+		let mut object = Map::new();
+		object.insert("name".to_string(), field.name.clone().into());
+		object.insert("type".to_string(), field.ty.ident().into());
+		object.insert(
+			"arch_overridable".to_string(),
+			field.arch_overridable.into(),
+		);
+		object.insert(
+			"deprecated_since".to_string(),
+			field.deprecated_since.clone().into(),
+		);
+		object.insert(
+			"description".to_string(),
+			field.description.clone().into(),
+		);
+		object.insert(
+			"allowed_values".to_string(),
+			field.allowed_values.clone().into(),
+		);
+		object.insert(
+			"checks".to_string(),
+			json!({
+				"newline": field.checks.newline,
+				"tab": field.checks.tab,
+				"surrounding_whitespace": field.checks.surrounding_whitespace,
+				"invisible_control": field.checks.invisible_control,
+				"desc": field.checks.desc.map(|desc| json!({
+					"max_len": desc.max_len,
+				})),
+			}),
+		);
+		object.insert("relation".to_string(), field.relation.into());
+		object.insert("spec".to_string(), field.spec.into());
+		Value::Object(object)
+	}
+}
+
+#[cfg(feature = "serde")]
+pub use json::SCHEMA_FORMAT_VERSION;
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::ApmlContext;
+
+	#[test]
+	fn test_schema() {
+		let schema = FieldSchema::default();
+		assert_eq!(schema.get("PKGDEP").unwrap().ty, FieldType::Array);
+		assert_eq!(schema.lookup("PKGDEP__AMD64").unwrap().name, "PKGDEP");
+		assert!(schema.lookup("PKGNAME__AMD64").is_none());
+		assert!(schema.lookup("PKGDEP__").is_none());
+		assert!(schema.get("SRCTBL").unwrap().is_deprecated());
+		let abtype = schema.get("ABTYPE").unwrap();
+		assert!(abtype.allows("cmakeninja"));
+		assert!(!abtype.allows("scons"));
+		assert!(schema.get("PKGDES").unwrap().allows("anything"));
+		assert_eq!(schema.suggest("pkgdes"), Some("PKGDES"));
+		assert_eq!(schema.suggest("CHKSUMZ"), Some("CHKSUMS"));
+
+		let schema = FieldSchema::builder()
+			.extend(&schema)
+			.field(FieldSpec::new("PKGDES", FieldType::Int))
+			.field(
+				FieldSpec::new("FOO", FieldType::Bool)
+					.arch_overridable(true)
+					.description("Foo."),
+			)
+			.build();
+		assert_eq!(schema.get("PKGDES").unwrap().ty, FieldType::Int);
+		assert!(schema.contains("FOO__RETRO"));
+		assert_eq!(schema.len(), FieldSchema::aosc().len() + 1);
+		assert!(schema.constraints().eq(FieldSchema::aosc().constraints()));
+	}
+
+	#[test]
+	fn test_constraints() {
+		let context = ApmlContext::eval_source(
+			"DUMMYSRC=1\nSRCS=\"\"\nNOSTATIC=0\nABSTATIC=1\nABTYPE=self\n",
+		)
+		.unwrap();
+		assert!(
+			FieldConstraint::conflicts("DUMMYSRC", "SRCS")
+				.is_satisfied(&context)
+		);
+		assert!(
+			FieldConstraint::conflicts("NOSTATIC", "ABSTATIC")
+				.is_satisfied(&context)
+		);
+		assert!(
+			!FieldConstraint::conflicts("DUMMYSRC", "ABSTATIC")
+				.is_satisfied(&context)
+		);
+		assert!(
+			FieldConstraint::requires("SRCS", "CHKSUMS").is_satisfied(&context)
+		);
+		assert!(
+			!FieldConstraint::requires("DUMMYSRC", "CHKSUMS")
+				.is_satisfied(&context)
+		);
+		assert!(
+			FieldConstraint::implies("ABTYPE", "self", "DUMMYSRC", "1")
+				.is_satisfied(&context)
+		);
+		assert!(
+			!FieldConstraint::implies("ABTYPE", "self", "NOLTO", "1")
+				.is_satisfied(&context)
+		);
+		assert!(
+			FieldConstraint::implies("NOLTO", "", "CHKSUMS", "")
+				.is_satisfied(&context)
+		);
+		assert_eq!(
+			FieldConstraint::implies("ABTYPE", "self", "NOLTO", "1")
+				.to_string(),
+			"ABTYPE=\"self\" implies NOLTO=\"1\""
+		);
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn test_schema_json() {
+		let schema = FieldSchema::aosc();
+		assert_eq!(FieldSchema::from_json(&schema.to_json()).unwrap(), schema);
+
+		let schema = FieldSchema::from_json(
+			r#"{"version": 1, "fields": [
+				{"name": "A", "type": "bool", "deprecated_since": "1"},
+				{"name": "B", "type": "array", "allowed_values": ["x"],
+					"checks": {"newline": true}},
+				{"name": "C", "type": "scalar",
+					"checks": {"desc": {"max_len": 10},
+						"invisible_control": false}}
+			], "constraints": [
+				{"conflicts": ["A", "B"]},
+				{"implies": ["A", "1", "C", "x"]}
+			]}"#,
+		)
+		.unwrap();
+		assert_eq!(
+			schema.constraints().cloned().collect::<Vec<_>>(),
+			vec![
+				FieldConstraint::conflicts("A", "B"),
+				FieldConstraint::implies("A", "1", "C", "x"),
+			]
+		);
+		assert_eq!(
+			schema.fields().cloned().collect::<Vec<_>>(),
+			vec![
+				FieldSpec::new("A", FieldType::Bool).deprecated_since("1"),
+				FieldSpec::new("B", FieldType::Array)
+					.allowed_values(["x"])
+					.checks(ValueChecks {
+						newline: true,
+						..ValueChecks::for_type(FieldType::Array)
+					}),
+				FieldSpec::new("C", FieldType::Scalar).checks(ValueChecks {
+					invisible_control: false,
+					desc: Some(DescPolicy { max_len: 10 }),
+					..ValueChecks::for_type(FieldType::Scalar)
+				}),
+			]
+		);
+		assert!(matches!(
+			FieldSchema::from_json(r#"{"version": 2, "fields": []}"#),
+			Err(SchemaError::UnsupportedVersion(2))
+		));
+		assert_eq!(
+			FieldSchema::from_json(
+				r#"{"version": 1, "fields": [{"name": "A", "type": "x"}]}"#
+			)
+			.unwrap_err()
+			.to_string(),
+			"Invalid schema: unknown type x"
+		);
+		assert_eq!(
+			FieldSchema::from_json(
+				r#"{"version": 1, "fields": [],
+					"constraints": [{"requires": ["A"]}]}"#
+			)
+			.unwrap_err()
+			.to_string(),
+			"Invalid schema: wrong number of arguments to requires"
+		);
+	}
+}
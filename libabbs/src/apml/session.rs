@@ -0,0 +1,457 @@
+//! Editing sessions applying independent edits to an LST at once.
+//!
+//! Edits collected in an [`EditSession`] are expressed as replacements of
+//! spans of the original source, so they stay valid no matter in which
+//! order they are collected. At [commit][EditSession::commit], the edits
+//! are checked for overlaps and applied in a single pass, and a
+//! [`SpanMap`] is returned for translating spans of the original source.
+//!
+//! [`ApmlLst::edit_matching`] edits many definitions at once through a
+//! session.
+
+use std::fmt::Display;
+
+use thiserror::Error;
+
+use super::{
+	editor::ValueEditor, lst::ApmlLst, parser::ParseError, pattern::NameFilter,
+	span::Span,
+};
+
+/// A replacement of a span of the source.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Edit {
+	/// The replaced span.
+	pub span: Span,
+	/// The text to insert in place of the span.
+	pub replacement: String,
+}
+
+impl Edit {
+	/// Creates an edit.
+	pub fn new<S: Into<String>>(span: Span, replacement: S) -> Self {
+		Self {
+			span,
+			replacement: replacement.into(),
+		}
+	}
+
+	/// Creates an edit removing a span.
+	pub fn remove(span: Span) -> Self {
+		Self::new(span, "")
+	}
+
+	/// Creates an edit inserting a text at an offset.
+	pub fn insert<S: Into<String>>(offset: usize, text: S) -> Self {
+		Self::new(Span::with_len(offset, 0), text)
+	}
+
+	/// Creates the smallest edit turning `old`, which starts at `offset`,
+	/// into `new`.
+	///
+	/// The common prefix and suffix of both texts are excluded from the
+	/// edit, so that edits of different parts of a node do not overlap.
+	pub fn between(offset: usize, old: &str, new: &str) -> Self {
+		let prefix = old
+			.char_indices()
+			.zip(new.chars())
+			.find(|((_, a), b)| a != b)
+			.map_or(old.len().min(new.len()), |((index, _), _)| index);
+		let (old_rest, new_rest) = (&old[prefix..], &new[prefix..]);
+		let suffix = old_rest
+			.char_indices()
+			.rev()
+			.zip(new_rest.chars().rev())
+			.find(|((_, a), b)| a != b)
+			.map_or(old_rest.len().min(new_rest.len()), |((index, ch), _)| {
+				old_rest.len() - index - ch.len_utf8()
+			});
+		Self::new(
+			Span::new(offset + prefix, offset + old.len() - suffix),
+			&new_rest[..new_rest.len() - suffix],
+		)
+	}
+
+	/// Returns if two edits cannot be applied together.
+	///
+	/// Edits conflict if their spans overlap, or if they start at the
+	/// same offset, in which case the order of the inserted texts is
+	/// ambiguous.
+	pub fn conflicts_with(&self, other: &Edit) -> bool {
+		self.span.start == other.span.start
+			|| (self.span.start < other.span.end
+				&& other.span.start < self.span.end)
+	}
+}
+
+/// Two conflicting edits in an [`EditSession`].
+///
+/// Edits are identified by the indexes returned by [`EditSession::push`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Conflict {
+	/// Index of the edit starting first.
+	pub first: usize,
+	/// Span of the edit starting first.
+	pub first_span: Span,
+	/// Index of the other edit.
+	pub second: usize,
+	/// Span of the other edit.
+	pub second_span: Span,
+}
+
+impl Display for Conflict {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"edit {} at {} overlaps edit {} at {}",
+			self.first, self.first_span, self.second, self.second_span
+		)
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum EditError {
+	#[error("Edit {0} at {1} is out of bounds or not on character boundaries")]
+	InvalidSpan(usize, Span),
+	#[error("Conflicting edits: {}", display_conflicts(.0))]
+	Conflict(Vec<Conflict>),
+	#[error(transparent)]
+	Parse(#[from] ParseError),
+}
+
+fn display_conflicts(conflicts: &[Conflict]) -> String {
+	conflicts
+		.iter()
+		.map(Conflict::to_string)
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+/// A set of edits to be applied to an LST at once.
+#[derive(Debug, Clone)]
+pub struct EditSession {
+	src: String,
+	edits: Vec<Edit>,
+}
+
+impl EditSession {
+	/// Starts a session for editing an LST.
+	pub fn new(lst: &ApmlLst) -> Self {
+		Self {
+			src: lst.to_string(),
+			edits: Vec::new(),
+		}
+	}
+
+	/// Returns the source that spans of edits refer to.
+	pub fn source(&self) -> &str {
+		&self.src
+	}
+
+	/// Returns the collected edits.
+	pub fn edits(&self) -> &[Edit] {
+		&self.edits
+	}
+
+	/// Adds an edit and returns its index.
+	pub fn push(&mut self, edit: Edit) -> usize {
+		self.edits.push(edit);
+		self.edits.len() - 1
+	}
+
+	/// Adds edits in order.
+	pub fn extend<I: IntoIterator<Item = Edit>>(&mut self, edits: I) {
+		self.edits.extend(edits);
+	}
+
+	/// Adds an edit replacing the whole token at an index of the LST.
+	///
+	/// Returns the index of the edit, or [`None`] if there is no such
+	/// token.
+	pub fn replace_token<S: Into<String>>(
+		&mut self,
+		lst: &ApmlLst,
+		index: usize,
+		replacement: S,
+	) -> Option<usize> {
+		let (span, _) = lst.token_spans().nth(index)?;
+		Some(self.push(Edit::new(span, replacement)))
+	}
+
+	/// Returns all pairs of conflicting edits.
+	///
+	/// See [`Edit::conflicts_with`].
+	pub fn conflicts(&self) -> Vec<Conflict> {
+		let order = self.order();
+		let mut conflicts = Vec::new();
+		for (i, &first) in order.iter().enumerate() {
+			let a = &self.edits[first];
+			for &second in &order[i + 1..] {
+				let b = &self.edits[second];
+				if !a.conflicts_with(b) {
+					break;
+				}
+				conflicts.push(Conflict {
+					first,
+					first_span: a.span,
+					second,
+					second_span: b.span,
+				});
+			}
+		}
+		conflicts
+	}
+
+	/// Returns indexes of edits sorted by spans.
+	fn order(&self) -> Vec<usize> {
+		let mut order = (0..self.edits.len()).collect::<Vec<_>>();
+		order.sort_by_key(|&index| (self.edits[index].span, index));
+		order
+	}
+
+	/// Applies all edits, returning the new LST and a table for mapping
+	/// spans of the original source to the new one.
+	///
+	/// Fails without applying anything if any edit is invalid or if any
+	/// two edits conflict.
+	pub fn commit(self) -> Result<(ApmlLst<'static>, SpanMap), EditError> {
+		for (index, edit) in self.edits.iter().enumerate() {
+			if edit.span.start > edit.span.end
+				|| self.src.get(edit.span.start..edit.span.end).is_none()
+			{
+				return Err(EditError::InvalidSpan(index, edit.span));
+			}
+		}
+		let conflicts = self.conflicts();
+		if !conflicts.is_empty() {
+			return Err(EditError::Conflict(conflicts));
+		}
+
+		let order = self.order();
+		let mut src = self.src;
+		// applied from the end, so that spans of remaining edits stay valid
+		for &index in order.iter().rev() {
+			let edit = &self.edits[index];
+			src.replace_range(
+				edit.span.start..edit.span.end,
+				&edit.replacement,
+			);
+		}
+		let mut map = Vec::with_capacity(order.len());
+		let mut delta = 0isize;
+		for &index in &order {
+			let edit = &self.edits[index];
+			let start = (edit.span.start as isize + delta) as usize;
+			map.push((
+				edit.span,
+				Span::with_len(start, edit.replacement.len()),
+			));
+			delta += edit.replacement.len() as isize;
+			delta -= edit.span.len() as isize;
+		}
+		let lst = ApmlLst::parse(&src)?.into_owned();
+		Ok((lst, SpanMap(map)))
+	}
+}
+
+impl ApmlLst<'_> {
+	/// Edits values of all definitions whose names are accepted by a
+	/// filter.
+	///
+	/// Each matching definition is handed to `f` along with its name, and
+	/// the changes are collected as [minimal edits][Edit::between] in an
+	/// [`EditSession`], which is then committed. Definitions left
+	/// unchanged produce no edits.
+	///
+	/// ```
+	/// use libabbs::apml::{lst::ApmlLst, pattern::NameFilter};
+	///
+	/// let lst = ApmlLst::parse("PKGDEP=\"a\"\nPKGDEP__AMD64=\"a b\"\n")
+	///     .unwrap();
+	/// let filter = NameFilter::parse("PKGDEP,PKGDEP__*").unwrap();
+	/// let (lst, _) = lst
+	///     .edit_matching(&filter, |_, editor| {
+	///         editor.push_word("c");
+	///     })
+	///     .unwrap();
+	/// assert_eq!(
+	///     lst.to_string(),
+	///     "PKGDEP=\"a c\"\nPKGDEP__AMD64=\"a b c\"\n"
+	/// );
+	/// ```
+	pub fn edit_matching<F>(
+		&self,
+		filter: &NameFilter,
+		mut f: F,
+	) -> Result<(ApmlLst<'static>, SpanMap), EditError>
+	where
+		F: FnMut(&str, &mut ValueEditor<'_, '_>),
+	{
+		let mut session = EditSession::new(self);
+		for (span, def) in self.variable_spans() {
+			if !filter.matches(&def.name) {
+				continue;
+			}
+			let mut changed = def.clone();
+			f(&def.name, &mut ValueEditor::wrap(&mut changed));
+			let (old, new) = (def.to_string(), changed.to_string());
+			if old != new {
+				session.push(Edit::between(span.start, &old, &new));
+			}
+		}
+		session.commit()
+	}
+}
+
+/// A table mapping spans of a source to the source after edits.
+///
+/// Each entry is a pair of the span replaced by an edit and the span of
+/// its replacement, in the order of spans.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SpanMap(pub Vec<(Span, Span)>);
+
+impl SpanMap {
+	/// Maps an offset in the original source.
+	///
+	/// Returns [`None`] if the offset is inside a replaced span. Offsets
+	/// at the edges of a replaced span are mapped to the edges of its
+	/// replacement, and an offset where a text is inserted is kept
+	/// before the inserted text.
+	pub fn map_offset(&self, offset: usize) -> Option<usize> {
+		let mut delta = 0isize;
+		for (old, new) in &self.0 {
+			if offset <= old.start {
+				break;
+			} else if offset < old.end {
+				return None;
+			}
+			delta = new.end as isize - old.end as isize;
+		}
+		offset.checked_add_signed(delta)
+	}
+
+	/// Maps a span in the original source.
+	///
+	/// Returns [`None`] if either edge of the span is inside a replaced
+	/// span. See [`SpanMap::map_offset`].
+	pub fn map_span(&self, span: Span) -> Option<Span> {
+		Some(Span::new(
+			self.map_offset(span.start)?,
+			self.map_offset(span.end)?,
+		))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_between() {
+		assert_eq!(
+			Edit::between(2, "A=$B-x", "A=${B}-x"),
+			Edit::new(Span::new(5, 6), "{B}")
+		);
+		assert_eq!(
+			Edit::between(0, "a b b", "a b"),
+			Edit::new(Span::new(3, 5), "")
+		);
+		assert_eq!(
+			Edit::between(0, "xé", "xè"),
+			Edit::new(Span::new(1, 3), "è")
+		);
+		assert_eq!(
+			Edit::between(0, "ab", "ab"),
+			Edit::new(Span::new(2, 2), "")
+		);
+	}
+
+	#[test]
+	fn test_commit() {
+		let src = "A=1\nB=\"a b\"\nC=(x y)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let mut session = EditSession::new(&lst);
+		assert_eq!(session.source(), src);
+		session.push(Edit::new(Span::new(17, 18), "yz"));
+		session.push(Edit::insert(0, "# c\n"));
+		assert_eq!(session.replace_token(&lst, 2, "B=b"), Some(2));
+		assert_eq!(session.replace_token(&lst, 10, "D=d"), None);
+		let (lst, map) = session.commit().unwrap();
+		assert_eq!(lst.to_string(), "# c\nA=1\nB=b\nC=(x yz)\n");
+		assert_eq!(map.map_offset(0), Some(0));
+		assert_eq!(map.map_offset(1), Some(5));
+		assert_eq!(map.map_span(Span::new(4, 11)), Some(Span::new(8, 11)));
+		assert_eq!(map.map_offset(5), None);
+		assert_eq!(map.map_span(Span::new(12, 19)), Some(Span::new(12, 20)));
+
+		let lst = ApmlLst::parse(src).unwrap();
+		let mut session = EditSession::new(&lst);
+		session.push(Edit::new(Span::new(4, 11), "B=b"));
+		session.push(Edit::remove(Span::new(0, 4)));
+		session.push(Edit::insert(8, "c"));
+		session.push(Edit::insert(4, "# c\n"));
+		let err = session.commit().unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"Conflicting edits: edit 3 at 4..4 overlaps edit 0 at 4..11, \
+			edit 0 at 4..11 overlaps edit 2 at 8..8"
+		);
+		let commit = |edit: Edit| {
+			let mut session = EditSession::new(&lst);
+			session.push(edit);
+			session.commit()
+		};
+		assert!(matches!(
+			commit(Edit::remove(Span::new(30, 31))),
+			Err(EditError::InvalidSpan(0, _))
+		));
+		assert!(matches!(
+			commit(Edit::insert(2, "'")),
+			Err(EditError::Parse(_))
+		));
+	}
+
+	#[test]
+	fn test_edit_matching() {
+		let src = "PKGDEP=\"glibc libbar \\\n    zlib\" # deps\n\
+			PKGDEP__AMD64=(libbar x)\nPKGDEP__ARM64='libbar'\n\
+			PKGDEP__RISCV=\"glibc\"\nPKGDEP__PPC64EL=\"$PKGDEP libbar\"\n\
+			BUILDDEP=\"libbar\"\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let filter = NameFilter::parse("PKGDEP,PKGDEP__*").unwrap();
+		let mut seen = Vec::new();
+		let (edited, map) = lst
+			.edit_matching(&filter, |name, editor| {
+				seen.push(name.to_string());
+				if editor.contains_word("libbar") {
+					assert!(editor.push_word("libfoo"));
+				}
+			})
+			.unwrap();
+		assert_eq!(
+			seen,
+			vec![
+				"PKGDEP",
+				"PKGDEP__AMD64",
+				"PKGDEP__ARM64",
+				"PKGDEP__RISCV",
+				"PKGDEP__PPC64EL"
+			]
+		);
+		assert_eq!(
+			edited.to_string(),
+			"PKGDEP=\"glibc libbar \\\n    zlib libfoo\" # deps\n\
+			PKGDEP__AMD64=(libbar x libfoo)\nPKGDEP__ARM64='libbar libfoo'\n\
+			PKGDEP__RISCV=\"glibc\"\nPKGDEP__PPC64EL=\"$PKGDEP libbar\"\n\
+			BUILDDEP=\"libbar\"\n"
+		);
+		// each edit only inserts the new word
+		let insert = |offset: usize, new: usize| {
+			(Span::with_len(offset, 0), Span::with_len(new, 7))
+		};
+		assert_eq!(
+			map.0,
+			vec![insert(31, 31), insert(63, 70), insert(86, 100)]
+		);
+	}
+}
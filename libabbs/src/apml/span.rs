@@ -0,0 +1,275 @@
+//! Source locations.
+//!
+//! Spans are byte ranges into the APML source text. As the
+//! [LST][super::lst] is lossless, spans can be computed by measuring the
+//! serialized form of each node, see [`ApmlLst::token_spans`].
+//!
+//! Spans point to the physical source text, so nodes in lines continued
+//! with a backslash get the offsets of the lines they are written in.
+//! [`SourceIndex`] converts offsets into lines and columns.
+//!
+//! [`ApmlLst::token_spans`]: super::lst::ApmlLst::token_spans
+
+use core::{
+	fmt::{Display, Write},
+	ops::Range,
+};
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+/// A byte range in the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Span {
+	/// Start offset (inclusive).
+	pub start: usize,
+	/// End offset (exclusive).
+	pub end: usize,
+}
+
+impl Span {
+	/// Creates a span from a start offset and an end offset.
+	pub fn new(start: usize, end: usize) -> Self {
+		Self { start, end }
+	}
+
+	/// Creates a span starting at an offset with the given length.
+	pub fn with_len(start: usize, len: usize) -> Self {
+		Self::new(start, start + len)
+	}
+
+	/// Returns the length of the span in bytes.
+	pub fn len(&self) -> usize {
+		self.end - self.start
+	}
+
+	/// Returns if the span is empty.
+	pub fn is_empty(&self) -> bool {
+		self.start == self.end
+	}
+
+	/// Returns if an offset is covered by the span.
+	///
+	/// The end offset is treated as inclusive, so that a cursor placed
+	/// right after the last character is still considered inside.
+	pub fn contains(&self, offset: usize) -> bool {
+		self.start <= offset && offset <= self.end
+	}
+
+	/// Returns a span moved forward by the given offset.
+	pub fn offset(&self, by: usize) -> Self {
+		Self::new(self.start + by, self.end + by)
+	}
+
+	/// Returns the text covered by the span.
+	pub fn slice<'a>(&self, src: &'a str) -> &'a str {
+		&src[self.start..self.end]
+	}
+}
+
+impl From<Range<usize>> for Span {
+	fn from(value: Range<usize>) -> Self {
+		Self::new(value.start, value.end)
+	}
+}
+
+impl From<Span> for Range<usize> {
+	fn from(value: Span) -> Self {
+		value.start..value.end
+	}
+}
+
+impl Display for Span {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.write_fmt(format_args!("{}..{}", self.start, self.end))
+	}
+}
+
+/// A line and a column in the source text, both starting from 1.
+///
+/// Columns are counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineCol {
+	/// Line number.
+	pub line: usize,
+	/// Column number.
+	pub column: usize,
+}
+
+impl Display for LineCol {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.write_fmt(format_args!("{}:{}", self.line, self.column))
+	}
+}
+
+/// An index of lines of a source text, converting offsets into lines
+/// and columns.
+///
+/// Lines are separated by `\n`. A line continued with a backslash is
+/// still a line of its own, so positions in continued values are
+/// reported in the lines they are written in.
+#[derive(Debug, Clone)]
+pub struct SourceIndex<'a> {
+	src: &'a str,
+	/// Offsets of the start of each line.
+	line_starts: Vec<usize>,
+}
+
+impl<'a> SourceIndex<'a> {
+	/// Indexes a source text.
+	pub fn new(src: &'a str) -> Self {
+		let line_starts = core::iter::once(0)
+			.chain(src.match_indices('\n').map(|(index, _)| index + 1))
+			.collect();
+		Self { src, line_starts }
+	}
+
+	/// Returns the number of lines.
+	///
+	/// A trailing newline starts an empty last line.
+	pub fn line_count(&self) -> usize {
+		self.line_starts.len()
+	}
+
+	/// Converts an offset into a line and a column.
+	///
+	/// Offsets past the end are clamped to the end, and offsets inside
+	/// characters are moved to the start of the characters.
+	pub fn line_col(&self, offset: usize) -> LineCol {
+		let mut offset = offset.min(self.src.len());
+		while !self.src.is_char_boundary(offset) {
+			offset -= 1;
+		}
+		let line = self.line_starts.partition_point(|start| *start <= offset);
+		let start = self.line_starts[line - 1];
+		LineCol {
+			line,
+			column: self.src[start..offset].chars().count() + 1,
+		}
+	}
+
+	/// Converts a span into the lines and columns of its ends.
+	pub fn span(&self, span: Span) -> (LineCol, LineCol) {
+		(self.line_col(span.start), self.line_col(span.end))
+	}
+}
+
+/// Returns the length of the serialized form of a node in bytes.
+pub(crate) fn display_len<T: Display + ?Sized>(node: &T) -> usize {
+	struct Counter(usize);
+
+	impl Write for Counter {
+		fn write_str(&mut self, s: &str) -> core::fmt::Result {
+			self.0 += s.len();
+			Ok(())
+		}
+	}
+
+	let mut counter = Counter(0);
+	write!(counter, "{}", node).expect("counting never fails");
+	counter.0
+}
+
+#[cfg(test)]
+mod test {
+	use crate::apml::{
+		ApmlContext,
+		eval::{EvalOptions, EvalWarningKind},
+		lst::ApmlLst,
+	};
+
+	use super::*;
+
+	#[test]
+	fn test_span() {
+		let span = Span::new(2, 5);
+		assert_eq!(span.len(), 3);
+		assert!(!span.is_empty());
+		assert!(span.contains(2));
+		assert!(span.contains(5));
+		assert!(!span.contains(6));
+		assert_eq!(span.offset(1), Span::from(3..6));
+		assert_eq!(span.slice("abcdefg"), "cde");
+		assert_eq!(span.to_string(), "2..5");
+		assert_eq!(Span::with_len(1, 0), Span::new(1, 1));
+	}
+
+	#[test]
+	fn test_token_spans() {
+		let src = "# c\nA=1\n\tB=(a b)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let spans = lst
+			.token_spans()
+			.map(|(span, _)| span.slice(src))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			spans,
+			vec!["# c", "\n", "A=1", "\n", "\t", "B=(a b)", "\n"]
+		);
+		let vars = lst
+			.variable_spans()
+			.map(|(span, def)| (span.slice(src), def.name.as_ref()))
+			.collect::<Vec<_>>();
+		assert_eq!(vars, vec![("A=1", "A"), ("B=(a b)", "B")]);
+	}
+
+	#[test]
+	fn test_source_index() {
+		let index = SourceIndex::new("ab\nçd\n");
+		assert_eq!(index.line_count(), 3);
+		let pos = |offset| index.line_col(offset).to_string();
+		assert_eq!(pos(0), "1:1");
+		assert_eq!(pos(2), "1:3");
+		assert_eq!(pos(3), "2:1");
+		assert_eq!(pos(4), "2:1");
+		assert_eq!(pos(5), "2:2");
+		assert_eq!(pos(7), "3:1");
+		assert_eq!(pos(100), "3:1");
+		assert_eq!(
+			index.span(Span::new(1, 6)),
+			(
+				LineCol { line: 1, column: 2 },
+				LineCol { line: 2, column: 3 }
+			)
+		);
+	}
+
+	#[test]
+	fn test_continuation_spans() {
+		// elements on the third continuation line
+		let src = "PKGNAME=foo\nPKGDEP=\"a \\\n\tb \\\n\tc \\\n\td-$PKGNAMR\"\n\
+			X=(a \\\n\tb \\\n\tc \\\n\t$PKGNAME-doc)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let index = SourceIndex::new(src);
+
+		let warnings = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+		let mut options = EvalOptions {
+			on_warning: Some(Box::new({
+				let warnings = warnings.clone();
+				move |warning| warnings.borrow_mut().push(warning.clone())
+			})),
+			..Default::default()
+		};
+		ApmlContext::eval_lst_with(&lst, &mut options).unwrap();
+		let warning = warnings.borrow()[0].clone();
+		assert_eq!(warning.kind, EvalWarningKind::PossibleTypo);
+		assert_eq!(warning.span.slice(src), "$PKGNAMR");
+		assert_eq!(index.line_col(warning.span.start).to_string(), "5:4");
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn test_lint_line_col() {
+		let src = "PKGNAME=foo\nPKGDEP=\"a \\\n\tb \\\n\tc \\\n\td-$PKGNAMR\"\n\
+			X=(a \\\n\tb \\\n\tc \\\n\t$PKGNAME-doc)\n";
+		let lst = ApmlLst::parse(src).unwrap();
+		let index = SourceIndex::new(src);
+		let issue = &crate::apml::lint::check_braces(&lst)[0];
+		assert_eq!(issue.expansion_span.slice(src), "$PKGNAME");
+		assert_eq!(
+			index.line_col(issue.expansion_span.start).to_string(),
+			"9:2"
+		);
+		assert_eq!(index.line_col(issue.span.start).to_string(), "6:1");
+	}
+}
@@ -0,0 +1,560 @@
+//! Utilities for source URLs.
+//!
+//! See [`infer_template`], and [`find_duplicate_sources`] for sources
+//! listed more than once.
+
+use std::{
+	collections::BTreeMap,
+	fmt::{Display, Formatter},
+};
+
+use super::{
+	ReadContext, VariableValue,
+	compat::{is_legacy_source_url, numbered_variant},
+	package::{FileTarget, Package},
+	span::Span,
+};
+
+/// Minimum length of values to be substituted by [`infer_template`].
+///
+/// Shorter values, such as `REL=1`, match too many unrelated parts of
+/// URLs.
+pub const MIN_VALUE_LEN: usize = 3;
+
+/// Maximum number of occurrences of a value to produce candidates for
+/// each combination of. Values occurring more often are substituted at
+/// all occurrences.
+const MAX_AMBIGUOUS_OCCURRENCES: usize = 6;
+
+/// Piece of a partially templated URL.
+#[derive(Debug, Clone)]
+enum Piece<'a> {
+	Literal(&'a str),
+	Variable(&'a str),
+}
+
+/// Infers the templated form of a concrete URL, such as
+/// `https://x/${PKGNAME}-${VER}.tar.gz` for `https://x/foo-1.0.tar.gz`.
+///
+/// Returns [`None`] if no variable is found in the URL, or if the
+/// placement is ambiguous. Use [`infer_templates`] to get all candidates
+/// in the latter case.
+pub fn infer_template<C: ReadContext + ?Sized>(
+	concrete_url: &str,
+	context: &C,
+) -> Option<String> {
+	let mut candidates = infer_templates(concrete_url, context);
+	if candidates.len() == 1 {
+		candidates.pop()
+	} else {
+		None
+	}
+}
+
+/// Infers all candidate templated forms of a concrete URL.
+///
+/// Values of string variables in the context are substituted back into
+/// the URL as braced expansions, longest values first. Only values of
+/// at least [`MIN_VALUE_LEN`] characters are considered, and a value
+/// covering the whole URL is never substituted.
+///
+/// A value occurring more than once, or shared by multiple variables, is
+/// ambiguous. In that case, candidates are produced for each combination
+/// of the occurrences and of the variables, starting with substituting
+/// all occurrences. Variables sharing a value are tried in the order of
+/// their names.
+///
+/// Returns an empty vector if no variable is found in the URL.
+pub fn infer_templates<C: ReadContext + ?Sized>(
+	concrete_url: &str,
+	context: &C,
+) -> Vec<String> {
+	let mut names = BTreeMap::<&str, Vec<&str>>::new();
+	for (name, value) in context.iter() {
+		if let VariableValue::String(value) = value
+			&& value.chars().count() >= MIN_VALUE_LEN
+			&& value != concrete_url
+		{
+			names.entry(value).or_default().push(name);
+		}
+	}
+	let mut values = names.into_iter().collect::<Vec<_>>();
+	for (_, names) in &mut values {
+		names.sort();
+	}
+	values.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+
+	let mut templates = Vec::new();
+	substitute(vec![Piece::Literal(concrete_url)], &values, &mut templates);
+	let mut result = Vec::<String>::new();
+	for pieces in templates {
+		if !pieces
+			.iter()
+			.any(|piece| matches!(piece, Piece::Variable(_)))
+		{
+			continue;
+		}
+		let template = render(&pieces);
+		if !result.contains(&template) {
+			result.push(template);
+		}
+	}
+	result
+}
+
+/// Substitutes values into literal pieces, collecting all outcomes.
+fn substitute<'a>(
+	pieces: Vec<Piece<'a>>,
+	values: &[(&'a str, Vec<&'a str>)],
+	out: &mut Vec<Vec<Piece<'a>>>,
+) {
+	let Some(((value, names), rest)) = values.split_first() else {
+		out.push(pieces);
+		return;
+	};
+	// occurrences as (piece index, byte offset)
+	let occurrences = pieces
+		.iter()
+		.enumerate()
+		.flat_map(|(index, piece)| match piece {
+			Piece::Literal(text) => text
+				.match_indices(value)
+				.map(|(offset, _)| (index, offset))
+				.collect::<Vec<_>>(),
+			Piece::Variable(_) => Vec::new(),
+		})
+		.collect::<Vec<_>>();
+	if occurrences.is_empty() {
+		substitute(pieces, rest, out);
+		return;
+	}
+
+	// subsets of occurrences as bit masks, with larger subsets first
+	let mut subsets = if occurrences.len() <= MAX_AMBIGUOUS_OCCURRENCES {
+		(1..1u64 << occurrences.len()).collect::<Vec<_>>()
+	} else {
+		vec![u64::MAX]
+	};
+	subsets.sort_by_key(|subset| std::cmp::Reverse(subset.count_ones()));
+	for subset in subsets {
+		for name in names {
+			let mut result = Vec::with_capacity(pieces.len() + 2);
+			for (index, piece) in pieces.iter().enumerate() {
+				let Piece::Literal(text) = piece else {
+					result.push(piece.clone());
+					continue;
+				};
+				let mut last = 0;
+				for (bit, &(_, offset)) in occurrences
+					.iter()
+					.enumerate()
+					.filter(|(_, (piece, _))| *piece == index)
+				{
+					if bit < 64 && subset & (1 << bit) == 0 {
+						continue;
+					}
+					if offset > last {
+						result.push(Piece::Literal(&text[last..offset]));
+					}
+					result.push(Piece::Variable(name));
+					last = offset + value.len();
+				}
+				if last < text.len() {
+					result.push(Piece::Literal(&text[last..]));
+				}
+			}
+			substitute(result, rest, out);
+		}
+	}
+}
+
+fn render(pieces: &[Piece]) -> String {
+	let mut result = String::new();
+	for piece in pieces {
+		match piece {
+			Piece::Literal(text) => result.push_str(text),
+			Piece::Variable(name) => {
+				result.push_str("${");
+				result.push_str(name);
+				result.push('}');
+			}
+		}
+	}
+	result
+}
+
+/// A source entry in canonical form.
+///
+/// Sources are written as `[fetcher::][options::]url`, where options are
+/// `key=value` pairs separated by `;`. The fetcher defaults to `tbl`.
+/// Sources are equal in canonical form if they fetch the same URL with
+/// the same fetcher and options into the same output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CanonicalSource {
+	/// Fetcher, such as `tbl` or `git`.
+	pub fetcher: String,
+	/// Options other than `rename`, sorted by keys.
+	pub options: BTreeMap<String, String>,
+	/// URL, with the scheme and the host in lowercase, the default port
+	/// of the scheme removed, and an empty path written as `/`.
+	pub url: String,
+	/// Name of the fetched file: the `rename` option if given, or the
+	/// last segment of the path of the URL.
+	pub output: String,
+}
+
+impl CanonicalSource {
+	/// Parses a source entry into canonical form.
+	pub fn parse(source: &str) -> Self {
+		let parts = source.splitn(3, "::").collect::<Vec<_>>();
+		let (fetcher, options, url) = match parts.as_slice() {
+			[url] => ("tbl", "", *url),
+			[fetcher, url] => (*fetcher, "", *url),
+			[fetcher, options, url, ..] => (*fetcher, *options, *url),
+			[] => unreachable!("splitn yields at least one part"),
+		};
+		let mut options = options
+			.split(';')
+			.filter(|option| !option.is_empty())
+			.map(|option| match option.split_once('=') {
+				Some((key, value)) => (key.to_string(), value.to_string()),
+				None => (option.to_string(), String::new()),
+			})
+			.collect::<BTreeMap<_, _>>();
+		let url = canonical_url(url);
+		let output = options
+			.remove("rename")
+			.unwrap_or_else(|| url_file_name(&url).to_string());
+		Self {
+			fetcher: fetcher.to_string(),
+			options,
+			url,
+			output,
+		}
+	}
+}
+
+impl Display for CanonicalSource {
+	/// Formats the source as an entry, with the `rename` option only if
+	/// the output differs from the name in the URL.
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}::", self.fetcher)?;
+		let mut options = self
+			.options
+			.iter()
+			.map(|(key, value)| format!("{}={}", key, value))
+			.collect::<Vec<_>>();
+		if self.output != url_file_name(&self.url) {
+			options.push(format!("rename={}", self.output));
+		}
+		if !options.is_empty() {
+			write!(f, "{}::", options.join(";"))?;
+		}
+		f.write_str(&self.url)
+	}
+}
+
+/// Returns the canonical form of a URL, see [`CanonicalSource::url`].
+///
+/// Values without a scheme are returned as is.
+fn canonical_url(url: &str) -> String {
+	let Some((scheme, rest)) = url.split_once("://") else {
+		return url.to_string();
+	};
+	let scheme = scheme.to_ascii_lowercase();
+	let (authority, path) =
+		rest.split_at(rest.find(['/', '?', '#']).unwrap_or(rest.len()));
+	let (userinfo, host) = match authority.rsplit_once('@') {
+		Some((userinfo, host)) => (Some(userinfo), host),
+		None => (None, authority),
+	};
+	let mut host = host.to_ascii_lowercase();
+	let default_port = match scheme.as_str() {
+		"http" => Some("80"),
+		"https" => Some("443"),
+		"ftp" => Some("21"),
+		_ => None,
+	};
+	if let Some(port) = default_port
+		&& let Some(name) = host.strip_suffix(port)
+		&& let Some(name) = name.strip_suffix(':')
+	{
+		host = name.to_string();
+	}
+	let mut result = format!("{}://", scheme);
+	if let Some(userinfo) = userinfo {
+		result.push_str(userinfo);
+		result.push('@');
+	}
+	result.push_str(&host);
+	if !path.starts_with('/') {
+		result.push('/');
+	}
+	result.push_str(path);
+	result
+}
+
+/// Returns the last segment of the path of a URL.
+fn url_file_name(url: &str) -> &str {
+	let path = &url[..url.find(['?', '#']).unwrap_or(url.len())];
+	path.rsplit('/').next().unwrap_or(path)
+}
+
+/// An occurrence of a source in a package.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceOccurrence {
+	/// Index of the package in the list given to
+	/// [`find_duplicate_sources`].
+	pub package: usize,
+	/// Field listing the source, such as `SRCS`, `SRCS_1` or `SRCTBL`.
+	pub field: String,
+	/// Index of the source in the field.
+	pub index: usize,
+	/// File and span of the definition of the field taking effect last,
+	/// see [`Package::locate_definition`].
+	pub location: Option<(FileTarget, Span)>,
+	/// The source as evaluated.
+	pub source: String,
+}
+
+/// A source listed more than once, as reported by
+/// [`find_duplicate_sources`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DuplicateSource {
+	/// Canonical form of the source.
+	pub canonical: CanonicalSource,
+	/// Occurrences, in the order of packages and of sources.
+	pub occurrences: Vec<SourceOccurrence>,
+}
+
+impl DuplicateSource {
+	/// Lists where the source is listed, by field and index.
+	pub fn explain(&self) -> String {
+		let packages = self
+			.occurrences
+			.iter()
+			.any(|occurrence| occurrence.package != 0);
+		let occurrences = self
+			.occurrences
+			.iter()
+			.map(|occurrence| {
+				let mut text =
+					format!("{}[{}]", occurrence.field, occurrence.index);
+				if packages {
+					text =
+						format!("{} of package {}", text, occurrence.package);
+				}
+				text
+			})
+			.collect::<Vec<_>>();
+		format!(
+			"{} is listed {} times, as {}.",
+			self.canonical,
+			self.occurrences.len(),
+			occurrences.join(", ")
+		)
+	}
+}
+
+/// Finds sources listed more than once in packages.
+///
+/// Sources are taken from `SRCS` of the combined contexts, including
+/// sources folded from legacy fields, and are attributed to the fields
+/// they come from, see
+/// [`normalize_source_fields`](super::ApmlContext::normalize_source_fields).
+/// As contexts are evaluated, variables in sources such as `${VER}` are
+/// already expanded. Sources are compared in [canonical
+/// form][CanonicalSource], so different renames of the same URL are not
+/// duplicates.
+///
+/// Packages sharing a `spec` file, as subpackages do, report sources
+/// defined in it once, for the first package.
+pub fn find_duplicate_sources(packages: &[&Package]) -> Vec<DuplicateSource> {
+	let mut groups = Vec::<DuplicateSource>::new();
+	for (index, package) in packages.iter().enumerate() {
+		let shared_spec = packages[..index].iter().any(|other| {
+			other.lst(FileTarget::Spec) == package.lst(FileTarget::Spec)
+		});
+		for occurrence in source_occurrences(index, package) {
+			if shared_spec
+				&& matches!(occurrence.location, Some((FileTarget::Spec, _)))
+			{
+				continue;
+			}
+			let canonical = CanonicalSource::parse(&occurrence.source);
+			match groups.iter_mut().find(|group| group.canonical == canonical) {
+				Some(group) => group.occurrences.push(occurrence),
+				None => groups.push(DuplicateSource {
+					canonical,
+					occurrences: vec![occurrence],
+				}),
+			}
+		}
+	}
+	groups.retain(|group| group.occurrences.len() > 1);
+	groups
+}
+
+/// Lists the sources of a package with the fields they come from.
+fn source_occurrences(
+	index: usize,
+	package: &Package,
+) -> Vec<SourceOccurrence> {
+	let sources = package
+		.context()
+		.get("SRCS")
+		.map(VariableValue::as_array)
+		.unwrap_or_default();
+	let legacy = package
+		.legacy_sources()
+		.filter(|(_, _, field)| !field.ignored)
+		.map(|(_, _, field)| field)
+		.collect::<Vec<_>>();
+	let numbered = legacy
+		.iter()
+		.filter(|field| numbered_variant(&field.name, "SRCS").is_some())
+		.map(|field| (field.name.as_str(), field.value.as_array().len()))
+		.collect::<Vec<_>>();
+	let numbered_len = numbered.iter().map(|(_, len)| len).sum::<usize>();
+	let mut fields = Vec::new();
+	match legacy
+		.iter()
+		.find(|field| is_legacy_source_url(&field.name))
+	{
+		Some(field) => fields.push((field.name.as_str(), 1)),
+		None => {
+			fields.push(("SRCS", sources.len().saturating_sub(numbered_len)))
+		}
+	}
+	fields.extend(numbered);
+
+	let mut sources = sources.into_iter();
+	let mut result = Vec::new();
+	for (field, len) in fields {
+		let location = package.locate_definition(field);
+		for (offset, source) in sources.by_ref().take(len).enumerate() {
+			result.push(SourceOccurrence {
+				package: index,
+				field: field.to_string(),
+				index: offset,
+				location,
+				source,
+			});
+		}
+	}
+	result
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::ApmlContext;
+
+	#[test]
+	fn test_infer_template() {
+		let context = ApmlContext::eval_source(
+			"PKGNAME=foo\nVER=1.2\nREL=1\nPKGVER=1.2.3\n\
+			SRCS=\"tbl::https://x/foo-1.2.3.tar.gz\"\n",
+		)
+		.unwrap();
+		assert_eq!(
+			infer_template("https://x/foo-1.2.3.tar.gz", &context).as_deref(),
+			Some("https://x/${PKGNAME}-${PKGVER}.tar.gz")
+		);
+		assert_eq!(infer_template("https://y/bar-1.tar.gz", &context), None);
+		assert!(infer_templates("https://y/bar-1.tar.gz", &context).is_empty());
+		assert_eq!(
+			infer_templates("https://x/v1.2/foo-1.2.tar.gz", &context),
+			vec![
+				"https://x/v${VER}/${PKGNAME}-${VER}.tar.gz",
+				"https://x/v${VER}/${PKGNAME}-1.2.tar.gz",
+				"https://x/v1.2/${PKGNAME}-${VER}.tar.gz",
+			]
+		);
+		assert_eq!(
+			infer_template("https://x/v1.2/foo-1.2.tar.gz", &context),
+			None
+		);
+
+		let context = ApmlContext::eval_source("VER=2.0\nMAJOR=2.0\n").unwrap();
+		assert_eq!(
+			infer_templates("https://x/2.0.tar.gz", &context),
+			vec!["https://x/${MAJOR}.tar.gz", "https://x/${VER}.tar.gz"]
+		);
+	}
+
+	#[test]
+	fn test_canonical_source() {
+		let source = |src: &str| CanonicalSource::parse(src);
+		assert_eq!(
+			source("tbl::HTTPS://Example.COM:443/Foo.tar.gz"),
+			source("https://example.com/Foo.tar.gz")
+		);
+		assert_ne!(
+			source("tbl::https://x/Foo.tar.gz"),
+			source("tbl::https://x/foo.tar.gz")
+		);
+		assert_eq!(
+			source("git::commit=v1;copy-repo=true::https://a@X:8080")
+				.to_string(),
+			"git::commit=v1;copy-repo=true::https://a@x:8080/"
+		);
+		assert_eq!(
+			source("tbl::rename=f.tar::https://x/f.tar"),
+			source("tbl::https://x/f.tar")
+		);
+		let renamed = source("tbl::rename=a.tar::https://x/f.tar?x=1");
+		assert_eq!(renamed.output, "a.tar");
+		assert_eq!(
+			renamed.to_string(),
+			"tbl::rename=a.tar::https://x/f.tar?x=1"
+		);
+		assert_ne!(renamed, source("tbl::rename=b.tar::https://x/f.tar?x=1"));
+	}
+
+	#[test]
+	fn test_find_duplicate_sources() {
+		let spec = "VER=1.0\n\
+			SRCS=\"tbl::https://x/foo-$VER.tar tbl::rename=a::https://y/z\"\n\
+			SRCS_1=\"tbl::rename=b::https://y/z tbl::HTTPS://X:443/foo-1.0.tar\"\n";
+		let package = Package::parse(spec, "").unwrap();
+		let duplicates = find_duplicate_sources(&[&package]);
+		assert_eq!(duplicates.len(), 1);
+		let duplicate = &duplicates[0];
+		assert_eq!(
+			duplicate
+				.occurrences
+				.iter()
+				.map(|occurrence| (
+					occurrence.field.as_str(),
+					occurrence.index,
+					occurrence
+						.location
+						.map(|(target, span)| (target, span.start))
+				))
+				.collect::<Vec<_>>(),
+			vec![
+				("SRCS", 0, Some((FileTarget::Spec, 8))),
+				("SRCS_1", 1, Some((FileTarget::Spec, 70)))
+			]
+		);
+		assert_eq!(
+			duplicate.explain(),
+			"tbl::https://x/foo-1.0.tar is listed 2 times, as SRCS[0], \
+			SRCS_1[1]."
+		);
+
+		// subpackages share the spec
+		let host = Package::parse(spec, "SRCTBL=https://y/w\n").unwrap();
+		let guest =
+			Package::parse("SRCTBL=https://y/w\n", "SRCS_2=https://y/w\n")
+				.unwrap();
+		let duplicates = find_duplicate_sources(&[&package, &host, &guest]);
+		assert_eq!(duplicates.len(), 2);
+		assert_eq!(
+			duplicates[1].explain(),
+			"tbl::https://y/w is listed 2 times, as SRCTBL[0] of package 2, \
+			SRCS_2[0] of package 2."
+		);
+	}
+}
@@ -0,0 +1,93 @@
+//! Suggestions for misspelled names.
+//!
+//! See [`did_you_mean`].
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+/// Minimum length of names to suggest candidates within edit distance 1.
+pub const MIN_EDIT_LEN: usize = 3;
+
+/// Finds the candidate most likely meant by a misspelled name.
+///
+/// Candidates are ranked by, in order:
+///
+/// 1. matching the name case-insensitively, such as `PKGVER` for
+///    `pkgver`,
+/// 2. being within edit distance 1 of the name,
+/// 3. being within edit distance 1 of the name case-insensitively.
+///
+/// Edit distances are only considered for names of at least
+/// [`MIN_EDIT_LEN`] characters, as short names are similar to too many
+/// others. Candidates equal to the name are never suggested. Among
+/// candidates with the same rank, the lexicographically smallest one is
+/// returned.
+pub fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+	I: IntoIterator<Item = &'a str>,
+{
+	let lowercase = name.to_ascii_lowercase();
+	let by_edits = name.chars().count() >= MIN_EDIT_LEN;
+	candidates
+		.into_iter()
+		.filter(|candidate| *candidate != name)
+		.filter_map(|candidate| {
+			let rank = if candidate.eq_ignore_ascii_case(name) {
+				0
+			} else if !by_edits {
+				return None;
+			} else if within_one_edit(name, candidate) {
+				1
+			} else if within_one_edit(
+				&lowercase,
+				&candidate.to_ascii_lowercase(),
+			) {
+				2
+			} else {
+				return None;
+			};
+			Some((rank, candidate))
+		})
+		.min()
+		.map(|(_, candidate)| candidate)
+}
+
+/// Returns if two strings differ by at most one insertion, deletion or
+/// substitution of a character.
+fn within_one_edit(a: &str, b: &str) -> bool {
+	let a = a.chars().collect::<Vec<_>>();
+	let b = b.chars().collect::<Vec<_>>();
+	let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+	if long.len() - short.len() > 1 {
+		return false;
+	}
+	let prefix = short.iter().zip(&long).take_while(|(a, b)| a == b).count();
+	if short.len() == long.len() {
+		short[prefix..].len() <= 1 || short[prefix + 1..] == long[prefix + 1..]
+	} else {
+		short[prefix..] == long[prefix + 1..]
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_did_you_mean() {
+		let names = ["PKGVER", "PKGDEP", "PKGDES", "pkgver2", "VER"];
+		let suggest = |name| did_you_mean(name, names.iter().copied());
+		assert_eq!(suggest("pkgver"), Some("PKGVER"));
+		assert_eq!(suggest("PKGVR"), Some("PKGVER"));
+		assert_eq!(suggest("PKGDEX"), Some("PKGDEP"));
+		assert_eq!(suggest("pkgdep"), Some("PKGDEP"));
+		assert_eq!(suggest("pkgdepp"), Some("PKGDEP"));
+		assert_eq!(suggest("PKGVER"), Some("pkgver2"));
+		assert_eq!(suggest("VER"), None);
+		assert_eq!(suggest("FOO"), None);
+		assert_eq!(did_you_mean("A", ["B", "a"]), Some("a"));
+		assert_eq!(did_you_mean("A", ["B"]), None);
+		assert!(within_one_edit("", "a"));
+		assert!(!within_one_edit("ab", "ba"));
+	}
+}
@@ -1,12 +1,13 @@
 //! String-like arrays
 
-use std::{
-	ops::{Deref, DerefMut},
-	sync::Arc,
-};
+use alloc::sync::Arc;
+use core::ops::{Deref, DerefMut};
 
 use crate::apml::lst;
 
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
 /// A array-like string delimited with spaces.
 #[derive(Debug, Clone)]
 pub struct StringArray(Vec<String>);
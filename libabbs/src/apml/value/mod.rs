@@ -5,4 +5,5 @@
 //! - [Union][union::Union]: `git::commit=xxx::schema://xxx`
 
 pub mod array;
+#[cfg(feature = "std")]
 pub mod union;
@@ -0,0 +1,263 @@
+//! Versions of packages and rules of version bumps.
+//!
+//! [`PackageVersion`] reads the version fields of a package with their
+//! values checked, and [`check_bump`] checks an upgrade of a package
+//! against the release policy:
+//!
+//! - `PKGEPOCH` must not decrease.
+//! - If `VER` changes, `REL` must reset to 0.
+//! - If `VER` is unchanged, `REL` must increase by exactly 1.
+
+use std::fmt::{Display, Formatter};
+
+use thiserror::Error;
+
+use super::{ReadContext, package::Package};
+
+/// Errors produced while reading version fields.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum VersionError {
+	#[error("VER is not defined")]
+	MissingVersion,
+	#[error("{0} must be a non-negative integer, got {1:?}")]
+	NotAnInteger(&'static str, String),
+}
+
+/// Errors produced by [`check_bump`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BumpError {
+	#[error(transparent)]
+	Version(#[from] VersionError),
+	#[error("Version is not changed")]
+	NotBumped,
+	#[error("PKGEPOCH decreased from {old} to {new}")]
+	EpochDecreased { old: u64, new: u64 },
+	#[error("REL must reset to 0 when VER changes, got {old} -> {new}")]
+	RelDidNotReset { old: u64, new: u64 },
+	#[error("REL must increase by 1 when VER is unchanged, got {old} -> {new}")]
+	RelSkipped { old: u64, new: u64 },
+	#[error("REL decreased from {old} to {new} while VER is unchanged")]
+	RelDecreased { old: u64, new: u64 },
+}
+
+/// Version of a package.
+///
+/// It is formatted as in package names, `[epoch:]version[-release]`,
+/// where the epoch and the release are omitted if they are zero.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PackageVersion {
+	/// Epoch (`PKGEPOCH`), 0 if not defined.
+	pub epoch: u64,
+	/// Upstream version (`VER`).
+	pub version: String,
+	/// Release (`REL`), 0 if not defined.
+	pub release: u64,
+}
+
+impl PackageVersion {
+	/// Reads the version fields of a context.
+	///
+	/// `PKGVER` and `PKGREL` are read if `VER` and `REL` are not
+	/// defined, see [`VersionSpelling`][super::compat::VersionSpelling].
+	pub fn of<C: ReadContext + ?Sized>(
+		context: &C,
+	) -> Result<Self, VersionError> {
+		let read = |names: &[&str]| {
+			names
+				.iter()
+				.find_map(|name| context.get(name))
+				.map(|value| value.as_string())
+		};
+		let integer = |name: &'static str, names: &[&str]| match read(names) {
+			None => Ok(0),
+			Some(value) if is_integer(&value) => value
+				.parse()
+				.map_err(|_| VersionError::NotAnInteger(name, value)),
+			Some(value) => Err(VersionError::NotAnInteger(name, value)),
+		};
+		Ok(Self {
+			epoch: integer("PKGEPOCH", &["PKGEPOCH"])?,
+			version: read(&["VER", "PKGVER"])
+				.ok_or(VersionError::MissingVersion)?,
+			release: integer("REL", &["REL", "PKGREL"])?,
+		})
+	}
+}
+
+/// Returns if a value is written as a non-negative decimal integer.
+fn is_integer(value: &str) -> bool {
+	!value.is_empty() && value.bytes().all(|ch| ch.is_ascii_digit())
+}
+
+impl Display for PackageVersion {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		if self.epoch != 0 {
+			write!(f, "{}:", self.epoch)?;
+		}
+		f.write_str(&self.version)?;
+		if self.release != 0 {
+			write!(f, "-{}", self.release)?;
+		}
+		Ok(())
+	}
+}
+
+/// Kind of a valid version bump, as reported by [`check_bump`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BumpKind {
+	/// The epoch is increased, usually for a version going backwards.
+	Epoch {
+		old: PackageVersion,
+		new: PackageVersion,
+	},
+	/// `VER` is changed and `REL` is reset.
+	Version { old: String, new: String },
+	/// `REL` is increased by 1, with `VER` unchanged.
+	Release { old: u64, new: u64 },
+}
+
+/// Checks an upgrade of a package against the release policy.
+///
+/// See [the module documentation][self] for the rules. The rules of
+/// `REL` apply to epoch bumps as well, except that `REL` may also stay
+/// unchanged. Versions are not compared, so a decreasing `VER` without
+/// an epoch bump is not detected.
+pub fn check_bump(old: &Package, new: &Package) -> Result<BumpKind, BumpError> {
+	check_versions(
+		PackageVersion::of(old.context())?,
+		PackageVersion::of(new.context())?,
+	)
+}
+
+fn check_versions(
+	old: PackageVersion,
+	new: PackageVersion,
+) -> Result<BumpKind, BumpError> {
+	if new.epoch < old.epoch {
+		return Err(BumpError::EpochDecreased {
+			old: old.epoch,
+			new: new.epoch,
+		});
+	}
+	let (old_rel, new_rel) = (old.release, new.release);
+	if old.version != new.version {
+		if new_rel != 0 {
+			return Err(BumpError::RelDidNotReset {
+				old: old_rel,
+				new: new_rel,
+			});
+		}
+	} else if new_rel == old_rel && new.epoch == old.epoch {
+		return Err(BumpError::NotBumped);
+	} else if new_rel < old_rel {
+		return Err(BumpError::RelDecreased {
+			old: old_rel,
+			new: new_rel,
+		});
+	} else if old_rel.checked_add(1).is_some_and(|next| new_rel > next) {
+		return Err(BumpError::RelSkipped {
+			old: old_rel,
+			new: new_rel,
+		});
+	}
+	Ok(if new.epoch > old.epoch {
+		BumpKind::Epoch { old, new }
+	} else if old.version != new.version {
+		BumpKind::Version {
+			old: old.version,
+			new: new.version,
+		}
+	} else {
+		BumpKind::Release {
+			old: old_rel,
+			new: new_rel,
+		}
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn check(old: &str, new: &str) -> Result<BumpKind, BumpError> {
+		check_bump(
+			&Package::parse(old, "").unwrap(),
+			&Package::parse(new, "").unwrap(),
+		)
+	}
+
+	#[test]
+	fn test_package_version() {
+		let package =
+			Package::parse("VER=1.0\nREL=2\n", "PKGEPOCH=1\n").unwrap();
+		assert_eq!(package.version().unwrap().to_string(), "1:1.0-2");
+		let package = Package::parse("PKGVER=1.0\n", "").unwrap();
+		assert_eq!(
+			PackageVersion::of(package.context()).unwrap().to_string(),
+			"1.0"
+		);
+		let package = Package::parse("VER=1.0\nREL=+1\n", "").unwrap();
+		assert_eq!(
+			PackageVersion::of(package.context()),
+			Err(VersionError::NotAnInteger("REL", "+1".to_string()))
+		);
+		let package = Package::parse("REL=1\n", "").unwrap();
+		assert_eq!(
+			PackageVersion::of(package.context()),
+			Err(VersionError::MissingVersion)
+		);
+	}
+
+	#[test]
+	fn test_check_bump() {
+		assert_eq!(
+			check("VER=1.0\nREL=1\n", "VER=1.1\n"),
+			Ok(BumpKind::Version {
+				old: "1.0".to_string(),
+				new: "1.1".to_string()
+			})
+		);
+		assert_eq!(
+			check("VER=1.0\n", "VER=1.0\nREL=1\n"),
+			Ok(BumpKind::Release { old: 0, new: 1 })
+		);
+		assert!(matches!(
+			check("VER=1.1\nPKGEPOCH=1\n", "VER=1.0\nPKGEPOCH=2\n"),
+			Ok(BumpKind::Epoch { old, new })
+				if old.epoch == 1 && new.to_string() == "2:1.0"
+		));
+		assert_eq!(
+			check("VER=1.0\nREL=1\n", "VER=1.1\nREL=2\n"),
+			Err(BumpError::RelDidNotReset { old: 1, new: 2 })
+		);
+		assert_eq!(
+			check("VER=1.0\nREL=1\n", "VER=1.0\nREL=3\n"),
+			Err(BumpError::RelSkipped { old: 1, new: 3 })
+		);
+		assert_eq!(
+			check("VER=1.0\nREL=1\n", "VER=1.0\n"),
+			Err(BumpError::RelDecreased { old: 1, new: 0 })
+		);
+		assert_eq!(
+			check("VER=1.0\nREL=1\n", "VER=1.0\nREL=1\n"),
+			Err(BumpError::NotBumped)
+		);
+		let max = format!("VER=1.0\nREL={}\n", u64::MAX);
+		assert!(matches!(
+			check(&max, &format!("{}PKGEPOCH=1\n", max)),
+			Ok(BumpKind::Epoch { old, new })
+				if old.release == u64::MAX && new.release == u64::MAX
+		));
+		assert_eq!(
+			check("VER=1.0\nPKGEPOCH=1\n", "VER=1.1\n"),
+			Err(BumpError::EpochDecreased { old: 1, new: 0 })
+		);
+		assert_eq!(
+			check("VER=1.0\n", "VER=1.1\nREL=x\n"),
+			Err(BumpError::Version(VersionError::NotAnInteger(
+				"REL",
+				"x".to_string()
+			)))
+		);
+	}
+}
@@ -0,0 +1,350 @@
+//! Wrapping of long definitions.
+//!
+//! See [`wrap_single_field`].
+
+use std::{borrow::Cow, sync::Arc};
+
+use thiserror::Error;
+
+use super::{
+	lst::{
+		self, ApmlLst, ArrayToken, LiteralPart, TextUnit, VariableValue, Word,
+	},
+	span::display_len,
+};
+
+/// Errors produced by [`wrap_single_field`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum WrapError {
+	#[error("{0} is not written in a single single- or double-quoted part")]
+	NotQuoted(String),
+}
+
+/// A character or an unbreakable part of a string value.
+#[derive(Debug, Clone)]
+enum Atom<'a> {
+	Char(char),
+	Escaped(char),
+	LineContinuation,
+	Word(Word<'a>),
+}
+
+impl Atom<'_> {
+	fn is_space(&self) -> bool {
+		matches!(self, Atom::Char(' ' | '\t'))
+	}
+
+	fn is_line_break(&self) -> bool {
+		matches!(self, Atom::Char('\n') | Atom::LineContinuation)
+	}
+
+	/// Returns the width of the atom in a line.
+	fn width(&self) -> usize {
+		match self {
+			Atom::Char(_) => 1,
+			Atom::Escaped(_) => 2,
+			Atom::LineContinuation => 0,
+			Atom::Word(word) => word.to_string().chars().count(),
+		}
+	}
+}
+
+/// Wraps long lines of all definitions of a field.
+///
+/// Definitions with lines longer than `width` characters are wrapped,
+/// and other tokens of the LST are left untouched:
+///
+/// - Quoted strings are broken at the spaces where the next entry would
+///   exceed the width, by a line continuation after the spaces. The next
+///   line is not indented, as the indentation would be a part of the
+///   value. Single-quoted strings, where line continuations are not
+///   recognized, are converted to double-quoted ones.
+/// - Arrays are written with one element per line, indented by a tab.
+///   Comments are kept on their own lines.
+///
+/// The evaluated value is kept for strings, while only the separators
+/// between elements may change for arrays. Expansions and escaped
+/// characters are never broken, so lines may still exceed the width
+/// with long entries.
+///
+/// Returns if the LST is changed. On errors, the LST is left unchanged.
+pub fn wrap_single_field(
+	lst: &mut ApmlLst,
+	name: &str,
+	width: usize,
+) -> Result<bool, WrapError> {
+	let src = lst.to_string();
+	let mut wrapped = Vec::new();
+	for (index, (span, token)) in lst.token_spans().enumerate() {
+		let lst::Token::Variable(def) = token else {
+			continue;
+		};
+		if def.name != name {
+			continue;
+		}
+		let line_start = src[..span.start].rfind('\n').map_or(0, |pos| pos + 1);
+		let column = src[line_start..span.start].chars().count();
+		let too_long =
+			span.slice(&src)
+				.split('\n')
+				.enumerate()
+				.any(|(line, text)| {
+					let start = if line == 0 { column } else { 0 };
+					start + text.chars().count() > width
+				});
+		if !too_long {
+			continue;
+		}
+		let value_column =
+			column + def.name.chars().count() + display_len(&def.op);
+		let value = match &def.value {
+			VariableValue::String(text) => VariableValue::String(Arc::new(
+				wrap_text(text, value_column, width)
+					.ok_or_else(|| WrapError::NotQuoted(name.into()))?,
+			)),
+			VariableValue::Array(tokens) => {
+				VariableValue::Array(wrap_array(tokens))
+			}
+		};
+		if value != def.value {
+			wrapped.push((index, value));
+		}
+	}
+	let changed = !wrapped.is_empty();
+	for (index, value) in wrapped {
+		if let lst::Token::Variable(def) = &mut lst.0[index] {
+			def.value = value;
+		}
+	}
+	Ok(changed)
+}
+
+/// Wraps a string value starting at a column.
+///
+/// Returns [`None`] if the value is not a single quoted unit.
+fn wrap_text<'a>(
+	text: &lst::Text<'a>,
+	column: usize,
+	width: usize,
+) -> Option<lst::Text<'a>> {
+	let unit = match text.0.as_slice() {
+		[TextUnit::SingleQuote(text)] => {
+			let words = vec![Word::Literal(LiteralPart::escape(text))];
+			let wrapped = wrap_words(&words, column + 1, width);
+			if wrapped == words {
+				// keep the quotes if no line is broken
+				return Some(lst::Text(vec![TextUnit::SingleQuote(
+					text.clone(),
+				)]));
+			}
+			TextUnit::DoubleQuote(wrapped)
+		}
+		[TextUnit::DoubleQuote(words)] => {
+			TextUnit::DoubleQuote(wrap_words(words, column + 1, width))
+		}
+		[TextUnit::LocaleQuote(words)] => {
+			TextUnit::LocaleQuote(wrap_words(words, column + 2, width))
+		}
+		_ => return None,
+	};
+	Some(lst::Text(vec![unit]))
+}
+
+/// Wraps double-quoted words, whose first line starts at `indent`.
+fn wrap_words<'a>(
+	words: &[Word<'a>],
+	indent: usize,
+	width: usize,
+) -> Vec<Word<'a>> {
+	let mut atoms = Vec::new();
+	for word in words {
+		let Word::Literal(parts) = word else {
+			atoms.push(Atom::Word(word.clone()));
+			continue;
+		};
+		for part in parts {
+			match part {
+				LiteralPart::String(text) => {
+					atoms.extend(text.chars().map(Atom::Char))
+				}
+				LiteralPart::Escaped(ch) => atoms.push(Atom::Escaped(*ch)),
+				LiteralPart::LineContinuation => {
+					atoms.push(Atom::LineContinuation)
+				}
+			}
+		}
+	}
+	words_of(wrap_atoms(atoms, indent, width))
+}
+
+/// Inserts line continuations after spaces where the next entry would
+/// exceed the width.
+///
+/// The first line starts at `indent`, and a closing quote follows the
+/// last atom.
+fn wrap_atoms(atoms: Vec<Atom>, indent: usize, width: usize) -> Vec<Atom> {
+	let mut result = Vec::with_capacity(atoms.len());
+	let mut column = indent;
+	let mut line_start = indent;
+	let mut index = 0;
+	while index < atoms.len() {
+		if !atoms[index].is_space() {
+			let atom = &atoms[index];
+			if atom.is_line_break() {
+				column = 0;
+				line_start = 0;
+			} else {
+				column += atom.width();
+			}
+			result.push(atom.clone());
+			index += 1;
+			continue;
+		}
+		let spaces = atoms[index..]
+			.iter()
+			.position(|atom| !atom.is_space())
+			.map_or(atoms.len(), |len| index + len);
+		let entry = atoms[spaces..]
+			.iter()
+			.position(|atom| atom.is_space() || atom.is_line_break())
+			.map_or(atoms.len(), |len| spaces + len);
+		let mut entry_width =
+			atoms[spaces..entry].iter().map(Atom::width).sum::<usize>();
+		if entry == atoms.len() {
+			entry_width += 1;
+		}
+		let fits = column + (spaces - index) + entry_width <= width;
+		result.extend_from_slice(&atoms[index..spaces]);
+		if spaces == entry || fits || column <= line_start {
+			column += spaces - index;
+		} else {
+			result.push(Atom::LineContinuation);
+			column = 0;
+			line_start = 0;
+		}
+		index = spaces;
+	}
+	result
+}
+
+/// Converts atoms back into words, joining adjacent literal parts.
+fn words_of(atoms: Vec<Atom>) -> Vec<Word> {
+	let mut words = Vec::new();
+	for atom in atoms {
+		let part = match atom {
+			Atom::Word(word) => {
+				words.push(word);
+				continue;
+			}
+			Atom::Char(ch) => {
+				if let Some(Word::Literal(parts)) = words.last_mut()
+					&& let Some(LiteralPart::String(text)) = parts.last_mut()
+				{
+					text.to_mut().push(ch);
+					continue;
+				}
+				LiteralPart::String(Cow::Owned(ch.to_string()))
+			}
+			Atom::Escaped(ch) => LiteralPart::Escaped(ch),
+			Atom::LineContinuation => LiteralPart::LineContinuation,
+		};
+		match words.last_mut() {
+			Some(Word::Literal(parts)) => parts.push(part),
+			_ => words.push(Word::Literal(vec![part])),
+		}
+	}
+	words
+}
+
+/// Lays out an array with one element or comment per line.
+fn wrap_array<'a>(tokens: &[ArrayToken<'a>]) -> Vec<ArrayToken<'a>> {
+	let mut result = Vec::new();
+	for token in tokens {
+		if matches!(token, ArrayToken::Element(_) | ArrayToken::Comment(_)) {
+			result.extend([ArrayToken::Newline, ArrayToken::Spacy('\t')]);
+			result.push(token.clone());
+		}
+	}
+	result.push(ArrayToken::Newline);
+	result
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::apml::ApmlContext;
+
+	/// Wraps a field, checking that only its definitions are changed and
+	/// that the evaluated values are kept.
+	fn wrap(src: &str, name: &str, width: usize) -> String {
+		let mut lst = ApmlLst::parse(src).unwrap();
+		let spans = lst
+			.variable_spans()
+			.filter(|(_, def)| def.name == name)
+			.map(|(span, _)| span)
+			.collect::<Vec<_>>();
+		wrap_single_field(&mut lst, name, width).unwrap();
+		let result = lst.to_string();
+		if let (Some(first), Some(last)) = (spans.first(), spans.last()) {
+			let suffix = src.len() - last.end;
+			assert_eq!(src[..first.start], result[..first.start]);
+			assert_eq!(src[last.end..], result[result.len() - suffix..]);
+		}
+		assert_eq!(
+			ApmlContext::eval_source(src).unwrap(),
+			ApmlContext::eval_source(&result).unwrap()
+		);
+		result
+	}
+
+	#[test]
+	fn test_wrap_string() {
+		let src = "# deps\nPKGNAME=a  \n\
+			PKGDEP=\"alpha beta gamma-1.0 delta ${X} epsilon\" # x\nA=1\n";
+		assert_eq!(
+			wrap(src, "PKGDEP", 24),
+			"# deps\nPKGNAME=a  \nPKGDEP=\"alpha beta \\\n\
+			gamma-1.0 delta ${X} \\\nepsilon\" # x\nA=1\n"
+		);
+		assert_eq!(wrap(src, "PKGDEP", 80), src);
+		let wrapped = wrap(src, "PKGDEP", 24);
+		assert_eq!(wrap(&wrapped, "PKGDEP", 24), wrapped);
+
+		// single quotes are converted, as they keep line continuations
+		let src = "  PKGDEP+='alpha beta gamma'\n";
+		assert_eq!(
+			wrap(src, "PKGDEP", 20),
+			"  PKGDEP+=\"alpha \\\nbeta gamma\"\n"
+		);
+		let src = "A='$a \"b\" \\c'\n";
+		assert_eq!(wrap(src, "A", 8), "A=\"\\$a \\\n\\\"b\\\" \\\n\\\\c\"\n");
+		let src = "A='$a'\n";
+		assert_eq!(wrap(src, "A", 4), src);
+
+		// existing lines are kept
+		let src = "A=\"alpha beta \\\n  gamma delta\"\n";
+		assert_eq!(
+			wrap(src, "A", 13),
+			"A=\"alpha beta \\\n  gamma \\\ndelta\"\n"
+		);
+
+		let mut lst = ApmlLst::parse("A=a\"b c\"\n").unwrap();
+		assert_eq!(
+			wrap_single_field(&mut lst, "A", 4),
+			Err(WrapError::NotQuoted("A".into()))
+		);
+		assert_eq!(lst.to_string(), "A=a\"b c\"\n");
+	}
+
+	#[test]
+	fn test_wrap_array() {
+		let src = "A=1\nPKGDEP=(alpha \"beta gamma\" ${X[@]})\nB=2\n";
+		assert_eq!(
+			wrap(src, "PKGDEP", 20),
+			"A=1\nPKGDEP=(\n\talpha\n\t\"beta gamma\"\n\t${X[@]}\n)\nB=2\n"
+		);
+		let src = "PKGDEP=(a # x\n  b)\n";
+		assert_eq!(wrap(src, "PKGDEP", 20), src);
+		assert_eq!(wrap(src, "PKGDEP", 8), "PKGDEP=(\n\ta\n\t# x\n\tb\n)\n");
+	}
+}
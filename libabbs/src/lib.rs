@@ -1,8 +1,34 @@
 //! libabbs is a utilities library for AOSC OS packaging scripts maintenance
 //! tasks.
+//!
+//! The `std` feature is enabled by default. Without it, the crate builds
+//! with `#![no_std]` and `alloc`, and the `apml` feature provides the
+//! core of APML only: parsing into [LSTs][apml::lst], emitting
+//! [ASTs][apml::ast], [evaluation][apml::eval] and
+//! [values][apml::VariableValue]. See `tests/no_std.rs` for the API
+//! available in that mode.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+/// Items of the standard prelude which are not in the prelude of `core`.
+#[cfg(not(feature = "std"))]
+mod prelude {
+	pub use alloc::{
+		borrow::ToOwned,
+		boxed::Box,
+		format,
+		string::{String, ToString},
+		vec,
+		vec::Vec,
+	};
+}
 
 #[cfg(feature = "apml")]
 pub mod apml;
+#[cfg(all(feature = "apml", any(test, feature = "testing")))]
+pub mod testing;
 #[cfg(feature = "tree")]
 pub mod tree;
 
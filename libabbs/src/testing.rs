@@ -0,0 +1,336 @@
+//! Helpers for tests built on evaluated contexts.
+//!
+//! [`assert_context_eq!`] compares two contexts and reports differences
+//! per variable, and [`assert_evals_to!`] checks variables of a source
+//! after evaluation. [`render_context`] produces a stable text of a
+//! context, suitable for snapshots.
+//!
+//! This module is enabled with the `testing` feature.
+//!
+//! [`assert_context_eq!`]: crate::assert_context_eq
+//! [`assert_evals_to!`]: crate::assert_evals_to
+
+use std::{
+	collections::BTreeSet,
+	fmt::{Arguments, Display, Write},
+};
+
+use crate::apml::{ApmlContext, VariableValue};
+
+/// Name used for positional parameters in diffs.
+pub const POSITIONAL_PARAMS: &str = "$@";
+
+/// Renders a context into text.
+///
+/// Each variable is written in a line as `NAME = "value"` or
+/// `NAME = ["a", "b"]`, with values escaped as Rust strings, so values
+/// containing newlines still take a single line. Variables are sorted by
+/// name, and positional parameters are written last as `$@ = [...]` if
+/// there are any.
+pub fn render_context(context: &ApmlContext) -> String {
+	let mut names = context.keys().collect::<Vec<_>>();
+	names.sort();
+	let mut result = String::new();
+	for name in names {
+		_ = writeln!(result, "{} = {}", name, render_value(&context[name]));
+	}
+	if !context.positional_params().is_empty() {
+		_ = writeln!(
+			result,
+			"{} = {:?}",
+			POSITIONAL_PARAMS,
+			context.positional_params()
+		);
+	}
+	result
+}
+
+/// Renders a value as in [`render_context`].
+pub fn render_value(value: &VariableValue) -> String {
+	match value {
+		VariableValue::String(text) => format!("{:?}", text),
+		VariableValue::Array(elements) => format!("{:?}", elements),
+	}
+}
+
+/// Difference of a variable between a left (actual) and a right
+/// (expected) context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariableDiff {
+	/// Defined in the right context only.
+	Missing { name: String, value: VariableValue },
+	/// Defined in the left context only.
+	Extra { name: String, value: VariableValue },
+	/// Defined in both contexts with different values.
+	Changed {
+		name: String,
+		left: VariableValue,
+		right: VariableValue,
+	},
+}
+
+impl Display for VariableDiff {
+	/// Formats the difference in one or more lines, without the trailing
+	/// newline.
+	///
+	/// Changes between arrays are listed by element.
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			VariableDiff::Missing { name, value } => {
+				write!(f, "missing {} = {}", name, render_value(value))
+			}
+			VariableDiff::Extra { name, value } => {
+				write!(f, "extra {} = {}", name, render_value(value))
+			}
+			VariableDiff::Changed {
+				name,
+				left: VariableValue::Array(left),
+				right: VariableValue::Array(right),
+			} => {
+				write!(f, "changed {}:", name)?;
+				for index in 0..left.len().max(right.len()) {
+					match (left.get(index), right.get(index)) {
+						(Some(left), Some(right)) if left != right => write!(
+							f,
+							"\n  [{}] {:?} != {:?}",
+							index, left, right
+						)?,
+						(Some(left), None) => {
+							write!(f, "\n  [{}] extra {:?}", index, left)?
+						}
+						(None, Some(right)) => {
+							write!(f, "\n  [{}] missing {:?}", index, right)?
+						}
+						_ => {}
+					}
+				}
+				Ok(())
+			}
+			VariableDiff::Changed { name, left, right } => write!(
+				f,
+				"changed {}: {} != {}",
+				name,
+				render_value(left),
+				render_value(right)
+			),
+		}
+	}
+}
+
+/// Compares two contexts by variable.
+///
+/// Differences are sorted by variable name. Positional parameters are
+/// compared as an array named [`POSITIONAL_PARAMS`], which comes last.
+/// The result is empty if and only if the contexts are equal.
+pub fn diff_contexts(
+	left: &ApmlContext,
+	right: &ApmlContext,
+) -> Vec<VariableDiff> {
+	let names = left.keys().chain(right.keys()).collect::<BTreeSet<_>>();
+	let mut diffs = names
+		.into_iter()
+		.filter_map(|name| diff_value(name, left.get(name), right.get(name)))
+		.collect::<Vec<_>>();
+	let params = |context: &ApmlContext| {
+		let params = context.positional_params();
+		(!params.is_empty()).then(|| VariableValue::Array(params.to_vec()))
+	};
+	diffs.extend(diff_value(
+		POSITIONAL_PARAMS,
+		params(left).as_ref(),
+		params(right).as_ref(),
+	));
+	diffs
+}
+
+fn diff_value(
+	name: &str,
+	left: Option<&VariableValue>,
+	right: Option<&VariableValue>,
+) -> Option<VariableDiff> {
+	let name = name.to_string();
+	match (left, right) {
+		(Some(left), Some(right)) if left != right => {
+			Some(VariableDiff::Changed {
+				name,
+				left: left.clone(),
+				right: right.clone(),
+			})
+		}
+		(Some(value), None) => Some(VariableDiff::Extra {
+			name,
+			value: value.clone(),
+		}),
+		(None, Some(value)) => Some(VariableDiff::Missing {
+			name,
+			value: value.clone(),
+		}),
+		_ => None,
+	}
+}
+
+/// Panics with a list of differences.
+#[track_caller]
+fn fail(diffs: &[VariableDiff], message: Option<Arguments>) -> ! {
+	let mut text = String::from("assertion `left == right` failed");
+	if let Some(message) = message {
+		_ = write!(text, ": {}", message);
+	}
+	for diff in diffs {
+		_ = write!(text, "\n  {}", diff.to_string().replace('\n', "\n  "));
+	}
+	panic!("{}", text)
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_context_eq_impl(
+	left: &ApmlContext,
+	right: &ApmlContext,
+	message: Option<Arguments>,
+) {
+	let diffs = diff_contexts(left, right);
+	if !diffs.is_empty() {
+		fail(&diffs, message);
+	}
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_evals_to_impl(src: &str, expected: &[(&str, VariableValue)]) {
+	let context = match ApmlContext::eval_source(src) {
+		Ok(context) => context,
+		Err(err) => panic!("failed to evaluate source: {}", err),
+	};
+	let diffs = expected
+		.iter()
+		.filter_map(|(name, value)| {
+			diff_value(name, context.get(name), Some(value))
+		})
+		.collect::<Vec<_>>();
+	if !diffs.is_empty() {
+		fail(&diffs, None);
+	}
+}
+
+/// Asserts that two [contexts][ApmlContext] are equal.
+///
+/// On failure, the panic message lists differences per variable as
+/// produced by [`diff_contexts`], instead of debug output of both
+/// contexts. Like [`assert_eq!`], a custom message can be given after
+/// the contexts.
+///
+/// ```
+/// use libabbs::{apml::ApmlContext, assert_context_eq};
+///
+/// let left = ApmlContext::eval_source("A=1\nB=(x y)\n").unwrap();
+/// let right = ApmlContext::eval_source("B=(x y)\nA=1\n").unwrap();
+/// assert_context_eq!(left, right);
+/// ```
+#[macro_export]
+macro_rules! assert_context_eq {
+	($left:expr, $right:expr $(,)?) => {
+		$crate::testing::assert_context_eq_impl(&$left, &$right, None)
+	};
+	($left:expr, $right:expr, $($arg:tt)+) => {
+		$crate::testing::assert_context_eq_impl(
+			&$left,
+			&$right,
+			Some(format_args!($($arg)+)),
+		)
+	};
+}
+
+/// Asserts that a source evaluates with variables of given values.
+///
+/// Values are written as string literals for strings, and as lists of
+/// string literals in brackets for arrays. Other expressions converting
+/// into a [`VariableValue`] must be parenthesized. Variables not listed
+/// are not checked.
+///
+/// ```
+/// use libabbs::assert_evals_to;
+///
+/// assert_evals_to!("VER=8.2\nSRCS=(a b)\n", {
+///     "VER" => "8.2",
+///     "SRCS" => ["a", "b"],
+/// });
+/// ```
+///
+/// [`VariableValue`]: crate::apml::VariableValue
+#[macro_export]
+macro_rules! assert_evals_to {
+	($src:expr, { $($name:expr => $value:tt),* $(,)? }) => {
+		$crate::testing::assert_evals_to_impl(
+			$src,
+			&[$(($name, $crate::assert_evals_to!(@value $value))),*],
+		)
+	};
+	(@value [$($element:expr),* $(,)?]) => {
+		$crate::apml::VariableValue::Array(
+			::std::vec![$(::std::string::String::from($element)),*],
+		)
+	};
+	(@value $value:expr) => {
+		$crate::apml::VariableValue::from($value)
+	};
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_diff_contexts() {
+		let mut left =
+			ApmlContext::eval_source("A=1\nB=(a b c)\nC=x\nE=\"a\nb\"\n")
+				.unwrap();
+		let right =
+			ApmlContext::eval_source("A=2\nB=(a x)\nC=(x)\nD=y\nE=\"a\nb\"\n")
+				.unwrap();
+		left.set_positional_params(vec!["p".to_string()]);
+		let diffs = diff_contexts(&left, &right);
+		assert_eq!(
+			diffs.iter().map(ToString::to_string).collect::<Vec<_>>(),
+			vec![
+				"changed A: \"1\" != \"2\"",
+				"changed B:\n  [1] \"b\" != \"x\"\n  [2] extra \"c\"",
+				"changed C: \"x\" != [\"x\"]",
+				"missing D = \"y\"",
+				"extra $@ = [\"p\"]",
+			]
+		);
+		assert!(diff_contexts(&right, &right.clone()).is_empty());
+		assert_eq!(
+			render_context(&left),
+			"A = \"1\"\nB = [\"a\", \"b\", \"c\"]\nC = \"x\"\nE = \"a\\nb\"\n\
+			$@ = [\"p\"]\n"
+		);
+
+		let result = std::panic::catch_unwind(|| {
+			assert_context_eq!(left, right, "case {}", 1);
+		});
+		let message = result.unwrap_err();
+		let message = message.downcast_ref::<String>().unwrap();
+		assert!(message.starts_with(
+			"assertion `left == right` failed: case 1\n  changed A: \"1\" \
+			!= \"2\"\n  changed B:\n    [1] \"b\" != \"x\"\n"
+		));
+	}
+
+	#[test]
+	fn test_assert_evals_to() {
+		assert_evals_to!("A=1\nB=(a \"$A\")\n", {
+			"A" => (1.to_string()),
+			"B" => ["a", "1"],
+		});
+		let result = std::panic::catch_unwind(|| {
+			assert_evals_to!("A=1\n", { "A" => ["1"], "C" => "x" });
+		});
+		assert_eq!(
+			result.unwrap_err().downcast_ref::<String>().unwrap(),
+			"assertion `left == right` failed\n  changed A: \"1\" != \
+			[\"1\"]\n  missing C = \"x\""
+		);
+	}
+}
@@ -85,6 +85,8 @@ impl AbbsTree {
 	}
 
 	/// Iterates over all source packages in a certain section and creates accessors.
+	///
+	/// Directories with names that are not valid UTF-8 are skipped.
 	pub fn section_packages(
 		&self,
 		section: &SectionName,
@@ -92,7 +94,9 @@ impl AbbsTree {
 		let mut result = Vec::new();
 		for entry in self.join(section.as_str()).read_dir()? {
 			let entry = entry?;
-			if entry.file_type()?.is_dir() {
+			if entry.file_type()?.is_dir()
+				&& entry.file_name().to_str().is_some()
+			{
 				result.push(AbbsSourcePackage::new(entry.path()));
 			}
 		}
@@ -155,6 +159,8 @@ impl AbbsTree {
 pub enum AbbsError {
 	#[error("I/O Error: {0}")]
 	IoError(#[from] std::io::Error),
+	#[error("I/O Error in {0}: {1}")]
+	FileError(PathBuf, std::io::Error),
 	#[error("Package not found: {0}")]
 	PackageNotFound(String),
 	#[error(transparent)]
@@ -267,6 +273,11 @@ impl AbbsSourcePackage {
 	}
 
 	/// Returns the name of package.
+	///
+	/// # Panics
+	///
+	/// Panics if the name of the package directory is not valid UTF-8.
+	/// Packages listed by [`AbbsTree`] always have valid names.
 	pub fn name(&self) -> &str {
 		self.0
 			.file_name()
@@ -276,6 +287,10 @@ impl AbbsSourcePackage {
 	}
 
 	/// Returns the section of package.
+	///
+	/// # Panics
+	///
+	/// Panics if the name of the section directory is not valid UTF-8.
 	pub fn section(&self) -> SectionName {
 		SectionName::from_ref(
 			self.0
@@ -300,11 +315,15 @@ impl AbbsSourcePackage {
 	}
 
 	/// Returns a list of subpackages of this package.
+	///
+	/// Directories with names that are not valid UTF-8 are skipped.
 	pub fn subpackages(&self) -> AbbsResult<Vec<AbbsSubPackage>> {
 		let mut result = Vec::new();
 		for entry in self.as_path().read_dir()? {
 			let entry = entry?;
-			if entry.file_type()?.is_dir() {
+			if entry.file_type()?.is_dir()
+				&& entry.file_name().to_str().is_some()
+			{
 				result.push(AbbsSubPackage::new(entry.path()));
 			}
 		}
@@ -378,6 +397,10 @@ impl AbbsSubPackage {
 	}
 
 	/// Returns the directory name of package.
+	///
+	/// # Panics
+	///
+	/// Panics if the name of the package directory is not valid UTF-8.
 	pub fn dir_name(&self) -> &str {
 		self.0
 			.file_name()
@@ -392,11 +415,12 @@ impl AbbsSubPackage {
 	/// and is a high-cost operation. The caller should cache the name
 	/// as much as possible.
 	pub fn name(&self) -> AbbsResult<String> {
-		Ok(ApmlContext::eval_source(&fs::read_to_string(
-			self.join("defines"),
-		)?)?
-		.read("PKGNAME")
-		.into_string())
+		let path = self.join("defines");
+		let src = fs::read_to_string(&path)
+			.map_err(|err| AbbsError::FileError(path, err))?;
+		Ok(ApmlContext::eval_source(&src)?
+			.read("PKGNAME")
+			.into_string())
 	}
 
 	/// Returns the source package.
@@ -549,4 +573,47 @@ mod test {
 		);
 		assert_eq!(guest.modifier_suffixes().unwrap().len(), 1);
 	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_non_utf8_paths() {
+		use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+		let mut root = b"libabbs-tree-\xff-".to_vec();
+		root.extend(std::process::id().to_string().bytes());
+		let root = std::env::temp_dir().join(OsString::from_vec(root));
+		let section = root.join("app-admin");
+		fs::create_dir_all(section.join("foo").join("autobuild")).unwrap();
+		fs::create_dir_all(
+			section
+				.join("foo")
+				.join(OsString::from_vec(b"\xfe".to_vec())),
+		)
+		.unwrap();
+		fs::create_dir_all(
+			section.join(OsString::from_vec(b"bar\xff".to_vec())),
+		)
+		.unwrap();
+		fs::write(section.join("foo").join("spec"), "VER=1\n").unwrap();
+		fs::write(
+			section.join("foo").join("autobuild").join("defines"),
+			"PKGNAME=foo\n",
+		)
+		.unwrap();
+
+		let tree = AbbsTree::new(&root);
+		let packages = tree.all_packages().unwrap();
+		assert_eq!(packages.len(), 1);
+		assert_eq!(packages[0].name(), "foo");
+		assert_eq!(packages[0].section().as_str(), "app-admin");
+		assert_eq!(packages[0].tree().as_path(), root);
+		assert_eq!(packages[0].subpackages().unwrap().len(), 1);
+		assert_eq!(tree.find_subpackage("foo").unwrap().name().unwrap(), "foo");
+		let err = AbbsSubPackage::new(section.join("foo")).name().unwrap_err();
+		fs::remove_dir_all(&root).unwrap();
+		let defines = section.join("foo").join("defines");
+		assert!(
+			matches!(err, AbbsError::FileError(path, _) if path == defines)
+		);
+	}
 }
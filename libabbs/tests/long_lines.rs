@@ -0,0 +1,108 @@
+//! Regression tests for extremely long single-line definitions.
+//!
+//! Parsing and evaluation must neither grow the stack with the number of
+//! elements nor use memory beyond [`MEMORY_FACTOR`] times the input size,
+//! which is measured with a counting allocator.
+
+#![cfg(feature = "apml")]
+
+use std::{
+	alloc::{GlobalAlloc, Layout, System},
+	sync::{
+		Mutex,
+		atomic::{AtomicUsize, Ordering},
+	},
+	thread,
+};
+
+use libabbs::apml::{ApmlContext, lst::ApmlLst};
+
+struct CountingAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let ptr = unsafe { System.alloc(layout) };
+		if !ptr.is_null() {
+			let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed)
+				+ layout.size();
+			PEAK.fetch_max(current, Ordering::Relaxed);
+		}
+		ptr
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		unsafe { System.dealloc(ptr, layout) };
+		CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+	}
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// Bound of memory used by parsing and evaluation, relative to the size
+/// of the source, as documented on [`ApmlLst::parse`].
+const MEMORY_FACTOR: usize = 64;
+
+/// Serializes measurements, as the counters are shared by all threads.
+static MEASURE: Mutex<()> = Mutex::new(());
+
+/// Runs a closure, returning its result and the peak of memory allocated
+/// during the call, in bytes.
+fn measure<T>(f: impl FnOnce() -> T) -> (T, usize) {
+	let base = CURRENT.load(Ordering::Relaxed);
+	PEAK.store(base, Ordering::Relaxed);
+	let result = f();
+	(result, PEAK.load(Ordering::Relaxed) - base)
+}
+
+/// Runs a closure on a thread with a small stack, so that recursion per
+/// element overflows.
+fn with_small_stack<T: Send + 'static>(
+	f: impl FnOnce() -> T + Send + 'static,
+) -> T {
+	thread::Builder::new()
+		.stack_size(256 * 1024)
+		.spawn(f)
+		.unwrap()
+		.join()
+		.unwrap()
+}
+
+#[test]
+fn test_long_array() {
+	let _guard = MEASURE.lock().unwrap();
+	with_small_stack(|| {
+		let mut src = String::from("VER=1\nPKGDEP=(");
+		for i in 0..100_000 {
+			src.push_str(&format!("pkg{}>=$VER \"b{}\"'c' ", i, i));
+		}
+		src.push_str(")\nPKGDEP+=(x)\n");
+		let (lst, parse_peak) = measure(|| ApmlLst::parse(&src).unwrap());
+		let (context, eval_peak) =
+			measure(|| ApmlContext::eval_lst(&lst).unwrap());
+		assert_eq!(context.get("PKGDEP").unwrap().as_array().len(), 200_001);
+		assert!(parse_peak < src.len() * MEMORY_FACTOR, "{}", parse_peak);
+		assert!(eval_peak < src.len() * MEMORY_FACTOR, "{}", eval_peak);
+	});
+}
+
+#[test]
+fn test_long_word() {
+	let _guard = MEASURE.lock().unwrap();
+	with_small_stack(|| {
+		let mut src = String::from("V=1\nA=");
+		for _ in 0..100_000 {
+			src.push_str("a$V\"b\"'c'\\\n");
+		}
+		src.push('\n');
+		let (lst, parse_peak) = measure(|| ApmlLst::parse(&src).unwrap());
+		let (context, eval_peak) =
+			measure(|| ApmlContext::eval_lst(&lst).unwrap());
+		assert_eq!(context.read("A").len(), 400_000);
+		assert!(parse_peak < src.len() * MEMORY_FACTOR, "{}", parse_peak);
+		assert!(eval_peak < src.len() * MEMORY_FACTOR, "{}", eval_peak);
+	});
+}
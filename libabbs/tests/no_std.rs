@@ -0,0 +1,56 @@
+//! Usage of the core of APML with `core` and `alloc` only.
+//!
+//! This crate is `#![no_std]`, so it only compiles if parsing, emitting
+//! and evaluation need nothing from `std`. It runs under the test harness
+//! of the host, which is the only part linking `std`. Build the library
+//! with `--no-default-features --features apml` to check the `no_std`
+//! configuration of it as well.
+
+#![no_std]
+#![cfg(feature = "apml")]
+
+extern crate alloc;
+
+use alloc::{string::ToString, vec};
+
+use libabbs::apml::{
+	ApmlContext, VariableValue,
+	ast::{ApmlAst, AstNode},
+	eval,
+	lst::ApmlLst,
+};
+
+const SRC: &str = "\
+# comment
+VER=1.2.3
+SRCS=\"tbl::https://example.org/a-$VER.tar\"
+ARR=(\"${VER%.*}\" b)
+A=\"${ARR[@]}\"
+";
+
+#[test]
+fn test_eval() {
+	let lst = ApmlLst::parse(SRC).unwrap();
+	assert_eq!(lst.to_string(), SRC);
+	let ast = ApmlAst::emit_from(&lst).unwrap();
+	let mut context = ApmlContext::new();
+	eval::eval_ast(&mut context, &ast).unwrap();
+
+	assert_eq!(context.get("VER"), Some(&VariableValue::from("1.2.3")));
+	assert_eq!(
+		context["SRCS"].as_string(),
+		"tbl::https://example.org/a-1.2.3.tar"
+	);
+	assert_eq!(
+		context["ARR"],
+		VariableValue::Array(vec!["1.2".to_string(), "b".to_string()])
+	);
+	assert_eq!(context["A"].as_string(), "1.2 b");
+	assert_eq!(context, ApmlContext::eval_source(SRC).unwrap());
+}
+
+#[test]
+fn test_errors() {
+	assert!(ApmlLst::parse("A=\"").is_err());
+	assert!(ApmlContext::eval_source("A=${B:?unset}").is_err());
+}
@@ -74,14 +74,21 @@ impl Snippet {
 		let line = lst.0.iter().position(|t| t == token).map(|index| {
 			lst.0[0..index]
 				.iter()
-				.filter(|token| matches!(token, lst::Token::Newline))
+				.filter(|token| {
+					matches!(
+						token,
+						lst::Token::Newline | lst::Token::LineContinuation
+					)
+				})
 				.count() + 1
 		});
 		let source = match token {
-			lst::Token::Spacy(_) | lst::Token::Newline => None,
-			lst::Token::Comment(_) | lst::Token::Variable(_) => {
-				Some(token.to_string())
-			}
+			lst::Token::Spacy(_)
+			| lst::Token::Newline
+			| lst::Token::LineContinuation => None,
+			lst::Token::Comment(_)
+			| lst::Token::Variable(_)
+			| lst::Token::Set(_) => Some(token.to_string()),
 		};
 		Self { path, line, source }
 	}